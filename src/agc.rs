@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::ops::Mul;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use crate::rate::RateAware;
+use crate::traits::*;
+
+
+/// Time constants of an [`Agc`]. Attack and decay are the time to cover 63% of a level change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AgcConfig {
+    pub attack: Duration,
+    pub decay: Duration,
+    /// Gain is held this long after the level drops before decaying, so the noise between
+    /// syllables or CW elements doesn't get pumped up.
+    pub hang: Option<Duration>,
+    /// Output peak level.
+    pub target: f32,
+    pub max_gain_db: f32,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgcPreset {
+    Fast,
+    Slow,
+    /// Fast attack, a long hold and then a quick recovery.
+    Hang,
+    /// Speech without a carrier, fast attack with hang.
+    Ssb,
+    /// The carrier keeps the level steady, slow enough not to follow the modulation.
+    Am,
+    /// Constant envelope, only needs to track fading.
+    Fm,
+    Cw,
+}
+
+
+impl AgcPreset {
+    pub fn config(&self) -> AgcConfig {
+        let ms = Duration::from_millis;
+        let (attack, decay, hang) = match self {
+            AgcPreset::Fast => (ms(2), ms(100), None),
+            AgcPreset::Slow => (ms(5), ms(1000), None),
+            AgcPreset::Hang => (ms(2), ms(100), Some(ms(1000))),
+            AgcPreset::Ssb => (ms(2), ms(300), Some(ms(500))),
+            AgcPreset::Am => (ms(50), ms(500), None),
+            AgcPreset::Fm => (ms(10), ms(200), None),
+            AgcPreset::Cw => (ms(1), ms(100), Some(ms(250))),
+        };
+        AgcConfig {
+            attack,
+            decay,
+            hang,
+            target: 0.5,
+            max_gain_db: 60.0,
+        }
+    }
+}
+
+
+/// Current AGC gain for UIs and meters on other threads.
+#[derive(Clone)]
+pub struct AgcGainReader {
+    gain: Arc<AtomicU32>,
+}
+
+
+impl AgcGainReader {
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        20.0 * self.gain().max(1e-12).log10()
+    }
+}
+
+
+/// Peak tracking AGC with attack, decay and hang times, for demodulated audio or complex baseband.
+/// Unlike the feedforward `modem::AGC` it reacts differently to rising and falling levels.
+pub struct Agc {
+    sample_rate: u32,
+    config: AgcConfig,
+    attack: f32,
+    decay: f32,
+    hang: usize,
+    max_gain: f32,
+    envelope: f32,
+    hold: usize,
+    gain: Arc<AtomicU32>,
+}
+
+
+fn coefficient(time: Duration, sample_rate: u32) -> f32 {
+    1.0 - (-1.0 / (time.as_secs_f32() * sample_rate as f32).max(1.0)).exp()
+}
+
+
+impl Agc {
+    pub fn new(sample_rate: u32, preset: AgcPreset) -> Self {
+        Self::with_config(sample_rate, preset.config())
+    }
+
+    pub fn with_config(sample_rate: u32, config: AgcConfig) -> Self {
+        let mut agc = Self {
+            sample_rate,
+            config,
+            attack: 0.0,
+            decay: 0.0,
+            hang: 0,
+            max_gain: 1.0,
+            envelope: 0.0,
+            hold: 0,
+            gain: Arc::new(AtomicU32::new(1f32.to_bits())),
+        };
+        agc.set_config(config);
+        agc
+    }
+
+    /// Switch time constants on the fly, e.g. when the demodulator mode changes. The current level is kept.
+    pub fn set_config(&mut self, config: AgcConfig) {
+        self.config = config;
+        self.attack = coefficient(config.attack, self.sample_rate);
+        self.decay = coefficient(config.decay, self.sample_rate);
+        self.hang = config.hang.map_or(0, |hang| (hang.as_secs_f32() * self.sample_rate as f32) as usize);
+        self.max_gain = 10f32.powf(config.max_gain_db / 20.0);
+        self.hold = self.hold.min(self.hang);
+    }
+
+    pub fn set_preset(&mut self, preset: AgcPreset) {
+        self.set_config(preset.config());
+    }
+
+    pub fn config(&self) -> AgcConfig {
+        self.config
+    }
+
+    pub fn gain_reader(&self) -> AgcGainReader {
+        AgcGainReader {
+            gain: self.gain.clone(),
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        if self.envelope > 0.0 { (self.config.target / self.envelope).min(self.max_gain) } else { self.max_gain }
+    }
+
+    fn track(&mut self, magnitude: f32) {
+        if magnitude > self.envelope {
+            self.envelope += (magnitude - self.envelope) * self.attack;
+            self.hold = self.hang;
+        } else if self.hold > 0 {
+            self.hold -= 1;
+        } else {
+            self.envelope += (magnitude - self.envelope) * self.decay;
+        }
+    }
+}
+
+
+impl<T: Magnitude + Mul<f32, Output = T>> Filter<T, T> for Agc {
+    fn filter(&mut self, input: &[T], output: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &sample in input {
+            self.track(sample.magnitude_sqr().sqrt());
+            output.push(sample * self.gain());
+        }
+        self.gain.store(self.gain().to_bits(), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+
+impl RateAware for Agc {}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_complex::Complex32;
+    use crate::agc::{Agc, AgcPreset};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_agc_presets() -> Result<(), Box<dyn Error>> {
+        let rate = 8000;
+        let loud = vec![1.0f32; rate as usize];
+        let quiet = vec![0.01f32; rate as usize / 10];
+        let mut output = Vec::new();
+
+        // 100 ms after a loud signal stops, hang AGC still holds its gain and fast AGC has recovered a lot
+        let mut gains = Vec::new();
+        for preset in [AgcPreset::Hang, AgcPreset::Fast, AgcPreset::Slow] {
+            let mut agc = Agc::new(rate, preset);
+            let reader = agc.gain_reader();
+            agc.filter(&loud, &mut output)?;
+            assert!((output.last().unwrap() - 0.5).abs() < 0.01);
+            assert!((reader.gain_db() + 6.0).abs() < 0.1);
+            agc.filter(&quiet, &mut output)?;
+            gains.push(reader.gain_db());
+        }
+        assert!((gains[0] + 6.0).abs() < 0.1, "{:?}", gains);
+        assert!(gains[1] > 0.0 && gains[2] < gains[1] - 5.0, "{:?}", gains);
+
+        // complex baseband works too, gain is capped by max_gain_db
+        let mut agc = Agc::new(rate, AgcPreset::Fm);
+        let mut output = Vec::new();
+        agc.filter(&vec![Complex32::new(1e-6, 0.0); 100], &mut output)?;
+        assert!((agc.gain_reader().gain_db() - 60.0).abs() < 0.1);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,182 @@
+use std::marker::PhantomData;
+use std::time::Instant;
+use crate::async_io::{AsyncError, FilterAsync, SinkAsync, SourceAsync};
+use crate::pipeline::{CancelToken, RunStats, StopReason};
+
+
+/// `Filtered` for async blocks: a source followed by a filter, itself a source of what the filter
+/// produces. Reads again while the filter holds samples back.
+pub struct AsyncFiltered<S, F, M> {
+    source: S,
+    filter: F,
+    buffer: Vec<M>,
+}
+
+
+impl<S, F, M> AsyncFiltered<S, F, M> {
+    pub fn new(source: S, filter: F) -> Self {
+        Self {
+            source,
+            filter,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> (S, F) {
+        (self.source, self.filter)
+    }
+}
+
+
+impl<S: SourceAsync<M>, F: FilterAsync<M, O>, M: Send + Sync, O: Send> SourceAsync<O> for AsyncFiltered<S, F, M> {
+    async fn read(&mut self, dst: &mut Vec<O>) -> Result<(), AsyncError> {
+        dst.clear();
+        while dst.is_empty() {
+            self.source.read(&mut self.buffer).await?;
+            if self.buffer.is_empty() {
+                break;
+            }
+            self.filter.filter(&self.buffer, dst).await?;
+        }
+        Ok(())
+    }
+}
+
+
+/// `Flowgraph` on an async runtime, for pipelines inside network services. A graph is a single
+/// future, so any number of them share the runtime's threads instead of a thread per block:
+///
+/// `tokio::spawn(flowgraph.source(socket).filter(demod).filter(resample).sink(http).run())`
+///
+/// Plain `Filter`s work as they are, blocking sources and sinks go through `AsyncSource` and
+/// `AsyncSink`. There's no rate check since the network blocks don't know their rates.
+pub struct AsyncFlowgraph {
+    cancel: CancelToken,
+}
+
+
+impl AsyncFlowgraph {
+    pub fn new(cancel: CancelToken) -> Self {
+        Self {
+            cancel,
+        }
+    }
+
+    pub fn source<T, S: SourceAsync<T>>(&self, source: S) -> AsyncChain<S, T> {
+        AsyncChain {
+            source,
+            cancel: self.cancel.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+
+/// A source and the filters attached to it so far, producing `T`.
+pub struct AsyncChain<S, T> {
+    source: S,
+    cancel: CancelToken,
+    _marker: PhantomData<T>,
+}
+
+
+impl<S: SourceAsync<T>, T: Send + Sync> AsyncChain<S, T> {
+    pub fn filter<O, F: FilterAsync<T, O>>(self, filter: F) -> AsyncChain<AsyncFiltered<S, F, T>, O> {
+        AsyncChain {
+            source: AsyncFiltered::new(self.source, filter),
+            cancel: self.cancel,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn sink<K: SinkAsync<T>>(self, sink: K) -> AsyncGraph<S, T, K> {
+        AsyncGraph {
+            source: self.source,
+            sink,
+            cancel: self.cancel,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+
+/// A complete async chain, owning its blocks so it can be spawned.
+pub struct AsyncGraph<S, T, K> {
+    source: S,
+    sink: K,
+    cancel: CancelToken,
+    buffer: Vec<T>,
+}
+
+
+impl<S: SourceAsync<T>, T: Send + Sync, K: SinkAsync<T>> AsyncGraph<S, T, K> {
+    /// Until the source ends or the token is cancelled, checked between buffers. Dropping the future
+    /// stops the graph as well.
+    pub async fn run(mut self) -> Result<(RunStats, K), AsyncError> {
+        let start = Instant::now();
+        let mut stats = RunStats {
+            samples: 0,
+            buffers: 0,
+            elapsed: Default::default(),
+            stop: StopReason::EndOfStream,
+        };
+        loop {
+            if self.cancel.is_cancelled() {
+                stats.stop = StopReason::Cancelled;
+                break;
+            }
+            self.source.read(&mut self.buffer).await?;
+            if self.buffer.is_empty() {
+                break;
+            }
+            stats.samples += self.buffer.len() as u64;
+            stats.buffers += 1;
+            self.sink.write(&self.buffer).await?;
+        }
+        stats.elapsed = start.elapsed();
+        Ok((stats, self.sink))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex32;
+    use crate::async_flowgraph::AsyncFlowgraph;
+    use crate::async_io::{AsyncError, AsyncSink, AsyncSource};
+    use crate::block::{MapFilter, MixerFilter, NullSink, NullSource, RationalResampler};
+    use crate::pipeline::{CancelToken, StopReason};
+
+    #[test]
+    fn test_async_flowgraph() -> Result<(), AsyncError> {
+        // two graphs sharing the one thread of the runtime
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let flowgraph = AsyncFlowgraph::new(CancelToken::new());
+        let graphs: Vec<_> = [48000, 96000].into_iter().map(|samples| {
+            let graph = flowgraph.source(AsyncSource::new(NullSource::new(48000, 1000).limit(samples)))
+                .filter(MapFilter::new(|x: f32| x + 1.0))
+                .filter(MixerFilter::new(48000, 1000.0))
+                .filter(MapFilter::new(|x: Complex32| x.norm()))
+                .filter(RationalResampler::<f32>::new(48000, 8000, 31))
+                .sink(AsyncSink::new(NullSink::new()));
+            runtime.spawn(graph.run())
+        }).collect();
+        runtime.block_on(async {
+            let mut outputs = Vec::new();
+            for graph in graphs {
+                let (stats, sink) = graph.await??;
+                assert_eq!(stats.stop, StopReason::EndOfStream);
+                outputs.push(sink.into_inner().unwrap().samples());
+            }
+            assert_eq!(outputs, [8000, 16000]);
+
+            let cancel = CancelToken::new();
+            cancel.cancel();
+            let (stats, _) = AsyncFlowgraph::new(cancel).source(AsyncSource::<_, f32>::new(NullSource::new(48000, 1000)))
+                .sink(AsyncSink::new(NullSink::new()))
+                .run().await?;
+            assert_eq!((stats.stop, stats.samples), (StopReason::Cancelled, 0));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,262 @@
+use std::error::Error;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::SystemTime;
+use num_complex::Complex32;
+use tokio::net::UdpSocket;
+use tokio::task::spawn_blocking;
+use crate::tag::{Tag, Tagged};
+use crate::traits::*;
+use crate::vita49::{unspecified, Context, Depacketizer, Packetizer, Profile};
+
+
+pub type AsyncError = Box<dyn Error + Send + Sync>;
+
+
+/// Async counterpart of `Source`, an empty read is the end of the stream. The futures are `Send` so
+/// graphs can be spawned on a multi threaded runtime.
+pub trait SourceAsync<T>: Send {
+    fn read(&mut self, dst: &mut Vec<T>) -> impl Future<Output = Result<(), AsyncError>> + Send;
+}
+
+
+/// Async counterpart of `Filter`. Every `Filter` is one, run in place since filters don't wait.
+pub trait FilterAsync<I, O>: Send {
+    fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> impl Future<Output = Result<(), AsyncError>> + Send;
+}
+
+
+/// Async counterpart of `Sink`.
+pub trait SinkAsync<T>: Send {
+    fn write(&mut self, src: &[T]) -> impl Future<Output = Result<(), AsyncError>> + Send;
+}
+
+
+impl<I: Sync, O: Send, F: Filter<I, O> + Send> FilterAsync<I, O> for F {
+    fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> impl Future<Output = Result<(), AsyncError>> + Send {
+        std::future::ready(Filter::filter(self, input, output).map_err(|e| e.to_string().into()))
+    }
+}
+
+
+/// Runs a blocking `Source` on tokio's blocking thread pool so it can be awaited, e.g. file backed
+/// sources like `WavSource` or `ReplaySource`. A read that gets cancelled loses the source.
+pub struct AsyncSource<S, T> {
+    source: Option<S>,
+    buffer: Vec<T>,
+}
+
+
+impl<S: Source<T> + Send + 'static, T: Send + 'static> AsyncSource<S, T> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source: Some(source),
+            buffer: Vec::new(),
+        }
+    }
+
+    pub async fn read(&mut self, dst: &mut Vec<T>) -> Result<(), AsyncError> {
+        let mut source = self.source.take().ok_or("source was lost in a cancelled read")?;
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let (source, buffer, result) = spawn_blocking(move || {
+            let result = source.read(&mut buffer).map_err(|e| e.to_string());
+            (source, buffer, result)
+        }).await?;
+        self.source = Some(source);
+        self.buffer = std::mem::replace(dst, buffer);
+        Ok(result?)
+    }
+
+    pub fn into_inner(self) -> Option<S> {
+        self.source
+    }
+}
+
+
+impl<S: Source<T> + Send + 'static, T: Send + 'static> SourceAsync<T> for AsyncSource<S, T> {
+    async fn read(&mut self, dst: &mut Vec<T>) -> Result<(), AsyncError> {
+        AsyncSource::read(self, dst).await
+    }
+}
+
+
+/// Runs a blocking `Sink` on tokio's blocking thread pool so it can be awaited.
+/// A write that gets cancelled loses the sink.
+pub struct AsyncSink<S, T> {
+    sink: Option<S>,
+    buffer: Vec<T>,
+}
+
+
+impl<S: Sink<T> + Send + 'static, T: Copy + Send + 'static> AsyncSink<S, T> {
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink: Some(sink),
+            buffer: Vec::new(),
+        }
+    }
+
+    pub async fn write(&mut self, src: &[T]) -> Result<(), AsyncError> {
+        let mut sink = self.sink.take().ok_or("sink was lost in a cancelled write")?;
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+        buffer.extend_from_slice(src);
+        let (sink, buffer, result) = spawn_blocking(move || {
+            let result = sink.write(&buffer).map_err(|e| e.to_string());
+            (sink, buffer, result)
+        }).await?;
+        self.sink = Some(sink);
+        self.buffer = buffer;
+        Ok(result?)
+    }
+
+    pub fn into_inner(self) -> Option<S> {
+        self.sink
+    }
+}
+
+
+impl<S: Sink<T> + Send + 'static, T: Copy + Send + Sync + 'static> SinkAsync<T> for AsyncSink<S, T> {
+    async fn write(&mut self, src: &[T]) -> Result<(), AsyncError> {
+        AsyncSink::write(self, src).await
+    }
+}
+
+
+/// `Vita49Source` on a tokio socket.
+pub struct AsyncVita49Source {
+    socket: UdpSocket,
+    depacketizer: Depacketizer,
+    buffer: Vec<u8>,
+}
+
+
+impl AsyncVita49Source {
+    pub async fn bind(address: SocketAddr, stream_id: Option<u32>) -> Result<Self, AsyncError> {
+        Ok(Self {
+            socket: UdpSocket::bind(address).await?,
+            depacketizer: Depacketizer::new(stream_id),
+            buffer: vec![0u8; 65536],
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, AsyncError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    pub fn context(&self) -> Option<Context> {
+        self.depacketizer.context()
+    }
+
+    pub fn lost_packets(&self) -> u64 {
+        self.depacketizer.lost_packets()
+    }
+
+    pub fn invalid_packets(&self) -> u64 {
+        self.depacketizer.invalid_packets()
+    }
+
+    /// Wait for the next data packet of the stream.
+    pub async fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), AsyncError> {
+        dst.clear();
+        loop {
+            let len = self.socket.recv(&mut self.buffer).await?;
+            if self.depacketizer.accept(&self.buffer[..len], dst) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+
+impl SourceAsync<Complex32> for AsyncVita49Source {
+    async fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), AsyncError> {
+        AsyncVita49Source::read(self, dst).await
+    }
+}
+
+
+impl Tagged for AsyncVita49Source {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        self.depacketizer.take_tags(dst);
+    }
+}
+
+
+/// `Vita49Sink` on a tokio socket.
+pub struct AsyncVita49Sink {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    packetizer: Packetizer,
+    datagrams: Vec<Vec<u8>>,
+}
+
+
+impl AsyncVita49Sink {
+    pub async fn new(destination: impl ToSocketAddrs, profile: Profile, stream_id: u32, context: Context, start: SystemTime) -> Result<Self, AsyncError> {
+        let destination = destination.to_socket_addrs()?.next().ok_or("no destination address")?;
+        Ok(Self {
+            socket: UdpSocket::bind(unspecified(&destination)).await?,
+            destination,
+            packetizer: Packetizer::new(profile, stream_id, context, start),
+            datagrams: Vec::new(),
+        })
+    }
+
+    pub fn packetizer(&mut self) -> &mut Packetizer {
+        &mut self.packetizer
+    }
+
+    pub async fn write(&mut self, src: &[Complex32]) -> Result<(), AsyncError> {
+        self.packetizer.packetize(src, &mut self.datagrams);
+        for datagram in &self.datagrams {
+            self.socket.send_to(datagram, self.destination).await?;
+        }
+        Ok(())
+    }
+}
+
+
+impl SinkAsync<Complex32> for AsyncVita49Sink {
+    async fn write(&mut self, src: &[Complex32]) -> Result<(), AsyncError> {
+        AsyncVita49Sink::write(self, src).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+    use num_complex::Complex32;
+    use crate::async_io::{AsyncError, AsyncSink, AsyncSource, AsyncVita49Sink, AsyncVita49Source};
+    use crate::block::{NullSink, NullSource};
+    use crate::vita49::{Context, Profile};
+
+    #[test]
+    fn test_async_wrappers() -> Result<(), AsyncError> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        runtime.block_on(async {
+            let mut source = AsyncSource::new(NullSource::new(48000, 100).limit(250));
+            let mut sink = AsyncSink::new(NullSink::new());
+            let mut buffer: Vec<f32> = Vec::new();
+            loop {
+                source.read(&mut buffer).await?;
+                if buffer.is_empty() {
+                    break;
+                }
+                sink.write(&buffer).await?;
+            }
+            assert_eq!(sink.into_inner().unwrap().samples(), 250);
+
+            let context = Context { sample_rate: 1e6, frequency: 100e6, bandwidth: 1e6 };
+            let mut source = AsyncVita49Source::bind("127.0.0.1:0".parse()?, None).await?;
+            let mut sink = AsyncVita49Sink::new(source.local_addr()?, Profile::Vita49, 1, context, SystemTime::now()).await?;
+            sink.write(&[Complex32::new(0.25, -0.5); 10]).await?;
+            let mut received = Vec::new();
+            source.read(&mut received).await?;
+            assert_eq!(received.len(), 10);
+            assert_eq!(source.context(), Some(context));
+            Ok(())
+        })
+    }
+
+}
@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::f32::consts::PI;
+use num_complex::Complex32;
+use crate::fft::FFT;
+use crate::traits::*;
+
+
+/// Spectral subtraction / Wiener style noise reduction for demodulated audio, using 50% overlap-add.
+pub struct NoiseReduction {
+    fft: FFT,
+    window: Vec<f32>,
+    aggressiveness: f32,
+    floor: f32,
+    noise: Vec<f32>,
+    gains: Vec<f32>,
+    frames: usize,
+    input: VecDeque<f32>,
+    overlap: Vec<f32>,
+    spectrum: Vec<Complex32>,
+}
+
+
+impl NoiseReduction {
+    /// `aggressiveness` scales the subtracted noise estimate (1.0 is plain Wiener filtering),
+    /// `floor_db` limits how far any bin can be attenuated.
+    pub fn new(frame_size: usize, aggressiveness: f32, floor_db: f32) -> Self {
+        // periodic sqrt-hann for analysis and synthesis sums to one at 50% overlap
+        let window = (0..frame_size)
+            .map(|n| (0.5 - 0.5 * (2.0 * PI * n as f32 / frame_size as f32).cos()).sqrt())
+            .collect();
+
+        let mut input = VecDeque::new();
+        input.resize(frame_size / 2, 0.0);
+
+        Self {
+            fft: FFT::new(frame_size),
+            window,
+            aggressiveness,
+            floor: 10f32.powf(floor_db / 20.0),
+            noise: vec![0.0; frame_size],
+            gains: vec![1.0; frame_size],
+            frames: 0,
+            input,
+            overlap: vec![0.0; frame_size / 2],
+            spectrum: Vec::with_capacity(frame_size),
+        }
+    }
+
+    pub fn set_aggressiveness(&mut self, aggressiveness: f32) {
+        self.aggressiveness = aggressiveness;
+    }
+
+    fn process_frame(&mut self, output: &mut Vec<f32>) {
+        let size = self.fft.size();
+        let hop = size / 2;
+
+        self.spectrum.clear();
+        self.spectrum.extend(self.input.iter().zip(self.window.iter()).map(|(&x, &w)| Complex32::new(x * w, 0.0)));
+        self.fft.forward(&mut self.spectrum);
+
+        for (k, bin) in self.spectrum.iter_mut().enumerate() {
+            let power = bin.norm_sqr();
+
+            // average bins that look like noise, only creep up on louder ones so speech doesn't leak in
+            let noise = &mut self.noise[k];
+            if self.frames < 8 {
+                *noise += (power - *noise) / (self.frames + 1) as f32;
+            } else if power < 3.0 * *noise {
+                *noise = 0.95 * *noise + 0.05 * power;
+            } else {
+                *noise *= 1.01;
+            }
+
+            let snr = (power - self.aggressiveness * *noise).max(0.0) / power.max(f32::MIN_POSITIVE);
+            let gain = snr.max(self.floor);
+            self.gains[k] = 0.5 * self.gains[k] + 0.5 * gain;
+            *bin *= self.gains[k];
+        }
+        self.frames += 1;
+
+        self.fft.inverse(&mut self.spectrum);
+        for i in 0..hop {
+            output.push(self.overlap[i] + self.spectrum[i].re * self.window[i]);
+            self.overlap[i] = self.spectrum[i + hop].re * self.window[i + hop];
+        }
+        self.input.drain(..hop);
+    }
+}
+
+
+impl Filter<f32, f32> for NoiseReduction {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        let size = self.fft.size();
+        for &sample in input {
+            self.input.push_back(sample);
+            if self.input.len() == size {
+                self.process_frame(output);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Spreads mono CW/SSB audio across the stereo field by pitch, `low_hz` and below on the left,
+/// `high_hz` and above on the right, with equal power panning in between. Stations at different
+/// pitches then appear at different places, which makes a crowded band easier to follow.
+/// Outputs interleaved left/right frames for a two channel `CpalSink`.
+pub struct BinauralFilter {
+    fft: FFT,
+    window: Vec<f32>,
+    left_gains: Vec<f32>,
+    right_gains: Vec<f32>,
+    input: VecDeque<f32>,
+    overlap: Vec<[f32; 2]>,
+    left: Vec<Complex32>,
+    right: Vec<Complex32>,
+    sample_rate: u32,
+    low_hz: f32,
+    high_hz: f32,
+    width: f32,
+}
+
+
+impl BinauralFilter {
+    pub fn new(sample_rate: u32, low_hz: f32, high_hz: f32) -> Self {
+        let frame_size = 512;
+        let window = (0..frame_size)
+            .map(|n| (0.5 - 0.5 * (2.0 * PI * n as f32 / frame_size as f32).cos()).sqrt())
+            .collect();
+        let mut input = VecDeque::new();
+        input.resize(frame_size / 2, 0.0);
+
+        let mut filter = Self {
+            fft: FFT::new(frame_size),
+            window,
+            left_gains: vec![0.0; frame_size],
+            right_gains: vec![0.0; frame_size],
+            input,
+            overlap: vec![[0.0; 2]; frame_size / 2],
+            left: Vec::with_capacity(frame_size),
+            right: Vec::with_capacity(frame_size),
+            sample_rate,
+            low_hz,
+            high_hz: high_hz.max(low_hz + 1.0),
+            width: 1.0,
+        };
+        filter.update_gains();
+        filter
+    }
+
+    /// 1.0 pans fully from left to right, 0.0 collapses to mono in the center.
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.clamp(0.0, 1.0);
+        self.update_gains();
+    }
+
+    fn update_gains(&mut self) {
+        let size = self.fft.size();
+        for k in 0..size {
+            // negative frequency bins mirror the positive ones so the output stays real
+            let frequency = k.min(size - k) as f32 * self.sample_rate as f32 / size as f32;
+            let position = ((frequency - self.low_hz) / (self.high_hz - self.low_hz)).clamp(0.0, 1.0);
+            let angle = PI / 4.0 + (position - 0.5) * self.width * PI / 2.0;
+            self.left_gains[k] = angle.cos();
+            self.right_gains[k] = angle.sin();
+        }
+    }
+
+    fn process_frame(&mut self, output: &mut Vec<f32>) {
+        let size = self.fft.size();
+        let hop = size / 2;
+
+        self.left.clear();
+        self.left.extend(self.input.iter().zip(self.window.iter()).map(|(&x, &w)| Complex32::new(x * w, 0.0)));
+        self.fft.forward(&mut self.left);
+        self.right.clear();
+        self.right.extend(self.left.iter().zip(&self.right_gains).map(|(x, g)| x * g));
+        self.left.iter_mut().zip(&self.left_gains).for_each(|(x, g)| *x *= g);
+        self.fft.inverse(&mut self.left);
+        self.fft.inverse(&mut self.right);
+
+        for i in 0..hop {
+            output.push(self.overlap[i][0] + self.left[i].re * self.window[i]);
+            output.push(self.overlap[i][1] + self.right[i].re * self.window[i]);
+            self.overlap[i] = [self.left[i + hop].re * self.window[i + hop], self.right[i + hop].re * self.window[i + hop]];
+        }
+        self.input.drain(..hop);
+    }
+}
+
+
+impl Filter<f32, f32> for BinauralFilter {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        let size = self.fft.size();
+        for &sample in input {
+            self.input.push_back(sample);
+            if self.input.len() == size {
+                self.process_frame(output);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::{BinauralFilter, NoiseReduction};
+    use crate::traits::Filter;
+
+    fn power(samples: &[f32]) -> f32 {
+        samples.iter().map(|v| v * v).sum::<f32>() / samples.len() as f32
+    }
+
+    #[test]
+    fn test_noise_reduction() -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = 0x1357_9bdfu32;
+        let noise: Vec<f32> = (0..48000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state as f32 / u32::MAX as f32 - 0.5) * 0.1
+        }).collect();
+
+        let mut nr = NoiseReduction::new(512, 1.5, -30.0);
+        let mut output = Vec::new();
+        nr.filter(&noise, &mut output)?;
+        let tail = output.len() / 2;
+        let ratio = power(&output[tail..]) / power(&noise[tail..]);
+        assert!(ratio < 0.25, "noise power ratio {}", ratio);
+
+        let tone: Vec<f32> = (0..48000).map(|n| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / 48000.0).sin() + noise[n]).collect();
+        nr.filter(&tone, &mut output)?;
+        let ratio = power(&output[tail..]) / power(&tone[tail..]);
+        assert!(ratio > 0.8 && ratio < 1.1, "tone power ratio {}", ratio);
+        Ok(())
+    }
+
+    #[test]
+    fn test_binaural() -> Result<(), Box<dyn std::error::Error>> {
+        let mut binaural = BinauralFilter::new(8000, 400.0, 1000.0);
+        let mut output = Vec::new();
+        let mut levels = |f: f32| -> Result<(f32, f32), Box<dyn std::error::Error>> {
+            let tone: Vec<f32> = (0..8000).map(|n| (2.0 * std::f32::consts::PI * f * n as f32 / 8000.0).sin()).collect();
+            binaural.filter(&tone, &mut output)?;
+            let tail = &output[4000..];
+            let left: Vec<f32> = tail.iter().step_by(2).copied().collect();
+            let right: Vec<f32> = tail.iter().skip(1).step_by(2).copied().collect();
+            Ok((power(&left), power(&right)))
+        };
+
+        let (left, right) = levels(300.0)?;
+        assert!(right < 1e-4 && (left - 0.5).abs() < 0.02, "{} {}", left, right);
+        let (left, right) = levels(700.0)?;
+        assert!((left - right).abs() < 0.02 && (left + right - 0.5).abs() < 0.02, "{} {}", left, right);
+        let (left, right) = levels(1500.0)?;
+        assert!(left < 1e-4 && (right - 0.5).abs() < 0.02, "{} {}", left, right);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,265 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use num_complex::Complex32;
+use crate::error::{check_nyquist, ConfigError};
+use crate::peaks::{noise_floor_db, PsdAverage};
+use crate::rate::{RateAware, SampleRate};
+use crate::traits::*;
+
+
+/// Carrier peak above the noise floor needed to start tracking.
+const ACQUIRE_DB: f32 = 12.0;
+/// Signal to noise ratio in the lock detector's bandwidth below which the loop doesn't count as locked.
+const LOCK_SNR_DB: f32 = 10.0;
+
+
+/// One report of a `CarrierTracker`, averaged over the report interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackPoint {
+    /// Seconds of input before the end of the interval.
+    pub time: f64,
+    /// Carrier frequency relative to the center of the input.
+    pub offset_hz: f64,
+    /// Carrier amplitude in dBFS.
+    pub level_db: f32,
+    pub snr_db: f32,
+    pub locked: bool,
+}
+
+
+/// Follows a slowly drifting CW carrier or beacon for propagation logs. The strongest carrier within
+/// the search range is found in an averaged spectrum, then a PLL tracks it to a fraction of an FFT
+/// bin. A carrier that stays out of lock for the hold time is searched for again. The output is the
+/// input with the tracked carrier mixed to DC, one per input sample.
+pub struct CarrierTracker {
+    sample_rate: u32,
+    search_hz: f64,
+    psd: PsdAverage,
+    fft_size: usize,
+    acquire_frames: usize,
+    acquired: bool,
+    phase: f64,
+    omega: f64,
+    alpha: f64,
+    beta: f64,
+    average: Complex32,
+    power: f32,
+    smoothing: f32,
+    unlocked: u64,
+    hold: u64,
+    report_interval: u64,
+    interval: (u64, f64),
+    samples: u64,
+    track: Vec<TrackPoint>,
+}
+
+
+impl CarrierTracker {
+    /// Tracks carriers within `search_hz` of the center of the input.
+    pub fn new(sample_rate: u32, search_hz: f64) -> Result<Self, ConfigError> {
+        check_nyquist("search range", search_hz, sample_rate)?;
+        let mut tracker = Self {
+            sample_rate,
+            search_hz,
+            psd: PsdAverage::new(16),
+            fft_size: 16,
+            acquire_frames: 4,
+            acquired: false,
+            phase: 0.0,
+            omega: 0.0,
+            alpha: 0.0,
+            beta: 0.0,
+            average: Complex32::new(0.0, 0.0),
+            power: 0.0,
+            // about 100 ms to settle the lock detector
+            smoothing: 1.0 - (-10.0 / sample_rate as f32).exp(),
+            unlocked: 0,
+            hold: 2 * sample_rate as u64,
+            report_interval: sample_rate as u64,
+            interval: (0, 0.0),
+            samples: 0,
+            track: Vec::new(),
+        };
+        tracker.set_bandwidth(5.0);
+        Ok(tracker)
+    }
+
+    /// Loop bandwidth in Hz, critically damped, defaults to 5 Hz. The acquisition FFT gets bins of at
+    /// most half of it so the loop pulls in from any bin.
+    pub fn set_bandwidth(&mut self, hz: f64) {
+        let wn = 2.0 * PI * hz / self.sample_rate as f64;
+        let damping = std::f64::consts::FRAC_1_SQRT_2;
+        self.alpha = 2.0 * damping * wn;
+        self.beta = wn * wn;
+        let fft_size = (2.0 * self.sample_rate as f64 / hz).ceil() as usize;
+        self.fft_size = fft_size.next_power_of_two().max(16);
+        self.psd = PsdAverage::new(self.fft_size);
+    }
+
+    /// Time out of lock before searching again, defaults to 2 s.
+    pub fn set_hold(&mut self, seconds: f64) {
+        self.hold = (seconds * self.sample_rate as f64) as u64;
+    }
+
+    /// Time a `TrackPoint` covers, defaults to 1 s.
+    pub fn set_report_interval(&mut self, seconds: f64) {
+        self.report_interval = ((seconds * self.sample_rate as f64) as u64).max(1);
+    }
+
+    pub fn acquired(&self) -> bool {
+        self.acquired
+    }
+
+    /// Frequency the loop is at now, relative to the center of the input.
+    pub fn offset_hz(&self) -> f64 {
+        self.omega * self.sample_rate as f64 / (2.0 * PI)
+    }
+
+    /// Reports finished since the last call, oldest first.
+    pub fn take_track(&mut self) -> Vec<TrackPoint> {
+        std::mem::take(&mut self.track)
+    }
+
+    fn acquire(&mut self) {
+        if self.psd.frames() < self.acquire_frames {
+            return;
+        }
+        let power_db = self.psd.average_db().unwrap();
+        self.psd.reset();
+        let size = power_db.len();
+        let bin_hz = self.sample_rate as f64 / size as f64;
+        let peak = (0..size)
+            .filter(|&k| ((k as f64 - (size / 2) as f64) * bin_hz).abs() <= self.search_hz)
+            .max_by(|&a, &b| power_db[a].total_cmp(&power_db[b]));
+        let (Some(peak), Some(floor)) = (peak, noise_floor_db(&power_db)) else { return };
+        if power_db[peak] < floor + ACQUIRE_DB {
+            return;
+        }
+        self.omega = 2.0 * PI * (peak as f64 - (size / 2) as f64) / size as f64;
+        self.average = Complex32::new(0.0, 0.0);
+        self.unlocked = 0;
+        self.acquired = true;
+    }
+
+    /// Lock detector: the carrier is steady in phase and stands out of the noise.
+    fn lock(&self) -> (bool, f32) {
+        let carrier = self.average.norm_sqr();
+        // noise power left in the smoothed average
+        let noise = (self.power - carrier).max(1e-20) * self.smoothing / (2.0 - self.smoothing);
+        let snr_db = 10.0 * (carrier / noise).max(1e-20).log10();
+        let locked = self.acquired && snr_db > LOCK_SNR_DB && self.average.im.abs() < 0.5 * self.average.re;
+        (locked, snr_db)
+    }
+
+    fn report(&mut self) {
+        let (count, omega_sum) = std::mem::take(&mut self.interval);
+        let (locked, snr_db) = self.lock();
+        self.track.push(TrackPoint {
+            time: self.samples as f64 / self.sample_rate as f64,
+            offset_hz: omega_sum / count.max(1) as f64 * self.sample_rate as f64 / (2.0 * PI),
+            level_db: 20.0 * self.average.norm().max(1e-10).log10(),
+            snr_db,
+            locked,
+        });
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for CarrierTracker {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for chunk in input.chunks(self.fft_size) {
+            for &x in chunk {
+                let mixed = x * Complex32::from_polar(1.0, -self.phase as f32);
+                output.push(mixed);
+                self.average += self.smoothing * (mixed - self.average);
+                self.power += self.smoothing * (x.norm_sqr() - self.power);
+
+                if self.acquired {
+                    let level = self.average.norm().max(1e-6);
+                    let error = (mixed.im / level).clamp(-1.0, 1.0) as f64;
+                    self.omega = (self.omega + self.beta * error).clamp(-PI, PI);
+                    self.phase = (self.phase + self.omega + self.alpha * error) % (2.0 * PI);
+                    self.unlocked = if self.lock().0 { 0 } else { self.unlocked + 1 };
+                    if self.unlocked > self.hold || self.offset_hz().abs() > self.search_hz {
+                        self.acquired = false;
+                        self.omega = 0.0;
+                    }
+                }
+
+                self.samples += 1;
+                self.interval.0 += 1;
+                self.interval.1 += self.omega;
+                if self.interval.0 == self.report_interval {
+                    self.report();
+                }
+            }
+            if !self.acquired {
+                self.psd.push(chunk);
+                self.acquire();
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for CarrierTracker {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::f64::consts::PI;
+    use num_complex::Complex32;
+    use crate::beacon::CarrierTracker;
+    use crate::seed::Rng;
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_carrier_tracker() -> Result<(), Box<dyn Error>> {
+        let mut rng = Rng::new(0x2545f491);
+        let mut noise = move || rng.uniform() - 0.5;
+
+        // a carrier at -300 Hz drifting up 0.5 Hz/s for 40 s, then gone for 10 s
+        let rate = 8000.0;
+        let frequency = |t: f64| -300.0 + 0.5 * t;
+        let mut phase = 0.0;
+        let samples: Vec<Complex32> = (0..50 * rate as usize).map(|n| {
+            let t = n as f64 / rate;
+            phase += 2.0 * PI * frequency(t) / rate;
+            let carrier = if t < 40.0 { Complex32::from_polar(0.05, phase as f32) } else { Complex32::new(0.0, 0.0) };
+            carrier + Complex32::new(noise(), noise()) * 0.2
+        }).collect();
+
+        let mut tracker = CarrierTracker::new(rate as u32, 1000.0)?;
+        let mut output = Vec::new();
+        let mut track = Vec::new();
+        for block in samples.chunks(1000) {
+            tracker.filter(block, &mut output)?;
+            track.extend(tracker.take_track());
+        }
+        assert_eq!(track.len(), 50);
+        for point in &track[5..39] {
+            assert!(point.locked, "{:?}", point);
+            // the report averages over the second before `time`
+            let expected = frequency(point.time - 0.5);
+            assert!((point.offset_hz - expected).abs() < 0.2, "{:?} {}", point, expected);
+            assert!((point.level_db + 26.0).abs() < 1.0, "{:?}", point);
+        }
+        assert!(track[45..].iter().all(|point| !point.locked), "{:?}", &track[45..]);
+        assert!(!tracker.acquired());
+
+        // the carrier ends up at DC in the output
+        let mut tracker = CarrierTracker::new(rate as u32, 1000.0)?;
+        tracker.filter(&samples[..20 * rate as usize], &mut output)?;
+        let tail = &output[output.len() - rate as usize..];
+        let mean = tail.iter().sum::<Complex32>() / tail.len() as f32;
+        assert!(mean.re > 0.045 && mean.im.abs() < 0.01, "{}", mean);
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use std::error::Error;
+use crate::rate::RateAware;
+use crate::traits::*;
+
+
+/// ITU-T O.150 style pseudo random bit sequences.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prbs {
+    /// x^7 + x^6 + 1
+    Prbs7,
+    /// x^9 + x^5 + 1
+    Prbs9,
+    /// x^15 + x^14 + 1
+    Prbs15,
+    /// x^23 + x^18 + 1
+    Prbs23,
+}
+
+
+impl Prbs {
+    fn taps(&self) -> (u32, u32) {
+        match self {
+            Prbs::Prbs7 => (7, 6),
+            Prbs::Prbs9 => (9, 5),
+            Prbs::Prbs15 => (15, 14),
+            Prbs::Prbs23 => (23, 18),
+        }
+    }
+
+    pub fn order(&self) -> u32 {
+        self.taps().0
+    }
+
+    /// Bits until the sequence repeats.
+    pub fn period(&self) -> u64 {
+        (1 << self.order()) - 1
+    }
+
+    /// Next bit of the sequence following the last `order` bits in `state`, newest in bit 0.
+    fn next(&self, state: u32) -> u8 {
+        let (a, b) = self.taps();
+        (((state >> (a - 1)) ^ (state >> (b - 1))) & 1) as u8
+    }
+
+    fn mask(&self) -> u32 {
+        (1 << self.order()) - 1
+    }
+}
+
+
+/// Unpacked PRBS bits, one per `u8`, from the all ones seed.
+pub struct PrbsSource {
+    prbs: Prbs,
+    state: u32,
+    bits_per_read: usize,
+    limit: Option<u64>,
+    bits: u64,
+}
+
+
+impl PrbsSource {
+    pub fn new(prbs: Prbs, bits_per_read: usize) -> Self {
+        Self {
+            prbs,
+            state: prbs.mask(),
+            bits_per_read,
+            limit: None,
+            bits: 0,
+        }
+    }
+
+    /// End the stream after `bits` bits.
+    pub fn limit(mut self, bits: u64) -> Self {
+        self.limit = Some(bits);
+        self
+    }
+
+    fn next_bit(&mut self) -> u8 {
+        let bit = self.prbs.next(self.state);
+        self.state = ((self.state << 1) | bit as u32) & self.prbs.mask();
+        bit
+    }
+}
+
+
+impl Source<u8> for PrbsSource {
+    fn read(&mut self, dst: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        let remaining = self.limit.map_or(u64::MAX, |limit| limit - self.bits);
+        let len = (self.bits_per_read as u64).min(remaining) as usize;
+        dst.clear();
+        for _ in 0..len {
+            let bit = self.next_bit();
+            dst.push(bit);
+        }
+        self.bits += len as u64;
+        Ok(())
+    }
+}
+
+
+impl RateAware for PrbsSource {}
+
+
+/// Bits checked per window, sync is dropped when a window has more than `SYNC_LOSS` errors.
+const WINDOW: u64 = 1000;
+const SYNC_LOSS: u64 = 200;
+
+
+/// Counts bit errors against a PRBS. It seeds itself from the received bits, so it locks at any point
+/// of the sequence and with unknown delay, then free runs so each channel error is counted once.
+/// Bits received while acquiring are not counted.
+pub struct BerCounter {
+    prbs: Prbs,
+    state: u32,
+    filled: u32,
+    locked: bool,
+    bits: u64,
+    errors: u64,
+    window_bits: u64,
+    window_errors: u64,
+    resyncs: u64,
+}
+
+
+impl BerCounter {
+    pub fn new(prbs: Prbs) -> Self {
+        Self {
+            prbs,
+            state: 0,
+            filled: 0,
+            locked: false,
+            bits: 0,
+            errors: 0,
+            window_bits: 0,
+            window_errors: 0,
+            resyncs: 0,
+        }
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Times the lock was lost, e.g. from bit slips.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    pub fn ber(&self) -> f64 {
+        if self.bits == 0 { 0.0 } else { self.errors as f64 / self.bits as f64 }
+    }
+
+    /// Start counting afresh, the lock is kept and the sync loss window starts over with the counts.
+    pub fn reset_counts(&mut self) {
+        self.bits = 0;
+        self.errors = 0;
+        self.window_bits = 0;
+        self.window_errors = 0;
+    }
+
+    fn push(&mut self, bit: u8) {
+        let bit = bit & 1;
+        if !self.locked {
+            self.state = ((self.state << 1) | bit as u32) & self.prbs.mask();
+            self.filled += 1;
+            // an all zero register would lock onto a dead link
+            if self.filled >= self.prbs.order() && self.state != 0 {
+                self.locked = true;
+                self.window_bits = 0;
+                self.window_errors = 0;
+            }
+            return;
+        }
+
+        let expected = self.prbs.next(self.state);
+        self.state = ((self.state << 1) | expected as u32) & self.prbs.mask();
+        let error = (expected != bit) as u64;
+        self.bits += 1;
+        self.errors += error;
+        self.window_bits += 1;
+        self.window_errors += error;
+        if self.window_bits == WINDOW {
+            if self.window_errors > SYNC_LOSS {
+                // drop the window that caused the loss, it was counting against the wrong phase
+                self.bits -= WINDOW;
+                self.errors -= self.window_errors;
+                self.locked = false;
+                self.filled = 0;
+                self.resyncs += 1;
+            }
+            self.window_bits = 0;
+            self.window_errors = 0;
+        }
+    }
+}
+
+
+impl Sink<u8> for BerCounter {
+    fn write(&mut self, src: &[u8]) -> Result<(), Box<dyn Error>> {
+        for &bit in src {
+            self.push(bit);
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for BerCounter {}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::ber::{BerCounter, Prbs, PrbsSource};
+    use crate::traits::{Sink, Source};
+
+    #[test]
+    fn test_prbs_ber() -> Result<(), Box<dyn Error>> {
+        for prbs in [Prbs::Prbs9, Prbs::Prbs15, Prbs::Prbs23] {
+            let mut bits = Vec::new();
+            PrbsSource::new(prbs, 20000).read(&mut bits)?;
+            // maximal length: the register state repeats exactly after the period
+            if prbs.period() < 20000 {
+                let period = prbs.period() as usize;
+                assert_eq!(bits[..100], bits[period..period + 100]);
+                assert_ne!(bits[..100], bits[period - 1..period + 99]);
+            }
+
+            // join the stream at an arbitrary point and flip every 100th bit
+            let mut received = bits[1234..].to_vec();
+            for bit in received.iter_mut().step_by(100).skip(1) {
+                *bit ^= 1;
+            }
+            let mut counter = BerCounter::new(prbs);
+            counter.write(&received)?;
+            assert!(counter.locked());
+            assert_eq!(counter.resyncs(), 0);
+            assert!((counter.ber() - 0.01).abs() < 1e-3, "{:?} {}", prbs, counter.ber());
+        }
+
+        // a slipped bit loses lock once and the counter reacquires
+        let mut bits = Vec::new();
+        PrbsSource::new(Prbs::Prbs9, 20000).read(&mut bits)?;
+        bits.remove(5000);
+        let mut counter = BerCounter::new(Prbs::Prbs9);
+        counter.write(&bits)?;
+        assert_eq!(counter.resyncs(), 1);
+        assert!(counter.locked());
+        assert!(counter.ber() < 0.05);
+
+        // resetting mid window and then losing the lock only drops what came after the reset
+        let mut counter = BerCounter::new(Prbs::Prbs9);
+        counter.write(&bits[..1500])?;
+        counter.reset_counts();
+        // one whole window of inverted bits
+        let inverted: Vec<u8> = bits[1500..2500].iter().map(|b| b ^ 1).collect();
+        counter.write(&inverted)?;
+        assert_eq!(counter.resyncs(), 1);
+        assert_eq!((counter.bits(), counter.errors()), (0, 0));
+        Ok(())
+    }
+
+}
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use num_complex::Complex32;
+use crate::rate::RateAware;
+use crate::traits::*;
+
+
+/// Impulse noise blanker for the full rate I/Q stream, before any channel filter smears the pulses out.
+/// Samples whose magnitude exceeds `threshold` times the running average are zeroed together with
+/// `guard` samples on either side, which delays the stream by `guard` samples. A "pulse" lasting longer
+/// than `guard` is taken as a step in the signal level and the reference average starts over from it.
+pub struct NoiseBlanker {
+    threshold: f32,
+    guard: usize,
+    rate: f32,
+    average: f32,
+    averaged: u64,
+    pulse: usize,
+    delay: VecDeque<Complex32>,
+    remaining: usize,
+    blanked: u64,
+    enabled: bool,
+}
+
+
+impl NoiseBlanker {
+    pub fn new(threshold: f32, guard: usize) -> Self {
+        Self {
+            threshold,
+            guard,
+            rate: 1e-3,
+            average: 0.0,
+            averaged: 0,
+            pulse: 0,
+            delay: VecDeque::from(vec![Complex32::new(0.0, 0.0); guard]),
+            remaining: 0,
+            blanked: 0,
+            enabled: true,
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// Passes samples unchanged, still delayed, while disabled.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Averaging weight of each sample for the reference level.
+    pub fn set_average_rate(&mut self, rate: f32) {
+        self.rate = rate.clamp(1e-6, 1.0);
+    }
+
+    /// Samples zeroed so far.
+    pub fn blanked(&self) -> u64 {
+        self.blanked
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for NoiseBlanker {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        let zero = Complex32::new(0.0, 0.0);
+        for &sample in input {
+            let magnitude = sample.norm();
+            // a plain mean until the running average has settled
+            let settled = self.averaged as f32 * self.rate >= 1.0;
+            let mut impulse = self.enabled && settled && magnitude > self.threshold * self.average;
+            self.pulse = if impulse { self.pulse + 1 } else { 0 };
+            if self.pulse > self.guard {
+                // too long for an impulse, the level has stepped up
+                impulse = false;
+                self.pulse = 0;
+                self.average = 0.0;
+                self.averaged = 0;
+            }
+            if impulse {
+                // the pulse's leading edge is already in the delay line
+                for delayed in self.delay.iter_mut().filter(|x| **x != zero) {
+                    *delayed = zero;
+                    self.blanked += 1;
+                }
+                self.remaining = self.guard + 1;
+            } else {
+                // pulses stay out of the reference level
+                self.averaged += 1;
+                self.average += (magnitude - self.average) * self.rate.max(1.0 / self.averaged as f32);
+            }
+
+            let sample = if self.remaining > 0 {
+                self.remaining -= 1;
+                self.blanked += 1;
+                zero
+            } else {
+                sample
+            };
+            self.delay.push_back(sample);
+            output.push(self.delay.pop_front().unwrap());
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for NoiseBlanker {}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_complex::Complex32;
+    use crate::blanker::NoiseBlanker;
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_noise_blanker() -> Result<(), Box<dyn Error>> {
+        let mut input: Vec<Complex32> = (0..20000).map(|n| Complex32::from_polar(0.1, n as f32 * 0.05)).collect();
+        // a 3 sample impulse with a small leading edge
+        input[10000] = Complex32::new(0.15, 0.0);
+        input[10001] = Complex32::new(5.0, 0.0);
+        input[10002] = Complex32::new(4.0, 0.0);
+
+        let mut blanker = NoiseBlanker::new(5.0, 4);
+        let mut output = Vec::new();
+        blanker.filter(&input, &mut output)?;
+        assert_eq!(output.len(), input.len());
+        // delayed by the guard, the impulse and 4 samples around it are gone
+        assert_eq!(output[4..].iter().filter(|x| x.norm() == 0.0).count() as u64, blanker.blanked());
+        assert!(output[10001..=10010].iter().all(|x| x.norm() == 0.0));
+        assert!(output.iter().all(|x| x.norm() < 0.11));
+        assert_eq!(output[5000], input[5000 - 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_noise_blanker_level_step() -> Result<(), Box<dyn Error>> {
+        // a strong signal coming on is not blanked for good
+        let input: Vec<Complex32> = (0..20000).map(|n| Complex32::from_polar(if n < 10000 { 0.01 } else { 1.0 }, n as f32 * 0.05)).collect();
+
+        let mut blanker = NoiseBlanker::new(5.0, 4);
+        let mut output = Vec::new();
+        blanker.filter(&input, &mut output)?;
+        assert!(blanker.blanked() < 20);
+        assert!(output[10100..].iter().all(|x| x.norm() > 0.99));
+
+        // impulses on the new level are blanked again once the average has settled
+        let mut input = input[10000..].to_vec();
+        input[5000] = Complex32::new(20.0, 0.0);
+        let blanked = blanker.blanked();
+        blanker.filter(&input, &mut output)?;
+        assert_eq!(blanker.blanked() - blanked, 9);
+        Ok(())
+    }
+
+}
@@ -6,13 +6,29 @@ use std::fs::File;
 use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, Write};
 use std::ops::{AddAssign, Mul};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use cpal::{BufferSize, Stream, StreamConfig};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
 use libhackrf::HackRf;
 use num_complex::{Complex, Complex32};
 use num_traits::{One, Zero};
-use crate::streambuf::{new_stream, StreamReader, StreamWriter};
+use crate::streambuf::{new_stream, BroadcastWriter, StreamReader, StreamWriter};
+use crate::gain::{ClipDetector, HackRFGain};
+use crate::error::{check_deviation, check_range, check_taps, ConfigError};
+use crate::pipeline::CancelToken;
+use crate::ppm::{corrected_frequency, stored_ppm};
+use crate::rate::{RateAware, SampleRate};
+use crate::replay::Recordable;
+use crate::sample::to_float;
+use crate::sizing::BufferSizer;
+use crate::state::{primed_history, Prime, StateReader, StateWriter, Stateful};
+use crate::tag::{Tag, TagValue, Tagged};
+use crate::timing::{ClockMismatch, SampleCounters};
+use crate::tuning::Tunable;
 use crate::traits::*;
 use crate::util::{lowpass_complex, lowpass_taps, resize_unchecked};
 
@@ -21,31 +37,96 @@ pub struct WavSource<D: Read> {
     reader: WavReader<D>,
     samples_per_buffer: usize,
     ratio: f32,
+    resample: Option<Resample>,
+}
+
+
+/// What `WavSource::resample_to` sets up, a resampler for either kind of sample and its input.
+struct Resample {
+    rate: u32,
+    real: WavResampler<f32>,
+    complex: WavResampler<Complex32>,
+    real_buffer: Vec<f32>,
+    complex_buffer: Vec<Complex32>,
+}
+
+
+/// Cubic interpolation going up in rate. Going down a `RationalResampler` low-passes first, so what
+/// the file has above the new Nyquist doesn't alias.
+enum WavResampler<T: FloatLike> {
+    Up(FractionalResampler<T>),
+    Down(RationalResampler<T>),
+}
+
+
+impl<T: FloatLike + From<f32>> WavResampler<T> {
+    fn new(native: u32, rate: u32) -> Result<Self, ConfigError> {
+        Ok(match rate < native {
+            true => Self::Down(RationalResamplerBuilder::new(native, rate).taps_per_ratio().build()?),
+            false => Self::Up(FractionalResampler::new(native, rate)),
+        })
+    }
+
+    fn filter(&mut self, input: &[T], output: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Up(resampler) => resampler.filter(input, output),
+            Self::Down(resampler) => resampler.filter(input, output),
+        }
+    }
+
+    fn delay(&self) -> f64 {
+        match self {
+            Self::Up(resampler) => resampler.delay(),
+            Self::Down(resampler) => resampler.delay(),
+        }
+    }
 }
 
 
 impl WavSource<BufReader<File>> {
     pub fn new(path: PathBuf, samples_per_buffer: usize) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_reader(WavReader::open(path)?, samples_per_buffer))
+    }
+}
+
+
+impl<D: Read> WavSource<D> {
+    pub fn from_reader(reader: WavReader<D>, samples_per_buffer: usize) -> Self {
         let mut it = Self {
-            reader: WavReader::open(path)?,
+            reader,
             samples_per_buffer,
             ratio: 0f32,
+            resample: None,
         };
         if samples_per_buffer == 0 {
             it.samples_per_buffer = it.reader.spec().sample_rate as usize;
         }
         it.ratio = ((1 << it.reader.spec().bits_per_sample) - 1) as f32;
-        Ok(it)
+        it
     }
 
     pub fn spec(&self) -> WavSpec {
         self.reader.spec()
     }
-}
 
+    /// Output `rate` whatever the file was recorded at, through a `FractionalResampler` going up and
+    /// a band limited `RationalResampler` going down.
+    pub fn resample_to(mut self, rate: u32) -> Result<Self, ConfigError> {
+        let native = self.reader.spec().sample_rate;
+        self.resample = match rate != native {
+            true => Some(Resample {
+                rate,
+                real: WavResampler::new(native, rate)?,
+                complex: WavResampler::new(native, rate)?,
+                real_buffer: Vec::new(),
+                complex_buffer: Vec::new(),
+            }),
+            false => None,
+        };
+        Ok(self)
+    }
 
-impl<D: Read> Source<f32> for WavSource<D> {
-    fn read(&mut self, dst: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+    fn read_real(&mut self, dst: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
         debug_assert!(self.reader.spec().channels == 1);
         dst.clear();
         let it = self.reader.samples::<i32>();
@@ -57,11 +138,8 @@ impl<D: Read> Source<f32> for WavSource<D> {
         }
         Ok(())
     }
-}
-
 
-impl<D: Read> Source<Complex32> for WavSource<D> {
-    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+    fn read_complex(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
         debug_assert!(self.reader.spec().channels == 2);
         dst.clear();
         let mut it = self.reader.samples::<i32>();
@@ -81,6 +159,116 @@ impl<D: Read> Source<Complex32> for WavSource<D> {
 }
 
 
+impl<D: Read> Source<f32> for WavSource<D> {
+    fn read(&mut self, dst: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        let Some(mut resample) = self.resample.take() else { return self.read_real(dst) };
+        dst.clear();
+        let mut result = Ok(());
+        while dst.is_empty() && result.is_ok() {
+            result = self.read_real(&mut resample.real_buffer);
+            if result.is_err() || resample.real_buffer.is_empty() {
+                break;
+            }
+            result = resample.real.filter(&resample.real_buffer, dst);
+        }
+        // put back whatever happened, a failed read leaves the resampler in place for the next one
+        self.resample = Some(resample);
+        result
+    }
+}
+
+
+impl<D: Read> Source<Complex32> for WavSource<D> {
+    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        let Some(mut resample) = self.resample.take() else { return self.read_complex(dst) };
+        dst.clear();
+        let mut result = Ok(());
+        while dst.is_empty() && result.is_ok() {
+            result = self.read_complex(&mut resample.complex_buffer);
+            if result.is_err() || resample.complex_buffer.is_empty() {
+                break;
+            }
+            result = resample.complex.filter(&resample.complex_buffer, dst);
+        }
+        self.resample = Some(resample);
+        result
+    }
+}
+
+
+impl<D: Read> RateAware for WavSource<D> {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.resample.as_ref().map_or(self.reader.spec().sample_rate, |resample| resample.rate)))
+    }
+
+    fn delay(&self) -> f64 {
+        self.resample.as_ref().map_or(0.0, |resample| resample.real.delay())
+    }
+}
+
+
+/// Coherent channels recorded into one wav file, each channel stored as an I/Q pair of wav channels.
+pub struct WavCoherentSource<D: Read> {
+    reader: WavReader<D>,
+    samples_per_buffer: usize,
+    ratio: f32,
+}
+
+
+impl WavCoherentSource<BufReader<File>> {
+    pub fn new(path: PathBuf, samples_per_buffer: usize) -> Result<Self, Box<dyn Error>> {
+        Self::from_reader(WavReader::open(path)?, samples_per_buffer)
+    }
+}
+
+
+impl<D: Read> WavCoherentSource<D> {
+    pub fn from_reader(reader: WavReader<D>, samples_per_buffer: usize) -> Result<Self, Box<dyn Error>> {
+        let spec = reader.spec();
+        if spec.channels < 2 || !spec.channels.is_multiple_of(2) {
+            return Err(format!("expected an even number of wav channels, got {}", spec.channels).into());
+        }
+        let samples_per_buffer = if samples_per_buffer == 0 { spec.sample_rate as usize } else { samples_per_buffer };
+        Ok(Self {
+            reader,
+            samples_per_buffer,
+            ratio: ((1 << spec.bits_per_sample) - 1) as f32,
+        })
+    }
+}
+
+
+impl<D: Read> CoherentSource<Complex32> for WavCoherentSource<D> {
+    fn channels(&self) -> usize {
+        self.reader.spec().channels as usize / 2
+    }
+
+    fn read_coherent(&mut self, dst: &mut [Vec<Complex32>]) -> Result<(), Box<dyn Error>> {
+        let channels = self.channels();
+        debug_assert!(dst.len() == channels);
+        for channel in dst.iter_mut() {
+            channel.clear();
+        }
+
+        let mut frame = vec![0f32; 2 * channels];
+        let mut it = self.reader.samples::<i32>();
+        'outer: for _ in 0..self.samples_per_buffer {
+            for (i, value) in frame.iter_mut().enumerate() {
+                match it.next() {
+                    Some(sample) => *value = sample? as f32 / self.ratio,
+                    None if i == 0 => break 'outer,
+                    None => return Err(Box::new(std::io::Error::new(ErrorKind::UnexpectedEof, "unexpected eof"))),
+                }
+            }
+            for (channel, iq) in dst.iter_mut().zip(frame.chunks_exact(2)) {
+                channel.push(Complex32::new(iq[0], iq[1]));
+            }
+        }
+        Ok(())
+    }
+}
+
+
 pub struct WavSink<D: Write + Seek> {
     writer: WavWriter<D>,
     ratio: f32,
@@ -151,132 +339,454 @@ impl<D: Write + Seek> Sink<Complex32> for WavSink<D> {
 }
 
 
+impl<D: Write + Seek> RateAware for WavSink<D> {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.writer.spec().sample_rate))
+    }
+}
+
+
+/// What `CpalSource` and `CpalSink` do when the audio backend reports an error, e.g. a USB device being unplugged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioErrorPolicy {
+    /// Return the error from the next read or write.
+    Fail,
+    /// Rebuild the stream on the default device, waiting `interval` between tries. `attempts` of 0 retries forever.
+    Reopen { attempts: usize, interval: Duration },
+}
+
+
+impl Default for AudioErrorPolicy {
+    fn default() -> Self {
+        AudioErrorPolicy::Reopen { attempts: 10, interval: Duration::from_millis(500) }
+    }
+}
+
+
+/// How long a blocked read or write waits before checking for device errors.
+const AUDIO_POLL: Duration = Duration::from_millis(100);
+
+
+/// Audio buffering by default, enough to ride out a stalled pipeline.
+pub const DEFAULT_AUDIO_BUFFER: Duration = Duration::from_secs(1);
+
+/// Audio buffering for live monitoring, the device has to be fed without pauses.
+pub const LOW_LATENCY_AUDIO_BUFFER: Duration = Duration::from_millis(20);
+
+
+fn buffer_samples(sample_rate: u32, channels: u16, buffer: Duration) -> usize {
+    ((sample_rate as f64 * buffer.as_secs_f64()) as usize * channels as usize).max(1)
+}
+
+
+/// Error side of a cpal stream, reported from the callbacks instead of panicking in the audio thread.
+struct AudioErrors {
+    policy: AudioErrorPolicy,
+    sender: Sender<cpal::StreamError>,
+    receiver: Receiver<cpal::StreamError>,
+    reopens: usize,
+}
+
+
+impl AudioErrors {
+    fn new() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            policy: AudioErrorPolicy::default(),
+            sender,
+            receiver,
+            reopens: 0,
+        }
+    }
+
+    fn callback(&self) -> impl FnMut(cpal::StreamError) + Send + 'static {
+        let sender = self.sender.clone();
+        move |error| {
+            let _ = sender.send(error);
+        }
+    }
+
+    /// Handle pending errors, rebuilding the stream with `build` when the policy allows. `build` hands
+    /// back a playing stream.
+    fn recover<S>(&mut self, stream: &mut Option<S>, mut build: impl FnMut(&Self) -> Result<S, Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+        let Ok(error) = self.receiver.try_recv() else {
+            return Ok(());
+        };
+
+        let (attempts, interval) = match self.policy {
+            AudioErrorPolicy::Fail => return Err(Box::new(error)),
+            AudioErrorPolicy::Reopen { attempts, interval } => (attempts, interval),
+        };
+
+        *stream = None;
+        let mut last = error.to_string();
+        let mut attempt = 0;
+        while attempts == 0 || attempt < attempts {
+            std::thread::sleep(interval);
+            attempt += 1;
+            // errors from the stream that just died are stale
+            while self.receiver.try_recv().is_ok() {}
+            match build(self) {
+                Ok(it) => {
+                    *stream = Some(it);
+                    self.reopens += 1;
+                    return Ok(());
+                },
+                Err(e) => last = e.to_string(),
+            }
+        }
+        Err(format!("audio device lost and not recovered after {} attempts: {}", attempts, last).into())
+    }
+}
+
+
 pub struct CpalSource {
-    audio_stream: Stream,
+    audio_stream: Option<Stream>,
     config: StreamConfig,
     reader: StreamReader<f32>,
+    writer: Arc<StreamWriter<f32>>,
+    errors: AudioErrors,
+    sizer: Option<BufferSizer>,
 }
 
 impl CpalSource {
+    /// Sizes its buffer from how long the pipeline takes between reads, up to `DEFAULT_AUDIO_BUFFER`.
     pub fn new(sample_rate: u32) -> Result<Self, Box<dyn Error>> {
-        let host = cpal::default_host();
-        let device = host.default_input_device().ok_or("unable to open default input audio device")?;
+        let sizer = BufferSizer::new(sample_rate).limits(LOW_LATENCY_AUDIO_BUFFER, DEFAULT_AUDIO_BUFFER);
+        let mut source = Self::with_buffer(sample_rate, LOW_LATENCY_AUDIO_BUFFER)?;
+        source.sizer = Some(sizer);
+        Ok(source)
+    }
 
+    /// Holds at most `buffer` of audio, older samples are overwritten when reads fall behind.
+    pub fn with_buffer(sample_rate: u32, buffer: Duration) -> Result<Self, Box<dyn Error>> {
         let config = StreamConfig {
             channels: 1,
             sample_rate: cpal::SampleRate(sample_rate as u32),
             buffer_size: BufferSize::Default,
         };
 
-        let (reader, writer) = new_stream::<f32>(sample_rate as usize, true, false, true)?;
+        let (reader, writer) = new_stream::<f32>(buffer_samples(sample_rate, 1, buffer), true, false, true)?;
+        let writer = Arc::new(writer);
+        let errors = AudioErrors::new();
+
+        let stream = Self::build_stream(&config, &writer, &errors)?;
+
+        Ok(Self {
+            audio_stream: Some(stream),
+            config,
+            reader,
+            writer,
+            errors,
+            sizer: None,
+        })
+    }
 
+    /// Input stream on the default device, already playing.
+    fn build_stream(config: &StreamConfig, writer: &Arc<StreamWriter<f32>>, errors: &AudioErrors) -> Result<Stream, Box<dyn Error>> {
+        let host = cpal::default_host();
+        let device = host.default_input_device().ok_or("unable to open default input audio device")?;
 
-        let stream = device.build_input_stream(&config, move |data: &[f32], _: &cpal::InputCallbackInfo| {
-            writer.put(data).unwrap();
+        let writer = Arc::clone(writer);
+        let stream = device.build_input_stream(config, move |data: &[f32], _: &cpal::InputCallbackInfo| {
+            if !data.is_empty() {
+                let _ = writer.put(data);
+            }
         },
-                                               move |error: cpal::StreamError| {
-                                                   panic!("{}", error);
-                                               },
+                                               errors.callback(),
                                                None
         )?;
+        stream.play()?;
+        Ok(stream)
+    }
 
-        let src = Self {
-            audio_stream: stream,
-            config,
-            reader,
-        };
+    pub fn set_error_policy(&mut self, policy: AudioErrorPolicy) {
+        self.errors.policy = policy;
+    }
 
-        src.audio_stream.play()?;
+    /// Stop a blocked `read` with `ErrorKind::Interrupted` once `cancel` fires.
+    pub fn set_cancel(&mut self, cancel: &CancelToken) {
+        self.reader.set_cancel(cancel);
+    }
+
+    /// Number of times the stream has been rebuilt after a device error.
+    pub fn reopens(&self) -> usize {
+        self.errors.reopens
+    }
 
-        Ok(src)
+    /// Audio the buffer can hold before the oldest is overwritten.
+    pub fn buffer(&self) -> Duration {
+        Duration::from_secs_f64(self.reader.capacity() as f64 / self.config.sample_rate.0 as f64)
     }
 }
 
 
 impl Source<f32> for CpalSource {
     fn read(&mut self, dst: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        if let Some(sizer) = self.sizer.as_mut() {
+            sizer.observe_since_release(self.reader.queued());
+            // growing keeps everything queued, shrinking waits until nothing would be lost
+            if let Some(capacity) = sizer.resize(self.reader.capacity()) && self.reader.queued() <= capacity {
+                self.reader.set_capacity(capacity);
+            }
+        }
+        let result = self.read_stream(dst);
+        if let Some(sizer) = self.sizer.as_mut() {
+            sizer.release();
+        }
+        result
+    }
+}
+
+
+impl CpalSource {
+    fn read_stream(&mut self, dst: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
         let sample_rate = self.config.sample_rate.0 as usize;
         unsafe { resize_unchecked(dst, sample_rate); }
 
-        let read = self.reader.get(dst.as_mut_slice())?;
-        unsafe { resize_unchecked(dst, read); }
-        Ok(())
+        loop {
+            let (config, writer) = (&self.config, &self.writer);
+            if let Err(e) = self.errors.recover(&mut self.audio_stream, |errors| Self::build_stream(config, writer, errors)) {
+                unsafe { resize_unchecked(dst, 0); }
+                return Err(e);
+            }
+            match self.reader.get_timeout(dst.as_mut_slice(), AUDIO_POLL) {
+                Ok(read) => {
+                    unsafe { resize_unchecked(dst, read); }
+                    return Ok(());
+                },
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    unsafe { resize_unchecked(dst, 0); }
+                    return Err(Box::new(e));
+                },
+            }
+        }
+    }
+}
+
+
+impl RateAware for CpalSource {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.config.sample_rate.0))
+    }
+
+    fn delay(&self) -> f64 {
+        self.reader.queued() as f64
     }
 }
 
 
 pub struct CpalSink {
-    audio_stream: Stream,
+    audio_stream: Option<Stream>,
     config: StreamConfig,
+    device: Option<String>,
+    reader: Arc<StreamReader<f32>>,
     writer: StreamWriter<f32>,
+    errors: AudioErrors,
+    sizer: Option<BufferSizer>,
+    latency_cap: Option<usize>,
+    dropped: u64,
+    drops: Vec<Sender<usize>>,
+    counters: Arc<SampleCounters>,
 }
 
 impl CpalSink {
     pub fn new(sample_rate: u32, channels: u16) -> Result<Self, Box<dyn Error>> {
-        let host = cpal::default_host();
-        let device = host.default_output_device().ok_or("unable to open default output audio device")?;
+        Self::with_device(sample_rate, channels, None)
+    }
 
+    /// Plays on the output device called `device`, or the default one. The queue starts small and
+    /// grows with the pipeline's stalls between writes, up to `DEFAULT_AUDIO_BUFFER`.
+    pub fn with_device(sample_rate: u32, channels: u16, device: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let sizer = BufferSizer::new(sample_rate).limits(LOW_LATENCY_AUDIO_BUFFER, DEFAULT_AUDIO_BUFFER);
+        let mut sink = Self::with_buffer(sample_rate, channels, device, LOW_LATENCY_AUDIO_BUFFER)?;
+        sink.sizer = Some(sizer);
+        Ok(sink)
+    }
+
+    /// Queues at most `buffer` of audio ahead of the device, writes block once it's full.
+    pub fn with_buffer(sample_rate: u32, channels: u16, device: Option<&str>, buffer: Duration) -> Result<Self, Box<dyn Error>> {
+        let device = device.map(str::to_string);
         let config = StreamConfig {
             channels,
             sample_rate: cpal::SampleRate(sample_rate),
             buffer_size: BufferSize::Default,
         };
 
-        let (reader, writer) = new_stream::<f32>(sample_rate as usize, false, true, false)?;
+        let (reader, writer) = new_stream::<f32>(buffer_samples(sample_rate, channels, buffer), false, true, false)?;
+        let reader = Arc::new(reader);
+        let errors = AudioErrors::new();
 
+        let counters = Arc::new(SampleCounters::default());
 
-        let stream = device.build_output_stream(&config, move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            let result = reader.get(data);
-            match result {
-                Ok(0) => {
-                    data.fill(0f32);
-                },
-                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
-                    data.fill(0f32);
-                },
+        let stream = Self::build_stream(&config, device.as_deref(), &reader, &counters, &errors)?;
+
+        Ok(Self {
+            audio_stream: Some(stream),
+            config,
+            device,
+            reader,
+            writer,
+            errors,
+            sizer: None,
+            latency_cap: None,
+            dropped: 0,
+            drops: Vec::new(),
+            counters,
+        })
+    }
+
+    /// Output stream on `device` or the default one, already playing.
+    fn build_stream(config: &StreamConfig, device: Option<&str>, reader: &Arc<StreamReader<f32>>, counters: &Arc<SampleCounters>, errors: &AudioErrors) -> Result<Stream, Box<dyn Error>> {
+        let host = cpal::default_host();
+        let device = match device {
+            Some(name) => host.output_devices()?
+                .find(|d| d.name().is_ok_and(|n| n == name))
+                .ok_or_else(|| format!("no output audio device named {:?}", name))?,
+            None => host.default_output_device().ok_or("unable to open default output audio device")?,
+        };
+
+        let reader = Arc::clone(reader);
+        let counters = Arc::clone(counters);
+        let stream = device.build_output_stream(config, move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+            match reader.get(data) {
                 Ok(read) => {
-                    if read < data.len() {
-                        data[read..].fill(0f32);
-                    }
+                    data[read..].fill(0f32);
+                    counters.consumed.fetch_add(read as u64, Ordering::Relaxed);
                 },
-                Err(e) => {
-                    panic!("{}", e);
-                }
+                Err(_) => data.fill(0f32),
             }
         },
-                                                move |error: cpal::StreamError| {
-                                                    panic!("{}", error);
-                                                },
+                                                errors.callback(),
                                                 None
         )?;
+        stream.play()?;
+        Ok(stream)
+    }
 
-        let dst = Self {
-            audio_stream: stream,
-            config,
-            writer,
-        };
+    pub fn set_error_policy(&mut self, policy: AudioErrorPolicy) {
+        self.errors.policy = policy;
+    }
+
+    /// Number of times the stream has been rebuilt after a device error.
+    pub fn reopens(&self) -> usize {
+        self.errors.reopens
+    }
+
+    /// Never queue more than `cap` of audio: instead of blocking a write that would go over it, the
+    /// oldest queued audio is dropped. A slow consumer then skips ahead instead of drifting behind.
+    pub fn set_latency_cap(&mut self, cap: Option<Duration>) {
+        let channels = self.config.channels as usize;
+        self.latency_cap = cap.map(|cap| buffer_samples(self.config.sample_rate.0, 1, cap) * channels);
+        if let Some(cap) = self.latency_cap && self.writer.capacity() < cap {
+            self.writer.set_capacity(cap);
+        }
+    }
 
-        dst.audio_stream.play()?;
+    /// Samples dropped to stay under the latency cap.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Receives the number of samples dropped every time the latency cap kicks in.
+    pub fn drops(&mut self) -> Receiver<usize> {
+        let (sender, receiver) = channel();
+        self.drops.push(sender);
+        receiver
+    }
+
+    /// Compares the rate audio is written at with the rate the sound card plays it, e.g. to feed
+    /// a `FractionalResampler` in front of this sink with `correct_drift`.
+    pub fn clock_mismatch(&self) -> ClockMismatch {
+        let rate = self.config.sample_rate.0 as f64 * self.config.channels as f64;
+        ClockMismatch::new(Arc::clone(&self.counters), rate)
+    }
 
-        Ok(dst)
+    /// Audio that can be queued ahead of the device.
+    pub fn buffer(&self) -> Duration {
+        let frames = self.writer.capacity() / self.config.channels as usize;
+        Duration::from_secs_f64(frames as f64 / self.config.sample_rate.0 as f64)
     }
 }
 
 
 impl Sink<f32> for CpalSink {
     fn write(&mut self, src: &[f32]) -> Result<(), Box<dyn Error>> {
-        let mut off = 0;
+        let channels = self.config.channels as usize;
+        if let Some(sizer) = self.sizer.as_mut() {
+            sizer.observe_since_release(src.len() / channels);
+            // shrinking waits until the device has played what would be cut
+            if let Some(frames) = sizer.resize(self.writer.capacity() / channels) && self.writer.queued() <= frames * channels {
+                self.writer.set_capacity(frames * channels);
+            }
+        }
+        let result = self.write_stream(src);
+        if let Some(sizer) = self.sizer.as_mut() {
+            sizer.release();
+        }
+        result
+    }
+}
+
+
+impl CpalSink {
+    /// Makes room for `incoming` samples under the latency cap, returns how many of them to skip.
+    fn enforce_latency_cap(&mut self, incoming: usize) -> usize {
+        let Some(cap) = self.latency_cap else {
+            return 0;
+        };
+        let channels = self.config.channels as usize;
+        // whole frames only, so the channels stay in order
+        let skip = incoming.saturating_sub(cap) / channels * channels;
+        let keep = cap.saturating_sub(incoming - skip) / channels * channels;
+        let dropped = skip + self.writer.discard_oldest(keep);
+        if dropped > 0 {
+            self.dropped += dropped as u64;
+            // dropped audio leaves the queue as if it had been played
+            self.counters.produced.fetch_add(skip as u64, Ordering::Relaxed);
+            self.counters.consumed.fetch_add(dropped as u64, Ordering::Relaxed);
+            self.drops.retain(|sender| sender.send(dropped).is_ok());
+        }
+        skip
+    }
+
+    fn write_stream(&mut self, src: &[f32]) -> Result<(), Box<dyn Error>> {
+        let mut off = self.enforce_latency_cap(src.len());
         while off < src.len() {
-            off += self.writer.put(&src[off..])?;
+            let (config, device, reader, counters) = (&self.config, self.device.as_deref(), &self.reader, &self.counters);
+            self.errors.recover(&mut self.audio_stream, |errors| Self::build_stream(config, device, reader, counters, errors))?;
+            match self.writer.put_timeout(&src[off..], AUDIO_POLL) {
+                Ok(written) => {
+                    off += written;
+                    self.counters.produced.fetch_add(written as u64, Ordering::Relaxed);
+                },
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(Box::new(e)),
+            }
         }
         Ok(())
     }
 }
 
 
+impl RateAware for CpalSink {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.config.sample_rate.0))
+    }
+
+    fn delay(&self) -> f64 {
+        (self.writer.queued() / self.config.channels as usize) as f64
+    }
+}
+
+
 impl Drop for CpalSink {
     fn drop(&mut self) {
-        self.writer.drain().unwrap()
+        // let queued audio play out, but don't hang on a device that stopped pulling samples
+        if self.audio_stream.is_some() {
+            let _ = self.writer.drain_timeout(Duration::from_secs(2));
+        }
     }
     
 }
@@ -286,87 +796,1249 @@ pub type Microphone = CpalSource;
 pub type Speakers = CpalSink;
 
 
-pub struct HackRFSource {
-    device: HackRf,
-    reader: StreamReader<Complex<i8>>,
-    samples_per_frame: usize,
+/// In-process audio loopback: samples written to `sink` come out of `source`, so demodulated audio can feed
+/// another pipeline (e.g. the AFSK decoder) without going through the OS sound system.
+pub struct VirtualAudioCable {
+    pub source: VirtualAudioSource,
+    pub sink: VirtualAudioSink,
 }
 
 
-impl Drop for HackRFSource {
-    fn drop(&mut self) {
-        self.device.stop_rx().unwrap();
+impl VirtualAudioCable {
+    /// Buffers up to a second of audio, the sink blocks once it's full.
+    pub fn new(sample_rate: u32) -> Result<Self, Box<dyn Error>> {
+        Self::with_buffer(sample_rate, DEFAULT_AUDIO_BUFFER)
+    }
+
+    pub fn with_buffer(sample_rate: u32, buffer: Duration) -> Result<Self, Box<dyn Error>> {
+        let (reader, writer) = new_stream::<f32>(buffer_samples(sample_rate, 1, buffer), false, true, true)?;
+        Ok(Self {
+            source: VirtualAudioSource {
+                reader,
+                sample_rate,
+                samples_per_read: (sample_rate as usize / 10).max(1),
+                sizer: Some(BufferSizer::new(sample_rate).limits(LOW_LATENCY_AUDIO_BUFFER, buffer)),
+            },
+            sink: VirtualAudioSink {
+                writer,
+                sample_rate,
+            },
+        })
     }
 }
 
 
-fn hackrf_rx_callback(_: &HackRf, samples: &[Complex<i8>], user: &dyn Any) {
-    if let Some(writer) = user.downcast_ref::<StreamWriter<Complex<i8>>>() {
-        writer.put(samples).unwrap();
-    }
+pub struct VirtualAudioSource {
+    reader: StreamReader<f32>,
+    sample_rate: u32,
+    samples_per_read: usize,
+    sizer: Option<BufferSizer>,
 }
 
 
-impl HackRFSource {
-    pub fn new(device: HackRf, samples_per_frame: usize) -> Result<Self, Box<dyn Error>> {
-        if samples_per_frame & 1 != 0 {
-            panic!("buffer size must be a multiple of 2");
-        }
+impl VirtualAudioSource {
+    /// A read blocked on an empty cable fails with `ErrorKind::Interrupted` once `cancel` fires.
+    pub fn set_cancel(&mut self, cancel: &CancelToken) {
+        self.reader.set_cancel(cancel);
+    }
 
-        let (reader, writer) = new_stream(samples_per_frame, true, false, true)?;
-        let it = Self {
-            device,
+    /// Read fixed size chunks instead of sizing them from how long the pipeline takes per read.
+    pub fn set_samples_per_read(&mut self, samples: usize) {
+        self.samples_per_read = samples.max(1);
+        self.sizer = None;
+    }
+
+    pub fn samples_per_read(&self) -> usize {
+        self.samples_per_read
+    }
+}
+
+
+impl Source<f32> for VirtualAudioSource {
+    /// Blocks until audio is available, returns no samples once the sink is dropped and drained.
+    fn read(&mut self, dst: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        if let Some(sizer) = self.sizer.as_mut() {
+            sizer.observe_since_release(dst.len());
+            self.samples_per_read = sizer.chunk_size();
+        }
+        unsafe { resize_unchecked(dst, self.samples_per_read); }
+        let result = self.reader.get(dst.as_mut_slice());
+        if let Some(sizer) = self.sizer.as_mut() {
+            sizer.release();
+        }
+        match result {
+            Ok(read) => {
+                unsafe { resize_unchecked(dst, read); }
+                Ok(())
+            },
+            Err(e) => {
+                unsafe { resize_unchecked(dst, 0); }
+                Err(Box::new(e))
+            },
+        }
+    }
+}
+
+
+impl RateAware for VirtualAudioSource {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+pub struct VirtualAudioSink {
+    writer: StreamWriter<f32>,
+    sample_rate: u32,
+}
+
+
+impl Sink<f32> for VirtualAudioSink {
+    fn write(&mut self, src: &[f32]) -> Result<(), Box<dyn Error>> {
+        let mut off = 0;
+        while off < src.len() {
+            off += self.writer.put(&src[off..])?;
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for VirtualAudioSink {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+
+    fn delay(&self) -> f64 {
+        self.writer.queued() as f64
+    }
+}
+
+
+/// Passes its input through unchanged while copying it to any number of branches, e.g. to record and
+/// feed a spectrum display from the audio that also goes to the speakers.
+pub struct Tee<T: Copy> {
+    broadcast: BroadcastWriter<T>,
+}
+
+
+impl<T: Copy> Tee<T> {
+    pub fn new() -> Self {
+        Self {
+            broadcast: BroadcastWriter::new(),
+        }
+    }
+
+    /// A branch that holds up the main stream when it falls more than `capacity` samples behind.
+    pub fn branch(&mut self, capacity: usize) -> Result<TeeOutput<T>, Box<dyn Error>> {
+        Ok(TeeOutput::new(self.broadcast.subscribe(capacity, false)?, capacity))
+    }
+
+    /// A branch that drops its oldest samples instead of holding up the main stream, for displays.
+    pub fn lossy_branch(&mut self, capacity: usize) -> Result<TeeOutput<T>, Box<dyn Error>> {
+        Ok(TeeOutput::new(self.broadcast.subscribe(capacity, true)?, capacity))
+    }
+
+    pub fn branches(&self) -> usize {
+        self.broadcast.subscribers()
+    }
+}
+
+
+impl<T: Copy> Default for Tee<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl<T: Copy> Filter<T, T> for Tee<T> {
+    fn filter(&mut self, input: &[T], output: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.broadcast.put(input)?;
+        output.extend_from_slice(input);
+        Ok(())
+    }
+}
+
+
+impl<T: Copy> Sink<T> for Tee<T> {
+    fn write(&mut self, src: &[T]) -> Result<(), Box<dyn Error>> {
+        Ok(self.broadcast.put(src)?)
+    }
+}
+
+
+impl<T: Copy> RateAware for Tee<T> {}
+
+
+/// One output of a `Tee`, it stops producing samples after the `Tee` is dropped and drained.
+pub struct TeeOutput<T: Copy> {
+    reader: StreamReader<T>,
+    samples_per_read: usize,
+}
+
+
+impl<T: Copy> TeeOutput<T> {
+    fn new(reader: StreamReader<T>, samples_per_read: usize) -> Self {
+        Self {
+            reader,
+            samples_per_read: samples_per_read.max(1),
+        }
+    }
+}
+
+
+impl<T: Copy> Source<T> for TeeOutput<T> {
+    fn read(&mut self, dst: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        unsafe { resize_unchecked(dst, self.samples_per_read); }
+        match self.reader.get(dst.as_mut_slice()) {
+            Ok(read) => {
+                unsafe { resize_unchecked(dst, read); }
+                Ok(())
+            },
+            Err(e) => {
+                unsafe { resize_unchecked(dst, 0); }
+                Err(Box::new(e))
+            },
+        }
+    }
+}
+
+
+/// Sample count and elapsed time since the first one, optionally printed when the block is dropped.
+struct Throughput {
+    samples: u64,
+    start: Option<Instant>,
+    report: Option<String>,
+}
+
+
+impl Throughput {
+    fn new() -> Self {
+        Self {
+            samples: 0,
+            start: None,
+            report: None,
+        }
+    }
+
+    fn count(&mut self, samples: usize) {
+        self.start.get_or_insert_with(Instant::now);
+        self.samples += samples as u64;
+    }
+
+    fn rate(&self) -> f64 {
+        let elapsed = self.start.map(|t| t.elapsed().as_secs_f64()).unwrap_or(0.0);
+        if elapsed > 0.0 { self.samples as f64 / elapsed } else { 0.0 }
+    }
+}
+
+
+impl Drop for Throughput {
+    fn drop(&mut self) {
+        if let Some(name) = &self.report {
+            eprintln!("{}: {} samples, {:.3} MS/s", name, self.samples, self.rate() / 1e6);
+        }
+    }
+}
+
+
+/// Discards everything written to it, for benchmarking the stages in front of it.
+pub struct NullSink {
+    throughput: Throughput,
+}
+
+
+impl NullSink {
+    pub fn new() -> Self {
+        Self {
+            throughput: Throughput::new(),
+        }
+    }
+
+    /// Print the sample count and throughput to stderr on drop.
+    pub fn report(mut self, name: &str) -> Self {
+        self.throughput.report = Some(name.to_string());
+        self
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.throughput.samples
+    }
+
+    /// Samples per second since the first write.
+    pub fn rate(&self) -> f64 {
+        self.throughput.rate()
+    }
+}
+
+
+impl Default for NullSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl<T> Sink<T> for NullSink {
+    fn write(&mut self, src: &[T]) -> Result<(), Box<dyn Error>> {
+        self.throughput.count(src.len());
+        Ok(())
+    }
+}
+
+
+impl RateAware for NullSink {}
+
+
+/// Produces zeros as fast as it's read, standing in for a real source that runs at `sample_rate`.
+pub struct NullSource {
+    sample_rate: u32,
+    samples_per_read: usize,
+    limit: Option<u64>,
+    throughput: Throughput,
+}
+
+
+impl NullSource {
+    pub fn new(sample_rate: u32, samples_per_read: usize) -> Self {
+        Self {
+            sample_rate,
+            samples_per_read,
+            limit: None,
+            throughput: Throughput::new(),
+        }
+    }
+
+    /// End the stream after `samples` samples.
+    pub fn limit(mut self, samples: u64) -> Self {
+        self.limit = Some(samples);
+        self
+    }
+
+    /// Print the sample count and throughput to stderr on drop.
+    pub fn report(mut self, name: &str) -> Self {
+        self.throughput.report = Some(name.to_string());
+        self
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.throughput.samples
+    }
+
+    /// Samples per second since the first read.
+    pub fn rate(&self) -> f64 {
+        self.throughput.rate()
+    }
+}
+
+
+impl<T: Zero + Clone> Source<T> for NullSource {
+    fn read(&mut self, dst: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        let remaining = self.limit.map(|l| l - self.throughput.samples).unwrap_or(u64::MAX);
+        let len = (self.samples_per_read as u64).min(remaining) as usize;
+        dst.clear();
+        dst.resize(len, T::zero());
+        self.throughput.count(len);
+        Ok(())
+    }
+}
+
+
+impl RateAware for NullSource {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+fn check_speed(speed: f64) -> Result<(), ConfigError> {
+    if !(speed > 0.0 && speed.is_finite()) {
+        return Err(ConfigError::Invalid(format!("playback speed must be positive, got {}", speed)));
+    }
+    Ok(())
+}
+
+
+/// Paces a source to wall clock time, e.g. to replay a recording as if it came off the air.
+/// `speed` scales the pace without touching the samples, 2.0 plays twice as fast.
+pub struct Throttle<S> {
+    source: S,
+    sample_rate: u32,
+    speed: f64,
+    start: Option<Instant>,
+    samples: u64,
+}
+
+
+impl<S> Throttle<S> {
+    pub fn new(source: S, sample_rate: u32) -> Self {
+        Self {
+            source,
+            sample_rate,
+            speed: 1.0,
+            start: None,
+            samples: 0,
+        }
+    }
+
+    pub fn speed(mut self, speed: f64) -> Result<Self, ConfigError> {
+        self.set_speed(speed)?;
+        Ok(self)
+    }
+
+    /// Takes effect from the next read, samples already released are not caught up on.
+    pub fn set_speed(&mut self, speed: f64) -> Result<(), ConfigError> {
+        check_speed(speed)?;
+        self.speed = speed;
+        self.start = None;
+        self.samples = 0;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+
+impl<T, S: Source<T>> Source<T> for Throttle<S> {
+    fn read(&mut self, dst: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.source.read(dst)?;
+        self.samples += dst.len() as u64;
+        let due = Duration::from_secs_f64(self.samples as f64 / (self.sample_rate as f64 * self.speed));
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        Ok(())
+    }
+}
+
+
+impl<S: Tagged> Tagged for Throttle<S> {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        self.source.take_tags(dst);
+    }
+}
+
+
+impl<S> RateAware for Throttle<S> {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+/// Replays a recording with timestamp tags, e.g. a `GrMetaSource`, at its original pace: samples
+/// run at `sample_rate` and every timestamp moves the schedule, so the gaps between bursts come back too.
+pub struct TimedReplay<S> {
+    source: S,
+    sample_rate: u32,
+    speed: f64,
+    max_gap: Option<Duration>,
+    start: Option<Instant>,
+    origin: Option<SystemTime>,
+    /// Recording time in seconds since `origin`, less skipped gaps, of the sample at `ref_offset`.
+    ref_secs: f64,
+    ref_offset: u64,
+    skipped: f64,
+    position: u64,
+    tags: Vec<Tag>,
+}
+
+
+impl<S> TimedReplay<S> {
+    pub fn new(source: S, sample_rate: u32) -> Self {
+        Self {
+            source,
+            sample_rate,
+            speed: 1.0,
+            max_gap: None,
+            start: None,
+            origin: None,
+            ref_secs: 0.0,
+            ref_offset: 0,
+            skipped: 0.0,
+            position: 0,
+            tags: Vec::new(),
+        }
+    }
+
+    /// 2.0 replays twice as fast, gaps included.
+    pub fn speed(mut self, speed: f64) -> Result<Self, ConfigError> {
+        check_speed(speed)?;
+        self.speed = speed;
+        Ok(self)
+    }
+
+    /// Shorten longer silences to `max_gap`, so an hour of capture with three bursts doesn't take an hour.
+    pub fn max_gap(mut self, max_gap: Duration) -> Self {
+        self.max_gap = Some(max_gap);
+        self
+    }
+
+    fn timestamp(&mut self, offset: u64, time: SystemTime) {
+        let origin = *self.origin.get_or_insert(time);
+        let secs = time.duration_since(origin).map_or(0.0, |d| d.as_secs_f64());
+        // tags can come in behind the last reference
+        let expected = self.ref_secs + (offset as f64 - self.ref_offset as f64) / self.sample_rate as f64;
+        let mut actual = secs - self.skipped;
+        if let Some(max_gap) = self.max_gap && actual - expected > max_gap.as_secs_f64() {
+            self.skipped += actual - expected - max_gap.as_secs_f64();
+            actual = expected + max_gap.as_secs_f64();
+        }
+        self.ref_secs = actual;
+        self.ref_offset = offset;
+    }
+}
+
+
+impl<T, S: Source<T> + Tagged> Source<T> for TimedReplay<S> {
+    fn read(&mut self, dst: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.source.read(dst)?;
+
+        let first = self.tags.len();
+        self.source.take_tags(&mut self.tags);
+        for i in first..self.tags.len() {
+            if let TagValue::Timestamp(time) = self.tags[i].value {
+                self.timestamp(self.tags[i].offset, time);
+            }
+        }
+
+        // the block goes out when its first sample is due, a timestamp inside it can be ahead of that
+        let due = self.ref_secs + (self.position as f64 - self.ref_offset as f64) / self.sample_rate as f64;
+        self.position += dst.len() as u64;
+        if let Some(wait) = Duration::from_secs_f64(due.max(0.0) / self.speed).checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        Ok(())
+    }
+}
+
+
+impl<S> Tagged for TimedReplay<S> {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        dst.append(&mut self.tags);
+    }
+}
+
+
+impl<S> RateAware for TimedReplay<S> {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+/// Reported by SDR sources so an application can show a lost device instead of looking hung.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceEvent {
+    /// No samples arrived within the stall timeout.
+    DeviceLost,
+    Reconnecting { attempt: usize },
+    Reconnected,
+    /// Reconnection was given up on, `read` returns an error from now on.
+    GaveUp,
+}
+
+
+/// When an SDR source counts as stalled and how hard it tries to get the device back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub stall_timeout: Duration,
+    /// 0 retries forever.
+    pub attempts: usize,
+    pub interval: Duration,
+}
+
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            stall_timeout: Duration::from_secs(2),
+            attempts: 10,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+
+pub struct HackRFSource {
+    control: HackRFControl,
+    reader: StreamReader<Complex<i8>>,
+    samples_per_frame: usize,
+    sample_rate: u32,
+    clock: Arc<HackRFClock>,
+    position: u64,
+    /// Produced samples lost with the streams of earlier connections.
+    lost: u64,
+    tags: Vec<Tag>,
+    reconnect: Option<ReconnectPolicy>,
+    events: Vec<Sender<SourceEvent>>,
+    clipping: ClipDetector,
+    cancel: Option<CancelToken>,
+    sizer: Option<BufferSizer>,
+    /// The board to reconnect to, so another HackRF plugged in meanwhile isn't picked up instead.
+    serial: Option<String>,
+}
+
+
+struct HackRFShared {
+    device: HackRf,
+    settings: Option<HackRFSourceBuilder>,
+    frequency: u64,
+}
+
+
+/// Thread safe handle to a running `HackRFSource`, e.g. for a scanner thread or a gain control in a
+/// UI. Changes reach the device right away while it streams and are kept across reconnects.
+#[derive(Clone)]
+pub struct HackRFControl {
+    shared: Arc<Mutex<HackRFShared>>,
+}
+
+
+impl HackRFControl {
+    fn new(device: HackRf) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(HackRFShared {
+                device,
+                settings: None,
+                frequency: 0,
+            })),
+        }
+    }
+
+    pub fn frequency(&self) -> u64 {
+        self.shared.lock().unwrap().frequency
+    }
+
+    /// Retunes with the PPM correction the source was built with.
+    pub fn set_freq(&self, frequency: u64) -> Result<(), Box<dyn Error>> {
+        check_range("frequency", frequency as f64, 1e6, 6e9)?;
+        let mut shared = self.shared.lock().unwrap();
+        let ppm = shared.settings.as_ref().and_then(|settings| settings.ppm).unwrap_or(0.0);
+        shared.device.set_freq(corrected_frequency(frequency, ppm))?;
+        shared.frequency = frequency;
+        if let Some(settings) = shared.settings.as_mut() {
+            settings.frequency = frequency;
+        }
+        Ok(())
+    }
+
+    /// IF gain, 0-40 dB in 8 dB steps.
+    pub fn set_lna_gain(&self, gain: u32) -> Result<(), Box<dyn Error>> {
+        check_lna_gain(gain)?;
+        let mut shared = self.shared.lock().unwrap();
+        shared.device.set_lna_gain(gain)?;
+        if let Some(settings) = shared.settings.as_mut() {
+            settings.lna_gain = gain;
+        }
+        Ok(())
+    }
+
+    /// Baseband gain, 0-62 dB in 2 dB steps.
+    pub fn set_rxvga_gain(&self, gain: u32) -> Result<(), Box<dyn Error>> {
+        check_vga_gain(gain)?;
+        let mut shared = self.shared.lock().unwrap();
+        shared.device.set_rxvga_gain(gain)?;
+        if let Some(settings) = shared.settings.as_mut() {
+            settings.vga_gain = gain;
+        }
+        Ok(())
+    }
+
+    pub fn set_amp(&self, enable: bool) -> Result<(), Box<dyn Error>> {
+        let mut shared = self.shared.lock().unwrap();
+        shared.device.set_amp_enable(enable)?;
+        if let Some(settings) = shared.settings.as_mut() {
+            settings.amp = enable;
+        }
+        Ok(())
+    }
+
+    /// The gain the source was built with and changed to since, None for a source built without `HackRFSourceBuilder`.
+    pub fn gain(&self) -> Option<HackRFGain> {
+        let shared = self.shared.lock().unwrap();
+        shared.settings.as_ref().map(|settings| HackRFGain { amp: settings.amp, lna: settings.lna_gain, vga: settings.vga_gain })
+    }
+
+    /// All three stages at once, e.g. from `FrontEndAGC`.
+    pub fn set_gain(&self, gain: HackRFGain) -> Result<(), Box<dyn Error>> {
+        check_lna_gain(gain.lna)?;
+        check_vga_gain(gain.vga)?;
+        self.set_amp(gain.amp)?;
+        self.set_lna_gain(gain.lna)?;
+        self.set_rxvga_gain(gain.vga)
+    }
+}
+
+
+fn check_lna_gain(gain: u32) -> Result<(), ConfigError> {
+    check_range("lna gain", gain as f64, 0.0, 40.0)?;
+    if !gain.is_multiple_of(8) {
+        return Err(ConfigError::Invalid(format!("lna gain must be a multiple of 8 dB, got {}", gain)));
+    }
+    Ok(())
+}
+
+
+fn check_vga_gain(gain: u32) -> Result<(), ConfigError> {
+    check_range("vga gain", gain as f64, 0.0, 62.0)?;
+    if !gain.is_multiple_of(2) {
+        return Err(ConfigError::Invalid(format!("vga gain must be a multiple of 2 dB, got {}", gain)));
+    }
+    Ok(())
+}
+
+
+/// Tags a `HackRFSource` holds for a consumer that never takes them, the oldest go first.
+const MAX_PENDING_TAGS: usize = 1024;
+
+
+/// Samples in one libhackrf USB transfer, the rx callback never delivers less.
+const HACKRF_TRANSFER: usize = 131_072;
+
+
+/// Bounds on the frames a `HackRFSource` sizes for itself.
+pub const HACKRF_MIN_BUFFER: Duration = Duration::from_millis(10);
+pub const HACKRF_MAX_BUFFER: Duration = Duration::from_secs(1);
+
+
+/// Sample counter and host timestamps shared with the rx callback.
+#[derive(Default)]
+struct HackRFClock {
+    produced: AtomicU64,
+    timestamps: Mutex<VecDeque<(u64, SystemTime)>>,
+}
+
+
+impl HackRFClock {
+    /// Timestamp a frame from the device and queue its samples.
+    fn record(&self, writer: &StreamWriter<Complex<i8>>, samples: &[Complex<i8>]) -> std::io::Result<()> {
+        let now = SystemTime::now();
+        let first = self.produced.fetch_add(samples.len() as u64, Ordering::Relaxed);
+        self.timestamps.lock().unwrap().push_back((first, now));
+        if !samples.is_empty() {
+            writer.put(samples)?;
+        }
+        Ok(())
+    }
+
+    /// Tag the frames starting among the samples read from `start` to `end`, `skipped` being how many
+    /// produced samples were dropped ahead of them. Frames that started in dropped samples are forgotten.
+    fn take_timestamps(&self, start: u64, end: u64, skipped: u64, tags: &mut Vec<Tag>) {
+        let mut timestamps = self.timestamps.lock().unwrap();
+        while let Some(&(produced, time)) = timestamps.front() {
+            if produced >= end + skipped {
+                break;
+            }
+            if produced >= start + skipped {
+                tags.push(Tag { offset: produced - skipped, value: TagValue::Timestamp(time) });
+            }
+            timestamps.pop_front();
+        }
+    }
+}
+
+
+struct HackRFContext {
+    writer: StreamWriter<Complex<i8>>,
+    clock: Arc<HackRFClock>,
+}
+
+
+impl Drop for HackRFSource {
+    fn drop(&mut self) {
+        // the device may already be gone
+        let _ = self.control.shared.lock().unwrap().device.stop_rx();
+    }
+}
+
+
+fn hackrf_rx_callback(_: &HackRf, samples: &[Complex<i8>], user: &dyn Any) {
+    if let Some(context) = user.downcast_ref::<HackRFContext>() {
+        context.clock.record(&context.writer, samples).unwrap();
+    }
+}
+
+
+fn hackrf_start(device: &HackRf, sample_rate: u32, capacity: usize, clock: &Arc<HackRFClock>) -> Result<StreamReader<Complex<i8>>, Box<dyn Error>> {
+    device.set_sample_rate(sample_rate)?;
+    let (reader, writer) = new_stream(capacity, true, false, true)?;
+    device.start_rx(hackrf_rx_callback, HackRFContext { writer, clock: Arc::clone(clock) })?;
+    Ok(reader)
+}
+
+
+impl HackRFSource {
+    pub fn new(device: HackRf, sample_rate: u32, samples_per_frame: usize) -> Result<Self, Box<dyn Error>> {
+        if samples_per_frame & 1 != 0 {
+            panic!("buffer size must be a multiple of 2");
+        }
+
+        let clock = Arc::new(HackRFClock::default());
+        let reader = hackrf_start(&device, sample_rate, samples_per_frame, &clock)?;
+        let serial = hackrf_serial(&device).ok();
+
+        Ok(Self {
+            control: HackRFControl::new(device),
             reader,
             samples_per_frame,
+            sample_rate,
+            clock,
+            position: 0,
+            lost: 0,
+            tags: Vec::new(),
+            reconnect: Some(ReconnectPolicy::default()),
+            events: Vec::new(),
+            clipping: ClipDetector::new(),
+            cancel: None,
+            sizer: None,
+            serial,
+        })
+    }
+
+    /// Picks the frame size and buffer from how fast the pipeline reads instead of a fixed
+    /// `samples_per_frame`, between `min` and `max` of samples.
+    pub fn with_sizer(device: HackRf, sample_rate: u32, min: Duration, max: Duration) -> Result<Self, Box<dyn Error>> {
+        let sizer = BufferSizer::new(sample_rate).limits(min, max);
+        let mut source = Self::new(device, sample_rate, sizer.chunk_size().next_multiple_of(2))?;
+        source.resize(&sizer);
+        source.sizer = Some(sizer);
+        Ok(source)
+    }
+
+    /// Samples each `read` hands out at most.
+    pub fn samples_per_frame(&self) -> usize {
+        self.samples_per_frame
+    }
+
+    fn resize(&mut self, sizer: &BufferSizer) {
+        self.samples_per_frame = sizer.chunk_size().next_multiple_of(2);
+        // room for at least two transfers so one can land while the other is read
+        let capacity = sizer.capacity().max(self.samples_per_frame).max(2 * HACKRF_TRANSFER);
+        // growing keeps everything queued, shrinking waits until nothing would be lost
+        if (capacity > self.reader.capacity() || capacity * 2 < self.reader.capacity()) && self.reader.queued() <= capacity {
+            self.reader.set_capacity(capacity);
+        }
+    }
+
+    /// Number of samples handed out by `read` so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// A handle for retuning and changing gain from other threads while samples are being read.
+    pub fn control(&self) -> HackRFControl {
+        self.control.clone()
+    }
+
+    /// Apply a gain setting, e.g. from `FrontEndAGC`. It is also reapplied after a reconnect.
+    pub fn set_gain(&mut self, gain: HackRFGain) -> Result<(), Box<dyn Error>> {
+        self.control.set_gain(gain)
+    }
+
+    pub fn set_freq(&mut self, frequency: u64) -> Result<(), Box<dyn Error>> {
+        self.control.set_freq(frequency)
+    }
+
+    pub fn set_lna_gain(&mut self, gain: u32) -> Result<(), Box<dyn Error>> {
+        self.control.set_lna_gain(gain)
+    }
+
+    pub fn set_rxvga_gain(&mut self, gain: u32) -> Result<(), Box<dyn Error>> {
+        self.control.set_rxvga_gain(gain)
+    }
+
+    /// ADC clipping seen so far, each buffer with clipped samples also gets a `TagValue::Clipping` tag.
+    pub fn clipping(&self) -> &ClipDetector {
+        &self.clipping
+    }
+
+    pub fn reset_clipping(&mut self) {
+        self.clipping.reset();
+    }
+
+    /// Stop a blocked `read` with `ErrorKind::Interrupted` once `cancel` fires, also across reconnects.
+    pub fn set_cancel(&mut self, cancel: &CancelToken) {
+        self.reader.set_cancel(cancel);
+        self.cancel = Some(cancel.clone());
+    }
+
+    /// `None` makes a stall an immediate error from `read`.
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect = policy;
+    }
+
+    /// Receive connection events, sent from inside `read`.
+    pub fn events(&mut self) -> Receiver<SourceEvent> {
+        let (sender, receiver) = channel();
+        self.events.push(sender);
+        receiver
+    }
+
+    fn notify(&mut self, event: SourceEvent) {
+        notify(&mut self.events, event);
+    }
+
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.notify(SourceEvent::DeviceLost);
+        let Some(policy) = self.reconnect else {
+            return Err("hackrf stopped delivering samples".into());
         };
 
-        it.device.start_rx(hackrf_rx_callback, writer)?;
+        let _ = self.control.shared.lock().unwrap().device.stop_rx();
+        // whatever the old stream still held is gone, the new one starts at the samples produced next
+        self.lost = self.clock.produced.load(Ordering::Relaxed) - self.position;
+        self.clock.timestamps.lock().unwrap().clear();
+        let (shared, clock, capacity) = (&self.control.shared, &self.clock, self.reader.capacity());
+        let (sample_rate, serial) = (self.sample_rate, self.serial.as_deref());
+        let events = &mut self.events;
+        let result = retry_connect(policy, self.cancel.as_ref(), |event| notify(events, event), || {
+            // libhackrf opens the first board it finds, make sure it's ours
+            let device = HackRf::open()?;
+            if let Some(serial) = serial && hackrf_serial(&device)? != serial {
+                return Err(format!("found another hackrf than {}", serial).into());
+            }
+            if let Some(settings) = &shared.lock().unwrap().settings {
+                settings.configure(&device)?;
+            }
+            let reader = hackrf_start(&device, sample_rate, capacity, clock)?;
+            Ok((device, reader))
+        });
+        match result {
+            Ok((device, reader)) => {
+                if let Some(cancel) = &self.cancel {
+                    reader.set_cancel(cancel);
+                }
+                self.control.shared.lock().unwrap().device = device;
+                self.reader = reader;
+                Ok(())
+            },
+            Err(e) => {
+                if !self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                    self.reconnect = None;
+                }
+                Err(e)
+            },
+        }
+    }
+}
+
+
+fn notify(events: &mut Vec<Sender<SourceEvent>>, event: SourceEvent) {
+    events.retain(|sender| sender.send(event.clone()).is_ok());
+}
+
+
+/// Serial number of a HackRF board as `hackrf_info` prints it.
+pub fn hackrf_serial(device: &HackRf) -> Result<String, Box<dyn Error>> {
+    Ok(device.get_serial_number()?.serial_no.iter().map(|word| format!("{:08x}", word)).collect())
+}
 
-        Ok(it)
+
+/// Calls `connect` every `policy.interval` until it succeeds, the attempts run out or `cancel`
+/// fires, reporting each attempt and the outcome to `notify`.
+fn retry_connect<D>(policy: ReconnectPolicy, cancel: Option<&CancelToken>, mut notify: impl FnMut(SourceEvent), mut connect: impl FnMut() -> Result<D, Box<dyn Error>>) -> Result<D, Box<dyn Error>> {
+    let mut last = String::new();
+    let mut attempt = 0;
+    while policy.attempts == 0 || attempt < policy.attempts {
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            return Err(Box::new(std::io::Error::new(ErrorKind::Interrupted, "reconnect was cancelled")));
+        }
+        attempt += 1;
+        notify(SourceEvent::Reconnecting { attempt });
+        std::thread::sleep(policy.interval);
+        match connect() {
+            Ok(device) => {
+                notify(SourceEvent::Reconnected);
+                return Ok(device);
+            },
+            Err(e) => last = e.to_string(),
+        }
     }
+    notify(SourceEvent::GaveUp);
+    Err(format!("hackrf lost and not recovered after {} attempts: {}", attempt, last).into())
 }
 
 
 impl Source<Complex32> for HackRFSource {
     fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
-        const SCALE: f32 = 128.0;
-        
+        if let Some(mut sizer) = self.sizer.take() {
+            sizer.observe_since_release(self.reader.queued());
+            self.resize(&sizer);
+            self.sizer = Some(sizer);
+        }
+        let result = self.read_stream(dst);
+        if let Some(sizer) = self.sizer.as_mut() {
+            sizer.release();
+        }
+        result
+    }
+}
+
+
+impl HackRFSource {
+    fn read_stream(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
         dst.clear();
+        let stall_timeout = self.reconnect.map_or(ReconnectPolicy::default().stall_timeout, |policy| policy.stall_timeout);
+        let mut it = loop {
+            match self.reader.peek_timeout(stall_timeout) {
+                Ok(it) => break it,
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => {},
+                Err(e) => return Err(Box::new(e)),
+            }
+            self.reconnect()?;
+        };
+
+        let skipped = self.lost + it.dropped();
         let mut off = 0;
-        let mut it = self.reader.peek()?;
+        let mut clipped: Option<(usize, usize)> = None;
         let read = std::cmp::min(self.samples_per_frame, it.len());
         while let Some(chunk) = it.next() {
             let rem = std::cmp::min(read - off, chunk.len());
             if rem == 0 {
                 break;
             }
-            for sample in &chunk[..rem] {
-                dst.push(Complex32 {
-                    re: sample.re as f32 / SCALE,
-                    im: sample.im as f32 / SCALE,
-                });
+            if let Some((first, count)) = self.clipping.check(&chunk[..rem]) {
+                let (first, total) = clipped.unwrap_or((off + first, 0));
+                clipped = Some((first, total + count));
             }
+            to_float(&chunk[..rem], dst);
             off += rem;
         };
         it.consume(off);
+        drop(it);
+
+        let pending = self.tags.len();
+        if let Some((first, count)) = clipped {
+            self.tags.push(Tag { offset: self.position + first as u64, value: TagValue::Clipping { count } });
+        }
+        let start = self.position;
+        self.position += off as u64;
+        self.clock.take_timestamps(start, self.position, skipped, &mut self.tags);
+        // the pending tags are all older than this read's
+        self.tags[pending..].sort_by_key(|tag| tag.offset);
+        let excess = self.tags.len().saturating_sub(MAX_PENDING_TAGS);
+        self.tags.drain(..excess);
+
+        Ok(())
+    }
+}
+
+
+impl Tagged for HackRFSource {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        dst.append(&mut self.tags);
+    }
+}
+
+
+impl Tunable for HackRFSource {
+    fn frequency(&self) -> u64 {
+        self.control.frequency()
+    }
+
+    /// Retunes with the PPM correction the source was built with.
+    fn set_frequency(&mut self, frequency: u64) -> Result<(), Box<dyn Error>> {
+        self.control.set_freq(frequency)
+    }
+}
+
+
+impl RateAware for HackRFSource {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+
+    fn delay(&self) -> f64 {
+        self.reader.queued() as f64
+    }
+}
 
+
+/// Validated HackRF receive settings, `build` applies them to the device and starts streaming.
+#[derive(Clone, Debug)]
+pub struct HackRFSourceBuilder {
+    frequency: u64,
+    sample_rate: u32,
+    baseband_bandwidth: Option<u32>,
+    lna_gain: u32,
+    vga_gain: u32,
+    amp: bool,
+    samples_per_frame: Option<usize>,
+    low_latency: bool,
+    ppm: Option<f64>,
+}
+
+
+impl HackRFSourceBuilder {
+    pub fn new(frequency: u64, sample_rate: u32) -> Self {
+        Self {
+            frequency,
+            sample_rate,
+            baseband_bandwidth: None,
+            lna_gain: 16,
+            vga_gain: 16,
+            amp: false,
+            samples_per_frame: None,
+            low_latency: false,
+            ppm: None,
+        }
+    }
+
+    /// Defaults to 75% of the sample rate.
+    pub fn baseband_bandwidth(mut self, bandwidth: u32) -> Self {
+        self.baseband_bandwidth = Some(bandwidth);
+        self
+    }
+
+    pub fn lna_gain(mut self, gain: u32) -> Self {
+        self.lna_gain = gain;
+        self
+    }
+
+    pub fn vga_gain(mut self, gain: u32) -> Self {
+        self.vga_gain = gain;
+        self
+    }
+
+    pub fn amp(mut self, enable: bool) -> Self {
+        self.amp = enable;
+        self
+    }
+
+    /// A fixed frame and buffer size, by default both follow how fast the pipeline reads, see `BufferSizer`.
+    pub fn samples_per_frame(mut self, samples: usize) -> Self {
+        self.samples_per_frame = Some(samples);
+        self
+    }
+
+    /// Keep sized frames at `HACKRF_MIN_BUFFER` instead of letting them grow when the pipeline falls
+    /// behind, for receivers playing live audio.
+    pub fn low_latency(mut self, enable: bool) -> Self {
+        self.low_latency = enable;
+        self
+    }
+
+    /// Crystal error to correct the tuning for, defaults to the one stored by a previous calibration.
+    pub fn ppm(mut self, ppm: f64) -> Self {
+        self.ppm = Some(ppm);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_range("frequency", self.frequency as f64, 1e6, 6e9)?;
+        check_range("ppm", self.ppm.unwrap_or(0.0), -200.0, 200.0)?;
+        check_range("sample rate", self.sample_rate as f64, 2e6, 20e6)?;
+        let bandwidth = self.resolved_bandwidth();
+        check_range("baseband bandwidth", bandwidth as f64, 1.75e6, 28e6)?;
+        if bandwidth > self.sample_rate {
+            return Err(ConfigError::AboveNyquist { name: "baseband bandwidth", value: bandwidth as f64 / 2.0, sample_rate: self.sample_rate });
+        }
+        check_lna_gain(self.lna_gain)?;
+        check_vga_gain(self.vga_gain)?;
+        if let Some(samples) = self.samples_per_frame && (samples == 0 || !samples.is_multiple_of(2)) {
+            return Err(ConfigError::Invalid(format!("samples per frame must be a non zero multiple of 2, got {}", samples)));
+        }
+        Ok(())
+    }
+
+    fn resolved_bandwidth(&self) -> u32 {
+        self.baseband_bandwidth.unwrap_or(self.sample_rate / 4 * 3)
+    }
+
+    fn configure(&self, device: &HackRf) -> Result<(), Box<dyn Error>> {
+        device.set_baseband_filter_bandwidth(self.resolved_bandwidth())?;
+        device.set_freq(corrected_frequency(self.frequency, self.ppm.unwrap_or(0.0)))?;
+        device.set_amp_enable(self.amp)?;
+        device.set_lna_gain(self.lna_gain)?;
+        device.set_rxvga_gain(self.vga_gain)?;
         Ok(())
     }
+
+    /// The settings are kept so a reconnected device comes back configured the same way.
+    pub fn build(mut self, device: HackRf) -> Result<HackRFSource, Box<dyn Error>> {
+        self.ppm = self.ppm.or_else(|| Some(stored_ppm("hackrf")));
+        self.validate()?;
+        self.configure(&device)?;
+        let max_buffer = if self.low_latency { HACKRF_MIN_BUFFER } else { HACKRF_MAX_BUFFER };
+        let source = match self.samples_per_frame {
+            Some(samples) => HackRFSource::new(device, self.sample_rate, samples)?,
+            None => HackRFSource::with_sizer(device, self.sample_rate, HACKRF_MIN_BUFFER, max_buffer)?,
+        };
+        let mut shared = source.control.shared.lock().unwrap();
+        shared.frequency = self.frequency;
+        shared.settings = Some(self);
+        drop(shared);
+        Ok(source)
+    }
 }
 
 
 pub struct MixerFilter {
+    sample_rate: u32,
     phase: f32,
     omega: f32,
+    position: u64,
+    hops: VecDeque<(u64, f32)>,
 }
 
 
 impl MixerFilter {
     pub fn new(sample_rate: u32, freq_shift: f32) -> Self {
         Self {
+            sample_rate,
             phase: 0.0,
             omega: 2.0 * PI * freq_shift / sample_rate as f32,
+            position: 0,
+            hops: VecDeque::new(),
+        }
+    }
+
+    /// Change the shift without a phase jump.
+    pub fn set_frequency(&mut self, freq_shift: f32) {
+        self.omega = 2.0 * PI * freq_shift / self.sample_rate as f32;
+    }
+
+    /// Switch to `freq_shift` when input sample `at` arrives, counted from the first sample filtered.
+    /// Hops in the past apply on the next sample.
+    pub fn schedule_hop(&mut self, at: u64, freq_shift: f32) {
+        let index = self.hops.partition_point(|&(t, _)| t <= at);
+        self.hops.insert(index, (at, freq_shift));
+    }
+
+    /// Replace the pending hops with `(sample index, shift)` pairs, e.g. an FHSS hop table.
+    pub fn set_hops(&mut self, hops: &[(u64, f32)]) {
+        self.hops.clear();
+        for &(at, freq_shift) in hops {
+            self.schedule_hop(at, freq_shift);
+        }
+    }
+
+    pub fn pending_hops(&self) -> usize {
+        self.hops.len()
+    }
+
+    /// Input samples filtered so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The local oscillator for the next sample, after any hop due at it.
+    fn next_lo(&mut self) -> (f32, f32) {
+        while let Some(&(at, freq_shift)) = self.hops.front() && at <= self.position {
+            self.set_frequency(freq_shift);
+            self.hops.pop_front();
         }
+        let lo = self.phase.sin_cos();
+        self.phase = (self.phase + self.omega).rem_euclid(2.0 * PI);
+        self.position += 1;
+        lo
     }
 }
 
@@ -374,10 +2046,9 @@ impl Filter<f32, Complex32> for MixerFilter {
     fn filter(&mut self, input: &[f32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
         output.clear();
         for sample in input.iter().copied() {
-            let (sin, cos) = self.phase.sin_cos();
+            let (sin, cos) = self.next_lo();
             let (i, q) = (sample * cos, sample * sin);
             output.push(Complex32 {re: i, im: -q});
-            self.phase = (self.phase + self.omega).rem_euclid(2.0 * PI);
         }
 
         Ok(())
@@ -389,10 +2060,9 @@ impl Filter<Complex32, f32> for MixerFilter {
     fn filter(&mut self, input: &[Complex32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
         output.clear();
         for sample in input.iter() {
-            let (sin, cos) = self.phase.sin_cos();
+            let (sin, cos) = self.next_lo();
             let real = sample.re * cos - sample.im * sin;
             output.push(real);
-            self.phase = (self.phase + self.omega).rem_euclid(2.0 * PI);
         }
 
         Ok(())
@@ -404,10 +2074,9 @@ impl Filter<Complex32, Complex32> for MixerFilter {
     fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
         output.clear();
         for sample in input.iter() {
-            let (sin, cos) = self.phase.sin_cos();
+            let (sin, cos) = self.next_lo();
             let lo = Complex32 { re: cos, im: sin };
             output.push(sample * lo);
-            self.phase = (self.phase + self.omega).rem_euclid(2.0 * PI);
         }
 
         Ok(())
@@ -415,6 +2084,29 @@ impl Filter<Complex32, Complex32> for MixerFilter {
 }
 
 
+impl Stateful for MixerFilter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(self.phase);
+        writer.put(self.omega);
+        writer.put(self.position);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        self.phase = reader.get()?;
+        self.omega = reader.get()?;
+        self.position = reader.get()?;
+        Ok(())
+    }
+}
+
+
+impl RateAware for MixerFilter {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
 pub fn cast_all<F, I, O>(func: F, input: &[I], output: &mut Vec<O>)
 where F: Fn(I) -> O, I: Copy
 {
@@ -425,6 +2117,67 @@ where F: Fn(I) -> O, I: Copy
 }
 
 
+/// Wraps a closure as a `Filter`, for one-off transforms that don't deserve their own struct.
+pub struct FnFilter<F> {
+    func: F,
+}
+
+
+impl<F> FnFilter<F> {
+    pub fn new<I, O>(func: F) -> Self
+    where F: FnMut(&[I], &mut Vec<O>) -> Result<(), Box<dyn Error>>
+    {
+        Self {
+            func,
+        }
+    }
+}
+
+
+impl<F, I, O> Filter<I, O> for FnFilter<F>
+where F: FnMut(&[I], &mut Vec<O>) -> Result<(), Box<dyn Error>>
+{
+    fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        (self.func)(input, output)
+    }
+}
+
+
+impl<F> RateAware for FnFilter<F> {}
+
+
+/// Applies a closure to every sample, one output per input.
+pub struct MapFilter<F> {
+    func: F,
+}
+
+
+impl<F> MapFilter<F> {
+    pub fn new<I, O>(func: F) -> Self
+    where F: FnMut(I) -> O
+    {
+        Self {
+            func,
+        }
+    }
+}
+
+
+impl<F, I: Copy, O> Filter<I, O> for MapFilter<F>
+where F: FnMut(I) -> O
+{
+    fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        output.extend(input.iter().map(|&x| (self.func)(x)));
+        Ok(())
+    }
+}
+
+
+impl<F> RateAware for MapFilter<F> {}
+
+
 pub struct FIRFilter<T>
 where T: Arithmetic
 {
@@ -472,7 +2225,41 @@ impl<T: Arithmetic> Filter<T, T> for FIRFilter<T> {
 }
 
 
+impl<T: Arithmetic + Recordable> Stateful for FIRFilter<T> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_slice(&self.history);
+        writer.put_usize(self.index);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        reader.get_slice(&mut self.history)?;
+        self.index = reader.get_usize()? % self.history.len();
+        Ok(())
+    }
+}
+
+
+impl<T: Arithmetic> Prime<T> for FIRFilter<T> {
+    fn prime(&mut self, preamble: &[T]) {
+        let len = self.history.len();
+        for (slot, sample) in self.history.iter_mut().zip(primed_history(preamble, len)) {
+            *slot = sample;
+        }
+        self.index = 0;
+    }
+}
+
+
+impl<T: Arithmetic> RateAware for FIRFilter<T> {
+    fn delay(&self) -> f64 {
+        self.taps.len().saturating_sub(1) as f64 / 2.0
+    }
+}
+
+
 pub struct RationalResampler<T: FloatLike> {
+    start: u32,
+    end: u32,
     up: usize,
     down: usize,
     phases: Vec<Vec<T>>,
@@ -507,42 +2294,257 @@ impl<T: FloatLike + From<f32>> RationalResampler<T> {
         state.resize(max_len, T::zero());
         
         Self {
-            up,
-            down,
-            phases,
-            state,
-            phase: 0,
+            start,
+            end,
+            up,
+            down,
+            phases,
+            state,
+            phase: 0,
+        }
+    }
+}
+
+
+impl<T: FloatLike + From<f32>> Filter<T, T> for RationalResampler<T> {
+    fn filter(&mut self, input: &[T], output: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        
+        for &sample in input {
+            self.state.pop_back();
+            self.state.push_front(sample);
+            
+            while self.phase < self.up {
+                let mut acc = T::zero();
+                let coeffs = self.phases[self.phase].iter();
+                for (&tap, &samp) in coeffs.zip(self.state.iter()) {
+                    acc += tap * samp;
+                }
+                output.push(acc);
+                self.phase += self.down;
+            }
+            
+            self.phase -= self.up;
+        }
+
+        Ok(())
+    }
+}
+
+
+impl<T: FloatLike + Recordable> Stateful for RationalResampler<T> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        let state: Vec<T> = self.state.iter().copied().collect();
+        writer.put_slice(&state);
+        writer.put_usize(self.phase);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        reader.get_slice(self.state.make_contiguous())?;
+        self.phase = reader.get_usize()?;
+        Ok(())
+    }
+}
+
+
+impl<T: FloatLike> Prime<T> for RationalResampler<T> {
+    fn prime(&mut self, preamble: &[T]) {
+        let len = self.state.len();
+        for (slot, sample) in self.state.iter_mut().rev().zip(primed_history(preamble, len)) {
+            *slot = sample;
+        }
+        self.phase = 0;
+    }
+}
+
+
+impl<T: FloatLike> RateAware for RationalResampler<T> {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.start))
+    }
+
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.end))
+    }
+
+    /// Half the prototype filter, which runs at `up` times the input rate.
+    fn delay(&self) -> f64 {
+        (self.phases[0].len() * self.up).saturating_sub(1) as f64 / 2.0 / self.up as f64
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct RationalResamplerBuilder {
+    start: u32,
+    end: u32,
+    num_taps: usize,
+    integer_only: bool,
+}
+
+
+impl RationalResamplerBuilder {
+    pub fn new(start: u32, end: u32) -> Self {
+        Self {
+            start,
+            end,
+            num_taps: 101,
+            integer_only: false,
+        }
+    }
+
+    pub fn num_taps(mut self, num_taps: usize) -> Self {
+        self.num_taps = num_taps;
+        self
+    }
+
+    /// About 10 taps per polyphase branch, within 101 to 4001 and always odd so the delay is a whole
+    /// number of samples.
+    pub fn taps_per_ratio(mut self) -> Self {
+        let gcd = num::integer::gcd(self.start, self.end).max(1);
+        let ratio = (self.start / gcd).max(self.end / gcd) as usize;
+        self.num_taps = (10 * ratio + 1).clamp(101, 4001).max(ratio) | 1;
+        self
+    }
+
+    /// Reject rate pairs that aren't a plain integer decimation.
+    pub fn integer_decimation(mut self) -> Self {
+        self.integer_only = true;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.start == 0 || self.end == 0 {
+            return Err(ConfigError::Invalid(format!("sample rates must be non zero, got {} and {}", self.start, self.end)));
+        }
+        check_taps(self.num_taps)?;
+        if self.integer_only && (self.end > self.start || !self.start.is_multiple_of(self.end)) {
+            return Err(ConfigError::NonIntegerDecimation { input: self.start, output: self.end });
+        }
+        let up = (self.end / num::integer::gcd(self.start, self.end)) as usize;
+        if self.num_taps < up {
+            return Err(ConfigError::Invalid(format!(
+                "{} taps leaves some of the {} polyphase branches for {} -> {} Hz empty", self.num_taps, up, self.start, self.end
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn build<T: FloatLike + From<f32>>(self) -> Result<RationalResampler<T>, ConfigError> {
+        self.validate()?;
+        Ok(RationalResampler::new(self.start, self.end, self.num_taps))
+    }
+}
+
+
+/// Resamples by an adjustable ratio with cubic interpolation, for small corrections like clock drift
+/// where a rational resampler would need huge factors.
+pub struct FractionalResampler<T: FloatLike> {
+    start: u32,
+    end: u32,
+    ratio: f64,
+    mu: f64,
+    history: [T; 4],
+    drift: Option<ClockMismatch>,
+    correction_ppm: f64,
+    max_ppm: f64,
+}
+
+
+impl<T: FloatLike + From<f32>> FractionalResampler<T> {
+    pub fn new(start: u32, end: u32) -> Self {
+        Self {
+            start,
+            end,
+            ratio: end as f64 / start as f64,
+            mu: 0.0,
+            history: [T::zero(); 4],
+            drift: None,
+            correction_ppm: 0.0,
+            max_ppm: 1000.0,
+        }
+    }
+
+    /// Output samples per input sample.
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Correction applied on top of `end / start`, in ppm.
+    pub fn correction_ppm(&self) -> f64 {
+        self.correction_ppm
+    }
+
+    pub fn set_correction_ppm(&mut self, ppm: f64) {
+        self.correction_ppm = ppm.clamp(-self.max_ppm, self.max_ppm);
+        self.ratio = self.end as f64 / self.start as f64 * (1.0 + self.correction_ppm * 1e-6);
+    }
+
+    /// Steer the ratio from `mismatch`, measured on the device this resampler feeds. Every new
+    /// estimate is the error left after the current correction, so it is added on and the
+    /// measurement starts over. Corrections are limited to `max_ppm`.
+    pub fn correct_drift(&mut self, mismatch: ClockMismatch, max_ppm: f64) {
+        self.drift = Some(mismatch);
+        self.max_ppm = max_ppm.abs();
+    }
+
+    fn interpolate(&self) -> T {
+        let mu = self.mu as f32;
+        let (mu2, mu3) = (mu * mu, mu * mu * mu);
+        // Catmull-Rom between history[1] and history[2]
+        let c = [
+            -0.5 * mu + mu2 - 0.5 * mu3,
+            1.0 - 2.5 * mu2 + 1.5 * mu3,
+            0.5 * mu + 2.0 * mu2 - 1.5 * mu3,
+            -0.5 * mu2 + 0.5 * mu3,
+        ];
+        let mut acc = T::zero();
+        for (&h, c) in self.history.iter().zip(c) {
+            acc += h * T::from(c);
         }
+        acc
     }
 }
 
 
-impl<T: FloatLike + From<f32>> Filter<T, T> for RationalResampler<T> {
+impl<T: FloatLike + From<f32>> Filter<T, T> for FractionalResampler<T> {
     fn filter(&mut self, input: &[T], output: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
         output.clear();
-        
+        if let Some(drift) = self.drift.as_mut() && let Some(ppm) = drift.update() {
+            drift.reset();
+            self.set_correction_ppm(self.correction_ppm - ppm);
+        }
+
+        let step = 1.0 / self.ratio;
         for &sample in input {
-            self.state.pop_back();
-            self.state.push_front(sample);
-            
-            while self.phase < self.up {
-                let mut acc = T::zero();
-                let coeffs = self.phases[self.phase].iter();
-                for (&tap, &samp) in coeffs.zip(self.state.iter()) {
-                    acc += tap * samp;
-                }
-                output.push(acc);
-                self.phase += self.down;
+            self.history.rotate_left(1);
+            self.history[3] = sample;
+            while self.mu < 1.0 {
+                output.push(self.interpolate());
+                self.mu += step;
             }
-            
-            self.phase -= self.up;
+            self.mu -= 1.0;
         }
-
         Ok(())
     }
 }
 
 
+impl<T: FloatLike> RateAware for FractionalResampler<T> {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.start))
+    }
+
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.end))
+    }
+
+    fn delay(&self) -> f64 {
+        2.0
+    }
+}
+
+
 pub struct FMDemod {
     sample_rate: u32,
     deviation: f32,
@@ -580,7 +2582,131 @@ impl Filter<Complex32, f32> for FMDemod {
 }
 
 
+impl Stateful for FMDemod {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(self.prev);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        self.prev = reader.get()?;
+        Ok(())
+    }
+}
+
+
+impl RateAware for FMDemod {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct FMDemodBuilder {
+    sample_rate: u32,
+    deviation: f32,
+}
+
+
+impl FMDemodBuilder {
+    pub fn new(sample_rate: u32, deviation: f32) -> Self {
+        Self {
+            sample_rate,
+            deviation,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_deviation(self.deviation, self.sample_rate)
+    }
+
+    pub fn build(self) -> Result<FMDemod, ConfigError> {
+        self.validate()?;
+        Ok(FMDemod::new(self.sample_rate, self.deviation))
+    }
+}
+
+
+/// Frequency modulates audio onto complex baseband, the inverse of `FMDemod`: an input of 1 shifts
+/// the carrier up by `deviation` Hz. Input beyond ±1 overdeviates, nothing limits it.
+pub struct FMMod {
+    sample_rate: u32,
+    deviation: f32,
+    phase: f32,
+}
+
+
+impl FMMod {
+    pub fn new(sample_rate: u32, deviation: f32) -> Self {
+        Self {
+            sample_rate,
+            deviation,
+            phase: 0.0,
+        }
+    }
+}
+
+
+impl Filter<f32, Complex32> for FMMod {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        let step = 2.0 * PI * self.deviation / self.sample_rate as f32;
+        for sample in input.iter().copied() {
+            self.phase = (self.phase + step * sample).rem_euclid(2.0 * PI);
+            output.push(Complex32::from_polar(1.0, self.phase));
+        }
+        Ok(())
+    }
+}
+
+
+impl Stateful for FMMod {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(self.phase);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        self.phase = reader.get()?;
+        Ok(())
+    }
+}
+
+
+impl RateAware for FMMod {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct FMModBuilder {
+    sample_rate: u32,
+    deviation: f32,
+}
+
+
+impl FMModBuilder {
+    pub fn new(sample_rate: u32, deviation: f32) -> Self {
+        Self {
+            sample_rate,
+            deviation,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_deviation(self.deviation, self.sample_rate)
+    }
+
+    pub fn build(self) -> Result<FMMod, ConfigError> {
+        self.validate()?;
+        Ok(FMMod::new(self.sample_rate, self.deviation))
+    }
+}
+
+
 pub struct DeEmphasisFilter {
+    sample_rate: u32,
     alpha: f32,
     y_prev: f32,
 }
@@ -592,6 +2718,7 @@ impl DeEmphasisFilter {
         let alpha = dt / (tau + dt);
         
         Self {
+            sample_rate,
             alpha,
             y_prev: 0.0,
         }
@@ -614,12 +2741,163 @@ impl Filter<f32, f32> for DeEmphasisFilter {
 }
 
 
+impl Stateful for DeEmphasisFilter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(self.y_prev);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        self.y_prev = reader.get()?;
+        Ok(())
+    }
+}
+
+
+impl Prime<f32> for DeEmphasisFilter {
+    fn prime(&mut self, preamble: &[f32]) {
+        // settle on the first sample and then run the rest through the filter
+        self.y_prev = preamble.first().copied().unwrap_or(0.0);
+        for &sample in preamble {
+            self.y_prev = self.alpha * sample + (1.0 - self.alpha) * self.y_prev;
+        }
+    }
+}
+
+
+impl RateAware for DeEmphasisFilter {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
-    use std::time::Instant;
-    use crate::traits::{Sink, Source};
-    use crate::block::{cast_all, Microphone, WavSink};
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+    use std::io::Cursor;
+    use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+    use num_complex::Complex32;
+    use crate::gnuradio::{GrMetaSink, GrMetaSource};
+    use crate::tag::Tagged;
+    use crate::traits::{CoherentSource, Filter, Sink, Source};
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use num_complex::Complex;
+    use crate::streambuf::new_stream;
+    use crate::block::{cast_all, retry_connect, AudioErrorPolicy, AudioErrors, HackRFClock, FMDemod, FMMod, FnFilter, FractionalResampler, MapFilter, Microphone, MixerFilter, NullSink, NullSource, ReconnectPolicy, SourceEvent, Tee, Throttle, TimedReplay, VirtualAudioCable, WavCoherentSource, WavSink, WavSource};
+    use crate::pipeline::CancelToken;
+    use crate::rate::{RateAware, SampleRate};
+    use crate::timing::{ClockMismatch, SampleCounters};
+
+    #[test]
+    fn test_hackrf_timestamps() -> Result<(), Box<dyn std::error::Error>> {
+        // frames of 4 samples into a stream that only holds 8, the first frame is overwritten
+        let clock = HackRFClock::default();
+        let (mut reader, writer) = new_stream::<Complex<i8>>(8, true, false, true)?;
+        for frame in 0..3 {
+            clock.record(&writer, &[Complex::new(frame, 0); 4])?;
+        }
+        let mut it = reader.peek()?;
+        let skipped = it.dropped();
+        assert_eq!(skipped, 4);
+        let first: Vec<i8> = it.next().unwrap().iter().map(|x| x.re).collect();
+        assert_eq!(first[0], 1);
+        it.consume(8);
+        drop(it);
+
+        // tags land on the samples read, not where the device produced them
+        let mut tags = Vec::new();
+        clock.take_timestamps(0, 8, skipped, &mut tags);
+        assert_eq!(tags.iter().map(|t| t.offset).collect::<Vec<_>>(), [0, 4]);
+        assert!(clock.timestamps.lock().unwrap().is_empty());
+
+        clock.record(&writer, &[Complex::new(3, 0); 4])?;
+        tags.clear();
+        clock.take_timestamps(8, 12, reader.peek()?.dropped(), &mut tags);
+        assert_eq!(tags.iter().map(|t| t.offset).collect::<Vec<_>>(), [8]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_audio_recover() -> Result<(), Box<dyn std::error::Error>> {
+        let mut errors = AudioErrors::new();
+        errors.policy = AudioErrorPolicy::Reopen { attempts: 3, interval: Duration::from_millis(1) };
+        let mut stream = Some(0);
+
+        // nothing reported, nothing rebuilt
+        errors.recover(&mut stream, |_| -> Result<i32, Box<dyn std::error::Error>> { panic!("rebuilt without an error") })?;
+
+        // the device comes back on the third try
+        let mut tries = 0;
+        (errors.callback())(cpal::StreamError::DeviceNotAvailable);
+        errors.recover(&mut stream, |_| {
+            tries += 1;
+            if tries < 3 { Err("still unplugged".into()) } else { Ok(tries) }
+        })?;
+        assert_eq!((stream, errors.reopens), (Some(3), 1));
+
+        // and then not at all
+        (errors.callback())(cpal::StreamError::DeviceNotAvailable);
+        let error = errors.recover(&mut stream, |_| Err::<i32, _>("still unplugged".into())).unwrap_err();
+        assert_eq!(error.to_string(), "audio device lost and not recovered after 3 attempts: still unplugged");
+        assert_eq!(stream, None);
+
+        errors.policy = AudioErrorPolicy::Fail;
+        (errors.callback())(cpal::StreamError::DeviceNotAvailable);
+        assert!(errors.recover(&mut stream, |_| Ok(0)).is_err());
+        assert_eq!(errors.reopens, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hackrf_reconnect() -> Result<(), Box<dyn std::error::Error>> {
+        let policy = ReconnectPolicy { stall_timeout: Duration::ZERO, attempts: 3, interval: Duration::ZERO };
+
+        // the board comes back on the third try
+        let (mut events, mut tries) = (Vec::new(), 0);
+        let device = retry_connect(policy, None, |event| events.push(event), || {
+            tries += 1;
+            if tries < 3 { Err("no hackrf".into()) } else { Ok(tries) }
+        })?;
+        assert_eq!(device, 3);
+        let attempts = (1..=3).map(|attempt| SourceEvent::Reconnecting { attempt });
+        assert_eq!(events, attempts.clone().chain([SourceEvent::Reconnected]).collect::<Vec<_>>());
+
+        // only a different board shows up
+        events.clear();
+        let error = retry_connect::<()>(policy, None, |event| events.push(event), || Err("found another hackrf than 1234".into())).unwrap_err();
+        assert_eq!(error.to_string(), "hackrf lost and not recovered after 3 attempts: found another hackrf than 1234");
+        assert_eq!(events, attempts.chain([SourceEvent::GaveUp]).collect::<Vec<_>>());
+
+        // a cancelled token stops retrying before the next attempt
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        events.clear();
+        let error = retry_connect(policy, Some(&cancel), |event| events.push(event), || Ok(())).unwrap_err();
+        assert_eq!(error.downcast_ref::<std::io::Error>().map(|e| e.kind()), Some(std::io::ErrorKind::Interrupted));
+        assert!(events.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fm_modulator() -> Result<(), Box<dyn std::error::Error>> {
+        let audio: Vec<f32> = (0..4800).map(|n| (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / 48000.0).sin()).collect();
+        let mut modulator = FMMod::new(48000, 5000.0);
+        let mut baseband = Vec::new();
+        let mut block = Vec::new();
+        for chunk in audio.chunks(1000) {
+            modulator.filter(chunk, &mut block)?;
+            baseband.extend_from_slice(&block);
+        }
+        assert!(baseband.iter().all(|x| (x.norm() - 1.0).abs() < 1e-5));
+
+        // demodulating with the same deviation gives the audio back
+        let mut demodulated = Vec::new();
+        FMDemod::new(48000, 5000.0).filter(&baseband, &mut demodulated)?;
+        assert!(demodulated.iter().zip(&audio).all(|(y, x)| (y - x).abs() < 1e-3));
+        Ok(())
+    }
 
     #[test]
     fn test_microphone() -> Result<(), Box<dyn std::error::Error>> {
@@ -649,4 +2927,318 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_wav_coherent_source() -> Result<(), Box<dyn std::error::Error>> {
+        let spec = WavSpec {
+            channels: 4,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut file = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut file, spec)?;
+        for n in 0..10i32 {
+            for channel in 0..4 {
+                writer.write_sample((100 * n + channel) as i16)?;
+            }
+        }
+        writer.finalize()?;
+        file.set_position(0);
+
+        let mut source = WavCoherentSource::from_reader(WavReader::new(file)?, 4)?;
+        assert_eq!(source.channels(), 2);
+        let mut dst = vec![Vec::new(), Vec::new()];
+        let mut total = 0;
+        loop {
+            source.read_coherent(&mut dst)?;
+            if dst[0].is_empty() {
+                break;
+            }
+            assert_eq!(dst[0].len(), dst[1].len());
+            for (a, b) in dst[0].iter().zip(dst[1].iter()) {
+                assert!(((b.re - a.re) * 65535.0 - 2.0).abs() < 1e-2);
+            }
+            total += dst[0].len();
+        }
+        assert_eq!(total, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wav_source_resample() -> Result<(), Box<dyn std::error::Error>> {
+        // a second of 1 kHz at 44.1 kHz, played at 48 kHz
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut file = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut file, spec)?;
+        for n in 0..44100 {
+            writer.write_sample((16000.0 * (2.0 * std::f64::consts::PI * 1000.0 * n as f64 / 44100.0).sin()) as i16)?;
+        }
+        writer.finalize()?;
+        file.set_position(0);
+
+        let mut source = WavSource::from_reader(WavReader::new(file)?, 1000).resample_to(48000)?;
+        assert_eq!(source.output_rate(None), Some(SampleRate(48000)));
+        let mut samples = Vec::new();
+        let mut dst: Vec<f32> = Vec::new();
+        loop {
+            source.read(&mut dst)?;
+            if dst.is_empty() {
+                break;
+            }
+            samples.extend_from_slice(&dst);
+        }
+        assert!(samples.len().abs_diff(48000) <= 2, "{}", samples.len());
+        let rising = samples.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count();
+        assert!(rising.abs_diff(1000) <= 1, "{}", rising);
+
+        // going down, a tone above the new Nyquist is filtered out instead of folding to 4 kHz
+        let spec = WavSpec { sample_rate: 48000, ..spec };
+        let mut file = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut file, spec)?;
+        for n in 0..48000 {
+            writer.write_sample((16000.0 * (2.0 * std::f64::consts::PI * 20000.0 * n as f64 / 48000.0).sin()) as i16)?;
+        }
+        writer.finalize()?;
+        file.set_position(0);
+        let mut source = WavSource::from_reader(WavReader::new(file)?, 1000).resample_to(16000)?;
+        samples.clear();
+        loop {
+            source.read(&mut dst)?;
+            if dst.is_empty() {
+                break;
+            }
+            samples.extend_from_slice(&dst);
+        }
+        assert!(samples.len().abs_diff(16000) <= 2, "{}", samples.len());
+        let tail = &samples[1000..];
+        let rms = (tail.iter().map(|x| x * x).sum::<f32>() / tail.len() as f32).sqrt();
+        assert!(rms < 0.01 * 16000.0 / 65535.0 / 2f32.sqrt(), "{}", rms);
+        Ok(())
+    }
+
+    #[test]
+    fn test_virtual_audio_cable() -> Result<(), Box<dyn std::error::Error>> {
+        let VirtualAudioCable { mut source, mut sink } = VirtualAudioCable::new(1000)?;
+        let writer = std::thread::spawn(move || -> Result<(), String> {
+            for n in 0..10 {
+                let block: Vec<f32> = (0..500).map(|i| (n * 500 + i) as f32).collect();
+                sink.write(&block).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        });
+
+        let mut received = Vec::new();
+        let mut dst = Vec::new();
+        loop {
+            source.read(&mut dst)?;
+            if dst.is_empty() {
+                break;
+            }
+            received.extend_from_slice(&dst);
+        }
+        writer.join().unwrap()?;
+        assert!(received.iter().enumerate().all(|(i, &x)| x == i as f32));
+        assert_eq!(received.len(), 5000);
+
+        // writing into a cable nobody listens to anymore fails instead of blocking forever
+        let VirtualAudioCable { source, mut sink } = VirtualAudioCable::new(10)?;
+        drop(source);
+        assert!(sink.write(&[0.0; 100]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fractional_resampler() -> Result<(), Box<dyn std::error::Error>> {
+        let tone = |t: f64| (2.0 * std::f64::consts::PI * 1000.0 * t).sin() as f32;
+        let input: Vec<f32> = (0..48_000).map(|n| tone(n as f64 / 48_000.0)).collect();
+        let mut resampler = FractionalResampler::new(48_000, 44_100);
+        let mut output = Vec::new();
+        resampler.filter(&input, &mut output)?;
+        assert!((output.len() as i64 - 44_100).abs() <= 1, "{}", output.len());
+        // the output lags by two input samples
+        for (n, &y) in output.iter().enumerate().skip(10) {
+            let expected = tone(n as f64 / 44_100.0 - 2.0 / 48_000.0);
+            assert!((y - expected).abs() < 2e-3, "{}: {} vs {}", n, y, expected);
+        }
+
+        // the sink fell 5 samples behind in about 150 ms at 48 kHz, a few hundred ppm
+        let counters = Arc::new(SampleCounters::default());
+        let mismatch = ClockMismatch::new(counters.clone(), 48_000.0).window(Duration::from_secs(1), Duration::from_millis(100));
+        let mut resampler = FractionalResampler::new(48_000, 48_000);
+        resampler.correct_drift(mismatch, 1000.0);
+        resampler.filter(&input[..480], &mut output)?;
+        std::thread::sleep(Duration::from_millis(150));
+        counters.produced.store(7205, Ordering::Relaxed);
+        counters.consumed.store(7200, Ordering::Relaxed);
+        resampler.filter(&input[..480], &mut output)?;
+        assert!((-700.0..-200.0).contains(&resampler.correction_ppm()), "{}", resampler.correction_ppm());
+        assert!(resampler.ratio() < 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tee() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tee = Tee::<f32>::new();
+        let mut record = tee.branch(64)?;
+        let mut display = tee.lossy_branch(4)?;
+
+        let mut output = Vec::new();
+        tee.filter(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], &mut output)?;
+        assert_eq!(output, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        drop(tee);
+
+        let mut dst = Vec::new();
+        record.read(&mut dst)?;
+        assert_eq!(dst, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        record.read(&mut dst)?;
+        assert!(dst.is_empty());
+        display.read(&mut dst)?;
+        assert_eq!(dst, [3.0, 4.0, 5.0, 6.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_null_blocks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut source = NullSource::new(48000, 1000).limit(2500);
+        let mut sink = NullSink::new();
+        let mut buffer: Vec<f32> = Vec::new();
+        loop {
+            source.read(&mut buffer)?;
+            if buffer.is_empty() {
+                break;
+            }
+            assert!(buffer.iter().all(|&x| x == 0.0));
+            sink.write(&buffer)?;
+        }
+        assert_eq!(source.samples(), 2500);
+        assert_eq!(sink.samples(), 2500);
+        Ok(())
+    }
+
+    #[test]
+    fn test_closure_filters() -> Result<(), Box<dyn std::error::Error>> {
+        let mut gain = MapFilter::new(|x: f32| x * 2.0);
+        let mut output = Vec::new();
+        gain.filter(&[1.0, -0.5], &mut output)?;
+        assert_eq!(output, [2.0, -1.0]);
+
+        let mut decimate = FnFilter::new(|input: &[f32], output: &mut Vec<f32>| {
+            output.extend(input.iter().step_by(2));
+            Ok(())
+        });
+        decimate.filter(&[1.0, 2.0, 3.0, 4.0, 5.0], &mut output)?;
+        assert_eq!(output, [1.0, 3.0, 5.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_throttle() -> Result<(), Box<dyn std::error::Error>> {
+        let mut source = Throttle::new(NullSource::new(1000, 50).limit(200), 1000).speed(4.0)?;
+        let mut buffer: Vec<f32> = Vec::new();
+        let start = std::time::Instant::now();
+        let mut samples = 0;
+        loop {
+            source.read(&mut buffer)?;
+            if buffer.is_empty() {
+                break;
+            }
+            samples += buffer.len();
+        }
+        // 200 samples at 1 kHz take 200 ms, at 4x 50 ms
+        let elapsed = start.elapsed().as_secs_f64();
+        assert_eq!(samples, 200);
+        assert!((0.045..0.15).contains(&elapsed), "took {}", elapsed);
+
+        assert!(source.set_speed(0.0).is_err());
+        assert!(source.set_speed(f64::NAN).is_err());
+        assert!(source.set_speed(-1.0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timed_replay() -> Result<(), Box<dyn std::error::Error>> {
+        // two 100 ms bursts at 1 kHz, the second starting 300 ms after the first
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut sink = GrMetaSink::new::<Complex32>(Cursor::new(Vec::new()), 1000.0, None, start)?;
+        sink.write(&[Complex32::new(1.0, 0.0); 100])?;
+        sink.new_segment(1000.0, None, start + Duration::from_millis(300))?;
+        sink.write(&[Complex32::new(1.0, 0.0); 100])?;
+        sink.finish()?;
+        let file = sink.get_ref().get_ref().clone();
+
+        let replay = |source: TimedReplay<_>| -> Result<(f64, usize), Box<dyn std::error::Error>> {
+            let mut source = source;
+            let mut buffer: Vec<Complex32> = Vec::new();
+            let begin = Instant::now();
+            loop {
+                source.read(&mut buffer)?;
+                if buffer.is_empty() {
+                    break;
+                }
+            }
+            let mut tags = Vec::new();
+            source.take_tags(&mut tags);
+            Ok((begin.elapsed().as_secs_f64(), tags.len()))
+        };
+
+        let source = GrMetaSource::new(Cursor::new(file.clone()), 50);
+        let (elapsed, tags) = replay(TimedReplay::new(source, 1000).speed(2.0)?)?;
+        assert_eq!(tags, 2);
+        assert!((0.19..0.3).contains(&elapsed), "took {}", elapsed);
+
+        let source = GrMetaSource::new(Cursor::new(file.clone()), 50);
+        let (elapsed, _) = replay(TimedReplay::new(source, 1000).speed(2.0)?.max_gap(Duration::ZERO))?;
+        assert!((0.09..0.18).contains(&elapsed), "took {}", elapsed);
+
+        // the first block is due right away, the second once the first has played
+        let mut source = TimedReplay::new(GrMetaSource::new(Cursor::new(file), 50), 1000);
+        let mut buffer: Vec<Complex32> = Vec::new();
+        let begin = Instant::now();
+        source.read(&mut buffer)?;
+        assert!(begin.elapsed() < Duration::from_millis(30), "took {:?}", begin.elapsed());
+        source.read(&mut buffer)?;
+        assert!(begin.elapsed() >= Duration::from_millis(50), "took {:?}", begin.elapsed());
+
+        // a timestamp behind the last one doesn't wrap the offsets
+        source.timestamp(100, start + Duration::from_millis(300));
+        source.timestamp(50, start + Duration::from_millis(50));
+        assert!((source.ref_secs - 0.05).abs() < 1e-9, "{}", source.ref_secs);
+
+        assert!(TimedReplay::new(NullSource::new(1000, 50), 1000).speed(0.0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixer_hops() -> Result<(), Box<dyn std::error::Error>> {
+        // a carrier hopping between +5 kHz and -2 kHz is brought back to DC by the matching hop table
+        let rate = 48000.0;
+        let table = [(0, 5000.0), (300, -2000.0), (700, 5000.0)];
+        let mut phase = 0f32;
+        let input: Vec<Complex32> = (0..1000).map(|n| {
+            let freq = table.iter().rev().find(|&&(at, _)| at <= n).unwrap().1;
+            let sample = Complex32::from_polar(1.0, phase);
+            phase += 2.0 * std::f32::consts::PI * freq / rate;
+            sample
+        }).collect();
+
+        let mut mixer = MixerFilter::new(48000, 0.0);
+        mixer.set_hops(&table.map(|(at, freq)| (at as u64, -freq)));
+        let mut output = Vec::new();
+        for chunk in input.chunks(128) {
+            let mut block: Vec<Complex32> = Vec::new();
+            mixer.filter(chunk, &mut block)?;
+            output.extend(block);
+        }
+        assert_eq!(mixer.pending_hops(), 0);
+        assert_eq!(mixer.position(), 1000);
+        for w in output.windows(2) {
+            assert!((w[1] * w[0].conj()).arg().abs() < 1e-3);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,243 @@
+use std::error::Error;
+use std::f32::consts::PI;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use num_complex::Complex32;
+use crate::rate::{RateAware, SampleRate};
+use crate::traits::*;
+use crate::util::lowpass_taps;
+
+
+/// Unity gain lowpass taps for `cutoff_hz`, moved up by `if_shift_hz`. The taps are reversed
+/// since the oldest sample is multiplied with the first one.
+fn channel_taps(sample_rate: u32, cutoff_hz: f32, if_shift_hz: f32, num_taps: usize) -> Vec<Complex32> {
+    let taps = lowpass_taps(cutoff_hz / sample_rate as f32, num_taps);
+    let gain: f32 = taps.iter().sum();
+    let center = (num_taps - 1) as f32 / 2.0;
+    let omega = 2.0 * PI * if_shift_hz / sample_rate as f32;
+    taps.into_iter()
+        .enumerate()
+        .map(|(i, t)| Complex32::from_polar(t / gain, omega * (center - i as f32)))
+        .collect()
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChannelRequest {
+    Cutoff(f32),
+    IfShift(f32),
+}
+
+
+/// Changes the cutoff or IF shift of a `ChannelFilter` from another thread, e.g. a UI.
+#[derive(Clone)]
+pub struct ChannelControl {
+    sender: Sender<ChannelRequest>,
+}
+
+
+impl ChannelControl {
+    pub fn set_cutoff(&self, cutoff_hz: f32) -> Result<(), Box<dyn Error>> {
+        self.sender.send(ChannelRequest::Cutoff(cutoff_hz)).map_err(|_| "channel filter is gone")?;
+        Ok(())
+    }
+
+    pub fn set_if_shift(&self, if_shift_hz: f32) -> Result<(), Box<dyn Error>> {
+        self.sender.send(ChannelRequest::IfShift(if_shift_hz)).map_err(|_| "channel filter is gone")?;
+        Ok(())
+    }
+}
+
+
+/// Cutoff, IF shift and the taps designed for them.
+type Design = (f32, f32, Vec<Complex32>);
+
+
+/// Lowpass channel filter whose cutoff can change while it runs. New taps are designed on a
+/// background thread and faded in over `crossfade` samples so the change doesn't click.
+/// The IF shift moves the passband relative to the carrier, passband tuning for SSB and CW.
+pub struct ChannelFilter {
+    sample_rate: u32,
+    cutoff_hz: f32,
+    if_shift_hz: f32,
+    taps: Vec<Complex32>,
+    next: Option<Design>,
+    history: Vec<Complex32>,
+    index: usize,
+    crossfade: usize,
+    fade: usize,
+    requests: Sender<ChannelRequest>,
+    designed: Receiver<Design>,
+}
+
+
+impl ChannelFilter {
+    pub fn new(sample_rate: u32, cutoff_hz: f32, num_taps: usize) -> Self {
+        let (requests, worker_requests) = channel::<ChannelRequest>();
+        let (worker_designed, designed) = channel();
+        std::thread::spawn(move || {
+            let (mut cutoff, mut if_shift) = (cutoff_hz, 0.0);
+            while let Ok(request) = worker_requests.recv() {
+                // a burst of requests only needs one design
+                for request in std::iter::once(request).chain(worker_requests.try_iter()) {
+                    match request {
+                        ChannelRequest::Cutoff(hz) => cutoff = hz,
+                        ChannelRequest::IfShift(hz) => if_shift = hz,
+                    }
+                }
+                if worker_designed.send((cutoff, if_shift, channel_taps(sample_rate, cutoff, if_shift, num_taps))).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            sample_rate,
+            cutoff_hz,
+            if_shift_hz: 0.0,
+            taps: channel_taps(sample_rate, cutoff_hz, 0.0, num_taps),
+            next: None,
+            history: vec![Complex32::default(); num_taps],
+            index: 0,
+            crossfade: (sample_rate / 100).max(1) as usize,
+            fade: 0,
+            requests,
+            designed,
+        }
+    }
+
+    pub fn control(&self) -> ChannelControl {
+        ChannelControl {
+            sender: self.requests.clone(),
+        }
+    }
+
+    /// Request a new cutoff, it is faded in once its taps are ready.
+    pub fn set_cutoff(&mut self, cutoff_hz: f32) {
+        let _ = self.requests.send(ChannelRequest::Cutoff(cutoff_hz));
+    }
+
+    /// Cutoff currently in effect.
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff_hz
+    }
+
+    /// Request a passband shift, positive moves it up in frequency.
+    pub fn set_if_shift(&mut self, if_shift_hz: f32) {
+        let _ = self.requests.send(ChannelRequest::IfShift(if_shift_hz));
+    }
+
+    /// IF shift currently in effect.
+    pub fn if_shift(&self) -> f32 {
+        self.if_shift_hz
+    }
+
+    pub fn set_crossfade(&mut self, samples: usize) {
+        self.crossfade = samples.max(1);
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for ChannelFilter {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        if self.next.is_none() && let Some(designed) = self.designed.try_iter().last() {
+            self.next = Some(designed);
+            self.fade = 0;
+        }
+
+        let len = self.history.len();
+        for &sample in input {
+            self.history[self.index] = sample;
+            self.index = (self.index + 1) % len;
+
+            let convolve = |taps: &[Complex32]| {
+                let mut acc = Complex32::default();
+                for (i, &tap) in taps.iter().enumerate() {
+                    acc += self.history[(self.index + i) % len] * tap;
+                }
+                acc
+            };
+            let current = convolve(&self.taps);
+            match &self.next {
+                Some((_, _, taps)) => {
+                    let w = self.fade as f32 / self.crossfade as f32;
+                    output.push(current * (1.0 - w) + convolve(taps) * w);
+                    self.fade += 1;
+                    if self.fade >= self.crossfade {
+                        let (cutoff, if_shift, taps) = self.next.take().unwrap();
+                        self.cutoff_hz = cutoff;
+                        self.if_shift_hz = if_shift;
+                        self.taps = taps;
+                    }
+                },
+                None => output.push(current),
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for ChannelFilter {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+
+    fn delay(&self) -> f64 {
+        self.taps.len().saturating_sub(1) as f64 / 2.0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::time::{Duration, Instant};
+    use num_complex::Complex32;
+    use crate::channel::ChannelFilter;
+    use crate::response::tone_response;
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_channel_filter() -> Result<(), Box<dyn std::error::Error>> {
+        let rate = 48000;
+        let tone: Vec<Complex32> = (0..4800).map(|n| Complex32::from_polar(1.0, 2.0 * PI * 6000.0 * n as f32 / rate as f32)).collect();
+        let mut filter = ChannelFilter::new(rate, 10000.0, 101);
+        let response = tone_response(&mut filter, rate as f32, 6000.0, 200, 4800)?;
+        assert!((response.gain - 1.0).abs() < 0.05, "{:?}", response);
+        let mut output = Vec::new();
+
+        filter.control().set_cutoff(3000.0)?;
+        let start = Instant::now();
+        while filter.cutoff() != 3000.0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            filter.filter(&tone, &mut output)?;
+        }
+        let response = tone_response(&mut filter, rate as f32, 6000.0, 200, 4800)?;
+        assert!(response.gain < 0.01, "{:?}", response);
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_shift() -> Result<(), Box<dyn std::error::Error>> {
+        let rate = 48000;
+        let tone = |f: f32| -> Vec<Complex32> { (0..4800).map(|n| Complex32::from_polar(1.0, 2.0 * PI * f * n as f32 / rate as f32)).collect() };
+        let mut filter = ChannelFilter::new(rate, 1500.0, 201);
+        let control = filter.control();
+        control.set_if_shift(2000.0)?;
+        let start = Instant::now();
+        let mut output = Vec::new();
+        while filter.if_shift() != 2000.0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            filter.filter(&tone(0.0), &mut output)?;
+        }
+
+        // the passband now spans 500 to 3500 Hz
+        filter.filter(&tone(2500.0), &mut output)?;
+        assert!((output[4000].norm() - 1.0).abs() < 0.05);
+        filter.filter(&tone(-1000.0), &mut output)?;
+        assert!(output[4000].norm() < 0.01);
+        Ok(())
+    }
+
+}
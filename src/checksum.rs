@@ -0,0 +1,234 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use blake3::{Hash, Hasher};
+use memmap2::Mmap;
+
+
+/// Bytes hashed together unless `ChunkHasher::new` says otherwise, corruption is located to within this.
+pub const DEFAULT_CHUNK_SIZE: u64 = 16 << 20;
+
+
+/// Where the hashes of `path` are kept, the file name with `.b3` added.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".b3");
+    PathBuf::from(name)
+}
+
+
+/// BLAKE3 hashes of consecutive chunks of a recording, so damage to an archived capture can be
+/// found and narrowed down to the chunks affected instead of throwing the whole file away. Stored
+/// next to the recording as text:
+///
+/// ```text
+/// blake3 chunk 16777216 length 52428800
+/// <hash of bytes 0..16777216>
+/// ...
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkHashes {
+    pub chunk_size: u64,
+    /// Length of the file hashed.
+    pub length: u64,
+    pub hashes: Vec<Hash>,
+}
+
+
+impl ChunkHashes {
+    /// Hashes a file already on disk.
+    pub fn of_file(path: &Path, chunk_size: u64) -> Result<Self, Box<dyn Error>> {
+        let mut hasher = ChunkHasher::new(chunk_size);
+        let file = File::open(path)?;
+        if file.metadata()?.len() > 0 {
+            hasher.update(&unsafe { Mmap::map(&file)? });
+        }
+        Ok(hasher.finish())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let header: Vec<&str> = lines.next().unwrap_or_default().split_whitespace().collect();
+        let ["blake3", "chunk", chunk_size, "length", length] = header.as_slice() else {
+            return Err(format!("{}: not a chunk hash file", path.display()).into());
+        };
+        let hashes = lines.filter(|line| !line.is_empty())
+            .map(|line| Hash::from_hex(line.trim()).map_err(|e| format!("{}: {}", path.display(), e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let it = Self { chunk_size: chunk_size.parse()?, length: length.parse()?, hashes };
+        if it.chunk_size == 0 || it.hashes.len() as u64 != it.length.div_ceil(it.chunk_size) {
+            return Err(format!("{}: {} hashes for {} bytes", path.display(), it.hashes.len(), it.length).into());
+        }
+        Ok(it)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut text = format!("blake3 chunk {} length {}\n", self.chunk_size, self.length);
+        for hash in &self.hashes {
+            text.push_str(&hash.to_hex());
+            text.push('\n');
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Byte ranges of `path` that differ from what was hashed, empty when the file is intact. Bytes
+    /// missing from or added to the end count as a mismatch as well.
+    pub fn verify(&self, path: &Path) -> Result<Vec<Range<u64>>, Box<dyn Error>> {
+        let current = Self::of_file(path, self.chunk_size)?;
+        let mut bad: Vec<Range<u64>> = Vec::new();
+        for (i, expected) in self.hashes.iter().enumerate() {
+            if current.hashes.get(i) == Some(expected) {
+                continue;
+            }
+            let start = i as u64 * self.chunk_size;
+            let end = (start + self.chunk_size).min(self.length);
+            match bad.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => bad.push(start..end),
+            }
+        }
+        if current.length > self.length {
+            match bad.last_mut() {
+                Some(last) if last.end == self.length => last.end = current.length,
+                _ => bad.push(self.length..current.length),
+            }
+        }
+        Ok(bad)
+    }
+}
+
+
+/// Hashes a stream of bytes in chunks as it goes by.
+pub struct ChunkHasher {
+    chunk_size: u64,
+    hasher: Hasher,
+    in_chunk: u64,
+    length: u64,
+    hashes: Vec<Hash>,
+}
+
+
+impl ChunkHasher {
+    pub fn new(chunk_size: u64) -> Self {
+        Self {
+            chunk_size: chunk_size.max(1),
+            hasher: Hasher::new(),
+            in_chunk: 0,
+            length: 0,
+            hashes: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let take = ((self.chunk_size - self.in_chunk) as usize).min(bytes.len());
+            self.hasher.update(&bytes[..take]);
+            self.in_chunk += take as u64;
+            self.length += take as u64;
+            bytes = &bytes[take..];
+            if self.in_chunk == self.chunk_size {
+                self.hashes.push(self.hasher.finalize());
+                self.hasher.reset();
+                self.in_chunk = 0;
+            }
+        }
+    }
+
+    /// The hashes so far, a partial last chunk included.
+    pub fn finish(mut self) -> ChunkHashes {
+        if self.in_chunk > 0 {
+            self.hashes.push(self.hasher.finalize());
+        }
+        ChunkHashes {
+            chunk_size: self.chunk_size,
+            length: self.length,
+            hashes: self.hashes,
+        }
+    }
+}
+
+
+/// Hashes everything written through it, for hashing a recording while it's written instead of
+/// reading it back afterwards. Only works for writers that don't seek back, like `IQFileSink`'s.
+pub struct HashingWriter<W: Write> {
+    writer: W,
+    hasher: ChunkHasher,
+}
+
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(writer: W, chunk_size: u64) -> Self {
+        Self {
+            writer,
+            hasher: ChunkHasher::new(chunk_size),
+        }
+    }
+
+    pub fn finish(self) -> (W, ChunkHashes) {
+        (self.writer, self.hasher.finish())
+    }
+}
+
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::Write;
+    use crate::checksum::{sidecar_path, ChunkHashes, HashingWriter};
+
+    #[test]
+    fn test_chunk_hashes() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("rust_dsp_checksum_{}.cu8", std::process::id()));
+        let bytes: Vec<u8> = (0..10_000u32).map(|n| (n * 7 % 251) as u8).collect();
+
+        // hashed while writing in odd sized pieces, the same as hashing the file afterwards
+        let mut writer = HashingWriter::new(std::fs::File::create(&path)?, 1024);
+        for piece in bytes.chunks(333) {
+            writer.write_all(piece)?;
+        }
+        let (_, hashes) = writer.finish();
+        assert_eq!((hashes.length, hashes.hashes.len()), (10_000, 10));
+        assert_eq!(hashes, ChunkHashes::of_file(&path, 1024)?);
+
+        let sidecar = sidecar_path(&path);
+        assert!(sidecar.to_string_lossy().ends_with(".cu8.b3"));
+        hashes.save(&sidecar)?;
+        let hashes = ChunkHashes::load(&sidecar)?;
+        assert!(hashes.verify(&path)?.is_empty());
+
+        // a flipped byte in the third chunk and a cut off tail
+        let mut damaged = bytes.clone();
+        damaged[2100] ^= 1;
+        damaged.truncate(9500);
+        std::fs::write(&path, &damaged)?;
+        assert_eq!(hashes.verify(&path)?, [2048..3072, 9216..10_000]);
+
+        // bytes added on the end
+        let mut longer = bytes.clone();
+        longer.extend_from_slice(&[0; 100]);
+        std::fs::write(&path, &longer)?;
+        let bad = hashes.verify(&path)?;
+        assert_eq!((bad.len(), &bad[0]), (1, &(9216..10_100)));
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(&sidecar)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+use std::error::Error;
+use num_complex::Complex32;
+use crate::demod::DemodMode;
+use crate::fft::{fftshift, power_spectrum, Window, FFT};
+use crate::traits::*;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Modulation {
+    Noise,
+    AM,
+    FM,
+    SSB,
+    CW,
+    Digital,
+}
+
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SignalFeatures {
+    pub snr_db: f32,
+    /// Width in Hz holding 99% of the power above the noise floor.
+    pub bandwidth: f32,
+    /// Fraction of the signal power within a bin of the strongest one.
+    pub carrier_ratio: f32,
+    /// (upper - lower) / (upper + lower) power around the tuned frequency.
+    pub spectral_symmetry: f32,
+    /// Standard deviation of the envelope over its mean.
+    pub envelope_variation: f32,
+    pub envelope_kurtosis: f32,
+    /// Kurtosis of the derivative of the instantaneous frequency, large when it jumps between levels.
+    pub frequency_step_kurtosis: f32,
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Classification {
+    pub modulation: Modulation,
+    pub features: SignalFeatures,
+}
+
+
+impl Classification {
+    /// The demodulator to listen with, None when there's nothing to turn into audio.
+    pub fn demod_mode(&self) -> Option<DemodMode> {
+        match self.modulation {
+            Modulation::AM => Some(DemodMode::AM),
+            Modulation::FM => Some(DemodMode::FM),
+            Modulation::SSB if self.features.spectral_symmetry > 0.0 => Some(DemodMode::USB),
+            Modulation::SSB => Some(DemodMode::LSB),
+            Modulation::CW => Some(DemodMode::CW),
+            Modulation::Digital | Modulation::Noise => None,
+        }
+    }
+}
+
+
+fn kurtosis(values: impl Iterator<Item = f32> + Clone) -> f32 {
+    let n = values.clone().count().max(1) as f32;
+    let mean = values.clone().sum::<f32>() / n;
+    let var = values.clone().map(|v| (v - mean).powi(2)).sum::<f32>() / n;
+    let m4 = values.map(|v| (v - mean).powi(4)).sum::<f32>() / n;
+    if var > 0.0 { m4 / (var * var) } else { 0.0 }
+}
+
+
+/// Heuristic AM/FM/SSB/CW/digital classifier working on windows of complex baseband centered on the signal.
+pub struct ModulationClassifier {
+    sample_rate: u32,
+    window_len: usize,
+    fft: FFT,
+    window: Vec<f32>,
+    buffer: Vec<Complex32>,
+}
+
+
+impl ModulationClassifier {
+    pub fn new(sample_rate: u32, fft_size: usize, window_len: usize) -> Self {
+        Self {
+            sample_rate,
+            window_len: window_len.max(fft_size),
+            fft: FFT::new(fft_size),
+            window: Window::Hann.coefficients(fft_size),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Samples each classification from `filter` covers.
+    pub fn window_len(&self) -> usize {
+        self.window_len
+    }
+
+    pub fn features(&self, samples: &[Complex32]) -> SignalFeatures {
+        let size = self.fft.size();
+        let mut spectrum = vec![0f32; size];
+        let mut segment = Vec::new();
+        let mut segments = 0;
+        for chunk in samples.chunks_exact(size) {
+            power_spectrum(&self.fft, &self.window, chunk, &mut segment);
+            for (acc, v) in spectrum.iter_mut().zip(segment.iter()) {
+                *acc += v;
+            }
+            segments += 1;
+        }
+        if segments == 0 {
+            return SignalFeatures::default();
+        }
+
+        let mut sorted = spectrum.clone();
+        sorted.sort_by(f32::total_cmp);
+        let floor = sorted[size / 2];
+        let excess: Vec<f32> = spectrum.iter().map(|&p| (p - 2.0 * floor).max(0.0)).collect();
+        let signal: f32 = excess.iter().sum();
+        let noise = floor * size as f32;
+        let snr_db = 10.0 * (signal.max(f32::MIN_POSITIVE) / noise.max(f32::MIN_POSITIVE)).log10();
+
+        let mut lower_edge = 0;
+        let mut upper_edge = size - 1;
+        let mut acc = 0.0;
+        for (i, &p) in excess.iter().enumerate() {
+            acc += p;
+            if acc <= 0.005 * signal {
+                lower_edge = i + 1;
+            }
+            if acc >= 0.995 * signal {
+                upper_edge = i;
+                break;
+            }
+        }
+        let bin_hz = self.sample_rate as f32 / size as f32;
+        let bandwidth = (upper_edge.saturating_sub(lower_edge) + 1) as f32 * bin_hz;
+
+        let peak = (0..size).max_by(|&a, &b| excess[a].total_cmp(&excess[b])).unwrap();
+        let carrier: f32 = excess[peak.saturating_sub(1)..(peak + 2).min(size)].iter().sum();
+        let carrier_ratio = if signal > 0.0 { carrier / signal } else { 0.0 };
+
+        // leave the bins around DC out so a carrier doesn't count toward either side
+        let center = size / 2;
+        let lower: f32 = excess[..center - 1].iter().sum();
+        let upper: f32 = excess[center + 2..].iter().sum();
+        let spectral_symmetry = if lower + upper > 0.0 { (upper - lower) / (upper + lower) } else { 0.0 };
+
+        let envelope = samples.iter().map(|s| s.norm());
+        let n = samples.len() as f32;
+        let mean = envelope.clone().sum::<f32>() / n;
+        let std = (envelope.clone().map(|v| (v - mean).powi(2)).sum::<f32>() / n).sqrt();
+        let envelope_variation = if mean > 0.0 { std / mean } else { 0.0 };
+        let envelope_kurtosis = kurtosis(envelope);
+
+        let freq: Vec<f32> = samples.windows(2).map(|w| (w[1] * w[0].conj()).arg()).collect();
+        let frequency_step_kurtosis = kurtosis(freq.windows(2).map(|w| w[1] - w[0]));
+
+        SignalFeatures {
+            snr_db,
+            bandwidth,
+            carrier_ratio,
+            spectral_symmetry,
+            envelope_variation,
+            envelope_kurtosis,
+            frequency_step_kurtosis,
+        }
+    }
+
+    pub fn classify(&self, samples: &[Complex32]) -> Classification {
+        let features = self.features(samples);
+        let bin_hz = self.sample_rate as f32 / self.fft.size() as f32;
+
+        let modulation = if features.snr_db < 6.0 {
+            Modulation::Noise
+        } else if features.carrier_ratio > 0.8 && features.bandwidth < (300.0f32).max(4.0 * bin_hz) {
+            Modulation::CW
+        } else if features.spectral_symmetry.abs() > 0.6 {
+            Modulation::SSB
+        } else if features.envelope_variation > 0.2 {
+            if features.carrier_ratio > 0.3 { Modulation::AM } else { Modulation::Digital }
+        } else if features.frequency_step_kurtosis > 5.0 {
+            Modulation::Digital
+        } else {
+            Modulation::FM
+        };
+
+        Classification {
+            modulation,
+            features,
+        }
+    }
+}
+
+
+impl Filter<Complex32, Classification> for ModulationClassifier {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Classification>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.buffer.extend_from_slice(input);
+        while self.buffer.len() >= self.window_len {
+            output.push(self.classify(&self.buffer[..self.window_len]));
+            self.buffer.drain(..self.window_len);
+        }
+        Ok(())
+    }
+}
+
+
+/// Number of higher-order moment and cyclostationary values after the PSD bins.
+const MOMENT_FEATURES: usize = 4;
+const CYCLIC_FEATURES: usize = 6;
+
+
+/// Fixed-size feature vectors for external classifiers, one per `window_len` samples:
+/// - `psd_bins` DC centered PSD values in dB relative to their mean
+/// - |M20|/M21, |C40|/C21², |C41|/C21², C42/C21²
+/// - peak over mean in dB and normalized position of the strongest spectral line of |x|², x² and x⁴
+pub struct FeatureExtractor {
+    psd: FFT,
+    window: Vec<f32>,
+    cyclic: FFT,
+    window_len: usize,
+    buffer: Vec<Complex32>,
+}
+
+
+impl FeatureExtractor {
+    pub fn new(psd_bins: usize, window_len: usize) -> Self {
+        let window_len = window_len.max(psd_bins);
+        // the largest power of two that fits the window
+        let cyclic_len = 1 << window_len.ilog2();
+        Self {
+            psd: FFT::new(psd_bins),
+            window: Window::Hann.coefficients(psd_bins),
+            cyclic: FFT::new(cyclic_len),
+            window_len,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn feature_len(&self) -> usize {
+        self.psd.size() + MOMENT_FEATURES + CYCLIC_FEATURES
+    }
+
+    pub fn window_len(&self) -> usize {
+        self.window_len
+    }
+
+    pub fn extract(&self, samples: &[Complex32], features: &mut Vec<f32>) {
+        features.clear();
+
+        let size = self.psd.size();
+        let mut psd = vec![0f32; size];
+        let mut segment = Vec::new();
+        for chunk in samples.chunks_exact(size) {
+            power_spectrum(&self.psd, &self.window, chunk, &mut segment);
+            psd.iter_mut().zip(segment.iter()).for_each(|(acc, p)| *acc += p);
+        }
+        let psd_db: Vec<f32> = psd.iter().map(|p| 10.0 * p.max(1e-20).log10()).collect();
+        let mean_db = psd_db.iter().sum::<f32>() / size as f32;
+        features.extend(psd_db.iter().map(|p| p - mean_db));
+
+        let n = samples.len().max(1) as f32;
+        let m21 = (samples.iter().map(|x| x.norm_sqr()).sum::<f32>() / n).max(f32::MIN_POSITIVE);
+        let moment = |f: &dyn Fn(Complex32) -> Complex32| samples.iter().map(|&x| f(x)).sum::<Complex32>() / n;
+        let m20 = moment(&|x| x * x);
+        let m40 = moment(&|x| x * x * x * x);
+        let m41 = moment(&|x| x * x * x * x.conj());
+        let m42 = samples.iter().map(|x| x.norm_sqr().powi(2)).sum::<f32>() / n;
+        let c40 = m40 - 3.0 * m20 * m20;
+        let c41 = m41 - 3.0 * m20 * m21;
+        let c42 = m42 - m20.norm_sqr() - 2.0 * m21 * m21;
+        features.extend([m20.norm() / m21, c40.norm() / (m21 * m21), c41.norm() / (m21 * m21), c42 / (m21 * m21)]);
+
+        let len = self.cyclic.size();
+        let mut line = |f: &dyn Fn(Complex32) -> Complex32, remove_mean: bool| {
+            let mut buffer: Vec<Complex32> = samples.iter().take(len).map(|&x| f(x) / m21).collect();
+            buffer.resize(len, Complex32::new(0.0, 0.0));
+            if remove_mean {
+                let mean = buffer.iter().sum::<Complex32>() / len as f32;
+                buffer.iter_mut().for_each(|x| *x -= mean);
+            }
+            self.cyclic.forward(&mut buffer);
+            fftshift(&mut buffer);
+            let magnitude: Vec<f32> = buffer.iter().map(|x| x.norm()).collect();
+            let peak = (0..len).max_by(|&a, &b| magnitude[a].total_cmp(&magnitude[b])).unwrap_or(0);
+            let mean = magnitude.iter().sum::<f32>() / len as f32;
+            let ratio_db = 20.0 * (magnitude[peak] / mean.max(f32::MIN_POSITIVE)).max(1e-10).log10();
+            features.extend([ratio_db, (peak as f32 - (len / 2) as f32) / len as f32]);
+        };
+        // symbol rate lines in the envelope, carrier lines for BPSK and QPSK
+        line(&|x| Complex32::new(x.norm_sqr(), 0.0), true);
+        line(&|x| x * x, false);
+        line(&|x| (x * x) * (x * x), false);
+    }
+}
+
+
+impl Filter<Complex32, Vec<f32>> for FeatureExtractor {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Vec<f32>>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.buffer.extend_from_slice(input);
+        while self.buffer.len() >= self.window_len {
+            let mut features = Vec::with_capacity(self.feature_len());
+            self.extract(&self.buffer[..self.window_len], &mut features);
+            output.push(features);
+            self.buffer.drain(..self.window_len);
+        }
+        Ok(())
+    }
+}
+
+
+/// Hook for external classifiers taking [`FeatureExtractor`] vectors, e.g. [`OnnxModel`].
+pub trait FeatureModel {
+    fn predict(&mut self, features: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>>;
+}
+
+
+/// Runs a model on every feature vector and outputs its scores.
+pub struct ModelClassifier<M> {
+    extractor: FeatureExtractor,
+    model: M,
+    features: Vec<Vec<f32>>,
+}
+
+
+impl<M: FeatureModel> ModelClassifier<M> {
+    pub fn new(extractor: FeatureExtractor, model: M) -> Self {
+        Self {
+            extractor,
+            model,
+            features: Vec::new(),
+        }
+    }
+}
+
+
+impl<M: FeatureModel> Filter<Complex32, Vec<f32>> for ModelClassifier<M> {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Vec<f32>>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.extractor.filter(input, &mut self.features)?;
+        for features in &self.features {
+            let mut scores = Vec::new();
+            self.model.predict(features, &mut scores)?;
+            output.push(scores);
+        }
+        Ok(())
+    }
+}
+
+
+/// ONNX model with a single `[1, feature_len]` f32 input, the first output is returned flattened.
+/// The onnxruntime library is loaded at runtime, see `ORT_DYLIB_PATH`.
+#[cfg(feature = "onnx")]
+pub struct OnnxModel {
+    session: ort::session::Session,
+}
+
+
+#[cfg(feature = "onnx")]
+impl OnnxModel {
+    pub fn load(path: &std::path::Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            session: ort::session::Session::builder()?.commit_from_file(path)?,
+        })
+    }
+}
+
+
+#[cfg(feature = "onnx")]
+impl FeatureModel for OnnxModel {
+    fn predict(&mut self, features: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        let input = ort::value::Tensor::from_array(([1usize, features.len()], features.to_vec()))?;
+        let outputs = self.session.run(ort::inputs![input])?;
+        let (_, scores) = outputs[0].try_extract_tensor::<f32>()?;
+        output.clear();
+        output.extend_from_slice(scores);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::demod::DemodMode;
+    use crate::classify::{FeatureExtractor, FeatureModel, Modulation, ModelClassifier, ModulationClassifier};
+    use crate::traits::Filter;
+
+    fn noisy(signal: impl Fn(usize) -> Complex32) -> Vec<Complex32> {
+        let mut state = 0x9e37_79b9u32;
+        let mut rand = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        };
+        (0..16384).map(|n| signal(n) + Complex32::new(rand(), rand()) * 0.02).collect()
+    }
+
+    #[test]
+    fn test_classify() {
+        let fs = 48000.0;
+        let classifier = ModulationClassifier::new(48000, 1024, 16384);
+        let tone = |f: f32, n: usize| 2.0 * PI * f * n as f32 / fs;
+
+        let am = noisy(|n| Complex32::new(1.0 + 0.8 * tone(1000.0, n).cos(), 0.0));
+        assert_eq!(classifier.classify(&am).modulation, Modulation::AM);
+
+        let fm = noisy(|n| Complex32::from_polar(1.0, 5.0 * tone(1000.0, n).sin()));
+        assert_eq!(classifier.classify(&fm).modulation, Modulation::FM);
+
+        let ssb = noisy(|n| [300.0, 800.0, 1700.0].iter().map(|&f| Complex32::from_polar(0.5, tone(f, n))).sum());
+        assert_eq!(classifier.classify(&ssb).modulation, Modulation::SSB);
+        assert_eq!(classifier.classify(&ssb).demod_mode(), Some(DemodMode::USB));
+        let lsb: Vec<Complex32> = ssb.iter().map(|x| x.conj()).collect();
+        assert_eq!(classifier.classify(&lsb).demod_mode(), Some(DemodMode::LSB));
+
+        let cw = noisy(|_| Complex32::new(1.0, 0.0));
+        assert_eq!(classifier.classify(&cw).modulation, Modulation::CW);
+
+        let mut phase = 0.0;
+        let bits: Vec<f32> = (0..16384u32).map(|n| {
+            let bit = ((n / 40).wrapping_mul(2654435761) >> 7) & 1;
+            phase += 2.0 * PI * if bit == 1 { 2400.0 } else { -2400.0 } / fs;
+            phase
+        }).collect();
+        let fsk = noisy(|n| Complex32::from_polar(1.0, bits[n]));
+        assert_eq!(classifier.classify(&fsk).modulation, Modulation::Digital);
+
+        let noise = noisy(|_| Complex32::new(0.0, 0.0));
+        assert_eq!(classifier.classify(&noise).modulation, Modulation::Noise);
+    }
+
+    #[test]
+    fn test_feature_extractor() -> Result<(), Box<dyn std::error::Error>> {
+        struct Sum;
+        impl FeatureModel for Sum {
+            fn predict(&mut self, features: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn std::error::Error>> {
+                output.clear();
+                output.push(features.len() as f32);
+                Ok(())
+            }
+        }
+
+        let extractor = FeatureExtractor::new(64, 4096);
+        let tone = noisy(|n| Complex32::from_polar(1.0, 2.0 * PI * 0.1 * n as f32));
+        let bpsk = noisy(|n| {
+            let bit = ((n / 8) as u32).wrapping_mul(2654435761) >> 31;
+            Complex32::new(if bit == 1 { 1.0 } else { -1.0 }, 0.0)
+        });
+
+        let mut features = Vec::new();
+        extractor.extract(&tone[..4096], &mut features);
+        assert_eq!(features.len(), extractor.feature_len());
+        let c42 = features[64 + 3];
+        assert!((c42 + 1.0).abs() < 0.1, "tone C42 {}", c42);
+
+        extractor.extract(&bpsk[..4096], &mut features);
+        let c42 = features[64 + 3];
+        assert!((c42 + 2.0).abs() < 0.1, "bpsk C42 {}", c42);
+        // squaring BPSK strips the modulation and leaves a line at twice the carrier, here DC
+        let (ratio_db, position) = (features[64 + 6], features[64 + 7]);
+        assert!(ratio_db > 30.0 && position == 0.0, "{} {}", ratio_db, position);
+
+        let mut classifier = ModelClassifier::new(FeatureExtractor::new(64, 4096), Sum);
+        let mut scores = Vec::new();
+        classifier.filter(&bpsk, &mut scores)?;
+        assert_eq!(scores, vec![vec![74.0]; 4]);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,397 @@
+use std::error::Error;
+use std::f32::consts::PI;
+use num_complex::Complex32;
+use crate::fft::{fftshift, Window, FFT};
+use crate::traits::*;
+
+
+const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+
+
+/// Direction estimate for one frequency bin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bearing {
+    /// Absolute frequency of the bin in Hz.
+    pub frequency: f64,
+    /// Calibrated phase of channel 0 relative to channel 1, in radians.
+    pub phase: f32,
+    /// Angle from broadside in degrees, positive toward channel 0. `None` when the phase is out of range for the spacing.
+    pub angle: Option<f32>,
+    /// Magnitude of the averaged cross spectrum over the geometric mean of the channel powers, 1.0 for a single clean source.
+    pub coherence: f32,
+    pub power: f32,
+}
+
+
+/// Two antenna phase interferometer. Averages the cross spectrum of the channels over `averages` frames
+/// and turns the calibrated phase difference in each bin into a bearing.
+pub struct PhaseInterferometer {
+    fft: FFT,
+    window: Vec<f32>,
+    sample_rate: f64,
+    center_frequency: f64,
+    spacing: f64,
+    averages: usize,
+    min_coherence: f32,
+    calibration: Vec<f32>,
+    cross: Vec<Complex32>,
+    power: [Vec<f32>; 2],
+    frames: usize,
+    buffers: [Vec<Complex32>; 2],
+    spectra: [Vec<Complex32>; 2],
+}
+
+
+impl PhaseInterferometer {
+    /// `spacing` is the antenna separation in meters, it should be at most half a wavelength to avoid ambiguity.
+    pub fn new(fft_size: usize, sample_rate: f64, center_frequency: f64, spacing: f64, averages: usize) -> Self {
+        Self {
+            fft: FFT::new(fft_size),
+            window: Window::Hann.coefficients(fft_size),
+            sample_rate,
+            center_frequency,
+            spacing,
+            averages: averages.max(1),
+            min_coherence: 0.0,
+            calibration: vec![0.0; fft_size],
+            cross: vec![Complex32::new(0.0, 0.0); fft_size],
+            power: [vec![0.0; fft_size], vec![0.0; fft_size]],
+            frames: 0,
+            buffers: [Vec::new(), Vec::new()],
+            spectra: [Vec::new(), Vec::new()],
+        }
+    }
+
+    /// Only output bins at least this coherent.
+    pub fn set_min_coherence(&mut self, min_coherence: f32) {
+        self.min_coherence = min_coherence;
+    }
+
+    pub fn set_center_frequency(&mut self, center_frequency: f64) {
+        self.center_frequency = center_frequency;
+    }
+
+    /// Per bin phase offset between the channels (cables, front ends), subtracted before computing bearings.
+    pub fn set_calibration(&mut self, calibration: Vec<f32>) {
+        assert_eq!(calibration.len(), self.fft.size());
+        self.calibration = calibration;
+    }
+
+    pub fn calibration(&self) -> &[f32] {
+        &self.calibration
+    }
+
+    /// Measure the calibration from a reference transmitter at a known `angle` from broadside.
+    pub fn calibrate(&mut self, input: &[Vec<Complex32>], angle: f32) -> Result<(), Box<dyn Error>> {
+        self.calibration.fill(0.0);
+        let mut cross = vec![Complex32::new(0.0, 0.0); self.fft.size()];
+        let mut frames = 0;
+        for (a, b) in input[0].chunks_exact(self.fft.size()).zip(input[1].chunks_exact(self.fft.size())) {
+            self.transform(a, b);
+            for (acc, (x, y)) in cross.iter_mut().zip(self.spectra[0].iter().zip(self.spectra[1].iter())) {
+                *acc += x * y.conj();
+            }
+            frames += 1;
+        }
+        if frames == 0 {
+            return Err("not enough samples to calibrate".into());
+        }
+        for (k, acc) in cross.iter().enumerate() {
+            let expected = self.expected_phase(self.bin_frequency(k), angle);
+            self.calibration[k] = wrap(acc.arg() - expected);
+        }
+        Ok(())
+    }
+
+    fn bin_frequency(&self, k: usize) -> f64 {
+        let size = self.fft.size();
+        self.center_frequency + (k as f64 - (size / 2) as f64) * self.sample_rate / size as f64
+    }
+
+    fn expected_phase(&self, frequency: f64, angle: f32) -> f32 {
+        (2.0 * std::f64::consts::PI * self.spacing * frequency / SPEED_OF_LIGHT) as f32 * angle.to_radians().sin()
+    }
+
+    fn transform(&mut self, a: &[Complex32], b: &[Complex32]) {
+        for (spectrum, samples) in self.spectra.iter_mut().zip([a, b]) {
+            spectrum.clear();
+            spectrum.extend(samples.iter().zip(self.window.iter()).map(|(&s, &w)| s * w));
+            self.fft.forward(spectrum);
+            fftshift(spectrum);
+        }
+    }
+
+    fn emit(&mut self, output: &mut Vec<Bearing>) {
+        let norm = self.frames as f32;
+        for k in 0..self.fft.size() {
+            let p0 = self.power[0][k] / norm;
+            let p1 = self.power[1][k] / norm;
+            let cross = self.cross[k] / norm;
+            let coherence = cross.norm() / (p0 * p1).sqrt().max(f32::MIN_POSITIVE);
+            if coherence < self.min_coherence {
+                continue;
+            }
+
+            let frequency = self.bin_frequency(k);
+            let phase = wrap(cross.arg() - self.calibration[k]);
+            let full_scale = self.expected_phase(frequency, 90.0);
+            let sine = phase / full_scale;
+            let angle = if sine.abs() <= 1.0 { Some(sine.asin().to_degrees()) } else { None };
+
+            output.push(Bearing {
+                frequency,
+                phase,
+                angle,
+                coherence,
+                power: (p0 + p1) / 2.0,
+            });
+        }
+
+        self.cross.fill(Complex32::new(0.0, 0.0));
+        self.power[0].fill(0.0);
+        self.power[1].fill(0.0);
+        self.frames = 0;
+    }
+}
+
+
+fn wrap(phase: f32) -> f32 {
+    (phase + PI).rem_euclid(2.0 * PI) - PI
+}
+
+
+impl CoherentFilter<Complex32, Bearing> for PhaseInterferometer {
+    fn filter_coherent(&mut self, input: &[Vec<Complex32>], output: &mut Vec<Bearing>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        if input.len() != 2 || input[0].len() != input[1].len() {
+            return Err("expected two channels of the same length".into());
+        }
+
+        let size = self.fft.size();
+        for (buffer, channel) in self.buffers.iter_mut().zip(input.iter()) {
+            buffer.extend_from_slice(channel);
+        }
+        while self.buffers[0].len() >= size {
+            let a: Vec<Complex32> = self.buffers[0].drain(..size).collect();
+            let b: Vec<Complex32> = self.buffers[1].drain(..size).collect();
+            self.transform(&a, &b);
+            for k in 0..size {
+                let (x, y) = (self.spectra[0][k], self.spectra[1][k]);
+                self.cross[k] += x * y.conj();
+                self.power[0][k] += x.norm_sqr();
+                self.power[1][k] += y.norm_sqr();
+            }
+            self.frames += 1;
+            if self.frames == self.averages {
+                self.emit(output);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Combining {
+    /// Weight each channel by its complex gain so the signal adds coherently, best with similar channels.
+    MaximalRatio,
+    /// Pass the channel with the better SNR through, switching only when the other is ahead by `SELECTION_HYSTERESIS_DB`.
+    Selection,
+}
+
+
+const SELECTION_HYSTERESIS_DB: f32 = 1.0;
+
+
+/// Merges two coherent IQ streams into one. Channel gains and SNR come from the averaged 2x2 covariance,
+/// assuming both receivers have the same noise power, so the smaller eigenvalue is the noise.
+pub struct DiversityCombiner {
+    mode: Combining,
+    block_len: usize,
+    alpha: f32,
+    covariance: [f32; 2],
+    cross: Complex32,
+    primed: bool,
+    selected: usize,
+    snr: [f32; 2],
+    weights: [Complex32; 2],
+    buffers: [Vec<Complex32>; 2],
+}
+
+
+impl DiversityCombiner {
+    /// Weights are updated every `block_len` samples, `alpha` is how much each block moves the covariance average.
+    pub fn new(mode: Combining, block_len: usize, alpha: f32) -> Self {
+        Self {
+            mode,
+            block_len: block_len.max(1),
+            alpha,
+            covariance: [0.0; 2],
+            cross: Complex32::new(0.0, 0.0),
+            primed: false,
+            selected: 0,
+            snr: [0.0; 2],
+            weights: [Complex32::new(1.0, 0.0), Complex32::new(0.0, 0.0)],
+            buffers: [Vec::new(), Vec::new()],
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: Combining) {
+        self.mode = mode;
+    }
+
+    /// Estimated SNR of each channel in dB.
+    pub fn snr_db(&self) -> [f32; 2] {
+        self.snr.map(|v| 10.0 * v.max(f32::MIN_POSITIVE).log10())
+    }
+
+    /// Channel currently passed through in selection mode.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    fn update(&mut self, a: &[Complex32], b: &[Complex32]) {
+        let n = a.len() as f32;
+        let r00 = a.iter().map(|v| v.norm_sqr()).sum::<f32>() / n;
+        let r11 = b.iter().map(|v| v.norm_sqr()).sum::<f32>() / n;
+        let r01 = a.iter().zip(b.iter()).map(|(x, y)| x * y.conj()).sum::<Complex32>() / n;
+        let alpha = if self.primed { self.alpha } else { 1.0 };
+        self.covariance[0] += alpha * (r00 - self.covariance[0]);
+        self.covariance[1] += alpha * (r11 - self.covariance[1]);
+        self.cross += (r01 - self.cross) * alpha;
+        self.primed = true;
+
+        let [p0, p1] = self.covariance;
+        let c = self.cross.norm();
+        let spread = (((p0 - p1) / 2.0).powi(2) + c * c).sqrt();
+        let largest = (p0 + p1) / 2.0 + spread;
+        let noise = ((p0 + p1) / 2.0 - spread).max(f32::MIN_POSITIVE);
+        self.snr = [((p0 - noise) / noise).max(0.0), ((p1 - noise) / noise).max(0.0)];
+
+        // principal eigenvector (c, largest - p0), rotated so channel 0 has zero phase
+        let v = if c > 1e-12 * (p0 + p1) {
+            [Complex32::new(c, 0.0), self.cross.conj() / c * (largest - p0)]
+        } else if p0 >= p1 {
+            [Complex32::new(1.0, 0.0), Complex32::new(0.0, 0.0)]
+        } else {
+            [Complex32::new(0.0, 0.0), Complex32::new(1.0, 0.0)]
+        };
+        let norm = (v[0].norm_sqr() + v[1].norm_sqr()).sqrt();
+        self.weights = [v[0].conj() / norm, v[1].conj() / norm];
+
+        let [s0, s1] = self.snr_db();
+        let other = 1 - self.selected;
+        let (current, candidate) = if self.selected == 0 { (s0, s1) } else { (s1, s0) };
+        if candidate > current + SELECTION_HYSTERESIS_DB {
+            self.selected = other;
+        }
+    }
+}
+
+
+impl CoherentFilter<Complex32, Complex32> for DiversityCombiner {
+    fn filter_coherent(&mut self, input: &[Vec<Complex32>], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        if input.len() != 2 || input[0].len() != input[1].len() {
+            return Err("expected two channels of the same length".into());
+        }
+
+        for (buffer, channel) in self.buffers.iter_mut().zip(input.iter()) {
+            buffer.extend_from_slice(channel);
+        }
+        while self.buffers[0].len() >= self.block_len {
+            let a: Vec<Complex32> = self.buffers[0].drain(..self.block_len).collect();
+            let b: Vec<Complex32> = self.buffers[1].drain(..self.block_len).collect();
+            self.update(&a, &b);
+            match self.mode {
+                Combining::MaximalRatio => {
+                    let [w0, w1] = self.weights;
+                    output.extend(a.iter().zip(b.iter()).map(|(x, y)| w0 * x + w1 * y));
+                },
+                Combining::Selection => {
+                    output.extend_from_slice(if self.selected == 0 { &a } else { &b });
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::coherent::{Combining, DiversityCombiner, PhaseInterferometer};
+    use crate::traits::CoherentFilter;
+
+    #[test]
+    fn test_phase_interferometer() -> Result<(), Box<dyn std::error::Error>> {
+        let center = 433.92e6;
+        let spacing = 0.3;
+        let wavelength = 299_792_458.0 / center;
+        let cable = 1.1;
+
+        // tone 8 bins above center arriving from `angle`, channel 1 lags through a longer cable
+        let signal = |angle: f32| {
+            let delay = (2.0 * std::f64::consts::PI * spacing / wavelength) as f32 * angle.to_radians().sin();
+            let tone = |n: usize, phase: f32| Complex32::from_polar(1.0, 2.0 * PI * 8.0 * n as f32 / 256.0 + phase);
+            vec![
+                (0..4096).map(|n| tone(n, delay)).collect::<Vec<_>>(),
+                (0..4096).map(|n| tone(n, -cable)).collect::<Vec<_>>(),
+            ]
+        };
+
+        let mut df = PhaseInterferometer::new(256, 256e3, center, spacing, 16);
+        df.calibrate(&signal(0.0), 0.0)?;
+        df.set_min_coherence(0.9);
+
+        let mut output = Vec::new();
+        df.filter_coherent(&signal(30.0), &mut output)?;
+        let peak = output.iter().max_by(|a, b| a.power.total_cmp(&b.power)).unwrap();
+        assert!((peak.frequency - center - 8e3).abs() < 1.0);
+        let angle = peak.angle.unwrap();
+        assert!((angle - 30.0).abs() < 1.0, "angle {}", angle);
+        Ok(())
+    }
+
+    fn snr_db(output: &[Complex32], reference: &[Complex32]) -> f32 {
+        let gain = output.iter().zip(reference).map(|(y, s)| y * s.conj()).sum::<Complex32>()
+            / reference.iter().map(|s| s.norm_sqr()).sum::<f32>();
+        let signal: f32 = reference.iter().map(|s| (s * gain).norm_sqr()).sum();
+        let noise: f32 = output.iter().zip(reference).map(|(y, s)| (y - s * gain).norm_sqr()).sum();
+        10.0 * (signal / noise).log10()
+    }
+
+    #[test]
+    fn test_diversity_combiner() -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = 0x2545_f491u32;
+        let mut rand = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        };
+        let reference: Vec<Complex32> = (0..32768).map(|n| Complex32::from_polar(1.0, 0.01 * n as f32 + (n as f32 * 0.003).sin())).collect();
+        let gains = [Complex32::new(1.0, 0.0), Complex32::from_polar(0.8, 1.0)];
+        let input: Vec<Vec<Complex32>> = gains.iter()
+            .map(|&h| reference.iter().map(|&s| s * h + Complex32::new(rand(), rand())).collect())
+            .collect();
+        let single = snr_db(&input[0], &reference);
+
+        let mut mrc = DiversityCombiner::new(Combining::MaximalRatio, 1024, 0.1);
+        let mut output = Vec::new();
+        mrc.filter_coherent(&input, &mut output)?;
+        let combined = snr_db(&output[4096..], &reference[4096..]);
+        assert!(combined > single + 1.5, "combined {} single {}", combined, single);
+        let [s0, s1] = mrc.snr_db();
+        assert!((s0 - single).abs() < 1.0 && s1 < s0, "snr {} {}", s0, s1);
+
+        let mut selection = DiversityCombiner::new(Combining::Selection, 1024, 0.1);
+        selection.filter_coherent(&input, &mut output)?;
+        assert_eq!(selection.selected(), 0);
+        assert_eq!(output, input[0]);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,312 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::iqfile::{IQFileSink, IQFileSource, IQFormat};
+
+
+/// Uncompressed bytes per frame unless `set_frame_size` says otherwise.
+const DEFAULT_FRAME_SIZE: usize = 1 << 20;
+const SKIPPABLE_MAGIC: u32 = 0x184d2a5e;
+const SEEKABLE_MAGIC: u32 = 0x8f92eab1;
+/// Frame count, descriptor and magic at the very end of a seekable file.
+const FOOTER_LEN: usize = 9;
+/// Largest block in a zstd frame. Every block takes at least 4 bytes, so no frame expands to more
+/// than this per 4 compressed bytes.
+const MAX_BLOCK: u64 = 128 << 10;
+
+
+/// The I/Q format of a compressed recording named like `capture.cs16.zst`.
+pub fn compressed_format(path: &Path) -> Option<IQFormat> {
+    match path.extension()?.to_str()? {
+        "zst" => IQFormat::from_path(&path.with_extension("")),
+        _ => None,
+    }
+}
+
+
+fn format_for(path: &Path, format: Option<IQFormat>) -> Result<IQFormat, Box<dyn Error>> {
+    Ok(format.or_else(|| compressed_format(path))
+        .ok_or_else(|| format!("{}: unknown I/Q format, expected an I/Q extension like .cs16 followed by .zst", path.display()))?)
+}
+
+
+/// Compresses what's written into independent zstd frames of a fixed uncompressed size, ending with
+/// a seek table in the zstd seekable format. The `zstd` tool decompresses the result like any other
+/// zstd file, `ZstdFrameReader` can also jump to any frame. Without `finish` the last frame and the
+/// seek table are lost.
+pub struct ZstdFrameWriter<W: Write> {
+    writer: W,
+    level: i32,
+    frame_size: usize,
+    buffer: Vec<u8>,
+    /// Compressed and uncompressed size of each frame written.
+    frames: Vec<(u32, u32)>,
+}
+
+
+impl<W: Write> ZstdFrameWriter<W> {
+    /// `level` from 1 to 22, 3 is zstd's default.
+    pub fn new(writer: W, level: i32) -> Self {
+        Self {
+            writer,
+            level,
+            frame_size: DEFAULT_FRAME_SIZE,
+            buffer: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Defaults to 1 MiB. Smaller frames make seeking cheaper and compress a little worse.
+    pub fn set_frame_size(&mut self, bytes: usize) {
+        self.frame_size = bytes.max(1);
+    }
+
+    fn write_frame(&mut self, len: usize) -> std::io::Result<()> {
+        let compressed = zstd::bulk::compress(&self.buffer[..len], self.level)?;
+        self.writer.write_all(&compressed)?;
+        self.frames.push((compressed.len() as u32, len as u32));
+        self.buffer.drain(..len);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Box<dyn Error>> {
+        if !self.buffer.is_empty() {
+            self.write_frame(self.buffer.len())?;
+        }
+        let mut table = Vec::with_capacity(8 * self.frames.len() + 8 + FOOTER_LEN);
+        table.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        table.extend_from_slice(&((8 * self.frames.len() + FOOTER_LEN) as u32).to_le_bytes());
+        for (compressed, uncompressed) in &self.frames {
+            table.extend_from_slice(&compressed.to_le_bytes());
+            table.extend_from_slice(&uncompressed.to_le_bytes());
+        }
+        table.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        // no checksums
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+        self.writer.write_all(&table)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+
+impl<W: Write> Write for ZstdFrameWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() >= self.frame_size {
+            self.write_frame(self.frame_size)?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Only flushes whole frames, a partial one waits for more data or `finish`.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+
+/// Reads what `ZstdFrameWriter` wrote, or any zstd file in the seekable format, decompressing one
+/// frame at a time. Seeking decompresses only the frame sought to.
+pub struct ZstdFrameReader<R: Read + Seek> {
+    reader: R,
+    /// Compressed offset, uncompressed offset and sizes of each frame.
+    frames: Vec<(u64, u64, u32, u32)>,
+    length: u64,
+    current: Option<usize>,
+    frame: Vec<u8>,
+    position: u64,
+}
+
+
+impl<R: Read + Seek> ZstdFrameReader<R> {
+    /// The seek table is checked against the file length, so a damaged one is an error rather than
+    /// a huge allocation.
+    pub fn new(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let not_seekable = "no zstd seek table, the file wasn't finished or isn't seekable";
+        let corrupt = "corrupt zstd seek table";
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        let mut footer = [0u8; FOOTER_LEN];
+        reader.seek(SeekFrom::End(-(FOOTER_LEN as i64))).map_err(|_| not_seekable)?;
+        reader.read_exact(&mut footer)?;
+        let count = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+        if u32::from_le_bytes(footer[5..].try_into().unwrap()) != SEEKABLE_MAGIC {
+            return Err(not_seekable.into());
+        }
+        let entry_len = if footer[4] & 0x80 != 0 { 12 } else { 8 };
+        // the frames, then the skippable frame header, the entries and the footer
+        let frames_len = count.checked_mul(entry_len)
+            .and_then(|table_len| file_len.checked_sub(table_len + 8 + FOOTER_LEN as u64))
+            .ok_or(corrupt)?;
+        let mut table = vec![0u8; (count * entry_len) as usize];
+        reader.seek(SeekFrom::End(-((FOOTER_LEN + table.len()) as i64)))?;
+        reader.read_exact(&mut table)?;
+
+        let mut frames = Vec::with_capacity(count as usize);
+        let (mut compressed_at, mut length) = (0u64, 0u64);
+        for entry in table.chunks_exact(entry_len as usize) {
+            let compressed = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            let uncompressed = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+            if uncompressed as u64 > (compressed as u64).div_ceil(4) * MAX_BLOCK {
+                return Err(corrupt.into());
+            }
+            frames.push((compressed_at, length, compressed, uncompressed));
+            compressed_at += compressed as u64;
+            length += uncompressed as u64;
+        }
+        if compressed_at > frames_len {
+            return Err(corrupt.into());
+        }
+        Ok(Self {
+            reader,
+            frames,
+            length,
+            current: None,
+            frame: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// Uncompressed length.
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    fn load(&mut self, index: usize) -> std::io::Result<()> {
+        if self.current == Some(index) {
+            return Ok(());
+        }
+        let (offset, _, compressed, uncompressed) = self.frames[index];
+        let mut data = vec![0u8; compressed as usize];
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(&mut data)?;
+        self.frame = zstd::bulk::decompress(&data, uncompressed as usize)?;
+        if self.frame.len() != uncompressed as usize {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "zstd frame size differs from the seek table"));
+        }
+        self.current = Some(index);
+        Ok(())
+    }
+}
+
+
+impl<R: Read + Seek> Read for ZstdFrameReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.length || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.frames.partition_point(|frame| frame.1 <= self.position) - 1;
+        self.load(index)?;
+        let start = (self.position - self.frames[index].1) as usize;
+        let len = buf.len().min(self.frame.len() - start);
+        buf[..len].copy_from_slice(&self.frame[start..start + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+
+impl<R: Read + Seek> Seek for ZstdFrameReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.position)
+    }
+}
+
+
+impl IQFileSource<ZstdFrameReader<BufReader<File>>> {
+    /// A `.zst` compressed recording, the format comes from the extension before it when `format`
+    /// is None.
+    pub fn open_zstd(path: PathBuf, format: Option<IQFormat>, sample_rate: u32) -> Result<Self, Box<dyn Error>> {
+        let format = format_for(&path, format)?;
+        let reader = ZstdFrameReader::new(BufReader::new(File::open(&path)?)).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self::new(reader, format, sample_rate))
+    }
+}
+
+
+impl IQFileSink<ZstdFrameWriter<BufWriter<File>>> {
+    /// Compressed with `level`, see `ZstdFrameWriter`. Finish with `into_inner()?.finish()`.
+    pub fn create_zstd(path: PathBuf, format: Option<IQFormat>, level: i32) -> Result<Self, Box<dyn Error>> {
+        let format = format_for(&path, format)?;
+        Ok(Self::new(ZstdFrameWriter::new(BufWriter::new(File::create(path)?), level), format))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+    use num_complex::Complex32;
+    use crate::compress::{compressed_format, ZstdFrameReader, ZstdFrameWriter};
+    use crate::iqfile::{IQFileSink, IQFileSource, IQFormat};
+    use crate::traits::{Sink, Source};
+
+    #[test]
+    fn test_zstd_frames() -> Result<(), Box<dyn Error>> {
+        assert_eq!(compressed_format(Path::new("capture.cs16.zst")), Some(IQFormat::CS16));
+        assert_eq!(compressed_format(Path::new("capture.cs16")), None);
+
+        // an oversampled tone, 8-bit like a HackRF capture
+        let samples: Vec<Complex32> = (0..100_000).map(|n| Complex32::from_polar(0.5, n as f32 * 0.01)).collect();
+        let mut writer = ZstdFrameWriter::new(Vec::new(), 3);
+        writer.set_frame_size(16384);
+        let mut sink = IQFileSink::new(writer, IQFormat::CS8);
+        for block in samples.chunks(3000) {
+            sink.write(block)?;
+        }
+        let compressed = sink.into_inner()?.finish()?;
+        assert!(compressed.len() < samples.len(), "{} bytes", compressed.len());
+
+        // plain zstd sees the raw recording and skips the seek table
+        let raw = zstd::stream::decode_all(compressed.as_slice())?;
+        assert_eq!(raw.len(), 2 * samples.len());
+
+        let mut reader = ZstdFrameReader::new(Cursor::new(compressed.as_slice()))?;
+        assert_eq!(reader.len(), raw.len() as u64);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read)?;
+        assert_eq!(read, raw);
+        reader.seek(SeekFrom::Start(50_001))?;
+        let mut byte = [0u8; 3];
+        reader.read_exact(&mut byte)?;
+        assert_eq!(byte, raw[50_001..50_004]);
+
+        let mut source = IQFileSource::new(ZstdFrameReader::new(Cursor::new(compressed.as_slice()))?, IQFormat::CS8, 48000);
+        source.seek(70_000)?;
+        let mut block = Vec::new();
+        source.read(&mut block)?;
+        assert!((block[0] - samples[70_000]).norm() < 1e-2);
+
+        // a damaged seek table is caught before anything is allocated from it
+        let mut damaged = compressed.clone();
+        let footer = damaged.len() - 9;
+        damaged[footer..footer + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(ZstdFrameReader::new(Cursor::new(damaged.as_slice())).is_err());
+        let mut damaged = compressed.clone();
+        let first_entry = damaged.len() - 9 - 8 * reader.frames.len();
+        damaged[first_entry + 4..first_entry + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(ZstdFrameReader::new(Cursor::new(damaged.as_slice())).is_err());
+
+        // unfinished files can't be read this way
+        let mut unfinished = ZstdFrameWriter::new(Vec::new(), 3);
+        unfinished.write_all(&raw)?;
+        let mut cursor = Cursor::new(unfinished.writer.clone());
+        cursor.seek(SeekFrom::End(0))?;
+        assert!(ZstdFrameReader::new(cursor).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use crate::recorder::ScanChannel;
+
+
+/// A squelch edge on a monitored channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CorEvent {
+    Open,
+    Close,
+}
+
+
+impl CorEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CorEvent::Open => "open",
+            CorEvent::Close => "close",
+        }
+    }
+}
+
+
+/// Something driven by the carrier operated relay, e.g. a repeater controller input or a notification.
+pub trait CorHook {
+    fn on_event(&mut self, event: CorEvent, channel: &ScanChannel) -> Result<(), Box<dyn Error>>;
+}
+
+
+impl<F: FnMut(CorEvent, &ScanChannel) -> Result<(), Box<dyn Error>>> CorHook for F {
+    fn on_event(&mut self, event: CorEvent, channel: &ScanChannel) -> Result<(), Box<dyn Error>> {
+        self(event, channel)
+    }
+}
+
+
+/// Runs a shell command on every edge without waiting for it. The command sees `COR_STATE`
+/// (`open` or `close`), `COR_FREQUENCY` and `COR_LABEL` in its environment.
+pub struct ExecHook {
+    command: String,
+    children: Vec<Child>,
+}
+
+
+impl ExecHook {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Blocks until every command started so far has exited.
+    pub fn wait(&mut self) -> Result<(), Box<dyn Error>> {
+        for mut child in self.children.drain(..) {
+            child.wait()?;
+        }
+        Ok(())
+    }
+}
+
+
+impl CorHook for ExecHook {
+    fn on_event(&mut self, event: CorEvent, channel: &ScanChannel) -> Result<(), Box<dyn Error>> {
+        // reap the ones that finished so they don't pile up as zombies
+        self.children.retain_mut(|child| !matches!(child.try_wait(), Ok(Some(_))));
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("COR_STATE", event.as_str())
+            .env("COR_FREQUENCY", channel.frequency.to_string())
+            .env("COR_LABEL", &channel.label)
+            .spawn()?;
+        self.children.push(child);
+        Ok(())
+    }
+}
+
+
+/// A digital output line.
+pub trait GpioPin {
+    fn set(&mut self, high: bool) -> Result<(), Box<dyn Error>>;
+}
+
+
+/// A line exported through `/sys/class/gpio` and already configured as an output.
+pub struct SysfsGpio {
+    value: PathBuf,
+}
+
+
+impl SysfsGpio {
+    pub fn new(number: u32) -> Self {
+        Self {
+            value: PathBuf::from(format!("/sys/class/gpio/gpio{}/value", number)),
+        }
+    }
+}
+
+
+impl GpioPin for SysfsGpio {
+    fn set(&mut self, high: bool) -> Result<(), Box<dyn Error>> {
+        OpenOptions::new().write(true).open(&self.value)?.write_all(if high { b"1" } else { b"0" })?;
+        Ok(())
+    }
+}
+
+
+/// Holds a GPIO line asserted while the squelch is open.
+pub struct GpioHook<P: GpioPin> {
+    pin: P,
+    active_low: bool,
+}
+
+
+impl<P: GpioPin> GpioHook<P> {
+    pub fn new(pin: P, active_low: bool) -> Self {
+        Self {
+            pin,
+            active_low,
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.pin
+    }
+}
+
+
+impl<P: GpioPin> CorHook for GpioHook<P> {
+    fn on_event(&mut self, event: CorEvent, _: &ScanChannel) -> Result<(), Box<dyn Error>> {
+        self.pin.set((event == CorEvent::Open) != self.active_low)
+    }
+}
+
+
+/// Carrier operated relay: follows the squelch and fires its hooks on the open and close edges.
+#[derive(Default)]
+pub struct Cor {
+    hooks: Vec<Box<dyn CorHook>>,
+    open: bool,
+}
+
+
+impl Cor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_hook(&mut self, hook: Box<dyn CorHook>) {
+        self.hooks.push(hook);
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Feeds the squelch state, returning the edge if it changed. Every hook runs even if one fails,
+    /// the first error is returned.
+    pub fn update(&mut self, open: bool, channel: &ScanChannel) -> Result<Option<CorEvent>, Box<dyn Error>> {
+        if open == self.open {
+            return Ok(None);
+        }
+        self.open = open;
+        let event = if open { CorEvent::Open } else { CorEvent::Close };
+        let mut first = None;
+        for hook in self.hooks.iter_mut() {
+            if let Err(e) = hook.on_event(event, channel) {
+                first.get_or_insert(e);
+            }
+        }
+        match first {
+            Some(e) => Err(e),
+            None => Ok(Some(event)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::error::Error;
+    use std::rc::Rc;
+    use crate::cor::{Cor, CorEvent, CorHook, ExecHook, GpioHook, GpioPin};
+    use crate::recorder::ScanChannel;
+
+    struct Pin(Rc<RefCell<Vec<bool>>>);
+
+    impl GpioPin for Pin {
+        fn set(&mut self, high: bool) -> Result<(), Box<dyn Error>> {
+            self.0.borrow_mut().push(high);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cor_hooks() -> Result<(), Box<dyn Error>> {
+        let channel = ScanChannel { frequency: 146_520_000, label: "simplex".into() };
+        let levels = Rc::new(RefCell::new(Vec::new()));
+        let events = Rc::new(RefCell::new(Vec::new()));
+
+        let mut cor = Cor::new();
+        cor.add_hook(Box::new(GpioHook::new(Pin(levels.clone()), true)));
+        let seen = events.clone();
+        cor.add_hook(Box::new(move |event, _: &ScanChannel| {
+            seen.borrow_mut().push(event);
+            Ok(())
+        }));
+        for open in [false, true, true, false, false, true] {
+            cor.update(open, &channel)?;
+        }
+        assert_eq!(*events.borrow(), [CorEvent::Open, CorEvent::Close, CorEvent::Open]);
+        assert_eq!(*levels.borrow(), [false, true, false]);
+
+        let path = std::env::temp_dir().join(format!("rust_dsp_cor_{}.txt", std::process::id()));
+        let mut exec = ExecHook::new(&format!("echo \"$COR_STATE $COR_FREQUENCY $COR_LABEL\" >> {}", path.display()));
+        exec.on_event(CorEvent::Open, &channel)?;
+        exec.wait()?;
+        assert_eq!(std::fs::read_to_string(&path)?, "open 146520000 simplex\n");
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use num_complex::Complex32;
+use crate::block::WavSink;
+use crate::packet::Crc16;
+use crate::seed::Rng;
+use crate::traits::Sink;
+use crate::util::rrc_taps;
+
+
+/// Peak amplitude of the clean signal, leaves headroom for the added noise.
+const LEVEL: f32 = 0.5;
+const PREAMBLE: [u8; 4] = [0x55; 4];
+const SYNC_WORD: [u8; 4] = [0xd3, 0x91, 0xd3, 0x91];
+
+
+/// A synthetic signal with known parameters, the ground truth for a decoder test.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TestSignal {
+    Fm { tone_hz: f32, deviation_hz: f32 },
+    Am { tone_hz: f32, depth: f32 },
+    /// RRC shaped BPSK bursts: preamble, sync word `d391d391`, length byte, payload, CCITT-FALSE CRC.
+    Psk { baud: u32, payloads: Vec<Vec<u8>> },
+    Noise { power_db: f32 },
+}
+
+
+impl TestSignal {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TestSignal::Fm { .. } => "fm",
+            TestSignal::Am { .. } => "am",
+            TestSignal::Psk { .. } => "psk",
+            TestSignal::Noise { .. } => "noise",
+        }
+    }
+
+    /// `key=value` pairs separated by `;`, payloads are hex separated by `|`.
+    pub fn params(&self) -> String {
+        match self {
+            TestSignal::Fm { tone_hz, deviation_hz } => format!("tone_hz={};deviation_hz={}", tone_hz, deviation_hz),
+            TestSignal::Am { tone_hz, depth } => format!("tone_hz={};depth={}", tone_hz, depth),
+            TestSignal::Psk { baud, payloads } => {
+                let payloads: Vec<String> = payloads.iter()
+                    .map(|payload| payload.iter().map(|b| format!("{:02x}", b)).collect())
+                    .collect();
+                format!("baud={};payloads={}", baud, payloads.join("|"))
+            },
+            TestSignal::Noise { power_db } => format!("power_db={}", power_db),
+        }
+    }
+}
+
+
+/// One file of the corpus.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorpusEntry {
+    pub name: String,
+    pub signal: TestSignal,
+    /// Noise added relative to the mean signal power, `None` for a clean file.
+    pub snr_db: Option<f32>,
+}
+
+
+impl CorpusEntry {
+    pub fn new(name: &str, signal: TestSignal, snr_db: Option<f32>) -> Self {
+        Self {
+            name: name.to_string(),
+            signal,
+            snr_db,
+        }
+    }
+}
+
+
+/// The files written by `rust_dsp generate`.
+pub fn default_corpus() -> Vec<CorpusEntry> {
+    let payloads = vec![b"hello world".to_vec(), (0..32).collect(), vec![0xff; 8]];
+    vec![
+        CorpusEntry::new("fm_1khz", TestSignal::Fm { tone_hz: 1000.0, deviation_hz: 5000.0 }, None),
+        CorpusEntry::new("fm_1khz_snr10", TestSignal::Fm { tone_hz: 1000.0, deviation_hz: 5000.0 }, Some(10.0)),
+        CorpusEntry::new("am_400hz_50pct", TestSignal::Am { tone_hz: 400.0, depth: 0.5 }, None),
+        CorpusEntry::new("am_400hz_90pct_snr20", TestSignal::Am { tone_hz: 400.0, depth: 0.9 }, Some(20.0)),
+        CorpusEntry::new("bpsk_4800", TestSignal::Psk { baud: 4800, payloads: payloads.clone() }, None),
+        CorpusEntry::new("bpsk_4800_snr12", TestSignal::Psk { baud: 4800, payloads }, Some(12.0)),
+        CorpusEntry::new("noise", TestSignal::Noise { power_db: -20.0 }, None),
+    ]
+}
+
+
+/// Synthesizes labeled IQ test files, deterministic for a given seed. The seed defaults to one
+/// derived from the crate wide seed.
+pub struct CorpusGenerator {
+    sample_rate: u32,
+    samples: usize,
+    seed: u32,
+}
+
+
+impl CorpusGenerator {
+    pub fn new(sample_rate: u32, seconds: f32) -> Self {
+        Self {
+            sample_rate,
+            samples: (sample_rate as f32 * seconds) as usize,
+            seed: Rng::stream("corpus").state(),
+        }
+    }
+
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.seed = seed.max(1);
+        self
+    }
+
+    pub fn synthesize(&self, signal: &TestSignal, snr_db: Option<f32>) -> Result<Vec<Complex32>, Box<dyn Error>> {
+        let rate = self.sample_rate as f32;
+        let mut samples = match signal {
+            TestSignal::Fm { tone_hz, deviation_hz } => {
+                let mut phase = 0f32;
+                (0..self.samples).map(|n| {
+                    let t = n as f32 / rate;
+                    phase = (phase + 2.0 * PI * deviation_hz * (2.0 * PI * tone_hz * t).sin() / rate) % (2.0 * PI);
+                    Complex32::from_polar(LEVEL, phase)
+                }).collect()
+            },
+            TestSignal::Am { tone_hz, depth } => (0..self.samples).map(|n| {
+                let t = n as f32 / rate;
+                let envelope = (1.0 + depth * (2.0 * PI * tone_hz * t).cos()) / (1.0 + depth);
+                Complex32::new(LEVEL * envelope, 0.0)
+            }).collect(),
+            TestSignal::Psk { baud, payloads } => self.psk(*baud, payloads)?,
+            TestSignal::Noise { .. } => vec![Complex32::ZERO; self.samples],
+        };
+
+        let noise_power = match (signal, snr_db) {
+            (TestSignal::Noise { power_db }, _) => 10f32.powf(power_db / 10.0),
+            (_, Some(snr_db)) => {
+                let power = samples.iter().map(|s| s.norm_sqr()).sum::<f32>() / samples.len().max(1) as f32;
+                power / 10f32.powf(snr_db / 10.0)
+            },
+            (_, None) => 0.0,
+        };
+        if noise_power > 0.0 {
+            let sigma = (noise_power / 2.0).sqrt();
+            let mut rng = Rng::new(self.seed);
+            for sample in samples.iter_mut() {
+                *sample += rng.gaussian() * sigma;
+            }
+        }
+        Ok(samples)
+    }
+
+    fn psk(&self, baud: u32, payloads: &[Vec<u8>]) -> Result<Vec<Complex32>, Box<dyn Error>> {
+        let sps = self.sample_rate / baud;
+        if sps < 2 || !self.sample_rate.is_multiple_of(baud) {
+            return Err(format!("sample rate {} is not a multiple of twice the baud {}", self.sample_rate, baud).into());
+        }
+        let taps = rrc_taps(sps as f32, 0.35, 8 * sps as usize + 1);
+        let peak = taps.iter().fold(0f32, |m, t| m.max(t.abs()));
+
+        let mut impulses = vec![0f32; self.samples];
+        let gap = self.samples / (payloads.len() + 1);
+        for (i, payload) in payloads.iter().enumerate() {
+            let frame = psk_frame(payload)?;
+            let start = (gap * (i + 1)).saturating_sub(frame.len() * 8 * sps as usize / 2);
+            for (j, bit) in frame.iter().flat_map(|byte| (0..8).rev().map(move |k| (byte >> k) & 1)).enumerate() {
+                if let Some(slot) = impulses.get_mut(start + j * sps as usize) {
+                    *slot = if bit == 1 { 1.0 } else { -1.0 };
+                }
+            }
+        }
+
+        let center = taps.len() / 2;
+        let mut samples = vec![Complex32::ZERO; self.samples];
+        for (n, &symbol) in impulses.iter().enumerate().filter(|(_, s)| **s != 0.0) {
+            for (k, tap) in taps.iter().enumerate() {
+                if let Some(sample) = (n + k).checked_sub(center).and_then(|i| samples.get_mut(i)) {
+                    sample.re += symbol * tap * LEVEL / peak;
+                }
+            }
+        }
+        Ok(samples)
+    }
+
+    /// Writes `<name>.wav` per entry as 2 channel IQ and `labels.csv` describing them.
+    pub fn write(&self, dir: &Path, entries: &[CorpusEntry]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let mut labels = BufWriter::new(File::create(dir.join("labels.csv"))?);
+        writeln!(labels, "file,kind,sample_rate,samples,snr_db,params")?;
+
+        let mut paths = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let samples = self.synthesize(&entry.signal, entry.snr_db)?;
+            let path = dir.join(format!("{}.wav", entry.name));
+            let mut sink = WavSink::new_file(self.sample_rate, 2, path.clone())?;
+            sink.write(&samples)?;
+
+            let snr = entry.snr_db.map(|snr| snr.to_string()).unwrap_or_default();
+            writeln!(labels, "{}.wav,{},{},{},{},{}", entry.name, entry.signal.kind(), self.sample_rate, samples.len(), snr, entry.signal.params())?;
+            paths.push(path);
+        }
+        labels.flush()?;
+        Ok(paths)
+    }
+}
+
+
+/// Preamble, sync word, length, payload and CRC over length and payload.
+fn psk_frame(payload: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let len: u8 = payload.len().try_into().map_err(|_| "PSK payload longer than 255 bytes")?;
+    let mut frame = Vec::with_capacity(payload.len() + 11);
+    frame.extend_from_slice(&PREAMBLE);
+    frame.extend_from_slice(&SYNC_WORD);
+    frame.push(len);
+    frame.extend_from_slice(payload);
+    let crc = Crc16::CCITT_FALSE.checksum(&frame[PREAMBLE.len() + SYNC_WORD.len()..]);
+    frame.extend_from_slice(&Crc16::CCITT_FALSE.to_bytes(crc));
+    Ok(frame)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::corpus::{default_corpus, CorpusGenerator, TestSignal};
+
+    #[test]
+    fn test_corpus() -> Result<(), Box<dyn Error>> {
+        let generator = CorpusGenerator::new(48000, 0.5);
+
+        let fm = generator.synthesize(&TestSignal::Fm { tone_hz: 1000.0, deviation_hz: 5000.0 }, None)?;
+        let peak_hz = fm.windows(2)
+            .map(|w| (w[1] * w[0].conj()).arg().abs() * 48000.0 / (2.0 * std::f32::consts::PI))
+            .fold(0f32, f32::max);
+        assert!((peak_hz - 5000.0).abs() < 50.0, "{}", peak_hz);
+
+        let am = generator.synthesize(&TestSignal::Am { tone_hz: 400.0, depth: 0.5 }, None)?;
+        let (min, max) = am.iter().fold((f32::MAX, 0f32), |(lo, hi), s| (lo.min(s.norm()), hi.max(s.norm())));
+        assert!(((max - min) / (max + min) - 0.5).abs() < 0.01);
+
+        let noise = generator.synthesize(&TestSignal::Noise { power_db: -20.0 }, None)?;
+        let power = noise.iter().map(|s| s.norm_sqr()).sum::<f32>() / noise.len() as f32;
+        assert!((10.0 * power.log10() + 20.0).abs() < 0.5);
+
+        // hard decisions at the symbol centers recover the framed payload
+        let payload = b"ground truth".to_vec();
+        let psk = generator.synthesize(&TestSignal::Psk { baud: 4800, payloads: vec![payload.clone()] }, None)?;
+        let (bytes, sync) = (0..10).find_map(|offset| {
+            let bits: Vec<u8> = psk[offset..].iter().step_by(10).map(|s| (s.re > 0.0) as u8).collect();
+            // the preamble may not start on a byte boundary
+            (0..8).find_map(|shift| {
+                let bytes: Vec<u8> = bits[shift..].chunks_exact(8).map(|c| c.iter().fold(0, |b, &bit| (b << 1) | bit)).collect();
+                let sync = bytes.windows(4).position(|w| w == [0xd3, 0x91, 0xd3, 0x91])?;
+                Some((bytes, sync))
+            })
+        }).unwrap();
+        assert_eq!(bytes[sync + 4] as usize, payload.len());
+        assert_eq!(&bytes[sync + 5..sync + 5 + payload.len()], payload.as_slice());
+
+        let dir = std::env::temp_dir().join(format!("rust_dsp_corpus_{}", std::process::id()));
+        let entries = default_corpus();
+        let paths = generator.write(&dir, &entries)?;
+        assert_eq!(paths.len(), entries.len());
+        let labels = std::fs::read_to_string(dir.join("labels.csv"))?;
+        assert_eq!(labels.lines().count(), entries.len() + 1);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use num_complex::Complex32;
+use serde::Serialize;
+use crate::fec::decode_terminated;
+use crate::ofdm::{differential, find_symbol, integer_offset, OfdmDemod, OfdmParams};
+use crate::packet::Crc16;
+use crate::rate::{RateAware, SampleRate};
+use crate::traits::Sink;
+
+
+pub const DAB_SAMPLE_RATE: u32 = 2_048_000;
+
+/// Transmission mode I, the only one in use on Band III.
+pub const MODE_I: OfdmParams = OfdmParams { fft_size: 2048, guard: 504 };
+
+/// The rate 1/4 mother code, generators 133, 171, 145 and 133 octal.
+pub const DAB_POLYS: [u8; 4] = [0x6d, 0x4f, 0x53, 0x6d];
+
+/// Samples of the null symbol, a dip shorter than half of it is a fade rather than a frame start.
+const NULL_LEN: usize = 2656;
+const CARRIERS: usize = 1536;
+const FIC_SYMBOLS: usize = 3;
+/// Transmitted bits of one FIC block, which decodes to three FIBs.
+const FIC_BLOCK: usize = 2304;
+const FIB_BITS: usize = 256;
+/// Samples per power measurement while looking for the null symbol.
+const POWER_WINDOW: usize = 256;
+/// How far the FFT window starts inside the guard, so a late timing estimate doesn't reach into
+/// the next symbol.
+const GUARD_BACKOFF: usize = 32;
+/// Whole carrier offsets searched, ±32 kHz.
+const MAX_SHIFT: isize = 32;
+
+
+/// Carrier of each QPSK symbol of an OFDM symbol, the frequency interleaving of mode I.
+pub fn frequency_interleaving() -> Vec<isize> {
+    let mut pi = 0usize;
+    let mut carriers = Vec::with_capacity(CARRIERS);
+    for _ in 1..MODE_I.fft_size {
+        pi = (13 * pi + 511) % MODE_I.fft_size;
+        if (256..=1792).contains(&pi) && pi != 1024 {
+            carriers.push(pi as isize - 1024);
+        }
+    }
+    carriers
+}
+
+
+/// Which of the 3096 mother code bits of a FIC block are sent: 21 blocks of 128 bits punctured
+/// with PI_16, 3 with PI_15 and the 24 tail bits with PI_X.
+pub fn fic_puncturing() -> Vec<bool> {
+    const PI_16: u32 = 0xeeee_eeee;
+    const PI_15: u32 = 0xeeee_eeec;
+    const PI_X: u32 = 0xcc_cccc;
+    let mut keep = Vec::with_capacity(3096);
+    for block in 0..24 {
+        let pi = if block < 21 { PI_16 } else { PI_15 };
+        for _ in 0..4 {
+            keep.extend((0..32).rev().map(|b| (pi >> b) & 1 == 1));
+        }
+    }
+    keep.extend((0..24).rev().map(|b| (PI_X >> b) & 1 == 1));
+    keep
+}
+
+
+/// The x^9 + x^5 + 1 sequence from the all ones state that every FIC block is scrambled with.
+pub fn energy_dispersal(len: usize) -> Vec<u8> {
+    let mut state = 0x1ffu16;
+    (0..len).map(|_| {
+        let bit = ((state >> 8) ^ (state >> 4)) & 1;
+        state = ((state << 1) | bit) & 0x1ff;
+        bit as u8
+    }).collect()
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DabService {
+    pub id: u32,
+    pub label: Option<String>,
+    /// Subchannels of its stream components, the primary one first.
+    pub subchannels: Vec<u8>,
+}
+
+
+/// What the FIC says about the multiplex.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Ensemble {
+    pub id: Option<u16>,
+    pub label: Option<String>,
+    pub services: BTreeMap<u32, DabService>,
+}
+
+
+impl Ensemble {
+    fn service(&mut self, id: u32) -> &mut DabService {
+        self.services.entry(id).or_insert_with(|| DabService { id, ..DabService::default() })
+    }
+}
+
+
+impl fmt::Display for Ensemble {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = self.id.map_or("????".to_string(), |id| format!("{:04X}", id));
+        writeln!(f, "ensemble {} {}", id, self.label.as_deref().unwrap_or("(no label)"))?;
+        for service in self.services.values() {
+            let subchannels: Vec<String> = service.subchannels.iter().map(|s| s.to_string()).collect();
+            writeln!(f, "  {:04X}  {:<16}  subchannel {}", service.id, service.label.as_deref().unwrap_or(""), subchannels.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+
+/// 16 character labels, the EBU character set matches Latin-1 for the usual letters.
+fn label(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect::<String>().trim_end().to_string()
+}
+
+
+/// Decodes FIC blocks into the ensemble, service and label information of FIG types 0 and 1.
+pub struct FicDecoder {
+    puncturing: Vec<bool>,
+    dispersal: Vec<u8>,
+    ensemble: Ensemble,
+    fibs_ok: u64,
+    fibs_bad: u64,
+    depunctured: Vec<i8>,
+}
+
+
+impl Default for FicDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl FicDecoder {
+    pub fn new() -> Self {
+        Self {
+            puncturing: fic_puncturing(),
+            dispersal: energy_dispersal(3 * FIB_BITS),
+            ensemble: Ensemble::default(),
+            fibs_ok: 0,
+            fibs_bad: 0,
+            depunctured: Vec::new(),
+        }
+    }
+
+    pub fn ensemble(&self) -> &Ensemble {
+        &self.ensemble
+    }
+
+    /// FIBs that passed their CRC.
+    pub fn fibs_ok(&self) -> u64 {
+        self.fibs_ok
+    }
+
+    pub fn fibs_bad(&self) -> u64 {
+        self.fibs_bad
+    }
+
+    /// One FIC block of 2304 soft bits, positive for a one.
+    pub fn push_block(&mut self, soft: &[i8]) {
+        let mut sent = soft.iter();
+        self.depunctured.clear();
+        self.depunctured.extend(self.puncturing.iter().map(|&keep| if keep { sent.next().copied().unwrap_or(0) } else { 0 }));
+        let mut bits = decode_terminated(&DAB_POLYS, &self.depunctured);
+        for (bit, p) in bits.iter_mut().zip(&self.dispersal) {
+            *bit ^= p;
+        }
+
+        for fib in bits.chunks_exact(FIB_BITS) {
+            let bytes: Vec<u8> = fib.chunks(8).map(|byte| byte.iter().fold(0, |acc, &b| (acc << 1) | b)).collect();
+            if Crc16::GENIBUS.checksum(&bytes[..30]) == u16::from_be_bytes([bytes[30], bytes[31]]) {
+                self.fibs_ok += 1;
+                self.parse_fib(&bytes[..30]);
+            } else {
+                self.fibs_bad += 1;
+            }
+        }
+    }
+
+    fn parse_fib(&mut self, fib: &[u8]) {
+        let mut i = 0;
+        while i < fib.len() && fib[i] != 0xff {
+            let (kind, len) = (fib[i] >> 5, (fib[i] & 0x1f) as usize);
+            let Some(body) = fib.get(i + 1..i + 1 + len) else { break };
+            match kind {
+                0 => self.fig0(body),
+                1 => self.fig1(body),
+                _ => {},
+            }
+            i += 1 + len;
+        }
+    }
+
+    /// FIG 0/0 ensemble id and 0/2 services, about this ensemble only.
+    fn fig0(&mut self, body: &[u8]) {
+        let Some((&header, data)) = body.split_first() else { return };
+        let (other_ensemble, long_ids, extension) = (header & 0x40 != 0, header & 0x20 != 0, header & 0x1f);
+        if other_ensemble {
+            return;
+        }
+        match extension {
+            0 if data.len() >= 2 => self.ensemble.id = Some(u16::from_be_bytes([data[0], data[1]])),
+            2 => {
+                let id_len = if long_ids { 4 } else { 2 };
+                let mut data = data;
+                while data.len() > id_len {
+                    let id = data[..id_len].iter().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+                    let components = (data[id_len] & 0x0f) as usize;
+                    let Some(list) = data.get(id_len + 1..id_len + 1 + 2 * components) else { return };
+                    let mut subchannels = Vec::new();
+                    for component in list.chunks_exact(2) {
+                        // packet mode components are addressed by service component id instead
+                        if component[0] >> 6 == 3 {
+                            continue;
+                        }
+                        let subchannel = component[1] >> 2;
+                        if component[1] & 0x02 != 0 {
+                            subchannels.insert(0, subchannel);
+                        } else {
+                            subchannels.push(subchannel);
+                        }
+                    }
+                    self.ensemble.service(id).subchannels = subchannels;
+                    data = &data[id_len + 1 + 2 * components..];
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// FIG 1/0 ensemble label, 1/1 programme and 1/5 data service labels.
+    fn fig1(&mut self, body: &[u8]) {
+        let Some((&header, data)) = body.split_first() else { return };
+        let (other_ensemble, extension) = (header & 0x08 != 0, header & 0x07);
+        if other_ensemble {
+            return;
+        }
+        let id_len = match extension {
+            0 | 1 => 2,
+            5 => 4,
+            _ => return,
+        };
+        let Some(text) = data.get(id_len..id_len + 16) else { return };
+        let id = data[..id_len].iter().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+        if extension == 0 {
+            self.ensemble.id.get_or_insert(id as u16);
+            self.ensemble.label = Some(label(text));
+        } else {
+            self.ensemble.service(id).label = Some(label(text));
+        }
+    }
+}
+
+
+/// First stages of a DAB+ receiver for surveying: finds the null symbol of every transmission frame,
+/// synchronizes on the phase reference symbol, demodulates the DQPSK of the FIC and keeps the
+/// ensemble and service listing up to date. Expects mode I at 2.048 MHz, the MSC is skipped.
+pub struct DabReceiver {
+    demod: OfdmDemod,
+    fic: FicDecoder,
+    carriers: Vec<isize>,
+    interleaving: Vec<isize>,
+    buffer: Vec<Complex32>,
+    position: usize,
+    level: f32,
+    /// Samples spent below the level so far while inside a dip.
+    null_len: Option<usize>,
+    frame_start: Option<usize>,
+    frames: u64,
+    previous: Vec<Complex32>,
+    current: Vec<Complex32>,
+    differential: Vec<Complex32>,
+    soft: Vec<i8>,
+}
+
+
+impl Default for DabReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl DabReceiver {
+    pub fn new() -> Self {
+        let half = CARRIERS as isize / 2;
+        Self {
+            demod: OfdmDemod::new(MODE_I),
+            fic: FicDecoder::new(),
+            carriers: (-half..=half).filter(|&k| k != 0).collect(),
+            interleaving: frequency_interleaving(),
+            buffer: Vec::new(),
+            position: 0,
+            level: 0.0,
+            null_len: None,
+            frame_start: None,
+            frames: 0,
+            previous: Vec::new(),
+            current: Vec::new(),
+            differential: Vec::new(),
+            soft: Vec::new(),
+        }
+    }
+
+    pub fn ensemble(&self) -> &Ensemble {
+        self.fic.ensemble()
+    }
+
+    pub fn fic(&self) -> &FicDecoder {
+        &self.fic
+    }
+
+    /// Transmission frames whose FIC was demodulated.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// `start` is about where the null ended, the phase reference symbol follows it.
+    fn decode_frame(&mut self, start: usize) {
+        let starts = start.saturating_sub(POWER_WINDOW)..start + POWER_WINDOW;
+        let Some(timing) = find_symbol(MODE_I, &self.buffer, starts) else { return };
+        if timing.quality < 0.5 {
+            return;
+        }
+        let first = timing.offset + MODE_I.guard - GUARD_BACKOFF;
+        self.demod.demodulate(&self.buffer[first..], 0, timing.cfo as f64, &mut self.previous);
+        let cfo = integer_offset(MODE_I, &self.previous, &self.carriers, MAX_SHIFT) as f64 + timing.cfo as f64;
+        self.demod.demodulate(&self.buffer[first..], 0, cfo, &mut self.previous);
+
+        self.soft.clear();
+        for l in 1..=FIC_SYMBOLS {
+            let position = l * MODE_I.symbol_len();
+            self.demod.demodulate(&self.buffer[first + position..], position as i64, cfo, &mut self.current);
+            differential(&self.previous, &self.current, &mut self.differential);
+            let symbols: Vec<Complex32> = self.interleaving.iter().map(|&k| self.differential[MODE_I.bin(k)]).collect();
+            let mean = symbols.iter().map(|q| q.re.abs() + q.im.abs()).sum::<f32>() / (2 * CARRIERS) as f32;
+            let scale = 64.0 / mean.max(f32::MIN_POSITIVE);
+            // a zero bit is sent as +1
+            let soft = |x: f32| (-x * scale).clamp(-127.0, 127.0) as i8;
+            self.soft.extend(symbols.iter().map(|q| soft(q.re)));
+            self.soft.extend(symbols.iter().map(|q| soft(q.im)));
+            std::mem::swap(&mut self.previous, &mut self.current);
+        }
+        for block in self.soft.chunks_exact(FIC_BLOCK) {
+            self.fic.push_block(block);
+        }
+        self.frames += 1;
+    }
+}
+
+
+impl Sink<Complex32> for DabReceiver {
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        self.buffer.extend_from_slice(src);
+        loop {
+            if let Some(start) = self.frame_start {
+                if self.buffer.len() < start + POWER_WINDOW + (1 + FIC_SYMBOLS) * MODE_I.symbol_len() {
+                    break;
+                }
+                self.decode_frame(start);
+                self.frame_start = None;
+                self.position = start + (1 + FIC_SYMBOLS) * MODE_I.symbol_len();
+                continue;
+            }
+            if self.position + POWER_WINDOW > self.buffer.len() {
+                break;
+            }
+
+            let power = self.buffer[self.position..self.position + POWER_WINDOW].iter().map(|x| x.norm_sqr()).sum::<f32>() / POWER_WINDOW as f32;
+            if let Some(null_len) = self.null_len {
+                if power > 0.5 * self.level {
+                    self.null_len = None;
+                    if null_len >= NULL_LEN / 2 {
+                        self.frame_start = Some(self.position);
+                    }
+                } else {
+                    self.null_len = Some(null_len + POWER_WINDOW);
+                }
+            } else if power < 0.2 * self.level {
+                self.null_len = Some(POWER_WINDOW);
+            } else {
+                self.level = if self.level == 0.0 { power } else { 0.9 * self.level + 0.1 * power };
+            }
+            self.position += POWER_WINDOW;
+        }
+
+        // keep what the timing search may look back on
+        let keep = self.frame_start.unwrap_or(self.position).min(self.position).saturating_sub(POWER_WINDOW);
+        self.buffer.drain(..keep);
+        self.position -= keep;
+        if let Some(start) = self.frame_start.as_mut() {
+            *start -= keep;
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for DabReceiver {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(DAB_SAMPLE_RATE))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use num_complex::Complex32;
+    use crate::dab::{energy_dispersal, fic_puncturing, frequency_interleaving, DabReceiver, DAB_POLYS, MODE_I, NULL_LEN};
+    use crate::fec::encode_terminated;
+    use crate::fft::FFT;
+    use crate::packet::Crc16;
+    use crate::seed::Rng;
+    use crate::traits::Sink;
+
+    fn fib(figs: &[&[u8]]) -> Vec<u8> {
+        let mut fib = figs.concat();
+        if fib.len() < 30 {
+            fib.push(0xff);
+        }
+        fib.resize(30, 0);
+        let crc = Crc16::GENIBUS.checksum(&fib);
+        fib.extend_from_slice(&crc.to_be_bytes());
+        fib
+    }
+
+    fn label_fig(extension: u8, id: u16, text: &str) -> Vec<u8> {
+        let mut fig = vec![(1 << 5) | 21, extension];
+        fig.extend_from_slice(&id.to_be_bytes());
+        fig.extend_from_slice(format!("{:<16}", text).as_bytes());
+        fig.extend_from_slice(&[0xff, 0x00]);
+        fig
+    }
+
+    #[test]
+    fn test_dab_receiver() -> Result<(), Box<dyn std::error::Error>> {
+        // ensemble E123 with two audio services on subchannels 3 and 5
+        let fig0_0 = [0x05, 0x00, 0xe1, 0x23, 0x00, 0x00];
+        let fig0_2 = [11, 0x02, 0xe1, 0xc1, 0x01, 0x00, (3 << 2) | 0x02, 0xe1, 0xc2, 0x01, 0x00, (5 << 2) | 0x02];
+        let fibs = [
+            fib(&[&fig0_0, &label_fig(0, 0xe123, "Test Ensemble")]),
+            fib(&[&fig0_2]),
+            fib(&[&label_fig(1, 0xe1c1, "Radio One")]),
+            fib(&[&label_fig(1, 0xe1c2, "Jazz")]),
+        ];
+        let empty = fib(&[]);
+
+        // FIBs to bits, scrambled, encoded and punctured per FIC block
+        let puncturing = fic_puncturing();
+        let dispersal = energy_dispersal(768);
+        let mut fic_bits = Vec::new();
+        for block in 0..4 {
+            let bits: Vec<u8> = (0..3)
+                .flat_map(|f| fibs.get(3 * block + f).unwrap_or(&empty).clone())
+                .flat_map(|byte| (0..8).rev().map(move |b| (byte >> b) & 1))
+                .zip(&dispersal)
+                .map(|(bit, p)| bit ^ p)
+                .collect();
+            let encoded = encode_terminated(&DAB_POLYS, &bits);
+            fic_bits.extend(encoded.iter().zip(&puncturing).filter(|(_, keep)| **keep).map(|(&bit, _)| bit));
+        }
+        assert_eq!(fic_bits.len(), 9216);
+
+        let mut rng = Rng::new(0x2545f491);
+        let interleaving = frequency_interleaving();
+        let fft = FFT::new(MODE_I.fft_size);
+        let qpsk = |a: u8, b: u8| Complex32::new(1.0 - 2.0 * a as f32, 1.0 - 2.0 * b as f32) / 2f32.sqrt();
+        let mut frame = || {
+            let mut samples = vec![Complex32::new(0.0, 0.0); NULL_LEN];
+            let mut carriers = vec![Complex32::new(0.0, 0.0); MODE_I.fft_size];
+            for l in 0..76 {
+                for (n, &k) in interleaving.iter().enumerate() {
+                    let (a, b) = match l {
+                        1..=3 => (fic_bits[(l - 1) * 3072 + n], fic_bits[(l - 1) * 3072 + 1536 + n]),
+                        _ => ((rng.next_u32() & 1) as u8, (rng.next_u32() & 1) as u8),
+                    };
+                    let bin = MODE_I.bin(k);
+                    carriers[bin] = if l == 0 { qpsk(a, b) } else { carriers[bin] * qpsk(a, b) };
+                }
+                let mut symbol = carriers.clone();
+                fft.inverse(&mut symbol);
+                samples.extend_from_slice(&symbol[MODE_I.fft_size - MODE_I.guard..]);
+                samples.extend_from_slice(&symbol);
+            }
+            samples
+        };
+
+        // the end of one frame and two whole ones, 2.3 kHz off and with some noise
+        let mut signal: Vec<Complex32> = frame()[190_000..].to_vec();
+        signal.extend(frame());
+        signal.extend(frame());
+        let mut noise = move || (rng.uniform() - 0.5) * 0.005;
+        let signal: Vec<Complex32> = signal.iter().enumerate()
+            .map(|(n, &x)| x * Complex32::from_polar(1.0, (2.0 * PI * 2.3 * n as f64 / 2048.0 % (2.0 * PI)) as f32) + Complex32::new(noise(), noise()))
+            .collect();
+
+        let mut receiver = DabReceiver::new();
+        for block in signal.chunks(10_000) {
+            receiver.write(block)?;
+        }
+        assert_eq!(receiver.frames(), 2);
+        assert_eq!(receiver.fic().fibs_bad(), 0);
+        assert_eq!(receiver.fic().fibs_ok(), 24);
+
+        let ensemble = receiver.ensemble();
+        assert_eq!(ensemble.id, Some(0xe123));
+        assert_eq!(ensemble.label.as_deref(), Some("Test Ensemble"));
+        assert_eq!(ensemble.services.len(), 2);
+        assert_eq!(ensemble.services[&0xe1c1].label.as_deref(), Some("Radio One"));
+        assert_eq!(ensemble.services[&0xe1c2].subchannels, [5]);
+        assert!(ensemble.to_string().contains("E1C2  Jazz"), "{}", ensemble);
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+use std::error::Error;
+use std::f32::consts::PI;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use num_complex::Complex32;
+use serde::{Deserialize, Serialize};
+use crate::block::{FIRFilter, FMDemod};
+use crate::rate::{RateAware, SampleRate};
+use crate::traits::*;
+use crate::error::ConfigError;
+use crate::util::{bandpass_complex_taps, whistle_notch, AmRegion};
+
+
+/// Envelope detector with the carrier's DC level removed.
+pub struct AMDemod {
+    sample_rate: u32,
+    alpha: f32,
+    dc: Option<f32>,
+    notch: Option<FIRFilter<f32>>,
+    buffer: Vec<f32>,
+}
+
+
+impl AMDemod {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            alpha: 1.0 - (-2.0 * PI * 20.0 / sample_rate as f32).exp(),
+            dc: None,
+            notch: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Remove the whistle of adjacent channel carriers on the `region` raster, `None` turns it off.
+    pub fn set_whistle_notch(&mut self, region: Option<AmRegion>) -> Result<(), ConfigError> {
+        self.notch = region.map(|region| whistle_notch(self.sample_rate, region)).transpose()?;
+        Ok(())
+    }
+}
+
+
+impl Filter<Complex32, f32> for AMDemod {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        let audio = if self.notch.is_some() { &mut self.buffer } else { &mut *output };
+        audio.clear();
+        for sample in input {
+            let envelope = sample.norm();
+            let dc = self.dc.get_or_insert(envelope);
+            *dc += self.alpha * (envelope - *dc);
+            audio.push(envelope - *dc);
+        }
+        if let Some(notch) = self.notch.as_mut() {
+            notch.filter(&self.buffer, output)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for AMDemod {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sideband {
+    Upper,
+    Lower,
+}
+
+
+/// Keeps one sideband of a baseband signal centered on the suppressed carrier and takes the real part.
+pub struct SSBDemod {
+    sample_rate: u32,
+    filter: FIRFilter<Complex32>,
+    buffer: Vec<Complex32>,
+}
+
+
+impl SSBDemod {
+    /// Passes 300 Hz up to `bandwidth` Hz away from the carrier.
+    pub fn new(sample_rate: u32, sideband: Sideband, bandwidth: f32) -> Self {
+        let (low, high) = match sideband {
+            Sideband::Upper => (300.0, bandwidth),
+            Sideband::Lower => (-bandwidth, -300.0),
+        };
+        Self {
+            sample_rate,
+            filter: FIRFilter::new(bandpass_complex_taps(sample_rate, low, high, 255)),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+
+impl Filter<Complex32, f32> for SSBDemod {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.filter.filter(input, &mut self.buffer)?;
+        output.extend(self.buffer.iter().map(|s| s.re));
+        Ok(())
+    }
+}
+
+
+impl RateAware for SSBDemod {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+/// Narrow filter around the carrier followed by a beat oscillator, a carrier at 0 Hz is heard at `pitch`.
+pub struct CWDemod {
+    sample_rate: u32,
+    filter: FIRFilter<Complex32>,
+    pitch: f32,
+    phase: f32,
+    buffer: Vec<Complex32>,
+}
+
+
+impl CWDemod {
+    pub fn new(sample_rate: u32, pitch: f32, bandwidth: f32) -> Self {
+        Self {
+            sample_rate,
+            filter: FIRFilter::new(bandpass_complex_taps(sample_rate, -0.5 * bandwidth, 0.5 * bandwidth, 255)),
+            pitch,
+            phase: 0.0,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+
+impl Filter<Complex32, f32> for CWDemod {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.filter.filter(input, &mut self.buffer)?;
+        let step = 2.0 * PI * self.pitch / self.sample_rate as f32;
+        for sample in &self.buffer {
+            output.push((sample * Complex32::from_polar(1.0, self.phase)).re);
+            self.phase = (self.phase + step) % (2.0 * PI);
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for CWDemod {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DemodMode {
+    FM,
+    AM,
+    USB,
+    LSB,
+    CW,
+}
+
+
+enum ActiveDemod {
+    Fm(FMDemod),
+    Am(AMDemod),
+    Ssb(SSBDemod),
+    Cw(CWDemod),
+}
+
+
+/// Switches between FM/AM/SSB/CW demodulation at runtime. Every mode takes complex baseband at the same
+/// rate and produces audio at that rate, a switch starts the new demodulator from a clean state.
+pub struct DemodSelector {
+    sample_rate: u32,
+    mode: DemodMode,
+    active: ActiveDemod,
+    fm_deviation: f32,
+    ssb_bandwidth: f32,
+    cw_pitch: f32,
+    cw_bandwidth: f32,
+    am_notch: Option<AmRegion>,
+    sender: Sender<DemodMode>,
+    receiver: Receiver<DemodMode>,
+}
+
+
+impl DemodSelector {
+    pub fn new(sample_rate: u32, mode: DemodMode) -> Self {
+        let (sender, receiver) = channel();
+        let mut selector = Self {
+            sample_rate,
+            mode,
+            active: ActiveDemod::Am(AMDemod::new(sample_rate)),
+            fm_deviation: 5e3,
+            ssb_bandwidth: 2.7e3,
+            cw_pitch: 700.0,
+            cw_bandwidth: 500.0,
+            am_notch: None,
+            sender,
+            receiver,
+        };
+        selector.set_mode(mode);
+        selector
+    }
+
+    /// Send mode changes from another thread, they apply at the start of the next block.
+    pub fn control(&self) -> Sender<DemodMode> {
+        self.sender.clone()
+    }
+
+    pub fn mode(&self) -> DemodMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: DemodMode) {
+        let rate = self.sample_rate;
+        self.mode = mode;
+        self.active = match mode {
+            DemodMode::FM => ActiveDemod::Fm(FMDemod::new(rate, self.fm_deviation)),
+            DemodMode::AM => {
+                let mut demod = AMDemod::new(rate);
+                // checked in set_am_whistle_notch
+                let _ = demod.set_whistle_notch(self.am_notch);
+                ActiveDemod::Am(demod)
+            },
+            DemodMode::USB => ActiveDemod::Ssb(SSBDemod::new(rate, Sideband::Upper, self.ssb_bandwidth)),
+            DemodMode::LSB => ActiveDemod::Ssb(SSBDemod::new(rate, Sideband::Lower, self.ssb_bandwidth)),
+            DemodMode::CW => ActiveDemod::Cw(CWDemod::new(rate, self.cw_pitch, self.cw_bandwidth)),
+        };
+    }
+
+    /// Takes effect on the next switch to FM.
+    pub fn set_fm_deviation(&mut self, deviation: f32) {
+        self.fm_deviation = deviation;
+    }
+
+    /// Takes effect on the next switch to USB or LSB.
+    pub fn set_ssb_bandwidth(&mut self, bandwidth: f32) {
+        self.ssb_bandwidth = bandwidth;
+    }
+
+    /// Whistle notch for AM, applied right away when AM is active.
+    pub fn set_am_whistle_notch(&mut self, region: Option<AmRegion>) -> Result<(), ConfigError> {
+        AMDemod::new(self.sample_rate).set_whistle_notch(region)?;
+        self.am_notch = region;
+        if let ActiveDemod::Am(demod) = &mut self.active {
+            demod.set_whistle_notch(region)?;
+        }
+        Ok(())
+    }
+
+    /// Takes effect on the next switch to CW.
+    pub fn set_cw(&mut self, pitch: f32, bandwidth: f32) {
+        self.cw_pitch = pitch;
+        self.cw_bandwidth = bandwidth;
+    }
+}
+
+
+impl Filter<Complex32, f32> for DemodSelector {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        if let Some(mode) = self.receiver.try_iter().last() && mode != self.mode {
+            self.set_mode(mode);
+        }
+        match &mut self.active {
+            ActiveDemod::Fm(demod) => demod.filter(input, output),
+            ActiveDemod::Am(demod) => demod.filter(input, output),
+            ActiveDemod::Ssb(demod) => demod.filter(input, output),
+            ActiveDemod::Cw(demod) => demod.filter(input, output),
+        }
+    }
+}
+
+
+impl RateAware for DemodSelector {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::demod::{DemodMode, DemodSelector};
+    use crate::traits::Filter;
+    use crate::util::AmRegion;
+
+    fn rms(x: &[f32]) -> f32 {
+        (x.iter().map(|v| v * v).sum::<f32>() / x.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_demod_selector() -> Result<(), Box<dyn std::error::Error>> {
+        let rate = 48000;
+        let tone: Vec<Complex32> = (0..4800).map(|n| Complex32::from_polar(0.5, 2.0 * PI * 1000.0 * n as f32 / rate as f32)).collect();
+        let mut selector = DemodSelector::new(rate, DemodMode::USB);
+        let control = selector.control();
+        let mut output = Vec::new();
+
+        // a carrier 1 kHz above the dial is heard in USB and rejected in LSB
+        selector.filter(&tone, &mut output)?;
+        assert_eq!(output.len(), tone.len());
+        assert!((rms(&output[1000..]) - 0.5 / 2f32.sqrt()).abs() < 0.02);
+
+        control.send(DemodMode::LSB)?;
+        selector.filter(&tone, &mut output)?;
+        assert_eq!(selector.mode(), DemodMode::LSB);
+        assert!(rms(&output[1000..]) < 0.01);
+
+        // 50% AM of a 500 Hz tone
+        let am: Vec<Complex32> = (0..9600).map(|n| Complex32::new(0.5 * (1.0 + 0.5 * (2.0 * PI * 500.0 * n as f32 / rate as f32).sin()), 0.0)).collect();
+        control.send(DemodMode::AM)?;
+        selector.filter(&am, &mut output)?;
+        assert_eq!(output.len(), am.len());
+        assert!((rms(&output[4800..]) - 0.25 / 2f32.sqrt()).abs() < 0.01);
+        Ok(())
+    }
+
+    #[test]
+    fn test_am_whistle_notch() -> Result<(), Box<dyn std::error::Error>> {
+        let rate = 48000;
+        // 1 kHz audio plus the 9 kHz beat of an adjacent carrier
+        let input: Vec<Complex32> = (0..9600).map(|n| {
+            let t = n as f32 / rate as f32;
+            Complex32::new(0.5 * (1.0 + 0.3 * (2.0 * PI * 1000.0 * t).sin()), 0.0) + Complex32::from_polar(0.1, 2.0 * PI * 9000.0 * t)
+        }).collect();
+        let mut selector = DemodSelector::new(rate, DemodMode::AM);
+        let mut plain = Vec::new();
+        selector.filter(&input, &mut plain)?;
+        selector.set_am_whistle_notch(Some(AmRegion::Itu1And3))?;
+        let mut notched = Vec::new();
+        selector.filter(&input, &mut notched)?;
+
+        let tone = |x: &[f32], f: f32| {
+            let mut acc = Complex32::default();
+            for (n, &v) in x.iter().enumerate() {
+                acc += Complex32::from_polar(v, -2.0 * PI * f * n as f32 / rate as f32);
+            }
+            2.0 * acc.norm() / x.len() as f32
+        };
+        assert!(tone(&plain[4800..], 9000.0) > 0.05);
+        assert!(tone(&notched[4800..], 9000.0) < 0.002);
+        assert!((tone(&notched[4800..], 1000.0) - tone(&plain[4800..], 1000.0)).abs() < 0.005);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use num_complex::Complex32;
+
+
+/// ATSC puts its pilot this far above the lower edge of the 6 MHz channel.
+pub const ATSC_PILOT_OFFSET_HZ: f64 = 309_441.0;
+/// Width of the flat part of the 8VSB spectrum, starting at the pilot.
+const ATSC_FLAT_HZ: f64 = 5.38e6;
+/// DVB-T elementary period for 8 MHz channels, 7 and 6 MHz channels stretch it by 8/7 and 8/6.
+const DVBT_PERIOD_8MHZ: f64 = 7.0 / 64e6;
+
+
+/// Guard interval as a fraction of the useful symbol time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuardInterval {
+    Quarter,
+    Eighth,
+    Sixteenth,
+    ThirtySecond,
+}
+
+
+impl GuardInterval {
+    pub const ALL: [GuardInterval; 4] = [Self::Quarter, Self::Eighth, Self::Sixteenth, Self::ThirtySecond];
+
+    pub fn fraction(&self) -> f64 {
+        match self {
+            Self::Quarter => 1.0 / 4.0,
+            Self::Eighth => 1.0 / 8.0,
+            Self::Sixteenth => 1.0 / 16.0,
+            Self::ThirtySecond => 1.0 / 32.0,
+        }
+    }
+}
+
+
+impl fmt::Display for GuardInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "1/{}", (1.0 / self.fraction()).round())
+    }
+}
+
+
+/// A digital TV transmission recognized by its signature.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DtvSignal {
+    Atsc {
+        center_hz: f64,
+        pilot_hz: f64,
+        /// Pilot line above the data plateau.
+        pilot_db: f32,
+    },
+    DvbT {
+        /// 2048 or 8192 carriers, 2k or 8k mode.
+        fft_size: usize,
+        bandwidth_hz: f64,
+        guard: GuardInterval,
+        /// Guard interval correlation, about g / (1 + g) for a clean signal.
+        correlation: f32,
+    },
+}
+
+
+impl fmt::Display for DtvSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DtvSignal::Atsc { center_hz, .. } => write!(f, "ATSC at {:.3} MHz", center_hz / 1e6),
+            DtvSignal::DvbT { fft_size, bandwidth_hz, guard, .. } => {
+                write!(f, "DVB-T {}k {} MHz guard {}", fft_size / 1024, bandwidth_hz / 1e6, guard)
+            },
+        }
+    }
+}
+
+
+fn median(values: &[f32]) -> f32 {
+    if values.is_empty() {
+        return f32::NAN;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    sorted[sorted.len() / 2]
+}
+
+
+/// Looks for ATSC pilots in a swept power spectrum, `power_db[i]` at `start_hz + i * bin_hz`:
+/// a narrow line standing out of the lower edge of a flat 5.38 MHz plateau above the noise floor.
+/// Bins should be narrower than about 50 kHz so the pilot is resolved.
+pub fn find_atsc(power_db: &[f32], start_hz: f64, bin_hz: f64) -> Vec<DtvSignal> {
+    let bins = |hz: f64| (hz / bin_hz).round() as isize;
+    let range = |from: isize, to: isize| {
+        let clamp = |i: isize| i.clamp(0, power_db.len() as isize) as usize;
+        &power_db[clamp(from)..clamp(to)]
+    };
+    let mut sorted = power_db.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let Some(&floor) = sorted.get(sorted.len() / 10) else { return Vec::new() };
+
+    let (flat, edge, line) = (bins(ATSC_FLAT_HZ), bins(300e3), bins(100e3).max(2));
+    let mut found: Vec<DtvSignal> = Vec::new();
+    for pilot in 0..power_db.len() as isize {
+        let plateau_range = range(pilot + flat / 10, pilot + flat * 9 / 10);
+        if (plateau_range.len() as isize) < flat * 4 / 5 {
+            break;
+        }
+        let plateau = median(plateau_range);
+        // the band edge just below the pilot, unknown at the start of the sweep
+        let below = median(range(pilot - edge, pilot - line));
+        let neighbours = range(pilot - line, pilot + line + 1);
+        let pilot_db = power_db[pilot as usize] - plateau;
+        let is_peak = neighbours.iter().all(|&p| p <= power_db[pilot as usize]);
+        if is_peak && pilot_db > 6.0 && plateau > floor + 6.0 && (below.is_nan() || below < plateau - 3.0) {
+            let pilot_hz = start_hz + pilot as f64 * bin_hz;
+            found.push(DtvSignal::Atsc {
+                center_hz: pilot_hz - ATSC_PILOT_OFFSET_HZ + 3e6,
+                pilot_hz,
+                pilot_db,
+            });
+        }
+    }
+    found
+}
+
+
+/// Fraction of the power that repeats `lag` samples later.
+fn lag_correlation(samples: &[Complex32], lag: usize) -> f32 {
+    if samples.len() <= lag {
+        return 0.0;
+    }
+    let n = samples.len() - lag;
+    let correlation: Complex32 = (0..n).map(|i| samples[i] * samples[i + lag].conj()).sum();
+    let power: f32 = samples.iter().map(|x| x.norm_sqr()).sum::<f32>() * n as f32 / samples.len() as f32;
+    correlation.norm() / power.max(f32::MIN_POSITIVE)
+}
+
+
+/// Folds the lag products onto one symbol period and measures how well one guard length of them
+/// lines up, near 1 when the symbol period is right.
+fn guard_alignment(samples: &[Complex32], lag: usize, guard: usize) -> f32 {
+    let period = lag + guard;
+    if guard == 0 || samples.len() < lag + 2 * period {
+        return 0.0;
+    }
+    let n = samples.len() - lag;
+    let mut folded = vec![Complex32::new(0.0, 0.0); period];
+    for i in 0..n {
+        folded[i % period] += samples[i] * samples[i + lag].conj();
+    }
+    let mut sum: Complex32 = folded[..guard].iter().sum();
+    let mut best = sum.norm();
+    for k in 1..period {
+        sum += folded[(k + guard - 1) % period] - folded[k - 1];
+        best = best.max(sum.norm());
+    }
+    let power = samples.iter().map(|x| x.norm_sqr()).sum::<f32>() / samples.len() as f32;
+    best / ((n / period) as f32 * guard as f32 * power).max(f32::MIN_POSITIVE)
+}
+
+
+/// Recognizes DVB-T in I/Q centered on a channel by the correlation of its guard intervals with the
+/// end of each symbol, trying 2k and 8k modes in 8, 7 and 6 MHz channels. Needs a few dozen
+/// symbols, e.g. 20 ms of samples.
+pub fn detect_dvbt(samples: &[Complex32], sample_rate: u32) -> Option<DtvSignal> {
+    let threshold = (5.0 / (samples.len() as f32).sqrt()).max(0.02);
+    let mut best: Option<(f32, usize, f64, usize)> = None;
+    for fft_size in [2048, 8192] {
+        for bandwidth_hz in [8e6, 7e6, 6e6] {
+            let useful = fft_size as f64 * DVBT_PERIOD_8MHZ * 8e6 / bandwidth_hz;
+            let lag = (useful * sample_rate as f64).round() as usize;
+            let correlation = lag_correlation(samples, lag);
+            if correlation > threshold && best.is_none_or(|(c, ..)| correlation > c) {
+                best = Some((correlation, fft_size, bandwidth_hz, lag));
+            }
+        }
+    }
+
+    let (correlation, fft_size, bandwidth_hz, lag) = best?;
+    let guard = GuardInterval::ALL.into_iter().max_by(|a, b| {
+        let alignment = |g: &GuardInterval| guard_alignment(samples, lag, (lag as f64 * g.fraction()).round() as usize);
+        alignment(a).total_cmp(&alignment(b))
+    })?;
+    Some(DtvSignal::DvbT { fft_size, bandwidth_hz, guard, correlation })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::dtv::{detect_dvbt, find_atsc, DtvSignal, GuardInterval};
+    use crate::fft::FFT;
+    use crate::seed::Rng;
+
+    #[test]
+    fn test_dtv_detection() {
+        let mut rng = Rng::new(0x2545f491);
+        let mut random = move || rng.uniform();
+
+        // 470 to 500 MHz in 10 kHz bins: an ATSC channel at 473 MHz, a CW carrier and a flat
+        // signal without a pilot
+        let (start, bin) = (470e6f64, 10e3f64);
+        let mut psd: Vec<f32> = (0..3000).map(|_| -100.0 + 2.0 * random()).collect();
+        let pilot = ((473e6 - 3e6 + 309_441.0 - start) / bin).round() as usize;
+        for p in &mut psd[pilot..pilot + 538] {
+            *p = -80.0 + random();
+        }
+        psd[pilot] = -62.0;
+        psd[1500] = -50.0;
+        for p in &mut psd[2000..2538] {
+            *p = -80.0 + random();
+        }
+        let found = find_atsc(&psd, start, bin);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        let DtvSignal::Atsc { center_hz, .. } = found[0] else { panic!("{:?}", found[0]) };
+        assert!((center_hz - 473e6).abs() < bin, "{}", center_hz);
+
+        // 40 symbols of 2k mode with a 1/8 guard at the native 64/7 MHz rate, with noise
+        let (fft_size, guard) = (2048, 256);
+        let fft = FFT::new(fft_size);
+        let mut samples = Vec::new();
+        for _ in 0..40 {
+            let mut symbol = vec![Complex32::new(0.0, 0.0); fft_size];
+            for k in -852isize..=852 {
+                symbol[k.rem_euclid(fft_size as isize) as usize] = Complex32::from_polar(1.0, 2.0 * PI * random());
+            }
+            fft.inverse(&mut symbol);
+            samples.extend_from_slice(&symbol[fft_size - guard..]);
+            samples.extend_from_slice(&symbol);
+        }
+        let rms = (samples.iter().map(|x| x.norm_sqr()).sum::<f32>() / samples.len() as f32).sqrt();
+        let samples: Vec<Complex32> = samples.iter().map(|&x| x + rms * 0.5 * Complex32::new(random() - 0.5, random() - 0.5)).collect();
+
+        let signal = detect_dvbt(&samples, 64_000_000 / 7).unwrap();
+        let DtvSignal::DvbT { fft_size, bandwidth_hz, guard, correlation } = signal else { panic!("{:?}", signal) };
+        assert_eq!((fft_size, bandwidth_hz, guard), (2048, 8e6, GuardInterval::Eighth));
+        assert!(correlation > 0.08, "{}", correlation);
+        assert_eq!(signal.to_string(), "DVB-T 2k 8 MHz guard 1/8");
+
+        let noise: Vec<Complex32> = (0..samples.len()).map(|_| Complex32::new(random() - 0.5, random() - 0.5)).collect();
+        assert_eq!(detect_dvbt(&noise, 64_000_000 / 7), None);
+    }
+}
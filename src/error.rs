@@ -0,0 +1,111 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+
+/// Rejected block parameters, returned by the builders before anything is constructed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    /// A frequency at or above half the sample rate it is used at.
+    AboveNyquist { name: &'static str, value: f64, sample_rate: u32 },
+    /// Linear phase filters need an odd number of taps to have a whole sample of delay.
+    EvenTaps(usize),
+    /// A decimation that doesn't divide the input rate.
+    NonIntegerDecimation { input: u32, output: u32 },
+    OutOfRange { name: &'static str, value: f64, min: f64, max: f64 },
+    Invalid(String),
+}
+
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::AboveNyquist { name, value, sample_rate } => {
+                write!(f, "{} of {} Hz is not below the nyquist frequency of {} Hz at {} Hz", name, value, *sample_rate as f64 / 2.0, sample_rate)
+            },
+            ConfigError::EvenTaps(taps) => write!(f, "filter needs an odd number of taps, got {}", taps),
+            ConfigError::NonIntegerDecimation { input, output } => {
+                write!(f, "{} Hz is not an integer decimation of {} Hz", output, input)
+            },
+            ConfigError::OutOfRange { name, value, min, max } => {
+                write!(f, "{} of {} is outside {}..={}", name, value, min, max)
+            },
+            ConfigError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+
+impl Error for ConfigError {}
+
+
+pub fn check_range(name: &'static str, value: f64, min: f64, max: f64) -> Result<(), ConfigError> {
+    if value < min || value > max || value.is_nan() {
+        return Err(ConfigError::OutOfRange { name, value, min, max });
+    }
+    Ok(())
+}
+
+
+pub fn check_nyquist(name: &'static str, value: f64, sample_rate: u32) -> Result<(), ConfigError> {
+    if value.abs() >= sample_rate as f64 / 2.0 {
+        return Err(ConfigError::AboveNyquist { name, value, sample_rate });
+    }
+    Ok(())
+}
+
+
+/// FM deviation at a complex sample rate. The carrier can swing all the way to ±Nyquist, so
+/// broadcast FM's 75 kHz fits 150 kS/s.
+pub fn check_deviation(deviation: f32, sample_rate: u32) -> Result<(), ConfigError> {
+    if deviation <= 0.0 {
+        return Err(ConfigError::Invalid(format!("deviation must be positive, got {}", deviation)));
+    }
+    if deviation as f64 > sample_rate as f64 / 2.0 {
+        return Err(ConfigError::AboveNyquist { name: "deviation", value: deviation as f64, sample_rate });
+    }
+    Ok(())
+}
+
+
+pub fn check_taps(num_taps: usize) -> Result<(), ConfigError> {
+    if num_taps == 0 || num_taps.is_multiple_of(2) {
+        return Err(ConfigError::EvenTaps(num_taps));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{FMDemodBuilder, FMModBuilder, HackRFSourceBuilder, RationalResamplerBuilder};
+    use crate::error::ConfigError;
+    use crate::util::{bandstop_taps, LowpassBuilder};
+
+    #[test]
+    fn test_builders() {
+        assert!(RationalResamplerBuilder::new(4_000_000, 150_000).num_taps(1001).build::<f32>().is_ok());
+        assert_eq!(RationalResamplerBuilder::new(48_000, 44_100).num_taps(100).validate(), Err(ConfigError::EvenTaps(100)));
+        assert_eq!(
+            RationalResamplerBuilder::new(48_000, 44_100).integer_decimation().validate(),
+            Err(ConfigError::NonIntegerDecimation { input: 48_000, output: 44_100 })
+        );
+        assert!(RationalResamplerBuilder::new(48_000, 44_100).num_taps(101).validate().is_err());
+
+        assert!(FMDemodBuilder::new(100_000, 75e3).validate().is_err());
+        assert!(FMDemodBuilder::new(150_000, 75e3).build().is_ok());
+        assert!(FMModBuilder::new(48_000, 0.0).validate().is_err());
+        assert!(FMModBuilder::new(48_000, 5e3).build().is_ok());
+
+        assert!(matches!(LowpassBuilder::new(48_000, 30e3).validate(), Err(ConfigError::AboveNyquist { .. })));
+        assert!(LowpassBuilder::new(48_000, 3e3).num_taps(63).build_complex().is_ok());
+        assert_eq!(bandstop_taps(48_000, 8.7e3, 9.3e3, 254), Err(ConfigError::EvenTaps(254)));
+        assert_eq!(bandstop_taps(48_000, 8.7e3, 9.3e3, 255).map(|taps| taps.len()), Ok(255));
+
+        let radio = HackRFSourceBuilder::new(100_000_000, 4_000_000).baseband_bandwidth(2_000_000).lna_gain(40).vga_gain(10);
+        assert_eq!(radio.validate(), Ok(()));
+        assert!(radio.clone().lna_gain(41).validate().is_err());
+        assert!(radio.clone().baseband_bandwidth(5_000_000).validate().is_err());
+        assert!(matches!(radio.samples_per_frame(3).validate(), Err(ConfigError::Invalid(_))));
+    }
+
+}
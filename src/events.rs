@@ -0,0 +1,158 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::{json, Value};
+use crate::hits::Hit;
+use crate::traits::Sink;
+
+
+/// Events a subscriber can fall behind by before newer ones are dropped for it.
+pub const EVENT_QUEUE: usize = 1024;
+
+
+/// Something a decoder or monitor noticed, shared with loggers, dashboards and brokers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A transmission caught by the scanner or recorder, or text a decoder found.
+    Hit(Hit),
+    /// A decoded packet, e.g. APRS or a CC1101 frame.
+    Packet { time: SystemTime, source: String, frequency: Option<u64>, payload: Vec<u8>, crc_ok: bool },
+    /// A position report, e.g. from ADS-B or APRS.
+    Position { time: SystemTime, source: String, id: String, latitude: f64, longitude: f64, altitude_m: Option<f64> },
+    /// Periodic signal level of a monitored channel.
+    Telemetry { time: SystemTime, frequency: u64, rssi_db: f32 },
+}
+
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Hit(_) => "hit",
+            Event::Packet { .. } => "packet",
+            Event::Position { .. } => "position",
+            Event::Telemetry { .. } => "telemetry",
+        }
+    }
+
+    pub fn time(&self) -> SystemTime {
+        match self {
+            Event::Hit(hit) => hit.time,
+            Event::Packet { time, .. } | Event::Position { time, .. } | Event::Telemetry { time, .. } => *time,
+        }
+    }
+
+    /// A flat JSON object with a `type` field, times as unix seconds and payloads as hex.
+    pub fn to_json(&self) -> Value {
+        let time = self.time().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        match self {
+            Event::Hit(hit) => json!({
+                "type": self.kind(),
+                "time": time,
+                "frequency": hit.frequency,
+                "duration": hit.duration.as_secs_f64(),
+                "rssi_db": hit.rssi_db,
+                "source": hit.source,
+                "text": hit.text,
+            }),
+            Event::Packet { source, frequency, payload, crc_ok, .. } => json!({
+                "type": self.kind(),
+                "time": time,
+                "source": source,
+                "frequency": frequency,
+                "payload": payload.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+                "crc_ok": crc_ok,
+            }),
+            Event::Position { source, id, latitude, longitude, altitude_m, .. } => json!({
+                "type": self.kind(),
+                "time": time,
+                "source": source,
+                "id": id,
+                "latitude": latitude,
+                "longitude": longitude,
+                "altitude_m": altitude_m,
+            }),
+            Event::Telemetry { frequency, rssi_db, .. } => json!({
+                "type": self.kind(),
+                "time": time,
+                "frequency": frequency,
+                "rssi_db": rssi_db,
+            }),
+        }
+    }
+}
+
+
+/// Fans events out to any number of subscribers, each reading from its own channel on its own thread.
+/// Publishing never blocks: a subscriber more than `EVENT_QUEUE` events behind misses the newer ones,
+/// and subscribers that hung up are dropped on the next publish.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<SyncSender<Event>>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = sync_channel(EVENT_QUEUE);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn publish(&self, event: Event) {
+        self.subscribers.lock().unwrap().retain(|tx| match tx.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            },
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    /// Events a subscriber missed because its queue was full, summed over all subscribers.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+
+impl Sink<Event> for EventBus {
+    fn write(&mut self, src: &[Event]) -> Result<(), Box<dyn Error>> {
+        for event in src {
+            self.publish(event.clone());
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+    use crate::events::{Event, EventBus, EVENT_QUEUE};
+
+    #[test]
+    fn test_event_bus() {
+        let bus = EventBus::new();
+        let slow = bus.subscribe();
+        let gone = bus.subscribe();
+        drop(gone);
+        let event = |frequency| Event::Telemetry { time: UNIX_EPOCH, frequency, rssi_db: -50.0 };
+
+        // a subscriber that doesn't keep up misses the newest events instead of holding up the publisher
+        for frequency in 0..EVENT_QUEUE as u64 + 10 {
+            bus.publish(event(frequency));
+        }
+        assert_eq!(bus.dropped(), 10);
+        let received: Vec<Event> = slow.try_iter().collect();
+        assert_eq!(received.len(), EVENT_QUEUE);
+        assert_eq!(received[0], event(0));
+    }
+}
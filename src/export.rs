@@ -0,0 +1,356 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use num_complex::Complex32;
+use crate::tag::{Tag, TagValue};
+use crate::traits::*;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Float(f64),
+    Int(i64),
+    Text(String),
+}
+
+
+/// Stream items that can be exported as table rows.
+pub trait Record {
+    const COLUMNS: &'static [&'static str];
+
+    /// Sample index the item belongs to, when it carries one instead of being one sample per row.
+    fn offset(&self) -> Option<u64> {
+        None
+    }
+
+    fn values(&self, out: &mut Vec<Value>);
+}
+
+
+impl Record for f32 {
+    const COLUMNS: &'static [&'static str] = &["value"];
+
+    fn values(&self, out: &mut Vec<Value>) {
+        out.push(Value::Float(*self as f64));
+    }
+}
+
+
+impl Record for Complex32 {
+    const COLUMNS: &'static [&'static str] = &["re", "im"];
+
+    fn values(&self, out: &mut Vec<Value>) {
+        out.push(Value::Float(self.re as f64));
+        out.push(Value::Float(self.im as f64));
+    }
+}
+
+
+impl Record for Tag {
+    const COLUMNS: &'static [&'static str] = &["tag", "value"];
+
+    fn offset(&self) -> Option<u64> {
+        Some(self.offset)
+    }
+
+    fn values(&self, out: &mut Vec<Value>) {
+        match &self.value {
+            TagValue::Timestamp(time) => {
+                out.push(Value::Text("timestamp".into()));
+                out.push(Value::Text(format!("{:.6}", unix_seconds(*time))));
+            },
+            TagValue::Clipping { count } => {
+                out.push(Value::Text("clipping".into()));
+                out.push(Value::Text(count.to_string()));
+            },
+            TagValue::FileStart(path) => {
+                out.push(Value::Text("file".into()));
+                out.push(Value::Text(path.display().to_string()));
+            },
+        }
+    }
+}
+
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+
+/// Maps sample indices to wall clock time for the `time` column.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timebase {
+    pub sample_rate: f64,
+    pub start: SystemTime,
+}
+
+
+impl Timebase {
+    pub fn new(sample_rate: f64, start: SystemTime) -> Self {
+        Self {
+            sample_rate,
+            start,
+        }
+    }
+
+    /// Seconds since the unix epoch of `sample`.
+    pub fn seconds(&self, sample: u64) -> f64 {
+        unix_seconds(self.start) + sample as f64 / self.sample_rate
+    }
+}
+
+
+/// Writes one row per item with `sample` and `time` columns in front, for pandas/Polars `read_csv`.
+pub struct CsvSink<W: Write> {
+    writer: W,
+    timebase: Timebase,
+    position: u64,
+    header: bool,
+    values: Vec<Value>,
+    line: String,
+}
+
+
+impl CsvSink<BufWriter<File>> {
+    pub fn create(path: PathBuf, timebase: Timebase) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), timebase))
+    }
+}
+
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W, timebase: Timebase) -> Self {
+        Self {
+            writer,
+            timebase,
+            position: 0,
+            header: false,
+            values: Vec::new(),
+            line: String::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+
+fn csv_field(line: &mut String, value: &Value) {
+    match value {
+        Value::Float(v) => line.push_str(&v.to_string()),
+        Value::Int(v) => line.push_str(&v.to_string()),
+        Value::Text(v) if v.contains([',', '"', '\n']) => {
+            line.push('"');
+            line.push_str(&v.replace('"', "\"\""));
+            line.push('"');
+        },
+        Value::Text(v) => line.push_str(v),
+    }
+}
+
+
+impl<T: Record, W: Write> Sink<T> for CsvSink<W> {
+    fn write(&mut self, src: &[T]) -> Result<(), Box<dyn Error>> {
+        if !self.header {
+            self.header = true;
+            writeln!(self.writer, "sample,time,{}", T::COLUMNS.join(","))?;
+        }
+        for item in src {
+            let sample = item.offset().unwrap_or_else(|| {
+                self.position += 1;
+                self.position - 1
+            });
+            self.values.clear();
+            item.values(&mut self.values);
+
+            self.line.clear();
+            self.line.push_str(&format!("{},{:.6}", sample, self.timebase.seconds(sample)));
+            for value in &self.values {
+                self.line.push(',');
+                csv_field(&mut self.line, value);
+            }
+            self.line.push('\n');
+            self.writer.write_all(self.line.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(feature = "parquet")]
+pub use parquet_sink::ParquetSink;
+
+
+#[cfg(feature = "parquet")]
+mod parquet_sink {
+    use std::error::Error;
+    use std::fs::File;
+    use std::marker::PhantomData;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use crate::export::{Record, Timebase, Value};
+    use crate::traits::Sink;
+
+    const ROWS_PER_BATCH: usize = 65536;
+
+    /// Parquet version of `CsvSink`, column types are taken from the first row. Call `close` to write the footer.
+    pub struct ParquetSink<T: Record> {
+        path: PathBuf,
+        timebase: Timebase,
+        position: u64,
+        writer: Option<ArrowWriter<File>>,
+        samples: Vec<u64>,
+        columns: Vec<Vec<Value>>,
+        values: Vec<Value>,
+        record: PhantomData<T>,
+    }
+
+    impl<T: Record> ParquetSink<T> {
+        pub fn new(path: PathBuf, timebase: Timebase) -> Self {
+            Self {
+                path,
+                timebase,
+                position: 0,
+                writer: None,
+                samples: Vec::new(),
+                columns: vec![Vec::new(); T::COLUMNS.len()],
+                values: Vec::new(),
+                record: PhantomData,
+            }
+        }
+
+        fn flush_batch(&mut self) -> Result<(), Box<dyn Error>> {
+            if self.samples.is_empty() {
+                return Ok(());
+            }
+
+            let mut fields = vec![
+                Field::new("sample", DataType::UInt64, false),
+                Field::new("time", DataType::Float64, false),
+            ];
+            let time: Vec<f64> = self.samples.iter().map(|&s| self.timebase.seconds(s)).collect();
+            let mut arrays: Vec<ArrayRef> = vec![
+                Arc::new(UInt64Array::from(std::mem::take(&mut self.samples))),
+                Arc::new(Float64Array::from(time)),
+            ];
+            for (name, column) in T::COLUMNS.iter().zip(self.columns.iter_mut()) {
+                let column = std::mem::take(column);
+                let (data_type, array): (DataType, ArrayRef) = match column.first() {
+                    Some(Value::Int(_)) => (DataType::Int64, Arc::new(column.into_iter().map(|v| match v {
+                        Value::Int(v) => Some(v),
+                        _ => None,
+                    }).collect::<Int64Array>())),
+                    Some(Value::Text(_)) => (DataType::Utf8, Arc::new(column.into_iter().map(|v| match v {
+                        Value::Text(v) => Some(v),
+                        _ => None,
+                    }).collect::<StringArray>())),
+                    _ => (DataType::Float64, Arc::new(column.into_iter().map(|v| match v {
+                        Value::Float(v) => Some(v),
+                        _ => None,
+                    }).collect::<Float64Array>())),
+                };
+                fields.push(Field::new(*name, data_type, true));
+                arrays.push(array);
+            }
+
+            let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?;
+            if self.writer.is_none() {
+                self.writer = Some(ArrowWriter::try_new(File::create(&self.path)?, batch.schema(), None)?);
+            }
+            self.writer.as_mut().unwrap().write(&batch)?;
+            Ok(())
+        }
+
+        pub fn close(mut self) -> Result<(), Box<dyn Error>> {
+            self.flush_batch()?;
+            if let Some(writer) = self.writer.take() {
+                writer.close()?;
+            }
+            Ok(())
+        }
+    }
+
+    impl<T: Record> Sink<T> for ParquetSink<T> {
+        fn write(&mut self, src: &[T]) -> Result<(), Box<dyn Error>> {
+            for item in src {
+                let sample = item.offset().unwrap_or(self.position);
+                self.position = self.position.max(sample + 1);
+                self.samples.push(sample);
+                self.values.clear();
+                item.values(&mut self.values);
+                for (column, value) in self.columns.iter_mut().zip(self.values.drain(..)) {
+                    column.push(value);
+                }
+            }
+            if self.samples.len() >= ROWS_PER_BATCH {
+                self.flush_batch()?;
+            }
+            Ok(())
+        }
+    }
+
+    impl<T: Record> Drop for ParquetSink<T> {
+        fn drop(&mut self) {
+            let _ = self.flush_batch();
+            if let Some(writer) = self.writer.take() {
+                let _ = writer.close();
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use num_complex::Complex32;
+    use crate::export::{CsvSink, Timebase};
+    use crate::tag::{Tag, TagValue};
+    use crate::traits::Sink;
+
+    #[test]
+    fn test_csv_sink() -> Result<(), Box<dyn std::error::Error>> {
+        let timebase = Timebase::new(1000.0, UNIX_EPOCH + Duration::from_secs(10));
+
+        let mut sink = CsvSink::new(Vec::new(), timebase);
+        sink.write(&[Complex32::new(0.5, -1.0), Complex32::new(0.25, 2.0)])?;
+        sink.write(&[Complex32::new(1.0, 0.0)])?;
+        let text = String::from_utf8(sink.into_inner())?;
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, ["sample,time,re,im", "0,10.000000,0.5,-1", "1,10.001000,0.25,2", "2,10.002000,1,0"]);
+
+        let mut sink = CsvSink::new(Vec::new(), timebase);
+        sink.write(&[Tag { offset: 500, value: TagValue::Clipping { count: 3 } }])?;
+        let text = String::from_utf8(sink.into_inner())?;
+        assert_eq!(text.lines().nth(1), Some("500,10.500000,clipping,3"));
+        Ok(())
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_sink() -> Result<(), Box<dyn std::error::Error>> {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use crate::export::ParquetSink;
+
+        let path = std::env::temp_dir().join(format!("rust_dsp_export_{}.parquet", std::process::id()));
+        let mut sink = ParquetSink::new(path.clone(), Timebase::new(48000.0, UNIX_EPOCH));
+        sink.write(&vec![0.5f32; 1000])?;
+        sink.close()?;
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path)?)?;
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 1000);
+        assert_eq!(reader.metadata().file_metadata().schema_descr().num_columns(), 3);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+}
@@ -0,0 +1,591 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use crate::traits::*;
+
+
+pub const CCSDS_POLYS: [u8; 2] = [0x4f, 0x6d];
+
+
+fn parity(v: u8) -> u8 {
+    (v.count_ones() & 1) as u8
+}
+
+
+/// Rate 1/2, constraint length 7 convolutional encoder operating on unpacked bits.
+pub struct ConvolutionalEncoder {
+    polys: [u8; 2],
+    sr: u8,
+}
+
+
+impl ConvolutionalEncoder {
+    pub fn new(polys: [u8; 2]) -> Self {
+        Self {
+            polys,
+            sr: 0,
+        }
+    }
+
+    pub fn encode_bit(&mut self, bit: u8) -> [u8; 2] {
+        self.sr = ((self.sr << 1) | (bit & 1)) & 0x7f;
+        [parity(self.sr & self.polys[0]), parity(self.sr & self.polys[1])]
+    }
+}
+
+
+impl Filter<u8, u8> for ConvolutionalEncoder {
+    fn filter(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &bit in input {
+            output.extend_from_slice(&self.encode_bit(bit));
+        }
+        Ok(())
+    }
+}
+
+
+/// Soft decision Viterbi decoder for the K=7 rate 1/2 code.
+///
+/// Input soft bits are positive for a one, output is one unpacked bit per byte.
+pub struct ViterbiDecoder {
+    expected: [[i32; 2]; 128],
+    metrics: [i32; 64],
+    decisions: VecDeque<u64>,
+    depth: usize,
+    pending: Option<i8>,
+}
+
+
+impl ViterbiDecoder {
+    pub fn new(polys: [u8; 2], depth: usize) -> Self {
+        let mut expected = [[0i32; 2]; 128];
+        for (reg, e) in expected.iter_mut().enumerate() {
+            for (k, &poly) in polys.iter().enumerate() {
+                e[k] = if parity(reg as u8 & poly) == 1 { 1 } else { -1 };
+            }
+        }
+
+        Self {
+            expected,
+            metrics: [0; 64],
+            decisions: VecDeque::new(),
+            depth,
+            pending: None,
+        }
+    }
+
+    fn step(&mut self, s0: i8, s1: i8) {
+        let (s0, s1) = (s0 as i32, s1 as i32);
+        let mut next = [i32::MIN; 64];
+        let mut decision = 0u64;
+        for (n, slot) in next.iter_mut().enumerate() {
+            for x in 0..2usize {
+                let prev = (n >> 1) | (x << 5);
+                let e = self.expected[(x << 6) | n];
+                let metric = self.metrics[prev] + e[0] * s0 + e[1] * s1;
+                if metric > *slot {
+                    *slot = metric;
+                    if x == 1 {
+                        decision |= 1 << n;
+                    } else {
+                        decision &= !(1 << n);
+                    }
+                }
+            }
+        }
+
+        let max = next.iter().copied().max().unwrap();
+        for (m, v) in self.metrics.iter_mut().zip(next.iter()) {
+            *m = v - max;
+        }
+        self.decisions.push_back(decision);
+    }
+
+    fn traceback(&mut self, output: &mut Vec<u8>, emit: usize) {
+        let mut state = (0..64).max_by_key(|&s| self.metrics[s]).unwrap();
+        let mut bits = vec![0u8; self.decisions.len()];
+        for t in (0..self.decisions.len()).rev() {
+            bits[t] = (state & 1) as u8;
+            let x = ((self.decisions[t] >> state) & 1) as usize;
+            state = (state >> 1) | (x << 5);
+        }
+        output.extend_from_slice(&bits[..emit]);
+        self.decisions.drain(..emit);
+    }
+
+    /// Emit every remaining bit, e.g. at the end of a recording.
+    pub fn flush(&mut self, output: &mut Vec<u8>) {
+        let emit = self.decisions.len();
+        self.traceback(output, emit);
+    }
+}
+
+
+impl Filter<i8, u8> for ViterbiDecoder {
+    fn filter(&mut self, input: &[i8], output: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &soft in input {
+            match self.pending.take() {
+                None => self.pending = Some(soft),
+                Some(first) => self.step(first, soft),
+            }
+        }
+
+        if self.decisions.len() >= 2 * self.depth {
+            let emit = self.decisions.len() - self.depth;
+            self.traceback(output, emit);
+        }
+        Ok(())
+    }
+}
+
+
+/// Encodes a block with any number of K=7 generator polynomials and appends the 6 zero tail
+/// bits that bring the encoder back to state 0, one output per polynomial and input bit.
+pub fn encode_terminated(polys: &[u8], bits: &[u8]) -> Vec<u8> {
+    let mut sr = 0u8;
+    let mut output = Vec::with_capacity((bits.len() + 6) * polys.len());
+    for &bit in bits.iter().chain(&[0; 6]) {
+        sr = ((sr << 1) | (bit & 1)) & 0x7f;
+        output.extend(polys.iter().map(|&poly| parity(sr & poly)));
+    }
+    output
+}
+
+
+/// Soft decision Viterbi decoding of a block from `encode_terminated`, rate 1/`polys.len()`.
+/// Soft bits are positive for a one and 0 for punctured ones, the tail is not returned.
+pub fn decode_terminated(polys: &[u8], soft: &[i8]) -> Vec<u8> {
+    let rate = polys.len();
+    let expected: Vec<Vec<i32>> = (0..128u8)
+        .map(|reg| polys.iter().map(|&poly| if parity(reg & poly) == 1 { 1 } else { -1 }).collect())
+        .collect();
+
+    let mut metrics = [i32::MIN / 2; 64];
+    metrics[0] = 0;
+    let mut decisions = Vec::with_capacity(soft.len() / rate);
+    for symbols in soft.chunks_exact(rate) {
+        let mut next = [i32::MIN; 64];
+        let mut decision = 0u64;
+        for (n, slot) in next.iter_mut().enumerate() {
+            for x in 0..2usize {
+                let reg = (x << 6) | n;
+                let branch: i32 = expected[reg].iter().zip(symbols).map(|(&e, &s)| e * s as i32).sum();
+                let metric = metrics[(n >> 1) | (x << 5)] + branch;
+                if metric > *slot {
+                    *slot = metric;
+                    decision = (decision & !(1 << n)) | ((x as u64) << n);
+                }
+            }
+        }
+        let max = next.iter().copied().max().unwrap();
+        for (m, v) in metrics.iter_mut().zip(next.iter()) {
+            *m = v.saturating_sub(max);
+        }
+        decisions.push(decision);
+    }
+
+    let mut state = 0;
+    let mut bits = vec![0u8; decisions.len()];
+    for t in (0..decisions.len()).rev() {
+        bits[t] = (state & 1) as u8;
+        let x = ((decisions[t] >> state) & 1) as usize;
+        state = (state >> 1) | (x << 5);
+    }
+    bits.truncate(bits.len().saturating_sub(6));
+    bits
+}
+
+
+pub struct ConvolutionalDeinterleaver<T: Copy + Default> {
+    branches: Vec<VecDeque<T>>,
+    index: usize,
+}
+
+
+impl<T: Copy + Default> ConvolutionalDeinterleaver<T> {
+    /// Undo an interleaver whose branch `b` delays its items by `b * delay`.
+    pub fn new(branches: usize, delay: usize) -> Self {
+        let branches = (0..branches).map(|b| {
+            let mut line = VecDeque::new();
+            line.resize((branches - 1 - b) * delay, T::default());
+            line
+        }).collect();
+
+        Self {
+            branches,
+            index: 0,
+        }
+    }
+}
+
+
+impl<T: Copy + Default> Filter<T, T> for ConvolutionalDeinterleaver<T> {
+    fn filter(&mut self, input: &[T], output: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &sample in input {
+            let line = &mut self.branches[self.index];
+            line.push_back(sample);
+            output.push(line.pop_front().unwrap());
+            self.index = (self.index + 1) % self.branches.len();
+        }
+        Ok(())
+    }
+}
+
+
+/// CCSDS pseudo-randomizer sequence (h(x) = x^8 + x^7 + x^5 + x^3 + 1, all ones seed).
+pub fn ccsds_pn_sequence(len: usize) -> Vec<u8> {
+    let mut x = 0xffu8;
+    let mut seq = Vec::with_capacity(len);
+    for _ in 0..len {
+        let mut byte = 0u8;
+        for _ in 0..8 {
+            byte = (byte << 1) | (x & 1);
+            let fb = (x ^ (x >> 3) ^ (x >> 5) ^ (x >> 7)) & 1;
+            x = (x >> 1) | (fb << 7);
+        }
+        seq.push(byte);
+    }
+    seq
+}
+
+
+pub struct Derandomizer {
+    sequence: Vec<u8>,
+}
+
+
+impl Derandomizer {
+    pub fn new(len: usize) -> Self {
+        Self {
+            sequence: ccsds_pn_sequence(len),
+        }
+    }
+
+    pub fn apply(&self, data: &mut [u8]) {
+        for (d, p) in data.iter_mut().zip(self.sequence.iter()) {
+            *d ^= p;
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Uncorrectable;
+
+
+impl std::fmt::Display for Uncorrectable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "too many errors to correct")
+    }
+}
+
+
+impl Error for Uncorrectable {}
+
+
+/// Reed-Solomon (255, 223) code with the CCSDS field, generator and optional dual basis representation.
+pub struct ReedSolomon {
+    exp: [u8; 512],
+    log: [u8; 256],
+    genpoly: Vec<u8>,
+    to_dual: [u8; 256],
+    from_dual: [u8; 256],
+    dual_basis: bool,
+}
+
+
+impl ReedSolomon {
+    pub const N: usize = 255;
+    pub const K: usize = 223;
+    const NROOTS: usize = 32;
+    const FCR: usize = 112;
+    const PRIM: usize = 11;
+
+    pub fn ccsds(dual_basis: bool) -> Self {
+        let mut exp = [0u8; 512];
+        let mut log = [0u8; 256];
+        let mut x = 1u16;
+        for (i, e) in exp.iter_mut().take(255).enumerate() {
+            *e = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x187;
+            }
+        }
+        for i in 255..512 {
+            exp[i] = exp[i - 255];
+        }
+
+        let tal = [0x8du8, 0xef, 0xec, 0x86, 0xfa, 0x99, 0xaf, 0x7b];
+        let mut to_dual = [0u8; 256];
+        let mut from_dual = [0u8; 256];
+        for (i, dual) in to_dual.iter_mut().enumerate() {
+            let mut v = 0u8;
+            for j in 0..8 {
+                for k in 0..8 {
+                    if i & (1 << k) != 0 {
+                        v ^= tal[7 - k] & (1 << j);
+                    }
+                }
+            }
+            *dual = v;
+            from_dual[v as usize] = i as u8;
+        }
+
+        let mut it = Self {
+            exp,
+            log,
+            genpoly: vec![1],
+            to_dual,
+            from_dual,
+            dual_basis,
+        };
+
+        // g(x) = prod (x - a^(prim * (fcr + i))), highest degree first
+        let mut genpoly = vec![1u8];
+        for i in 0..Self::NROOTS {
+            let root = it.exp[(Self::PRIM * (Self::FCR + i)) % 255];
+            let mut next = vec![0u8; genpoly.len() + 1];
+            for (j, &g) in genpoly.iter().enumerate() {
+                next[j] ^= g;
+                next[j + 1] ^= it.mul(g, root);
+            }
+            genpoly = next;
+        }
+        it.genpoly = genpoly;
+        it
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            0
+        } else {
+            self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+        }
+    }
+
+    fn div(&self, a: u8, b: u8) -> u8 {
+        if a == 0 {
+            0
+        } else {
+            self.exp[(self.log[a as usize] as usize + 255 - self.log[b as usize] as usize) % 255]
+        }
+    }
+
+    fn pow(&self, exponent: usize) -> u8 {
+        self.exp[exponent % 255]
+    }
+
+    /// Compute parity for `data[..223]` and store it in `data[223..]`.
+    pub fn encode(&self, data: &mut [u8; 255]) {
+        let mut msg = [0u8; 255];
+        for i in 0..Self::K {
+            msg[i] = if self.dual_basis { self.from_dual[data[i] as usize] } else { data[i] };
+        }
+
+        let mut parity = [0u8; 32];
+        for &m in msg[..Self::K].iter() {
+            let feedback = m ^ parity[0];
+            parity.copy_within(1.., 0);
+            parity[Self::NROOTS - 1] = 0;
+            if feedback != 0 {
+                for (p, &g) in parity.iter_mut().zip(self.genpoly[1..].iter()) {
+                    *p ^= self.mul(feedback, g);
+                }
+            }
+        }
+
+        for (i, &p) in parity.iter().enumerate() {
+            data[Self::K + i] = if self.dual_basis { self.to_dual[p as usize] } else { p };
+        }
+    }
+
+    /// Correct a codeword in place, returning the number of corrected bytes.
+    pub fn decode(&self, data: &mut [u8; 255]) -> Result<usize, Uncorrectable> {
+        let mut r = [0u8; 255];
+        for i in 0..Self::N {
+            r[i] = if self.dual_basis { self.from_dual[data[i] as usize] } else { data[i] };
+        }
+
+        // syndromes, r[0] is the highest degree coefficient
+        let mut syndromes = [0u8; 32];
+        let mut clean = true;
+        for (i, s) in syndromes.iter_mut().enumerate() {
+            let root = self.pow(Self::PRIM * (Self::FCR + i));
+            let mut acc = 0u8;
+            for &c in r.iter() {
+                acc = self.mul(acc, root) ^ c;
+            }
+            *s = acc;
+            clean &= acc == 0;
+        }
+        if clean {
+            return Ok(0);
+        }
+
+        // Berlekamp-Massey, lowest degree first
+        let mut lambda = vec![0u8; Self::NROOTS + 1];
+        lambda[0] = 1;
+        let mut b = lambda.clone();
+        let mut l = 0usize;
+        let mut m = 1usize;
+        let mut last = 1u8;
+        for n in 0..Self::NROOTS {
+            let mut delta = syndromes[n];
+            for i in 1..=l {
+                delta ^= self.mul(lambda[i], syndromes[n - i]);
+            }
+            if delta == 0 {
+                m += 1;
+            } else {
+                let coef = self.div(delta, last);
+                let prev = lambda.clone();
+                for i in m..=Self::NROOTS {
+                    lambda[i] ^= self.mul(coef, b[i - m]);
+                }
+                if 2 * l <= n {
+                    l = n + 1 - l;
+                    b = prev;
+                    last = delta;
+                    m = 1;
+                } else {
+                    m += 1;
+                }
+            }
+        }
+
+        // omega(x) = S(x) * lambda(x) mod x^nroots
+        let mut omega = [0u8; 32];
+        for (i, o) in omega.iter_mut().enumerate() {
+            for j in 0..=i.min(l) {
+                *o ^= self.mul(lambda[j], syndromes[i - j]);
+            }
+        }
+
+        // Chien search over every position with Forney's algorithm for the magnitude
+        let mut corrected = 0;
+        for (pos, c) in r.iter_mut().enumerate() {
+            let degree = Self::N - 1 - pos;
+            let x_inv = self.pow(255 * Self::PRIM - (Self::PRIM * degree) % 255);
+
+            let mut value = 0u8;
+            for &c in lambda[..=l].iter().rev() {
+                value = self.mul(value, x_inv) ^ c;
+            }
+            if value != 0 {
+                continue;
+            }
+
+            let mut num = 0u8;
+            for &c in omega.iter().rev() {
+                num = self.mul(num, x_inv) ^ c;
+            }
+            let mut den = 0u8;
+            for i in (1..=l).step_by(2) {
+                den ^= self.mul(lambda[i], self.pow(self.log[x_inv as usize] as usize * (i - 1)));
+            }
+            if den == 0 {
+                return Err(Uncorrectable);
+            }
+
+            // X^(1 - fcr) with X = a^(prim * degree)
+            let x_pow = self.pow((Self::PRIM * degree * (255 - (Self::FCR - 1) % 255)) % 255);
+            *c ^= self.mul(self.div(num, den), x_pow);
+            corrected += 1;
+        }
+
+        if corrected != l {
+            return Err(Uncorrectable);
+        }
+
+        for i in 0..Self::N {
+            data[i] = if self.dual_basis { self.to_dual[r[i] as usize] } else { r[i] };
+        }
+        Ok(corrected)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::fec::{ccsds_pn_sequence, ConvolutionalDeinterleaver, ConvolutionalEncoder, ReedSolomon, ViterbiDecoder, CCSDS_POLYS};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_pn_sequence() {
+        assert_eq!(ccsds_pn_sequence(8), vec![0xff, 0x48, 0x0e, 0xc0, 0x9a, 0x0d, 0x70, 0xbc]);
+    }
+
+    #[test]
+    fn test_viterbi() -> Result<(), Box<dyn std::error::Error>> {
+        let mut bits: Vec<u8> = (0..2000u32).map(|i| ((i * 7919) >> 3 & 1) as u8).collect();
+        bits.extend([0; 6]);
+        let mut coded = Vec::new();
+        ConvolutionalEncoder::new(CCSDS_POLYS).filter(&bits, &mut coded)?;
+
+        let mut soft: Vec<i8> = coded.iter().map(|&b| if b == 1 { 64 } else { -64 }).collect();
+        for i in (0..soft.len()).step_by(37) {
+            soft[i] = -soft[i];
+        }
+
+        let mut decoder = ViterbiDecoder::new(CCSDS_POLYS, 64);
+        let mut decoded = Vec::new();
+        decoder.filter(&soft, &mut decoded)?;
+        let mut tail = Vec::new();
+        decoder.flush(&mut tail);
+        decoded.extend(tail);
+
+        assert_eq!(decoded, bits);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reed_solomon() {
+        for dual_basis in [false, true] {
+            let rs = ReedSolomon::ccsds(dual_basis);
+            let mut block = [0u8; 255];
+            for (i, b) in block.iter_mut().enumerate().take(ReedSolomon::K) {
+                *b = (i * 31 + 7) as u8;
+            }
+            rs.encode(&mut block);
+            let original = block;
+            assert_eq!(rs.decode(&mut block).unwrap(), 0);
+
+            for i in 0..16 {
+                block[i * 13 + 2] ^= 0x5a;
+            }
+            assert_eq!(rs.decode(&mut block).unwrap(), 16);
+            assert_eq!(block, original);
+
+            for i in 0..17 {
+                block[i * 13 + 2] ^= 0xa5;
+            }
+            assert!(rs.decode(&mut block).is_err());
+        }
+    }
+
+    #[test]
+    fn test_deinterleaver() -> Result<(), Box<dyn std::error::Error>> {
+        let (branches, delay) = (3, 2);
+        let input: Vec<u32> = (1..=60).collect();
+
+        // interleave: branch b delays by b * delay
+        let mut lines: Vec<Vec<u32>> = (0..branches).map(|b| vec![0; b * delay]).collect();
+        let mut interleaved = Vec::new();
+        for (i, &v) in input.iter().enumerate() {
+            let line = &mut lines[i % branches];
+            line.push(v);
+            interleaved.push(line.remove(0));
+        }
+
+        let mut output = Vec::new();
+        ConvolutionalDeinterleaver::new(branches, delay).filter(&interleaved, &mut output)?;
+        let latency = branches * (branches - 1) * delay;
+        assert_eq!(&output[latency..], &input[..input.len() - latency]);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,272 @@
+use std::error::Error;
+use std::f32::consts::PI;
+use num_complex::Complex32;
+use crate::traits::Filter;
+
+
+/// Radix-2 FFT with precomputed twiddles and bit reversal table.
+pub struct FFT {
+    size: usize,
+    twiddles: Vec<Complex32>,
+    reversed: Vec<usize>,
+}
+
+
+impl FFT {
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two(), "fft size must be a power of two");
+        let bits = size.trailing_zeros();
+
+        let twiddles = (0..size / 2)
+            .map(|k| Complex32::from_polar(1.0, -2.0 * PI * k as f32 / size as f32))
+            .collect();
+        let reversed = (0..size)
+            .map(|i| if bits == 0 { 0 } else { i.reverse_bits() >> (usize::BITS - bits) })
+            .collect();
+
+        Self {
+            size,
+            twiddles,
+            reversed,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn transform(&self, data: &mut [Complex32], inverse: bool) {
+        assert_eq!(data.len(), self.size);
+
+        for i in 0..self.size {
+            let j = self.reversed[i];
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= self.size {
+            let step = self.size / len;
+            for start in (0..self.size).step_by(len) {
+                for k in 0..len / 2 {
+                    let mut w = self.twiddles[k * step];
+                    if inverse {
+                        w = w.conj();
+                    }
+                    let a = data[start + k];
+                    let b = data[start + k + len / 2] * w;
+                    data[start + k] = a + b;
+                    data[start + k + len / 2] = a - b;
+                }
+            }
+            len <<= 1;
+        }
+    }
+
+    pub fn forward(&self, data: &mut [Complex32]) {
+        self.transform(data, false);
+    }
+
+    /// Inverse transform, scaled by 1/N so that `inverse(forward(x)) == x`.
+    pub fn inverse(&self, data: &mut [Complex32]) {
+        self.transform(data, true);
+        let scale = 1.0 / self.size as f32;
+        for v in data.iter_mut() {
+            *v *= scale;
+        }
+    }
+}
+
+
+/// Swap the halves of a spectrum so DC sits in the middle.
+pub fn fftshift<T>(data: &mut [T]) {
+    let half = data.len() / 2;
+    data.rotate_left(half);
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+
+impl Window {
+    pub fn coefficients(&self, len: usize) -> Vec<f32> {
+        let m = (len.max(2) - 1) as f32;
+        (0..len).map(|n| {
+            let x = 2.0 * PI * n as f32 / m;
+            match self {
+                Window::Rectangular => 1.0,
+                Window::Hann => 0.5 - 0.5 * x.cos(),
+                Window::Hamming => 0.54 - 0.46 * x.cos(),
+                Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+            }
+        }).collect()
+    }
+}
+
+
+/// Power spectrum of one window of samples, DC centered.
+pub fn power_spectrum(fft: &FFT, window: &[f32], samples: &[Complex32], output: &mut Vec<f32>) {
+    let mut buffer: Vec<Complex32> = samples.iter().zip(window.iter()).map(|(&s, &w)| s * w).collect();
+    buffer.resize(fft.size(), Complex32::new(0.0, 0.0));
+    fft.forward(&mut buffer);
+    fftshift(&mut buffer);
+
+    let norm = window.iter().map(|w| w * w).sum::<f32>().max(f32::EPSILON);
+    output.clear();
+    output.extend(buffer.iter().map(|v| v.norm_sqr() / norm));
+}
+
+
+/// Power spectra in dB, one DC centered row of `cols` bins per frame, stored row major.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Spectrogram {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<f32>,
+}
+
+
+impl Spectrogram {
+    pub fn row(&self, row: usize) -> &[f32] {
+        &self.data[row * self.cols..(row + 1) * self.cols]
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f32 {
+        self.data[row * self.cols + col]
+    }
+
+    pub fn to_rows(&self) -> Vec<Vec<f32>> {
+        self.data.chunks(self.cols.max(1)).map(<[f32]>::to_vec).collect()
+    }
+}
+
+
+/// One row per `hop` samples for every full `fft_size` frame in `samples`.
+pub fn spectrogram(samples: &[Complex32], fft_size: usize, hop: usize, window: Window) -> Spectrogram {
+    let mut stream = SpectrogramStream::new(fft_size, hop, window);
+    let mut result = Spectrogram {
+        rows: 0,
+        cols: fft_size,
+        data: Vec::new(),
+    };
+    stream.push(samples, |row| {
+        result.data.extend_from_slice(row);
+        result.rows += 1;
+    });
+    result
+}
+
+
+/// Incremental [`spectrogram`], rows come out as soon as their frame is complete.
+pub struct SpectrogramStream {
+    fft: FFT,
+    window: Vec<f32>,
+    hop: usize,
+    buffer: Vec<Complex32>,
+    skip: usize,
+    row: Vec<f32>,
+}
+
+
+impl SpectrogramStream {
+    pub fn new(fft_size: usize, hop: usize, window: Window) -> Self {
+        assert!(hop > 0, "hop must be at least one sample");
+        Self {
+            fft: FFT::new(fft_size),
+            window: window.coefficients(fft_size),
+            hop,
+            buffer: Vec::new(),
+            skip: 0,
+            row: Vec::new(),
+        }
+    }
+
+    /// Feed samples, `on_row` gets every finished row in dB.
+    pub fn push(&mut self, samples: &[Complex32], mut on_row: impl FnMut(&[f32])) {
+        let size = self.fft.size();
+        let skip = self.skip.min(samples.len());
+        self.skip -= skip;
+        self.buffer.extend_from_slice(&samples[skip..]);
+        let mut off = 0;
+        while self.buffer.len() >= off + size {
+            power_spectrum(&self.fft, &self.window, &self.buffer[off..off + size], &mut self.row);
+            self.row.iter_mut().for_each(|p| *p = 10.0 * p.max(1e-20).log10());
+            on_row(&self.row);
+            off += self.hop;
+        }
+        // a hop longer than the frame also skips samples that haven't arrived yet
+        let drained = off.min(self.buffer.len());
+        self.skip += off - drained;
+        self.buffer.drain(..drained);
+    }
+}
+
+
+impl Filter<Complex32, Vec<f32>> for SpectrogramStream {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Vec<f32>>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.push(input, |row| output.push(row.to_vec()));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex32;
+    use crate::fft::{spectrogram, SpectrogramStream, Window, FFT};
+
+    #[test]
+    fn test_fft() {
+        let fft = FFT::new(64);
+        let tone: Vec<Complex32> = (0..64)
+            .map(|n| Complex32::from_polar(1.0, 2.0 * std::f32::consts::PI * 5.0 * n as f32 / 64.0))
+            .collect();
+
+        let mut data = tone.clone();
+        fft.forward(&mut data);
+        let peak = (0..64).max_by(|&a, &b| data[a].norm().total_cmp(&data[b].norm())).unwrap();
+        assert_eq!(peak, 5);
+        assert!((data[5].norm() - 64.0).abs() < 1e-3);
+
+        fft.inverse(&mut data);
+        for (a, b) in data.iter().zip(tone.iter()) {
+            assert!((a - b).norm() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_spectrogram() {
+        // a tone jumping from bin +8 to bin -16 halfway through
+        let samples: Vec<Complex32> = (0..4096)
+            .map(|n| {
+                let bin = if n < 2048 { 8.0 } else { -16.0 };
+                Complex32::from_polar(1.0, 2.0 * std::f32::consts::PI * bin * n as f32 / 128.0)
+            })
+            .collect();
+        let result = spectrogram(&samples, 128, 64, Window::Hann);
+        assert_eq!((result.rows, result.cols), (63, 128));
+        let peak = |row: &[f32]| (0..row.len()).max_by(|&a, &b| row[a].total_cmp(&row[b])).unwrap();
+        assert_eq!(peak(result.row(0)), 64 + 8);
+        assert_eq!(peak(result.row(62)), 64 - 16);
+
+        // streaming in odd sized pieces, also with a hop longer than the frame, gives the same rows
+        for hop in [64, 200] {
+            let expected = spectrogram(&samples, 128, hop, Window::Hann).to_rows();
+            let mut stream = SpectrogramStream::new(128, hop, Window::Hann);
+            let mut rows = Vec::new();
+            for chunk in samples.chunks(77) {
+                stream.push(chunk, |row| rows.push(row.to_vec()));
+            }
+            assert_eq!(rows, expected);
+        }
+    }
+
+}
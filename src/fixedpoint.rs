@@ -0,0 +1,181 @@
+use std::f64::consts::TAU;
+use num_complex::Complex64;
+use crate::error::ConfigError;
+
+
+/// Frequencies from DC to Nyquist the responses are compared at.
+const GRID_POINTS: usize = 1024;
+
+
+/// A signed fixed-point coefficient format, one sign bit plus `integer_bits` and `fraction_bits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QFormat {
+    pub integer_bits: u32,
+    pub fraction_bits: u32,
+}
+
+
+impl QFormat {
+    /// 16-bit, -1 to just under 1, the usual format for taps on 16-bit DSPs.
+    pub const Q15: QFormat = QFormat { integer_bits: 0, fraction_bits: 15 };
+    /// 16-bit, -2 to just under 2, for taps or gains above 1 at half the resolution.
+    pub const Q1_14: QFormat = QFormat { integer_bits: 1, fraction_bits: 14 };
+
+    pub fn new(integer_bits: u32, fraction_bits: u32) -> Result<Self, ConfigError> {
+        if integer_bits + fraction_bits > 31 {
+            return Err(ConfigError::Invalid(format!("Q{}.{} is wider than 32 bits", integer_bits, fraction_bits)));
+        }
+        Ok(Self { integer_bits, fraction_bits })
+    }
+
+    /// Word size including the sign bit.
+    pub fn bits(&self) -> u32 {
+        1 + self.integer_bits + self.fraction_bits
+    }
+
+    /// Value of one step of the integer code.
+    pub fn resolution(&self) -> f64 {
+        (-(self.fraction_bits as f64)).exp2()
+    }
+
+    fn max_code(&self) -> i32 {
+        ((1i64 << (self.integer_bits + self.fraction_bits)) - 1) as i32
+    }
+
+    /// The nearest code to `value` and whether it had to be clipped to the range.
+    pub fn quantize(&self, value: f32) -> (i32, bool) {
+        let code = (value as f64 / self.resolution()).round();
+        let max = self.max_code() as f64;
+        (code.clamp(-max - 1.0, max) as i32, code > max || code < -max - 1.0)
+    }
+
+    pub fn to_float(&self, code: i32) -> f32 {
+        (code as f64 * self.resolution()) as f32
+    }
+}
+
+
+/// Taps rounded to a fixed-point format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuantizedTaps {
+    pub format: QFormat,
+    pub codes: Vec<i32>,
+    /// Taps outside the format's range, clipped to its limits.
+    pub saturated: usize,
+}
+
+
+impl QuantizedTaps {
+    pub fn new(taps: &[f32], format: QFormat) -> Self {
+        let mut saturated = 0;
+        let codes = taps.iter().map(|&tap| {
+            let (code, clipped) = format.quantize(tap);
+            saturated += clipped as usize;
+            code
+        }).collect();
+        Self { format, codes, saturated }
+    }
+
+    /// The taps the fixed-point filter actually applies.
+    pub fn taps(&self) -> Vec<f32> {
+        self.codes.iter().map(|&code| self.format.to_float(code)).collect()
+    }
+}
+
+
+/// How much a filter's frequency response suffers from quantizing its taps, compared over a grid
+/// from DC to Nyquist. Levels are relative to the peak of the designed response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuantizationReport {
+    pub saturated: usize,
+    /// Largest change in gain where the designed response is within 3 dB of its peak.
+    pub passband_change_db: f32,
+    /// Largest difference between the responses. Stopband attenuation beyond this is lost, the
+    /// quantized filter can't reliably get below it.
+    pub error_floor_db: f32,
+    /// Highest gain where the designed response is `stopband_db` or more down.
+    pub designed_stopband_db: f32,
+    pub quantized_stopband_db: f32,
+}
+
+
+/// Frequency response of `taps` at `frequency` cycles per sample.
+fn response(taps: &[f32], frequency: f64) -> Complex64 {
+    taps.iter().enumerate().map(|(n, &tap)| Complex64::from_polar(tap as f64, -TAU * frequency * n as f64)).sum()
+}
+
+
+fn db(gain: f64) -> f32 {
+    (20.0 * gain.max(1e-15).log10()) as f32
+}
+
+
+/// Quantizes `taps` to `format` and reports the damage. `stopband_db`, e.g. -60, picks the
+/// frequencies counted as stopband: those where the designed response is at least that far down.
+pub fn analyze_quantization(taps: &[f32], format: QFormat, stopband_db: f32) -> QuantizationReport {
+    let quantized = QuantizedTaps::new(taps, format);
+    let quantized_taps = quantized.taps();
+    let responses: Vec<(Complex64, Complex64)> = (0..=GRID_POINTS)
+        .map(|i| 0.5 * i as f64 / GRID_POINTS as f64)
+        .map(|frequency| (response(taps, frequency), response(&quantized_taps, frequency)))
+        .collect();
+    let peak = responses.iter().map(|(designed, _)| designed.norm()).fold(0.0, f64::max);
+    let stopband = peak * 10f64.powf(stopband_db as f64 / 20.0);
+
+    let (mut passband_change, mut error, mut designed_stopband, mut quantized_stopband) = (0.0f64, 0.0f64, 0.0f64, 0.0f64);
+    for (designed, quantized) in &responses {
+        error = error.max((quantized - designed).norm());
+        if designed.norm() >= peak * 0.5f64.sqrt() {
+            passband_change = passband_change.max((quantized.norm() / designed.norm()).log10().abs());
+        }
+        if designed.norm() <= stopband {
+            designed_stopband = designed_stopband.max(designed.norm());
+            quantized_stopband = quantized_stopband.max(quantized.norm());
+        }
+    }
+    QuantizationReport {
+        saturated: quantized.saturated,
+        passband_change_db: 20.0 * passband_change as f32,
+        error_floor_db: db(error / peak),
+        designed_stopband_db: db(designed_stopband / peak),
+        quantized_stopband_db: db(quantized_stopband / peak),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::fixedpoint::{analyze_quantization, QFormat, QuantizedTaps};
+    use crate::util::lowpass_taps;
+
+    #[test]
+    fn test_tap_quantization() {
+        assert_eq!(QFormat::Q15.quantize(0.5), (16384, false));
+        assert_eq!(QFormat::Q15.quantize(1.0), (32767, true));
+        assert_eq!(QFormat::Q15.quantize(-1.0), (-32768, false));
+        assert_eq!(QFormat::Q1_14.quantize(1.5), (24576, false));
+        assert_eq!(QFormat::Q1_14.bits(), 16);
+        assert!(QFormat::new(16, 16).is_err());
+        assert_eq!(QuantizedTaps::new(&[0.25, 1.5], QFormat::Q15).saturated, 1);
+
+        // a unity gain lowpass with about 45 dB of stopband
+        let taps = lowpass_taps(0.1, 101);
+        let sum: f32 = taps.iter().sum();
+        let taps: Vec<f32> = taps.iter().map(|tap| tap / sum).collect();
+
+        let q15 = analyze_quantization(&taps, QFormat::Q15, -45.0);
+        assert_eq!(q15.saturated, 0);
+        assert!(q15.passband_change_db < 0.01, "{:?}", q15);
+        assert!(q15.error_floor_db < -70.0, "{:?}", q15);
+        assert!((q15.quantized_stopband_db - q15.designed_stopband_db).abs() < 0.5, "{:?}", q15);
+
+        // each bit less costs about 6 dB of floor
+        let q1_14 = analyze_quantization(&taps, QFormat::Q1_14, -45.0);
+        assert!((q1_14.error_floor_db - q15.error_floor_db - 6.0).abs() < 4.0, "{:?} {:?}", q1_14, q15);
+
+        // 8-bit taps can't hold the stopband
+        let q7 = analyze_quantization(&taps, QFormat::new(0, 7).unwrap(), -45.0);
+        assert!(q7.error_floor_db > -50.0, "{:?}", q7);
+        assert!(q7.quantized_stopband_db > q7.designed_stopband_db + 3.0, "{:?}", q7);
+    }
+}
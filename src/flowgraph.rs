@@ -0,0 +1,257 @@
+use std::error::Error;
+use std::marker::PhantomData;
+use std::time::Duration;
+use crate::pipeline::{CancelToken, RunStats, Runner};
+use crate::rate::{RateAware, RateMismatch, SampleRate};
+use crate::traits::{Filter, Sink, Source};
+
+
+/// A source followed by a filter, itself a source of what the filter produces. Filters that hold
+/// samples back, like a decimator fed less than one output's worth, don't end the stream: the
+/// source is read again until the filter has something or the source is done.
+pub struct Filtered<S, F, M> {
+    source: S,
+    filter: F,
+    buffer: Vec<M>,
+}
+
+
+impl<S, F, M> Filtered<S, F, M> {
+    pub fn new(source: S, filter: F) -> Self {
+        Self {
+            source,
+            filter,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn filter(&self) -> &F {
+        &self.filter
+    }
+
+    pub fn into_inner(self) -> (S, F) {
+        (self.source, self.filter)
+    }
+}
+
+
+impl<S: Source<M>, F: Filter<M, O>, M, O> Source<O> for Filtered<S, F, M> {
+    fn read(&mut self, dst: &mut Vec<O>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        while dst.is_empty() {
+            self.source.read(&mut self.buffer)?;
+            if self.buffer.is_empty() {
+                break;
+            }
+            self.filter.filter(&self.buffer, dst)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl<S: RateAware, F: RateAware, M> RateAware for Filtered<S, F, M> {
+    fn output_rate(&self, input: Option<SampleRate>) -> Option<SampleRate> {
+        let rate = self.source.output_rate(input);
+        self.filter.output_rate(rate.or(self.filter.input_rate()))
+    }
+
+    /// Both delays moved to the output rate when the rates are known.
+    fn delay(&self) -> f64 {
+        let between = self.source.output_rate(None).or(self.filter.input_rate());
+        let delay = self.source.delay() + self.filter.delay();
+        match (between, self.filter.output_rate(between)) {
+            (Some(SampleRate(input)), Some(SampleRate(output))) if input > 0 => delay * output as f64 / input as f64,
+            _ => delay,
+        }
+    }
+}
+
+
+/// Follows the sample rate down a chain like `check_chain`, remembering the first mismatch.
+pub(crate) struct RateCheck {
+    pub rate: Option<SampleRate>,
+    blocks: usize,
+    mismatch: Option<RateMismatch>,
+}
+
+
+impl RateCheck {
+    pub fn new(source: &dyn RateAware) -> Self {
+        Self {
+            rate: source.output_rate(source.input_rate()),
+            blocks: 1,
+            mismatch: None,
+        }
+    }
+
+    pub fn check(&mut self, block: &dyn RateAware) {
+        if let (Some(upstream), Some(expected)) = (self.rate, block.input_rate())
+            && upstream != expected {
+            self.mismatch = self.mismatch.or(Some(RateMismatch { index: self.blocks, upstream, expected }));
+        }
+        self.rate = block.output_rate(self.rate.or(block.input_rate()));
+        self.blocks += 1;
+    }
+
+    pub fn result(&self) -> Result<(), RateMismatch> {
+        self.mismatch.map_or(Ok(()), Err)
+    }
+}
+
+
+/// Connects blocks by method calls instead of a hand written loop:
+///
+/// `flowgraph.source(hackrf).filter(mix).filter(resample).sink(speakers)?.run()?`
+///
+/// Every connection gets its own buffer and the sample types come from the `Filter` impls, so a
+/// mismatched chain fails to compile. Sample rates are checked like `check_chain` when the sink is
+/// attached. Blocks are moved in, pass `&mut block` to keep using one after the run.
+pub struct Flowgraph {
+    runner: Runner,
+}
+
+
+impl Flowgraph {
+    pub fn new(cancel: CancelToken) -> Self {
+        Self {
+            runner: Runner::new(cancel),
+        }
+    }
+
+    /// For stop conditions and the cancel token.
+    pub fn runner(&mut self) -> &mut Runner {
+        &mut self.runner
+    }
+
+    pub fn source<T, S: Source<T> + RateAware>(&mut self, source: S) -> Chain<'_, S, T> {
+        Chain {
+            runner: &mut self.runner,
+            rates: RateCheck::new(&source),
+            source,
+            _marker: PhantomData,
+        }
+    }
+}
+
+
+/// A source and the filters attached to it so far, producing `T`.
+pub struct Chain<'a, S, T> {
+    runner: &'a mut Runner,
+    source: S,
+    rates: RateCheck,
+    _marker: PhantomData<T>,
+}
+
+
+impl<'a, S: Source<T> + RateAware, T> Chain<'a, S, T> {
+    pub fn filter<O, F: Filter<T, O> + RateAware>(mut self, filter: F) -> Chain<'a, Filtered<S, F, T>, O> {
+        self.rates.check(&filter);
+        Chain {
+            runner: self.runner,
+            source: Filtered::new(self.source, filter),
+            rates: self.rates,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Rate coming out of the last block, if anything in the chain knows it.
+    pub fn rate(&self) -> Option<SampleRate> {
+        self.rates.rate
+    }
+
+    /// Finishes the chain, failing on the first connection whose rates don't match.
+    pub fn sink<K: Sink<T> + RateAware>(mut self, sink: K) -> Result<Connected<'a, S, T, K>, RateMismatch> {
+        self.rates.check(&sink);
+        self.rates.result()?;
+        Ok(Connected {
+            runner: self.runner,
+            source: self.source,
+            sink,
+            _marker: PhantomData,
+        })
+    }
+}
+
+
+/// A complete chain, ready to run as often as wanted.
+pub struct Connected<'a, S, T, K> {
+    runner: &'a mut Runner,
+    source: S,
+    sink: K,
+    _marker: PhantomData<T>,
+}
+
+
+impl<S: Source<T> + RateAware, T, K: Sink<T> + RateAware> Connected<'_, S, T, K> {
+    /// Until the source ends, the token is cancelled or a stop condition fires.
+    pub fn run(&mut self) -> Result<RunStats, Box<dyn Error>> {
+        let sink = &mut self.sink;
+        self.runner.run(&mut self.source, |block| sink.write(block))
+    }
+
+    pub fn run_for(&mut self, duration: Duration) -> Result<RunStats, Box<dyn Error>> {
+        let sink = &mut self.sink;
+        self.runner.run_for(duration, &mut self.source, |block| sink.write(block))
+    }
+
+    /// Counts samples read from the source.
+    pub fn run_samples(&mut self, samples: u64) -> Result<RunStats, Box<dyn Error>> {
+        let sink = &mut self.sink;
+        self.runner.run_samples(samples, &mut self.source, |block| sink.write(block))
+    }
+
+    /// Samples from the source to the sink, its own delay included, at the sink's rate.
+    pub fn delay(&self) -> f64 {
+        self.source.delay() + self.sink.delay()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex32;
+    use crate::block::{FnFilter, MapFilter, MixerFilter, NullSink, NullSource, RationalResampler};
+    use crate::flowgraph::Flowgraph;
+    use crate::pipeline::{CancelToken, StopReason};
+    use crate::rate::SampleRate;
+
+    #[test]
+    fn test_flowgraph() -> Result<(), Box<dyn std::error::Error>> {
+        let mut flowgraph = Flowgraph::new(CancelToken::new());
+        let mut sink = NullSink::new();
+        // only every fourth buffer makes it through, the others mustn't end the stream
+        let mut buffers = 0;
+        let every_fourth = FnFilter::new(move |input: &[f32], output: &mut Vec<f32>| {
+            buffers += 1;
+            if buffers % 4 == 0 {
+                output.extend_from_slice(input);
+            }
+            Ok(())
+        });
+        let stats = flowgraph.source(NullSource::new(48000, 1000).limit(8000))
+            .filter(MapFilter::new(|x: f32| x + 1.0))
+            .filter(MixerFilter::new(48000, 1000.0))
+            .filter(MapFilter::new(|x: Complex32| x.norm()))
+            .filter(every_fourth)
+            .sink(&mut sink)?
+            .run()?;
+        assert_eq!((stats.stop, stats.buffers), (StopReason::EndOfStream, 2));
+        assert_eq!(sink.samples(), 2000);
+
+        let chain = flowgraph.source(NullSource::new(48000, 1000))
+            .filter(RationalResampler::<f32>::new(48000, 8000, 31));
+        assert_eq!(chain.rate(), Some(SampleRate(8000)));
+        let mut connected = chain.sink(NullSink::new())?;
+        assert_eq!(connected.run_samples(4000)?.samples, 4000);
+
+        // a 44.1 kHz resampler behind a 48 kHz source
+        let mismatch = flowgraph.source(NullSource::new(48000, 1000))
+            .filter(MapFilter::new(|x: f32| x))
+            .filter(RationalResampler::<f32>::new(44100, 8000, 31))
+            .sink(NullSink::new())
+            .err().unwrap();
+        assert_eq!((mismatch.index, mismatch.upstream, mismatch.expected), (2, SampleRate(48000), SampleRate(44100)));
+        Ok(())
+    }
+}
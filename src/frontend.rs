@@ -0,0 +1,328 @@
+use std::error::Error;
+use std::path::Path;
+use num_complex::Complex32;
+use crate::rate::RateAware;
+use crate::traits::Filter;
+
+
+/// Response of the external frontend at one frequency, as gain and phase to apply to undo it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GainCorrection {
+    pub frequency_hz: f64,
+    pub gain_db: f32,
+    pub phase_deg: f32,
+}
+
+
+/// Per-frequency corrections, linearly interpolated and held flat past the ends.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CorrectionTable {
+    points: Vec<GainCorrection>,
+}
+
+
+impl CorrectionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, point: GainCorrection) {
+        self.points.push(point);
+        self.points.sort_by(|a, b| a.frequency_hz.total_cmp(&b.frequency_hz));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Gain in dB and phase in degrees at `frequency_hz`, zero for an empty table.
+    pub fn at(&self, frequency_hz: f64) -> (f32, f32) {
+        let after = self.points.partition_point(|p| p.frequency_hz < frequency_hz);
+        match (after.checked_sub(1).map(|i| &self.points[i]), self.points.get(after)) {
+            (Some(a), Some(b)) => {
+                let t = ((frequency_hz - a.frequency_hz) / (b.frequency_hz - a.frequency_hz)) as f32;
+                (a.gain_db + t * (b.gain_db - a.gain_db), a.phase_deg + t * (b.phase_deg - a.phase_deg))
+            },
+            (Some(p), None) | (None, Some(p)) => (p.gain_db, p.phase_deg),
+            (None, None) => (0.0, 0.0),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut text = String::from("frequency_hz,gain_db,phase_deg\n");
+        for p in &self.points {
+            text += &format!("{},{},{}\n", p.frequency_hz, p.gain_db, p.phase_deg);
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut table = Self::new();
+        for (number, line) in std::fs::read_to_string(path)?.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [frequency_hz, gain_db, phase_deg] = fields[..] else {
+                return Err(format!("{}:{}: expected 3 fields", path.display(), number + 1).into());
+            };
+            table.add(GainCorrection {
+                frequency_hz: frequency_hz.parse()?,
+                gain_db: gain_db.parse()?,
+                phase_deg: phase_deg.parse()?,
+            });
+        }
+        Ok(table)
+    }
+}
+
+
+/// Multiplies by a fixed complex gain to compensate downconverters, transverters or calibrated antennas.
+/// The table correction for the current frequency is applied on top of the fixed gain.
+pub struct ComplexGainFilter {
+    gain_db: f32,
+    phase_deg: f32,
+    table: CorrectionTable,
+    frequency_hz: f64,
+    factor: Complex32,
+}
+
+
+impl ComplexGainFilter {
+    pub fn new(gain_db: f32, phase_deg: f32) -> Self {
+        let mut filter = Self {
+            gain_db,
+            phase_deg,
+            table: CorrectionTable::new(),
+            frequency_hz: 0.0,
+            factor: Complex32::new(1.0, 0.0),
+        };
+        filter.update();
+        filter
+    }
+
+    pub fn with_table(mut self, table: CorrectionTable, frequency_hz: f64) -> Self {
+        self.table = table;
+        self.frequency_hz = frequency_hz;
+        self.update();
+        self
+    }
+
+    fn update(&mut self) {
+        let (gain_db, phase_deg) = self.table.at(self.frequency_hz);
+        let amplitude = 10f32.powf((self.gain_db + gain_db) / 20.0);
+        self.factor = Complex32::from_polar(amplitude, (self.phase_deg + phase_deg).to_radians());
+    }
+
+    pub fn set_gain(&mut self, gain_db: f32, phase_deg: f32) {
+        self.gain_db = gain_db;
+        self.phase_deg = phase_deg;
+        self.update();
+    }
+
+    /// Selects the table entry to apply, call it whenever the frontend is retuned.
+    pub fn set_frequency(&mut self, frequency_hz: f64) {
+        self.frequency_hz = frequency_hz;
+        self.update();
+    }
+
+    pub fn factor(&self) -> Complex32 {
+        self.factor
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for ComplexGainFilter {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        output.extend(input.iter().map(|x| x * self.factor));
+        Ok(())
+    }
+}
+
+
+impl RateAware for ComplexGainFilter {}
+
+
+/// Mismatched I and Q branches of a zero-IF receiver, the Q branch has `gain_db` extra gain and
+/// lags by `phase_deg`. Stands in for real hardware when checking `IqBalance`.
+pub struct IqImpairment {
+    gain: f32,
+    sin: f32,
+    cos: f32,
+}
+
+
+impl IqImpairment {
+    pub fn new(gain_db: f32, phase_deg: f32) -> Self {
+        let (sin, cos) = phase_deg.to_radians().sin_cos();
+        Self {
+            gain: 10f32.powf(gain_db / 20.0),
+            sin,
+            cos,
+        }
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for IqImpairment {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        output.extend(input.iter().map(|x| Complex32::new(x.re, self.gain * (x.im * self.cos - x.re * self.sin))));
+        Ok(())
+    }
+}
+
+
+impl RateAware for IqImpairment {}
+
+
+/// Blind I/Q gain and phase correction. Running averages of I², Q² and IQ drive a Gram-Schmidt
+/// step that makes Q orthogonal to I with the same power.
+pub struct IqBalance {
+    rate: f32,
+    ii: f32,
+    qq: f32,
+    iq: f32,
+    enabled: bool,
+}
+
+
+impl IqBalance {
+    /// `rate` is the averaging weight per sample, e.g. 1e-4.
+    pub fn new(rate: f32) -> Self {
+        Self {
+            rate,
+            ii: 0.0,
+            qq: 0.0,
+            iq: 0.0,
+            enabled: true,
+        }
+    }
+
+    /// When disabled samples pass unchanged, the estimates keep tracking.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Estimated Q gain relative to I in dB and the phase error in degrees.
+    pub fn estimate(&self) -> (f32, f32) {
+        if self.ii <= 0.0 || self.qq <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let sin = (self.iq / (self.ii * self.qq).sqrt()).clamp(-1.0, 1.0);
+        (10.0 * (self.qq / self.ii).log10(), -sin.asin().to_degrees())
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for IqBalance {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &x in input {
+            self.ii += self.rate * (x.re * x.re - self.ii);
+            self.qq += self.rate * (x.im * x.im - self.qq);
+            self.iq += self.rate * (x.re * x.im - self.iq);
+            if !self.enabled || self.ii <= 0.0 {
+                output.push(x);
+                continue;
+            }
+            let projection = self.iq / self.ii;
+            let orthogonal = self.qq - projection * self.iq;
+            let scale = if orthogonal > 0.0 { (self.ii / orthogonal).sqrt() } else { 1.0 };
+            output.push(Complex32::new(x.re, (x.im - projection * x.re) * scale));
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for IqBalance {}
+
+
+/// Image rejection through an RX chain, with the correction bypassed and applied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageRejection {
+    pub uncorrected_db: f32,
+    pub corrected_db: f32,
+}
+
+
+/// Injects a complex tone at `tone_hz` into `chain`, then compares the output at the tone and at
+/// its image, once as is and once through an `IqBalance`. The first half of `samples` lets the
+/// correction settle and is not measured.
+pub fn measure_image_rejection<F: Filter<Complex32, Complex32>>(chain: &mut F, sample_rate: u32, tone_hz: f32, samples: usize) -> Result<ImageRejection, Box<dyn Error>> {
+    let omega = 2.0 * std::f32::consts::PI * tone_hz / sample_rate as f32;
+    let tone: Vec<Complex32> = (0..samples).map(|n| Complex32::from_polar(0.5, (omega * n as f32) % (2.0 * std::f32::consts::PI))).collect();
+    let mut received = Vec::new();
+    chain.filter(&tone, &mut received)?;
+
+    let mut balance = IqBalance::new(1e-3);
+    let mut corrected = Vec::new();
+    balance.filter(&received, &mut corrected)?;
+
+    let settled = received.len() / 2;
+    Ok(ImageRejection {
+        uncorrected_db: rejection_db(&received[settled..], omega),
+        corrected_db: rejection_db(&corrected[settled..], omega),
+    })
+}
+
+
+/// Wanted over image power from single bin DFTs at `omega` and `-omega` radians per sample.
+fn rejection_db(samples: &[Complex32], omega: f32) -> f32 {
+    let bin = |omega: f32| samples.iter().enumerate()
+        .map(|(n, x)| x * Complex32::from_polar(1.0, -(omega * n as f32) % (2.0 * std::f32::consts::PI)))
+        .sum::<Complex32>()
+        .norm_sqr();
+    10.0 * (bin(omega) / bin(-omega).max(f32::MIN_POSITIVE)).log10()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_complex::Complex32;
+    use crate::frontend::{measure_image_rejection, ComplexGainFilter, CorrectionTable, GainCorrection, IqImpairment};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_complex_gain() -> Result<(), Box<dyn Error>> {
+        let mut table = CorrectionTable::new();
+        table.add(GainCorrection { frequency_hz: 144e6, gain_db: 0.0, phase_deg: 0.0 });
+        table.add(GainCorrection { frequency_hz: 146e6, gain_db: 6.0, phase_deg: 20.0 });
+        assert_eq!(table.at(145e6), (3.0, 10.0));
+        assert_eq!(table.at(150e6), (6.0, 20.0));
+
+        let path = std::env::temp_dir().join("rust_dsp_test_frontend.csv");
+        table.save(&path)?;
+        assert_eq!(CorrectionTable::load(&path)?, table);
+        std::fs::remove_file(&path)?;
+
+        let mut gain = ComplexGainFilter::new(-3.0, 80.0).with_table(table, 145e6);
+        let mut output = Vec::new();
+        gain.filter(&[Complex32::new(1.0, 0.0)], &mut output)?;
+        assert!((output[0].norm() - 1.0).abs() < 1e-5);
+        assert!((output[0].arg().to_degrees() - 90.0).abs() < 1e-3);
+
+        gain.set_frequency(144e6);
+        assert!((gain.factor().norm() - 10f32.powf(-3.0 / 20.0)).abs() < 1e-5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_image_rejection() -> Result<(), Box<dyn Error>> {
+        // 1 dB and 3 degrees of mismatch limits a zero-IF receiver to roughly 25 dB
+        let report = measure_image_rejection(&mut IqImpairment::new(1.0, 3.0), 48000, 3000.0, 48000)?;
+        assert!((report.uncorrected_db - 25.0).abs() < 3.0, "{:?}", report);
+        assert!(report.corrected_db > 50.0, "{:?}", report);
+
+        let report = measure_image_rejection(&mut ComplexGainFilter::new(0.0, 0.0), 48000, 3000.0, 48000)?;
+        assert!(report.uncorrected_db > 60.0, "{:?}", report);
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use num_complex::{Complex, Complex32};
+
+
+pub const HACKRF_AMP_DB: u32 = 14;
+pub const HACKRF_LNA_MAX: u32 = 40;
+pub const HACKRF_VGA_MAX: u32 = 62;
+
+
+/// Gain settings for the three HackRF receive stages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HackRFGain {
+    pub amp: bool,
+    /// IF gain, 0-40 dB in 8 dB steps.
+    pub lna: u32,
+    /// Baseband gain, 0-62 dB in 2 dB steps.
+    pub vga: u32,
+}
+
+
+impl HackRFGain {
+    pub const MAX_DB: u32 = HACKRF_AMP_DB + HACKRF_LNA_MAX + HACKRF_VGA_MAX;
+
+    pub fn total_db(&self) -> u32 {
+        let amp = if self.amp { HACKRF_AMP_DB } else { 0 };
+        amp + self.lna + self.vga
+    }
+
+    /// Spread `total_db` over the stages the way the HackRF docs suggest: raise the LNA first, then the VGA,
+    /// and only switch the RF amp on once both are well up, since it hurts strong signal handling the most.
+    /// The result is the nearest reachable setting at or below the target.
+    pub fn distribute(total_db: u32) -> Self {
+        // (stage, cap, step) in the order gain is added
+        const PLAN: [(usize, u32, u32); 5] = [
+            (1, 24, 8),
+            (2, 20, 2),
+            (1, HACKRF_LNA_MAX, 8),
+            (2, 40, 2),
+            (0, HACKRF_AMP_DB, HACKRF_AMP_DB),
+        ];
+
+        let mut stages = [0u32; 3];
+        let mut remaining = total_db.min(Self::MAX_DB);
+        for (stage, cap, step) in PLAN {
+            let add = (cap.saturating_sub(stages[stage]).min(remaining) / step) * step;
+            stages[stage] += add;
+            remaining -= add;
+        }
+        // whatever is left goes on the VGA
+        let add = (HACKRF_VGA_MAX.saturating_sub(stages[2]).min(remaining) / 2) * 2;
+        stages[2] += add;
+
+        Self {
+            amp: stages[0] > 0,
+            lna: stages[1],
+            vga: stages[2],
+        }
+    }
+}
+
+
+/// RMS level of raw samples relative to the 8 bit ADC full scale.
+pub fn adc_level_dbfs(samples: &[Complex32]) -> f32 {
+    if samples.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let power = samples.iter().map(|s| s.norm_sqr()).sum::<f32>() / samples.len() as f32;
+    10.0 * power.max(f32::MIN_POSITIVE).log10()
+}
+
+
+/// Raw ADC sample types with a known full scale.
+pub trait FullScale: Copy {
+    fn at_full_scale(self) -> bool;
+}
+
+
+impl FullScale for i8 {
+    fn at_full_scale(self) -> bool {
+        self <= -127 || self == i8::MAX
+    }
+}
+
+
+impl FullScale for i16 {
+    fn at_full_scale(self) -> bool {
+        self <= -i16::MAX || self == i16::MAX
+    }
+}
+
+
+/// Counts raw I/Q components sitting at ADC full scale, a sign the front end gain is too high.
+#[derive(Default)]
+pub struct ClipDetector {
+    clipped: u64,
+    samples: u64,
+}
+
+
+impl ClipDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the index of the first clipped sample and the number of clipped components in `samples`.
+    pub fn check<T: FullScale>(&mut self, samples: &[Complex<T>]) -> Option<(usize, usize)> {
+        let mut first = None;
+        let mut count = 0;
+        for (i, sample) in samples.iter().enumerate() {
+            let clipped = sample.re.at_full_scale() as usize + sample.im.at_full_scale() as usize;
+            if clipped > 0 && first.is_none() {
+                first = Some(i);
+            }
+            count += clipped;
+        }
+        self.clipped += count as u64;
+        self.samples += samples.len() as u64;
+        first.map(|i| (i, count))
+    }
+
+    /// Fraction of components clipped since the last reset.
+    pub fn ratio(&self) -> f64 {
+        if self.samples == 0 { 0.0 } else { self.clipped as f64 / (2 * self.samples) as f64 }
+    }
+
+    pub fn clipped(&self) -> u64 {
+        self.clipped
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+
+/// Slow AGC for the RF front end, meant to be called every second or so with the observed ADC loading.
+/// Only suggests a new setting when the level leaves the `target_dbfs +- hysteresis_db` window.
+pub struct FrontEndAGC {
+    target_dbfs: f32,
+    hysteresis_db: f32,
+    max_step_db: u32,
+    gain: HackRFGain,
+}
+
+
+impl FrontEndAGC {
+    pub fn new(target_dbfs: f32, hysteresis_db: f32, initial: HackRFGain) -> Self {
+        Self {
+            target_dbfs,
+            hysteresis_db,
+            max_step_db: 10,
+            gain: initial,
+        }
+    }
+
+    /// Largest change made by one update, keeps a burst from slamming the gain.
+    pub fn set_max_step(&mut self, max_step_db: u32) {
+        self.max_step_db = max_step_db;
+    }
+
+    pub fn gain(&self) -> HackRFGain {
+        self.gain
+    }
+
+    /// Returns the new setting to apply, if it changed.
+    pub fn update(&mut self, level_dbfs: f32) -> Option<HackRFGain> {
+        let error = self.target_dbfs - level_dbfs;
+        if error.abs() <= self.hysteresis_db || error.is_nan() {
+            return None;
+        }
+
+        let step = (error.abs().round() as u32).min(self.max_step_db) as i64 * error.signum() as i64;
+        let total = (self.gain.total_db() as i64 + step).clamp(0, HackRFGain::MAX_DB as i64) as u32;
+        let gain = HackRFGain::distribute(total);
+        if gain == self.gain {
+            return None;
+        }
+        self.gain = gain;
+        Some(gain)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::{Complex, Complex32};
+    use crate::gain::{adc_level_dbfs, ClipDetector, FrontEndAGC, HackRFGain};
+
+    #[test]
+    fn test_gain_distribution() {
+        assert_eq!(HackRFGain::distribute(0), HackRFGain { amp: false, lna: 0, vga: 0 });
+        assert_eq!(HackRFGain::distribute(32), HackRFGain { amp: false, lna: 24, vga: 8 });
+        assert_eq!(HackRFGain::distribute(60), HackRFGain { amp: false, lna: 40, vga: 20 });
+        assert_eq!(HackRFGain::distribute(94), HackRFGain { amp: true, lna: 40, vga: 40 });
+        assert_eq!(HackRFGain::distribute(500).total_db(), HackRFGain::MAX_DB);
+        for total in 0..=HackRFGain::MAX_DB {
+            let gain = HackRFGain::distribute(total);
+            assert!(gain.total_db() <= total && total - gain.total_db() < 8, "{} -> {:?}", total, gain);
+            assert!(gain.lna.is_multiple_of(8) && gain.vga.is_multiple_of(2));
+        }
+
+        let mut agc = FrontEndAGC::new(-20.0, 3.0, HackRFGain::distribute(60));
+        let loud: Vec<Complex32> = vec![Complex32::new(0.9, 0.0); 100];
+        let level = adc_level_dbfs(&loud);
+        let backed_off = agc.update(level).unwrap();
+        assert_eq!(backed_off.total_db(), 50);
+        assert_eq!(agc.update(-21.0), None);
+    }
+
+    #[test]
+    fn test_clip_detector() {
+        let mut detector = ClipDetector::new();
+        let quiet = vec![Complex::new(10i8, -20); 50];
+        assert_eq!(detector.check(&quiet), None);
+
+        let mut loud = quiet.clone();
+        loud[7] = Complex::new(127, -128);
+        loud[9].im = -127;
+        assert_eq!(detector.check(&loud), Some((7, 3)));
+        assert!((detector.ratio() - 3.0 / 200.0).abs() < 1e-9);
+
+        assert_eq!(detector.check(&[Complex::new(i16::MIN, 0i16)]), Some((0, 1)));
+    }
+
+}
@@ -0,0 +1,648 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use num_complex::{Complex, Complex32};
+use crate::replay::Recordable;
+use crate::sample::{decode_le, encode_le, Sample};
+use crate::tag::{Tag, TagValue, Tagged};
+use crate::traits::*;
+
+
+/// Size of the fixed part of a GNU Radio metadata header.
+pub const HEADER_SIZE: usize = 149;
+const METADATA_VERSION: i32 = 0;
+/// Extra dict entries are a few tags, anything beyond this is a corrupt `strt`.
+const MAX_EXTRA_SIZE: usize = 1 << 20;
+
+
+/// The PMT subset used by file_meta headers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pmt {
+    Bool(bool),
+    Symbol(String),
+    Int(i64),
+    UInt64(u64),
+    Double(f64),
+    Tuple(Vec<Pmt>),
+    Dict(Vec<(String, Pmt)>),
+}
+
+
+const PST_TRUE: u8 = 0x00;
+const PST_FALSE: u8 = 0x01;
+const PST_SYMBOL: u8 = 0x02;
+const PST_INT32: u8 = 0x03;
+const PST_DOUBLE: u8 = 0x04;
+const PST_NULL: u8 = 0x06;
+const PST_PAIR: u8 = 0x07;
+const PST_UINT64: u8 = 0x0b;
+const PST_TUPLE: u8 = 0x0c;
+const PST_INT64: u8 = 0x0d;
+
+
+impl Pmt {
+    pub fn serialize(&self, out: &mut Vec<u8>) {
+        match self {
+            Pmt::Bool(true) => out.push(PST_TRUE),
+            Pmt::Bool(false) => out.push(PST_FALSE),
+            Pmt::Symbol(s) => {
+                out.push(PST_SYMBOL);
+                out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+                out.extend_from_slice(s.as_bytes());
+            },
+            Pmt::Int(v) => match i32::try_from(*v) {
+                Ok(v) => {
+                    out.push(PST_INT32);
+                    out.extend_from_slice(&v.to_be_bytes());
+                },
+                Err(_) => {
+                    out.push(PST_INT64);
+                    out.extend_from_slice(&v.to_be_bytes());
+                },
+            },
+            Pmt::UInt64(v) => {
+                out.push(PST_UINT64);
+                out.extend_from_slice(&v.to_be_bytes());
+            },
+            Pmt::Double(v) => {
+                out.push(PST_DOUBLE);
+                out.extend_from_slice(&v.to_be_bytes());
+            },
+            Pmt::Tuple(items) => {
+                out.push(PST_TUPLE);
+                out.extend_from_slice(&(items.len() as u32).to_be_bytes());
+                for item in items {
+                    item.serialize(out);
+                }
+            },
+            // a dict is a list of (key . value) pairs, newest entry first like pmt::dict_add leaves it
+            Pmt::Dict(entries) => {
+                for (key, value) in entries.iter().rev() {
+                    out.push(PST_PAIR);
+                    out.push(PST_PAIR);
+                    Pmt::Symbol(key.clone()).serialize(out);
+                    value.serialize(out);
+                }
+                out.push(PST_NULL);
+            },
+        }
+    }
+
+    pub fn deserialize(src: &mut &[u8]) -> Result<Pmt, Box<dyn Error>> {
+        fn take<'a>(src: &mut &'a [u8], n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+            if src.len() < n {
+                return Err("truncated pmt".into());
+            }
+            let (head, tail) = src.split_at(n);
+            *src = tail;
+            Ok(head)
+        }
+
+        let tag = take(src, 1)?[0];
+        Ok(match tag {
+            PST_TRUE => Pmt::Bool(true),
+            PST_FALSE => Pmt::Bool(false),
+            PST_SYMBOL => {
+                let len = u16::from_be_bytes(take(src, 2)?.try_into()?) as usize;
+                Pmt::Symbol(String::from_utf8(take(src, len)?.to_vec())?)
+            },
+            PST_INT32 => Pmt::Int(i32::from_be_bytes(take(src, 4)?.try_into()?) as i64),
+            PST_INT64 => Pmt::Int(i64::from_be_bytes(take(src, 8)?.try_into()?)),
+            PST_UINT64 => Pmt::UInt64(u64::from_be_bytes(take(src, 8)?.try_into()?)),
+            PST_DOUBLE => Pmt::Double(f64::from_be_bytes(take(src, 8)?.try_into()?)),
+            PST_TUPLE => {
+                let len = u32::from_be_bytes(take(src, 4)?.try_into()?) as usize;
+                Pmt::Tuple((0..len).map(|_| Pmt::deserialize(src)).collect::<Result<_, _>>()?)
+            },
+            PST_NULL => Pmt::Dict(Vec::new()),
+            PST_PAIR => {
+                let mut entries = Vec::new();
+                let mut tag = PST_PAIR;
+                while tag == PST_PAIR {
+                    if take(src, 1)?[0] != PST_PAIR {
+                        return Err("only dicts of pairs are supported".into());
+                    }
+                    let Pmt::Symbol(key) = Pmt::deserialize(src)? else {
+                        return Err("dict key is not a symbol".into());
+                    };
+                    entries.push((key, Pmt::deserialize(src)?));
+                    tag = take(src, 1)?[0];
+                }
+                if tag != PST_NULL {
+                    return Err(format!("unexpected pmt tag {:#x} at end of dict", tag).into());
+                }
+                entries.reverse();
+                Pmt::Dict(entries)
+            },
+            _ => return Err(format!("unsupported pmt tag {:#x}", tag).into()),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Pmt> {
+        match self {
+            Pmt::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Pmt::Double(v) => Some(*v),
+            Pmt::Int(v) => Some(*v as f64),
+            Pmt::UInt64(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Pmt::Int(v) => u64::try_from(*v).ok(),
+            Pmt::UInt64(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+
+/// Item types matching GNU Radio's `gr_file_types`.
+pub trait GrItem: Recordable {
+    const TYPE: i64;
+    const COMPLEX: bool;
+}
+
+
+impl GrItem for i8 {
+    const TYPE: i64 = 0;
+    const COMPLEX: bool = false;
+}
+
+
+impl GrItem for i16 {
+    const TYPE: i64 = 1;
+    const COMPLEX: bool = false;
+}
+
+
+impl GrItem for i32 {
+    const TYPE: i64 = 2;
+    const COMPLEX: bool = false;
+}
+
+
+impl GrItem for f32 {
+    const TYPE: i64 = 5;
+    const COMPLEX: bool = false;
+}
+
+
+impl GrItem for f64 {
+    const TYPE: i64 = 6;
+    const COMPLEX: bool = false;
+}
+
+
+impl<T: GrItem> GrItem for Complex<T> {
+    const TYPE: i64 = T::TYPE;
+    const COMPLEX: bool = true;
+}
+
+
+/// Stream parameters stored in each segment header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub sample_rate: f64,
+    pub time: SystemTime,
+    pub item_size: usize,
+    pub item_type: i64,
+    pub complex: bool,
+    /// Data bytes in the segment.
+    pub bytes: u64,
+    /// Entries of the extra dict, e.g. `rx_freq`.
+    pub extra: Vec<(String, Pmt)>,
+}
+
+
+impl Segment {
+    pub fn frequency(&self) -> Option<f64> {
+        self.extra.iter().find(|(k, _)| k == "rx_freq").and_then(|(_, v)| v.as_f64())
+    }
+
+    fn rx_time(&self) -> Pmt {
+        let since = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Pmt::Tuple(vec![Pmt::UInt64(since.as_secs()), Pmt::Double(since.subsec_nanos() as f64 * 1e-9)])
+    }
+
+    fn extra_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        Pmt::Dict(self.extra.clone()).serialize(&mut out);
+        out
+    }
+
+    /// Header and extra dict, ready to write in front of the segment data.
+    pub fn serialize(&self) -> Vec<u8> {
+        let extra = self.extra_bytes();
+        let header = Pmt::Dict(vec![
+            ("version".into(), Pmt::Int(METADATA_VERSION as i64)),
+            ("rx_rate".into(), Pmt::Double(self.sample_rate)),
+            ("rx_time".into(), self.rx_time()),
+            ("size".into(), Pmt::Int(self.item_size as i64)),
+            ("type".into(), Pmt::Int(self.item_type)),
+            ("cplx".into(), Pmt::Bool(self.complex)),
+            ("strt".into(), Pmt::UInt64((HEADER_SIZE + extra.len()) as u64)),
+            ("bytes".into(), Pmt::UInt64(self.bytes)),
+        ]);
+        let mut out = Vec::with_capacity(HEADER_SIZE + extra.len());
+        header.serialize(&mut out);
+        debug_assert_eq!(out.len(), HEADER_SIZE);
+        out.extend_from_slice(&extra);
+        out
+    }
+
+    /// Parse the fixed header, returning the segment and how many extra dict bytes follow it.
+    fn parse_header(header: &[u8]) -> Result<(Segment, usize), Box<dyn Error>> {
+        let mut src = header;
+        let dict = Pmt::deserialize(&mut src)?;
+        let field = |key: &str| dict.get(key).ok_or_else(|| format!("metadata header is missing {}", key));
+
+        let time = match field("rx_time")? {
+            Pmt::Tuple(parts) if parts.len() == 2 => {
+                let secs = parts[0].as_u64().ok_or("bad rx_time")?;
+                let frac = parts[1].as_f64().ok_or("bad rx_time")?;
+                let frac = Duration::try_from_secs_f64(frac.clamp(0.0, 1.0)).map_err(|_| "bad rx_time")?;
+                UNIX_EPOCH.checked_add(Duration::from_secs(secs)).and_then(|t| t.checked_add(frac)).ok_or("bad rx_time")?
+            },
+            _ => return Err("bad rx_time".into()),
+        };
+        let start = field("strt")?.as_u64().ok_or("bad strt")? as usize;
+        if start < HEADER_SIZE || start - HEADER_SIZE > MAX_EXTRA_SIZE {
+            return Err("bad strt".into());
+        }
+        let segment = Segment {
+            sample_rate: field("rx_rate")?.as_f64().ok_or("bad rx_rate")?,
+            time,
+            item_size: field("size")?.as_u64().ok_or("bad size")? as usize,
+            item_type: field("type")?.as_u64().ok_or("bad type")? as i64,
+            complex: matches!(field("cplx")?, Pmt::Bool(true)),
+            bytes: field("bytes")?.as_u64().ok_or("bad bytes")?,
+            extra: Vec::new(),
+        };
+        Ok((segment, start - HEADER_SIZE))
+    }
+}
+
+
+fn check_sample_rate(sample_rate: f64) -> Result<(), Box<dyn Error>> {
+    if !sample_rate.is_finite() || sample_rate <= 0.0 {
+        return Err(format!("invalid sample rate {}", sample_rate).into());
+    }
+    Ok(())
+}
+
+
+/// Writes GNU Radio file_meta_sink compatible files with attached headers. A new segment starts
+/// every `max_segment_items` items or when `new_segment` is called, e.g. after a retune.
+pub struct GrMetaSink<W: Write + Seek> {
+    writer: W,
+    segment: Segment,
+    header_position: u64,
+    max_segment_items: u64,
+    items: u64,
+    scratch: Vec<u8>,
+}
+
+
+impl GrMetaSink<BufWriter<File>> {
+    pub fn create<T: GrItem>(path: PathBuf, sample_rate: f64, frequency: Option<f64>, time: SystemTime) -> Result<Self, Box<dyn Error>> {
+        Self::new::<T>(BufWriter::new(File::create(path)?), sample_rate, frequency, time)
+    }
+}
+
+
+impl<W: Write + Seek> GrMetaSink<W> {
+    pub fn new<T: GrItem>(mut writer: W, sample_rate: f64, frequency: Option<f64>, time: SystemTime) -> Result<Self, Box<dyn Error>> {
+        check_sample_rate(sample_rate)?;
+        let segment = Segment {
+            sample_rate,
+            time,
+            item_size: T::SIZE,
+            item_type: T::TYPE,
+            complex: T::COMPLEX,
+            bytes: 0,
+            extra: frequency.map(|f| vec![("rx_freq".to_string(), Pmt::Double(f))]).unwrap_or_default(),
+        };
+        let header_position = writer.stream_position()?;
+        writer.write_all(&segment.serialize())?;
+        Ok(Self {
+            writer,
+            segment,
+            header_position,
+            max_segment_items: 1_000_000,
+            items: 0,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub fn set_max_segment_items(&mut self, items: u64) {
+        self.max_segment_items = items.max(1);
+    }
+
+    /// Close the current segment and start one with new parameters.
+    pub fn new_segment(&mut self, sample_rate: f64, frequency: Option<f64>, time: SystemTime) -> Result<(), Box<dyn Error>> {
+        check_sample_rate(sample_rate)?;
+        self.finish_segment()?;
+        self.segment.sample_rate = sample_rate;
+        self.segment.time = time;
+        self.segment.bytes = 0;
+        self.items = 0;
+        self.segment.extra.retain(|(k, _)| k != "rx_freq");
+        if let Some(f) = frequency {
+            self.segment.extra.push(("rx_freq".to_string(), Pmt::Double(f)));
+        }
+        self.start_segment()
+    }
+
+    fn start_segment(&mut self) -> Result<(), Box<dyn Error>> {
+        self.header_position = self.writer.stream_position()?;
+        self.writer.write_all(&self.segment.serialize())?;
+        Ok(())
+    }
+
+    /// Rewrite the current header with the final byte count.
+    fn finish_segment(&mut self) -> Result<(), Box<dyn Error>> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(self.header_position))?;
+        self.writer.write_all(&self.segment.serialize())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+
+    /// Write the final byte count of the current segment, also done on drop.
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.finish_segment()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Converts to the stored item type `T` on the way out.
+    fn write_samples<T: GrItem + Sample>(&mut self, src: &[T::Float]) -> Result<(), Box<dyn Error>> {
+        if T::SIZE != self.segment.item_size || T::COMPLEX != self.segment.complex {
+            return Err("item type doesn't match the metadata header".into());
+        }
+        let mut off = 0;
+        while off < src.len() {
+            if self.items == self.max_segment_items {
+                let duration = Duration::from_secs_f64(self.items as f64 / self.segment.sample_rate);
+                let (rate, frequency, time) = (self.segment.sample_rate, self.segment.frequency(), self.segment.time + duration);
+                self.new_segment(rate, frequency, time)?;
+            }
+            let n = ((self.max_segment_items - self.items) as usize).min(src.len() - off);
+            self.scratch.clear();
+            encode_le::<T>(&src[off..off + n], &mut self.scratch);
+            self.writer.write_all(&self.scratch)?;
+            self.segment.bytes += self.scratch.len() as u64;
+            self.items += n as u64;
+            off += n;
+        }
+        Ok(())
+    }
+}
+
+
+impl<W: Write + Seek> Drop for GrMetaSink<W> {
+    fn drop(&mut self) {
+        let _ = self.finish_segment();
+        let _ = self.writer.flush();
+    }
+}
+
+
+/// Files created for `Complex<i8>` or `Complex<i16>` items are scaled from full scale ±1.
+impl<W: Write + Seek> Sink<Complex32> for GrMetaSink<W> {
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        match self.segment.item_type {
+            0 => self.write_samples::<Complex<i8>>(src),
+            1 => self.write_samples::<Complex<i16>>(src),
+            _ => self.write_samples::<Complex32>(src),
+        }
+    }
+}
+
+
+impl<W: Write + Seek> Sink<f32> for GrMetaSink<W> {
+    fn write(&mut self, src: &[f32]) -> Result<(), Box<dyn Error>> {
+        match self.segment.item_type {
+            0 => self.write_samples::<i8>(src),
+            1 => self.write_samples::<i16>(src),
+            _ => self.write_samples::<f32>(src),
+        }
+    }
+}
+
+
+/// Reads files written by GNU Radio's file_meta_sink with attached headers. Each segment start is tagged
+/// with its `rx_time`, and `segment` describes the one currently being read.
+pub struct GrMetaSource<R: Read> {
+    reader: R,
+    segment: Option<Segment>,
+    remaining: u64,
+    items_per_read: usize,
+    position: u64,
+    tags: Vec<Tag>,
+    scratch: Vec<u8>,
+}
+
+
+impl GrMetaSource<BufReader<File>> {
+    pub fn open(path: PathBuf, items_per_read: usize) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(BufReader::new(File::open(path)?), items_per_read))
+    }
+}
+
+
+impl<R: Read> GrMetaSource<R> {
+    pub fn new(reader: R, items_per_read: usize) -> Self {
+        Self {
+            reader,
+            segment: None,
+            remaining: 0,
+            items_per_read: items_per_read.max(1),
+            position: 0,
+            tags: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn segment(&self) -> Option<&Segment> {
+        self.segment.as_ref()
+    }
+
+    /// Advance to the next segment with a whole item left, false at the end of the file.
+    fn next_segment(&mut self) -> Result<bool, Box<dyn Error>> {
+        while self.remaining < self.segment.as_ref().map_or(1, |s| s.item_size.max(1)) as u64 {
+            // a truncated last item can't be decoded, skip it and carry on with the next segment
+            if self.remaining > 0 {
+                let skipped = io::copy(&mut (&mut self.reader).take(self.remaining), &mut io::sink())?;
+                if skipped < self.remaining {
+                    return Ok(false);
+                }
+                self.remaining = 0;
+            }
+            let mut header = [0u8; HEADER_SIZE];
+            match self.reader.read_exact(&mut header) {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+                result => result?,
+            }
+            let (mut segment, extra_len) = Segment::parse_header(&header)?;
+            let mut extra = vec![0u8; extra_len];
+            self.reader.read_exact(&mut extra)?;
+            if let Pmt::Dict(entries) = Pmt::deserialize(&mut extra.as_slice())? {
+                segment.extra = entries;
+            }
+            self.tags.push(Tag { offset: self.position, value: TagValue::Timestamp(segment.time) });
+            self.remaining = segment.bytes;
+            self.segment = Some(segment);
+        }
+        Ok(true)
+    }
+
+    /// Item type of the segment to read next, None at the end of the file.
+    fn item_type(&mut self) -> Result<Option<i64>, Box<dyn Error>> {
+        Ok(if self.next_segment()? { self.segment.as_ref().map(|s| s.item_type) } else { None })
+    }
+
+    fn read_samples<T: GrItem + Sample>(&mut self, dst: &mut Vec<T::Float>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        if !self.next_segment()? {
+            return Ok(());
+        }
+        let segment = self.segment.as_ref().unwrap();
+        if segment.item_size != T::SIZE || segment.complex != T::COMPLEX || segment.item_type != T::TYPE {
+            return Err(format!("file holds type {} size {} complex {}, which doesn't match the requested items", segment.item_type, segment.item_size, segment.complex).into());
+        }
+
+        let n = (self.remaining / T::SIZE as u64).min(self.items_per_read as u64) as usize;
+        self.scratch.resize(n * T::SIZE, 0);
+        self.reader.read_exact(&mut self.scratch)?;
+        decode_le::<T>(&self.scratch, dst);
+        self.remaining -= (n * T::SIZE) as u64;
+        self.position += n as u64;
+        Ok(())
+    }
+}
+
+
+/// Byte and short items are scaled to full scale ±1.
+impl<R: Read> Source<Complex32> for GrMetaSource<R> {
+    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        match self.item_type()? {
+            Some(0) => self.read_samples::<Complex<i8>>(dst),
+            Some(1) => self.read_samples::<Complex<i16>>(dst),
+            _ => self.read_samples::<Complex32>(dst),
+        }
+    }
+}
+
+
+impl<R: Read> Source<f32> for GrMetaSource<R> {
+    fn read(&mut self, dst: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        match self.item_type()? {
+            Some(0) => self.read_samples::<i8>(dst),
+            Some(1) => self.read_samples::<i16>(dst),
+            _ => self.read_samples::<f32>(dst),
+        }
+    }
+}
+
+
+impl<R: Read> Tagged for GrMetaSource<R> {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        dst.append(&mut self.tags);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+    use num_complex::Complex32;
+    use crate::gnuradio::{GrMetaSink, GrMetaSource, Segment, HEADER_SIZE};
+    use crate::tag::{Tag, TagValue, Tagged};
+    use crate::traits::{Sink, Source};
+
+    #[test]
+    fn test_file_meta_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let mut sink = GrMetaSink::new::<Complex32>(Cursor::new(Vec::new()), 1e6, Some(433.92e6), start)?;
+        sink.set_max_segment_items(1000);
+        let samples: Vec<Complex32> = (0..2500).map(|n| Complex32::new(n as f32, -(n as f32))).collect();
+        sink.write(&samples)?;
+        sink.finish()?;
+        let file = sink.get_ref().get_ref().clone();
+        assert!(sink.new_segment(0.0, None, start).is_err());
+
+        // the header layout is fixed, the first one starts with the bytes entry as gnu radio writes it
+        let segment = Segment::parse_header(&file[..HEADER_SIZE])?.0;
+        assert_eq!(segment.bytes, 8000);
+        assert_eq!(&file[..11], b"\x07\x07\x02\x00\x05bytes\x0b");
+
+        let mut source = GrMetaSource::new(Cursor::new(file), 4096);
+        let mut read = Vec::new();
+        let mut buffer: Vec<Complex32> = Vec::new();
+        loop {
+            source.read(&mut buffer)?;
+            if buffer.is_empty() {
+                break;
+            }
+            read.extend_from_slice(&buffer);
+        }
+        assert_eq!(read, samples);
+        assert_eq!(source.segment().unwrap().frequency(), Some(433.92e6));
+
+        let mut tags = Vec::new();
+        source.take_tags(&mut tags);
+        let offsets: Vec<u64> = tags.iter().map(|t| t.offset).collect();
+        assert_eq!(offsets, [0, 1000, 2000]);
+        let Tag { value: TagValue::Timestamp(time), .. } = tags[2].clone() else { panic!() };
+        let error = time.duration_since(start + Duration::from_millis(2)).or(start.duration_since(time))?;
+        assert!(error < Duration::from_micros(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_meta_partial_item() -> Result<(), Box<dyn std::error::Error>> {
+        let mut sink = GrMetaSink::new::<Complex32>(Cursor::new(Vec::new()), 1e6, None, UNIX_EPOCH)?;
+        let samples: Vec<Complex32> = (0..10).map(|n| Complex32::new(n as f32, 0.0)).collect();
+        sink.write(&samples)?;
+        sink.finish()?;
+        let file = sink.get_ref().get_ref().clone();
+
+        // a segment ending in half an item is followed by a good one
+        let (mut segment, extra_len) = Segment::parse_header(&file[..HEADER_SIZE])?;
+        segment.bytes += 3;
+        let mut corrupt = segment.serialize();
+        corrupt.extend_from_slice(&file[HEADER_SIZE + extra_len..]);
+        corrupt.extend_from_slice(&[0u8; 3]);
+        corrupt.extend_from_slice(&file);
+
+        let mut source = GrMetaSource::new(Cursor::new(corrupt), 4096);
+        let mut read = Vec::new();
+        let mut buffer: Vec<Complex32> = Vec::new();
+        loop {
+            source.read(&mut buffer)?;
+            if buffer.is_empty() {
+                break;
+            }
+            read.extend_from_slice(&buffer);
+        }
+        assert_eq!(read, [samples.clone(), samples].concat());
+        Ok(())
+    }
+
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use libhackrf::HackRf;
+use num_complex::Complex32;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use crate::block::*;
+use crate::error::ConfigError;
+use crate::flowgraph::Filtered;
+use crate::pipeline::{CancelToken, RunStats, Runner};
+use crate::playlist::PlaylistSource;
+use crate::rate::{check_chain, RateAware};
+use crate::traits::{Filter, Sink, Source};
+
+
+/// One block of a graph file: a name to connect it by, the registry type and its parameters.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+}
+
+
+/// A receiver described in a file instead of code:
+///
+/// ```text
+/// blocks:
+///   - { name: radio, type: hackrf, params: { frequency: 100300000, sample_rate: 2000000 } }
+///   - { name: fm, type: fm_demod, params: { sample_rate: 2000000, deviation: 75000 } }
+///   - { name: audio, type: rational_resampler, params: { input_rate: 2000000, output_rate: 48000 } }
+///   - { name: out, type: speakers, params: { sample_rate: 48000 } }
+/// connections: [[radio, fm], [fm, audio], [audio, out]]
+/// ```
+///
+/// The connections have to make one chain from a source to a sink.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GraphSpec {
+    pub blocks: Vec<BlockSpec>,
+    pub connections: Vec<[String; 2]>,
+}
+
+
+impl GraphSpec {
+    pub fn from_json(text: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(text: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_yaml::from_str(text)?)
+    }
+
+    #[cfg(not(feature = "yaml"))]
+    pub fn from_yaml(_text: &str) -> Result<Self, Box<dyn Error>> {
+        Err("built without the yaml feature".into())
+    }
+
+    /// YAML for `.yaml` and `.yml` files, JSON otherwise.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&text),
+            _ => Self::from_json(&text),
+        }
+    }
+
+    /// Block indices from the source to the sink.
+    fn order(&self) -> Result<Vec<usize>, ConfigError> {
+        let index = |name: &str| self.blocks.iter().position(|block| block.name == name)
+            .ok_or_else(|| ConfigError::Invalid(format!("connection to unknown block {:?}", name)));
+        let mut next = vec![None; self.blocks.len()];
+        let mut has_input = vec![false; self.blocks.len()];
+        for [from, to] in &self.connections {
+            let (from, to) = (index(from)?, index(to)?);
+            if next[from].replace(to).is_some() || std::mem::replace(&mut has_input[to], true) {
+                return Err(ConfigError::Invalid(format!("{} and {} aren't a single chain, use a Tee block in code", self.blocks[from].name, self.blocks[to].name)));
+            }
+        }
+        let mut sources = (0..self.blocks.len()).filter(|&i| !has_input[i]);
+        let (Some(source), None) = (sources.next(), sources.next()) else {
+            return Err(ConfigError::Invalid("a graph needs exactly one block without an input".into()));
+        };
+        let mut order = vec![source];
+        while let Some(to) = next[*order.last().unwrap()] {
+            if order.contains(&to) {
+                return Err(ConfigError::Invalid(format!("loop through {}", self.blocks[to].name)));
+            }
+            order.push(to);
+        }
+        if order.len() != self.blocks.len() {
+            return Err(ConfigError::Invalid("some blocks aren't connected".into()));
+        }
+        Ok(order)
+    }
+}
+
+
+/// What flows over a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleKind {
+    Real,
+    Complex,
+}
+
+
+pub trait GraphSource<T>: Source<T> + RateAware {}
+impl<T, S: Source<T> + RateAware> GraphSource<T> for S {}
+pub trait GraphFilter<I, O>: Filter<I, O> + RateAware {}
+impl<I, O, F: Filter<I, O> + RateAware> GraphFilter<I, O> for F {}
+pub trait GraphSink<T>: Sink<T> + RateAware {}
+impl<T, S: Sink<T> + RateAware> GraphSink<T> for S {}
+
+
+/// A block made by a `Registry`, by the samples it takes and produces.
+pub enum GraphBlock {
+    RealSource(Box<dyn GraphSource<f32>>),
+    ComplexSource(Box<dyn GraphSource<Complex32>>),
+    RealFilter(Box<dyn GraphFilter<f32, f32>>),
+    ComplexFilter(Box<dyn GraphFilter<Complex32, Complex32>>),
+    RealToComplex(Box<dyn GraphFilter<f32, Complex32>>),
+    ComplexToReal(Box<dyn GraphFilter<Complex32, f32>>),
+    RealSink(Box<dyn GraphSink<f32>>),
+    ComplexSink(Box<dyn GraphSink<Complex32>>),
+}
+
+
+impl GraphBlock {
+    pub fn input(&self) -> Option<SampleKind> {
+        match self {
+            Self::RealSource(_) | Self::ComplexSource(_) => None,
+            Self::RealFilter(_) | Self::RealToComplex(_) | Self::RealSink(_) => Some(SampleKind::Real),
+            Self::ComplexFilter(_) | Self::ComplexToReal(_) | Self::ComplexSink(_) => Some(SampleKind::Complex),
+        }
+    }
+
+    pub fn output(&self) -> Option<SampleKind> {
+        match self {
+            Self::RealSource(_) | Self::RealFilter(_) | Self::ComplexToReal(_) => Some(SampleKind::Real),
+            Self::ComplexSource(_) | Self::ComplexFilter(_) | Self::RealToComplex(_) => Some(SampleKind::Complex),
+            Self::RealSink(_) | Self::ComplexSink(_) => None,
+        }
+    }
+
+    fn rate_aware(&self) -> &dyn RateAware {
+        match self {
+            Self::RealSource(block) => block,
+            Self::ComplexSource(block) => block,
+            Self::RealFilter(block) => block,
+            Self::ComplexFilter(block) => block,
+            Self::RealToComplex(block) => block,
+            Self::ComplexToReal(block) => block,
+            Self::RealSink(block) => block,
+            Self::ComplexSink(block) => block,
+        }
+    }
+}
+
+
+/// The parameters of one block for its constructor.
+pub struct Params<'a> {
+    name: &'a str,
+    values: &'a Map<String, Value>,
+    input: Option<SampleKind>,
+}
+
+
+impl Params<'_> {
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// What the block before it produces, None for sources.
+    pub fn input(&self) -> Option<SampleKind> {
+        self.input
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<T, ConfigError> {
+        let value = self.values.get(key).ok_or_else(|| ConfigError::Invalid(format!("{} is missing {}", self.name, key)))?;
+        serde_json::from_value(value.clone()).map_err(|e| ConfigError::Invalid(format!("{}.{}: {}", self.name, key, e)))
+    }
+
+    pub fn get_or<T: DeserializeOwned>(&self, key: &str, default: T) -> Result<T, ConfigError> {
+        match self.values.contains_key(key) {
+            true => self.get(key),
+            false => Ok(default),
+        }
+    }
+}
+
+
+pub type Constructor = Box<dyn Fn(&Params) -> Result<GraphBlock, Box<dyn Error>>>;
+
+
+/// Block types a graph file can name.
+pub struct Registry {
+    constructors: HashMap<String, Constructor>,
+}
+
+
+impl Registry {
+    /// No block types, see `Default` for the built in ones.
+    pub fn empty() -> Self {
+        Self {
+            constructors: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, kind: &str, constructor: impl Fn(&Params) -> Result<GraphBlock, Box<dyn Error>> + 'static) {
+        self.constructors.insert(kind.to_string(), Box::new(constructor));
+    }
+
+    /// Makes the blocks of `spec` in chain order, checking sample types and rates.
+    pub fn build(&self, spec: &GraphSpec) -> Result<Graph, Box<dyn Error>> {
+        let mut blocks = Vec::new();
+        let mut input = None;
+        for index in spec.order()? {
+            let block_spec = &spec.blocks[index];
+            let constructor = self.constructors.get(&block_spec.kind)
+                .ok_or_else(|| format!("{}: unknown block type {:?}", block_spec.name, block_spec.kind))?;
+            let block = constructor(&Params { name: &block_spec.name, values: &block_spec.params, input })
+                .map_err(|e| format!("{}: {}", block_spec.name, e))?;
+            if block.input() != input {
+                return Err(format!("{} takes {:?} samples but gets {:?}", block_spec.name, block.input(), input).into());
+            }
+            input = block.output();
+            blocks.push((block_spec.name.clone(), block));
+        }
+        let rates: Vec<&dyn RateAware> = blocks.iter().map(|(_, block)| block.rate_aware()).collect();
+        check_chain(&rates).map_err(|mismatch| format!("{}: {}", blocks[mismatch.index].0, mismatch))?;
+        if input.is_some() {
+            return Err(format!("{} isn't a sink", blocks.last().unwrap().0).into());
+        }
+        Graph::new(blocks)
+    }
+}
+
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        let path = |params: &Params| -> Result<PathBuf, ConfigError> { Ok(PathBuf::from(params.get::<String>("path")?)) };
+
+        registry.register("null_source", |params| {
+            let source = NullSource::new(params.get("sample_rate")?, params.get_or("samples_per_read", 4096)?);
+            Ok(match params.get_or::<Option<u64>>("samples", None)? {
+                Some(samples) => GraphBlock::RealSource(Box::new(source.limit(samples))),
+                None => GraphBlock::RealSource(Box::new(source)),
+            })
+        });
+        registry.register("wav_source", move |params| {
+            let mut source = WavSource::new(path(params)?, params.get_or("samples_per_read", 0)?)?;
+            if let Some(rate) = params.get_or::<Option<u32>>("resample_to", None)? {
+                source = source.resample_to(rate)?;
+            }
+            Ok(match source.spec().channels {
+                1 => GraphBlock::RealSource(Box::new(source)),
+                _ => GraphBlock::ComplexSource(Box::new(source)),
+            })
+        });
+        registry.register("playlist", |params| {
+            let mut source = PlaylistSource::glob(&params.get::<String>("pattern")?, params.get_or("sample_rate", None)?)?;
+            if let Some(samples) = params.get_or::<Option<usize>>("samples_per_read", None)? {
+                source.set_samples_per_read(samples);
+            }
+            Ok(match source.is_complex() {
+                true => GraphBlock::ComplexSource(Box::new(source)),
+                false => GraphBlock::RealSource(Box::new(source)),
+            })
+        });
+        registry.register("hackrf", |params| {
+            let source = HackRFSourceBuilder::new(params.get("frequency")?, params.get("sample_rate")?)
+                .lna_gain(params.get_or("lna_gain", 16)?)
+                .vga_gain(params.get_or("vga_gain", 16)?)
+                .amp(params.get_or("amp", false)?)
+                .build(HackRf::open()?)?;
+            Ok(GraphBlock::ComplexSource(Box::new(source)))
+        });
+        registry.register("mixer", |params| {
+            let mixer = MixerFilter::new(params.get("sample_rate")?, params.get("shift_hz")?);
+            Ok(match params.input() {
+                Some(SampleKind::Real) => GraphBlock::RealToComplex(Box::new(mixer)),
+                _ => GraphBlock::ComplexFilter(Box::new(mixer)),
+            })
+        });
+        registry.register("rational_resampler", |params| {
+            let builder = RationalResamplerBuilder::new(params.get("input_rate")?, params.get("output_rate")?)
+                .num_taps(params.get_or("taps", 1001)?);
+            Ok(match params.input() {
+                Some(SampleKind::Real) => GraphBlock::RealFilter(Box::new(builder.build::<f32>()?)),
+                _ => GraphBlock::ComplexFilter(Box::new(builder.build::<Complex32>()?)),
+            })
+        });
+        registry.register("fractional_resampler", |params| {
+            let (start, end) = (params.get("input_rate")?, params.get("output_rate")?);
+            Ok(match params.input() {
+                Some(SampleKind::Real) => GraphBlock::RealFilter(Box::new(FractionalResampler::<f32>::new(start, end))),
+                _ => GraphBlock::ComplexFilter(Box::new(FractionalResampler::<Complex32>::new(start, end))),
+            })
+        });
+        registry.register("fm_demod", |params| {
+            let demod = FMDemodBuilder::new(params.get("sample_rate")?, params.get_or("deviation", 75e3)?).build()?;
+            Ok(GraphBlock::ComplexToReal(Box::new(demod)))
+        });
+        registry.register("fm_mod", |params| {
+            let modulator = FMModBuilder::new(params.get("sample_rate")?, params.get_or("deviation", 75e3)?).build()?;
+            Ok(GraphBlock::RealToComplex(Box::new(modulator)))
+        });
+        registry.register("deemphasis", |params| {
+            let filter = DeEmphasisFilter::new(params.get("sample_rate")?, params.get_or("tau", 75e-6)?);
+            Ok(GraphBlock::RealFilter(Box::new(filter)))
+        });
+        registry.register("null_sink", |params| Ok(match params.input() {
+            Some(SampleKind::Real) => GraphBlock::RealSink(Box::new(NullSink::new())),
+            _ => GraphBlock::ComplexSink(Box::new(NullSink::new())),
+        }));
+        registry.register("wav_sink", move |params| {
+            let sample_rate = params.get("sample_rate")?;
+            Ok(match params.input() {
+                Some(SampleKind::Real) => GraphBlock::RealSink(Box::new(WavSink::new_file(sample_rate, 1, path(params)?)?)),
+                _ => GraphBlock::ComplexSink(Box::new(WavSink::new_file(sample_rate, 2, path(params)?)?)),
+            })
+        });
+        registry.register("speakers", |params| {
+            let device = params.get_or::<Option<String>>("device", None)?;
+            Ok(GraphBlock::RealSink(Box::new(Speakers::with_device(params.get("sample_rate")?, 1, device.as_deref())?)))
+        });
+        registry
+    }
+}
+
+
+enum Stream {
+    Real(Box<dyn GraphSource<f32>>),
+    Complex(Box<dyn GraphSource<Complex32>>),
+}
+
+
+enum Output {
+    Real(Box<dyn GraphSink<f32>>),
+    Complex(Box<dyn GraphSink<Complex32>>),
+}
+
+
+/// The blocks of a `GraphSpec`, chained and ready to run.
+pub struct Graph {
+    names: Vec<String>,
+    stream: Stream,
+    sink: Output,
+}
+
+
+impl Graph {
+    fn new(blocks: Vec<(String, GraphBlock)>) -> Result<Self, Box<dyn Error>> {
+        let names = blocks.iter().map(|(name, _)| name.clone()).collect();
+        let mut blocks = blocks.into_iter().map(|(_, block)| block);
+        let mut stream = match blocks.next() {
+            Some(GraphBlock::RealSource(source)) => Stream::Real(source),
+            Some(GraphBlock::ComplexSource(source)) => Stream::Complex(source),
+            _ => return Err("a graph starts with a source".into()),
+        };
+        for block in blocks {
+            stream = match (stream, block) {
+                (Stream::Real(source), GraphBlock::RealFilter(filter)) => Stream::Real(Box::new(Filtered::new(source, filter))),
+                (Stream::Real(source), GraphBlock::RealToComplex(filter)) => Stream::Complex(Box::new(Filtered::new(source, filter))),
+                (Stream::Complex(source), GraphBlock::ComplexFilter(filter)) => Stream::Complex(Box::new(Filtered::new(source, filter))),
+                (Stream::Complex(source), GraphBlock::ComplexToReal(filter)) => Stream::Real(Box::new(Filtered::new(source, filter))),
+                (stream @ Stream::Real(_), GraphBlock::RealSink(sink)) => return Ok(Self { names, stream, sink: Output::Real(sink) }),
+                (stream @ Stream::Complex(_), GraphBlock::ComplexSink(sink)) => return Ok(Self { names, stream, sink: Output::Complex(sink) }),
+                _ => return Err("sample types don't line up".into()),
+            };
+        }
+        Err("a graph ends with a sink".into())
+    }
+
+    /// Block names from source to sink.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Until the source ends or the token is cancelled.
+    pub fn run(&mut self, cancel: CancelToken) -> Result<RunStats, Box<dyn Error>> {
+        let mut runner = Runner::new(cancel);
+        match (&mut self.stream, &mut self.sink) {
+            (Stream::Real(source), Output::Real(sink)) => runner.run(source, |block| sink.write(block)),
+            (Stream::Complex(source), Output::Complex(sink)) => runner.run(source, |block| sink.write(block)),
+            _ => unreachable!("checked when the graph was built"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::graph::{GraphBlock, GraphSpec, Registry};
+    use crate::pipeline::{CancelToken, StopReason};
+    use crate::block::MapFilter;
+
+    #[test]
+    fn test_graph() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("rust_dsp_graph_{}.wav", std::process::id()));
+        let json = r#"{
+            "blocks": [
+                {"name": "sink", "type": "wav_sink", "params": {"sample_rate": 8000, "path": "PATH"}},
+                {"name": "tone", "type": "null_source", "params": {"sample_rate": 48000, "samples": 48000}},
+                {"name": "shift", "type": "mixer", "params": {"sample_rate": 48000, "shift_hz": 1000}},
+                {"name": "down", "type": "rational_resampler", "params": {"input_rate": 48000, "output_rate": 8000, "taps": 31}},
+                {"name": "fm", "type": "fm_demod", "params": {"sample_rate": 8000, "deviation": 2500}},
+                {"name": "half", "type": "halve"}
+            ],
+            "connections": [["tone", "shift"], ["shift", "down"], ["down", "fm"], ["fm", "half"], ["half", "sink"]]
+        }"#.replace("PATH", path.to_str().unwrap());
+        let spec = GraphSpec::from_json(&json)?;
+
+        // a block type added by the application
+        let mut registry = Registry::default();
+        registry.register("halve", |_| Ok(GraphBlock::RealFilter(Box::new(MapFilter::new(|x: f32| x / 2.0)))));
+        let mut graph = registry.build(&spec)?;
+        assert_eq!(graph.names(), ["tone", "shift", "down", "fm", "half", "sink"]);
+        let stats = graph.run(CancelToken::new())?;
+        assert_eq!((stats.stop, stats.samples), (StopReason::EndOfStream, 8000));
+        drop(graph);
+        assert_eq!(hound::WavReader::open(&path)?.len(), 8000);
+        std::fs::remove_file(&path)?;
+
+        // the resampler expects 44.1 kHz
+        let mismatch = json.replace(r#""input_rate": 48000"#, r#""input_rate": 44100"#);
+        let error = registry.build(&GraphSpec::from_json(&mismatch)?).err().unwrap();
+        assert!(error.to_string().starts_with("down: "), "{}", error);
+        // real samples into a complex only block
+        let types = json.replace(r#"["tone", "shift"], ["shift", "down"], ["down", "fm"]"#, r#"["tone", "fm"], ["fm", "shift"], ["shift", "down"]"#)
+            .replace(r#"["fm", "half"]"#, r#"["down", "half"]"#);
+        let error = registry.build(&GraphSpec::from_json(&types)?).err().unwrap();
+        assert!(error.to_string().starts_with("fm takes Some(Complex)"), "{}", error);
+        let branch = json.replace(r#"["fm", "half"]"#, r#"["fm", "half"], ["fm", "sink"]"#);
+        assert!(registry.build(&GraphSpec::from_json(&branch)?).is_err());
+        assert!(registry.build(&GraphSpec::from_json(&json.replace("halve", "double"))?).is_err());
+
+        #[cfg(feature = "yaml")]
+        {
+            let yaml = "blocks:\n  - { name: a, type: null_source, params: { sample_rate: 8000, samples: 100 } }\n  - { name: b, type: null_sink }\nconnections: [[a, b]]\n";
+            let stats = registry.build(&GraphSpec::from_yaml(yaml)?)?.run(CancelToken::new())?;
+            assert_eq!(stats.samples, 100);
+        }
+        Ok(())
+    }
+}
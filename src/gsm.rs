@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use num_complex::{Complex32, Complex64};
+use crate::error::{check_range, ConfigError};
+use crate::ppm::FCCH_OFFSET_HZ;
+
+
+/// GSM bit rate, 1625/6 kbit/s.
+pub const GSM_SYMBOL_RATE: f64 = 1625e3 / 6.0;
+/// The frequency correction burst is 148 bits of the same tone.
+const FCCH_BITS: f64 = 148.0;
+const CHANNEL_SPACING_HZ: f64 = 200e3;
+const COHERENCE_THRESHOLD: f32 = 0.6;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GsmBand {
+    Gsm850,
+    /// P-GSM and the E-GSM extension.
+    Gsm900,
+    Dcs1800,
+    Pcs1900,
+}
+
+
+impl GsmBand {
+    /// Band names as kalibrate spells them, or just the number.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "GSM850" | "850" => Some(Self::Gsm850),
+            "GSM900" | "EGSM" | "900" => Some(Self::Gsm900),
+            "DCS" | "DCS1800" | "1800" => Some(Self::Dcs1800),
+            "PCS" | "PCS1900" | "1900" => Some(Self::Pcs1900),
+            _ => None,
+        }
+    }
+
+    pub fn arfcns(&self) -> Vec<u16> {
+        match self {
+            Self::Gsm850 => (128..=251).collect(),
+            Self::Gsm900 => (0..=124).chain(975..=1023).collect(),
+            Self::Dcs1800 => (512..=885).collect(),
+            Self::Pcs1900 => (512..=810).collect(),
+        }
+    }
+
+    /// Base station transmit frequency of `arfcn`, None when the band doesn't have that channel.
+    pub fn downlink_hz(&self, arfcn: u16) -> Option<f64> {
+        if !self.arfcns().contains(&arfcn) {
+            return None;
+        }
+        let n = arfcn as i64;
+        let hz = match self {
+            Self::Gsm850 => 869_200_000 + 200_000 * (n - 128),
+            Self::Gsm900 if n >= 975 => 935_000_000 + 200_000 * (n - 1024),
+            Self::Gsm900 => 935_000_000 + 200_000 * n,
+            Self::Dcs1800 => 1_805_200_000 + 200_000 * (n - 512),
+            Self::Pcs1900 => 1_930_200_000 + 200_000 * (n - 512),
+        };
+        Some(hz as f64)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FcchBurst {
+    /// Sample the burst starts at, to within about 15 bits in noise.
+    pub position: usize,
+    /// Where the tone was found relative to where it should be, the receiver's frequency error.
+    pub offset_hz: f64,
+    /// How pure the tone is, 1 without noise.
+    pub coherence: f32,
+}
+
+
+/// Median frequency error of `bursts`, robust against the odd burst that was really data.
+pub fn median_offset(bursts: &[FcchBurst]) -> Option<f64> {
+    let mut offsets: Vec<f64> = bursts.iter().map(|burst| burst.offset_hz).collect();
+    offsets.sort_by(f64::total_cmp);
+    offsets.get(offsets.len() / 2).copied()
+}
+
+
+/// A channel `FcchDetector::scan` found bursts on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GsmChannel {
+    pub arfcn: u16,
+    pub frequency_hz: f64,
+    pub bursts: Vec<FcchBurst>,
+}
+
+
+impl GsmChannel {
+    pub fn offset_hz(&self) -> Option<f64> {
+        median_offset(&self.bursts)
+    }
+}
+
+
+/// Finds GSM frequency correction bursts, the pure tone a quarter of the bit rate above the carrier
+/// every base station sends every 10 or 11 frames. The tone is mixed to DC and the correlation of
+/// the signal with itself two bits later is averaged over most of a burst: GMSK data averages out,
+/// the tone doesn't. Tones that last much longer than a burst aren't from a base station and are
+/// left out.
+pub struct FcchDetector {
+    sample_rate: u32,
+    smoothing: usize,
+    lag: usize,
+    window: usize,
+    burst: usize,
+}
+
+
+impl FcchDetector {
+    pub fn new(sample_rate: u32) -> Result<Self, ConfigError> {
+        check_range("sample rate", sample_rate as f64, CHANNEL_SPACING_HZ, 100e6)?;
+        let fs = sample_rate as f64;
+        let burst = (FCCH_BITS * fs / GSM_SYMBOL_RATE).round() as usize;
+        Ok(Self {
+            sample_rate,
+            smoothing: (fs / CHANNEL_SPACING_HZ).round().max(1.0) as usize,
+            lag: (2.0 * fs / GSM_SYMBOL_RATE).round().max(1.0) as usize,
+            window: burst * 4 / 5,
+            burst,
+        })
+    }
+
+    /// Length of a burst in samples.
+    pub fn burst_len(&self) -> usize {
+        self.burst
+    }
+
+    /// Largest frequency error that can be measured, errors beyond it alias.
+    pub fn max_offset_hz(&self) -> f64 {
+        self.sample_rate as f64 / (2.0 * self.lag as f64)
+    }
+
+    /// Bursts of the carrier `carrier_hz` away from the center of `samples`.
+    pub fn detect(&self, samples: &[Complex32], carrier_hz: f64) -> Vec<FcchBurst> {
+        let fs = self.sample_rate as f64;
+        let step = -2.0 * PI * (carrier_hz + FCCH_OFFSET_HZ) / fs;
+        let mixed: Vec<Complex32> = samples.iter().enumerate()
+            .map(|(n, &x)| x * Complex32::from_polar(1.0, ((step * n as f64) % (2.0 * PI)) as f32))
+            .collect();
+        // two boxcars put nulls on the neighbouring channels
+        let filtered = boxcar(&boxcar(&mixed, self.smoothing), self.smoothing);
+        let products: Vec<Complex64> = (self.lag..filtered.len()).map(|n| {
+            let p = filtered[n] * filtered[n - self.lag].conj();
+            Complex64::new(p.re as f64, p.im as f64)
+        }).collect();
+        if products.len() < self.window {
+            return Vec::new();
+        }
+
+        let mut sum: Complex64 = products[..self.window].iter().sum();
+        let mut magnitude: f64 = products[..self.window].iter().map(|p| p.norm()).sum();
+        // keeps rounding leftovers of the running sums in silent stretches from looking coherent
+        let floor = products.iter().map(|p| p.norm()).sum::<f64>() / products.len() as f64 * self.window as f64 * 1e-6;
+        let mut bursts = Vec::new();
+        // start of the run of windows above the threshold and its best window
+        let mut run: Option<(usize, f32, Complex64)> = None;
+        for t in 0..=products.len() - self.window {
+            if t > 0 {
+                sum += products[t + self.window - 1] - products[t - 1];
+                magnitude += products[t + self.window - 1].norm() - products[t - 1].norm();
+            }
+            let coherence = (sum.norm() / (magnitude + floor).max(f64::MIN_POSITIVE)) as f32;
+            let last = t == products.len() - self.window;
+            if coherence > COHERENCE_THRESHOLD {
+                match &mut run {
+                    Some((_, best, best_sum)) if coherence > *best => (*best, *best_sum) = (coherence, sum),
+                    Some(_) => {},
+                    None => run = Some((t, coherence, sum)),
+                }
+                if !last {
+                    continue;
+                }
+            }
+            let Some((start, coherence, best_sum)) = run.take() else { continue };
+            if t - start > 2 * self.burst {
+                continue;
+            }
+            // the run is centered on the burst, the filters delay it a little
+            let center = (start + t) as f64 / 2.0 + (self.window + self.lag) as f64 / 2.0 - (self.smoothing - 1) as f64;
+            bursts.push(FcchBurst {
+                position: (center - self.burst as f64 / 2.0).max(0.0).round() as usize,
+                offset_hz: best_sum.arg() * fs / (2.0 * PI * self.lag as f64),
+                coherence,
+            });
+        }
+        bursts
+    }
+
+    /// Looks for bursts on every channel of `band` in the middle 3/4 of `samples`, I/Q from a receiver
+    /// tuned to `tuned_hz`. Only channels with bursts are returned.
+    pub fn scan(&self, samples: &[Complex32], tuned_hz: f64, band: GsmBand) -> Vec<GsmChannel> {
+        let reach = self.sample_rate as f64 * 3.0 / 8.0 - CHANNEL_SPACING_HZ / 2.0;
+        band.arfcns().into_iter().filter_map(|arfcn| {
+            let frequency_hz = band.downlink_hz(arfcn)?;
+            if (frequency_hz - tuned_hz).abs() > reach {
+                return None;
+            }
+            let bursts = self.detect(samples, frequency_hz - tuned_hz);
+            (!bursts.is_empty()).then_some(GsmChannel { arfcn, frequency_hz, bursts })
+        }).collect()
+    }
+
+    /// `scan` over a capture of `len` samples too large to hold in memory, `window` samples at a time.
+    /// `read_at(start, count, dst)` appends up to `count` samples from `start`, e.g.
+    /// `MmapIQSource::read_at`. The windows overlap by a few bursts and every burst is taken from the
+    /// window that holds all of it.
+    pub fn scan_windows(&self, len: u64, window: usize, mut read_at: impl FnMut(u64, usize, &mut Vec<Complex32>), tuned_hz: f64, band: GsmBand) -> Vec<GsmChannel> {
+        let overlap = 4 * self.burst + 2 * self.smoothing + self.lag;
+        let window = window.max(2 * overlap);
+        let step = (window - overlap) as u64;
+        let mut channels: BTreeMap<u16, GsmChannel> = BTreeMap::new();
+        let mut samples = Vec::new();
+        let mut start = 0;
+        while start < len {
+            samples.clear();
+            read_at(start, window, &mut samples);
+            let first = start > 0;
+            let last = start + step >= len;
+            for mut channel in self.scan(&samples, tuned_hz, band) {
+                // bursts cut by either end of the window are found whole in the neighbouring one
+                channel.bursts.retain(|burst| (!first || burst.position >= overlap / 2) && (last || (burst.position as u64) < step + overlap as u64 / 2));
+                channel.bursts.iter_mut().for_each(|burst| burst.position += start as usize);
+                match channels.get_mut(&channel.arfcn) {
+                    Some(found) => found.bursts.append(&mut channel.bursts),
+                    None => { channels.insert(channel.arfcn, channel); },
+                }
+            }
+            start += step;
+        }
+        channels.into_values().filter(|channel| !channel.bursts.is_empty()).collect()
+    }
+}
+
+
+/// Moving average over `len` samples.
+fn boxcar(samples: &[Complex32], len: usize) -> Vec<Complex32> {
+    let mut sum = Complex32::new(0.0, 0.0);
+    samples.iter().enumerate().map(|(n, &x)| {
+        sum += x;
+        if n >= len {
+            sum -= samples[n - len];
+        }
+        sum / len as f32
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use num_complex::Complex32;
+    use crate::gsm::{FcchDetector, GsmBand, GSM_SYMBOL_RATE};
+    use crate::ppm::FCCH_OFFSET_HZ;
+    use crate::seed::Rng;
+
+    #[test]
+    fn test_fcch_detector() {
+        let mut rng = Rng::new(0x2545f491);
+
+        assert_eq!(GsmBand::from_name("egsm"), Some(GsmBand::Gsm900));
+        assert_eq!(GsmBand::Gsm900.downlink_hz(1), Some(935.2e6));
+        assert_eq!(GsmBand::Gsm900.downlink_hz(975), Some(925.2e6));
+        assert_eq!(GsmBand::Dcs1800.downlink_hz(600), Some(1822.8e6));
+        assert_eq!(GsmBand::Pcs1900.downlink_hz(900), None);
+
+        // tuned to ARFCN 2: MSK data with a burst every 10 frames on ARFCN 1, received 1.5 kHz high,
+        // and a carrier where ARFCN 3 would have its bursts
+        let rate = 1e6;
+        let tuned = 935.4e6;
+        let len = 200_000;
+        let bits: Vec<bool> = (0..(len as f64 * GSM_SYMBOL_RATE / rate) as usize + 1)
+            .map(|bit| (1000..1148).contains(&(bit % 12_500)) || rng.next_u32() & 1 == 0)
+            .collect();
+        let starts: Vec<usize> = (0..bits.len()).step_by(12_500).map(|bit| ((bit + 1000) as f64 * rate / GSM_SYMBOL_RATE).round() as usize).collect();
+        let mut phase = 0.0;
+        let samples: Vec<Complex32> = (0..len).map(|n| {
+            let bit = bits[(n as f64 * GSM_SYMBOL_RATE / rate) as usize];
+            let data = (bit as u8 as f64 * 2.0 - 1.0) * PI / 2.0 * GSM_SYMBOL_RATE / rate;
+            phase += data - 2.0 * PI * (200e3 - 1.5e3) / rate;
+            let carrier = 2.0 * PI * (200e3 + FCCH_OFFSET_HZ) * n as f64 / rate;
+            let noise = Complex32::new(rng.uniform() - 0.5, rng.uniform() - 0.5);
+            Complex32::from_polar(0.5, phase as f32) + Complex32::from_polar(0.5, carrier as f32) + noise * 0.2
+        }).collect();
+
+        let detector = FcchDetector::new(rate as u32).unwrap();
+        let channels = detector.scan(&samples, tuned, GsmBand::Gsm900);
+        assert_eq!(channels.len(), 1, "{:?}", channels);
+        assert_eq!(channels[0].arfcn, 1);
+        let found: Vec<usize> = channels[0].bursts.iter().map(|burst| burst.position).collect();
+        assert_eq!(found.len(), starts.iter().filter(|&&start| start + detector.burst_len() < len).count(), "{:?}", found);
+        for (position, start) in found.iter().zip(&starts) {
+            assert!(position.abs_diff(*start) < 60, "{:?} {:?}", found, starts);
+        }
+        let offset = channels[0].offset_hz().unwrap();
+        assert!((offset - 1.5e3).abs() < 20.0, "{}", offset);
+
+        // the same bursts when the capture is read a piece at a time
+        let windows = detector.scan_windows(len as u64, 30_000, |start, count, dst| {
+            dst.extend_from_slice(&samples[start as usize..(start as usize + count).min(len)]);
+        }, tuned, GsmBand::Gsm900);
+        assert_eq!(windows.len(), 1);
+        let positions: Vec<usize> = windows[0].bursts.iter().map(|burst| burst.position).collect();
+        assert_eq!(positions.len(), found.len(), "{:?} {:?}", positions, found);
+        for (position, found) in positions.iter().zip(&found) {
+            assert!(position.abs_diff(*found) < 60, "{:?} {:?}", positions, found);
+        }
+        assert!(FcchDetector::new(100_000).is_err());
+    }
+}
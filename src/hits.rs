@@ -0,0 +1,171 @@
+#[cfg(feature = "sqlite")]
+use std::error::Error;
+#[cfg(feature = "sqlite")]
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+#[cfg(feature = "sqlite")]
+use std::time::UNIX_EPOCH;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, Row};
+
+
+/// Something heard on the air, by the scanner or a decoder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub time: SystemTime,
+    pub frequency: u64,
+    pub duration: Duration,
+    pub rssi_db: Option<f32>,
+    /// What logged the hit, e.g. `scanner` or the decoder name.
+    pub source: String,
+    pub text: Option<String>,
+}
+
+
+#[cfg(feature = "sqlite")]
+fn to_unix(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+
+#[cfg(feature = "sqlite")]
+fn hit_from_row(row: &Row) -> rusqlite::Result<Hit> {
+    Ok(Hit {
+        time: UNIX_EPOCH + Duration::from_secs_f64(row.get(0)?),
+        frequency: row.get::<_, i64>(1)? as u64,
+        duration: Duration::from_secs_f64(row.get(2)?),
+        rssi_db: row.get(3)?,
+        source: row.get(4)?,
+        text: row.get(5)?,
+    })
+}
+
+
+/// Per-frequency totals from [`HitLog::busiest`].
+#[cfg(feature = "sqlite")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Activity {
+    pub frequency: u64,
+    pub hits: u64,
+    pub seconds: f64,
+}
+
+
+#[cfg(feature = "sqlite")]
+const COLUMNS: &str = "time, frequency, duration, rssi_db, source, text";
+
+
+/// SQLite store of hits with a few canned queries, anything else can go through `connection`.
+#[cfg(feature = "sqlite")]
+pub struct HitLog {
+    connection: Connection,
+}
+
+
+#[cfg(feature = "sqlite")]
+impl HitLog {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::new(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, Box<dyn Error>> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(connection: Connection) -> Result<Self, Box<dyn Error>> {
+        connection.execute_batch("
+            CREATE TABLE IF NOT EXISTS hits (
+                id INTEGER PRIMARY KEY,
+                time REAL NOT NULL,
+                frequency INTEGER NOT NULL,
+                duration REAL NOT NULL,
+                rssi_db REAL,
+                source TEXT NOT NULL,
+                text TEXT
+            );
+            CREATE INDEX IF NOT EXISTS hits_time ON hits (time);
+            CREATE INDEX IF NOT EXISTS hits_frequency ON hits (frequency);
+        ")?;
+        Ok(Self { connection })
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Stores `hit` and returns its row id.
+    pub fn log(&self, hit: &Hit) -> Result<i64, Box<dyn Error>> {
+        self.connection.execute(
+            &format!("INSERT INTO hits ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)", COLUMNS),
+            params![to_unix(hit.time), hit.frequency as i64, hit.duration.as_secs_f64(), hit.rssi_db, hit.source, hit.text],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    fn query(&self, filter: &str, params: impl rusqlite::Params) -> Result<Vec<Hit>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(&format!("SELECT {} FROM hits {}", COLUMNS, filter))?;
+        let hits = statement.query_map(params, hit_from_row)?.collect::<Result<_, _>>()?;
+        Ok(hits)
+    }
+
+    /// Newest first.
+    pub fn recent(&self, limit: usize) -> Result<Vec<Hit>, Box<dyn Error>> {
+        self.query("ORDER BY time DESC LIMIT ?1", [limit as i64])
+    }
+
+    /// Hits between `low_hz` and `high_hz` inclusive since `since`, oldest first.
+    pub fn in_range(&self, low_hz: u64, high_hz: u64, since: SystemTime) -> Result<Vec<Hit>, Box<dyn Error>> {
+        self.query("WHERE frequency BETWEEN ?1 AND ?2 AND time >= ?3 ORDER BY time",
+                   params![low_hz as i64, high_hz as i64, to_unix(since)])
+    }
+
+    /// Decoded text containing `pattern`, oldest first.
+    pub fn search_text(&self, pattern: &str) -> Result<Vec<Hit>, Box<dyn Error>> {
+        self.query("WHERE instr(text, ?1) > 0 ORDER BY time", [pattern])
+    }
+
+    /// Frequencies with the most hits.
+    pub fn busiest(&self, limit: usize) -> Result<Vec<Activity>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT frequency, COUNT(*), SUM(duration) FROM hits GROUP BY frequency ORDER BY COUNT(*) DESC, frequency LIMIT ?1")?;
+        let rows = statement.query_map([limit as i64], |row| {
+            Ok(Activity {
+                frequency: row.get::<_, i64>(0)? as u64,
+                hits: row.get::<_, i64>(1)? as u64,
+                seconds: row.get(2)?,
+            })
+        })?.collect::<Result<_, _>>()?;
+        Ok(rows)
+    }
+}
+
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use std::error::Error;
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::hits::{Activity, Hit, HitLog};
+
+    #[test]
+    fn test_hit_log() -> Result<(), Box<dyn Error>> {
+        let log = HitLog::open_in_memory()?;
+        let hit = |seconds: u64, frequency: u64, text: Option<&str>| Hit {
+            time: UNIX_EPOCH + Duration::from_secs(seconds),
+            frequency,
+            duration: Duration::from_secs(2),
+            rssi_db: Some(-60.0),
+            source: "scanner".into(),
+            text: text.map(String::from),
+        };
+        log.log(&hit(100, 146_520_000, None))?;
+        log.log(&hit(200, 162_550_000, Some("WX ALERT")))?;
+        log.log(&hit(300, 146_520_000, None))?;
+
+        assert_eq!(log.recent(1)?, [hit(300, 146_520_000, None)]);
+        assert_eq!(log.in_range(144_000_000, 148_000_000, UNIX_EPOCH + Duration::from_secs(150))?.len(), 1);
+        assert_eq!(log.search_text("ALERT")?, [hit(200, 162_550_000, Some("WX ALERT"))]);
+        assert_eq!(log.busiest(1)?, [Activity { frequency: 146_520_000, hits: 2, seconds: 4.0 }]);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,421 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use memmap2::Mmap;
+use half::f16;
+use num_complex::{Complex, Complex32};
+use crate::rate::{RateAware, SampleRate};
+use crate::sample::{decode_le, decode_packed12, encode_le, encode_packed12, PACKED12_SIZE};
+use crate::traits::*;
+
+
+/// Sample formats of headerless interleaved I/Q recordings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IQFormat {
+    /// Unsigned 8-bit as written by rtl_sdr.
+    CU8,
+    /// Signed 8-bit as written by hackrf_transfer.
+    CS8,
+    /// Signed 12-bit packed into 3 bytes per sample, see `decode_packed12`.
+    CS12,
+    /// Signed 16-bit little endian.
+    CS16,
+    /// 16-bit float little endian.
+    CF16,
+    /// 32-bit float little endian, GNU Radio's `.cfile`.
+    CF32,
+}
+
+
+impl IQFormat {
+    /// From the usual file extensions, e.g. `capture.cu8` or `capture.cfile`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "cu8" => Some(Self::CU8),
+            "cs8" => Some(Self::CS8),
+            "cs12" => Some(Self::CS12),
+            "cs16" => Some(Self::CS16),
+            "cf16" => Some(Self::CF16),
+            "cf32" | "cfile" => Some(Self::CF32),
+            _ => None,
+        }
+    }
+
+    /// Bytes per complex sample.
+    pub fn frame_size(&self) -> usize {
+        match self {
+            Self::CU8 | Self::CS8 => 2,
+            Self::CS12 => PACKED12_SIZE,
+            Self::CS16 | Self::CF16 => 4,
+            Self::CF32 => 8,
+        }
+    }
+
+    /// Appends the whole samples in `src` to `dst`, normalized so integer formats span ±1.
+    pub fn decode(&self, src: &[u8], dst: &mut Vec<Complex32>) {
+        match self {
+            Self::CU8 => decode_le::<Complex<u8>>(src, dst),
+            Self::CS8 => decode_le::<Complex<i8>>(src, dst),
+            Self::CS12 => decode_packed12(src, dst),
+            Self::CS16 => decode_le::<Complex<i16>>(src, dst),
+            Self::CF16 => decode_le::<Complex<f16>>(src, dst),
+            Self::CF32 => decode_le::<Complex32>(src, dst),
+        }
+    }
+
+    /// Appends `src` encoded, rounding and saturating integer formats at full scale.
+    pub fn encode(&self, src: &[Complex32], dst: &mut Vec<u8>) {
+        match self {
+            Self::CU8 => encode_le::<Complex<u8>>(src, dst),
+            Self::CS8 => encode_le::<Complex<i8>>(src, dst),
+            Self::CS12 => encode_packed12(src, dst),
+            Self::CS16 => encode_le::<Complex<i16>>(src, dst),
+            Self::CF16 => encode_le::<Complex<f16>>(src, dst),
+            Self::CF32 => encode_le::<Complex32>(src, dst),
+        }
+    }
+}
+
+
+fn resolve_format(path: &Path, format: Option<IQFormat>) -> Result<IQFormat, Box<dyn Error>> {
+    Ok(format.or_else(|| IQFormat::from_path(path))
+        .ok_or_else(|| format!("{}: unknown I/Q format, expected .cu8, .cs8, .cs12, .cs16, .cf16, .cf32 or .cfile", path.display()))?)
+}
+
+
+/// Reads headerless I/Q recordings, normalized so integer formats span ±1. The files carry no
+/// sample rate so it has to be given. A trailing partial sample is ignored.
+pub struct IQFileSource<R: Read> {
+    reader: R,
+    format: IQFormat,
+    sample_rate: u32,
+    samples_per_read: usize,
+    scratch: Vec<u8>,
+}
+
+
+impl IQFileSource<BufReader<File>> {
+    /// The format comes from the file extension when `format` is None.
+    pub fn open(path: PathBuf, format: Option<IQFormat>, sample_rate: u32) -> Result<Self, Box<dyn Error>> {
+        let format = resolve_format(&path, format)?;
+        Ok(Self::new(BufReader::new(File::open(path)?), format, sample_rate))
+    }
+}
+
+
+impl<R: Read> IQFileSource<R> {
+    pub fn new(reader: R, format: IQFormat, sample_rate: u32) -> Self {
+        Self {
+            reader,
+            format,
+            sample_rate,
+            samples_per_read: (sample_rate as usize / 100).max(1),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn format(&self) -> IQFormat {
+        self.format
+    }
+
+    /// Defaults to 10 ms of samples.
+    pub fn set_samples_per_read(&mut self, samples: usize) {
+        self.samples_per_read = samples.max(1);
+    }
+}
+
+
+impl<R: Read + Seek> IQFileSource<R> {
+    /// Continue reading at `sample`.
+    pub fn seek(&mut self, sample: u64) -> Result<(), Box<dyn Error>> {
+        self.reader.seek(SeekFrom::Start(sample * self.format.frame_size() as u64))?;
+        Ok(())
+    }
+}
+
+
+impl<R: Read> Source<Complex32> for IQFileSource<R> {
+    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        let len = (self.samples_per_read * self.format.frame_size()) as u64;
+        self.scratch.clear();
+        self.reader.by_ref().take(len).read_to_end(&mut self.scratch)?;
+        self.format.decode(&self.scratch, dst);
+        Ok(())
+    }
+}
+
+
+impl<R: Read> RateAware for IQFileSource<R> {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+/// `IQFileSource` over a memory mapped file. Samples are converted straight from the page cache as
+/// they're read and seeking costs nothing, so captures of any size can be scrubbed through or parts
+/// of them decoded again. The file must not be truncated while it's open.
+pub struct MmapIQSource {
+    map: Mmap,
+    format: IQFormat,
+    sample_rate: u32,
+    samples_per_read: usize,
+    position: u64,
+}
+
+
+impl MmapIQSource {
+    /// The format comes from the file extension when `format` is None.
+    pub fn open(path: &Path, format: Option<IQFormat>, sample_rate: u32) -> Result<Self, Box<dyn Error>> {
+        let format = resolve_format(path, format)?;
+        // only ever read, a file changed underneath shows up as changed samples
+        let map = unsafe { Mmap::map(&File::open(path)?)? };
+        Ok(Self {
+            map,
+            format,
+            sample_rate,
+            samples_per_read: (sample_rate as usize / 100).max(1),
+            position: 0,
+        })
+    }
+
+    pub fn format(&self) -> IQFormat {
+        self.format
+    }
+
+    /// Defaults to 10 ms of samples.
+    pub fn set_samples_per_read(&mut self, samples: usize) {
+        self.samples_per_read = samples.max(1);
+    }
+
+    /// Whole samples in the file.
+    pub fn len(&self) -> u64 {
+        (self.map.len() / self.format.frame_size()) as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The next sample `read` returns.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Positions past the end are moved to the end.
+    pub fn seek(&mut self, sample: u64) {
+        self.position = sample.min(self.len());
+    }
+
+    pub fn seek_time(&mut self, seconds: f64) {
+        self.seek((seconds.max(0.0) * self.sample_rate as f64) as u64);
+    }
+
+    /// Appends up to `count` samples from `start` to `dst` without moving the read position.
+    pub fn read_at(&self, start: u64, count: usize, dst: &mut Vec<Complex32>) {
+        let start = start.min(self.len()) as usize;
+        let end = (start + count).min(self.len() as usize);
+        let frame_size = self.format.frame_size();
+        self.format.decode(&self.map[start * frame_size..end * frame_size], dst);
+    }
+}
+
+
+impl Source<Complex32> for MmapIQSource {
+    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        self.read_at(self.position, self.samples_per_read, dst);
+        self.position += dst.len() as u64;
+        Ok(())
+    }
+}
+
+
+impl RateAware for MmapIQSource {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+/// Writes headerless I/Q recordings, the counterpart of `IQFileSource`. Samples are multiplied by
+/// the scale before they are stored, integer formats saturate at full scale.
+pub struct IQFileSink<W: Write> {
+    writer: W,
+    format: IQFormat,
+    scale: f32,
+    scaled: Vec<Complex32>,
+    scratch: Vec<u8>,
+}
+
+
+impl IQFileSink<BufWriter<File>> {
+    /// The format comes from the file extension when `format` is None.
+    pub fn create(path: PathBuf, format: Option<IQFormat>) -> Result<Self, Box<dyn Error>> {
+        let format = resolve_format(&path, format)?;
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+}
+
+
+impl<W: Write> IQFileSink<W> {
+    pub fn new(writer: W, format: IQFormat) -> Self {
+        Self {
+            writer,
+            format,
+            scale: 1.0,
+            scaled: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn format(&self) -> IQFormat {
+        self.format
+    }
+
+    /// Defaults to 1, e.g. 4 makes use of the 8-bit range for a capture peaking at 0.25.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    pub fn into_inner(mut self) -> Result<W, Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+
+impl<W: Write> Sink<Complex32> for IQFileSink<W> {
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        self.scaled.clear();
+        self.scaled.extend(src.iter().map(|&x| x * self.scale));
+        self.scratch.clear();
+        self.format.encode(&self.scaled, &mut self.scratch);
+        self.writer.write_all(&self.scratch)?;
+        Ok(())
+    }
+}
+
+
+impl<W: Write> RateAware for IQFileSink<W> {}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::path::Path;
+    use half::f16;
+    use num_complex::{Complex, Complex32};
+    use crate::iqfile::{IQFileSink, IQFileSource, IQFormat, MmapIQSource};
+    use crate::sample::{encode_le, encode_packed12};
+    use crate::traits::{Sink, Source};
+
+    #[test]
+    fn test_iq_file_source() -> Result<(), Box<dyn Error>> {
+        assert_eq!(IQFormat::from_path(Path::new("fm.cu8")), Some(IQFormat::CU8));
+        assert_eq!(IQFormat::from_path(Path::new("capture.CFILE")), Some(IQFormat::CF32));
+        assert_eq!(IQFormat::from_path(Path::new("pluto.cs12")), Some(IQFormat::CS12));
+        assert_eq!(IQFormat::from_path(Path::new("capture.wav")), None);
+
+        let values: Vec<Complex32> = (0..1000).map(|n| Complex32::from_polar(0.9, n as f32 * 0.1)).collect();
+        for (format, tolerance) in [(IQFormat::CU8, 1e-2), (IQFormat::CS8, 1e-2), (IQFormat::CS12, 1e-3), (IQFormat::CS16, 1e-4), (IQFormat::CF16, 1e-3), (IQFormat::CF32, 0.0)] {
+            let mut bytes = Vec::new();
+            match format {
+                IQFormat::CU8 => encode_le::<Complex<u8>>(&values, &mut bytes),
+                IQFormat::CS8 => encode_le::<Complex<i8>>(&values, &mut bytes),
+                IQFormat::CS12 => encode_packed12(&values, &mut bytes),
+                IQFormat::CS16 => encode_le::<Complex<i16>>(&values, &mut bytes),
+                IQFormat::CF16 => encode_le::<Complex<f16>>(&values, &mut bytes),
+                IQFormat::CF32 => encode_le::<Complex32>(&values, &mut bytes),
+            }
+            // a cut off last sample is dropped
+            bytes.push(0);
+
+            let mut source = IQFileSource::new(bytes.as_slice(), format, 48_000);
+            source.set_samples_per_read(300);
+            let mut read = Vec::new();
+            let mut block = Vec::new();
+            loop {
+                source.read(&mut block)?;
+                if block.is_empty() {
+                    break;
+                }
+                assert!(block.len() <= 300);
+                read.extend_from_slice(&block);
+            }
+            assert_eq!(read.len(), values.len(), "{:?}", format);
+            for (a, b) in values.iter().zip(&read) {
+                assert!((a - b).norm() <= tolerance, "{:?}: {} vs {}", format, a, b);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_iq_file_sink() -> Result<(), Box<dyn Error>> {
+        let values: Vec<Complex32> = (0..500).map(|n| Complex32::from_polar(0.2, n as f32 * 0.3)).collect();
+        for format in [IQFormat::CS8, IQFormat::CS16, IQFormat::CF32] {
+            let mut sink = IQFileSink::new(Vec::new(), format);
+            sink.set_scale(4.0);
+            for block in values.chunks(128) {
+                sink.write(block)?;
+            }
+            let bytes = sink.into_inner()?;
+            assert_eq!(bytes.len(), values.len() * format.frame_size());
+
+            let mut source = IQFileSource::new(bytes.as_slice(), format, 48_000);
+            source.set_samples_per_read(values.len());
+            let mut read = Vec::new();
+            source.read(&mut read)?;
+            for (a, b) in values.iter().zip(&read) {
+                assert!((a * 4.0 - b).norm() < 1e-2, "{:?}: {} vs {}", format, a * 4.0, b);
+            }
+        }
+
+        // too much gain clips instead of wrapping around
+        let mut sink = IQFileSink::new(Vec::new(), IQFormat::CS8);
+        sink.set_scale(10.0);
+        sink.write(&[Complex32::new(0.5, -0.5)])?;
+        assert_eq!(sink.into_inner()?, [127, 128]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmap_iq_source() -> Result<(), Box<dyn Error>> {
+        let values: Vec<Complex32> = (0..5000).map(|n| Complex32::from_polar(0.7, n as f32 * 0.01)).collect();
+        let path = std::env::temp_dir().join(format!("rust_dsp_mmap_{}.cs16", std::process::id()));
+        let mut sink = IQFileSink::create(path.clone(), None)?;
+        sink.write(&values)?;
+        sink.into_inner()?;
+
+        let mut source = MmapIQSource::open(&path, None, 10_000)?;
+        assert_eq!((source.format(), source.len()), (IQFormat::CS16, 5000));
+        let mut read = Vec::new();
+        let mut block = Vec::new();
+        loop {
+            source.read(&mut block)?;
+            if block.is_empty() {
+                break;
+            }
+            assert_eq!(block.len(), 100);
+            read.extend_from_slice(&block);
+        }
+        assert_eq!(read.len(), values.len());
+        assert!(values.iter().zip(&read).all(|(a, b)| (a - b).norm() < 1e-4));
+
+        // seeking and random access see the same samples
+        source.seek_time(0.25);
+        source.read(&mut block)?;
+        assert_eq!((source.position(), block[0]), (2600, read[2500]));
+        let mut range = Vec::new();
+        source.read_at(4990, 100, &mut range);
+        assert_eq!(range, read[4990..]);
+        source.seek(1 << 40);
+        source.read(&mut block)?;
+        assert!(block.is_empty());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
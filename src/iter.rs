@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::marker::PhantomData;
+use crate::traits::*;
+
+
+/// Consumes a `Source` as an iterator of the buffers it reads. Iteration stops at the end of the
+/// stream or on the first error, which `take_error` hands back.
+pub struct SourceIter<S, T> {
+    source: S,
+    error: Option<Box<dyn Error>>,
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+
+impl<S: Source<T>, T> SourceIter<S, T> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            error: None,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<Box<dyn Error>> {
+        self.error.take()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+
+impl<S: Source<T>, T> Iterator for SourceIter<S, T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Vec<T>> {
+        if self.done {
+            return None;
+        }
+        let mut buffer = Vec::new();
+        match self.source.read(&mut buffer) {
+            Ok(()) if !buffer.is_empty() => Some(buffer),
+            Ok(()) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                self.error = Some(e);
+                None
+            },
+        }
+    }
+}
+
+
+/// Runs every buffer of an iterator through a `Filter`, skipping buffers the filter produced nothing for.
+pub struct FilterIter<It, F, I, O> {
+    iter: It,
+    filter: F,
+    error: Option<Box<dyn Error>>,
+    _marker: PhantomData<(I, O)>,
+}
+
+
+impl<It, F, I, O> FilterIter<It, F, I, O>
+where It: Iterator<Item = Vec<I>>, F: Filter<I, O>
+{
+    pub fn new(iter: It, filter: F) -> Self {
+        Self {
+            iter,
+            filter,
+            error: None,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<Box<dyn Error>> {
+        self.error.take()
+    }
+}
+
+
+impl<It, F, I, O> Iterator for FilterIter<It, F, I, O>
+where It: Iterator<Item = Vec<I>>, F: Filter<I, O>
+{
+    type Item = Vec<O>;
+
+    fn next(&mut self) -> Option<Vec<O>> {
+        if self.error.is_some() {
+            return None;
+        }
+        for input in self.iter.by_ref() {
+            let mut output = Vec::new();
+            if let Err(e) = self.filter.filter(&input, &mut output) {
+                self.error = Some(e);
+                return None;
+            }
+            if !output.is_empty() {
+                return Some(output);
+            }
+        }
+        None
+    }
+}
+
+
+/// Turns an iterator of samples into a `Source` that reads up to `samples_per_read` at a time.
+pub struct IterSource<It> {
+    iter: It,
+    samples_per_read: usize,
+}
+
+
+impl<It: Iterator> IterSource<It> {
+    pub fn new(iter: impl IntoIterator<IntoIter = It>, samples_per_read: usize) -> Self {
+        Self {
+            iter: iter.into_iter(),
+            samples_per_read: samples_per_read.max(1),
+        }
+    }
+}
+
+
+impl<It: Iterator<Item = T>, T> Source<T> for IterSource<It> {
+    fn read(&mut self, dst: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        dst.extend(self.iter.by_ref().take(self.samples_per_read));
+        Ok(())
+    }
+}
+
+
+/// Write every buffer of an iterator to a `Sink`.
+pub fn write_all<T, S: Sink<T>>(sink: &mut S, iter: impl IntoIterator<Item = Vec<T>>) -> Result<(), Box<dyn Error>> {
+    for buffer in iter {
+        sink.write(&buffer)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::block::MapFilter;
+    use crate::iter::{write_all, FilterIter, IterSource, SourceIter};
+    use crate::traits::Sink;
+
+    struct Collect(Vec<f32>);
+
+    impl Sink<f32> for Collect {
+        fn write(&mut self, src: &[f32]) -> Result<(), Box<dyn std::error::Error>> {
+            self.0.extend_from_slice(src);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_iterator_adapters() -> Result<(), Box<dyn std::error::Error>> {
+        let source = IterSource::new((0..10).map(|x| x as f32), 4);
+        let lengths: Vec<usize> = SourceIter::new(source).map(|b| b.len()).collect();
+        assert_eq!(lengths, [4, 4, 2]);
+
+        let blocks = SourceIter::new(IterSource::new((0..10).map(|x| x as f32), 3));
+        let doubled = FilterIter::new(blocks, MapFilter::new(|x: f32| 2.0 * x));
+        let mut sink = Collect(Vec::new());
+        write_all(&mut sink, doubled)?;
+        assert_eq!(sink.0, (0..10).map(|x| 2.0 * x as f32).collect::<Vec<_>>());
+        Ok(())
+    }
+
+}
@@ -0,0 +1,527 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use num_complex::Complex32;
+use crate::fec::{ConvolutionalDeinterleaver, ConvolutionalEncoder, Derandomizer, ReedSolomon, ViterbiDecoder, CCSDS_POLYS};
+use crate::modem::{soft_bits, QPSKDemod};
+use crate::traits::*;
+
+
+pub const ASM: u32 = 0x1acffc1d;
+pub const CADU_LEN: usize = 1024;
+pub type Cadu = [u8; CADU_LEN];
+
+const INTERLEAVE_MARKER: u8 = 0x27;
+const INTERLEAVE_STRIDE: usize = 80;
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct LrptConfig {
+    pub symbol_rate: u32,
+    pub offset: bool,
+    pub interleaved: bool,
+    pub differential: bool,
+    pub interleave_branches: usize,
+    pub interleave_delay: usize,
+}
+
+
+impl LrptConfig {
+    /// Meteor-M2 72k QPSK without interleaving.
+    pub fn meteor_72k() -> Self {
+        Self {
+            symbol_rate: 72000,
+            offset: false,
+            interleaved: false,
+            differential: false,
+            interleave_branches: 36,
+            interleave_delay: 2048,
+        }
+    }
+
+    /// Meteor-M2-x 80k OQPSK with interleaving and differential coding.
+    pub fn meteor_80k() -> Self {
+        Self {
+            symbol_rate: 80000,
+            offset: true,
+            interleaved: true,
+            differential: true,
+            interleave_branches: 36,
+            interleave_delay: 2048,
+        }
+    }
+}
+
+
+/// Undo one of the eight QPSK phase rotation / IQ swap ambiguities on a soft symbol.
+fn rotate(a: i8, b: i8, rotation: usize) -> (i8, i8) {
+    let (na, nb) = (a.saturating_neg(), b.saturating_neg());
+    match rotation {
+        0 => (a, b),
+        1 => (nb, a),
+        2 => (na, nb),
+        3 => (b, na),
+        4 => (b, a),
+        5 => (na, b),
+        6 => (nb, na),
+        _ => (a, nb),
+    }
+}
+
+
+/// Resolve the phase ambiguity by correlating against the convolutionally encoded sync word.
+struct RotationSync {
+    pattern: Vec<i32>,
+    window: VecDeque<i8>,
+    rotation: usize,
+    pending: Option<i8>,
+}
+
+
+impl RotationSync {
+    fn new() -> Self {
+        let mut encoder = ConvolutionalEncoder::new(CCSDS_POLYS);
+        let mut pattern = Vec::new();
+        for i in (0..32).rev() {
+            let coded = encoder.encode_bit(((ASM >> i) & 1) as u8);
+            // the first six outputs depend on the previous frame
+            if i < 26 {
+                pattern.extend(coded.iter().map(|&b| if b == 1 { 1 } else { -1 }));
+            }
+        }
+
+        Self {
+            window: VecDeque::with_capacity(pattern.len()),
+            pattern,
+            rotation: 0,
+            pending: None,
+        }
+    }
+
+    fn push(&mut self, input: &[i8], output: &mut Vec<i8>) {
+        output.clear();
+        for &soft in input {
+            let Some(a) = self.pending.take() else {
+                self.pending = Some(soft);
+                continue;
+            };
+            self.window.push_back(a);
+            self.window.push_back(soft);
+            if self.window.len() < self.pattern.len() {
+                continue;
+            }
+
+            let energy: i32 = self.window.iter().map(|&v| (v as i32).abs()).sum();
+            if energy > 0 {
+                let mut best = (0, 0);
+                for rotation in 0..8 {
+                    let mut acc = 0;
+                    for k in (0..self.pattern.len()).step_by(2) {
+                        let (x, y) = rotate(self.window[k], self.window[k + 1], rotation);
+                        acc += self.pattern[k] * x as i32 + self.pattern[k + 1] * y as i32;
+                    }
+                    if acc > best.0 {
+                        best = (acc, rotation);
+                    }
+                }
+                if best.0 * 10 > energy * 8 {
+                    self.rotation = best.1;
+                }
+            }
+
+            let a = self.window.pop_front().unwrap();
+            let b = self.window.pop_front().unwrap();
+            let (x, y) = rotate(a, b, self.rotation);
+            output.push(x);
+            output.push(y);
+        }
+    }
+}
+
+
+/// Lock onto the sync marker inserted every 80 bits by the Meteor interleaver and strip it.
+struct MarkerSync {
+    buffer: VecDeque<i8>,
+    locked: Option<usize>,
+    misses: usize,
+}
+
+
+impl MarkerSync {
+    const CONFIRM: usize = 4;
+
+    fn new() -> Self {
+        Self {
+            buffer: VecDeque::new(),
+            locked: None,
+            misses: 0,
+        }
+    }
+
+    fn marker_errors(&self, at: usize, rotation: usize) -> u32 {
+        let mut bits = 0u8;
+        for k in (0..8).step_by(2) {
+            let (x, y) = rotate(self.buffer[at + k], self.buffer[at + k + 1], rotation);
+            bits = (bits << 2) | (((x > 0) as u8) << 1) | (y > 0) as u8;
+        }
+        (bits ^ INTERLEAVE_MARKER).count_ones()
+    }
+
+    fn push(&mut self, input: &[i8], output: &mut Vec<i8>) {
+        output.clear();
+        self.buffer.extend(input.iter().copied());
+
+        loop {
+            match self.locked {
+                None => {
+                    if self.buffer.len() < INTERLEAVE_STRIDE * Self::CONFIRM {
+                        break;
+                    }
+                    let found = (0..8).find(|&rotation| {
+                        (0..Self::CONFIRM).all(|n| self.marker_errors(n * INTERLEAVE_STRIDE, rotation) <= 1)
+                    });
+                    match found {
+                        Some(rotation) => {
+                            self.locked = Some(rotation);
+                            self.misses = 0;
+                        }
+                        None => {
+                            // markers sit on symbol boundaries, so slide by a whole symbol
+                            self.buffer.drain(..2);
+                        }
+                    }
+                }
+                Some(rotation) => {
+                    if self.buffer.len() < INTERLEAVE_STRIDE {
+                        break;
+                    }
+                    if self.marker_errors(0, rotation) > 2 {
+                        self.misses += 1;
+                        if self.misses > Self::CONFIRM {
+                            self.locked = None;
+                            continue;
+                        }
+                    } else {
+                        self.misses = 0;
+                    }
+                    self.buffer.drain(..8);
+                    for _ in (0..INTERLEAVE_STRIDE - 8).step_by(2) {
+                        let a = self.buffer.pop_front().unwrap();
+                        let b = self.buffer.pop_front().unwrap();
+                        let (x, y) = rotate(a, b, rotation);
+                        output.push(x);
+                        output.push(y);
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+/// Find the attached sync marker in the decoded bit stream, derandomize and Reed-Solomon correct each frame.
+struct Deframer {
+    shift: u32,
+    frame: Option<(Vec<u8>, bool)>,
+    derandomizer: Derandomizer,
+    rs: ReedSolomon,
+    frames_ok: usize,
+    frames_bad: usize,
+}
+
+
+impl Deframer {
+    fn new() -> Self {
+        Self {
+            shift: 0,
+            frame: None,
+            derandomizer: Derandomizer::new(CADU_LEN - 4),
+            rs: ReedSolomon::ccsds(true),
+            frames_ok: 0,
+            frames_bad: 0,
+        }
+    }
+
+    fn push(&mut self, bits: &[u8], output: &mut Vec<Cadu>) {
+        for &bit in bits {
+            self.shift = (self.shift << 1) | bit as u32;
+
+            match self.frame.as_mut() {
+                None => {
+                    if (self.shift ^ ASM).count_ones() <= 3 {
+                        self.frame = Some((Vec::with_capacity((CADU_LEN - 4) * 8), false));
+                    } else if (self.shift ^ !ASM).count_ones() <= 3 {
+                        self.frame = Some((Vec::with_capacity((CADU_LEN - 4) * 8), true));
+                    }
+                }
+                Some((frame_bits, invert)) => {
+                    frame_bits.push(bit ^ *invert as u8);
+                    if frame_bits.len() == (CADU_LEN - 4) * 8 {
+                        let (frame_bits, _) = self.frame.take().unwrap();
+                        if let Some(cadu) = self.finish(&frame_bits) {
+                            output.push(cadu);
+                        }
+                        self.shift = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, bits: &[u8]) -> Option<Cadu> {
+        let mut cadu = [0u8; CADU_LEN];
+        cadu[..4].copy_from_slice(&ASM.to_be_bytes());
+        for (i, byte) in bits.chunks(8).enumerate() {
+            cadu[4 + i] = byte.iter().fold(0, |acc, &b| (acc << 1) | b);
+        }
+        self.derandomizer.apply(&mut cadu[4..]);
+
+        let data = &mut cadu[4..];
+        for i in 0..4 {
+            let mut codeword = [0u8; ReedSolomon::N];
+            for j in 0..ReedSolomon::N {
+                codeword[j] = data[i + 4 * j];
+            }
+            if self.rs.decode(&mut codeword).is_err() {
+                self.frames_bad += 1;
+                return None;
+            }
+            for j in 0..ReedSolomon::N {
+                data[i + 4 * j] = codeword[j];
+            }
+        }
+
+        self.frames_ok += 1;
+        Some(cadu)
+    }
+}
+
+
+/// Turn Meteor LRPT soft symbols (interleaved I/Q, positive is a one) into corrected CADUs.
+pub struct LrptDecoder {
+    marker: Option<MarkerSync>,
+    deinterleaver: Option<ConvolutionalDeinterleaver<i8>>,
+    rotation: RotationSync,
+    viterbi: ViterbiDecoder,
+    differential: Option<u8>,
+    deframer: Deframer,
+    buff0: Vec<i8>,
+    buff1: Vec<i8>,
+    bits: Vec<u8>,
+}
+
+
+impl LrptDecoder {
+    pub fn new(config: LrptConfig) -> Self {
+        Self {
+            marker: config.interleaved.then(MarkerSync::new),
+            deinterleaver: config.interleaved.then(|| ConvolutionalDeinterleaver::new(config.interleave_branches, config.interleave_delay)),
+            rotation: RotationSync::new(),
+            viterbi: ViterbiDecoder::new(CCSDS_POLYS, 96),
+            differential: config.differential.then_some(0),
+            deframer: Deframer::new(),
+            buff0: Vec::new(),
+            buff1: Vec::new(),
+            bits: Vec::new(),
+        }
+    }
+
+    pub fn frames_ok(&self) -> usize {
+        self.deframer.frames_ok
+    }
+
+    pub fn frames_bad(&self) -> usize {
+        self.deframer.frames_bad
+    }
+}
+
+
+impl Filter<i8, Cadu> for LrptDecoder {
+    fn filter(&mut self, input: &[i8], output: &mut Vec<Cadu>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+
+        let soft = match (self.marker.as_mut(), self.deinterleaver.as_mut()) {
+            (Some(marker), Some(deinterleaver)) => {
+                marker.push(input, &mut self.buff0);
+                deinterleaver.filter(&self.buff0, &mut self.buff1)?;
+                self.buff1.as_slice()
+            }
+            _ => input,
+        };
+
+        self.rotation.push(soft, &mut self.buff0);
+        self.viterbi.filter(&self.buff0, &mut self.bits)?;
+
+        if let Some(prev) = self.differential.as_mut() {
+            for bit in self.bits.iter_mut() {
+                let raw = *bit;
+                *bit ^= *prev;
+                *prev = raw;
+            }
+        }
+
+        self.deframer.push(&self.bits, output);
+        Ok(())
+    }
+}
+
+
+/// Complete Meteor-M LRPT receive chain from complex baseband to CADUs.
+pub struct LrptReceiver {
+    demod: QPSKDemod,
+    decoder: LrptDecoder,
+    symbols: Vec<Complex32>,
+    soft: Vec<i8>,
+}
+
+
+impl LrptReceiver {
+    pub fn new(sample_rate: u32, config: LrptConfig) -> Self {
+        Self {
+            demod: QPSKDemod::new(sample_rate, config.symbol_rate, 0.6, 65, config.offset),
+            decoder: LrptDecoder::new(config),
+            symbols: Vec::new(),
+            soft: Vec::new(),
+        }
+    }
+
+    pub fn decoder(&self) -> &LrptDecoder {
+        &self.decoder
+    }
+}
+
+
+impl Filter<Complex32, Cadu> for LrptReceiver {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Cadu>) -> Result<(), Box<dyn Error>> {
+        self.demod.filter(input, &mut self.symbols)?;
+        soft_bits(&self.symbols, 80.0, &mut self.soft);
+        self.decoder.filter(&self.soft, output)
+    }
+}
+
+
+pub struct CaduFileSink {
+    writer: BufWriter<File>,
+}
+
+
+impl CaduFileSink {
+    pub fn new(path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+}
+
+
+impl Sink<Cadu> for CaduFileSink {
+    fn write(&mut self, src: &[Cadu]) -> Result<(), Box<dyn Error>> {
+        for cadu in src {
+            self.writer.write_all(cadu)?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::fec::{ConvolutionalEncoder, Derandomizer, ReedSolomon, CCSDS_POLYS};
+    use crate::lrpt::{rotate, Cadu, LrptConfig, LrptDecoder, ASM, CADU_LEN, INTERLEAVE_MARKER};
+    use crate::traits::Filter;
+
+    fn make_cadu(seed: u8) -> (Cadu, Cadu) {
+        let rs = ReedSolomon::ccsds(true);
+        let mut clean = [0u8; CADU_LEN];
+        clean[..4].copy_from_slice(&ASM.to_be_bytes());
+        for i in 0..4 {
+            let mut codeword = [0u8; ReedSolomon::N];
+            for (j, b) in codeword.iter_mut().enumerate().take(ReedSolomon::K) {
+                *b = (j as u8).wrapping_mul(seed).wrapping_add(i as u8);
+            }
+            rs.encode(&mut codeword);
+            for (j, &b) in codeword.iter().enumerate() {
+                clean[4 + i + 4 * j] = b;
+            }
+        }
+        let mut sent = clean;
+        Derandomizer::new(CADU_LEN - 4).apply(&mut sent[4..]);
+        (clean, sent)
+    }
+
+    fn decode(config: LrptConfig) -> Result<(), Box<dyn std::error::Error>> {
+        let frames: Vec<(Cadu, Cadu)> = (1..=4).map(|s| make_cadu(s * 3 + 1)).collect();
+
+        let mut bits = Vec::new();
+        for (_, sent) in frames.iter() {
+            for byte in sent {
+                bits.extend((0..8).rev().map(|i| (byte >> i) & 1));
+            }
+        }
+        if config.differential {
+            let mut prev = 0;
+            for bit in bits.iter_mut() {
+                *bit ^= prev;
+                prev = *bit;
+            }
+        }
+
+        let mut coded = Vec::new();
+        ConvolutionalEncoder::new(CCSDS_POLYS).filter(&bits, &mut coded)?;
+        let mut soft: Vec<i8> = coded.iter().map(|&b| if b == 1 { 60 } else { -60 }).collect();
+
+        if config.interleaved {
+            let branches = config.interleave_branches;
+            let mut lines: Vec<Vec<i8>> = (0..branches).map(|b| vec![0; b * config.interleave_delay]).collect();
+            let mut interleaved = Vec::new();
+            for (i, &v) in soft.iter().enumerate() {
+                let line = &mut lines[i % branches];
+                line.push(v);
+                interleaved.push(line.remove(0));
+            }
+            soft.clear();
+            for chunk in interleaved.chunks(72) {
+                soft.extend((0..8).rev().map(|i| if (INTERLEAVE_MARKER >> i) & 1 == 1 { 60 } else { -60 }));
+                soft.extend_from_slice(chunk);
+            }
+        }
+
+        // apply a 90 degree rotation the decoder has to undo
+        for pair in soft.chunks_mut(2) {
+            let (x, y) = rotate(pair[0], pair[1], 3);
+            pair[0] = x;
+            pair[1] = y;
+        }
+
+        let mut decoder = LrptDecoder::new(config);
+        let mut cadus = Vec::new();
+        let mut output = Vec::new();
+        for chunk in soft.chunks(4096) {
+            decoder.filter(chunk, &mut output)?;
+            cadus.extend_from_slice(&output);
+        }
+
+        assert!(cadus.len() >= 2, "only decoded {} frames", cadus.len());
+        for cadu in cadus.iter() {
+            assert!(frames.iter().any(|(clean, _)| clean == cadu));
+        }
+        assert_eq!(decoder.frames_bad(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_72k() -> Result<(), Box<dyn std::error::Error>> {
+        decode(LrptConfig::meteor_72k())
+    }
+
+    #[test]
+    fn test_decode_interleaved() -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = LrptConfig::meteor_80k();
+        config.interleave_delay = 4;
+        decode(config)
+    }
+
+}
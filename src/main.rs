@@ -1,19 +1,131 @@
+use std::convert::Infallible;
 use std::error::Error;
-use std::io::Read;
-use std::path::PathBuf;
-use std::time::Instant;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use bitvec::prelude::*;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use cpal::traits::{DeviceTrait, HostTrait};
 use libhackrf::ffi::HackrfDevice;
 use libhackrf::HackRf;
 use num_complex::Complex32;
 use crate::traits::{Filter, Sink, Source};
 use crate::block::*;
-use crate::util::BufferBank;
+use crate::pipeline::{CancelToken, RunStats};
+use crate::flowgraph::Filtered;
+use crate::scheduler::Scheduler;
+use crate::rate::RateAware;
+use crate::state::{Primer, WarmStart};
+use crate::split::{concat_raw, concat_wav, raw_frame_size, split_raw, split_wav, SplitLimit};
+use crate::cor::{Cor, ExecHook};
+use crate::corpus::{default_corpus, CorpusGenerator};
+use crate::demod::{AMDemod, DemodMode, DemodSelector};
+use crate::classify::ModulationClassifier;
+use crate::smeter::{CalibrationTable, SMeter};
+use crate::agc::{Agc, AgcPreset};
+use crate::settings::{LastTuned, Settings};
+use crate::recorder::{load_channels, Scanner, Squelch, TransmissionRecorder};
+use crate::tuning::{Tunable, TunedSource};
+use crate::gsm::{FcchDetector, GsmBand, GsmChannel};
+use crate::iqfile::{IQFileSink, IQFormat, MmapIQSource};
+use crate::playlist::PlaylistSource;
+use crate::checksum::{sidecar_path, ChunkHashes, HashingWriter, DEFAULT_CHUNK_SIZE};
+use crate::occupancy::OccupancyLog;
+use crate::rtltcp::{control_hackrf, RtlTcpServer};
+use crate::peaks::{PeakDetector, PsdAverage};
+use crate::dtv::{detect_dvbt, find_atsc, DtvSignal};
+use crate::beacon::CarrierTracker;
+use crate::graph::{GraphSpec, Registry};
+use crate::ppm::{estimate_ppm, fcch_estimate, FrequencyReference};
+use crate::stereo::StereoDecoder;
+use crate::timing::ClockMismatch;
+use crate::rds::{RdsDecoder, RdsMessage, TmcDecoder};
+use crate::events::{Event, EventBus};
+use crate::hits::Hit;
+use crate::mqtt::{MqttConfig, MqttSink};
 
 pub mod traits;
 pub mod block;
 pub mod streambuf;
 pub mod util;
+pub mod modem;
+pub mod fec;
+pub mod lrpt;
+pub mod packet;
+pub mod fft;
+pub mod classify;
+pub mod audio;
+pub mod tag;
+pub mod timing;
+pub mod coherent;
+pub mod replay;
+pub mod state;
+pub mod rate;
+pub mod error;
+pub mod gain;
+pub mod plot;
+pub mod export;
+pub mod gnuradio;
+pub mod vita49;
+pub mod probe;
+pub mod iter;
+pub mod pipeline;
+pub mod flowgraph;
+pub mod graph;
+pub mod scheduler;
+pub mod demod;
+pub mod channel;
+pub mod smeter;
+pub mod ppm;
+pub mod tuning;
+pub mod frontend;
+pub mod recorder;
+pub mod split;
+pub mod snapshot;
+pub mod ber;
+pub mod requantize;
+pub mod fixedpoint;
+pub mod spur;
+pub mod agc;
+pub mod blanker;
+pub mod corpus;
+pub mod sample;
+pub mod seed;
+pub mod cor;
+pub mod events;
+pub mod hits;
+pub mod mqtt;
+pub mod vfo;
+pub mod settings;
+pub mod sizing;
+pub mod stereo;
+pub mod iqfile;
+pub mod playlist;
+pub mod checksum;
+pub mod subcarrier;
+pub mod rds;
+pub mod ofdm;
+pub mod dab;
+pub mod dtv;
+pub mod udp;
+pub mod gsm;
+pub mod occupancy;
+pub mod peaks;
+pub mod beacon;
+pub mod rtltcp;
+#[cfg(test)]
+mod response;
+#[cfg(feature = "async")]
+pub mod async_io;
+#[cfg(feature = "async")]
+pub mod async_flowgraph;
+#[cfg(feature = "zstd")]
+pub mod compress;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+#[cfg(feature = "websocket")]
+pub mod websdr;
 
 struct Tone {
     freq: f32,
@@ -74,83 +186,1103 @@ fn canonical_path(path: String) -> PathBuf {
 }
 
 
+fn parse_path(arg: &str) -> Result<PathBuf, Infallible> {
+    Ok(canonical_path(arg.to_string()))
+}
+
+
+/// Receivers and tools for the HackRF. Without a command a frequency is listened to as WBFM, and
+/// without that the last tuned frequency and mode. Setting RUST_DSP_SEED makes noise, dither and
+/// the block sizes between threads the same on every run.
+#[derive(Parser, Debug)]
+#[command(name = "rust_dsp", args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Frequency in Hz
+    frequency: Option<u64>,
+    /// Keep only a few milliseconds of audio and radio samples queued, for monitoring your own transmissions
+    #[arg(long)]
+    low_latency: bool,
+    /// Play WBFM in mono even when the station sends stereo
+    #[arg(long)]
+    mono: bool,
+    #[command(flatten)]
+    radio: RadioOptions,
+}
+
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// WBFM broadcast receiver playing on the speakers, in stereo where the station sends it
+    RxFm(Receive),
+    /// AM receiver playing on the speakers
+    RxAm(Receive),
+    /// Record I/Q, the extension picks the format: .wav, .cu8, .cs8, .cs12, .cs16, .cf16, .cf32 or .cfile, any of those but .wav with .zst added compresses it
+    Record {
+        /// Frequency in Hz
+        frequency: u64,
+        #[arg(value_parser = parse_path)]
+        file: PathBuf,
+        #[arg(long, default_value_t = 2_000_000)]
+        sample_rate: u32,
+        /// Seconds to record instead of until interrupted
+        #[arg(long)]
+        duration: Option<f64>,
+        /// Write chunk hashes next to the recording for `verify`
+        #[arg(long)]
+        checksum: bool,
+        #[command(flatten)]
+        radio: RadioOptions,
+    },
+    /// Play a recording, mono WAV as audio and I/Q recordings, .zst compressed too, or stereo WAV through a demodulator
+    Play {
+        #[arg(value_parser = parse_path)]
+        file: PathBuf,
+        /// Needed for headerless I/Q recordings
+        #[arg(long)]
+        sample_rate: Option<u32>,
+        #[arg(long, value_enum, default_value_t = Modulation::Wfm)]
+        mode: Modulation,
+        /// Frequency of the signal relative to the center of the recording in Hz
+        #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+        offset: f32,
+        /// Keep only a few milliseconds of audio queued
+        #[arg(long)]
+        low_latency: bool,
+        /// Play WBFM in mono even when the station sends stereo
+        #[arg(long)]
+        mono: bool,
+    },
+    /// List the HackRF and the audio devices
+    Devices,
+    /// Scan a channel list recording every transmission to its own WAV file, each demodulated as the mode it is classified as
+    Scan {
+        #[arg(value_parser = parse_path)]
+        channels: PathBuf,
+        #[arg(value_parser = parse_path)]
+        dir: PathBuf,
+        /// Squelch level in dBFS
+        #[arg(default_value_t = -40.0, allow_negative_numbers = true)]
+        squelch: f32,
+        /// Command run when the squelch opens and closes
+        cor: Option<String>,
+        /// Add the signals seen around each channel to the scan list
+        #[arg(long)]
+        discover: bool,
+        #[command(flatten)]
+        radio: RadioOptions,
+    },
+    /// Cut a WAV or headerless I/Q recording into pieces by time or size
+    Split {
+        #[arg(value_parser = parse_path)]
+        file: PathBuf,
+        #[arg(value_parser = parse_path)]
+        dir: PathBuf,
+        /// 30s, 10m, 1h, 100M or 2G
+        limit: String,
+        /// Needed to split headerless I/Q by time
+        sample_rate: Option<u32>,
+    },
+    /// Join recordings of the same format, the output extension picks WAV or headerless I/Q
+    Concat {
+        #[arg(value_parser = parse_path)]
+        output: PathBuf,
+        #[arg(value_parser = parse_path, required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Write chunk hashes next to existing recordings for `verify`
+    Checksum {
+        #[arg(value_parser = parse_path, required = true)]
+        files: Vec<PathBuf>,
+        /// Bytes per hash, damage is located to within this
+        #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+        chunk_size: u64,
+    },
+    /// Check recordings against their chunk hashes and list the damaged parts
+    Verify {
+        #[arg(value_parser = parse_path, required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Write labeled test signals with known parameters for checking decoders
+    Generate {
+        #[arg(value_parser = parse_path)]
+        dir: PathBuf,
+        #[arg(default_value_t = 48000)]
+        sample_rate: u32,
+        #[arg(default_value_t = 2.0)]
+        seconds: f32,
+        /// Seed for the noise, the same seed gives the same files
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Measure the crystal error on GSM base stations
+    Calibrate {
+        /// GSM850, GSM900, DCS or PCS
+        #[arg(default_value = "GSM900")]
+        band: String,
+        /// Scan this capture instead, nothing is stored
+        #[arg(value_parser = parse_path)]
+        capture: Option<PathBuf>,
+        /// Center frequency of the capture in Hz
+        center: Option<f64>,
+        /// Sample rate of the capture
+        sample_rate: Option<u32>,
+        #[command(flatten)]
+        radio: RadioOptions,
+    },
+    /// Sweep a span logging how often every bin is in use
+    Occupancy {
+        /// Start frequency in Hz
+        start: f64,
+        /// Stop frequency in Hz
+        stop: f64,
+        /// .csv or .db log
+        #[arg(value_parser = parse_path)]
+        log: PathBuf,
+        #[arg(default_value_t = 10e3)]
+        bin_hz: f64,
+        /// Level in dBFS a bin counts as occupied above
+        #[arg(default_value_t = -70.0, allow_negative_numbers = true)]
+        threshold: f32,
+        #[command(flatten)]
+        radio: RadioOptions,
+    },
+    /// Serve the HackRF to SDR programs speaking rtl_tcp
+    #[command(name = "rtl_tcp")]
+    RtlTcp {
+        #[arg(default_value = "127.0.0.1:1234")]
+        address: String,
+        #[arg(default_value_t = 2_400_000)]
+        sample_rate: u32,
+        #[command(flatten)]
+        radio: RadioOptions,
+    },
+    /// Log the frequency and level of a beacon
+    Beacon {
+        /// Frequency in Hz
+        frequency: u64,
+        #[arg(value_parser = parse_path)]
+        log: PathBuf,
+        /// Range to search around the frequency in Hz
+        #[arg(default_value_t = 1000.0)]
+        search_hz: f64,
+        #[command(flatten)]
+        radio: RadioOptions,
+    },
+    /// Run a receiver described in a JSON or YAML graph file
+    Graph {
+        #[arg(value_parser = parse_path)]
+        file: PathBuf,
+    },
+}
+
+
+#[derive(Args, Debug)]
+struct Receive {
+    /// Frequency in Hz
+    frequency: u64,
+    /// Channel bandwidth in Hz, 150 kHz for WBFM and 10 kHz for AM by default
+    #[arg(long)]
+    bandwidth: Option<u32>,
+    /// Keep only a few milliseconds of audio and radio samples queued, for monitoring your own transmissions
+    #[arg(long)]
+    low_latency: bool,
+    /// Play WBFM in mono even when the station sends stereo
+    #[arg(long)]
+    mono: bool,
+    #[command(flatten)]
+    radio: RadioOptions,
+}
+
+
+/// HackRF settings taking precedence over the settings file, which takes precedence over the
+/// command's defaults.
+#[derive(Args, Clone, Debug, Default)]
+struct RadioOptions {
+    /// RF gain in dB, 0 to 40 in steps of 8
+    #[arg(long)]
+    lna_gain: Option<u32>,
+    /// Baseband gain in dB, 0 to 62 in steps of 2
+    #[arg(long)]
+    vga_gain: Option<u32>,
+    /// Turn on the RF amplifier
+    #[arg(long)]
+    amp: bool,
+}
+
+
+impl RadioOptions {
+    fn hackrf(&self, settings: &Settings, frequency: u64, sample_rate: u32, lna_gain: u32, vga_gain: u32) -> HackRFSourceBuilder {
+        let device_settings = settings.device("hackrf");
+        HackRFSourceBuilder::new(frequency, sample_rate)
+            .lna_gain(self.lna_gain.or(device_settings.lna_gain).unwrap_or(lna_gain))
+            .vga_gain(self.vga_gain.or(device_settings.vga_gain).unwrap_or(vga_gain))
+            .amp(self.amp || device_settings.amp.unwrap_or(false))
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Modulation {
+    /// Broadcast FM with 75 µs de-emphasis
+    Wfm,
+    Am,
+}
+
+
+impl Modulation {
+    fn bandwidth(&self) -> u32 {
+        match self {
+            Modulation::Wfm => 150_000,
+            Modulation::Am => 10_000,
+        }
+    }
+
+    fn mode(&self) -> DemodMode {
+        match self {
+            Modulation::Wfm => DemodMode::FM,
+            Modulation::Am => DemodMode::AM,
+        }
+    }
+}
+
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let args = std::env::args().nth(1).ok_or("missing tune frequency")?;
-    
-    // radio parameters
-    let bandwidth: u32 = 2_000_000;
-    let cutoff_hz = 75e3f32;
-    let sample_rate_audio: u32 = 44100;
+    let cli = Cli::parse();
+    // reproducible runs for comparing results
+    if let Ok(value) = std::env::var("RUST_DSP_SEED") {
+        seed::set_seed(value.parse().map_err(|_| format!("RUST_DSP_SEED has to be a number, not {:?}", value))?);
+        seed::set_deterministic(true);
+    }
+    let mut settings = Settings::load_default_or_warn();
+    match cli.command {
+        Some(Command::RxFm(rx)) => listen(&rx, Modulation::Wfm, &mut settings),
+        Some(Command::RxAm(rx)) => listen(&rx, Modulation::Am, &mut settings),
+        Some(Command::Record { frequency, file, sample_rate, duration, checksum, radio }) => record(frequency, &file, sample_rate, duration, checksum, &radio, &settings),
+        Some(Command::Play { file, sample_rate, mode, offset, low_latency, mono }) => {
+            let output = AudioOutput { device: settings.audio.output_device.as_deref(), low_latency, stereo: !mono };
+            play(&file, sample_rate, mode, offset, output)
+        },
+        Some(Command::Devices) => devices(),
+        Some(Command::Scan { channels, dir, squelch, cor, discover, radio }) => scan(&channels, dir, squelch, cor, discover, &radio, &settings),
+        Some(Command::Split { file, dir, limit, sample_rate }) => split(&file, &dir, &limit, sample_rate),
+        Some(Command::Concat { output, inputs }) => concat(&output, &inputs),
+        Some(Command::Checksum { files, chunk_size }) => checksum(&files, chunk_size),
+        Some(Command::Verify { files }) => verify(&files),
+        Some(Command::Generate { dir, sample_rate, seconds, seed }) => {
+            if let Some(seed) = seed {
+                seed::set_seed(seed);
+            }
+            generate(&dir, sample_rate, seconds)
+        },
+        Some(Command::Calibrate { band, capture, center, sample_rate, radio }) => calibrate(&band, capture, center, sample_rate, &radio, &mut settings),
+        Some(Command::Occupancy { start, stop, log, bin_hz, threshold, radio }) => occupancy(start, stop, &log, bin_hz, threshold, &radio, &settings),
+        Some(Command::RtlTcp { address, sample_rate, radio }) => rtl_tcp(&address, sample_rate, &radio, &settings),
+        Some(Command::Beacon { frequency, log, search_hz, radio }) => beacon(frequency, &log, search_hz, &radio, &settings),
+        Some(Command::Graph { file }) => run_graph(&file),
+        None => {
+            let frequency = cli.frequency.or(settings.last.frequency).ok_or("no frequency given or tuned before, see --help")?;
+            let modulation = match (cli.frequency, settings.last.mode) {
+                (None, Some(DemodMode::AM)) => Modulation::Am,
+                _ => Modulation::Wfm,
+            };
+            let rx = Receive { frequency, bandwidth: None, low_latency: cli.low_latency, mono: cli.mono, radio: cli.radio };
+            listen(&rx, modulation, &mut settings)
+        },
+    }
+}
+
+
+/// Rate every receiver plays its audio at.
+const SAMPLE_RATE_AUDIO: u32 = 44100;
+
+/// How often a receiver prints its latency, the queues settle over the first few seconds.
+const LATENCY_REPORT: Duration = Duration::from_secs(10);
+
+
+fn speakers(sample_rate: u32, channels: u16, device: Option<&str>, low_latency: bool) -> Result<Speakers, Box<dyn Error>> {
+    match low_latency {
+        true => Speakers::with_buffer(sample_rate, channels, device, LOW_LATENCY_AUDIO_BUFFER),
+        false => Speakers::with_device(sample_rate, channels, device),
+    }
+}
+
+
+/// Where and how demodulated audio plays.
+#[derive(Clone, Copy, Debug)]
+struct AudioOutput<'a> {
+    device: Option<&'a str>,
+    low_latency: bool,
+    /// Decode WBFM stereo, mono stations play the same on both channels.
+    stereo: bool,
+}
+
+
+/// Receiver playing on the speakers, remembered as the last tuned frequency and mode.
+fn listen(rx: &Receive, modulation: Modulation, settings: &mut Settings) -> Result<(), Box<dyn Error>> {
+    let (sample_rate, offset) = match modulation {
+        Modulation::Wfm => (4_000_000, -150_000),
+        Modulation::Am => (2_000_000, TunedSource::<HackRFSource>::default_offset(2_000_000)),
+    };
+    let cancel = CancelToken::ctrl_c()?;
+    let mut hackrf = rx.radio.hackrf(settings, rx.frequency, sample_rate, 40, 10)
+        .baseband_bandwidth(sample_rate / 2)
+        .low_latency(rx.low_latency)
+        .build(HackRf::open()?)?;
+    hackrf.set_cancel(&cancel);
+    let source = TunedSource::new(hackrf, sample_rate, rx.frequency, offset)?;
+
+    settings.last = LastTuned { frequency: Some(rx.frequency), mode: Some(modulation.mode()) };
+    if let Err(e) = settings.save_default() {
+        eprintln!("settings not saved: {}", e);
+    }
+    let output_device = settings.audio.output_device.clone();
+    let output = AudioOutput { device: output_device.as_deref(), low_latency: rx.low_latency, stereo: !rx.mono };
+    let events = DecoderEvents { bus: event_bus(settings)?, frequency: Some(rx.frequency) };
+    demodulate(source, sample_rate, modulation, rx.bandwidth, output, events, cancel)
+}
+
+
+/// Where the decoders running next to the audio, e.g. RDS, report what they find.
+#[derive(Clone, Default)]
+struct DecoderEvents {
+    bus: EventBus,
+    /// Frequency the receiver is tuned to, unknown for recordings.
+    frequency: Option<u64>,
+}
+
+
+/// Events go to the MQTT broker from the settings file, if there is one.
+fn event_bus(settings: &Settings) -> Result<EventBus, Box<dyn Error>> {
+    let bus = EventBus::new();
+    if let Some(host) = &settings.events.mqtt_host {
+        let mut config = MqttConfig::new(host);
+        config.port = settings.events.mqtt_port.unwrap_or(config.port);
+        config.topic_prefix = settings.events.topic_prefix.clone().unwrap_or(config.topic_prefix);
+        let mut sink = MqttSink::connect(config)?;
+        let events = bus.subscribe();
+        std::thread::spawn(move || {
+            for event in events {
+                if let Err(e) = sink.write(&[event]) {
+                    eprintln!("mqtt: {}", e);
+                }
+            }
+        });
+    }
+    Ok(bus)
+}
+
+
+/// Demodulates I/Q centered on the signal into the speakers, two channels for stereo WBFM.
+fn demodulate<S>(source: S, sample_rate: u32, modulation: Modulation, bandwidth: Option<u32>, output: AudioOutput, events: DecoderEvents, cancel: CancelToken) -> Result<(), Box<dyn Error>>
+where S: Source<Complex32> + RateAware + Send + 'static {
+    let stereo = modulation == Modulation::Wfm && output.stereo;
+    let sink = speakers(SAMPLE_RATE_AUDIO, if stereo { 2 } else { 1 }, output.device, output.low_latency)?;
+    eprintln!("audio queue {} ms for now", sink.buffer().as_millis());
+    let clock = sink.clock_mismatch();
+    demodulate_into(source, sample_rate, modulation, bandwidth, stereo, sink, Some(clock), events, cancel)?;
+    Ok(())
+}
+
+
+/// Demodulates I/Q centered on the signal into `sink` at `SAMPLE_RATE_AUDIO`, interleaved left and
+/// right when `stereo`. Each block runs on its own core since the resamplers can't keep up with a few
+/// Msps sharing one. `bandwidth` defaults to the modulation's, `clock` steers the audio rate to the
+/// sink's crystal.
+#[allow(clippy::too_many_arguments)]
+fn demodulate_into<S, K>(source: S, sample_rate: u32, modulation: Modulation, bandwidth: Option<u32>, stereo: bool, sink: K, clock: Option<ClockMismatch>, events: DecoderEvents, cancel: CancelToken) -> Result<RunStats, Box<dyn Error>>
+where S: Source<Complex32> + RateAware + Send + 'static, K: Sink<f32> + RateAware {
+    let sample_rate_channel = bandwidth.unwrap_or(modulation.bandwidth());
     let num_taps = 1001;
-    let lna_gain = 40;
-    let rxvga_gain = 10;
-    let tune_freq: f32 = args.as_str().parse()?;
-    
-    
-    let device = HackRf::open()?;
-    let tune_off = -2.0 * cutoff_hz;
-    let tune_hardware = (tune_freq + tune_off) as u64;
-
-    let sample_rate_hardware: u32 = bandwidth * 2;
-    let sample_rate_fm = (2.0 * cutoff_hz) as u32;
-    
-    device.set_sample_rate(sample_rate_hardware)?;
-    device.set_baseband_filter_bandwidth(bandwidth)?;
-    device.set_freq(tune_hardware)?;
-    device.set_amp_enable(false)?;
-    
-    device.set_lna_gain(lna_gain)?;
-    device.set_rxvga_gain(rxvga_gain)?;
-    
-    
-    let mut bank_complex = BufferBank::default();
-    let mut bank_real = BufferBank::<f32>::default();
-
-    let mut source = HackRFSource::new(device, sample_rate_hardware as usize)?;
-    let mut mix = MixerFilter::new(sample_rate_hardware, tune_off);
-    let mut resample0 = RationalResampler::new(sample_rate_hardware, sample_rate_fm, num_taps);
-    let mut demod = FMDemod::new(sample_rate_fm, 75e3);
-    let mut resample1 = RationalResampler::new(sample_rate_fm, sample_rate_audio, num_taps);
-    let mut deemph = DeEmphasisFilter::new(sample_rate_audio, 75e-6);
-    let mut sink = Speakers::new(sample_rate_audio, 1)?;
-    
-    let mut total: u64 = 0;
-    
-    let mut frame = 0;
-    
-    let start = Instant::now();
+
+    let resample0 = RationalResamplerBuilder::new(sample_rate, sample_rate_channel).num_taps(num_taps).build()?;
+    let mut scheduler = Scheduler::new(cancel);
+    scheduler.report_latency(LATENCY_REPORT, |latency| eprintln!("latency {}", latency));
+    if modulation == Modulation::Wfm && stereo {
+        let demod = FMDemodBuilder::new(sample_rate_channel, 75e3).build()?;
+        let mut stereo = StereoDecoder::new(sample_rate_channel, SAMPLE_RATE_AUDIO)?;
+        if let Some(clock) = clock {
+            stereo.correct_drift(clock, 500.0);
+        }
+        // RDS comes off a lossy branch so a slow decoder never holds up the audio, narrow channels
+        // that cut off the subcarrier go without
+        let mut tee = Tee::new();
+        if let Ok(rds) = RdsDecoder::new(sample_rate_channel) {
+            let mpx = tee.lossy_branch(sample_rate_channel as usize / 10)?;
+            std::thread::spawn(move || {
+                if let Err(e) = print_rds(mpx, rds, events) {
+                    eprintln!("rds: {}", e);
+                }
+            });
+        }
+        return scheduler.source(source)
+            .filter(resample0)
+            .filter(demod)
+            .filter(tee)
+            .filter(stereo)
+            .sink(sink)?
+            .run();
+    }
+
+    let resample1 = WarmStart::new(RationalResamplerBuilder::new(sample_rate_channel, SAMPLE_RATE_AUDIO).num_taps(num_taps).build()?, Primer::FirstSample);
+    // the radio and the sound card run off different crystals, keep the queue between them level
+    let mut drift = FractionalResampler::new(SAMPLE_RATE_AUDIO, SAMPLE_RATE_AUDIO);
+    if let Some(clock) = clock {
+        drift.correct_drift(clock, 500.0);
+    }
+
+    match modulation {
+        Modulation::Wfm => {
+            let demod = FMDemodBuilder::new(sample_rate_channel, 75e3).build()?;
+            let deemph = WarmStart::new(DeEmphasisFilter::new(SAMPLE_RATE_AUDIO, 75e-6), Primer::FirstSample);
+            scheduler.source(source)
+                .filter(resample0)
+                .filter(demod)
+                .filter(resample1)
+                .filter(deemph)
+                .filter(drift)
+                .sink(sink)?
+                .run()
+        },
+        Modulation::Am => {
+            // levels the carrier, slow enough not to follow the modulation
+            let agc = Agc::new(sample_rate_channel, AgcPreset::Am);
+            let demod = AMDemod::new(sample_rate_channel);
+            scheduler.source(source)
+                .filter(resample0)
+                .filter(agc)
+                .filter(demod)
+                .filter(resample1)
+                .filter(drift)
+                .sink(sink)?
+                .run()
+        },
+    }
+}
+
+
+/// Prints the station name, radiotext and TMC traffic messages found in the MPX on stderr as they
+/// change, and publishes them and every group.
+fn print_rds(mut mpx: TeeOutput<f32>, mut decoder: RdsDecoder, events: DecoderEvents) -> Result<(), Box<dyn Error>> {
+    let mut tmc = TmcDecoder::new();
+    let text = |source: &str, text: &str| if let Some(frequency) = events.frequency {
+        events.bus.publish(Event::Hit(Hit {
+            time: SystemTime::now(),
+            frequency,
+            duration: Duration::ZERO,
+            rssi_db: None,
+            source: source.into(),
+            text: Some(text.to_string()),
+        }));
+    };
+    let (mut block, mut messages) = (Vec::new(), Vec::new());
     loop {
-        let (src, dst) = bank_complex.swap();
-        if let Ok(()) = source.read(src) {
-            total += src.len() as u64;
-            if src.len() == 0 || start.elapsed().as_secs_f32() > u64::MAX as f32 {
-                break;
+        mpx.read(&mut block)?;
+        if block.is_empty() {
+            return Ok(());
+        }
+        decoder.filter(&block, &mut messages)?;
+        for message in &messages {
+            match message {
+                RdsMessage::StationName { name, .. } => {
+                    eprintln!("station {}", name);
+                    text("rds", name);
+                },
+                RdsMessage::RadioText { text: radiotext, .. } => {
+                    eprintln!("radiotext {}", radiotext);
+                    text("rds", radiotext);
+                },
+                RdsMessage::Group(group) => {
+                    events.bus.publish(Event::Packet {
+                        time: SystemTime::now(),
+                        source: "rds".into(),
+                        frequency: events.frequency,
+                        payload: group.blocks.iter().flat_map(|block| block.to_be_bytes()).collect(),
+                        crc_ok: true,
+                    });
+                    if let Some(message) = tmc.push(group) {
+                        eprintln!("traffic {}", message);
+                        text("tmc", &message.to_string());
+                    }
+                },
             }
+        }
+    }
+}
+
+
+/// I/Q straight from the HackRF into a file until interrupted or for `duration` seconds. Headerless
+/// recordings are hashed while they're written, WAV and `.zst` compressed files once they're finished.
+fn record(frequency: u64, file: &Path, sample_rate: u32, duration: Option<f64>, checksum: bool, radio: &RadioOptions, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    fn capture<K: Sink<Complex32> + RateAware>(scheduler: &mut Scheduler, source: HackRFSource, sink: K, duration: Option<f64>) -> Result<RunStats, Box<dyn Error>> {
+        let mut graph = scheduler.source(source).sink(sink)?;
+        match duration {
+            Some(seconds) => graph.run_for(Duration::from_secs_f64(seconds)),
+            None => graph.run(),
+        }
+    }
+
+    let cancel = CancelToken::ctrl_c()?;
+    let mut hackrf = radio.hackrf(settings, frequency, sample_rate, 16, 16).build(HackRf::open()?)?;
+    hackrf.set_cancel(&cancel);
+    let mut scheduler = Scheduler::new(cancel.clone());
+    let stats = match IQFormat::from_path(file) {
+        Some(format) => {
+            let writer = HashingWriter::new(BufWriter::new(File::create(file)?), DEFAULT_CHUNK_SIZE);
+            let mut sink = IQFileSink::new(writer, format);
+            let stats = capture(&mut scheduler, hackrf, &mut sink, duration)?;
+            let (_, hashes) = sink.into_inner()?.finish();
+            if checksum {
+                hashes.save(&sidecar_path(file))?;
+            }
+            stats
+        },
+        #[cfg(feature = "zstd")]
+        None if file.extension().is_some_and(|extension| extension == "zst") => {
+            let mut sink = IQFileSink::create_zstd(file.to_path_buf(), None, 3)?;
+            let stats = capture(&mut scheduler, hackrf, &mut sink, duration)?;
+            sink.into_inner()?.finish()?;
+            if checksum {
+                ChunkHashes::of_file(file, DEFAULT_CHUNK_SIZE)?.save(&sidecar_path(file))?;
+            }
+            stats
+        },
+        #[cfg(not(feature = "zstd"))]
+        None if file.extension().is_some_and(|extension| extension == "zst") => {
+            return Err("compressed recordings need the zstd feature".into());
+        },
+        None => {
+            let stats = capture(&mut scheduler, hackrf, WavSink::new_file(sample_rate, 2, file.to_path_buf())?, duration)?;
+            if checksum {
+                ChunkHashes::of_file(file, DEFAULT_CHUNK_SIZE)?.save(&sidecar_path(file))?;
+            }
+            stats
+        },
+    };
+    // a timed run leaves the device thread running
+    cancel.cancel();
+    eprintln!("{} samples in {:.1} s", stats.samples, stats.elapsed.as_secs_f64());
+    Ok(())
+}
 
-            mix.filter(src, dst)?;
-            let (src, dst) = bank_complex.swap();
-            resample0.filter(src, dst)?;
 
-            // WBFM Mono start
-            let (src, _) = bank_complex.swap();
-            let (_, dst) = bank_real.swap();
-            demod.filter(src, dst)?;
-            
-            let (src, dst) = bank_real.swap();
-            resample1.filter(src, dst)?;
+/// Plays a recording until it ends, using the output device from the settings.
+fn play(file: &Path, sample_rate: Option<u32>, modulation: Modulation, offset_hz: f32, output: AudioOutput) -> Result<(), Box<dyn Error>> {
+    let source = PlaylistSource::new(vec![file.to_path_buf()], sample_rate)?;
+    let rate = source.output_rate(None).ok_or("recording without a sample rate")?.0;
+    let cancel = CancelToken::ctrl_c()?;
+    if !source.is_complex() {
+        let sink = speakers(rate, 1, output.device, output.low_latency)?;
+        Scheduler::new(cancel).source::<f32, _>(source).sink(sink)?.run()?;
+        return Ok(());
+    }
+    let source: Filtered<_, _, Complex32> = Filtered::new(source, MixerFilter::new(rate, -offset_hz));
+    demodulate(source, rate, modulation, None, output, DecoderEvents::default(), cancel)
+}
+
 
-            let (src, dst) = bank_real.swap();
-            deemph.filter(src, dst)?;
-            // WBFM Mono end
-            
-            sink.write(dst.as_slice())?;
-            frame += 1;
+/// The HackRF and the audio devices the settings file's `output_device` can name.
+fn devices() -> Result<(), Box<dyn Error>> {
+    match HackRf::open() {
+        Ok(device) => {
+            println!("HackRF {} firmware {}", hackrf_serial(&device)?, device.version());
+        },
+        Err(e) => println!("no HackRF: {}", e),
+    }
+    let host = cpal::default_host();
+    let default_output = host.default_output_device().and_then(|device| device.name().ok());
+    let default_input = host.default_input_device().and_then(|device| device.name().ok());
+    for (kind, devices, default) in [("output", host.output_devices()?.collect::<Vec<_>>(), default_output), ("input", host.input_devices()?.collect(), default_input)] {
+        for device in devices {
+            let name = device.name()?;
+            let marker = if default.as_ref() == Some(&name) { " (default)" } else { "" };
+            println!("audio {}: {}{}", kind, name, marker);
         }
     }
+    Ok(())
+}
+
+
+/// Scanner tape recorder: scans the channel list and records every transmission to its own WAV file,
+/// demodulated as NFM until the classifier has picked the mode it sounds like.
+fn scan(channels: &Path, dir: PathBuf, threshold_db: f32, cor_command: Option<String>, discover: bool, radio: &RadioOptions, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    const CHANNEL_SPACING: u64 = 12_500;
+    let channels = load_channels(channels)?;
+    let mut cor = Cor::new();
+    if let Some(command) = cor_command {
+        cor.add_hook(Box::new(ExecHook::new(&command)));
+    }
 
-    // println!("samples captured: {}", total);
+    let sample_rate_hardware: u32 = 2_000_000;
+    let sample_rate_audio: u32 = 16_000;
 
+    let cancel = CancelToken::ctrl_c()?;
+    let mut hackrf = radio.hackrf(settings, channels[0].frequency, sample_rate_hardware, 32, 20).build(HackRf::open()?)?;
+    hackrf.set_cancel(&cancel);
+    let mut meter = smeter(&hackrf, channels[0].frequency);
+    let offset = TunedSource::<HackRFSource>::default_offset(sample_rate_hardware);
+    let mut source = TunedSource::new(hackrf, sample_rate_hardware, channels[0].frequency, offset)?;
+    let mut resample = RationalResamplerBuilder::new(sample_rate_hardware, sample_rate_audio).num_taps(1001).build()?;
+    // every transmission starts out as NFM until the classifier has heard enough of it
+    let mut demod = DemodSelector::new(sample_rate_audio, DemodMode::FM);
+    let classifier = ModulationClassifier::new(sample_rate_audio, 256, sample_rate_audio as usize / 4);
+    let mut heard = Vec::new();
+    let mut squelch = Squelch::new(threshold_db, (sample_rate_audio / 2) as usize);
+    let mut scanner = Scanner::new(channels, (sample_rate_audio / 10) as usize, (sample_rate_audio / 100) as usize);
+    #[cfg(feature = "sqlite")]
+    let hits = hits::HitLog::open(&dir.join("hits.db"))?;
+    let mut recorder = TransmissionRecorder::new(dir, sample_rate_audio)?;
+    recorder.set_min_duration(Duration::from_millis(250));
+    let events = event_bus(settings)?;
+    recorder.set_events(&events);
+    let mut peak_db = f32::NEG_INFINITY;
+    // the strongest the channel got during the dwell, published when moving on
+    let mut dwell_db = f32::NEG_INFINITY;
+    // with discovery every dwell also looks for signals in the rest of the capture
+    let mut psd = PsdAverage::new(1024);
+    let mut detector = PeakDetector::new(10.0);
+    detector.set_min_bins(2);
+    let bin_hz = sample_rate_hardware as f64 / 1024.0;
+
+    let mut block = Vec::new();
+    let mut channel = Vec::new();
+    let mut audio = Vec::new();
+    while !cancel.is_cancelled() {
+        source.read(&mut block)?;
+        if block.is_empty() {
+            break;
+        }
+        resample.filter(&block, &mut channel)?;
+        if scanner.settling() {
+            scanner.update(channel.len(), false);
+            continue;
+        }
+        if discover {
+            psd.push(&block);
+        }
+
+        let power = channel.iter().map(|x| x.norm_sqr()).sum::<f32>() / channel.len().max(1) as f32;
+        let level_db = 10.0 * power.max(1e-20).log10();
+        let open = squelch.update(level_db, channel.len());
+        // a failing hook shouldn't stop the scan, it gets the next edge all the same
+        if let Err(e) = cor.update(open, scanner.channel()) {
+            eprintln!("cor: {}", e);
+        }
+        if open && !recorder.recording() {
+            recorder.start(scanner.channel(), SystemTime::now())?;
+            peak_db = level_db;
+            demod.set_mode(DemodMode::FM);
+            heard.clear();
+            eprintln!("{} {}: open, {}", scanner.channel().frequency, scanner.channel().label, meter.reading(level_db));
+        } else if !open && recorder.recording() && let Some(recording) = recorder.stop()? {
+            eprintln!("{} {}: {:.1} s, peak {}", recording.channel.frequency, recording.channel.label,
+                      recording.duration.as_secs_f32(), meter.reading(peak_db));
+            #[cfg(feature = "sqlite")]
+            hits.log(&Hit {
+                time: recording.start,
+                frequency: recording.channel.frequency,
+                duration: recording.duration,
+                rssi_db: Some(peak_db),
+                source: "scanner".into(),
+                text: None,
+            })?;
+        }
+        // parked on a transmission, its demodulator is picked once a classification window is in
+        if open && heard.len() < classifier.window_len() {
+            heard.extend_from_slice(&channel);
+            if heard.len() >= classifier.window_len() && let Some(mode) = classifier.classify(&heard).demod_mode() && mode != demod.mode() {
+                eprintln!("{} {}: {:?}", scanner.channel().frequency, scanner.channel().label, mode);
+                demod.set_mode(mode);
+            }
+        }
+        peak_db = peak_db.max(level_db);
+        dwell_db = dwell_db.max(level_db);
+        demod.filter(&channel, &mut audio)?;
+        recorder.write(&audio)?;
+
+        let tuned = scanner.channel().frequency;
+        if let Some(next) = scanner.update(channel.len(), open) {
+            let next = next.frequency;
+            events.publish(Event::Telemetry { time: SystemTime::now(), frequency: tuned, rssi_db: dwell_db });
+            dwell_db = f32::NEG_INFINITY;
+            if let Some(power_db) = psd.average_db() {
+                // only the middle 3/4 is inside the baseband filter
+                let (from, to) = (power_db.len() / 8, power_db.len() - power_db.len() / 8);
+                let start_hz = tuned as f64 - sample_rate_hardware as f64 / 2.0 + from as f64 * bin_hz;
+                let signals = detector.detect(&power_db[from..to], start_hz, bin_hz);
+                let added = scanner.add_channels(signals.iter().map(|signal| signal.scan_channel(CHANNEL_SPACING as f64)), CHANNEL_SPACING);
+                if added > 0 {
+                    eprintln!("discovered {} channels, scanning {}", added, scanner.channels().len());
+                }
+            }
+            psd.reset();
+            source.set_frequency(next)?;
+            meter.set_frequency(next as f64);
+            squelch.reset();
+        }
+    }
+    if let Err(e) = cor.update(false, scanner.channel()) {
+        eprintln!("cor: {}", e);
+    }
+    recorder.stop()?;
+    Ok(())
+}
+
+
+/// S-meter for the HackRF at the gain it was built with, calibrated from the table in the config
+/// directory when there is one and in dBFS otherwise.
+fn smeter(hackrf: &HackRFSource, frequency: u64) -> SMeter {
+    let table = CalibrationTable::default_path().filter(|path| path.exists()).and_then(|path| {
+        CalibrationTable::load(&path).map_err(|e| eprintln!("s-meter calibration not loaded: {}", e)).ok()
+    });
+    let gain = hackrf.control().gain().map_or(0, |gain| gain.total_db());
+    SMeter::new(table.unwrap_or_default(), "hackrf", gain, frequency as f64)
+}
+
+
+/// Cut a WAV or headerless IQ recording into pieces by time or size.
+fn split(path: &Path, dir: &Path, limit: &str, sample_rate: Option<u32>) -> Result<(), Box<dyn Error>> {
+    let limit = SplitLimit::parse(limit)?;
+    let pieces = match raw_frame_size(path) {
+        Some(frame_size) => split_raw(path, dir, frame_size, sample_rate, limit)?,
+        None => split_wav(path, dir, limit)?,
+    };
+    for piece in pieces {
+        println!("{}", piece.display());
+    }
+    Ok(())
+}
+
+
+/// Join recordings of the same format, the output extension picks WAV or headerless IQ.
+fn concat(output: &Path, inputs: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let frames = match raw_frame_size(output) {
+        Some(frame_size) => concat_raw(inputs, output, frame_size)?,
+        None => concat_wav(inputs, output)?,
+    };
+    println!("{} frames written to {}", frames, output.display());
+    Ok(())
+}
+
+
+/// Hashes recordings that were made without `--checksum`.
+fn checksum(files: &[PathBuf], chunk_size: u64) -> Result<(), Box<dyn Error>> {
+    for file in files {
+        let sidecar = sidecar_path(file);
+        ChunkHashes::of_file(file, chunk_size)?.save(&sidecar)?;
+        println!("{}", sidecar.display());
+    }
+    Ok(())
+}
+
+
+/// Fails when any of the recordings is damaged, after listing the damage in all of them.
+fn verify(files: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let mut damaged = 0;
+    for file in files {
+        let bad = ChunkHashes::load(&sidecar_path(file))?.verify(file)?;
+        if bad.is_empty() {
+            println!("{}: ok", file.display());
+            continue;
+        }
+        damaged += 1;
+        for range in bad {
+            match IQFormat::from_path(file).map(|format| format.frame_size() as u64) {
+                Some(frame_size) => println!("{}: bytes {}..{}, samples {}..{}", file.display(), range.start, range.end,
+                                             range.start / frame_size, range.end.div_ceil(frame_size)),
+                None => println!("{}: bytes {}..{}", file.display(), range.start, range.end),
+            }
+        }
+    }
+    match damaged {
+        0 => Ok(()),
+        damaged => Err(format!("{} of {} recordings damaged", damaged, files.len()).into()),
+    }
+}
+
+
+/// Labeled test signals with known parameters for checking decoders.
+fn generate(dir: &Path, sample_rate: u32, seconds: f32) -> Result<(), Box<dyn Error>> {
+    for path in CorpusGenerator::new(sample_rate, seconds).write(dir, &default_corpus())? {
+        println!("{}", path.display());
+    }
+    println!("{}", dir.join("labels.csv").display());
+    Ok(())
+}
+
+
+/// Kalibrate style crystal calibration: scans `band` for GSM base stations, measures the frequency
+/// error on the one with the most frequency correction bursts and stores it for the HackRF. Given a
+/// capture file only that capture is scanned and nothing is stored.
+fn calibrate(band: &str, capture: Option<PathBuf>, center: Option<f64>, sample_rate: Option<u32>, radio: &RadioOptions, settings: &mut Settings) -> Result<(), Box<dyn Error>> {
+    const SAMPLE_RATE: u32 = 2_000_000;
+    let band = GsmBand::from_name(band).ok_or("unknown band, expected GSM850, GSM900, DCS or PCS")?;
+    let strongest = |channels: Vec<GsmChannel>, best: &mut Option<GsmChannel>| {
+        for channel in channels {
+            eprintln!("ARFCN {} {:.1} MHz: {} bursts, {:+.0} Hz", channel.arfcn, channel.frequency_hz / 1e6, channel.bursts.len(), channel.offset_hz().unwrap_or(0.0));
+            if best.as_ref().is_none_or(|best| channel.bursts.len() > best.bursts.len()) {
+                *best = Some(channel);
+            }
+        }
+    };
+
+    if let Some(path) = capture {
+        let tuned = center.ok_or("missing capture center frequency")?;
+        let sample_rate = sample_rate.ok_or("missing capture sample rate")?;
+        let source = MmapIQSource::open(&path, None, sample_rate)?;
+        let detector = FcchDetector::new(sample_rate)?;
+        let mut best = None;
+        // a second at a time, the capture can be far larger than memory
+        let channels = detector.scan_windows(source.len(), sample_rate as usize, |start, count, dst| source.read_at(start, count, dst), tuned, band);
+        strongest(channels, &mut best);
+        let channel = best.ok_or("no GSM base station in the capture")?;
+        let estimate = fcch_estimate(&channel.bursts, detector.burst_len(), source.len(), tuned).ok_or("no GSM base station in the capture")?;
+        println!("ARFCN {}: {:+.2} ppm", channel.arfcn, estimate.ppm);
+        return Ok(());
+    }
+
+    let frequencies: Vec<f64> = band.arfcns().into_iter().filter_map(|arfcn| band.downlink_hz(arfcn)).collect();
+    let low = frequencies.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = frequencies.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    // a quarter second holds five bursts of every base station, measured without any stored correction
+    let mut hackrf = radio.hackrf(settings, low as u64, SAMPLE_RATE, 40, 20)
+        .samples_per_frame(SAMPLE_RATE as usize / 4)
+        .ppm(0.0)
+        .build(HackRf::open()?)?;
+    let detector = FcchDetector::new(SAMPLE_RATE)?;
+    let mut capture = Vec::new();
+    let mut best = None;
+    // 1 MHz steps keep every channel inside the middle of some capture
+    let mut tuned = low + 500e3;
+    while tuned < high + 500e3 {
+        hackrf.set_frequency(tuned as u64)?;
+        // the first frame after retuning is still settling
+        hackrf.read(&mut capture)?;
+        hackrf.read(&mut capture)?;
+        strongest(detector.scan(&capture, tuned, band), &mut best);
+        tuned += 1e6;
+    }
+    let channel = best.ok_or("no GSM base station found")?;
+
+    // tuned right on the carrier the tone stays clear of the DC spike
+    hackrf.set_frequency(channel.frequency_hz as u64)?;
+    hackrf.read(&mut capture)?;
+    let mut samples = Vec::new();
+    for _ in 0..4 {
+        hackrf.read(&mut capture)?;
+        samples.extend_from_slice(&capture);
+    }
+    let estimate = estimate_ppm(&samples, SAMPLE_RATE, channel.frequency_hz, FrequencyReference::GsmFcch { carrier_hz: channel.frequency_hz })?;
+    println!("ARFCN {} {:.1} MHz: {:+.2} ppm", channel.arfcn, channel.frequency_hz / 1e6, estimate.ppm);
+    settings.device_mut("hackrf").ppm = Some(estimate.ppm);
+    settings.save_default()?;
     Ok(())
 }
 
+
+/// Sweeps a span until interrupted, logging how often and how strongly every bin is in use. The log
+/// is rewritten after every sweep and picked up again when started with the same span. The signals
+/// found in the last sweep are written next to it as a channel list `record` can scan, and the wide
+/// ones that are ATSC or DVB-T are named.
+fn occupancy(start: f64, stop: f64, path: &Path, bin_hz: f64, threshold_db: f32, radio: &RadioOptions, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    const SAMPLE_RATE: u32 = 10_000_000;
+    let mut log = OccupancyLog::new(start, stop, bin_hz, threshold_db)?.resume(path)?;
+    if log.sweeps() > 0 {
+        eprintln!("resuming after {} sweeps", log.sweeps());
+    }
+
+    let fft_size = (SAMPLE_RATE as f64 / bin_hz).round().max(16.0) as usize;
+    let fft_size = fft_size.next_power_of_two();
+    let mut psd = PsdAverage::new(fft_size);
+    let segment_bin_hz = SAMPLE_RATE as f64 / fft_size as f64;
+    // only the middle 3/4 is inside the baseband filter
+    let (keep_from, keep_to) = (fft_size / 8, fft_size - fft_size / 8);
+    let step = (keep_to - keep_from) as f64 * segment_bin_hz;
+    let detector = PeakDetector::new(10.0);
+    // detections this wide may be a 6 to 8 MHz digital TV channel
+    const DTV_MIN_BANDWIDTH: f64 = 5e6;
+    // what was heard in the last sweep, as a channel list for `record`
+    let channels_path = path.with_extension("channels.csv");
+
+    let cancel = CancelToken::ctrl_c()?;
+    // 20 ms per step, enough spectra to average out the noise
+    let mut hackrf = radio.hackrf(settings, start as u64, SAMPLE_RATE, 32, 20)
+        .samples_per_frame(SAMPLE_RATE as usize / 50)
+        .build(HackRf::open()?)?;
+    hackrf.set_cancel(&cancel);
+    let mut samples = Vec::new();
+    while !cancel.is_cancelled() {
+        let mut signals = Vec::new();
+        // the whole sweep's spectrum, the steps line up bin for bin
+        let mut sweep_db = Vec::new();
+        let mut low = start;
+        while low < stop + segment_bin_hz && !cancel.is_cancelled() {
+            let center = low - keep_from as f64 * segment_bin_hz + SAMPLE_RATE as f64 / 2.0;
+            hackrf.set_frequency(center as u64)?;
+            // the first frame after retuning is still settling
+            hackrf.read(&mut samples)?;
+            psd.reset();
+            // narrow bins take an FFT longer than a frame, keep reading until there's a whole one
+            while psd.frames() == 0 && !cancel.is_cancelled() {
+                hackrf.read(&mut samples)?;
+                psd.push(&samples);
+            }
+            let Some(power_db) = psd.average_db() else { break };
+            let power_db = &power_db[keep_from..keep_to];
+            log.add(low, segment_bin_hz, power_db);
+            signals.extend(detector.detect(power_db, low, segment_bin_hz));
+            sweep_db.extend_from_slice(power_db);
+            low += step;
+        }
+        if cancel.is_cancelled() {
+            break;
+        }
+        // ATSC shows in the spectrum when the bins resolve its pilot, anything else wide gets a look for DVB-T
+        let atsc = if segment_bin_hz <= 50e3 { find_atsc(&sweep_db, start, segment_bin_hz) } else { Vec::new() };
+        for found in &atsc {
+            eprintln!("{}", found);
+        }
+        for signal in signals.iter().filter(|signal| signal.bandwidth_hz >= DTV_MIN_BANDWIDTH) {
+            if atsc.iter().any(|found| matches!(found, DtvSignal::Atsc { center_hz, .. } if (center_hz - signal.center_hz).abs() < 3e6)) {
+                continue;
+            }
+            // the first frame after retuning is still settling
+            hackrf.set_frequency(signal.center_hz as u64)?;
+            hackrf.read(&mut samples)?;
+            hackrf.read(&mut samples)?;
+            if let Some(found) = detect_dvbt(&samples, SAMPLE_RATE) {
+                eprintln!("{:.3} MHz: {}", signal.center_hz / 1e6, found);
+            }
+        }
+        log.finish_sweep();
+        log.save(path)?;
+        let lines: Vec<String> = signals.iter().map(|signal| {
+            let channel = signal.scan_channel(bin_hz);
+            format!("{},{}", channel.frequency, channel.label)
+        }).collect();
+        std::fs::write(&channels_path, lines.join("\n") + "\n")?;
+        let busy = log.bins().iter().filter(|bin| bin.duty_cycle() > 0.0).count();
+        eprintln!("sweep {}: {} of {} bins seen occupied, {} signals", log.sweeps(), busy, log.bins().len(), signals.len());
+    }
+    Ok(())
+}
+
+
+/// Serves the HackRF to SDR programs speaking rtl_tcp. The sample rate is fixed when starting since
+/// the HackRF can't go as low as the RTL-SDR rates clients ask for.
+fn rtl_tcp(address: &str, sample_rate: u32, radio: &RadioOptions, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let cancel = CancelToken::ctrl_c()?;
+    let mut hackrf = radio.hackrf(settings, settings.last.frequency.unwrap_or(100_000_000), sample_rate, 16, 16)
+        .samples_per_frame(sample_rate as usize / 100 * 2)
+        .build(HackRf::open()?)?;
+    hackrf.set_cancel(&cancel);
+    let mut server = RtlTcpServer::bind(address)?;
+    eprintln!("rtl_tcp listening on {}", server.local_addr());
+    server.serve(&mut hackrf, &cancel, control_hackrf, |command, e| eprintln!("rtl_tcp {:?}: {}", command, e))
+}
+
+
+/// Follows a beacon near `frequency` until interrupted, appending its frequency and level to a CSV
+/// log every second for propagation studies.
+fn beacon(frequency: u64, path: &Path, search_hz: f64, radio: &RadioOptions, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    use std::io::Write;
+
+    let sample_rate_hardware: u32 = 2_000_000;
+    let sample_rate_channel: u32 = 8_000;
+    let cancel = CancelToken::ctrl_c()?;
+    let mut hackrf = radio.hackrf(settings, frequency, sample_rate_hardware, 32, 20).build(HackRf::open()?)?;
+    hackrf.set_cancel(&cancel);
+    let meter = smeter(&hackrf, frequency);
+    let offset = TunedSource::<HackRFSource>::default_offset(sample_rate_hardware);
+    let mut source = TunedSource::new(hackrf, sample_rate_hardware, frequency, offset)?;
+    let mut resample = RationalResamplerBuilder::new(sample_rate_hardware, sample_rate_channel).num_taps(1001).build()?;
+    let mut tracker = CarrierTracker::new(sample_rate_channel, search_hz)?;
+
+    let new = !path.exists();
+    let mut log = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    if new {
+        writeln!(log, "unix_time,frequency_hz,level_dbfs,snr_db,locked")?;
+    }
+    let (mut block, mut channel, mut mixed) = (Vec::new(), Vec::new(), Vec::new());
+    while !cancel.is_cancelled() {
+        source.read(&mut block)?;
+        if block.is_empty() {
+            break;
+        }
+        resample.filter(&block, &mut channel)?;
+        tracker.filter(&channel, &mut mixed)?;
+        for point in tracker.take_track() {
+            let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs_f64();
+            let hz = frequency as f64 + point.offset_hz;
+            writeln!(log, "{:.1},{:.2},{:.1},{:.1},{}", time, hz, point.level_db, point.snr_db, point.locked)?;
+            eprintln!("{:.2} Hz {} {:.1} dB{}", hz, meter.reading(point.level_db), point.snr_db, if point.locked { "" } else { " (searching)" });
+        }
+        log.flush()?;
+    }
+    Ok(())
+}
+
+
+/// Runs a receiver described in a graph file with the built in block types.
+fn run_graph(path: &Path) -> Result<(), Box<dyn Error>> {
+    let spec = GraphSpec::load(path)?;
+    let mut graph = Registry::default().build(&spec)?;
+    eprintln!("{}", graph.names().join(" -> "));
+    let stats = graph.run(CancelToken::ctrl_c()?)?;
+    eprintln!("{} samples in {:.1} s", stats.samples, stats.elapsed.as_secs_f64());
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use clap::{CommandFactory, Parser};
+    use crate::block::{NullSink, NullSource};
+    use crate::pipeline::CancelToken;
+    use crate::{demodulate_into, Cli, Command, DecoderEvents, Modulation, SAMPLE_RATE_AUDIO};
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        // a bare frequency still listens
+        let cli = Cli::try_parse_from(["rust_dsp", "97900000", "--low-latency", "--lna-gain", "24"]).unwrap();
+        assert_eq!((cli.frequency, cli.low_latency, cli.radio.lna_gain), (Some(97_900_000), true, Some(24)));
+        assert!(cli.command.is_none());
+
+        let cli = Cli::try_parse_from(["rust_dsp", "rx-fm", "97900000", "--mono"]).unwrap();
+        let Some(Command::RxFm(rx)) = cli.command else { panic!("{:?}", cli.command) };
+        assert!(rx.mono);
+
+        let cli = Cli::try_parse_from(["rust_dsp", "rx-am", "1000000", "--bandwidth", "8000"]).unwrap();
+        let Some(Command::RxAm(rx)) = cli.command else { panic!("{:?}", cli.command) };
+        assert_eq!((rx.frequency, rx.bandwidth), (1_000_000, Some(8000)));
+
+        let cli = Cli::try_parse_from(["rust_dsp", "play", "capture.cu8", "--sample-rate", "2000000", "--mode", "am", "--offset", "-25000"]).unwrap();
+        let Some(Command::Play { sample_rate, mode, offset, .. }) = cli.command else { panic!("{:?}", cli.command) };
+        assert_eq!((sample_rate, mode, offset), (Some(2_000_000), Modulation::Am, -25000.0));
+
+        let cli = Cli::try_parse_from(["rust_dsp", "scan", "channels.csv", "out", "-50"]).unwrap();
+        let Some(Command::Scan { squelch, cor, .. }) = cli.command else { panic!("{:?}", cli.command) };
+        assert_eq!((squelch, cor), (-50.0, None));
+
+        assert!(Cli::try_parse_from(["rust_dsp", "record", "100000000"]).is_err());
+        assert!(Cli::try_parse_from(["rust_dsp", "97900000", "devices"]).is_err());
+    }
+
+    #[test]
+    fn test_demodulate() -> Result<(), Box<dyn Error>> {
+        // the chains the receivers and `play` run, a quarter second of a 600 kS/s recording each
+        let sample_rate = 600_000;
+        for (modulation, stereo, channels) in [(Modulation::Wfm, true, 2), (Modulation::Wfm, false, 1), (Modulation::Am, false, 1)] {
+            let source = NullSource::new(sample_rate, 8192).limit(sample_rate as u64 / 4);
+            let mut sink = NullSink::new();
+            demodulate_into(source, sample_rate, modulation, None, stereo, &mut sink, None, DecoderEvents::default(), CancelToken::new())?;
+            let expected = channels * SAMPLE_RATE_AUDIO as u64 / 4;
+            assert!(sink.samples().abs_diff(expected) < expected / 20, "{:?}: {} samples", modulation, sink.samples());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::f32::consts::PI;
+use num_complex::Complex32;
+use num_traits::Zero;
+use crate::block::{FIRFilter, FMDemod};
+use crate::state::{StateReader, StateWriter, Stateful};
+use crate::traits::*;
+use crate::util::rrc_taps;
+
+
+pub struct AGC {
+    rate: f32,
+    target: f32,
+    average: f32,
+}
+
+
+impl AGC {
+    pub fn new(rate: f32, target: f32) -> Self {
+        Self {
+            rate,
+            target,
+            average: target,
+        }
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for AGC {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &sample in input {
+            self.average += (sample.norm() - self.average) * self.rate;
+            let gain = if self.average > 1e-9 { self.target / self.average } else { 1.0 };
+            output.push(sample * gain);
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Stateful for AGC {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(self.average);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        self.average = reader.get()?;
+        Ok(())
+    }
+}
+
+
+/// Gardner timing error detector with linear interpolation, turning samples into symbols.
+pub struct ClockRecovery {
+    omega: f32,
+    omega_nominal: f32,
+    omega_limit: f32,
+    gain_mu: f32,
+    gain_omega: f32,
+    t: f32,
+    prev: Complex32,
+    mid: Option<Complex32>,
+    last_symbol: Complex32,
+}
+
+
+impl ClockRecovery {
+    pub fn new(samples_per_symbol: f32, loop_bandwidth: f32) -> Self {
+        let damping = std::f32::consts::FRAC_1_SQRT_2;
+        let denom = 1.0 + 2.0 * damping * loop_bandwidth + loop_bandwidth * loop_bandwidth;
+        Self {
+            omega: samples_per_symbol,
+            omega_nominal: samples_per_symbol,
+            omega_limit: samples_per_symbol * 0.005,
+            gain_mu: 4.0 * damping * loop_bandwidth / denom,
+            gain_omega: 4.0 * loop_bandwidth * loop_bandwidth / denom,
+            t: 0.0,
+            prev: Complex32::zero(),
+            mid: None,
+            last_symbol: Complex32::zero(),
+        }
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for ClockRecovery {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &sample in input {
+            self.t += 1.0;
+
+            let half = self.omega / 2.0;
+            if self.mid.is_none() && self.t >= half {
+                let f = 1.0 - (self.t - half);
+                self.mid = Some(self.prev + (sample - self.prev) * f);
+            }
+
+            if self.t >= self.omega {
+                let f = 1.0 - (self.t - self.omega);
+                let symbol = self.prev + (sample - self.prev) * f;
+                let mid = self.mid.take().unwrap_or(symbol);
+
+                let err = ((symbol - self.last_symbol) * mid.conj()).re.clamp(-1.0, 1.0);
+                self.omega = (self.omega - self.gain_omega * err)
+                    .clamp(self.omega_nominal - self.omega_limit, self.omega_nominal + self.omega_limit);
+                self.t -= self.omega;
+                self.t += self.gain_mu * err;
+
+                self.last_symbol = symbol;
+                output.push(symbol);
+            }
+
+            self.prev = sample;
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Stateful for ClockRecovery {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(self.omega);
+        writer.put(self.t);
+        writer.put(self.prev);
+        writer.put_option(self.mid);
+        writer.put(self.last_symbol);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        self.omega = reader.get()?;
+        self.t = reader.get()?;
+        self.prev = reader.get()?;
+        self.mid = reader.get_option()?;
+        self.last_symbol = reader.get()?;
+        Ok(())
+    }
+}
+
+
+/// Decision directed carrier recovery for BPSK (order 2) or QPSK (order 4) symbols.
+pub struct CostasLoop {
+    order: usize,
+    phase: f32,
+    freq: f32,
+    alpha: f32,
+    beta: f32,
+}
+
+
+impl CostasLoop {
+    pub fn new(order: usize, loop_bandwidth: f32) -> Self {
+        assert!(order == 2 || order == 4, "costas loop order must be 2 or 4");
+        let damping = std::f32::consts::FRAC_1_SQRT_2;
+        let denom = 1.0 + 2.0 * damping * loop_bandwidth + loop_bandwidth * loop_bandwidth;
+        Self {
+            order,
+            phase: 0.0,
+            freq: 0.0,
+            alpha: 4.0 * damping * loop_bandwidth / denom,
+            beta: 4.0 * loop_bandwidth * loop_bandwidth / denom,
+        }
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.freq
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for CostasLoop {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &sample in input {
+            let (sin, cos) = self.phase.sin_cos();
+            let y = sample * Complex32::new(cos, -sin);
+
+            let err = if self.order == 2 {
+                y.re.signum() * y.im
+            } else {
+                y.re.signum() * y.im - y.im.signum() * y.re
+            }.clamp(-1.0, 1.0);
+
+            self.freq = (self.freq + self.beta * err).clamp(-0.5, 0.5);
+            self.phase = (self.phase + self.freq + self.alpha * err).rem_euclid(2.0 * PI);
+            output.push(y);
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Stateful for CostasLoop {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(self.phase);
+        writer.put(self.freq);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>> {
+        self.phase = reader.get()?;
+        self.freq = reader.get()?;
+        Ok(())
+    }
+}
+
+
+/// AGC, root raised cosine matched filter, symbol timing and carrier recovery for (O)QPSK.
+pub struct QPSKDemod {
+    agc: AGC,
+    rrc: FIRFilter<Complex32>,
+    clock: ClockRecovery,
+    costas: CostasLoop,
+    offset: Option<VecDeque<f32>>,
+    buff0: Vec<Complex32>,
+    buff1: Vec<Complex32>,
+}
+
+
+impl QPSKDemod {
+    pub fn new(sample_rate: u32, symbol_rate: u32, alpha: f32, num_taps: usize, offset: bool) -> Self {
+        let sps = sample_rate as f32 / symbol_rate as f32;
+        let taps = rrc_taps(sps, alpha, num_taps).into_iter().map(|r| Complex32::new(r, 0.0)).collect();
+
+        // OQPSK staggers Q by half a symbol, so delay I by the same amount to line them back up
+        let offset = offset.then(|| {
+            let mut delay = VecDeque::new();
+            delay.resize((sps / 2.0).round() as usize, 0.0);
+            delay
+        });
+
+        Self {
+            agc: AGC::new(1e-3, 1.0),
+            rrc: FIRFilter::new(taps),
+            clock: ClockRecovery::new(sps, 0.005),
+            costas: CostasLoop::new(4, 0.005),
+            offset,
+            buff0: Vec::new(),
+            buff1: Vec::new(),
+        }
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for QPSKDemod {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        self.agc.filter(input, &mut self.buff0)?;
+        self.rrc.filter(&self.buff0, &mut self.buff1)?;
+
+        if let Some(delay) = self.offset.as_mut() {
+            for sample in self.buff1.iter_mut() {
+                delay.push_back(sample.re);
+                sample.re = delay.pop_front().unwrap();
+            }
+        }
+
+        self.clock.filter(&self.buff1, &mut self.buff0)?;
+        self.costas.filter(&self.buff0, output)
+    }
+}
+
+
+/// Convert symbols into interleaved I/Q soft bits where positive values mean a one.
+pub fn soft_bits(symbols: &[Complex32], scale: f32, output: &mut Vec<i8>) {
+    output.clear();
+    for sample in symbols {
+        output.push((sample.re * scale).clamp(-127.0, 127.0) as i8);
+        output.push((sample.im * scale).clamp(-127.0, 127.0) as i8);
+    }
+}
+
+
+/// Binary FSK demodulator producing one unpacked bit per symbol (higher tone is a one).
+pub struct FSKDemod {
+    discriminator: FMDemod,
+    matched: FIRFilter<f32>,
+    clock: ClockRecovery,
+    freq: Vec<f32>,
+    smoothed: Vec<f32>,
+    filtered: Vec<Complex32>,
+    symbols: Vec<Complex32>,
+}
+
+
+impl FSKDemod {
+    pub fn new(sample_rate: u32, baud: u32, deviation: f32) -> Self {
+        let sps = sample_rate as f32 / baud as f32;
+        let len = sps.round().max(1.0) as usize;
+
+        Self {
+            discriminator: FMDemod::new(sample_rate, deviation),
+            matched: FIRFilter::new(vec![1.0 / len as f32; len]),
+            clock: ClockRecovery::new(sps, 0.02),
+            freq: Vec::new(),
+            smoothed: Vec::new(),
+            filtered: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+}
+
+
+impl Filter<Complex32, u8> for FSKDemod {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.discriminator.filter(input, &mut self.freq)?;
+        self.matched.filter(&self.freq, &mut self.smoothed)?;
+
+        self.filtered.clear();
+        self.filtered.extend(self.smoothed.iter().map(|&v| Complex32::new(v, 0.0)));
+        self.clock.filter(&self.filtered, &mut self.symbols)?;
+
+        output.extend(self.symbols.iter().map(|s| (s.re > 0.0) as u8));
+        Ok(())
+    }
+}
+
+
+/// Four level FSK (C4FM) demodulator producing dibits, as used by P25 phase 1 and DMR.
+pub struct C4FMDemod {
+    discriminator: FMDemod,
+    rrc: FIRFilter<f32>,
+    clock: ClockRecovery,
+    level: f32,
+    freq: Vec<f32>,
+    shaped: Vec<f32>,
+    filtered: Vec<Complex32>,
+    symbols: Vec<Complex32>,
+}
+
+
+impl C4FMDemod {
+    /// Outer symbol deviation shared by P25 and DMR.
+    pub const DEVIATION: f32 = 1800.0;
+
+    pub fn new(sample_rate: u32, symbol_rate: u32) -> Self {
+        let sps = sample_rate as f32 / symbol_rate as f32;
+        let num_taps = (8.0 * sps) as usize | 1;
+
+        Self {
+            discriminator: FMDemod::new(sample_rate, Self::DEVIATION),
+            rrc: FIRFilter::new(rrc_taps(sps, 0.2, num_taps)),
+            clock: ClockRecovery::new(sps, 0.01),
+            level: 1.0,
+            freq: Vec::new(),
+            shaped: Vec::new(),
+            filtered: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Map a normalized symbol (+3, +1, -1, -3 scaled to +1 .. -1) to its dibit.
+    fn slice(&mut self, x: f32) -> u8 {
+        let threshold = self.level * 2.0 / 3.0;
+        if x.abs() > threshold {
+            self.level += (x.abs() - self.level) * 0.02;
+        }
+
+        if x >= threshold {
+            0b01
+        } else if x >= 0.0 {
+            0b00
+        } else if x >= -threshold {
+            0b10
+        } else {
+            0b11
+        }
+    }
+}
+
+
+impl Filter<Complex32, u8> for C4FMDemod {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.discriminator.filter(input, &mut self.freq)?;
+        self.rrc.filter(&self.freq, &mut self.shaped)?;
+
+        self.filtered.clear();
+        self.filtered.extend(self.shaped.iter().map(|&v| Complex32::new(v, 0.0)));
+        self.clock.filter(&self.filtered, &mut self.symbols)?;
+
+        let symbols = std::mem::take(&mut self.symbols);
+        for symbol in symbols.iter() {
+            output.push(self.slice(symbol.re));
+        }
+        self.symbols = symbols;
+
+        Ok(())
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncKind {
+    P25,
+    DmrBsVoice,
+    DmrBsData,
+    DmrMsVoice,
+    DmrMsData,
+}
+
+
+impl SyncKind {
+    /// The 48 bit (24 dibit) sync word.
+    pub fn pattern(&self) -> u64 {
+        match self {
+            SyncKind::P25 => 0x5575_f5ff_77ff,
+            SyncKind::DmrBsVoice => 0x755f_d7df_75f7,
+            SyncKind::DmrBsData => 0xdff5_7d75_df5d,
+            SyncKind::DmrMsVoice => 0x7f7d_5dd5_7dfd,
+            SyncKind::DmrMsData => 0xd5d7_f77f_d757,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct SyncMatch {
+    pub kind: SyncKind,
+    /// Index of the dibit just after the sync word.
+    pub index: u64,
+    pub errors: u32,
+}
+
+
+/// Search a dibit stream for frame sync words.
+pub struct FrameSync {
+    kinds: Vec<SyncKind>,
+    max_errors: u32,
+    shift: u64,
+    count: u64,
+}
+
+
+impl FrameSync {
+    pub fn new(kinds: &[SyncKind], max_errors: u32) -> Self {
+        Self {
+            kinds: kinds.to_vec(),
+            max_errors,
+            shift: 0,
+            count: 0,
+        }
+    }
+
+    pub fn p25() -> Self {
+        Self::new(&[SyncKind::P25], 4)
+    }
+
+    pub fn dmr() -> Self {
+        Self::new(&[SyncKind::DmrBsVoice, SyncKind::DmrBsData, SyncKind::DmrMsVoice, SyncKind::DmrMsData], 4)
+    }
+}
+
+
+impl Filter<u8, SyncMatch> for FrameSync {
+    fn filter(&mut self, input: &[u8], output: &mut Vec<SyncMatch>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &dibit in input {
+            self.shift = ((self.shift << 2) | (dibit & 0b11) as u64) & 0xffff_ffff_ffff;
+            self.count += 1;
+            if self.count < 24 {
+                continue;
+            }
+
+            for &kind in self.kinds.iter() {
+                let errors = (self.shift ^ kind.pattern()).count_ones();
+                if errors <= self.max_errors {
+                    output.push(SyncMatch {
+                        kind,
+                        index: self.count,
+                        errors,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex32;
+    use crate::block::FIRFilter;
+    use crate::modem::{C4FMDemod, ClockRecovery, FrameSync, QPSKDemod, SyncKind};
+    use crate::seed::Rng;
+    use crate::traits::Filter;
+    use crate::util::rrc_taps;
+
+    #[test]
+    fn test_clock_recovery_tracks_rate() -> Result<(), Box<dyn std::error::Error>> {
+        // ±1 symbols with raised cosine transitions, sent 0.25% slower than the receiver expects
+        let sps = 4.01f32;
+        let mut rng = Rng::new(0x2545f491);
+        let symbols: Vec<f32> = (0..20000).map(|_| if rng.next_u32() & 1 == 1 { 1.0 } else { -1.0 }).collect();
+        let samples: Vec<Complex32> = (0..(symbols.len() as f32 * sps) as usize - 8).map(|n| {
+            let t = n as f32 / sps;
+            let (k, frac) = (t as usize, t.fract());
+            let shape = 0.5 - 0.5 * (std::f32::consts::PI * frac).cos();
+            Complex32::new(symbols[k] + (symbols[k + 1] - symbols[k]) * shape, 0.0)
+        }).collect();
+
+        let mut clock = ClockRecovery::new(4.0, 0.01);
+        let mut output = Vec::new();
+        for block in samples.chunks(4096) {
+            clock.filter(block, &mut output)?;
+        }
+        assert!((clock.omega - sps).abs() < 0.003, "{}", clock.omega);
+        // settled on the symbol centres
+        assert!(output.iter().all(|x| x.re.abs() > 0.9), "{:?}", output.iter().map(|x| x.re.abs()).fold(1.0, f32::min));
+        Ok(())
+    }
+
+    #[test]
+    fn test_qpsk_demod() -> Result<(), Box<dyn std::error::Error>> {
+        let sps = 4;
+        let mut state = 0x1234_5678u32;
+        let mut symbols = Vec::new();
+        for _ in 0..4000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let (i, q) = (state & 1, (state >> 1) & 1);
+            symbols.push(Complex32::new(if i == 1 { 1.0 } else { -1.0 }, if q == 1 { 1.0 } else { -1.0 }));
+        }
+
+        let mut upsampled = Vec::new();
+        for &s in symbols.iter() {
+            upsampled.push(s * sps as f32);
+            upsampled.extend(std::iter::repeat_n(Complex32::new(0.0, 0.0), sps - 1));
+        }
+        let taps = rrc_taps(sps as f32, 0.6, 65).into_iter().map(|r| Complex32::new(r, 0.0)).collect();
+        let mut shaped = Vec::new();
+        FIRFilter::new(taps).filter(&upsampled, &mut shaped)?;
+
+        // small carrier offset and phase rotation
+        for (n, s) in shaped.iter_mut().enumerate() {
+            *s *= Complex32::from_polar(0.5, 0.3 + 0.002 * n as f32);
+        }
+
+        let mut demod = QPSKDemod::new(4 * 72000, 72000, 0.6, 65, false);
+        let mut output = Vec::new();
+        demod.filter(&shaped, &mut output)?;
+
+        let tail = &output[output.len() - 1000..];
+        let spread = tail.iter().map(|s| (s.re.abs() - s.im.abs()).abs()).sum::<f32>() / tail.len() as f32;
+        assert!(spread < 0.3, "constellation did not converge: {}", spread);
+
+        Ok(())
+    }
+
+
+    #[test]
+    fn test_c4fm_sync() -> Result<(), Box<dyn std::error::Error>> {
+        let (sample_rate, symbol_rate) = (48000u32, 4800u32);
+        let sps = (sample_rate / symbol_rate) as usize;
+
+        let sync = SyncKind::P25.pattern();
+        let mut dibits = Vec::new();
+        for frame in 0..6u64 {
+            dibits.extend((0..24).rev().map(|i| ((sync >> (2 * i)) & 0b11) as u8));
+            dibits.extend((0..100u64).map(|i| ((i * 7 + frame) % 4) as u8));
+        }
+
+        let mut levels = Vec::new();
+        for &d in dibits.iter() {
+            let level = match d { 0b01 => 3.0, 0b00 => 1.0, 0b10 => -1.0, _ => -3.0 };
+            levels.push(level * sps as f32);
+            levels.extend(std::iter::repeat_n(0.0, sps - 1));
+        }
+        let mut shaped = Vec::new();
+        FIRFilter::new(rrc_taps(sps as f32, 0.2, 8 * sps + 1)).filter(&levels, &mut shaped)?;
+
+        let mut phase = 0f32;
+        let baseband: Vec<Complex32> = shaped.iter().map(|&v| {
+            phase += 2.0 * std::f32::consts::PI * 600.0 * v / sample_rate as f32;
+            Complex32::from_polar(1.0, phase)
+        }).collect();
+
+        let mut demod = C4FMDemod::new(sample_rate, symbol_rate);
+        let mut output = Vec::new();
+        demod.filter(&baseband, &mut output)?;
+
+        let mut matches = Vec::new();
+        FrameSync::p25().filter(&output, &mut matches)?;
+        assert!(matches.len() >= 4, "found {} syncs", matches.len());
+
+        let last = matches.last().unwrap();
+        let index = last.index as usize;
+        assert_eq!(&output[index..index + 40], &dibits[dibits.len() - 100..dibits.len() - 60]);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,256 @@
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use crate::events::Event;
+use crate::traits::Sink;
+
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const DISCONNECT: u8 = 0xe0;
+
+
+/// Broker and topic settings. Events go to `<topic_prefix>/<kind>` unless a kind has its own topic.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic_prefix: String,
+    pub topics: Vec<(String, String)>,
+    pub retain: bool,
+    pub timeout: Duration,
+}
+
+
+impl MqttConfig {
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port: 1883,
+            client_id: "rust_dsp".to_string(),
+            username: None,
+            password: None,
+            topic_prefix: "rust_dsp".to_string(),
+            topics: Vec::new(),
+            retain: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Publish events of `kind`, e.g. `hit` or `position`, to `topic` instead of the default.
+    pub fn topic(mut self, kind: &str, topic: &str) -> Self {
+        self.topics.retain(|(k, _)| k != kind);
+        self.topics.push((kind.to_string(), topic.to_string()));
+        self
+    }
+
+    pub fn topic_for(&self, event: &Event) -> String {
+        match self.topics.iter().find(|(kind, _)| kind == event.kind()) {
+            Some((_, topic)) => topic.clone(),
+            None => format!("{}/{}", self.topic_prefix, event.kind()),
+        }
+    }
+}
+
+
+fn put_length(out: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        out.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+
+fn put_string(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s);
+}
+
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![kind];
+    put_length(&mut out, body.len());
+    out.extend_from_slice(body);
+    out
+}
+
+
+/// Publishes events as JSON over MQTT 3.1.1 with QoS 0, e.g. for Home Assistant or Node-RED.
+/// Keep alive is off, a dropped connection is reopened on the next write.
+pub struct MqttSink {
+    config: MqttConfig,
+    stream: Option<TcpStream>,
+}
+
+
+impl MqttSink {
+    pub fn connect(config: MqttConfig) -> Result<Self, Box<dyn Error>> {
+        let mut sink = Self {
+            config,
+            stream: None,
+        };
+        sink.reconnect()?;
+        Ok(sink)
+    }
+
+    pub fn config(&self) -> &MqttConfig {
+        &self.config
+    }
+
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.stream = None;
+        let mut stream = TcpStream::connect((self.config.host.as_str(), self.config.port))?;
+        stream.set_read_timeout(Some(self.config.timeout))?;
+        stream.set_write_timeout(Some(self.config.timeout))?;
+
+        let mut body = Vec::new();
+        put_string(&mut body, b"MQTT");
+        let mut flags = 0x02;
+        if self.config.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.config.password.is_some() {
+            flags |= 0x40;
+        }
+        body.extend_from_slice(&[4, flags, 0, 0]);
+        put_string(&mut body, self.config.client_id.as_bytes());
+        for field in [&self.config.username, &self.config.password].into_iter().flatten() {
+            put_string(&mut body, field.as_bytes());
+        }
+        stream.write_all(&packet(CONNECT, &body))?;
+
+        let mut ack = [0u8; 4];
+        stream.read_exact(&mut ack)?;
+        if ack[0] != CONNACK || ack[3] != 0 {
+            return Err(format!("mqtt broker refused the connection, code {}", ack[3]).into());
+        }
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+        put_string(&mut body, topic.as_bytes());
+        body.extend_from_slice(payload);
+        let packet = packet(PUBLISH | self.config.retain as u8, &body);
+
+        if let Some(stream) = self.stream.as_mut() && stream.write_all(&packet).is_ok() {
+            return Ok(());
+        }
+        self.reconnect()?;
+        self.stream.as_mut().unwrap().write_all(&packet)?;
+        Ok(())
+    }
+}
+
+
+impl Sink<Event> for MqttSink {
+    fn write(&mut self, src: &[Event]) -> Result<(), Box<dyn Error>> {
+        for event in src {
+            let topic = self.config.topic_for(event);
+            self.publish(&topic, event.to_json().to_string().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.as_mut() {
+            let _ = stream.write_all(&[DISCONNECT, 0]);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::time::{Duration, UNIX_EPOCH};
+    use serde_json::Value;
+    use crate::events::{Event, EventBus};
+    use crate::hits::Hit;
+    use crate::mqtt::{MqttConfig, MqttSink};
+    use crate::traits::Sink;
+
+    /// Reads one packet, returning the first byte and the body.
+    fn read_packet(stream: &mut impl Read) -> (u8, Vec<u8>) {
+        let mut kind = [0u8; 1];
+        stream.read_exact(&mut kind).unwrap();
+        let (mut len, mut shift) = (0usize, 0);
+        loop {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).unwrap();
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).unwrap();
+        (kind[0], body)
+    }
+
+    #[test]
+    fn test_mqtt_events() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let broker = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (kind, connect) = read_packet(&mut stream);
+            assert_eq!(kind, 0x10);
+            assert_eq!(&connect[..6], b"\x00\x04MQTT");
+            stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+            (0..2).map(|_| read_packet(&mut stream)).collect::<Vec<_>>()
+        });
+
+        let mut config = MqttConfig::new("127.0.0.1").topic("position", "adsb/positions");
+        config.port = port;
+        let mut sink = MqttSink::connect(config)?;
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+        bus.publish(Event::Hit(Hit {
+            time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            frequency: 146_520_000,
+            duration: Duration::from_millis(2500),
+            rssi_db: Some(-42.5),
+            source: "scanner".into(),
+            text: None,
+        }));
+        bus.publish(Event::Position {
+            time: UNIX_EPOCH,
+            source: "adsb".into(),
+            id: "a1b2c3".into(),
+            latitude: 40.5,
+            longitude: -111.25,
+            altitude_m: None,
+        });
+        drop(bus);
+        let events: Vec<Event> = events.into_iter().collect();
+        sink.write(&events)?;
+
+        let packets = broker.join().unwrap();
+        let topic = |body: &[u8]| String::from_utf8(body[2..2 + u16::from_be_bytes([body[0], body[1]]) as usize].to_vec()).unwrap();
+        assert_eq!(packets[0].0, 0x30);
+        assert_eq!(topic(&packets[0].1), "rust_dsp/hit");
+        assert_eq!(topic(&packets[1].1), "adsb/positions");
+        let hit: Value = serde_json::from_slice(&packets[0].1[2 + "rust_dsp/hit".len()..])?;
+        assert_eq!(hit["frequency"], 146_520_000);
+        assert_eq!(hit["duration"], 2.5);
+        assert_eq!(hit["source"], "scanner");
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use crate::error::ConfigError;
+
+
+const CSV_HEADER: &str = "frequency_hz,sweeps,occupied,duty_cycle,max_db,avg_db";
+
+
+/// Statistics of one frequency bin over the sweeps that covered it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BinStats {
+    pub frequency_hz: f64,
+    pub sweeps: u64,
+    /// Sweeps the bin was above the occupancy threshold in.
+    pub occupied: u64,
+    pub max_db: f32,
+    /// Linear power summed over the sweeps.
+    power_sum: f64,
+}
+
+
+impl BinStats {
+    fn new(frequency_hz: f64) -> Self {
+        Self {
+            frequency_hz,
+            sweeps: 0,
+            occupied: 0,
+            max_db: f32::NEG_INFINITY,
+            power_sum: 0.0,
+        }
+    }
+
+    pub fn duty_cycle(&self) -> f64 {
+        self.occupied as f64 / self.sweeps.max(1) as f64
+    }
+
+    /// Average of the linear power, in dB.
+    pub fn avg_db(&self) -> f32 {
+        if self.sweeps == 0 {
+            return f32::NEG_INFINITY;
+        }
+        (10.0 * (self.power_sum / self.sweeps as f64).log10()) as f32
+    }
+
+    fn restore(&mut self, sweeps: u64, occupied: u64, max_db: f32, avg_db: f32) {
+        self.sweeps = sweeps;
+        self.occupied = occupied;
+        self.max_db = max_db;
+        self.power_sum = 10f64.powf(avg_db as f64 / 10.0) * sweeps as f64;
+    }
+}
+
+
+/// Per bin occupancy of a swept span for long running surveys. Segments of a sweep are added as
+/// they are measured, at any resolution and in any order, and a bin counts once per sweep with the
+/// strongest power seen in it. The statistics can be saved to CSV or SQLite and resumed from there
+/// after a restart.
+pub struct OccupancyLog {
+    start_hz: f64,
+    bin_hz: f64,
+    threshold_db: f32,
+    bins: Vec<BinStats>,
+    current: Vec<Option<f32>>,
+    sweeps: u64,
+}
+
+
+impl OccupancyLog {
+    pub fn new(start_hz: f64, stop_hz: f64, bin_hz: f64, threshold_db: f32) -> Result<Self, ConfigError> {
+        if !(bin_hz > 0.0 && stop_hz > start_hz) {
+            return Err(ConfigError::Invalid(format!("empty span {} to {} Hz in {} Hz bins", start_hz, stop_hz, bin_hz)));
+        }
+        let count = ((stop_hz - start_hz) / bin_hz).round() as usize + 1;
+        Ok(Self {
+            start_hz,
+            bin_hz,
+            threshold_db,
+            bins: (0..count).map(|i| BinStats::new(start_hz + i as f64 * bin_hz)).collect(),
+            current: vec![None; count],
+            sweeps: 0,
+        })
+    }
+
+    /// Picks up the statistics saved at `path` if there are any. Saved bins have to line up with
+    /// this span, resuming with another threshold mixes the two in the duty cycle.
+    pub fn resume(mut self, path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(self);
+        }
+        let rows = if is_sqlite(path) { sqlite::load(path)? } else { load_csv(path)? };
+        for (frequency_hz, sweeps, occupied, max_db, avg_db) in rows {
+            let index = self.index(frequency_hz)
+                .filter(|&i| (self.bins[i].frequency_hz - frequency_hz).abs() < self.bin_hz / 100.0)
+                .ok_or_else(|| format!("{} has a bin at {} Hz, not in the span being swept", path.display(), frequency_hz))?;
+            self.bins[index].restore(sweeps, occupied, max_db, avg_db);
+            self.sweeps = self.sweeps.max(sweeps);
+        }
+        Ok(self)
+    }
+
+    pub fn bins(&self) -> &[BinStats] {
+        &self.bins
+    }
+
+    /// Sweeps finished, including the ones resumed from a file.
+    pub fn sweeps(&self) -> u64 {
+        self.sweeps
+    }
+
+    fn index(&self, frequency_hz: f64) -> Option<usize> {
+        let i = ((frequency_hz - self.start_hz) / self.bin_hz).round();
+        (i >= 0.0 && (i as usize) < self.bins.len()).then_some(i as usize)
+    }
+
+    /// Part of the current sweep, `power_db[i]` measured at `start_hz + i * bin_hz`. Anything outside
+    /// the span is ignored.
+    pub fn add(&mut self, start_hz: f64, bin_hz: f64, power_db: &[f32]) {
+        for (i, &power) in power_db.iter().enumerate() {
+            if let Some(index) = self.index(start_hz + i as f64 * bin_hz) {
+                let current = &mut self.current[index];
+                *current = Some(current.map_or(power, |c| c.max(power)));
+            }
+        }
+    }
+
+    /// Folds the current sweep into the statistics. Bins no segment covered are left alone.
+    pub fn finish_sweep(&mut self) {
+        for (bin, current) in self.bins.iter_mut().zip(self.current.iter_mut()) {
+            let Some(power) = current.take() else { continue };
+            bin.sweeps += 1;
+            bin.occupied += (power > self.threshold_db) as u64;
+            bin.max_db = bin.max_db.max(power);
+            bin.power_sum += 10f64.powf(power as f64 / 10.0);
+        }
+        self.sweeps += 1;
+    }
+
+    /// SQLite for `.db`, `.sqlite` and `.sqlite3` files, CSV otherwise. CSV is written next to `path`
+    /// and renamed over it, so a restart never finds half a file.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let swept = self.bins.iter().filter(|bin| bin.sweeps > 0);
+        if is_sqlite(path) {
+            return sqlite::save(path, swept);
+        }
+        let partial = path.with_extension("csv.partial");
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        writeln!(writer, "{}", CSV_HEADER)?;
+        for bin in swept {
+            writeln!(writer, "{},{},{},{:.4},{:.2},{:.2}", bin.frequency_hz, bin.sweeps, bin.occupied, bin.duty_cycle(), bin.max_db, bin.avg_db())?;
+        }
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(partial, path)?;
+        Ok(())
+    }
+}
+
+
+fn is_sqlite(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("db" | "sqlite" | "sqlite3"))
+}
+
+
+type SavedBin = (f64, u64, u64, f32, f32);
+
+
+fn load_csv(path: &Path) -> Result<Vec<SavedBin>, Box<dyn Error>> {
+    let mut rows = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines().skip(1) {
+        let line = line?;
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != 6 {
+            return Err(format!("bad occupancy row {:?}", line).into());
+        }
+        rows.push((fields[0].parse()?, fields[1].parse()?, fields[2].parse()?, fields[4].parse()?, fields[5].parse()?));
+    }
+    Ok(rows)
+}
+
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::error::Error;
+    use std::path::Path;
+    use rusqlite::{params, Connection};
+    use crate::occupancy::{BinStats, SavedBin};
+
+    fn open(path: &Path) -> Result<Connection, Box<dyn Error>> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch("
+            CREATE TABLE IF NOT EXISTS occupancy (
+                frequency_hz REAL PRIMARY KEY,
+                sweeps INTEGER NOT NULL,
+                occupied INTEGER NOT NULL,
+                duty_cycle REAL NOT NULL,
+                max_db REAL NOT NULL,
+                avg_db REAL NOT NULL
+            );
+        ")?;
+        Ok(connection)
+    }
+
+    pub fn save<'a>(path: &Path, bins: impl Iterator<Item = &'a BinStats>) -> Result<(), Box<dyn Error>> {
+        let mut connection = open(path)?;
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare("INSERT OR REPLACE INTO occupancy VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            for bin in bins {
+                statement.execute(params![bin.frequency_hz, bin.sweeps as i64, bin.occupied as i64, bin.duty_cycle(), bin.max_db, bin.avg_db()])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Vec<SavedBin>, Box<dyn Error>> {
+        let connection = open(path)?;
+        let mut statement = connection.prepare("SELECT frequency_hz, sweeps, occupied, max_db, avg_db FROM occupancy ORDER BY frequency_hz")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get(0)?, row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64, row.get(3)?, row.get(4)?))
+        })?.collect::<Result<_, _>>()?;
+        Ok(rows)
+    }
+}
+
+
+#[cfg(not(feature = "sqlite"))]
+mod sqlite {
+    use std::error::Error;
+    use std::path::Path;
+    use crate::occupancy::{BinStats, SavedBin};
+
+    pub fn save<'a>(_path: &Path, _bins: impl Iterator<Item = &'a BinStats>) -> Result<(), Box<dyn Error>> {
+        Err("built without the sqlite feature".into())
+    }
+
+    pub fn load(_path: &Path) -> Result<Vec<SavedBin>, Box<dyn Error>> {
+        Err("built without the sqlite feature".into())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::occupancy::OccupancyLog;
+
+    #[test]
+    fn test_occupancy_log() -> Result<(), Box<dyn Error>> {
+        // 100.0 to 100.1 MHz in 25 kHz bins, swept as two overlapping segments at a finer resolution
+        let new = || OccupancyLog::new(100.0e6, 100.1e6, 25e3, -60.0);
+        let mut log = new()?;
+        assert_eq!(log.bins().len(), 5);
+        for sweep in 0..4 {
+            let busy = if sweep % 2 == 0 { -40.0 } else { -90.0 };
+            log.add(99.99e6, 12.5e3, &[-90.0, -90.0, -90.0, -90.0, -90.0, busy, -90.0]);
+            log.add(100.05e6, 12.5e3, &[-95.0, -30.0, -90.0, -90.0, -90.0, -90.0]);
+            log.finish_sweep();
+        }
+        let bins = log.bins();
+        assert_eq!(bins[2].frequency_hz, 100.05e6);
+        assert_eq!((bins[2].sweeps, bins[2].occupied, bins[2].max_db), (4, 2, -40.0));
+        assert_eq!(bins[2].duty_cycle(), 0.5);
+        assert_eq!(bins[3].duty_cycle(), 1.0);
+        assert_eq!(bins[0].duty_cycle(), 0.0);
+        // two sweeps at -40 and two at -90 average to 3 dB below -40
+        assert!((bins[2].avg_db() + 43.01).abs() < 0.01, "{}", bins[2].avg_db());
+
+        let mut extensions = vec!["csv"];
+        if cfg!(feature = "sqlite") {
+            extensions.push("db");
+        }
+        for extension in extensions {
+            let path = std::env::temp_dir().join(format!("rust_dsp_occupancy_{}.{}", std::process::id(), extension));
+            log.save(&path)?;
+            let mut resumed = new()?.resume(&path)?;
+            assert_eq!(resumed.sweeps(), 4);
+            assert_eq!((resumed.bins()[2].sweeps, resumed.bins()[2].occupied), (4, 2));
+            assert!((resumed.bins()[2].avg_db() - bins[2].avg_db()).abs() < 0.01);
+            resumed.add(100.05e6, 25e3, &[-50.0]);
+            resumed.finish_sweep();
+            assert_eq!((resumed.bins()[2].sweeps, resumed.bins()[2].occupied), (5, 3));
+
+            // a different span doesn't line up
+            assert!(OccupancyLog::new(200e6, 200.1e6, 25e3, -60.0)?.resume(&path).is_err());
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
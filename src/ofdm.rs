@@ -0,0 +1,186 @@
+use std::f64::consts::PI;
+use std::ops::Range;
+use num_complex::Complex32;
+use crate::fft::FFT;
+
+
+/// Symbol layout of an OFDM signal, in samples at its native rate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OfdmParams {
+    pub fft_size: usize,
+    /// Cyclic prefix in front of every symbol.
+    pub guard: usize,
+}
+
+
+impl OfdmParams {
+    pub fn symbol_len(&self) -> usize {
+        self.fft_size + self.guard
+    }
+
+    /// FFT bin of carrier `k`, negative carriers sit at the top of the FFT.
+    pub fn bin(&self, k: isize) -> usize {
+        k.rem_euclid(self.fft_size as isize) as usize
+    }
+}
+
+
+/// Where a symbol starts according to its cyclic prefix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SymbolTiming {
+    /// Index of the first guard sample.
+    pub offset: usize,
+    /// Frequency offset modulo one carrier spacing, in carrier spacings.
+    pub cfo: f32,
+    /// Correlation of the guard with the end of the symbol, 1 for a clean signal and near 0 for noise.
+    pub quality: f32,
+}
+
+
+/// Correlates the guard interval against the end of the symbol it copies for every symbol start in
+/// `starts` and returns the best one, None if `samples` doesn't reach far enough.
+pub fn find_symbol(params: OfdmParams, samples: &[Complex32], starts: Range<usize>) -> Option<SymbolTiming> {
+    let (n, g) = (params.fft_size, params.guard);
+    if starts.is_empty() || starts.end - 1 + n + g > samples.len() {
+        return None;
+    }
+    let term = |t: usize| samples[t] * samples[t + n].conj();
+
+    let first = starts.start;
+    let mut correlation: Complex32 = (first..first + g).map(term).sum();
+    let mut head: f32 = samples[first..first + g].iter().map(|x| x.norm_sqr()).sum();
+    let mut tail: f32 = samples[first + n..first + n + g].iter().map(|x| x.norm_sqr()).sum();
+    let mut best = (correlation.norm(), first, correlation, head, tail);
+    for t in first + 1..starts.end {
+        correlation += term(t + g - 1) - term(t - 1);
+        head += samples[t + g - 1].norm_sqr() - samples[t - 1].norm_sqr();
+        tail += samples[t + n + g - 1].norm_sqr() - samples[t + n - 1].norm_sqr();
+        if correlation.norm() > best.0 {
+            best = (correlation.norm(), t, correlation, head, tail);
+        }
+    }
+
+    let (magnitude, offset, correlation, head, tail) = best;
+    Some(SymbolTiming {
+        offset,
+        cfo: -correlation.arg() / (2.0 * std::f32::consts::PI),
+        quality: magnitude / (head * tail).sqrt().max(f32::EPSILON),
+    })
+}
+
+
+/// Whole carrier frequency offset, the shift of `spectrum` that puts the most energy on `carriers`.
+pub fn integer_offset(params: OfdmParams, spectrum: &[Complex32], carriers: &[isize], max_shift: isize) -> isize {
+    (-max_shift..=max_shift).max_by(|&a, &b| {
+        let energy = |shift: isize| carriers.iter().map(|&k| spectrum[params.bin(k + shift)].norm_sqr()).sum::<f32>();
+        energy(a).total_cmp(&energy(b))
+    }).unwrap_or(0)
+}
+
+
+/// Turns the FFT windows of OFDM symbols into carriers, removing a frequency offset on the way.
+pub struct OfdmDemod {
+    params: OfdmParams,
+    fft: FFT,
+}
+
+
+impl OfdmDemod {
+    pub fn new(params: OfdmParams) -> Self {
+        Self {
+            params,
+            fft: FFT::new(params.fft_size),
+        }
+    }
+
+    pub fn params(&self) -> OfdmParams {
+        self.params
+    }
+
+    /// FFT of `window`, one FFT size of samples after the guard. `position` counts samples from
+    /// any fixed reference so the offset correction stays phase continuous across symbols, and
+    /// `cfo` is in carrier spacings. The output is in FFT order, see `OfdmParams::bin`.
+    pub fn demodulate(&self, window: &[Complex32], position: i64, cfo: f64, output: &mut Vec<Complex32>) {
+        let n = self.params.fft_size;
+        output.clear();
+        output.extend(window[..n].iter().enumerate().map(|(i, &x)| {
+            let phase = (-2.0 * PI * cfo * (position + i as i64) as f64 / n as f64) % (2.0 * PI);
+            x * Complex32::from_polar(1.0, phase as f32)
+        }));
+        self.fft.forward(output);
+    }
+}
+
+
+/// Differential demodulation of one symbol against the previous one, carrier by carrier.
+pub fn differential(previous: &[Complex32], current: &[Complex32], output: &mut Vec<Complex32>) {
+    output.clear();
+    output.extend(current.iter().zip(previous).map(|(c, p)| c * p.conj()));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::fft::FFT;
+    use crate::ofdm::{differential, find_symbol, integer_offset, OfdmDemod, OfdmParams};
+    use crate::seed::Rng;
+
+    #[test]
+    fn test_ofdm_sync() {
+        let params = OfdmParams { fft_size: 256, guard: 32 };
+        let carriers: Vec<isize> = (-100..=100).filter(|&k| k != 0).collect();
+        let mut rng = Rng::new(0x2545f491);
+
+        // noise, then three DQPSK symbols 3.2 carriers off
+        let fft = FFT::new(params.fft_size);
+        let mut phases = vec![0u32; carriers.len()];
+        let mut sent = Vec::new();
+        let mut signal: Vec<Complex32> = (0..100).map(|_| Complex32::new(0.0, 0.0)).collect();
+        for _ in 0..3 {
+            let data: Vec<u32> = carriers.iter().map(|_| rng.next_u32() % 4).collect();
+            let mut spectrum = vec![Complex32::new(0.0, 0.0); params.fft_size];
+            for ((&k, phase), &d) in carriers.iter().zip(phases.iter_mut()).zip(&data) {
+                *phase = (*phase + d) % 4;
+                spectrum[params.bin(k)] = Complex32::from_polar(1.0, PI / 4.0 + *phase as f32 * PI / 2.0);
+            }
+            fft.inverse(&mut spectrum);
+            signal.extend_from_slice(&spectrum[params.fft_size - params.guard..]);
+            signal.extend_from_slice(&spectrum);
+            sent.push(data);
+        }
+        let signal: Vec<Complex32> = signal.iter().enumerate()
+            .map(|(n, &x)| x * Complex32::from_polar(1.0, 2.0 * PI * 3.2 * n as f32 / params.fft_size as f32))
+            .collect();
+
+        let timing = find_symbol(params, &signal, 50..150).unwrap();
+        assert!(timing.offset.abs_diff(100) <= 4, "{:?}", timing);
+        assert!((timing.cfo - 0.2).abs() < 0.01, "{:?}", timing);
+        assert!(timing.quality > 0.9, "{:?}", timing);
+        assert!(find_symbol(params, &signal, 600..700).is_none());
+
+        let demod = OfdmDemod::new(params);
+        // start the FFT a little inside the guard in case the estimate is late
+        let start = timing.offset + params.guard - 4;
+        let mut spectrum = Vec::new();
+        demod.demodulate(&signal[start..], 0, timing.cfo as f64, &mut spectrum);
+        let shift = integer_offset(params, &spectrum, &carriers, 10);
+        assert_eq!(shift, 3);
+
+        let cfo = shift as f64 + timing.cfo as f64;
+        let mut previous = Vec::new();
+        demod.demodulate(&signal[start..], 0, cfo, &mut previous);
+        for (l, data) in sent.iter().enumerate().skip(1) {
+            let position = l * params.symbol_len();
+            demod.demodulate(&signal[start + position..], position as i64, cfo, &mut spectrum);
+            let mut symbols = Vec::new();
+            differential(&previous, &spectrum, &mut symbols);
+            for (&k, &d) in carriers.iter().zip(data) {
+                let phase = symbols[params.bin(k)].arg().rem_euclid(2.0 * PI);
+                assert_eq!((phase / (PI / 2.0)).round() as u32 % 4, d, "symbol {} carrier {}", l, k);
+            }
+            previous = spectrum.clone();
+        }
+    }
+}
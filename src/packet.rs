@@ -0,0 +1,451 @@
+use std::error::Error;
+use num_complex::Complex32;
+use crate::modem::FSKDemod;
+use crate::snapshot::SnapshotTrigger;
+use crate::traits::*;
+
+
+/// Power squelch that only passes samples while a burst is on the air.
+pub struct BurstDetector {
+    threshold: f32,
+    hang: usize,
+    power: f32,
+    noise: f32,
+    quiet: usize,
+    active: bool,
+}
+
+
+impl BurstDetector {
+    pub fn new(threshold_db: f32, hang: usize) -> Self {
+        Self {
+            threshold: 10f32.powf(threshold_db / 10.0),
+            hang,
+            power: 0.0,
+            noise: 0.0,
+            quiet: 0,
+            active: false,
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for BurstDetector {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &sample in input {
+            self.power += (sample.norm_sqr() - self.power) * 0.05;
+
+            if !self.active {
+                self.noise += (self.power - self.noise) * 0.001;
+                if self.power > (self.noise * self.threshold).max(1e-12) {
+                    self.active = true;
+                    self.quiet = 0;
+                }
+            } else if self.power < self.noise * self.threshold / 2.0 {
+                self.quiet += 1;
+                if self.quiet > self.hang {
+                    self.active = false;
+                }
+            } else {
+                self.quiet = 0;
+            }
+
+            if self.active {
+                output.push(sample);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Match a sync word against a bit stream, tolerating a few bit errors.
+pub struct AccessCodeCorrelator {
+    code: u64,
+    mask: u64,
+    max_errors: u32,
+    shift: u64,
+    count: usize,
+    bits: usize,
+}
+
+
+impl AccessCodeCorrelator {
+    pub fn new(code: u64, bits: usize, max_errors: u32) -> Self {
+        assert!(bits > 0 && bits <= 64, "access code must be 1 to 64 bits");
+        let mask = if bits == 64 { u64::MAX } else { (1 << bits) - 1 };
+        Self {
+            code: code & mask,
+            mask,
+            max_errors,
+            shift: 0,
+            count: 0,
+            bits,
+        }
+    }
+
+    /// Returns the number of bit errors when the access code has just been received.
+    pub fn push(&mut self, bit: u8) -> Option<u32> {
+        self.shift = ((self.shift << 1) | (bit & 1) as u64) & self.mask;
+        self.count += 1;
+        if self.count < self.bits {
+            return None;
+        }
+        let errors = (self.shift ^ self.code).count_ones();
+        (errors <= self.max_errors).then_some(errors)
+    }
+
+    pub fn reset(&mut self) {
+        self.shift = 0;
+        self.count = 0;
+    }
+}
+
+
+/// PN9 data whitening (x^9 + x^5 + 1, all ones seed) as used by CC1101 style radios.
+pub struct Whitening {
+    sequence: Vec<u8>,
+}
+
+
+impl Whitening {
+    pub fn pn9(len: usize) -> Self {
+        let mut state = 0x1ffu16;
+        let mut sequence = Vec::with_capacity(len);
+        for _ in 0..len {
+            sequence.push(state as u8);
+            for _ in 0..8 {
+                let bit = (state ^ (state >> 5)) & 1;
+                state = (state >> 1) | (bit << 8);
+            }
+        }
+        Self {
+            sequence,
+        }
+    }
+
+    pub fn apply(&self, data: &mut [u8]) {
+        for (d, p) in data.iter_mut().zip(self.sequence.iter()) {
+            *d ^= p;
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Crc16 {
+    pub poly: u16,
+    pub init: u16,
+    pub reflect: bool,
+    pub xor_out: u16,
+}
+
+
+impl Crc16 {
+    pub const CCITT_FALSE: Crc16 = Crc16 { poly: 0x1021, init: 0xffff, reflect: false, xor_out: 0 };
+    pub const CC1101: Crc16 = Crc16 { poly: 0x8005, init: 0xffff, reflect: false, xor_out: 0 };
+    pub const KERMIT: Crc16 = Crc16 { poly: 0x1021, init: 0, reflect: true, xor_out: 0 };
+    /// As DAB protects its FIBs.
+    pub const GENIBUS: Crc16 = Crc16 { poly: 0x1021, init: 0xffff, reflect: false, xor_out: 0xffff };
+
+    pub fn checksum(&self, data: &[u8]) -> u16 {
+        if self.reflect {
+            let poly = self.poly.reverse_bits();
+            let mut crc = self.init.reverse_bits();
+            for &byte in data {
+                crc ^= byte as u16;
+                for _ in 0..8 {
+                    crc = if crc & 1 != 0 { (crc >> 1) ^ poly } else { crc >> 1 };
+                }
+            }
+            crc ^ self.xor_out
+        } else {
+            let mut crc = self.init;
+            for &byte in data {
+                crc ^= (byte as u16) << 8;
+                for _ in 0..8 {
+                    crc = if crc & 0x8000 != 0 { (crc << 1) ^ self.poly } else { crc << 1 };
+                }
+            }
+            crc ^ self.xor_out
+        }
+    }
+
+    /// The checksum as transmitted, most significant byte first unless reflected.
+    pub fn to_bytes(&self, crc: u16) -> [u8; 2] {
+        if self.reflect { crc.to_le_bytes() } else { crc.to_be_bytes() }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub enum PacketLength {
+    Fixed(usize),
+    /// The first byte after the access code holds the payload length.
+    Variable { max: usize },
+}
+
+
+#[derive(Clone, Debug)]
+pub struct PacketConfig {
+    pub baud: u32,
+    pub deviation: f32,
+    pub sync_word: u64,
+    pub sync_bits: usize,
+    pub max_sync_errors: u32,
+    pub length: PacketLength,
+    pub crc: Option<Crc16>,
+    pub whitening: bool,
+    pub burst_threshold_db: f32,
+}
+
+
+impl PacketConfig {
+    /// Defaults matching a CC1101 with variable length, whitening and CRC enabled.
+    pub fn cc1101(baud: u32, deviation: f32) -> Self {
+        Self {
+            baud,
+            deviation,
+            sync_word: 0xd391d391,
+            sync_bits: 32,
+            max_sync_errors: 2,
+            length: PacketLength::Variable { max: 255 },
+            crc: Some(Crc16::CC1101),
+            whitening: true,
+            burst_threshold_db: 10.0,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub payload: Vec<u8>,
+    pub sync_errors: u32,
+    pub crc_ok: bool,
+}
+
+
+/// Total bytes expected after the access code, once enough of the packet has arrived to know.
+fn expected_len(config: &PacketConfig, whitening: &Whitening, bytes: &[u8]) -> Option<usize> {
+    let crc_len = if config.crc.is_some() { 2 } else { 0 };
+    match config.length {
+        PacketLength::Fixed(len) => Some(len + crc_len),
+        PacketLength::Variable { max } => {
+            let mut first = [*bytes.first()?];
+            if config.whitening {
+                whitening.apply(&mut first);
+            }
+            Some(1 + (first[0] as usize).min(max) + crc_len)
+        }
+    }
+}
+
+
+struct Collecting {
+    bytes: Vec<u8>,
+    byte: u8,
+    bits: usize,
+    sync_errors: u32,
+}
+
+
+/// Burst detection, FSK demodulation, sync, de-whitening and CRC checking in one block.
+pub struct PacketReceiver {
+    config: PacketConfig,
+    burst: BurstDetector,
+    demod: FSKDemod,
+    correlator: AccessCodeCorrelator,
+    whitening: Whitening,
+    collecting: Option<Collecting>,
+    samples: Vec<Complex32>,
+    bits: Vec<u8>,
+    snapshot: Option<SnapshotTrigger>,
+}
+
+
+impl PacketReceiver {
+    pub fn new(sample_rate: u32, config: PacketConfig) -> Self {
+        let sps = (sample_rate / config.baud) as usize;
+        Self {
+            burst: BurstDetector::new(config.burst_threshold_db, 4 * sps),
+            demod: FSKDemod::new(sample_rate, config.baud, config.deviation),
+            correlator: AccessCodeCorrelator::new(config.sync_word, config.sync_bits, config.max_sync_errors),
+            whitening: Whitening::pn9(258),
+            collecting: None,
+            samples: Vec::new(),
+            bits: Vec::new(),
+            snapshot: None,
+            config,
+        }
+    }
+
+    /// Request an IQ snapshot for every packet that fails its CRC.
+    pub fn set_snapshot_trigger(&mut self, trigger: SnapshotTrigger) {
+        self.snapshot = Some(trigger);
+    }
+
+    fn finish(&self, mut bytes: Vec<u8>, sync_errors: u32) -> Packet {
+        if self.config.whitening {
+            self.whitening.apply(&mut bytes);
+        }
+
+        let crc_ok = match self.config.crc {
+            Some(crc) => {
+                let split = bytes.len() - 2;
+                let expected = crc.to_bytes(crc.checksum(&bytes[..split]));
+                let ok = bytes[split..] == expected;
+                bytes.truncate(split);
+                ok
+            }
+            None => true,
+        };
+
+        if let PacketLength::Variable { .. } = self.config.length {
+            bytes.remove(0);
+        }
+
+        Packet {
+            payload: bytes,
+            sync_errors,
+            crc_ok,
+        }
+    }
+}
+
+
+impl Filter<Complex32, Packet> for PacketReceiver {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Packet>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+
+        self.burst.filter(input, &mut self.samples)?;
+        if !self.samples.is_empty() {
+            self.demod.filter(&self.samples, &mut self.bits)?;
+        } else {
+            self.bits.clear();
+        }
+
+        let bits = std::mem::take(&mut self.bits);
+        for &bit in bits.iter() {
+            match self.collecting.as_mut() {
+                None => {
+                    if let Some(sync_errors) = self.correlator.push(bit) {
+                        self.collecting = Some(Collecting {
+                            bytes: Vec::new(),
+                            byte: 0,
+                            bits: 0,
+                            sync_errors,
+                        });
+                    }
+                }
+                Some(state) => {
+                    state.byte = (state.byte << 1) | bit;
+                    state.bits += 1;
+                    if state.bits == 8 {
+                        state.bytes.push(state.byte);
+                        state.bits = 0;
+                    }
+
+                    let done = expected_len(&self.config, &self.whitening, &state.bytes)
+                        .is_some_and(|len| state.bytes.len() >= len);
+                    if done {
+                        let state = self.collecting.take().unwrap();
+                        let packet = self.finish(state.bytes, state.sync_errors);
+                        if !packet.crc_ok && let Some(trigger) = &self.snapshot {
+                            trigger.request("crc");
+                        }
+                        output.push(packet);
+                        self.correlator.reset();
+                    }
+                }
+            }
+        }
+        self.bits = bits;
+
+        if !self.burst.active() {
+            self.correlator.reset();
+            self.collecting = None;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex32;
+    use crate::packet::{Crc16, PacketConfig, PacketReceiver, Whitening};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(Crc16::CCITT_FALSE.checksum(b"123456789"), 0x29b1);
+        assert_eq!(Crc16::KERMIT.checksum(b"123456789"), 0x2189);
+    }
+
+    #[test]
+    fn test_whitening() {
+        let mut data = [0u8; 4];
+        Whitening::pn9(4).apply(&mut data);
+        assert_eq!(data, [0xff, 0xe1, 0x1d, 0x9a]);
+    }
+
+    #[test]
+    fn test_packet_receiver() -> Result<(), Box<dyn std::error::Error>> {
+        let (sample_rate, baud, deviation) = (96000u32, 9600u32, 20000.0f32);
+        let sps = (sample_rate / baud) as usize;
+        let config = PacketConfig::cc1101(baud, deviation);
+        let payload = b"hello telemetry".to_vec();
+
+        let mut frame = vec![payload.len() as u8];
+        frame.extend_from_slice(&payload);
+        let crc = Crc16::CC1101.checksum(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        Whitening::pn9(frame.len()).apply(&mut frame);
+
+        let mut bytes = vec![0xaa; 4];
+        bytes.extend_from_slice(&config.sync_word.to_be_bytes()[4..]);
+        bytes.extend_from_slice(&frame);
+        bytes.extend_from_slice(&[0xaa; 2]);
+
+        let mut noise = 0x2545_f491u32;
+        let mut rand = move || {
+            noise ^= noise << 13;
+            noise ^= noise >> 17;
+            noise ^= noise << 5;
+            (noise as f32 / u32::MAX as f32 - 0.5) * 0.01
+        };
+
+        let mut samples: Vec<Complex32> = (0..4000).map(|_| Complex32::new(rand(), rand())).collect();
+        let mut phase = 0f32;
+        for byte in bytes.iter() {
+            for i in (0..8).rev() {
+                let freq = if (byte >> i) & 1 == 1 { deviation } else { -deviation };
+                for _ in 0..sps {
+                    phase += 2.0 * std::f32::consts::PI * freq / sample_rate as f32;
+                    samples.push(Complex32::from_polar(1.0, phase));
+                }
+            }
+        }
+        samples.extend((0..4000).map(|_| Complex32::new(rand(), rand())));
+
+        let mut receiver = PacketReceiver::new(sample_rate, config);
+        let mut packets = Vec::new();
+        let mut output = Vec::new();
+        for chunk in samples.chunks(1000) {
+            receiver.filter(chunk, &mut output)?;
+            packets.append(&mut output);
+        }
+
+        assert_eq!(packets.len(), 1);
+        assert!(packets[0].crc_ok, "{:?}", packets[0]);
+        assert_eq!(packets[0].payload, payload);
+        Ok(())
+    }
+
+}
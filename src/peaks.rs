@@ -0,0 +1,202 @@
+use num_complex::Complex32;
+use crate::fft::{power_spectrum, Window, FFT};
+use crate::recorder::ScanChannel;
+
+
+/// Averages power spectra of I/Q frames, samples can arrive in buffers of any length.
+pub struct PsdAverage {
+    fft: FFT,
+    window: Vec<f32>,
+    sum: Vec<f32>,
+    frames: usize,
+    pending: Vec<Complex32>,
+    spectrum: Vec<f32>,
+}
+
+
+impl PsdAverage {
+    pub fn new(fft_size: usize) -> Self {
+        Self {
+            fft: FFT::new(fft_size),
+            window: Window::Hann.coefficients(fft_size),
+            sum: vec![0.0; fft_size],
+            frames: 0,
+            pending: Vec::with_capacity(fft_size),
+            spectrum: Vec::with_capacity(fft_size),
+        }
+    }
+
+    pub fn push(&mut self, samples: &[Complex32]) {
+        let size = self.fft.size();
+        for chunk in samples.chunks(size) {
+            let take = (size - self.pending.len()).min(chunk.len());
+            self.pending.extend_from_slice(&chunk[..take]);
+            if self.pending.len() == size {
+                power_spectrum(&self.fft, &self.window, &self.pending, &mut self.spectrum);
+                for (s, p) in self.sum.iter_mut().zip(&self.spectrum) {
+                    *s += p;
+                }
+                self.frames += 1;
+                self.pending.clear();
+            }
+            self.pending.extend_from_slice(&chunk[take..]);
+        }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// DC centered average in dB, None before the first whole frame.
+    pub fn average_db(&self) -> Option<Vec<f32>> {
+        (self.frames > 0).then(|| self.sum.iter().map(|s| 10.0 * (s / self.frames as f32).max(1e-20).log10()).collect())
+    }
+
+    pub fn reset(&mut self) {
+        self.sum.iter_mut().for_each(|s| *s = 0.0);
+        self.frames = 0;
+        self.pending.clear();
+    }
+}
+
+
+/// A signal standing out of the noise floor of a PSD.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DetectedSignal {
+    /// Power weighted center of the bins the signal covers.
+    pub center_hz: f64,
+    /// Width of the bins above the threshold, so strong signals come out wider.
+    pub bandwidth_hz: f64,
+    pub peak_db: f32,
+    /// Peak above the noise floor.
+    pub snr_db: f32,
+}
+
+
+impl DetectedSignal {
+    /// Scan list entry on the nearest multiple of `raster_hz`, labeled with the bandwidth.
+    pub fn scan_channel(&self, raster_hz: f64) -> ScanChannel {
+        let frequency = ((self.center_hz / raster_hz).round() * raster_hz) as u64;
+        ScanChannel {
+            frequency,
+            label: format!("{:.4} MHz {:.1} kHz {:.0} dB", frequency as f64 / 1e6, self.bandwidth_hz / 1e3, self.snr_db),
+        }
+    }
+}
+
+
+/// The 25th percentile of the bins, so up to three quarters of a span can be occupied.
+pub fn noise_floor_db(power_db: &[f32]) -> Option<f32> {
+    let mut sorted = power_db.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    sorted.get(sorted.len() / 4).copied()
+}
+
+
+/// Lists the signals in an averaged PSD: runs of bins more than `threshold_db` above the noise
+/// floor, where runs separated by a few quiet bins count as one signal.
+pub struct PeakDetector {
+    threshold_db: f32,
+    max_gap: usize,
+    min_bins: usize,
+}
+
+
+impl PeakDetector {
+    pub fn new(threshold_db: f32) -> Self {
+        Self {
+            threshold_db,
+            max_gap: 1,
+            min_bins: 1,
+        }
+    }
+
+    /// Bins below the threshold a signal may have inside it, defaults to 1.
+    pub fn set_max_gap(&mut self, bins: usize) {
+        self.max_gap = bins;
+    }
+
+    /// Narrower runs are ignored, defaults to 1.
+    pub fn set_min_bins(&mut self, bins: usize) {
+        self.min_bins = bins.max(1);
+    }
+
+    /// `power_db[i]` at `start_hz + i * bin_hz`, e.g. a DC centered `PsdAverage` starting at
+    /// center minus half the sample rate.
+    pub fn detect(&self, power_db: &[f32], start_hz: f64, bin_hz: f64) -> Vec<DetectedSignal> {
+        let Some(floor) = noise_floor_db(power_db) else { return Vec::new() };
+        let above: Vec<usize> = (0..power_db.len()).filter(|&i| power_db[i] > floor + self.threshold_db).collect();
+
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for i in above {
+            match runs.last_mut() {
+                Some((_, last)) if i - *last <= self.max_gap + 1 => *last = i,
+                _ => runs.push((i, i)),
+            }
+        }
+
+        runs.into_iter().filter(|(first, last)| last - first + 1 >= self.min_bins).map(|(first, last)| {
+            let bins = &power_db[first..=last];
+            let weights: Vec<f64> = bins.iter().map(|&p| 10f64.powf(p as f64 / 10.0)).collect();
+            let total: f64 = weights.iter().sum();
+            let center: f64 = weights.iter().enumerate().map(|(k, w)| (first + k) as f64 * w).sum::<f64>() / total;
+            let peak_db = bins.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            DetectedSignal {
+                center_hz: start_hz + center * bin_hz,
+                bandwidth_hz: (last - first + 1) as f64 * bin_hz,
+                peak_db,
+                snr_db: peak_db - floor,
+            }
+        }).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use num_complex::Complex32;
+    use crate::peaks::{PeakDetector, PsdAverage};
+    use crate::seed::Rng;
+
+    #[test]
+    fn test_peak_detector() {
+        let mut rng = Rng::new(0x2545f491);
+        let mut random = move || rng.next_u32() as f64 / u32::MAX as f64;
+
+        // 1 MHz of noise with a carrier at +100 kHz and 20 kHz of tones around -200 kHz
+        let rate = 1e6;
+        let phases: Vec<f64> = (0..21).map(|_| 2.0 * PI * random()).collect();
+        let samples: Vec<Complex32> = (0..64 * 1024).map(|n| {
+            let t = n as f64 / rate;
+            let carrier = 2.0 * PI * 100e3 * t;
+            let mut x = Complex32::from_polar(0.3, carrier as f32);
+            for (k, phase) in phases.iter().enumerate() {
+                x += Complex32::from_polar(0.05, (2.0 * PI * (-210e3 + k as f64 * 1e3) * t + phase) as f32);
+            }
+            x + Complex32::new((random() - 0.5) as f32, (random() - 0.5) as f32) * 0.01
+        }).collect();
+
+        let mut psd = PsdAverage::new(1024);
+        for buffer in samples.chunks(3000) {
+            psd.push(buffer);
+        }
+        assert_eq!(psd.frames(), 64);
+        let power_db = psd.average_db().unwrap();
+        let bin = rate / 1024.0;
+        let signals = PeakDetector::new(15.0).detect(&power_db, -rate / 2.0, bin);
+        assert_eq!(signals.len(), 2, "{:?}", signals);
+
+        let (band, carrier) = (signals[0], signals[1]);
+        assert!((carrier.center_hz - 100e3).abs() < bin / 2.0, "{:?}", carrier);
+        // measured at the threshold, the window skirt of a strong carrier counts
+        assert!(carrier.bandwidth_hz < 12.0 * bin, "{:?}", carrier);
+        assert!(carrier.snr_db > 40.0, "{:?}", carrier);
+        assert!((band.center_hz + 200e3).abs() < 2.0 * bin, "{:?}", band);
+        assert!((band.bandwidth_hz - 20e3).abs() < 5.0 * bin, "{:?}", band);
+
+        let channel = carrier.scan_channel(12.5e3);
+        assert_eq!(channel.frequency, 100_000);
+        assert!(PeakDetector::new(15.0).detect(&[], 0.0, bin).is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use std::error::Error;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::traits::*;
+
+
+/// How often blocked waits and the ctrl-c watcher look at the token, in case a wakeup was missed.
+pub const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+
+#[derive(Default)]
+struct CancelInner {
+    cancelled: AtomicBool,
+    condvars: Mutex<Vec<Weak<Condvar>>>,
+}
+
+
+/// Cooperative stop signal shared between a pipeline runner and the blocking waits in its blocks.
+/// Cancelled waits fail with `ErrorKind::Interrupted`.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelInner>,
+}
+
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let mut condvars = self.inner.condvars.lock().unwrap();
+        condvars.retain(|condvar| match condvar.upgrade() {
+            Some(condvar) => {
+                condvar.notify_all();
+                true
+            },
+            None => false,
+        });
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wake `condvar` when the token is cancelled.
+    pub(crate) fn register(&self, condvar: &Arc<Condvar>) {
+        self.inner.condvars.lock().unwrap().push(Arc::downgrade(condvar));
+    }
+
+    /// A token cancelled by the first SIGINT, a second one terminates the process as usual.
+    #[cfg(unix)]
+    pub fn ctrl_c() -> Result<Self, Box<dyn Error>> {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+        extern "C" fn on_sigint(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+            unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL); }
+        }
+
+        let token = Self::new();
+        let handler = on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(libc::SIGINT, handler) } == libc::SIG_ERR {
+            return Err("unable to install the SIGINT handler".into());
+        }
+
+        // the signal handler can only set a flag, cancelling happens out here
+        let inner = Arc::downgrade(&token.inner);
+        std::thread::spawn(move || {
+            while let Some(inner) = inner.upgrade() {
+                let token = CancelToken { inner };
+                if token.is_cancelled() {
+                    break;
+                }
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    token.cancel();
+                    break;
+                }
+                drop(token);
+                std::thread::sleep(CANCEL_POLL);
+            }
+        });
+        Ok(token)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    EndOfStream,
+    Cancelled,
+    Duration,
+    SampleLimit,
+    /// A `stop_when` condition returned true.
+    Condition,
+}
+
+
+#[derive(Clone, Debug)]
+pub struct RunStats {
+    /// Samples read from the source.
+    pub samples: u64,
+    pub buffers: u64,
+    pub elapsed: Duration,
+    pub stop: StopReason,
+}
+
+
+pub type StopCondition = Box<dyn FnMut(&RunStats) -> bool>;
+
+
+/// Reads a source and hands every buffer to the stages behind it until the stream ends or the token
+/// is cancelled. Returning normally lets the blocks flush and shut down in their `Drop`.
+pub struct Runner {
+    cancel: CancelToken,
+    conditions: Vec<StopCondition>,
+}
+
+
+impl Runner {
+    pub fn new(cancel: CancelToken) -> Self {
+        Self {
+            cancel,
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Stop every run once `condition` returns true, checked after each buffer is processed.
+    pub fn stop_when(&mut self, condition: impl FnMut(&RunStats) -> bool + 'static) -> &mut Self {
+        self.conditions.push(Box::new(condition));
+        self
+    }
+
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    pub fn run<T, S>(&mut self, source: &mut S, process: impl FnMut(&[T]) -> Result<(), Box<dyn Error>>) -> Result<RunStats, Box<dyn Error>>
+    where S: Source<T> + ?Sized
+    {
+        self.run_limited(source, process, None, None)
+    }
+
+    /// Run for at most `duration` of wall clock time.
+    pub fn run_for<T, S>(&mut self, duration: Duration, source: &mut S, process: impl FnMut(&[T]) -> Result<(), Box<dyn Error>>) -> Result<RunStats, Box<dyn Error>>
+    where S: Source<T> + ?Sized
+    {
+        self.run_limited(source, process, Some(duration), None)
+    }
+
+    /// Process exactly `samples` samples unless the stream ends first, the last buffer is cut short.
+    pub fn run_samples<T, S>(&mut self, samples: u64, source: &mut S, process: impl FnMut(&[T]) -> Result<(), Box<dyn Error>>) -> Result<RunStats, Box<dyn Error>>
+    where S: Source<T> + ?Sized
+    {
+        self.run_limited(source, process, None, Some(samples))
+    }
+
+    fn run_limited<T, S>(&mut self, source: &mut S, mut process: impl FnMut(&[T]) -> Result<(), Box<dyn Error>>, duration: Option<Duration>, samples: Option<u64>) -> Result<RunStats, Box<dyn Error>>
+    where S: Source<T> + ?Sized
+    {
+        let start = Instant::now();
+        let mut stats = RunStats {
+            samples: 0,
+            buffers: 0,
+            elapsed: Duration::ZERO,
+            stop: StopReason::EndOfStream,
+        };
+        let mut buffer = Vec::new();
+        loop {
+            if self.cancel.is_cancelled() {
+                stats.stop = StopReason::Cancelled;
+                break;
+            }
+            if duration.is_some_and(|duration| start.elapsed() >= duration) {
+                stats.stop = StopReason::Duration;
+                break;
+            }
+            if samples.is_some_and(|samples| stats.samples >= samples) {
+                stats.stop = StopReason::SampleLimit;
+                break;
+            }
+            match source.read(&mut buffer) {
+                // whatever error a cancelled wait surfaced as, the stop was requested
+                Err(_) if self.cancel.is_cancelled() => {
+                    stats.stop = StopReason::Cancelled;
+                    break;
+                },
+                Err(e) => return Err(e),
+                Ok(()) if buffer.is_empty() => break,
+                Ok(()) => {},
+            }
+            if let Some(samples) = samples {
+                buffer.truncate((samples - stats.samples).min(buffer.len() as u64) as usize);
+            }
+            stats.samples += buffer.len() as u64;
+            stats.buffers += 1;
+            process(&buffer)?;
+            stats.elapsed = start.elapsed();
+            if self.conditions.iter_mut().any(|condition| condition(&stats)) {
+                stats.stop = StopReason::Condition;
+                break;
+            }
+        }
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::block::{NullSource, VirtualAudioCable};
+    use crate::pipeline::{CancelToken, Runner, StopReason};
+    use crate::traits::Sink;
+
+    #[test]
+    fn test_runner_cancel() -> Result<(), Box<dyn std::error::Error>> {
+        let VirtualAudioCable { mut source, mut sink } = VirtualAudioCable::new(1000)?;
+        let cancel = CancelToken::new();
+        source.set_cancel(&cancel);
+        sink.write(&[0.5; 300])?;
+
+        let canceller = cancel.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+
+        // the source blocks on the still open cable until the token fires
+        let mut seen = 0;
+        let stats = Runner::new(cancel).run(&mut source, |block: &[f32]| {
+            seen += block.len();
+            Ok(())
+        })?;
+        thread.join().unwrap();
+        assert_eq!(stats.stop, StopReason::Cancelled);
+        assert_eq!(stats.samples, 300);
+        assert_eq!(seen, 300);
+        drop(sink);
+        Ok(())
+    }
+
+    #[test]
+    fn test_runner_limits() -> Result<(), Box<dyn std::error::Error>> {
+        let mut runner = Runner::new(CancelToken::new());
+        let mut source = NullSource::new(48000, 1000);
+        let mut seen = 0;
+        let stats = runner.run_samples(2500, &mut source, |block: &[f32]| {
+            seen += block.len();
+            Ok(())
+        })?;
+        assert_eq!((stats.stop, stats.samples, stats.buffers, seen), (StopReason::SampleLimit, 2500, 3, 2500));
+
+        let stats = runner.run_for(Duration::from_millis(20), &mut source, |_: &[f32]| Ok(()))?;
+        assert_eq!(stats.stop, StopReason::Duration);
+        assert!(stats.elapsed >= Duration::from_millis(20));
+
+        runner.stop_when(|stats| stats.buffers == 5);
+        let stats = runner.run(&mut source, |_: &[f32]| Ok(()))?;
+        assert_eq!((stats.stop, stats.samples), (StopReason::Condition, 5000));
+        Ok(())
+    }
+}
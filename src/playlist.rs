@@ -0,0 +1,297 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use hound::WavReader;
+use num_complex::Complex32;
+use crate::block::WavSource;
+#[cfg(feature = "zstd")]
+use crate::compress::{compressed_format, ZstdFrameReader};
+use crate::iqfile::{IQFileSource, IQFormat};
+use crate::rate::{RateAware, SampleRate};
+use crate::tag::{Tag, TagValue, Tagged};
+use crate::traits::*;
+
+
+/// The file a `PlaylistSource` is reading.
+enum Entry {
+    Wav(Box<WavSource<BufReader<File>>>),
+    IQ(IQFileSource<BufReader<File>>),
+    #[cfg(feature = "zstd")]
+    Zstd(IQFileSource<ZstdFrameReader<BufReader<File>>>),
+}
+
+
+/// Whether `path` is an I/Q recording, `.zst` compressed ones included. Those are an error without
+/// the `zstd` feature, found before anything plays.
+fn is_iq(path: &Path) -> Result<bool, Box<dyn Error>> {
+    if path.extension().is_some_and(|extension| extension == "zst") {
+        #[cfg(feature = "zstd")]
+        return match compressed_format(path) {
+            Some(_) => Ok(true),
+            None => Err(format!("{}: unknown I/Q format, expected an I/Q extension like .cs16 before .zst", path.display()).into()),
+        };
+        #[cfg(not(feature = "zstd"))]
+        return Err(format!("{}: compressed recordings need the zstd feature", path.display()).into());
+    }
+    Ok(IQFormat::from_path(path).is_some())
+}
+
+
+type ReadEntry<T> = fn(&mut Entry, &mut Vec<T>) -> Result<(), Box<dyn Error>>;
+
+
+/// Plays WAV or headerless I/Q recordings, plain or `.zst` compressed, back to back as one stream, e.g. the pieces `split`
+/// wrote. Every file has to be at the same rate, WAV files need the same channel count and mono ones
+/// can't mix with I/Q, which is checked up front so a long run doesn't fail halfway. Each file's first sample gets a
+/// `TagValue::FileStart` tag.
+pub struct PlaylistSource {
+    files: Vec<PathBuf>,
+    next: usize,
+    entry: Option<Entry>,
+    sample_rate: u32,
+    complex: bool,
+    samples_per_read: usize,
+    position: u64,
+    tags: Vec<Tag>,
+}
+
+
+impl PlaylistSource {
+    /// `sample_rate` is needed when there are I/Q files, otherwise the WAV headers give it.
+    pub fn new(files: Vec<PathBuf>, sample_rate: Option<u32>) -> Result<Self, Box<dyn Error>> {
+        if files.is_empty() {
+            return Err("empty playlist".into());
+        }
+        let mut rate = sample_rate;
+        let mut channels = None;
+        let mut iq = None;
+        for path in &files {
+            if is_iq(path)? {
+                if sample_rate.is_none() {
+                    return Err(format!("{}: I/Q recordings need the sample rate", path.display()).into());
+                }
+                iq = iq.or(Some(path));
+                continue;
+            }
+            let spec = WavReader::open(path).map_err(|e| format!("{}: {}", path.display(), e))?.spec();
+            if *rate.get_or_insert(spec.sample_rate) != spec.sample_rate {
+                return Err(format!("{}: {} Hz in a playlist at {} Hz", path.display(), spec.sample_rate, rate.unwrap()).into());
+            }
+            if *channels.get_or_insert(spec.channels) != spec.channels {
+                return Err(format!("{}: {} channels after files with {}", path.display(), spec.channels, channels.unwrap()).into());
+            }
+        }
+        // mono files only read as f32 and I/Q files only as complex
+        if let (Some(1), Some(path)) = (channels, iq) {
+            return Err(format!("{}: I/Q recording in a playlist of mono WAV files", path.display()).into());
+        }
+        let sample_rate = rate.unwrap();
+        Ok(Self {
+            files,
+            next: 0,
+            entry: None,
+            sample_rate,
+            complex: channels != Some(1),
+            samples_per_read: (sample_rate as usize / 100).max(1),
+            position: 0,
+            tags: Vec::new(),
+        })
+    }
+
+    /// The files matching `pattern` in name order, which is recording order for `split` pieces.
+    pub fn glob(pattern: &str, sample_rate: Option<u32>) -> Result<Self, Box<dyn Error>> {
+        let mut files = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+        files.sort();
+        if files.is_empty() {
+            return Err(format!("no files match {}", pattern).into());
+        }
+        Self::new(files, sample_rate)
+    }
+
+    /// False for playlists of mono WAV files only, the ones that can be read as `f32`.
+    pub fn is_complex(&self) -> bool {
+        self.complex
+    }
+
+    /// Defaults to 10 ms of samples, takes effect from the next file.
+    pub fn set_samples_per_read(&mut self, samples: usize) {
+        self.samples_per_read = samples.max(1);
+    }
+
+    /// The file being read, None before the first read and after the last file.
+    pub fn current(&self) -> Option<&Path> {
+        self.entry.as_ref().map(|_| self.files[self.next - 1].as_path())
+    }
+
+    /// Moves on to the next file, false after the last one.
+    fn advance(&mut self) -> Result<bool, Box<dyn Error>> {
+        self.entry = None;
+        let Some(path) = self.files.get(self.next) else { return Ok(false) };
+        self.next += 1;
+        self.entry = Some(match IQFormat::from_path(path) {
+            Some(format) => {
+                let mut source = IQFileSource::open(path.clone(), Some(format), self.sample_rate)?;
+                source.set_samples_per_read(self.samples_per_read);
+                Entry::IQ(source)
+            },
+            #[cfg(feature = "zstd")]
+            None if compressed_format(path).is_some() => {
+                let mut source = IQFileSource::open_zstd(path.clone(), None, self.sample_rate)?;
+                source.set_samples_per_read(self.samples_per_read);
+                Entry::Zstd(source)
+            },
+            None => Entry::Wav(Box::new(WavSource::new(path.clone(), self.samples_per_read)?)),
+        });
+        self.tags.push(Tag { offset: self.position, value: TagValue::FileStart(path.clone()) });
+        Ok(true)
+    }
+
+    /// Reads from the current file, going on to the next ones while they come up empty.
+    fn read_with<T>(&mut self, dst: &mut Vec<T>, read: ReadEntry<T>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        loop {
+            if let Some(entry) = &mut self.entry {
+                read(entry, dst)?;
+                if !dst.is_empty() {
+                    self.position += dst.len() as u64;
+                    return Ok(());
+                }
+            }
+            if !self.advance()? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+
+impl Source<Complex32> for PlaylistSource {
+    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        self.read_with(dst, |entry, dst| match entry {
+            Entry::Wav(source) => source.read(dst),
+            Entry::IQ(source) => source.read(dst),
+            #[cfg(feature = "zstd")]
+            Entry::Zstd(source) => source.read(dst),
+        })
+    }
+}
+
+
+/// For playlists of mono WAV files.
+impl Source<f32> for PlaylistSource {
+    fn read(&mut self, dst: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        self.read_with(dst, |entry, dst| match entry {
+            Entry::Wav(source) => source.read(dst),
+            _ => Err("I/Q recordings only play as complex samples".into()),
+        })
+    }
+}
+
+
+impl Tagged for PlaylistSource {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        dst.append(&mut self.tags);
+    }
+}
+
+
+impl RateAware for PlaylistSource {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use hound::{SampleFormat, WavSpec, WavWriter};
+    use num_complex::Complex32;
+    use crate::iqfile::IQFileSink;
+    use crate::playlist::PlaylistSource;
+    use crate::tag::{Tag, TagValue, Tagged};
+    use crate::traits::{Sink, Source};
+
+    #[test]
+    fn test_playlist_source() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("rust_dsp_playlist_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        // three pieces of a ramp, the middle one as a WAV file
+        let ramp: Vec<Complex32> = (0..3000).map(|n| Complex32::new(n as f32 / 65536.0, -(n as f32) / 65536.0)).collect();
+        let paths = [dir.join("capture_000.cf32"), dir.join("capture_001.wav"), dir.join("capture_002.cf32")];
+        for (path, piece) in paths.iter().zip(ramp.chunks(1000)) {
+            if path.extension().unwrap() == "wav" {
+                let spec = WavSpec { channels: 2, sample_rate: 48000, bits_per_sample: 16, sample_format: SampleFormat::Int };
+                let mut writer = WavWriter::create(path, spec)?;
+                for x in piece {
+                    // WavSource divides by 2^16 - 1
+                    writer.write_sample((x.re * 65535.0).round() as i16)?;
+                    writer.write_sample((x.im * 65535.0).round() as i16)?;
+                }
+                writer.finalize()?;
+            } else {
+                let mut sink = IQFileSink::create(path.clone(), None)?;
+                sink.write(piece)?;
+                sink.into_inner()?;
+            }
+        }
+
+        let mut playlist = PlaylistSource::glob(dir.join("capture_*").to_str().unwrap(), Some(48000))?;
+        playlist.set_samples_per_read(700);
+        let mut output = Vec::new();
+        let mut buffer = Vec::new();
+        loop {
+            Source::<Complex32>::read(&mut playlist, &mut buffer)?;
+            if buffer.is_empty() {
+                break;
+            }
+            output.extend_from_slice(&buffer);
+        }
+        assert_eq!(output.len(), ramp.len());
+        assert!(output.iter().zip(&ramp).all(|(a, b)| (a - b).norm() < 1e-4));
+
+        let mut tags = Vec::new();
+        playlist.take_tags(&mut tags);
+        let expected: Vec<Tag> = paths.iter().enumerate()
+            .map(|(i, path)| Tag { offset: 1000 * i as u64, value: TagValue::FileStart(path.clone()) })
+            .collect();
+        assert_eq!(tags, expected);
+
+        // compressed recordings play like the others
+        #[cfg(feature = "zstd")]
+        {
+            let path = dir.join("capture.cs16.zst");
+            let mut sink = IQFileSink::create_zstd(path.clone(), None, 3)?;
+            sink.write(&ramp)?;
+            sink.into_inner()?.finish()?;
+            let mut playlist = PlaylistSource::new(vec![path], Some(48000))?;
+            let mut output = Vec::new();
+            loop {
+                Source::<Complex32>::read(&mut playlist, &mut buffer)?;
+                if buffer.is_empty() {
+                    break;
+                }
+                output.extend_from_slice(&buffer);
+            }
+            assert_eq!(output.len(), ramp.len());
+            assert!(output.iter().zip(&ramp).all(|(a, b)| (a - b).norm() < 1e-4));
+        }
+
+        assert!(PlaylistSource::new(paths.to_vec(), None).is_err());
+        assert!(PlaylistSource::new(paths[1..2].to_vec(), Some(44100)).is_err());
+
+        // mono audio can't play in the same stream as I/Q
+        let mono = dir.join("audio.wav");
+        let spec = WavSpec { channels: 1, sample_rate: 48000, bits_per_sample: 16, sample_format: SampleFormat::Int };
+        let mut writer = WavWriter::create(&mono, spec)?;
+        writer.write_sample(0i16)?;
+        writer.finalize()?;
+        assert!(PlaylistSource::new(vec![mono.clone()], None).is_ok_and(|playlist| !playlist.is_complex()));
+        let error = PlaylistSource::new(vec![mono, paths[0].clone()], Some(48000)).err().unwrap();
+        assert!(error.to_string().contains("capture_000.cf32"), "{}", error);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
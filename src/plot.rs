@@ -0,0 +1,462 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use num_complex::Complex32;
+use crate::fft::{power_spectrum, Window, FFT};
+use crate::traits::*;
+
+
+const MARGIN_LEFT: usize = 56;
+const MARGIN_RIGHT: usize = 72;
+const MARGIN_TOP: usize = 20;
+const MARGIN_BOTTOM: usize = 28;
+const FONT_SCALE: usize = 2;
+const BACKGROUND: [u8; 3] = [16, 16, 24];
+const FOREGROUND: [u8; 3] = [220, 220, 220];
+const GRID: [u8; 3] = [56, 56, 72];
+
+
+/// 3x5 glyphs, one row of 3 bits per nibble from the top.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        'k' => [0b100, 0b101, 0b110, 0b101, 0b101],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'z' => [0b000, 0b111, 0b010, 0b100, 0b111],
+        'd' => [0b001, 0b001, 0b111, 0b101, 0b111],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        _ => [0; 5],
+    }
+}
+
+
+/// Perceptually ordered dark blue -> green -> yellow map for `t` in 0..=1.
+pub fn colormap(t: f32) -> [u8; 3] {
+    const STOPS: [[f32; 3]; 5] = [
+        [68.0, 1.0, 84.0],
+        [59.0, 82.0, 139.0],
+        [33.0, 145.0, 140.0],
+        [94.0, 201.0, 98.0],
+        [253.0, 231.0, 37.0],
+    ];
+    let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) } * (STOPS.len() - 1) as f32;
+    let i = (t as usize).min(STOPS.len() - 2);
+    let f = t - i as f32;
+    let mut rgb = [0u8; 3];
+    for (c, out) in rgb.iter_mut().enumerate() {
+        *out = (STOPS[i][c] + f * (STOPS[i + 1][c] - STOPS[i][c])).round() as u8;
+    }
+    rgb
+}
+
+
+pub fn format_frequency(hz: f64) -> String {
+    let abs = hz.abs();
+    if abs >= 1e9 {
+        format!("{:.4}GHz", hz / 1e9)
+    } else if abs >= 1e6 {
+        format!("{:.3}MHz", hz / 1e6)
+    } else if abs >= 1e3 {
+        format!("{:.1}kHz", hz / 1e3)
+    } else {
+        format!("{:.0}Hz", hz)
+    }
+}
+
+
+/// RGB image with just enough drawing for spectrum plots.
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for _ in 0..width * height {
+            pixels.extend_from_slice(&BACKGROUND);
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = 3 * (y * self.width + x);
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let i = 3 * (y * self.width + x);
+            self.pixels[i..i + 3].copy_from_slice(&rgb);
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, rgb: [u8; 3]) {
+        for yy in y..(y + h).min(self.height) {
+            for xx in x..(x + w).min(self.width) {
+                self.set(xx, yy, rgb);
+            }
+        }
+    }
+
+    pub fn text_width(text: &str) -> usize {
+        text.chars().count() * 4 * FONT_SCALE
+    }
+
+    pub fn text(&mut self, x: usize, y: usize, text: &str, rgb: [u8; 3]) {
+        for (n, c) in text.chars().enumerate() {
+            let x0 = x + n * 4 * FONT_SCALE;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) != 0 {
+                        self.fill_rect(x0 + col * FONT_SCALE, y + row * FONT_SCALE, FONT_SCALE, FONT_SCALE, rgb);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn save_png(&self, path: &PathBuf) -> Result<(), Box<dyn Error>> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+
+/// Axis ranges shared by the PSD and waterfall renderers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlotRange {
+    pub start_hz: f64,
+    pub stop_hz: f64,
+    pub min_db: f32,
+    pub max_db: f32,
+}
+
+
+impl PlotRange {
+    /// dB range from the 10th percentile up to a little above the peak.
+    pub fn auto(start_hz: f64, stop_hz: f64, values_db: &[f32]) -> Self {
+        let mut sorted: Vec<f32> = values_db.iter().copied().filter(|v| v.is_finite()).collect();
+        sorted.sort_by(f32::total_cmp);
+        let (min_db, max_db) = if sorted.is_empty() {
+            (-100.0, 0.0)
+        } else {
+            (sorted[sorted.len() / 10] - 5.0, sorted[sorted.len() - 1] + 5.0)
+        };
+        Self {
+            start_hz,
+            stop_hz,
+            min_db: min_db.floor(),
+            max_db: max_db.max(min_db + 10.0).ceil(),
+        }
+    }
+
+    fn normalize(&self, db: f32) -> f32 {
+        (db - self.min_db) / (self.max_db - self.min_db)
+    }
+}
+
+
+fn draw_frame(canvas: &mut Canvas, range: &PlotRange, db_axis: bool) {
+    let plot_w = canvas.width - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_h = canvas.height - MARGIN_TOP - MARGIN_BOTTOM;
+    let bottom = MARGIN_TOP + plot_h;
+
+    for tick in 0..=4 {
+        let x = MARGIN_LEFT + tick * (plot_w - 1) / 4;
+        canvas.fill_rect(x, bottom, 1, 4, FOREGROUND);
+        let hz = range.start_hz + (range.stop_hz - range.start_hz) * tick as f64 / 4.0;
+        let label = format_frequency(hz);
+        let w = Canvas::text_width(&label);
+        let x = x.saturating_sub(w / 2).clamp(0, canvas.width - w);
+        canvas.text(x, bottom + 8, &label, FOREGROUND);
+    }
+
+    // colorbar on the right doubles as the dB scale
+    let bar_x = MARGIN_LEFT + plot_w + 8;
+    for y in 0..plot_h {
+        let t = 1.0 - y as f32 / (plot_h - 1).max(1) as f32;
+        canvas.fill_rect(bar_x, MARGIN_TOP + y, 12, 1, colormap(t));
+    }
+    let step = db_step(range.max_db - range.min_db);
+    let mut db = (range.min_db / step).ceil() * step;
+    while db <= range.max_db {
+        let y = MARGIN_TOP + ((1.0 - range.normalize(db)) * (plot_h - 1) as f32).round() as usize;
+        let label = format!("{}", db);
+        canvas.fill_rect(bar_x + 12, y, 4, 1, FOREGROUND);
+        canvas.text(bar_x + 18, y.saturating_sub(5), &label, FOREGROUND);
+        if db_axis {
+            canvas.fill_rect(MARGIN_LEFT, y, plot_w, 1, GRID);
+            let w = Canvas::text_width(&label);
+            canvas.text(MARGIN_LEFT.saturating_sub(w + 6), y.saturating_sub(5), &label, FOREGROUND);
+        }
+        db += step;
+    }
+    canvas.text(bar_x, 4, "dB", FOREGROUND);
+
+    canvas.fill_rect(MARGIN_LEFT - 1, MARGIN_TOP, 1, plot_h + 1, FOREGROUND);
+    canvas.fill_rect(MARGIN_LEFT - 1, bottom, plot_w + 1, 1, FOREGROUND);
+}
+
+
+fn db_step(span: f32) -> f32 {
+    [5.0, 10.0, 20.0, 50.0].into_iter().find(|step| span / step <= 8.0).unwrap_or(100.0)
+}
+
+
+/// Value of the bins a pixel column covers, the max so narrow peaks survive downsampling.
+fn column_value(values: &[f32], x: usize, plot_w: usize) -> f32 {
+    let lo = x * values.len() / plot_w;
+    let hi = ((x + 1) * values.len() / plot_w).max(lo + 1).min(values.len());
+    values[lo..hi].iter().copied().fold(f32::NEG_INFINITY, f32::max)
+}
+
+
+/// Render a DC centered PSD in dB, filled under the trace with the colorbar colors.
+pub fn render_psd(psd_db: &[f32], range: &PlotRange, width: usize, height: usize) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    let plot_w = width - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_h = height - MARGIN_TOP - MARGIN_BOTTOM;
+    draw_frame(&mut canvas, range, true);
+
+    let mut prev_y = None;
+    for x in 0..plot_w {
+        let t = range.normalize(column_value(psd_db, x, plot_w)).clamp(0.0, 1.0);
+        let top = MARGIN_TOP + ((1.0 - t) * (plot_h - 1) as f32).round() as usize;
+        for y in top..MARGIN_TOP + plot_h {
+            let shade = colormap(1.0 - (y - MARGIN_TOP) as f32 / plot_h as f32);
+            canvas.set(MARGIN_LEFT + x, y, shade.map(|v| v / 2));
+        }
+        let (a, b) = match prev_y {
+            Some(prev) if prev < top => (prev, top),
+            Some(prev) => (top, prev),
+            None => (top, top),
+        };
+        canvas.fill_rect(MARGIN_LEFT + x, a, 1, b - a + 1, FOREGROUND);
+        prev_y = Some(top);
+    }
+    canvas
+}
+
+
+/// Render waterfall rows, oldest at the top, one pixel row per spectrum row stretched to fit.
+pub fn render_waterfall(rows_db: &[Vec<f32>], range: &PlotRange, width: usize, height: usize) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    let plot_w = width - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_h = height - MARGIN_TOP - MARGIN_BOTTOM;
+    if !rows_db.is_empty() {
+        for y in 0..plot_h {
+            let row = &rows_db[y * rows_db.len() / plot_h];
+            for x in 0..plot_w {
+                let t = range.normalize(column_value(row, x, plot_w));
+                canvas.set(MARGIN_LEFT + x, MARGIN_TOP + y, colormap(t));
+            }
+        }
+    }
+    draw_frame(&mut canvas, range, false);
+    canvas
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpectrumImage {
+    /// One PSD averaged over everything written.
+    Psd,
+    /// The latest `rows` spectra, each averaged over `frames_per_row` FFT frames.
+    Waterfall { rows: usize, frames_per_row: usize },
+}
+
+
+/// Collects complex baseband and writes a PSD or waterfall PNG on `finish` (or drop).
+pub struct SpectrumPngSink {
+    path: PathBuf,
+    image: SpectrumImage,
+    sample_rate: f64,
+    center_frequency: f64,
+    width: usize,
+    height: usize,
+    range: Option<(f32, f32)>,
+    fft: FFT,
+    window: Vec<f32>,
+    buffer: Vec<Complex32>,
+    segment: Vec<f32>,
+    accumulator: Vec<f32>,
+    frames: usize,
+    rows: VecDeque<Vec<f32>>,
+    written: bool,
+}
+
+
+impl SpectrumPngSink {
+    pub fn new(path: PathBuf, image: SpectrumImage, fft_size: usize, sample_rate: f64, center_frequency: f64) -> Self {
+        Self {
+            path,
+            image,
+            sample_rate,
+            center_frequency,
+            width: 1024,
+            height: 480,
+            range: None,
+            fft: FFT::new(fft_size),
+            window: Window::Hann.coefficients(fft_size),
+            buffer: Vec::new(),
+            segment: Vec::new(),
+            accumulator: vec![0.0; fft_size],
+            frames: 0,
+            rows: VecDeque::new(),
+            written: false,
+        }
+    }
+
+    pub fn set_size(&mut self, width: usize, height: usize) {
+        self.width = width.max(MARGIN_LEFT + MARGIN_RIGHT + 16);
+        self.height = height.max(MARGIN_TOP + MARGIN_BOTTOM + 16);
+    }
+
+    /// Fixed colorbar range instead of picking one from the data.
+    pub fn set_range(&mut self, min_db: f32, max_db: f32) {
+        self.range = Some((min_db, max_db));
+    }
+
+    fn averaged_db(&self) -> Vec<f32> {
+        let frames = self.frames.max(1) as f32;
+        self.accumulator.iter().map(|p| 10.0 * (p / frames).max(1e-20).log10()).collect()
+    }
+
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.written = true;
+        let half = self.sample_rate / 2.0;
+        let (start, stop) = (self.center_frequency - half, self.center_frequency + half);
+
+        let canvas = match self.image {
+            SpectrumImage::Psd => {
+                let psd = self.averaged_db();
+                let range = self.plot_range(start, stop, &psd);
+                render_psd(&psd, &range, self.width, self.height)
+            },
+            SpectrumImage::Waterfall { .. } => {
+                let rows: Vec<Vec<f32>> = self.rows.iter().cloned().collect();
+                let all: Vec<f32> = rows.iter().flatten().copied().collect();
+                let range = self.plot_range(start, stop, &all);
+                render_waterfall(&rows, &range, self.width, self.height)
+            },
+        };
+        canvas.save_png(&self.path)
+    }
+
+    fn plot_range(&self, start: f64, stop: f64, values: &[f32]) -> PlotRange {
+        match self.range {
+            Some((min_db, max_db)) => PlotRange { start_hz: start, stop_hz: stop, min_db, max_db },
+            None => PlotRange::auto(start, stop, values),
+        }
+    }
+}
+
+
+impl Sink<Complex32> for SpectrumPngSink {
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        let size = self.fft.size();
+        self.buffer.extend_from_slice(src);
+        let mut off = 0;
+        while self.buffer.len() - off >= size {
+            power_spectrum(&self.fft, &self.window, &self.buffer[off..off + size], &mut self.segment);
+            off += size;
+            for (acc, p) in self.accumulator.iter_mut().zip(self.segment.iter()) {
+                *acc += p;
+            }
+            self.frames += 1;
+
+            if let SpectrumImage::Waterfall { rows, frames_per_row } = self.image
+                && self.frames >= frames_per_row {
+                let row = self.averaged_db();
+                self.rows.push_back(row);
+                while self.rows.len() > rows {
+                    self.rows.pop_front();
+                }
+                self.accumulator.fill(0.0);
+                self.frames = 0;
+            }
+        }
+        self.buffer.drain(..off);
+        Ok(())
+    }
+}
+
+
+impl Drop for SpectrumPngSink {
+    fn drop(&mut self) {
+        if !self.written {
+            let _ = self.finish();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::fs::File;
+    use num_complex::Complex32;
+    use crate::plot::{format_frequency, SpectrumImage, SpectrumPngSink};
+    use crate::traits::Sink;
+
+    #[test]
+    fn test_spectrum_png() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(format_frequency(100.125e6), "100.125MHz");
+        assert_eq!(format_frequency(-12.5e3), "-12.5kHz");
+
+        let tone: Vec<Complex32> = (0..65536)
+            .map(|n| Complex32::from_polar(1.0, 2.0 * PI * 0.125 * n as f32) + Complex32::new(0.001, 0.0))
+            .collect();
+        for (name, image) in [("psd", SpectrumImage::Psd), ("waterfall", SpectrumImage::Waterfall { rows: 32, frames_per_row: 2 })] {
+            let path = std::env::temp_dir().join(format!("rust_dsp_{}_{}.png", name, std::process::id()));
+            let mut sink = SpectrumPngSink::new(path.clone(), image, 1024, 2e6, 100e6);
+            sink.set_size(640, 320);
+            sink.write(&tone)?;
+            sink.finish()?;
+
+            let decoder = png::Decoder::new(std::io::BufReader::new(File::open(&path)?));
+            let reader = decoder.read_info()?;
+            assert_eq!((reader.info().width, reader.info().height), (640, 320));
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+}
@@ -0,0 +1,195 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use num_complex::{Complex32, Complex64};
+use crate::block::FMDemod;
+use crate::fft::{power_spectrum, Window, FFT};
+use crate::gsm::{median_offset, FcchBurst, FcchDetector};
+use crate::settings::Settings;
+use crate::traits::Filter;
+
+
+/// GSM frequency correction bursts are a tone 1625/24 kHz above the carrier.
+pub const FCCH_OFFSET_HZ: f64 = 1625e3 / 24.0;
+pub const FM_PILOT_HZ: f64 = 19e3;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrequencyReference {
+    /// Stereo pilot of an FM broadcast station tuned to the center. The pilot is measured against the
+    /// sample clock, which shares the crystal with the LO on the HackRF.
+    FmPilot,
+    /// Frequency correction channel of a GSM base station on `carrier_hz`.
+    GsmFcch { carrier_hz: f64 },
+    /// An unmodulated carrier known to be on `frequency_hz`, e.g. a beacon or a signal generator.
+    Carrier { frequency_hz: f64 },
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PpmEstimate {
+    /// Crystal error, positive when the device runs fast.
+    pub ppm: f64,
+    /// Where the reference tone was found relative to where it should be.
+    pub offset_hz: f64,
+    /// Fraction of the capture the reference tone was present in.
+    pub coverage: f32,
+}
+
+
+/// Tone frequency near `expected_hz` within `search_hz`, coarse from an averaged spectrum and refined
+/// from the phase slope over 1 ms blocks. Only blocks where the tone is strong count, so bursts work too.
+pub fn tone_frequency(samples: &[Complex32], sample_rate: u32, expected_hz: f64, search_hz: f64) -> Option<(f64, f32)> {
+    let fs = sample_rate as f64;
+    let size = 8192.min(samples.len().next_power_of_two() / 2).max(64);
+    let fft = FFT::new(size);
+    let window = Window::Hann.coefficients(size);
+    let mut average = vec![0f32; size];
+    let mut spectrum = Vec::new();
+    for segment in samples.chunks_exact(size) {
+        power_spectrum(&fft, &window, segment, &mut spectrum);
+        for (a, p) in average.iter_mut().zip(&spectrum) {
+            *a += p;
+        }
+    }
+
+    let bin_hz = fs / size as f64;
+    let bin = |f: f64| ((f / bin_hz).round() as isize + size as isize / 2).clamp(1, size as isize - 2) as usize;
+    let peak = (bin(expected_hz - search_hz)..=bin(expected_hz + search_hz)).max_by(|&a, &b| average[a].total_cmp(&average[b]))?;
+    let (l, c, r) = (average[peak - 1] as f64, average[peak] as f64, average[peak + 1] as f64);
+    let delta = if l - 2.0 * c + r != 0.0 { 0.5 * (l - r) / (l - 2.0 * c + r) } else { 0.0 };
+    let coarse = (peak as f64 - size as f64 / 2.0 + delta) * bin_hz;
+
+    // mix the coarse estimate down to DC and average 1 ms blocks, their phase advances with the residual
+    let block = (sample_rate as usize / 1000).max(1);
+    let step = -2.0 * PI * coarse / fs;
+    let blocks: Vec<Complex64> = samples.chunks_exact(block).enumerate().map(|(k, chunk)| {
+        chunk.iter().enumerate().map(|(n, s)| {
+            let phase = step * (k * block + n) as f64;
+            Complex64::new(s.re as f64, s.im as f64) * Complex64::from_polar(1.0, phase)
+        }).sum::<Complex64>() / block as f64
+    }).collect();
+    let strongest = blocks.iter().map(|z| z.norm()).fold(0.0, f64::max);
+    if strongest == 0.0 {
+        return None;
+    }
+    let strong: Vec<bool> = blocks.iter().map(|z| z.norm() >= 0.5 * strongest).collect();
+    let mut rotation = Complex64::default();
+    for k in 1..blocks.len() {
+        if strong[k - 1] && strong[k] {
+            rotation += blocks[k] * blocks[k - 1].conj();
+        }
+    }
+    let residual = rotation.arg() / (2.0 * PI * block as f64 / fs);
+    let coverage = strong.iter().filter(|&&s| s).count() as f32 / blocks.len() as f32;
+    Some((coarse + residual, coverage))
+}
+
+
+/// Estimate the frequency error of a receiver tuned to `tuned_hz` from a capture containing `reference`.
+pub fn estimate_ppm(samples: &[Complex32], sample_rate: u32, tuned_hz: f64, reference: FrequencyReference) -> Result<PpmEstimate, Box<dyn Error>> {
+    let not_found = || -> Box<dyn Error> { "reference signal not found in the capture".into() };
+    match reference {
+        FrequencyReference::FmPilot => {
+            let mut audio = Vec::new();
+            FMDemod::new(sample_rate, 75e3).filter(samples, &mut audio)?;
+            let audio: Vec<Complex32> = audio.iter().map(|&a| Complex32::new(a, 0.0)).collect();
+            let (measured, coverage) = tone_frequency(&audio, sample_rate, FM_PILOT_HZ, 50.0).ok_or_else(not_found)?;
+            // a fast sample clock makes the pilot look low
+            Ok(PpmEstimate { ppm: (FM_PILOT_HZ / measured - 1.0) * 1e6, offset_hz: measured - FM_PILOT_HZ, coverage })
+        },
+        FrequencyReference::GsmFcch { carrier_hz } => fcch_error(samples, sample_rate, tuned_hz, carrier_hz).ok_or_else(not_found),
+        FrequencyReference::Carrier { frequency_hz } => carrier_error(samples, sample_rate, tuned_hz, frequency_hz).ok_or_else(not_found),
+    }
+}
+
+
+fn carrier_error(samples: &[Complex32], sample_rate: u32, tuned_hz: f64, frequency_hz: f64) -> Option<PpmEstimate> {
+    let expected = frequency_hz - tuned_hz;
+    // up to 50 ppm of the tuned frequency
+    let search = (tuned_hz * 50e-6).max(1e3);
+    let (measured, coverage) = tone_frequency(samples, sample_rate, expected, search)?;
+    // a fast LO lands above the intended frequency and shifts signals down
+    let offset_hz = measured - expected;
+    Some(PpmEstimate { ppm: -offset_hz / tuned_hz * 1e6, offset_hz, coverage })
+}
+
+
+/// Measured on the frequency correction bursts only, so a carrier that happens to sit on the tone
+/// frequency doesn't count.
+fn fcch_error(samples: &[Complex32], sample_rate: u32, tuned_hz: f64, carrier_hz: f64) -> Option<PpmEstimate> {
+    let detector = FcchDetector::new(sample_rate).ok()?;
+    let bursts = detector.detect(samples, carrier_hz - tuned_hz);
+    fcch_estimate(&bursts, detector.burst_len(), samples.len() as u64, tuned_hz)
+}
+
+
+/// The error from bursts already found in `len` samples from a receiver tuned to `tuned_hz`, e.g. by
+/// `FcchDetector::scan_windows` over a capture too large to estimate from in one piece.
+pub fn fcch_estimate(bursts: &[FcchBurst], burst_len: usize, len: u64, tuned_hz: f64) -> Option<PpmEstimate> {
+    let offset_hz = median_offset(bursts)?;
+    let coverage = (bursts.len() * burst_len) as f32 / len as f32;
+    Some(PpmEstimate { ppm: -offset_hz / tuned_hz * 1e6, offset_hz, coverage })
+}
+
+
+/// Frequency to ask for so a device off by `ppm` actually tunes to `frequency`.
+pub fn corrected_frequency(frequency: u64, ppm: f64) -> u64 {
+    (frequency as f64 / (1.0 + ppm * 1e-6)).round() as u64
+}
+
+
+/// The correction `calibrate` stored for `device` in the settings file, zero when none was
+/// measured yet.
+pub fn stored_ppm(device: &str) -> f64 {
+    Settings::load_default().ok().and_then(|settings| settings.device(device).ppm).unwrap_or(0.0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use num_complex::Complex32;
+    use crate::ppm::{corrected_frequency, estimate_ppm, FrequencyReference, FCCH_OFFSET_HZ};
+
+    fn tone(rate: f64, frequency: f64, len: usize, on: impl Fn(usize) -> bool) -> Vec<Complex32> {
+        (0..len).map(|n| {
+            let phase = 2.0 * PI * frequency * n as f64 / rate;
+            let amplitude = if on(n) { 0.5 } else { 0.0 };
+            Complex32::new((amplitude * phase.cos()) as f32, (amplitude * phase.sin()) as f32)
+        }).collect()
+    }
+
+    #[test]
+    fn test_estimate_ppm() -> Result<(), Box<dyn std::error::Error>> {
+        let rate = 1e6;
+        // device 10 ppm fast at 100 MHz puts a carrier 30 kHz up 1 kHz low
+        let capture = tone(rate, 29e3, 200_000, |_| true);
+        let estimate = estimate_ppm(&capture, rate as u32, 100e6, FrequencyReference::Carrier { frequency_hz: 100.03e6 })?;
+        assert!((estimate.ppm - 10.0).abs() < 0.01, "{:?}", estimate);
+
+        // fcch bursts only fill part of the capture
+        let tuned = 935e6;
+        let carrier = 935.2e6;
+        let offset = -(tuned * -3e-6);
+        let capture = tone(rate, carrier + FCCH_OFFSET_HZ - tuned + offset, 400_000, |n| n % 50_000 < 600);
+        let estimate = estimate_ppm(&capture, rate as u32, tuned, FrequencyReference::GsmFcch { carrier_hz: carrier })?;
+        assert!((estimate.ppm + 3.0).abs() < 0.05, "{:?}", estimate);
+        assert!(estimate.coverage < 0.05);
+
+        // a 20 ppm fast sample clock hears the pilot a little low
+        let rate = 240e3;
+        let pilot = 19e3 / (1.0 + 20e-6);
+        let mut phase = 0.0;
+        let capture: Vec<Complex32> = (0..480_000).map(|n| {
+            phase += 2.0 * PI * 7.5e3 * (2.0 * PI * pilot * n as f64 / rate).sin() / rate;
+            Complex32::new(phase.cos() as f32, phase.sin() as f32)
+        }).collect();
+        let estimate = estimate_ppm(&capture, rate as u32, 98.1e6, FrequencyReference::FmPilot)?;
+        assert!((estimate.ppm - 20.0).abs() < 1.0, "{:?}", estimate);
+
+        assert_eq!(corrected_frequency(100_000_000, 10.0), 99_999_000);
+
+        Ok(())
+    }
+
+}
@@ -0,0 +1,157 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use crate::rate::RateAware;
+use crate::traits::*;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProbeLevels<T> {
+    /// Most recent sample seen.
+    pub last: T,
+    /// RMS magnitude over the last complete window.
+    pub rms: f32,
+    /// Peak magnitude over the last complete window.
+    pub peak: f32,
+    pub samples: u64,
+}
+
+
+impl<T> ProbeLevels<T> {
+    pub fn rms_db(&self) -> f32 {
+        20.0 * self.rms.max(1e-12).log10()
+    }
+
+    pub fn peak_db(&self) -> f32 {
+        20.0 * self.peak.max(1e-12).log10()
+    }
+}
+
+
+/// Measures the stream passing through it so UIs and control loops on other threads can poll the
+/// levels through a `ProbeReader`.
+pub struct SignalProbe<T> {
+    window: usize,
+    count: usize,
+    sum: f64,
+    peak: f32,
+    samples: u64,
+    shared: Arc<Mutex<ProbeLevels<T>>>,
+}
+
+
+/// Cheap to clone handle onto the levels published by a `SignalProbe`.
+#[derive(Clone)]
+pub struct ProbeReader<T> {
+    shared: Arc<Mutex<ProbeLevels<T>>>,
+}
+
+
+impl<T: Magnitude + Default> SignalProbe<T> {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            count: 0,
+            sum: 0.0,
+            peak: 0.0,
+            samples: 0,
+            shared: Arc::new(Mutex::new(ProbeLevels::default())),
+        }
+    }
+
+    pub fn reader(&self) -> ProbeReader<T> {
+        ProbeReader {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    fn measure(&mut self, input: &[T]) {
+        let Some(&last) = input.last() else {
+            return;
+        };
+        let mut published = None;
+        for &x in input {
+            let power = x.magnitude_sqr();
+            self.sum += power as f64;
+            self.peak = self.peak.max(power);
+            self.count += 1;
+            if self.count == self.window {
+                published = Some(((self.sum / self.window as f64).sqrt() as f32, self.peak.sqrt()));
+                self.count = 0;
+                self.sum = 0.0;
+                self.peak = 0.0;
+            }
+        }
+        self.samples += input.len() as u64;
+
+        let mut levels = self.shared.lock().unwrap();
+        levels.last = last;
+        levels.samples = self.samples;
+        if let Some((rms, peak)) = published {
+            levels.rms = rms;
+            levels.peak = peak;
+        }
+    }
+}
+
+
+impl<T: Copy> ProbeReader<T> {
+    pub fn levels(&self) -> ProbeLevels<T> {
+        *self.shared.lock().unwrap()
+    }
+}
+
+
+impl<T: Magnitude + Default> Filter<T, T> for SignalProbe<T> {
+    fn filter(&mut self, input: &[T], output: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.measure(input);
+        output.extend_from_slice(input);
+        Ok(())
+    }
+}
+
+
+impl<T: Magnitude + Default> Sink<T> for SignalProbe<T> {
+    fn write(&mut self, src: &[T]) -> Result<(), Box<dyn Error>> {
+        self.measure(src);
+        Ok(())
+    }
+}
+
+
+impl<T> RateAware for SignalProbe<T> {}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex32;
+    use crate::probe::SignalProbe;
+    use crate::traits::{Filter, Sink};
+
+    #[test]
+    fn test_signal_probe() -> Result<(), Box<dyn std::error::Error>> {
+        let mut probe = SignalProbe::<Complex32>::new(100);
+        let reader = probe.reader();
+
+        let tone: Vec<Complex32> = (0..150).map(|n| Complex32::from_polar(0.5, n as f32 * 0.3)).collect();
+        let mut output = Vec::new();
+        probe.filter(&tone, &mut output)?;
+        assert_eq!(output, tone);
+
+        let levels = std::thread::spawn(move || reader.levels()).join().unwrap();
+        assert_eq!(levels.last, tone[149]);
+        assert_eq!(levels.samples, 150);
+        assert!((levels.rms - 0.5).abs() < 1e-4);
+        assert!((levels.peak - 0.5).abs() < 1e-4);
+        assert!((levels.rms_db() + 6.02).abs() < 0.01);
+
+        // a partial window doesn't change the published levels
+        let reader = probe.reader();
+        probe.write(&[Complex32::new(2.0, 0.0); 10])?;
+        assert!((reader.levels().peak - 0.5).abs() < 1e-4);
+        probe.write(&[Complex32::new(2.0, 0.0); 40])?;
+        assert!((reader.levels().peak - 2.0).abs() < 1e-4);
+        Ok(())
+    }
+
+}
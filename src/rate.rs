@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SampleRate(pub u32);
+
+
+impl SampleRate {
+    pub fn hz(&self) -> u32 {
+        self.0
+    }
+}
+
+
+impl Display for SampleRate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} Hz", self.0)
+    }
+}
+
+
+impl From<u32> for SampleRate {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+
+/// Blocks that know the sample rate of their ports. The defaults describe a block that works at any rate
+/// and doesn't change it, so rate agnostic blocks only need an empty impl.
+pub trait RateAware {
+    /// Rate the block was built for on its input, `None` for sources and rate agnostic blocks.
+    fn input_rate(&self) -> Option<SampleRate> {
+        None
+    }
+
+    /// Rate the block produces given the rate flowing into it.
+    fn output_rate(&self, input: Option<SampleRate>) -> Option<SampleRate> {
+        input
+    }
+
+    /// Samples a signal spends inside the block, its group delay plus anything queued, counted at the
+    /// rate flowing in or at the output rate of a source.
+    fn delay(&self) -> f64 {
+        0.0
+    }
+}
+
+
+impl<B: RateAware + ?Sized> RateAware for &mut B {
+    fn input_rate(&self) -> Option<SampleRate> {
+        (**self).input_rate()
+    }
+
+    fn output_rate(&self, input: Option<SampleRate>) -> Option<SampleRate> {
+        (**self).output_rate(input)
+    }
+
+    fn delay(&self) -> f64 {
+        (**self).delay()
+    }
+}
+
+
+impl<B: RateAware + ?Sized> RateAware for Box<B> {
+    fn input_rate(&self) -> Option<SampleRate> {
+        (**self).input_rate()
+    }
+
+    fn output_rate(&self, input: Option<SampleRate>) -> Option<SampleRate> {
+        (**self).output_rate(input)
+    }
+
+    fn delay(&self) -> f64 {
+        (**self).delay()
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateMismatch {
+    /// Position in the chain of the block whose input doesn't match.
+    pub index: usize,
+    pub upstream: SampleRate,
+    pub expected: SampleRate,
+}
+
+
+impl Display for RateMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "block {} expects {} but is fed {}", self.index, self.expected, self.upstream)
+    }
+}
+
+
+impl Error for RateMismatch {}
+
+
+/// Walk a chain of blocks from source to sink and check every connection, returning the final rate.
+pub fn check_chain(blocks: &[&dyn RateAware]) -> Result<Option<SampleRate>, RateMismatch> {
+    let mut rate = None;
+    for (index, block) in blocks.iter().enumerate() {
+        if let (Some(upstream), Some(expected)) = (rate, block.input_rate())
+            && upstream != expected {
+            return Err(RateMismatch { index, upstream, expected });
+        }
+        rate = block.output_rate(rate.or(block.input_rate()));
+    }
+    Ok(rate)
+}
+
+
+/// Estimated end-to-end latency of a chain, block by block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyReport {
+    pub blocks: Vec<Duration>,
+    pub total: Duration,
+}
+
+
+impl Display for LatencyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1} ms (", self.total.as_secs_f64() * 1e3)?;
+        for (index, block) in self.blocks.iter().enumerate() {
+            let sep = if index == 0 { "" } else { " + " };
+            write!(f, "{}{:.1}", sep, block.as_secs_f64() * 1e3)?;
+        }
+        write!(f, ")")
+    }
+}
+
+
+impl LatencyReport {
+    pub fn new(blocks: Vec<Duration>) -> Self {
+        let total = blocks.iter().sum();
+        Self { blocks, total }
+    }
+}
+
+
+/// A block's delay in time, given the rate coming into it. Zero when the rate is unknown.
+pub fn block_latency(block: &dyn RateAware, input: Option<SampleRate>) -> Duration {
+    let input = input.or(block.input_rate());
+    match input.or(block.output_rate(input)) {
+        Some(SampleRate(hz)) if hz > 0 => Duration::from_secs_f64(block.delay().max(0.0) / hz as f64),
+        _ => Duration::ZERO,
+    }
+}
+
+
+/// Walk a chain like `check_chain` and add up every block's delay. Buffer fills change while the
+/// chain runs, so this is a snapshot, `ThreadedGraph::latency` follows a running chain. Blocks whose
+/// rate is unknown count as zero.
+pub fn chain_latency(blocks: &[&dyn RateAware]) -> LatencyReport {
+    let mut rate = None;
+    let mut latencies = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let input = rate.or(block.input_rate());
+        latencies.push(block_latency(*block, input));
+        rate = block.output_rate(input);
+    }
+    LatencyReport::new(latencies)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::block::{DeEmphasisFilter, FIRFilter, FMDemod, MixerFilter, RationalResampler, VirtualAudioCable};
+    use crate::rate::{chain_latency, check_chain, SampleRate};
+    use crate::traits::Sink;
+
+    #[test]
+    fn test_check_chain() {
+        let mix = MixerFilter::new(2_000_000, -150e3);
+        let resample0 = RationalResampler::<f32>::new(2_000_000, 150_000, 31);
+        let fir = FIRFilter::new(vec![1.0f32]);
+        let demod = FMDemod::new(150_000, 75e3);
+        let resample1 = RationalResampler::<f32>::new(150_000, 44_100, 31);
+        let deemph = DeEmphasisFilter::new(44_100, 75e-6);
+        assert_eq!(check_chain(&[&mix, &resample0, &fir, &demod, &resample1, &deemph]), Ok(Some(SampleRate(44_100))));
+
+        let error = check_chain(&[&mix, &resample0, &fir, &demod, &deemph]).unwrap_err();
+        assert_eq!(error.index, 4);
+        assert_eq!(error.upstream, SampleRate(150_000));
+        assert_eq!(error.expected, SampleRate(44_100));
+    }
+
+
+    #[test]
+    fn test_chain_latency() -> Result<(), Box<dyn std::error::Error>> {
+        let resample = RationalResampler::<f32>::new(48_000, 24_000, 101);
+        let fir = FIRFilter::new(vec![0.05f32; 21]);
+        let VirtualAudioCable { source, mut sink } = VirtualAudioCable::with_buffer(24_000, Duration::from_millis(20))?;
+        sink.write(&[0.0; 240])?;
+
+        // 50 samples at 48 kHz, 10 at 24 kHz and 240 queued at 24 kHz
+        let report = chain_latency(&[&resample, &fir, &sink]);
+        let ms: Vec<f64> = report.blocks.iter().map(|d| d.as_secs_f64() * 1e3).collect();
+        assert!((ms[0] - 50.0 / 48.0).abs() < 1e-6, "{:?}", ms);
+        assert!((ms[1] - 10.0 / 24.0).abs() < 1e-6, "{:?}", ms);
+        assert!((ms[2] - 10.0).abs() < 1e-6, "{:?}", ms);
+        assert_eq!(report.total, report.blocks.iter().sum());
+        assert_eq!(report.to_string(), "11.5 ms (1.0 + 0.4 + 10.0)");
+        drop(source);
+        Ok(())
+    }
+}
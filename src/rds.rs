@@ -0,0 +1,649 @@
+use std::error::Error;
+use num_complex::Complex32;
+use serde::Serialize;
+use crate::block::{FIRFilter, MixerFilter, RationalResampler, RationalResamplerBuilder};
+use crate::error::ConfigError;
+use crate::rate::{RateAware, SampleRate};
+use crate::traits::*;
+use crate::util::lowpass_taps;
+
+
+/// Open data application id RDS-TMC announces in group 3A, the second one is ALERT-C with
+/// the location table in the same group.
+const TMC_AIDS: [u16; 2] = [0xcd46, 0xcd47];
+
+
+/// RDS data rate, the 57 kHz subcarrier divided by 48.
+pub const RDS_BIT_RATE: f64 = 1187.5;
+/// Rate the subcarrier is demodulated at, 16 samples per bit.
+const RDS_RATE: u32 = 19_000;
+const SAMPLES_PER_BIT: usize = 16;
+/// Added to the checkwords of blocks A, B, C, D and C', marking each block's place in the group.
+const OFFSETS: [u16; 5] = [0x0fc, 0x198, 0x168, 0x1b4, 0x350];
+/// Damaged blocks in a row after which block sync counts as lost.
+const MAX_BAD_BLOCKS: u32 = 12;
+
+
+/// One RDS group as four error checked 16-bit blocks, without the checkwords.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RdsGroup {
+    pub blocks: [u16; 4],
+}
+
+
+impl RdsGroup {
+    pub fn new(blocks: [u16; 4]) -> Self {
+        Self { blocks }
+    }
+
+    /// Program identification of the station.
+    pub fn pi(&self) -> u16 {
+        self.blocks[0]
+    }
+
+    /// Group type 0 to 15, the number in "8A".
+    pub fn group_type(&self) -> u8 {
+        (self.blocks[1] >> 12) as u8
+    }
+
+    /// Version B groups repeat the PI in block 3.
+    pub fn version_b(&self) -> bool {
+        self.blocks[1] & 0x0800 != 0
+    }
+
+    /// The 5 bits at the end of block 2 each group type uses for itself.
+    fn low_bits(&self) -> u16 {
+        self.blocks[1] & 0x1f
+    }
+}
+
+
+/// An ALERT-C traffic message, location and event codes refer to the tables of the broadcaster's
+/// country, see `TmcDecoder::location_table`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TmcMessage {
+    pub pi: u16,
+    pub event: u16,
+    pub location: u16,
+    /// How many locations past `location` the event reaches.
+    pub extent: u8,
+    /// The event affects the negative direction of the road.
+    pub negative: bool,
+    /// Drivers are advised to take a diversion, single group messages only.
+    pub diversion: bool,
+    /// Duration and persistence code, single group messages only.
+    pub duration: u8,
+    /// 28-bit chunks of optional content from the later groups of a multi group message.
+    pub free_format: Vec<u32>,
+}
+
+
+impl std::fmt::Display for TmcMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "event {} at location {}", self.event, self.location)?;
+        if self.extent > 0 {
+            write!(f, " +{}", self.extent)?;
+        }
+        if self.negative {
+            write!(f, " negative")?;
+        }
+        if self.diversion {
+            write!(f, " diversion")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Multi group message waiting for its remaining groups.
+struct Pending {
+    continuity: u8,
+    message: TmcMessage,
+    next: Option<u8>,
+}
+
+
+/// Pulls RDS-TMC traffic messages out of decoded RDS groups. Messages are sent in group 8A unless
+/// a 3A group announces another group type. Broadcasters repeat every message, repeats of the last
+/// message are dropped.
+pub struct TmcDecoder {
+    group_type: (u8, bool),
+    location_table: Option<u8>,
+    pending: Option<Pending>,
+    last: Option<TmcMessage>,
+}
+
+
+impl Default for TmcDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl TmcDecoder {
+    pub fn new() -> Self {
+        Self {
+            group_type: (8, false),
+            location_table: None,
+            pending: None,
+            last: None,
+        }
+    }
+
+    /// Location table number from the 3A announcement, once one was received.
+    pub fn location_table(&self) -> Option<u8> {
+        self.location_table
+    }
+
+    /// Returns a message once all of its groups arrived.
+    pub fn push(&mut self, group: &RdsGroup) -> Option<TmcMessage> {
+        if group.group_type() == 3 && !group.version_b() {
+            self.announcement(group);
+            return None;
+        }
+        if (group.group_type(), group.version_b()) != self.group_type {
+            return None;
+        }
+
+        let bits = group.low_bits();
+        // tuning information about other networks
+        if bits & 0x10 != 0 {
+            return None;
+        }
+        let [_, _, block3, block4] = group.blocks;
+        let first = TmcMessage {
+            pi: group.pi(),
+            event: block3 & 0x07ff,
+            location: block4,
+            extent: ((block3 >> 11) & 0x07) as u8,
+            negative: block3 & 0x4000 != 0,
+            ..TmcMessage::default()
+        };
+        let message = if bits & 0x08 != 0 {
+            Some(TmcMessage {
+                diversion: block3 & 0x8000 != 0,
+                duration: (bits & 0x07) as u8,
+                ..first
+            })
+        } else {
+            self.multi_group((bits & 0x07) as u8, block3, block4, first)
+        }?;
+
+        if self.last.as_ref() == Some(&message) {
+            return None;
+        }
+        self.last = Some(message.clone());
+        Some(message)
+    }
+
+    fn announcement(&mut self, group: &RdsGroup) {
+        let [_, block2, block3, block4] = group.blocks;
+        if !TMC_AIDS.contains(&block4) {
+            return;
+        }
+        let application = (block2 & 0x1f) as u8;
+        // 0 means the ODA isn't carried in any group, only announced
+        if application != 0 {
+            self.group_type = (application >> 1, application & 1 != 0);
+        }
+        if block3 >> 14 == 0 {
+            self.location_table = Some(((block3 >> 6) & 0x3f) as u8);
+        }
+    }
+
+    fn multi_group(&mut self, continuity: u8, block3: u16, block4: u16, first: TmcMessage) -> Option<TmcMessage> {
+        if block3 & 0x8000 != 0 {
+            self.pending = Some(Pending { continuity, message: first, next: None });
+            return None;
+        }
+
+        let pending = self.pending.as_mut().filter(|pending| pending.continuity == continuity)?;
+        let second = block3 & 0x4000 != 0;
+        let remaining = ((block3 >> 12) & 0x03) as u8;
+        // the second group says how many follow, each later one counts down by one
+        let expected = match pending.next {
+            None if second => true,
+            Some(next) => !second && remaining == next,
+            None => false,
+        };
+        if !expected {
+            self.pending = None;
+            return None;
+        }
+        pending.message.free_format.push(((block3 as u32 & 0x0fff) << 16) | block4 as u32);
+        if remaining == 0 {
+            return self.pending.take().map(|pending| pending.message);
+        }
+        pending.next = Some(remaining - 1);
+        None
+    }
+}
+
+
+/// What an `RdsDecoder` makes of the RDS data.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum RdsMessage {
+    /// Every group received intact, for decoding other applications, e.g. with `TmcDecoder`.
+    Group(RdsGroup),
+    /// The 8 character program service name, whenever it's been received whole and changed.
+    StationName { pi: u16, name: String },
+    /// Radiotext up to its end or 64 characters, whenever it's been received whole and changed.
+    RadioText { pi: u16, text: String },
+}
+
+
+/// The 10-bit checkword of `data`, generator x^10 + x^8 + x^7 + x^5 + x^4 + x^3 + 1.
+fn checkword(data: u16) -> u16 {
+    let mut register = (data as u32) << 10;
+    for bit in (10..26).rev() {
+        if register & (1 << bit) != 0 {
+            register ^= 0x5b9 << (bit - 10);
+        }
+    }
+    register as u16
+}
+
+
+/// Position in the group of the 26-bit `block`, C' counting as C, if its checkword is right.
+fn block_position(block: u32) -> Option<usize> {
+    let syndrome = (block as u16 & 0x3ff) ^ checkword((block >> 10) as u16);
+    OFFSETS.iter().position(|&offset| offset == syndrome).map(|i| if i == 4 { 2 } else { i })
+}
+
+
+/// Finds the block boundaries in the bit stream and assembles the groups. Sync needs two intact
+/// blocks the right distance apart, a random 26 bits pass the check too often to trust one.
+struct BlockSync {
+    register: u32,
+    bits: u64,
+    candidate: Option<(usize, u64)>,
+    synced: bool,
+    since_block: usize,
+    expected: usize,
+    bad_blocks: u32,
+    blocks: [Option<u16>; 4],
+}
+
+
+impl BlockSync {
+    fn new() -> Self {
+        Self {
+            register: 0,
+            bits: 0,
+            candidate: None,
+            synced: false,
+            since_block: 0,
+            expected: 0,
+            bad_blocks: 0,
+            blocks: [None; 4],
+        }
+    }
+
+    fn push(&mut self, bit: bool) -> Option<RdsGroup> {
+        self.register = ((self.register << 1) | bit as u32) & 0x03ff_ffff;
+        self.bits += 1;
+        if !self.synced {
+            let position = block_position(self.register)?;
+            if let Some((previous, at)) = self.candidate.replace((position, self.bits)) {
+                let distance = self.bits - at;
+                if distance.is_multiple_of(26) && (previous + (distance / 26) as usize) % 4 == position {
+                    self.synced = true;
+                    self.since_block = 0;
+                    self.bad_blocks = 0;
+                    self.blocks = [None; 4];
+                    self.blocks[position] = Some((self.register >> 10) as u16);
+                    self.expected = (position + 1) % 4;
+                }
+            }
+            return None;
+        }
+
+        self.since_block += 1;
+        if self.since_block < 26 {
+            return None;
+        }
+        self.since_block = 0;
+        let position = self.expected;
+        self.expected = (position + 1) % 4;
+        if block_position(self.register) == Some(position) {
+            self.blocks[position] = Some((self.register >> 10) as u16);
+            self.bad_blocks = 0;
+        } else {
+            self.blocks[position] = None;
+            self.bad_blocks += 1;
+            if self.bad_blocks > MAX_BAD_BLOCKS {
+                self.synced = false;
+                self.candidate = None;
+            }
+        }
+        if position < 3 {
+            return None;
+        }
+        let blocks = std::mem::take(&mut self.blocks);
+        match blocks {
+            [Some(a), Some(b), Some(c), Some(d)] => Some(RdsGroup::new([a, b, c, d])),
+            _ => None,
+        }
+    }
+}
+
+
+/// RDS characters, the printable ASCII range maps straight through and the rest of the EBU
+/// table shows as '?'.
+fn rds_char(byte: u8) -> char {
+    if (0x20..0x7f).contains(&byte) { byte as char } else { '?' }
+}
+
+
+/// Assembles the program service name from 0A/0B groups and radiotext from 2A/2B groups.
+pub struct RdsText {
+    name: [u8; 8],
+    name_segments: u8,
+    last_name: Option<String>,
+    text: [u8; 64],
+    text_segments: u16,
+    text_flag: Option<bool>,
+    last_text: Option<String>,
+}
+
+
+impl Default for RdsText {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl RdsText {
+    pub fn new() -> Self {
+        Self {
+            name: [b' '; 8],
+            name_segments: 0,
+            last_name: None,
+            text: [b' '; 64],
+            text_segments: 0,
+            text_flag: None,
+            last_text: None,
+        }
+    }
+
+    pub fn push(&mut self, group: &RdsGroup) -> Option<RdsMessage> {
+        let [_, block2, block3, block4] = group.blocks;
+        match group.group_type() {
+            0 => {
+                let segment = (block2 & 0x03) as usize;
+                self.name[2 * segment..2 * segment + 2].copy_from_slice(&block4.to_be_bytes());
+                self.name_segments |= 1 << segment;
+                if self.name_segments != 0x0f {
+                    return None;
+                }
+                self.name_segments = 0;
+                let name: String = self.name.iter().map(|&byte| rds_char(byte)).collect();
+                if self.last_name.as_ref() == Some(&name) {
+                    return None;
+                }
+                self.last_name = Some(name.clone());
+                Some(RdsMessage::StationName { pi: group.pi(), name })
+            },
+            2 => {
+                // the A/B flag toggling means a new text
+                let flag = block2 & 0x10 != 0;
+                if self.text_flag.replace(flag) != Some(flag) {
+                    self.text = [b' '; 64];
+                    self.text_segments = 0;
+                }
+                let segment = (block2 & 0x0f) as usize;
+                let (chars, width) = if group.version_b() {
+                    (block4.to_be_bytes().to_vec(), 2)
+                } else {
+                    ([block3.to_be_bytes(), block4.to_be_bytes()].concat(), 4)
+                };
+                self.text[width * segment..width * (segment + 1)].copy_from_slice(&chars);
+                self.text_segments |= 1 << segment;
+
+                let end = self.text[..16 * width].iter().position(|&byte| byte == b'\r').unwrap_or(16 * width);
+                let needed = (1u32 << end.div_ceil(width).max(1)) - 1;
+                if self.text_segments as u32 & needed != needed {
+                    return None;
+                }
+                self.text_segments = 0;
+                let text: String = self.text[..end].iter().map(|&byte| rds_char(byte)).collect::<String>().trim_end().to_string();
+                if self.last_text.as_ref() == Some(&text) {
+                    return None;
+                }
+                self.last_text = Some(text.clone());
+                Some(RdsMessage::RadioText { pi: group.pi(), text })
+            },
+            _ => None,
+        }
+    }
+}
+
+
+/// Decodes RDS from demodulated FM MPX: mixes the 57 kHz subcarrier to baseband at 19 kHz,
+/// recovers its phase from the squared signal and the bit clock from where the biphase symbols
+/// are strongest, then differentially decodes the bits and finds the blocks. The phase is only
+/// known up to 180° but the differential coding doesn't care. Groups with a damaged block are
+/// dropped, there is no error correction.
+pub struct RdsDecoder {
+    mpx_rate: u32,
+    mixer: MixerFilter,
+    resampler: RationalResampler<Complex32>,
+    channel: FIRFilter<Complex32>,
+    /// Averaged square of the baseband, its angle is twice the carrier phase.
+    square: Complex32,
+    history: [f32; SAMPLES_PER_BIT],
+    samples: usize,
+    /// Average biphase correlation at each of the 16 sampling phases.
+    strength: [f32; SAMPLES_PER_BIT],
+    since_bit: usize,
+    previous: bool,
+    sync: BlockSync,
+    text: RdsText,
+    mixed: Vec<Complex32>,
+    baseband: Vec<Complex32>,
+    filtered: Vec<Complex32>,
+}
+
+
+impl RdsDecoder {
+    pub fn new(mpx_rate: u32) -> Result<Self, ConfigError> {
+        if (mpx_rate as f32) < 2.0 * 60e3 {
+            return Err(ConfigError::Invalid(format!("{} Hz MPX is too slow for the 57 kHz RDS subcarrier", mpx_rate)));
+        }
+        let taps = lowpass_taps(2400.0 / RDS_RATE as f32, 65);
+        let gain: f32 = taps.iter().sum();
+        Ok(Self {
+            mpx_rate,
+            mixer: MixerFilter::new(mpx_rate, 57e3),
+            resampler: RationalResamplerBuilder::new(mpx_rate, RDS_RATE).taps_per_ratio().build()?,
+            channel: FIRFilter::new(taps.into_iter().map(|t| Complex32::new(t / gain, 0.0)).collect()),
+            square: Complex32::ZERO,
+            history: [0.0; SAMPLES_PER_BIT],
+            samples: 0,
+            strength: [0.0; SAMPLES_PER_BIT],
+            since_bit: 0,
+            previous: false,
+            sync: BlockSync::new(),
+            text: RdsText::new(),
+            mixed: Vec::new(),
+            baseband: Vec::new(),
+            filtered: Vec::new(),
+        })
+    }
+
+    /// Whether the block boundaries are found.
+    pub fn synced(&self) -> bool {
+        self.sync.synced
+    }
+
+    fn bit(&mut self, bit: bool, output: &mut Vec<RdsMessage>) {
+        let data = bit != self.previous;
+        self.previous = bit;
+        if let Some(group) = self.sync.push(data) {
+            output.push(RdsMessage::Group(group));
+            output.extend(self.text.push(&group));
+        }
+    }
+}
+
+
+impl Filter<f32, RdsMessage> for RdsDecoder {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<RdsMessage>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.mixer.filter(input, &mut self.mixed)?;
+        self.resampler.filter(&self.mixed, &mut self.baseband)?;
+        self.channel.filter(&self.baseband, &mut self.filtered)?;
+
+        for i in 0..self.filtered.len() {
+            let x = self.filtered[i];
+            // about 50 ms to settle the phase
+            self.square += 0.001 * (x * x - self.square);
+            let y = (x * Complex32::from_polar(1.0, -0.5 * self.square.arg())).re;
+
+            let slot = self.samples % SAMPLES_PER_BIT;
+            self.history[slot] = y;
+            self.samples += 1;
+            // the oldest half of the last bit minus the newest half, oldest first in the ring
+            let correlation: f32 = (0..SAMPLES_PER_BIT).map(|k| {
+                let sample = self.history[(self.samples + k) % SAMPLES_PER_BIT];
+                if k < SAMPLES_PER_BIT / 2 { sample } else { -sample }
+            }).sum();
+            self.strength[slot] += 0.02 * (correlation.abs() - self.strength[slot]);
+
+            // sample where the symbols are strongest, but never twice within half a bit
+            self.since_bit += 1;
+            let best = (0..SAMPLES_PER_BIT).max_by(|&a, &b| self.strength[a].total_cmp(&self.strength[b])).unwrap();
+            if (slot == best && self.since_bit >= SAMPLES_PER_BIT / 2) || self.since_bit >= 3 * SAMPLES_PER_BIT / 2 {
+                self.since_bit = 0;
+                self.bit(correlation > 0.0, output);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for RdsDecoder {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.mpx_rate))
+    }
+
+    /// Messages come at no fixed rate.
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::f64::consts::TAU;
+    use crate::rds::{checkword, RdsDecoder, RdsGroup, RdsMessage, TmcDecoder, TmcMessage, OFFSETS, RDS_BIT_RATE};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_tmc_decoder() {
+        let pi = 0xd3c2;
+        let mut decoder = TmcDecoder::new();
+
+        // 3A announcing TMC in 8A with location table 58
+        assert_eq!(decoder.push(&RdsGroup::new([pi, 0x3010, 58 << 6, 0xcd46])), None);
+        assert_eq!(decoder.location_table(), Some(58));
+
+        // single group: diversion, negative direction, extent 2, event 101 at location 12345, duration 3
+        let single = RdsGroup::new([pi, 0x8000 | 0x08 | 3, 0x8000 | 0x4000 | (2 << 11) | 101, 12345]);
+        assert_eq!(decoder.push(&single), Some(TmcMessage {
+            pi,
+            event: 101,
+            location: 12345,
+            extent: 2,
+            negative: true,
+            diversion: true,
+            duration: 3,
+            free_format: Vec::new(),
+        }));
+        assert_eq!(decoder.last.as_ref().unwrap().to_string(), "event 101 at location 12345 +2 negative diversion");
+        // the repeat is dropped
+        assert_eq!(decoder.push(&single), None);
+
+        // three group message with continuity index 5: the second group says one more follows
+        assert_eq!(decoder.push(&RdsGroup::new([pi, 0x8000 | 5, 0x8000 | (1 << 11) | 1478, 4000])), None);
+        assert_eq!(decoder.push(&RdsGroup::new([pi, 0x8000 | 5, 0x4000 | (1 << 12) | 0x0abc, 0x1234])), None);
+        let message = decoder.push(&RdsGroup::new([pi, 0x8000 | 5, 0x0def, 0x5678])).unwrap();
+        assert_eq!((message.event, message.location, message.extent), (1478, 4000, 1));
+        assert_eq!(message.free_format, [0x0abc_1234, 0x0def_5678]);
+
+        // a group lost in the middle drops the message
+        assert_eq!(decoder.push(&RdsGroup::new([pi, 0x8000 | 6, 0x8000 | 1478, 4001])), None);
+        assert_eq!(decoder.push(&RdsGroup::new([pi, 0x8000 | 6, 0x0def, 0x5678])), None);
+
+        // tuning information and other group types are ignored
+        assert_eq!(decoder.push(&RdsGroup::new([pi, 0x8000 | 0x10, 0, 0])), None);
+        assert_eq!(decoder.push(&RdsGroup::new([pi, 0x0008, 101, 12345])), None);
+    }
+
+    /// The bits of `groups` as transmitted, each block followed by its checkword and offset.
+    fn group_bits(groups: &[[u16; 4]]) -> Vec<bool> {
+        let mut bits = Vec::new();
+        for group in groups {
+            for (position, &data) in group.iter().enumerate() {
+                let block = ((data as u32) << 10) | (checkword(data) ^ OFFSETS[position]) as u32;
+                bits.extend((0..26).rev().map(|bit| block & (1 << bit) != 0));
+            }
+        }
+        bits
+    }
+
+    #[test]
+    fn test_rds_decoder() -> Result<(), Box<dyn Error>> {
+        let pi = 0x1234;
+        let name = b"RUST FM ";
+        let text = b"Hello RDS\r  ";
+        let mut groups = Vec::new();
+        for _ in 0..4 {
+            groups.extend((0..4).map(|segment| [pi, segment as u16, 0xe0cd, u16::from_be_bytes([name[2 * segment], name[2 * segment + 1]])]));
+            groups.extend((0..3).map(|segment| [pi, 0x2000 | segment as u16,
+                u16::from_be_bytes([text[4 * segment], text[4 * segment + 1]]),
+                u16::from_be_bytes([text[4 * segment + 2], text[4 * segment + 3]])]));
+        }
+
+        // differentially coded biphase on a subcarrier at three times the pilot's phase, under mono audio
+        let mut level = false;
+        let symbols: Vec<f64> = group_bits(&groups).into_iter().map(|bit| {
+            level ^= bit;
+            if level { 1.0 } else { -1.0 }
+        }).collect();
+        let rate = 192_000;
+        let duration = symbols.len() as f64 / RDS_BIT_RATE;
+        let mpx: Vec<f32> = (0..(duration * rate as f64) as usize).map(|n| {
+            let t = n as f64 / rate as f64;
+            let bits = t * RDS_BIT_RATE;
+            let half = if bits.fract() < 0.5 { 1.0 } else { -1.0 };
+            let pilot = TAU * 19e3 * t + 0.3;
+            let rds = 0.05 * symbols[bits as usize] * half * (3.0 * pilot).cos();
+            (0.5 * (TAU * 1e3 * t).sin() + 0.1 * pilot.sin() + rds) as f32
+        }).collect();
+
+        let mut decoder = RdsDecoder::new(rate)?;
+        let mut messages = Vec::new();
+        let mut block = Vec::new();
+        for chunk in mpx.chunks(4096) {
+            decoder.filter(chunk, &mut block)?;
+            messages.append(&mut block);
+        }
+        assert!(decoder.synced());
+        // sync and the phase take the first groups, after that every one makes it
+        let received = messages.iter().filter(|message| matches!(message, RdsMessage::Group(_))).count();
+        assert!(received >= groups.len() - 10, "{} of {} groups", received, groups.len());
+        let names: Vec<&RdsMessage> = messages.iter().filter(|message| matches!(message, RdsMessage::StationName { .. })).collect();
+        assert_eq!(names, [&RdsMessage::StationName { pi, name: "RUST FM ".to_string() }]);
+        let texts: Vec<&RdsMessage> = messages.iter().filter(|message| matches!(message, RdsMessage::RadioText { .. })).collect();
+        assert_eq!(texts, [&RdsMessage::RadioText { pi, text: "Hello RDS".to_string() }]);
+
+        assert!(RdsDecoder::new(48000).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::block::WavSink;
+use crate::events::{Event, EventBus};
+use crate::hits::Hit;
+use crate::traits::Sink;
+
+
+/// One entry of the scan list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanChannel {
+    pub frequency: u64,
+    pub label: String,
+}
+
+
+/// Reads `frequency_hz[,label]` lines, skipping blank lines and `#` comments.
+pub fn load_channels(path: &Path) -> Result<Vec<ScanChannel>, Box<dyn Error>> {
+    let mut channels = Vec::new();
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (frequency, label) = line.split_once(',').unwrap_or((line, ""));
+        let frequency: f64 = frequency.trim().parse()
+            .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+        let label = match label.trim() {
+            "" => format!("{}", frequency as u64),
+            label => label.to_string(),
+        };
+        channels.push(ScanChannel { frequency: frequency as u64, label });
+    }
+    if channels.is_empty() {
+        return Err(format!("{}: no channels", path.display()).into());
+    }
+    Ok(channels)
+}
+
+
+/// Opens above `threshold_db` and stays open until the level has been below it for `hang` samples.
+pub struct Squelch {
+    threshold_db: f32,
+    hang: usize,
+    quiet: usize,
+    open: bool,
+}
+
+
+impl Squelch {
+    pub fn new(threshold_db: f32, hang: usize) -> Self {
+        Self {
+            threshold_db,
+            hang,
+            quiet: 0,
+            open: false,
+        }
+    }
+
+    /// Feeds the level of a block of `samples` samples and returns whether the squelch is open.
+    pub fn update(&mut self, level_db: f32, samples: usize) -> bool {
+        if level_db >= self.threshold_db {
+            self.open = true;
+            self.quiet = 0;
+        } else if self.open {
+            self.quiet += samples;
+            self.open = self.quiet < self.hang;
+        }
+        self.open
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn reset(&mut self) {
+        self.quiet = 0;
+        self.open = false;
+    }
+}
+
+
+/// Steps through the channel list, lingering `dwell` samples on each and holding while the squelch is open.
+pub struct Scanner {
+    channels: Vec<ScanChannel>,
+    index: usize,
+    dwell: usize,
+    settle: usize,
+    elapsed: usize,
+}
+
+
+impl Scanner {
+    /// `settle` samples after every retune are not trusted, the hardware is still switching.
+    pub fn new(channels: Vec<ScanChannel>, dwell: usize, settle: usize) -> Self {
+        assert!(!channels.is_empty(), "scan list is empty");
+        Self {
+            channels,
+            index: 0,
+            dwell: dwell.max(settle),
+            settle,
+            elapsed: 0,
+        }
+    }
+
+    pub fn channel(&self) -> &ScanChannel {
+        &self.channels[self.index]
+    }
+
+    pub fn settling(&self) -> bool {
+        self.elapsed < self.settle
+    }
+
+    pub fn channels(&self) -> &[ScanChannel] {
+        &self.channels
+    }
+
+    /// Append channels found while scanning, e.g. from a `PeakDetector`, skipping those within
+    /// `spacing_hz` of one already on the list. Returns how many were added.
+    pub fn add_channels(&mut self, channels: impl IntoIterator<Item = ScanChannel>, spacing_hz: u64) -> usize {
+        let before = self.channels.len();
+        for channel in channels {
+            if self.channels.iter().all(|c| c.frequency.abs_diff(channel.frequency) >= spacing_hz) {
+                self.channels.push(channel);
+            }
+        }
+        self.channels.len() - before
+    }
+
+    /// Returns the next channel to tune to once the dwell is over and nothing is being received.
+    pub fn update(&mut self, samples: usize, open: bool) -> Option<&ScanChannel> {
+        self.elapsed += samples;
+        if open || self.elapsed < self.dwell || self.channels.len() == 1 {
+            return None;
+        }
+        self.index = (self.index + 1) % self.channels.len();
+        self.elapsed = 0;
+        Some(&self.channels[self.index])
+    }
+}
+
+
+/// `YYYYMMDDTHHMMSSZ` without pulling in a date crate.
+pub(crate) fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, rem) = (seconds / 86400, seconds % 86400);
+
+    // civil from days, Howard Hinnant's algorithm
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+
+struct Transmission {
+    sink: WavSink<BufWriter<File>>,
+    path: PathBuf,
+    channel: ScanChannel,
+    start: SystemTime,
+    samples: u64,
+}
+
+
+/// A finished transmission as written by [`TransmissionRecorder`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub path: PathBuf,
+    pub channel: ScanChannel,
+    pub start: SystemTime,
+    pub duration: Duration,
+}
+
+
+/// Writes every transmission to its own timestamped WAV file and appends a row per transmission
+/// to `activity.csv` in the same directory.
+pub struct TransmissionRecorder {
+    dir: PathBuf,
+    sample_rate: u32,
+    log: BufWriter<File>,
+    current: Option<Transmission>,
+    min_duration: Duration,
+    events: Option<EventBus>,
+}
+
+
+impl TransmissionRecorder {
+    pub fn new(dir: PathBuf, sample_rate: u32) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(&dir)?;
+        let log_path = dir.join("activity.csv");
+        let empty = std::fs::metadata(&log_path).map_or(true, |m| m.len() == 0);
+        let mut log = BufWriter::new(OpenOptions::new().create(true).append(true).open(&log_path)?);
+        if empty {
+            writeln!(log, "start_utc,duration_s,frequency_hz,label,file")?;
+            log.flush()?;
+        }
+        Ok(Self {
+            dir,
+            sample_rate,
+            log,
+            current: None,
+            min_duration: Duration::ZERO,
+            events: None,
+        })
+    }
+
+    /// Transmissions shorter than this are deleted instead of logged, e.g. squelch pops.
+    pub fn set_min_duration(&mut self, min_duration: Duration) {
+        self.min_duration = min_duration;
+    }
+
+    /// Publish every kept transmission as a hit with its file name as the text.
+    pub fn set_events(&mut self, events: &EventBus) {
+        self.events = Some(events.clone());
+    }
+
+    pub fn recording(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn start(&mut self, channel: &ScanChannel, time: SystemTime) -> Result<(), Box<dyn Error>> {
+        self.stop()?;
+        let label: String = channel.label.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        let path = self.dir.join(format!("{}_{}_{}.wav", utc_timestamp(time), channel.frequency, label));
+        self.current = Some(Transmission {
+            sink: WavSink::new_file(self.sample_rate, 1, path.clone())?,
+            path,
+            channel: channel.clone(),
+            start: time,
+            samples: 0,
+        });
+        Ok(())
+    }
+
+    /// Closes the current transmission, `None` if there was none or it was too short to keep.
+    pub fn stop(&mut self) -> Result<Option<Recording>, Box<dyn Error>> {
+        let Some(transmission) = self.current.take() else {
+            return Ok(None);
+        };
+        drop(transmission.sink);
+        let duration = transmission.samples as f64 / self.sample_rate as f64;
+        if duration < self.min_duration.as_secs_f64() {
+            std::fs::remove_file(&transmission.path)?;
+            return Ok(None);
+        }
+        let file = transmission.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        writeln!(self.log, "{},{:.2},{},{},{}", utc_timestamp(transmission.start), duration,
+                 transmission.channel.frequency, transmission.channel.label.replace(',', " "), file)?;
+        self.log.flush()?;
+        let recording = Recording {
+            path: transmission.path,
+            channel: transmission.channel,
+            start: transmission.start,
+            duration: Duration::from_secs_f64(duration),
+        };
+        if let Some(events) = &self.events {
+            events.publish(Event::Hit(Hit {
+                time: recording.start,
+                frequency: recording.channel.frequency,
+                duration: recording.duration,
+                rssi_db: None,
+                source: "recorder".into(),
+                text: Some(file),
+            }));
+        }
+        Ok(Some(recording))
+    }
+}
+
+
+impl Sink<f32> for TransmissionRecorder {
+    /// Audio outside of a transmission is dropped.
+    fn write(&mut self, src: &[f32]) -> Result<(), Box<dyn Error>> {
+        if let Some(transmission) = self.current.as_mut() {
+            transmission.sink.write(src)?;
+            transmission.samples += src.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+
+impl Drop for TransmissionRecorder {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::events::{Event, EventBus};
+    use crate::recorder::{load_channels, utc_timestamp, ScanChannel, Scanner, Squelch, TransmissionRecorder};
+    use crate::traits::Sink;
+
+    #[test]
+    fn test_scanner_recorder() -> Result<(), Box<dyn Error>> {
+        assert_eq!(utc_timestamp(UNIX_EPOCH + Duration::from_secs(1_709_210_096)), "20240229T123456Z");
+
+        let dir = std::env::temp_dir().join("rust_dsp_test_recorder");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("channels.csv"), "# scan list\n146520000,calling\n\n162.55e6\n")?;
+        let channels = load_channels(&dir.join("channels.csv"))?;
+        assert_eq!(channels[1], ScanChannel { frequency: 162_550_000, label: "162550000".into() });
+
+        let mut squelch = Squelch::new(-40.0, 200);
+        let mut scanner = Scanner::new(channels, 300, 100);
+        let mut recorder = TransmissionRecorder::new(dir.clone(), 1000)?;
+        recorder.set_min_duration(Duration::from_millis(250));
+        let bus = EventBus::new();
+        let events = bus.subscribe();
+        recorder.set_events(&bus);
+
+        // the first channel is quiet, then a transmission on the second one followed by a short pop
+        let levels = [-90.0, -90.0, -90.0, -90.0, -20.0, -20.0, -90.0, -90.0, -90.0, -90.0, -90.0, -90.0, -20.0, -90.0, -90.0];
+        let mut time = UNIX_EPOCH;
+        for level in levels {
+            time += Duration::from_millis(100);
+            if scanner.settling() {
+                scanner.update(100, false);
+                continue;
+            }
+            let open = squelch.update(level, 100);
+            if open && !recorder.recording() {
+                recorder.start(scanner.channel(), time)?;
+            } else if !open && recorder.recording() {
+                recorder.stop()?;
+            }
+            recorder.write(&[0.1; 100])?;
+            if scanner.update(100, open).is_some() {
+                squelch.reset();
+            }
+        }
+        drop(recorder);
+
+        let log = std::fs::read_to_string(dir.join("activity.csv"))?;
+        let rows: Vec<&str> = log.lines().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1], "19700101T000000Z,0.30,162550000,162550000,19700101T000000Z_162550000_162550000.wav");
+        let wavs = std::fs::read_dir(&dir)?.filter(|e| e.as_ref().is_ok_and(|e| e.path().extension().is_some_and(|x| x == "wav"))).count();
+        assert_eq!(wavs, 1);
+        // only the kept transmission is published
+        let events: Vec<Event> = events.try_iter().collect();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Event::Hit(hit) if hit.frequency == 162_550_000 && hit.text.as_deref() == Some("19700101T000000Z_162550000_162550000.wav")));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scanner_add_channels() {
+        let channel = |frequency| ScanChannel { frequency, label: String::new() };
+        let mut scanner = Scanner::new(vec![channel(146_520_000)], 300, 100);
+        assert_eq!(scanner.add_channels([channel(146_525_000), channel(146_550_000), channel(146_552_000)], 12_500), 1);
+        assert_eq!(scanner.channels().len(), 2);
+        assert_eq!(scanner.channels()[1].frequency, 146_550_000);
+    }
+
+}
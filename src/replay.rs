@@ -0,0 +1,268 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use half::f16;
+use num_complex::Complex;
+use crate::traits::*;
+
+
+const MAGIC: &[u8; 8] = b"RDSPRPL1";
+
+
+/// Sample types that can be written to a replay recording, stored little endian.
+pub trait Recordable: Sized + Copy {
+    const SIZE: usize;
+
+    fn write_le(&self, dst: &mut Vec<u8>);
+
+    fn read_le(src: &[u8]) -> Self;
+}
+
+
+macro_rules! impl_recordable {
+    ($($t:ty),*) => {
+        $(
+            impl Recordable for $t {
+                const SIZE: usize = size_of::<$t>();
+
+                fn write_le(&self, dst: &mut Vec<u8>) {
+                    dst.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn read_le(src: &[u8]) -> Self {
+                    <$t>::from_le_bytes(src[..Self::SIZE].try_into().unwrap())
+                }
+            }
+        )*
+    };
+}
+
+impl_recordable!(u8, i8, i16, u16, i32, u32, i64, u64, f16, f32, f64);
+
+
+impl<T: Recordable> Recordable for Complex<T> {
+    const SIZE: usize = 2 * T::SIZE;
+
+    fn write_le(&self, dst: &mut Vec<u8>) {
+        self.re.write_le(dst);
+        self.im.write_le(dst);
+    }
+
+    fn read_le(src: &[u8]) -> Self {
+        Complex::new(T::read_le(src), T::read_le(&src[T::SIZE..]))
+    }
+}
+
+
+/// Writes every buffer handed to it, keeping the buffer boundaries so a replay feeds a block exactly the same calls.
+pub struct BufferRecorder<W: Write> {
+    writer: W,
+    scratch: Vec<u8>,
+}
+
+
+impl<W: Write> BufferRecorder<W> {
+    pub fn new<T: Recordable>(mut writer: W) -> Result<Self, Box<dyn Error>> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(T::SIZE as u32).to_le_bytes())?;
+        Ok(Self {
+            writer,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub fn record<T: Recordable>(&mut self, buffer: &[T]) -> Result<(), Box<dyn Error>> {
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&(buffer.len() as u64).to_le_bytes());
+        for sample in buffer {
+            sample.write_le(&mut self.scratch);
+        }
+        self.writer.write_all(&self.scratch)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+
+/// Wraps a filter and records each input buffer before passing it on.
+pub struct RecordingFilter<F, W: Write> {
+    inner: F,
+    recorder: BufferRecorder<W>,
+}
+
+
+impl<F, W: Write> RecordingFilter<F, W> {
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+
+impl<I: Recordable, O, F: Filter<I, O>, W: Write> Filter<I, O> for RecordingFilter<F, W> {
+    fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> Result<(), Box<dyn Error>> {
+        self.recorder.record(input)?;
+        self.inner.filter(input, output)
+    }
+}
+
+
+/// Wraps a source and records each buffer it produces.
+pub struct RecordingSource<S, W: Write> {
+    inner: S,
+    recorder: BufferRecorder<W>,
+}
+
+
+impl<I: Recordable, S: Source<I>, W: Write> Source<I> for RecordingSource<S, W> {
+    fn read(&mut self, dst: &mut Vec<I>) -> Result<(), Box<dyn Error>> {
+        self.inner.read(dst)?;
+        self.recorder.record(dst)
+    }
+}
+
+
+/// Plays a recording back buffer by buffer, then returns empty buffers once it runs out.
+pub struct ReplaySource<R: Read> {
+    reader: R,
+    size: usize,
+    scratch: Vec<u8>,
+}
+
+
+impl ReplaySource<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+
+impl<R: Read> ReplaySource<R> {
+    pub fn new(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err("not a replay recording".into());
+        }
+        Ok(Self {
+            reader,
+            size: u32::from_le_bytes(header[8..].try_into()?) as usize,
+            scratch: Vec::new(),
+        })
+    }
+}
+
+
+impl<I: Recordable, R: Read> Source<I> for ReplaySource<R> {
+    fn read(&mut self, dst: &mut Vec<I>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        if self.size != I::SIZE {
+            return Err(format!("recording holds {} byte samples, expected {}", self.size, I::SIZE).into());
+        }
+
+        let mut len = [0u8; 8];
+        match self.reader.read_exact(&mut len) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        self.scratch.resize(u64::from_le_bytes(len) as usize * self.size, 0);
+        self.reader.read_exact(&mut self.scratch)?;
+        dst.extend(self.scratch.chunks_exact(self.size).map(I::read_le));
+        Ok(())
+    }
+}
+
+
+/// Records the blocks of a pipeline run into one file per named block under `dir`.
+pub struct ReplayRecorder {
+    dir: PathBuf,
+}
+
+
+impl ReplayRecorder {
+    pub fn new(dir: PathBuf) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.replay", name))
+    }
+
+    pub fn filter<I: Recordable, F>(&self, name: &str, inner: F) -> Result<RecordingFilter<F, BufWriter<File>>, Box<dyn Error>> {
+        let writer = BufWriter::new(File::create(self.path(name))?);
+        Ok(RecordingFilter {
+            inner,
+            recorder: BufferRecorder::new::<I>(writer)?,
+        })
+    }
+
+    pub fn source<I: Recordable, S: Source<I>>(&self, name: &str, inner: S) -> Result<RecordingSource<S, BufWriter<File>>, Box<dyn Error>> {
+        let writer = BufWriter::new(File::create(self.path(name))?);
+        Ok(RecordingSource {
+            inner,
+            recorder: BufferRecorder::new::<I>(writer)?,
+        })
+    }
+
+    /// Feed the recorded input of `name` through `filter` again, handing each output buffer to `on_output`.
+    pub fn replay<I: Recordable, O, F: Filter<I, O>>(&self, name: &str, filter: &mut F, mut on_output: impl FnMut(&[O])) -> Result<(), Box<dyn Error>> {
+        let mut source = ReplaySource::open(&self.path(name))?;
+        let mut input = Vec::new();
+        let mut output = Vec::new();
+        loop {
+            Source::<I>::read(&mut source, &mut input)?;
+            if input.is_empty() {
+                return Ok(());
+            }
+            filter.filter(&input, &mut output)?;
+            on_output(&output);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex32;
+    use crate::block::{FIRFilter, MixerFilter};
+    use crate::replay::ReplayRecorder;
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_record_replay() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("rust_dsp_replay_{}", std::process::id()));
+        let recorder = ReplayRecorder::new(dir.clone())?;
+
+        let mut mix = recorder.filter::<f32, _>("mix", MixerFilter::new(48000, 1000.0))?;
+        let mut fir = recorder.filter::<f32, _>("fir", FIRFilter::new(vec![0.25f32; 4]))?;
+        let mut expected = Vec::new();
+        let mut mixed: Vec<Complex32> = Vec::new();
+        let mut output = Vec::new();
+        for len in [100, 37, 512, 1] {
+            let input: Vec<f32> = (0..len).map(|n| (n as f32 * 0.1).sin()).collect();
+            mix.filter(&input, &mut mixed)?;
+            let real: Vec<f32> = mixed.iter().map(|v| v.re).collect();
+            fir.filter(&real, &mut output)?;
+            expected.push(output.clone());
+        }
+        drop(mix);
+        drop(fir);
+
+        let mut replayed = Vec::new();
+        recorder.replay("fir", &mut FIRFilter::new(vec![0.25f32; 4]), |out| replayed.push(out.to_vec()))?;
+        assert_eq!(replayed, expected);
+
+        let mut count = 0;
+        recorder.replay::<f32, Complex32, _>("mix", &mut MixerFilter::new(48000, 1000.0), |out| count += out.len())?;
+        assert_eq!(count, 650);
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+}
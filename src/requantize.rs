@@ -0,0 +1,197 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use num_complex::Complex32;
+use crate::rate::RateAware;
+use crate::seed::Rng;
+use crate::traits::*;
+
+
+/// Interleaved 8-bit I/Q layouts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteFormat {
+    /// Offset binary centered on 127.5, as RTL-SDR and rtl_tcp use.
+    U8,
+    /// Two's complement, as HackRF uses.
+    I8,
+}
+
+
+/// Converts f32 I/Q in [-1, 1] to 8-bit with TPDF dither, which trades the quantization distortion for
+/// a flat noise floor. `set_noise_shaping` additionally moves that noise away from DC towards the band edges.
+pub struct Requantizer {
+    format: ByteFormat,
+    dither: bool,
+    shaping: f32,
+    error: Complex32,
+    rng: Rng,
+}
+
+
+impl Requantizer {
+    pub fn new(format: ByteFormat) -> Self {
+        Self {
+            format,
+            dither: true,
+            shaping: 0.0,
+            error: Complex32::new(0.0, 0.0),
+            rng: Rng::instance("requantize"),
+        }
+    }
+
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    /// Restarts the dither from `seed` instead of this instance's stream under the crate wide seed.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// First order error feedback, 0 is off and 1 gives the full (1 - z^-1) shape.
+    pub fn set_noise_shaping(&mut self, amount: f32) {
+        self.shaping = amount.clamp(0.0, 1.0);
+    }
+
+    /// Triangular between -1 and 1 LSB.
+    fn tpdf(&mut self) -> f32 {
+        if self.dither { self.rng.uniform() - self.rng.uniform() } else { 0.0 }
+    }
+
+    fn quantize(&mut self, level: f32) -> (u8, f32) {
+        let dithered = level + self.tpdf();
+        match self.format {
+            ByteFormat::U8 => {
+                let q = (dithered + 127.5).round().clamp(0.0, 255.0);
+                (q as u8, q - 127.5)
+            },
+            ByteFormat::I8 => {
+                let q = dithered.round().clamp(-128.0, 127.0);
+                (q as i8 as u8, q)
+            },
+        }
+    }
+
+    fn scale(&self) -> f32 {
+        match self.format {
+            ByteFormat::U8 => 127.5,
+            ByteFormat::I8 => 127.0,
+        }
+    }
+}
+
+
+impl Filter<Complex32, u8> for Requantizer {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        output.reserve(2 * input.len());
+        let scale = self.scale();
+        for &x in input {
+            let wanted = x * scale - self.error * self.shaping;
+            let (i, re) = self.quantize(wanted.re);
+            let (q, im) = self.quantize(wanted.im);
+            self.error = Complex32::new(re, im) - wanted;
+            output.push(i);
+            output.push(q);
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for Requantizer {}
+
+
+/// Writes f32 I/Q as dithered 8-bit, e.g. a `.cu8` or `.cs8` file or a socket.
+pub struct ByteIqSink<W: Write> {
+    requantizer: Requantizer,
+    writer: W,
+    scratch: Vec<u8>,
+}
+
+
+impl ByteIqSink<BufWriter<File>> {
+    pub fn create(path: PathBuf, format: ByteFormat) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+}
+
+
+impl<W: Write> ByteIqSink<W> {
+    pub fn new(writer: W, format: ByteFormat) -> Self {
+        Self {
+            requantizer: Requantizer::new(format),
+            writer,
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn requantizer(&mut self) -> &mut Requantizer {
+        &mut self.requantizer
+    }
+
+    pub fn into_inner(mut self) -> Result<W, Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+
+impl<W: Write> Sink<Complex32> for ByteIqSink<W> {
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        self.requantizer.filter(src, &mut self.scratch)?;
+        self.writer.write_all(&self.scratch)?;
+        Ok(())
+    }
+}
+
+
+impl<W: Write> RateAware for ByteIqSink<W> {}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use num_complex::Complex32;
+    use crate::requantize::{ByteFormat, ByteIqSink, Requantizer};
+    use crate::traits::{Filter, Sink};
+
+    #[test]
+    fn test_requantizer() -> Result<(), Box<dyn Error>> {
+        // a DC level of 0.3 LSB vanishes without dither and survives on average with it
+        let input = vec![Complex32::new(0.3 / 127.0, -0.3 / 127.0); 100_000];
+        let mean = |bytes: &[u8]| bytes.iter().step_by(2).map(|&b| b as i8 as f32).sum::<f32>() / (bytes.len() / 2) as f32;
+        let mut output = Vec::new();
+        let mut plain = Requantizer::new(ByteFormat::I8);
+        plain.set_dither(false);
+        plain.filter(&input, &mut output)?;
+        assert_eq!(mean(&output), 0.0);
+        Requantizer::new(ByteFormat::I8).filter(&input, &mut output)?;
+        assert!((mean(&output) - 0.3).abs() < 0.02, "{}", mean(&output));
+
+        // two requantizers, e.g. one per channel, don't dither alike
+        let mut other = Vec::new();
+        Requantizer::new(ByteFormat::I8).filter(&input, &mut other)?;
+        assert_ne!(output, other);
+
+        // noise shaping keeps the error low near DC: compare the error averaged over 32 samples
+        let input: Vec<Complex32> = (0..100_000).map(|n| Complex32::from_polar(0.5, n as f32 * 0.001)).collect();
+        let dc_error = |shaping: f32| -> Result<f32, Box<dyn Error>> {
+            let mut requantizer = Requantizer::new(ByteFormat::U8);
+            requantizer.set_noise_shaping(shaping);
+            let mut output = Vec::new();
+            requantizer.filter(&input, &mut output)?;
+            let errors: Vec<f32> = input.iter().zip(output.chunks(2)).map(|(x, b)| b[0] as f32 - 127.5 - x.re * 127.5).collect();
+            Ok(errors.chunks(32).map(|c| (c.iter().sum::<f32>() / 32.0).powi(2)).sum::<f32>())
+        };
+        assert!(dc_error(1.0)? < 0.1 * dc_error(0.0)?);
+
+        let mut sink = ByteIqSink::new(Vec::new(), ByteFormat::U8);
+        sink.requantizer().set_dither(false);
+        sink.write(&[Complex32::new(1.0, -1.0), Complex32::new(0.0, 2.0)])?;
+        assert_eq!(sink.into_inner()?, [255, 0, 128, 255]);
+        Ok(())
+    }
+
+}
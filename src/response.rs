@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::f64::consts::TAU;
+use num_complex::Complex32;
+use num_traits::{One, Zero};
+use crate::traits::Filter;
+
+
+/// Real or complex samples the response helpers can drive a filter with.
+pub trait TestSignal: Copy + Zero + One {
+    /// What a unit tone is correlated with scaled by, 2 for real tones as half of a cosine is
+    /// at the negative frequency.
+    const TONE_SCALE: f32;
+
+    /// A unit amplitude tone at `phase` radians.
+    fn tone(phase: f32) -> Self;
+
+    fn to_complex(self) -> Complex32;
+}
+
+
+impl TestSignal for f32 {
+    const TONE_SCALE: f32 = 2.0;
+
+    fn tone(phase: f32) -> Self {
+        phase.cos()
+    }
+
+    fn to_complex(self) -> Complex32 {
+        Complex32::new(self, 0.0)
+    }
+}
+
+
+impl TestSignal for Complex32 {
+    const TONE_SCALE: f32 = 1.0;
+
+    fn tone(phase: f32) -> Self {
+        Complex32::from_polar(1.0, phase)
+    }
+
+    fn to_complex(self) -> Complex32 {
+        self
+    }
+}
+
+
+/// Gain and phase a filter applies to a tone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneResponse {
+    /// Output amplitude over input amplitude.
+    pub gain: f32,
+    /// Radians the output leads the input by, between -π and π.
+    pub phase: f32,
+}
+
+
+impl ToneResponse {
+    pub fn gain_db(&self) -> f32 {
+        20.0 * self.gain.log10()
+    }
+}
+
+
+/// Feeds `input` through `filter` `block_size` samples at a time and collects the output. A block
+/// that keeps its state right gives the same output for any block size.
+pub fn run<I: Copy, O: Copy, F: Filter<I, O>>(filter: &mut F, input: &[I], block_size: usize) -> Result<Vec<O>, Box<dyn Error>> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut block = Vec::new();
+    for chunk in input.chunks(block_size.max(1)) {
+        filter.filter(chunk, &mut block)?;
+        output.extend_from_slice(&block);
+    }
+    Ok(output)
+}
+
+
+/// The output for a unit impulse followed by `len - 1` zeros. `FIRFilter` gives its taps back
+/// reversed, it applies the first one to the oldest sample.
+pub fn impulse_response<I: TestSignal, O: Copy, F: Filter<I, O>>(filter: &mut F, len: usize) -> Result<Vec<O>, Box<dyn Error>> {
+    let mut input = vec![I::zero(); len];
+    if let Some(first) = input.first_mut() {
+        *first = I::one();
+    }
+    run(filter, &input, len)
+}
+
+
+/// The output for `len` ones, which settles at the DC gain.
+pub fn step_response<I: TestSignal, O: Copy, F: Filter<I, O>>(filter: &mut F, len: usize) -> Result<Vec<O>, Box<dyn Error>> {
+    run(filter, &vec![I::one(); len], len)
+}
+
+
+/// Gain and phase of `filter` at `frequency`, negative for complex filters meaning below DC. The
+/// tone runs for `settle` samples to get past the transient, then `measure` samples are correlated
+/// with it. Real tones leak their negative frequency into the result unless `measure` holds about a
+/// whole number of cycles or many of them. Only for blocks keeping the sample rate.
+pub fn tone_response<T: TestSignal, F: Filter<T, T>>(filter: &mut F, sample_rate: f32, frequency: f32, settle: usize, measure: usize) -> Result<ToneResponse, Box<dyn Error>> {
+    let step = TAU * frequency as f64 / sample_rate as f64;
+    // reduced in f64 so long tones keep their phase accurate
+    let phase = |n: usize| (step * n as f64 % TAU) as f32;
+    let input: Vec<T> = (0..settle + measure).map(|n| T::tone(phase(n))).collect();
+    let output = run(filter, &input, 4096)?;
+    if output.len() != input.len() {
+        return Err(format!("{} samples out for {} in, tone responses need the rate kept", output.len(), input.len()).into());
+    }
+    let correlation: Complex32 = output.iter().enumerate().skip(settle)
+        .map(|(n, y)| y.to_complex() * Complex32::from_polar(1.0, -phase(n)))
+        .sum();
+    let response = correlation * T::TONE_SCALE / measure.max(1) as f32;
+    Ok(ToneResponse { gain: response.norm(), phase: response.arg() })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::f32::consts::FRAC_PI_4;
+    use num_complex::Complex32;
+    use crate::block::{DeEmphasisFilter, FIRFilter};
+    use crate::response::{impulse_response, run, step_response, tone_response};
+
+    #[test]
+    fn test_filter_responses() -> Result<(), Box<dyn Error>> {
+        let taps = vec![0.1, 0.2, 0.3, 0.4];
+        assert_eq!(impulse_response(&mut FIRFilter::new(taps.clone()), 6)?, [0.4, 0.3, 0.2, 0.1, 0.0, 0.0]);
+        let step: Vec<f32> = step_response(&mut FIRFilter::new(taps.clone()), 6)?;
+        assert!((step[5] - 1.0).abs() < 1e-6);
+
+        let input: Vec<f32> = (0..100).map(|n| (n as f32 * 0.3).sin()).collect();
+        assert_eq!(run(&mut FIRFilter::new(taps.clone()), &input, 7)?, run(&mut FIRFilter::new(taps), &input, 100)?);
+
+        // a two sample average at a quarter of the rate is down 3 dB and lags by half a sample
+        let mut average = FIRFilter::new(vec![0.5, 0.5]);
+        let response = tone_response(&mut average, 48000.0, 12000.0, 10, 1000)?;
+        assert!((response.gain - FRAC_PI_4.cos()).abs() < 1e-4, "{:?}", response);
+        assert!((response.phase + FRAC_PI_4).abs() < 1e-4, "{:?}", response);
+        let mut average = FIRFilter::new(vec![Complex32::new(0.5, 0.0); 2]);
+        let response = tone_response(&mut average, 48000.0, -12000.0, 10, 1000)?;
+        assert!((response.phase - FRAC_PI_4).abs() < 1e-4, "{:?}", response);
+
+        // 75 µs de-emphasis has its corner near 2.1 kHz and lets 100 Hz through
+        let mut deemphasis = DeEmphasisFilter::new(48000, 75e-6);
+        let corner = tone_response(&mut deemphasis, 48000.0, 2122.0, 1000, 48000)?;
+        assert!((-4.0..-2.5).contains(&corner.gain_db()), "{:?}", corner);
+        let low = tone_response(&mut deemphasis, 48000.0, 100.0, 1000, 48000)?;
+        assert!(low.gain_db() > -0.1, "{:?}", low);
+        Ok(())
+    }
+}
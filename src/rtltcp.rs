@@ -0,0 +1,293 @@
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+use num_complex::Complex32;
+use crate::block::HackRFSource;
+use crate::gain::HackRFGain;
+use crate::pipeline::CancelToken;
+use crate::rate::RateAware;
+use crate::requantize::{ByteFormat, ByteIqSink};
+use crate::traits::{Sink, Source};
+use crate::tuning::Tunable;
+
+
+const MAGIC: [u8; 4] = *b"RTL0";
+/// Clients are told they talk to an R820T so they offer its gain table.
+const TUNER_R820T: u32 = 5;
+/// R820T gains in tenths of a dB, what `SetGainByIndex` indexes.
+pub const R820T_GAINS: [u32; 29] = [
+    0, 9, 14, 27, 37, 77, 87, 125, 144, 157, 166, 197, 207, 229, 254,
+    280, 297, 328, 338, 364, 372, 386, 402, 421, 434, 439, 445, 480, 496,
+];
+/// A client that stops reading for this long is dropped instead of stalling the radio.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+
+/// The 5 byte commands rtl_tcp clients send, a command byte and a big endian parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtlTcpCommand {
+    SetFrequency(u32),
+    SetSampleRate(u32),
+    /// Manual gain when true, tuner AGC otherwise.
+    SetGainMode(bool),
+    /// Tenths of a dB.
+    SetGain(u32),
+    SetFrequencyCorrection(i32),
+    SetIfGain { stage: u16, gain: i16 },
+    SetTestMode(bool),
+    SetAgcMode(bool),
+    SetDirectSampling(u32),
+    SetOffsetTuning(bool),
+    SetRtlXtal(u32),
+    SetTunerXtal(u32),
+    SetGainByIndex(u32),
+    SetBiasTee(bool),
+    Unknown(u8, u32),
+}
+
+
+impl RtlTcpCommand {
+    pub fn parse(bytes: [u8; 5]) -> Self {
+        let param = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+        match bytes[0] {
+            0x01 => Self::SetFrequency(param),
+            0x02 => Self::SetSampleRate(param),
+            0x03 => Self::SetGainMode(param != 0),
+            0x04 => Self::SetGain(param),
+            0x05 => Self::SetFrequencyCorrection(param as i32),
+            0x06 => Self::SetIfGain { stage: (param >> 16) as u16, gain: param as u16 as i16 },
+            0x07 => Self::SetTestMode(param != 0),
+            0x08 => Self::SetAgcMode(param != 0),
+            0x09 => Self::SetDirectSampling(param),
+            0x0a => Self::SetOffsetTuning(param != 0),
+            0x0b => Self::SetRtlXtal(param),
+            0x0c => Self::SetTunerXtal(param),
+            0x0d => Self::SetGainByIndex(param),
+            0x0e => Self::SetBiasTee(param != 0),
+            command => Self::Unknown(command, param),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let (command, param) = match *self {
+            Self::SetFrequency(hz) => (0x01, hz),
+            Self::SetSampleRate(rate) => (0x02, rate),
+            Self::SetGainMode(manual) => (0x03, manual as u32),
+            Self::SetGain(gain) => (0x04, gain),
+            Self::SetFrequencyCorrection(ppm) => (0x05, ppm as u32),
+            Self::SetIfGain { stage, gain } => (0x06, (stage as u32) << 16 | gain as u16 as u32),
+            Self::SetTestMode(on) => (0x07, on as u32),
+            Self::SetAgcMode(on) => (0x08, on as u32),
+            Self::SetDirectSampling(mode) => (0x09, mode),
+            Self::SetOffsetTuning(on) => (0x0a, on as u32),
+            Self::SetRtlXtal(hz) => (0x0b, hz),
+            Self::SetTunerXtal(hz) => (0x0c, hz),
+            Self::SetGainByIndex(index) => (0x0d, index),
+            Self::SetBiasTee(on) => (0x0e, on as u32),
+            Self::Unknown(command, param) => (command, param),
+        };
+        let param = param.to_be_bytes();
+        [command, param[0], param[1], param[2], param[3]]
+    }
+}
+
+
+/// Plays the part of `rtl_tcp` so SDR programs can use any source as their backend. Samples written
+/// to the server go to the connected client as offset binary 8-bit I/Q, the commands the client sends
+/// are collected for the caller to apply, see `serve`. Like rtl_tcp it serves one client at a time,
+/// others are turned away until it leaves.
+pub struct RtlTcpServer {
+    incoming: Receiver<TcpStream>,
+    local_addr: SocketAddr,
+    client: Option<(ByteIqSink<TcpStream>, Receiver<RtlTcpCommand>)>,
+    commands: Vec<RtlTcpCommand>,
+}
+
+
+impl RtlTcpServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, Box<dyn Error>> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (tx, incoming) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if tx.send(stream).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            incoming,
+            local_addr,
+            client: None,
+            commands: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    fn accept_client(&mut self) {
+        while let Ok(mut stream) = self.incoming.try_recv() {
+            if self.client.is_some() {
+                continue;
+            }
+            let mut header = MAGIC.to_vec();
+            header.extend_from_slice(&TUNER_R820T.to_be_bytes());
+            header.extend_from_slice(&(R820T_GAINS.len() as u32).to_be_bytes());
+            let Ok(mut reader) = stream.try_clone() else { continue };
+            if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() || stream.write_all(&header).is_err() {
+                continue;
+            }
+            let (tx, commands) = channel();
+            thread::spawn(move || {
+                let mut bytes = [0u8; 5];
+                while reader.read_exact(&mut bytes).is_ok() && tx.send(RtlTcpCommand::parse(bytes)).is_ok() {}
+            });
+            let mut sink = ByteIqSink::new(stream, ByteFormat::U8);
+            sink.requantizer().set_dither(false);
+            self.client = Some((sink, commands));
+        }
+    }
+
+    /// Commands received since the last call, oldest first.
+    pub fn take_commands(&mut self) -> Vec<RtlTcpCommand> {
+        self.accept_client();
+        if let Some((_, commands)) = &self.client {
+            self.commands.extend(commands.try_iter());
+        }
+        std::mem::take(&mut self.commands)
+    }
+
+    /// Streams `source` until it ends or `cancel` fires, handing every command to `control`. Commands
+    /// `control` can't carry out go to `rejected` and are otherwise ignored, the client keeps its stream.
+    pub fn serve<S: Source<Complex32>>(&mut self, source: &mut S, cancel: &CancelToken,
+                                       mut control: impl FnMut(&mut S, RtlTcpCommand) -> Result<(), Box<dyn Error>>,
+                                       mut rejected: impl FnMut(RtlTcpCommand, Box<dyn Error>)) -> Result<(), Box<dyn Error>> {
+        let mut samples = Vec::new();
+        while !cancel.is_cancelled() {
+            for command in self.take_commands() {
+                if let Err(e) = control(source, command) {
+                    rejected(command, e);
+                }
+            }
+            source.read(&mut samples)?;
+            if samples.is_empty() {
+                break;
+            }
+            self.write(&samples)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl Sink<Complex32> for RtlTcpServer {
+    /// Dropped while nobody is connected. A client that can't keep up within the write timeout is disconnected.
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        self.accept_client();
+        if let Some((sink, commands)) = &mut self.client && sink.write(src).is_err() {
+            // keep what it asked for before leaving, e.g. the final gain
+            self.commands.extend(commands.try_iter());
+            self.client = None;
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for RtlTcpServer {}
+
+
+/// Carries out rtl_tcp commands on a HackRF. Gains are spread over the HackRF's much larger range,
+/// the sample rate can't change while streaming and the tuner AGC and the RTL specific settings
+/// don't exist.
+pub fn control_hackrf(source: &mut HackRFSource, command: RtlTcpCommand) -> Result<(), Box<dyn Error>> {
+    let set_gain = |source: &mut HackRFSource, tenths: u32| {
+        let db = tenths as f64 / *R820T_GAINS.last().unwrap() as f64 * HackRFGain::MAX_DB as f64;
+        source.set_gain(HackRFGain::distribute(db.round() as u32))
+    };
+    match command {
+        RtlTcpCommand::SetFrequency(hz) => source.set_frequency(hz as u64),
+        RtlTcpCommand::SetGain(tenths) => set_gain(source, tenths),
+        RtlTcpCommand::SetGainByIndex(index) => set_gain(source, *R820T_GAINS.get(index as usize).ok_or("gain index out of range")?),
+        RtlTcpCommand::SetSampleRate(rate) => match source.output_rate(None) {
+            Some(current) if current.0 == rate => Ok(()),
+            current => Err(format!("sample rate is fixed at {:?} Hz", current.map(|rate| rate.0)).into()),
+        },
+        RtlTcpCommand::SetFrequencyCorrection(_) => Err("the stored ppm correction is applied instead".into()),
+        _ => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::{Duration, Instant};
+    use num_complex::Complex32;
+    use crate::rtltcp::{RtlTcpCommand, RtlTcpServer};
+    use crate::traits::Sink;
+
+    #[test]
+    fn test_rtl_tcp_server() -> Result<(), Box<dyn Error>> {
+        for command in [RtlTcpCommand::SetFrequency(100_000_000), RtlTcpCommand::SetIfGain { stage: 2, gain: -30 }, RtlTcpCommand::SetFrequencyCorrection(-12)] {
+            assert_eq!(RtlTcpCommand::parse(command.to_bytes()), command);
+        }
+
+        let mut server = RtlTcpServer::bind("127.0.0.1:0")?;
+        let mut client = TcpStream::connect(server.local_addr())?;
+        client.set_read_timeout(Some(Duration::from_secs(5)))?;
+        client.write_all(&RtlTcpCommand::SetFrequency(145_500_000).to_bytes())?;
+        client.write_all(&RtlTcpCommand::SetGain(496).to_bytes())?;
+
+        let mut commands = Vec::new();
+        let start = Instant::now();
+        while commands.len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            commands.extend(server.take_commands());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(server.connected());
+        assert_eq!(commands, [RtlTcpCommand::SetFrequency(145_500_000), RtlTcpCommand::SetGain(496)]);
+
+        let mut header = [0u8; 12];
+        client.read_exact(&mut header)?;
+        assert_eq!(&header[..4], b"RTL0");
+        assert_eq!(u32::from_be_bytes(header[8..].try_into()?), 29);
+
+        server.write(&[Complex32::new(1.0, -1.0), Complex32::new(0.0, 0.5)])?;
+        let mut samples = [0u8; 4];
+        client.read_exact(&mut samples)?;
+        assert_eq!(samples, [255, 0, 128, 191]);
+
+        // a client that left is noticed on the next writes
+        drop(client);
+        let start = Instant::now();
+        while server.connected() && start.elapsed() < Duration::from_secs(5) {
+            server.write(&[Complex32::new(0.0, 0.0); 1024])?;
+        }
+        assert!(!server.connected());
+
+        // so is one that stops reading, rather than blocking the writes
+        let _stalled = TcpStream::connect(server.local_addr())?;
+        let start = Instant::now();
+        while !server.connected() && start.elapsed() < Duration::from_secs(5) {
+            server.write(&[])?;
+        }
+        while server.connected() && start.elapsed() < Duration::from_secs(20) {
+            server.write(&[Complex32::new(0.0, 0.0); 65536])?;
+        }
+        assert!(!server.connected());
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use half::f16;
+use num_complex::{Complex, Complex32};
+use crate::replay::Recordable;
+
+
+/// A stored or transported sample format with a fixed full scale, converted to and from the
+/// f32 or Complex32 used by the processing blocks.
+pub trait Sample: Recordable {
+    /// `f32` for real formats, `Complex32` for complex ones.
+    type Float: Copy;
+
+    /// Scaled so full scale maps to about ±1.
+    fn to_float(self) -> Self::Float;
+
+    /// Rounds and saturates at full scale.
+    fn from_float(value: Self::Float) -> Self;
+}
+
+
+/// Offset binary as produced by RTL-SDR dongles.
+impl Sample for u8 {
+    type Float = f32;
+
+    fn to_float(self) -> f32 {
+        (self as f32 - 127.5) / 127.5
+    }
+
+    fn from_float(value: f32) -> Self {
+        (value * 127.5 + 127.5).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+
+impl Sample for i8 {
+    type Float = f32;
+
+    fn to_float(self) -> f32 {
+        self as f32 / 128.0
+    }
+
+    fn from_float(value: f32) -> Self {
+        (value * 128.0).round().clamp(-128.0, 127.0) as i8
+    }
+}
+
+
+impl Sample for i16 {
+    type Float = f32;
+
+    fn to_float(self) -> f32 {
+        self as f32 / 32767.0
+    }
+
+    fn from_float(value: f32) -> Self {
+        (value * 32767.0).round().clamp(-32768.0, 32767.0) as i16
+    }
+}
+
+
+impl Sample for f32 {
+    type Float = f32;
+
+    fn to_float(self) -> f32 {
+        self
+    }
+
+    fn from_float(value: f32) -> Self {
+        value
+    }
+}
+
+
+/// IEEE half precision, about 3 significant digits but 11 bits of mantissa at any level.
+impl Sample for f16 {
+    type Float = f32;
+
+    fn to_float(self) -> f32 {
+        self.to_f32()
+    }
+
+    fn from_float(value: f32) -> Self {
+        f16::from_f32(value)
+    }
+}
+
+
+impl<T: Sample<Float = f32>> Sample for Complex<T> {
+    type Float = Complex32;
+
+    fn to_float(self) -> Complex32 {
+        Complex32::new(self.re.to_float(), self.im.to_float())
+    }
+
+    fn from_float(value: Complex32) -> Self {
+        Complex::new(T::from_float(value.re), T::from_float(value.im))
+    }
+}
+
+
+/// Converts stored samples, appending to `dst`.
+pub fn to_float<T: Sample>(src: &[T], dst: &mut Vec<T::Float>) {
+    dst.extend(src.iter().map(|s| s.to_float()));
+}
+
+
+/// Decodes little endian samples from `src`, ignoring a trailing partial sample.
+pub fn decode_le<T: Sample>(src: &[u8], dst: &mut Vec<T::Float>) {
+    dst.extend(src.chunks_exact(T::SIZE).map(|b| T::read_le(b).to_float()));
+}
+
+
+/// Encodes samples little endian, appending to `dst`.
+pub fn encode_le<T: Sample>(src: &[T::Float], dst: &mut Vec<u8>) {
+    for &value in src {
+        T::from_float(value).write_le(dst);
+    }
+}
+
+
+/// Bytes per complex sample of packed 12-bit I/Q.
+pub const PACKED12_SIZE: usize = 3;
+
+
+/// Decodes packed 12-bit I/Q as AD936x based radios like the Pluto deliver it with the padding
+/// stripped: each sample is a little endian 24-bit word with I, two's complement, in the low 12 bits
+/// and Q in the high 12. Scaled so ±2047 maps to ±1, a trailing partial sample is ignored.
+pub fn decode_packed12(src: &[u8], dst: &mut Vec<Complex32>) {
+    dst.extend(src.chunks_exact(PACKED12_SIZE).map(|b| {
+        let word = u32::from_le_bytes([b[0], b[1], b[2], 0]);
+        // shift each value to the top of an i32 and back down to sign extend it
+        let i = ((word << 20) as i32) >> 20;
+        let q = ((word << 8) as i32) >> 20;
+        Complex32::new(i as f32 / 2047.0, q as f32 / 2047.0)
+    }));
+}
+
+
+/// Encodes packed 12-bit I/Q, see `decode_packed12`. Rounds and saturates at full scale.
+pub fn encode_packed12(src: &[Complex32], dst: &mut Vec<u8>) {
+    let level = |x: f32| (x * 2047.0).round().clamp(-2048.0, 2047.0) as i32 as u32 & 0xfff;
+    for value in src {
+        let word = level(value.re) | (level(value.im) << 12);
+        dst.extend_from_slice(&word.to_le_bytes()[..PACKED12_SIZE]);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use half::f16;
+    use num_complex::{Complex, Complex32};
+    use crate::sample::{decode_le, decode_packed12, encode_le, encode_packed12, Sample};
+
+    #[test]
+    fn test_sample_formats() {
+        assert_eq!(u8::from_float(0.0), 128);
+        assert_eq!(u8::from_float(-2.0), 0);
+        assert_eq!(i8::from_float(1.0), 127);
+        assert_eq!(i16::from_float(-1.0), -32767);
+        assert_eq!((-128i8).to_float(), -1.0);
+
+        let values = [Complex32::new(0.5, -0.25), Complex32::new(-1.0, 0.999)];
+        let mut bytes = Vec::new();
+        encode_le::<Complex<i16>>(&values, &mut bytes);
+        assert_eq!(bytes.len(), 8);
+        let mut decoded = Vec::new();
+        decode_le::<Complex<i16>>(&bytes, &mut decoded);
+        for (a, b) in values.iter().zip(&decoded) {
+            assert!((a - b).norm() < 1e-4);
+        }
+
+        decoded.clear();
+        decode_le::<Complex<u8>>(&[255, 0, 128], &mut decoded);
+        assert_eq!(decoded, [Complex32::new(1.0, -1.0)]);
+
+        // half precision keeps 11 significant bits and small values too
+        assert_eq!(f16::from_float(0.5).to_bits(), 0x3800);
+        assert!((f16::from_float(1e-5).to_float() - 1e-5).abs() < 1e-7);
+        bytes.clear();
+        encode_le::<Complex<f16>>(&values, &mut bytes);
+        assert_eq!(bytes.len(), 8);
+        decoded.clear();
+        decode_le::<Complex<f16>>(&bytes, &mut decoded);
+        for (a, b) in values.iter().zip(&decoded) {
+            assert!((a - b).norm() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_packed12() {
+        // I = 0x123, Q = -2 = 0xffe: 0xffe123 little endian
+        let mut bytes = Vec::new();
+        encode_packed12(&[Complex32::new(0x123 as f32 / 2047.0, -2.0 / 2047.0)], &mut bytes);
+        assert_eq!(bytes, [0x23, 0xe1, 0xff]);
+
+        // full scale both ways, saturating past it
+        bytes.clear();
+        encode_packed12(&[Complex32::new(1.0, -1.0), Complex32::new(-3.0, 3.0)], &mut bytes);
+        assert_eq!(bytes, [0xff, 0x17, 0x80, 0x00, 0xf8, 0x7f]);
+        let mut decoded = Vec::new();
+        decode_packed12(&bytes, &mut decoded);
+        assert_eq!(decoded, [Complex32::new(1.0, -1.0), Complex32::new(-2048.0 / 2047.0, 1.0)]);
+
+        // every 12-bit value survives the round trip, a trailing partial sample is dropped
+        let values: Vec<Complex32> = (-2048..2048).map(|n| Complex32::new(n as f32 / 2047.0, -(n + 1).min(2047) as f32 / 2047.0)).collect();
+        bytes.clear();
+        encode_packed12(&values, &mut bytes);
+        bytes.extend_from_slice(&[1, 2]);
+        decoded.clear();
+        decode_packed12(&bytes, &mut decoded);
+        assert_eq!(decoded, values);
+    }
+}
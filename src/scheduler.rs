@@ -0,0 +1,398 @@
+use std::error::Error;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::flowgraph::{Filtered, RateCheck};
+use crate::pipeline::{CancelToken, RunStats, Runner, StopReason};
+use crate::rate::{block_latency, LatencyReport, RateAware, RateMismatch, SampleRate};
+use crate::seed::is_deterministic;
+use crate::streambuf::{new_stream, StreamReader, StreamWriter};
+use crate::traits::{Filter, Sink, Source};
+use crate::util::resize_unchecked;
+
+
+/// Samples queued between two blocks unless `set_capacity` says otherwise.
+const DEFAULT_CAPACITY: usize = 1 << 16;
+
+type Worker = Box<dyn FnOnce() -> Result<(), String> + Send>;
+
+/// The latest latency of the source and every filter, updated by their threads after each read.
+type Delays = Arc<Mutex<Vec<Duration>>>;
+
+
+/// Hands the chain's latency to `report` every `every` while it runs.
+struct LatencyLog {
+    every: Duration,
+    last: Option<Instant>,
+    report: Box<dyn FnMut(&LatencyReport)>,
+}
+
+
+/// The reading end of the queue behind a block.
+struct Queue<T: Copy> {
+    reader: StreamReader<T>,
+    samples_per_read: usize,
+    /// Wait for whole blocks, only the last one may be short.
+    fill: bool,
+}
+
+
+impl<T: Copy> Source<T> for Queue<T> {
+    fn read(&mut self, dst: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        unsafe { resize_unchecked(dst, self.samples_per_read); }
+        let mut filled = 0;
+        while filled < self.samples_per_read {
+            match self.reader.get(&mut dst[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) => {
+                    unsafe { resize_unchecked(dst, 0); }
+                    return Err(Box::new(e));
+                },
+            }
+            if !self.fill {
+                break;
+            }
+        }
+        unsafe { resize_unchecked(dst, filled); }
+        Ok(())
+    }
+}
+
+
+impl<T: Copy> RateAware for Queue<T> {}
+
+
+/// Reads `source` into `writer` until the source ends, the reader goes away or the token fires. On
+/// errors the queues close like at the end of the stream, so the other threads stop as well. The
+/// block's `latency` goes into `delays` after every read.
+fn pump<T: Copy, S: Source<T>>(index: usize, mut source: S, writer: StreamWriter<T>, cancel: CancelToken, delays: Delays, latency: impl Fn(&S) -> Duration) -> Result<(), String> {
+    let mut buffer = Vec::new();
+    loop {
+        if let Err(e) = source.read(&mut buffer) {
+            if cancel.is_cancelled() && e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::Interrupted) {
+                return Ok(());
+            }
+            return Err(format!("block {}: {}", index, e));
+        }
+        delays.lock().unwrap()[index] = latency(&source);
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let mut written = 0;
+        while written < buffer.len() {
+            match writer.put(&buffer[written..]) {
+                Ok(count) => written += count,
+                // downstream is done or cancelled
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+}
+
+
+/// Like `Flowgraph` but the source and every filter get a thread of their own, connected by
+/// blocking `streambuf` queues, so a heavy FIR or resampler has a core to itself:
+///
+/// `scheduler.source(hackrf).filter(mix).filter(resample).sink(speakers)?.run()?`
+///
+/// The sink runs on the calling thread and doesn't have to be `Send`, e.g. `Speakers`. The other
+/// blocks are moved to their threads when the graph first runs.
+pub struct Scheduler {
+    runner: Runner,
+    cancel: CancelToken,
+    capacity: usize,
+    deterministic: bool,
+    latency: Option<LatencyLog>,
+}
+
+
+impl Scheduler {
+    pub fn new(cancel: CancelToken) -> Self {
+        Self {
+            runner: Runner::new(cancel.clone()),
+            cancel,
+            capacity: DEFAULT_CAPACITY,
+            deterministic: is_deterministic(),
+            latency: None,
+        }
+    }
+
+    /// Samples queued after each block, 65536 by default. Larger queues ride out longer stalls of a
+    /// block at the cost of latency.
+    pub fn set_capacity(&mut self, samples: usize) {
+        self.capacity = samples.max(1);
+    }
+
+    /// Hand every block the same block sizes on every run, a quarter of the capacity. Defaults to
+    /// `seed::is_deterministic()`.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Hand the chain's latency to `report` as soon as the sink gets samples and then every `every`,
+    /// the block delays change as buffers fill and drain.
+    pub fn report_latency(&mut self, every: Duration, report: impl FnMut(&LatencyReport) + 'static) {
+        self.latency = Some(LatencyLog { every, last: None, report: Box::new(report) });
+    }
+
+    /// For stop conditions and the cancel token.
+    pub fn runner(&mut self) -> &mut Runner {
+        &mut self.runner
+    }
+
+    /// The queue a block writes to and the chain's source reading it.
+    fn queue<T: Copy>(&self) -> (Queue<T>, StreamWriter<T>) {
+        let (reader, writer) = new_stream(self.capacity, false, true, true).expect("blocking queue");
+        reader.set_cancel(&self.cancel);
+        writer.set_cancel(&self.cancel);
+        (Queue { reader, samples_per_read: (self.capacity / 4).max(1), fill: self.deterministic }, writer)
+    }
+
+    pub fn source<T, S>(&mut self, source: S) -> ThreadedChain<'_, T>
+    where T: Copy + Send + 'static, S: Source<T> + RateAware + Send + 'static {
+        let rates = RateCheck::new(&source);
+        let (output, writer) = self.queue();
+        let cancel = self.cancel.clone();
+        let delays = Arc::new(Mutex::new(vec![Duration::ZERO]));
+        let worker_delays = Arc::clone(&delays);
+        let input = source.input_rate();
+        ThreadedChain {
+            scheduler: self,
+            workers: vec![Box::new(move || pump(0, source, writer, cancel, worker_delays, move |source| block_latency(source, input)))],
+            output,
+            rates,
+            delays,
+        }
+    }
+}
+
+
+/// A source and the filters attached to it so far, producing `T` into a queue.
+pub struct ThreadedChain<'a, T: Copy> {
+    scheduler: &'a mut Scheduler,
+    workers: Vec<Worker>,
+    output: Queue<T>,
+    rates: RateCheck,
+    delays: Delays,
+}
+
+
+impl<'a, T: Copy + Send + 'static> ThreadedChain<'a, T> {
+    pub fn filter<O, F>(mut self, filter: F) -> ThreadedChain<'a, O>
+    where O: Copy + Send + 'static, F: Filter<T, O> + RateAware + Send + 'static {
+        let rate = self.rates.rate.or(filter.input_rate());
+        self.rates.check(&filter);
+        let (output, writer) = self.scheduler.queue();
+        let (index, input, cancel) = (self.workers.len(), self.output, self.scheduler.cancel.clone());
+        self.delays.lock().unwrap().push(Duration::ZERO);
+        let delays = Arc::clone(&self.delays);
+        let latency = move |filtered: &Filtered<Queue<T>, F, T>| block_latency(filtered.filter(), rate);
+        self.workers.push(Box::new(move || pump(index, Filtered::new(input, filter), writer, cancel, delays, latency)));
+        ThreadedChain {
+            scheduler: self.scheduler,
+            workers: self.workers,
+            output,
+            rates: self.rates,
+            delays: self.delays,
+        }
+    }
+
+    /// Rate coming out of the last block, if anything in the chain knows it.
+    pub fn rate(&self) -> Option<SampleRate> {
+        self.rates.rate
+    }
+
+    /// Finishes the chain, failing on the first connection whose rates don't match.
+    pub fn sink<K: Sink<T> + RateAware>(mut self, sink: K) -> Result<ThreadedGraph<'a, T, K>, RateMismatch> {
+        let rate = self.rates.rate.or(sink.input_rate());
+        self.rates.check(&sink);
+        self.rates.result()?;
+        let scheduler = self.scheduler;
+        Ok(ThreadedGraph {
+            runner: &mut scheduler.runner,
+            latency: scheduler.latency.as_mut(),
+            workers: self.workers,
+            threads: Vec::new(),
+            output: self.output,
+            sink,
+            sink_rate: rate,
+            delays: self.delays,
+        })
+    }
+}
+
+
+/// A complete threaded chain. Runs that stop on a duration, sample count or condition leave the
+/// threads running for the next run; the end of the stream or the token stops them for good.
+pub struct ThreadedGraph<'a, T: Copy, K> {
+    runner: &'a mut Runner,
+    latency: Option<&'a mut LatencyLog>,
+    workers: Vec<Worker>,
+    threads: Vec<JoinHandle<Result<(), String>>>,
+    output: Queue<T>,
+    sink: K,
+    /// Rate coming into the sink.
+    sink_rate: Option<SampleRate>,
+    delays: Delays,
+}
+
+
+/// Latency of every block as their threads last saw it, followed by the sink's.
+fn latency(delays: &Delays, sink: &dyn RateAware, sink_rate: Option<SampleRate>) -> LatencyReport {
+    let mut blocks = delays.lock().unwrap().clone();
+    blocks.push(block_latency(sink, sink_rate));
+    LatencyReport::new(blocks)
+}
+
+
+impl<T: Copy, K: Sink<T> + RateAware> ThreadedGraph<'_, T, K> {
+    fn start(&mut self) {
+        for worker in self.workers.drain(..) {
+            self.threads.push(thread::spawn(worker));
+        }
+    }
+
+    /// Waits for the threads once the stream ended, an error in any block is returned here.
+    fn finish(&mut self, stats: RunStats) -> Result<RunStats, Box<dyn Error>> {
+        if matches!(stats.stop, StopReason::EndOfStream | StopReason::Cancelled) {
+            for thread in self.threads.drain(..) {
+                thread.join().map_err(|_| "block thread panicked")??;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Latency of the chain from the source to the sink, from the block delays as they are now.
+    pub fn latency(&self) -> LatencyReport {
+        latency(&self.delays, &self.sink, self.sink_rate)
+    }
+
+    /// Starts the threads and hands `run` the runner, the last queue and a sink writing and
+    /// reporting the latency when it's due.
+    fn run_with<R>(&mut self, run: R) -> Result<RunStats, Box<dyn Error>>
+    where R: FnOnce(&mut Runner, &mut Queue<T>, &mut dyn FnMut(&[T]) -> Result<(), Box<dyn Error>>) -> Result<RunStats, Box<dyn Error>> {
+        self.start();
+        let (sink, delays, sink_rate, log) = (&mut self.sink, &self.delays, self.sink_rate, &mut self.latency);
+        let mut write = |block: &[T]| {
+            sink.write(block)?;
+            if let Some(log) = log.as_mut()
+                && log.last.is_none_or(|last| last.elapsed() >= log.every) {
+                log.last = Some(Instant::now());
+                (log.report)(&latency(delays, &*sink, sink_rate));
+            }
+            Ok(())
+        };
+        let stats = run(self.runner, &mut self.output, &mut write)?;
+        self.finish(stats)
+    }
+
+    /// Until the source ends, the token is cancelled or a stop condition fires.
+    pub fn run(&mut self) -> Result<RunStats, Box<dyn Error>> {
+        self.run_with(|runner, output, write| runner.run(output, write))
+    }
+
+    pub fn run_for(&mut self, duration: Duration) -> Result<RunStats, Box<dyn Error>> {
+        self.run_with(|runner, output, write| runner.run_for(duration, output, write))
+    }
+
+    /// Counts samples reaching the sink, unlike `Connected::run_samples`.
+    pub fn run_samples(&mut self, samples: u64) -> Result<RunStats, Box<dyn Error>> {
+        self.run_with(|runner, output, write| runner.run_samples(samples, output, write))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use num_complex::Complex32;
+    use crate::block::{FIRFilter, FnFilter, MapFilter, MixerFilter, NullSink, NullSource, RationalResampler};
+    use crate::pipeline::{CancelToken, StopReason};
+    use crate::rate::SampleRate;
+    use crate::scheduler::Scheduler;
+
+    #[test]
+    fn test_scheduler() -> Result<(), Box<dyn Error>> {
+        let mut scheduler = Scheduler::new(CancelToken::new());
+        scheduler.set_capacity(4096);
+        let mut sink = NullSink::new();
+        let stats = scheduler.source(NullSource::new(48000, 1000).limit(96000))
+            .filter(MapFilter::new(|x: f32| x + 1.0))
+            .filter(MixerFilter::new(48000, 1000.0))
+            .filter(MapFilter::new(|x: Complex32| x.norm()))
+            .filter(RationalResampler::<f32>::new(48000, 8000, 31))
+            .sink(&mut sink)?
+            .run()?;
+        assert_eq!(stats.stop, StopReason::EndOfStream);
+        assert_eq!(sink.samples(), 16000);
+
+        // runs can be split, the threads keep going in between
+        let chain = scheduler.source::<f32, _>(NullSource::new(48000, 1000));
+        assert_eq!(chain.rate(), Some(SampleRate(48000)));
+        let mut graph = chain.sink(NullSink::new())?;
+        assert_eq!(graph.run_samples(4000)?.stop, StopReason::SampleLimit);
+        assert_eq!(graph.run_samples(4000)?.samples, 4000);
+
+        // an error in a block ends the run with it
+        let failing = FnFilter::new(|_: &[f32], _: &mut Vec<f32>| Err("broken block".into()));
+        let error = scheduler.source(NullSource::new(48000, 1000))
+            .filter(MapFilter::new(|x: f32| x))
+            .filter(failing)
+            .sink(NullSink::new())?
+            .run().err().unwrap();
+        assert_eq!(error.to_string(), "block 2: broken block");
+
+        let mismatch = scheduler.source(NullSource::new(48000, 1000))
+            .filter(RationalResampler::<f32>::new(44100, 8000, 31))
+            .sink(NullSink::new())
+            .err().unwrap();
+        assert_eq!((mismatch.index, mismatch.expected), (1, SampleRate(44100)));
+
+        // deterministic runs see whole blocks of a quarter of the capacity, whatever the timing
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&sizes);
+        let record = FnFilter::new(move |input: &[f32], output: &mut Vec<f32>| {
+            seen.lock().unwrap().push(input.len());
+            output.clear();
+            output.extend_from_slice(input);
+            Ok(())
+        });
+        scheduler.set_deterministic(true);
+        scheduler.source(NullSource::new(48000, 1000).limit(10_000))
+            .filter(record)
+            .sink(NullSink::new())?
+            .run()?;
+        let mut expected = vec![1024; 9];
+        expected.push(784);
+        assert_eq!(*sizes.lock().unwrap(), expected);
+        Ok(())
+    }
+
+
+    #[test]
+    fn test_scheduler_latency() -> Result<(), Box<dyn Error>> {
+        let mut scheduler = Scheduler::new(CancelToken::new());
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&reports);
+        scheduler.report_latency(Duration::ZERO, move |latency| seen.lock().unwrap().push(latency.clone()));
+        let mut graph = scheduler.source(NullSource::new(48000, 1000).limit(48000))
+            .filter(RationalResampler::<f32>::new(48000, 24000, 101))
+            .filter(FIRFilter::new(vec![0.05f32; 21]))
+            .sink(NullSink::new())?;
+        graph.run()?;
+
+        // 50 samples at 48 kHz and 10 at 24 kHz, reported while the chain ran
+        let reports = reports.lock().unwrap();
+        assert!(!reports.is_empty());
+        let last = reports.last().unwrap();
+        assert_eq!(last.blocks.len(), 4);
+        assert_eq!(last.blocks[1], Duration::from_secs_f64(50.0 / 48000.0));
+        assert_eq!(last.blocks[2], Duration::from_secs_f64(10.0 / 24000.0));
+        assert_eq!(graph.latency(), *last);
+        Ok(())
+    }
+}
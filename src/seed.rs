@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use num_complex::Complex32;
+
+
+/// Seed of the whole crate until `set_seed` changes it.
+pub const DEFAULT_SEED: u64 = 0x2545f491;
+
+static SEED: AtomicU64 = AtomicU64::new(DEFAULT_SEED);
+static DETERMINISTIC: AtomicBool = AtomicBool::new(false);
+/// How many `Rng::instance` streams each name has handed out since the seed was set.
+static INSTANCES: Mutex<BTreeMap<String, u32>> = Mutex::new(BTreeMap::new());
+
+
+/// Seed the noise and dither sources created from now on derive theirs from. With the same seed a
+/// simulation produces the same samples on every run and platform.
+pub fn set_seed(seed: u64) {
+    SEED.store(seed, Ordering::Relaxed);
+    INSTANCES.lock().unwrap().clear();
+}
+
+
+pub fn seed() -> u64 {
+    SEED.load(Ordering::Relaxed)
+}
+
+
+/// Makes graphs built from now on hand fixed size blocks between their threads instead of whatever
+/// is queued, so blocks whose output depends on the block boundaries see the same ones on every run.
+/// Costs a little latency.
+pub fn set_deterministic(deterministic: bool) {
+    DETERMINISTIC.store(deterministic, Ordering::Relaxed);
+}
+
+
+pub fn is_deterministic() -> bool {
+    DETERMINISTIC.load(Ordering::Relaxed)
+}
+
+
+/// splitmix64's finalizer, spreads neighbouring inputs over the whole range.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+
+/// xorshift32, the generator behind every noise and dither source. Integer only, so a seed gives
+/// the same sequence everywhere.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: u32,
+}
+
+
+impl Rng {
+    /// A zero seed, which would only ever give zeros, is replaced.
+    pub fn new(seed: u32) -> Self {
+        Self { state: if seed == 0 { DEFAULT_SEED as u32 } else { seed } }
+    }
+
+    /// The generator of the source called `name` under the crate wide seed. Every name draws its own
+    /// sequence, so adding a noise source doesn't change what the others produce.
+    pub fn stream(name: &str) -> Self {
+        // FNV-1a
+        let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        let mixed = mix(seed() ^ mix(hash));
+        Self::new((mixed ^ (mixed >> 32)) as u32)
+    }
+
+    /// Like `stream` for blocks that can have several instances, e.g. dither, which shouldn't be
+    /// correlated. Each call gets the next stream under `name`, so a simulation building its
+    /// blocks in the same order after `set_seed` gets the same ones again.
+    pub fn instance(name: &str) -> Self {
+        let mut instances = INSTANCES.lock().unwrap();
+        let count = instances.entry(name.to_string()).or_default();
+        let rng = Self::stream(&format!("{} {}", name, count));
+        *count += 1;
+        rng
+    }
+
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    /// Between 0 and 1.
+    pub fn uniform(&mut self) -> f32 {
+        self.next_u32() as f32 / u32::MAX as f32
+    }
+
+    /// Unit variance per component, Box-Muller.
+    pub fn gaussian(&mut self) -> Complex32 {
+        // kept off 0 for the logarithm
+        let u1 = (self.next_u32() as f32 + 1.0) / (u32::MAX as f32 + 2.0);
+        let u2 = (self.next_u32() as f32 + 1.0) / (u32::MAX as f32 + 2.0);
+        Complex32::from_polar((-2.0 * u1.ln()).sqrt(), 2.0 * PI * u2)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::seed::{seed, Rng, DEFAULT_SEED};
+
+    #[test]
+    fn test_rng_streams() {
+        // the classic xorshift32 sequence
+        let mut rng = Rng::new(1);
+        assert_eq!([rng.next_u32(), rng.next_u32(), rng.next_u32()], [270369, 67634689, 2647435461]);
+        assert_eq!(Rng::new(0), Rng::new(DEFAULT_SEED as u32));
+
+        // the global seed is left alone here, other tests run in parallel
+        assert_eq!(seed(), DEFAULT_SEED);
+        assert_eq!(Rng::stream("requantize"), Rng::stream("requantize"));
+        assert_ne!(Rng::stream("requantize"), Rng::stream("corpus"));
+        assert_ne!(Rng::instance("test instance"), Rng::instance("test instance"));
+
+        let mut rng = Rng::stream("test");
+        let samples: Vec<f32> = (0..100_000).map(|_| rng.uniform()).collect();
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+        let power = (0..100_000).map(|_| rng.gaussian().norm_sqr()).sum::<f32>() / 100_000.0;
+        assert!((power - 2.0).abs() < 0.05, "{}", power);
+    }
+}
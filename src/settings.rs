@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::demod::DemodMode;
+
+
+/// Defaults for one device, e.g. `[devices.hackrf]`. Unset fields fall back to the CLI defaults.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    pub lna_gain: Option<u32>,
+    pub vga_gain: Option<u32>,
+    pub amp: Option<bool>,
+    pub ppm: Option<f64>,
+    pub sample_rate: Option<u32>,
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// Output device name as listed by the audio host, the default device when unset.
+    pub output_device: Option<String>,
+    pub sample_rate: Option<u32>,
+}
+
+
+/// Where events from the scanner, the recorder and the decoders go besides the terminal.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventSettings {
+    /// MQTT broker to publish to, nothing is published when unset.
+    pub mqtt_host: Option<String>,
+    pub mqtt_port: Option<u16>,
+    pub topic_prefix: Option<String>,
+}
+
+
+/// What was tuned last, so a bare `rust_dsp` picks up where it left off.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LastTuned {
+    pub frequency: Option<u64>,
+    pub mode: Option<DemodMode>,
+}
+
+
+/// Persistent defaults stored as TOML in the user's config directory.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub devices: BTreeMap<String, DeviceSettings>,
+    pub audio: AudioSettings,
+    pub events: EventSettings,
+    pub last: LastTuned,
+    /// The file these defaults stand in for didn't load, `save_default` leaves it alone.
+    #[serde(skip)]
+    unreadable: bool,
+}
+
+
+impl Settings {
+    /// `rust_dsp/settings.toml` in the user's config directory, `$XDG_CONFIG_HOME` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rust_dsp").join("settings.toml"))
+    }
+
+    /// A missing file gives the defaults, a malformed one is an error.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load_default() -> Result<Self, Box<dyn Error>> {
+        Self::default_path().map_or(Ok(Self::default()), |path| Self::load(&path))
+    }
+
+    /// `load_default`, but a file that doesn't load is reported and the defaults used instead, so a
+    /// typo doesn't keep the radio from working. The file isn't overwritten then.
+    pub fn load_default_or_warn() -> Self {
+        Self::default_path().map_or(Self::default(), |path| Self::load_or_warn(&path))
+    }
+
+    fn load_or_warn(path: &Path) -> Self {
+        Self::load(path).unwrap_or_else(|e| {
+            eprintln!("settings not loaded, using the defaults: {}", e);
+            Self { unreadable: true, ..Self::default() }
+        })
+    }
+
+    pub fn save_default(&self) -> Result<(), Box<dyn Error>> {
+        if self.unreadable {
+            return Err("the settings file didn't load, not overwriting it".into());
+        }
+        self.save(&Self::default_path().ok_or("no config directory")?)
+    }
+
+    /// Settings for `device`, all unset if it has none.
+    pub fn device(&self, device: &str) -> DeviceSettings {
+        self.devices.get(device).cloned().unwrap_or_default()
+    }
+
+    pub fn device_mut(&mut self, device: &str) -> &mut DeviceSettings {
+        self.devices.entry(device.to_string()).or_default()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use crate::demod::DemodMode;
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_settings_roundtrip() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("rust_dsp_settings_{}.toml", std::process::id()));
+        assert_eq!(Settings::load(&path)?, Settings::default());
+
+        let mut settings = Settings::default();
+        settings.device_mut("hackrf").lna_gain = Some(24);
+        settings.device_mut("hackrf").ppm = Some(-1.5);
+        settings.audio.output_device = Some("USB Audio".into());
+        settings.last.frequency = Some(101_100_000);
+        settings.last.mode = Some(DemodMode::FM);
+        settings.save(&path)?;
+        let text = std::fs::read_to_string(&path)?;
+        assert!(text.contains("[devices.hackrf]"), "{}", text);
+        assert_eq!(Settings::load(&path)?, settings);
+        assert_eq!(settings.device("rtlsdr").lna_gain, None);
+
+        // hand edited files may leave out whole sections
+        std::fs::write(&path, "[last]\nfrequency = 7074000\nmode = \"USB\"\n")?;
+        let settings = Settings::load(&path)?;
+        assert_eq!(settings.last.mode, Some(DemodMode::USB));
+        assert!(settings.devices.is_empty());
+
+        std::fs::write(&path, "[last]\nfrequency = \"soon\"\n")?;
+        assert!(Settings::load(&path).is_err());
+
+        // commands run on the defaults instead, and the broken file isn't replaced by them
+        let settings = Settings::load_or_warn(&path);
+        assert_eq!(settings.last, Settings::default().last);
+        assert!(settings.save_default().is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::{Duration, Instant};
+
+
+/// How much of the worst stall is forgotten per observation, so a one-off hiccup doesn't keep the
+/// buffers large forever.
+const STALL_DECAY: f64 = 0.99;
+
+
+/// Picks stream buffer capacities and per-read chunk sizes from what a running pipeline actually
+/// does, instead of always buffering a second of samples. Feed it every chunk with `observe`.
+#[derive(Clone, Debug)]
+pub struct BufferSizer {
+    sample_rate: u32,
+    min: Duration,
+    max: Duration,
+    stall: Duration,
+    largest_chunk: usize,
+    samples: u64,
+    start: Option<Instant>,
+    released: Option<Instant>,
+}
+
+
+impl BufferSizer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            min: Duration::from_millis(10),
+            max: Duration::from_secs(1),
+            stall: Duration::ZERO,
+            largest_chunk: 0,
+            samples: 0,
+            start: None,
+            released: None,
+        }
+    }
+
+    /// Bounds on the time a buffer covers.
+    pub fn limits(mut self, min: Duration, max: Duration) -> Self {
+        self.min = min.min(max);
+        self.max = max;
+        self
+    }
+
+    /// One chunk of `samples` went through and the other side was busy for `busy` before it
+    /// came back, e.g. the time between a sink's writes.
+    pub fn observe(&mut self, samples: usize, busy: Duration) {
+        self.start.get_or_insert_with(Instant::now);
+        self.samples += samples as u64;
+        self.stall = self.stall.mul_f64(STALL_DECAY).max(busy);
+        self.largest_chunk = ((self.largest_chunk as f64 * STALL_DECAY) as usize).max(samples);
+    }
+
+    /// A block calls this when it returns to the pipeline, e.g. at the end of a sink's `write`.
+    pub fn release(&mut self) {
+        self.released = Some(Instant::now());
+    }
+
+    /// `observe` with the time the pipeline took since `release`.
+    pub fn observe_since_release(&mut self, samples: usize) {
+        let busy = self.released.map_or(Duration::ZERO, |released| released.elapsed());
+        self.observe(samples, busy);
+    }
+
+    /// The measured rate once there's enough to go on, otherwise the nominal one.
+    pub fn sample_rate(&self) -> f64 {
+        let elapsed = self.start.map_or(0.0, |start| start.elapsed().as_secs_f64());
+        if elapsed < 0.5 {
+            return self.sample_rate as f64;
+        }
+        (self.samples as f64 / elapsed).max(self.sample_rate as f64)
+    }
+
+    pub fn stall(&self) -> Duration {
+        self.stall
+    }
+
+    fn samples_for(&self, duration: Duration) -> usize {
+        ((self.sample_rate() * duration.as_secs_f64()).round() as usize).max(1)
+    }
+
+    /// Time each read covers, long enough that the worst stall fits in a quarter of it.
+    pub fn chunk_period(&self) -> Duration {
+        (self.stall * 4).clamp(self.min, self.max / 2)
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.samples_for(self.chunk_period())
+    }
+
+    /// Room for the largest chunk seen plus twice the worst stall.
+    pub fn capacity(&self) -> usize {
+        (self.largest_chunk + self.samples_for(self.stall * 2)).clamp(self.samples_for(self.min), self.samples_for(self.max))
+    }
+
+    /// The capacity to switch `current` to, if it's worth it. Growing happens right away, shrinking
+    /// only once the buffer is twice as large as needed.
+    pub fn resize(&self, current: usize) -> Option<usize> {
+        let capacity = self.capacity();
+        (capacity > current || capacity * 2 < current).then_some(capacity)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::sizing::BufferSizer;
+
+    #[test]
+    fn test_buffer_sizer() {
+        let mut sizer = BufferSizer::new(48_000).limits(Duration::from_millis(10), Duration::from_secs(1));
+        assert_eq!(sizer.chunk_size(), 480);
+        assert_eq!(sizer.resize(48_000), Some(480));
+
+        // a 50 ms stall calls for 200 ms chunks, and room for 100 ms on top of the chunk in flight
+        sizer.observe(480, Duration::from_millis(50));
+        assert_eq!(sizer.chunk_size(), 9600);
+        assert_eq!(sizer.capacity(), 5280);
+        assert_eq!(sizer.resize(480), Some(5280));
+        assert_eq!(sizer.resize(8000), None);
+
+        // the stall is forgotten slowly and the buffers shrink with it
+        for _ in 0..500 {
+            sizer.observe(480, Duration::from_millis(1));
+        }
+        assert_eq!(sizer.stall(), Duration::from_millis(1));
+        assert_eq!(sizer.resize(5280), Some(576));
+
+        // chunks larger than the computed capacity must still fit
+        sizer.observe(5000, Duration::ZERO);
+        assert!(sizer.capacity() >= 5000);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+
+/// S9 below 30 MHz, above it S9 is 20 dB lower.
+pub const S9_HF_DBM: f32 = -73.0;
+pub const S9_VHF_DBM: f32 = -93.0;
+pub const DB_PER_S_UNIT: f32 = 6.0;
+
+
+/// A known signal level at the antenna and the level the receiver measured for it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationPoint {
+    pub frequency_hz: f64,
+    pub dbfs: f32,
+    pub dbm: f32,
+}
+
+
+/// User supplied calibration points per device and total gain setting. Lookups interpolate the
+/// dBFS to dBm offset over frequency and fall back on the nearest gain setting, corrected by the gain difference.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CalibrationTable {
+    entries: BTreeMap<(String, u32), Vec<CalibrationPoint>>,
+}
+
+
+impl CalibrationTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `rust_dsp/smeter.csv` in the user's config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rust_dsp").join("smeter.csv"))
+    }
+
+    pub fn add(&mut self, device: &str, gain_db: u32, point: CalibrationPoint) {
+        let points = self.entries.entry((device.to_string(), gain_db)).or_default();
+        points.push(point);
+        points.sort_by(|a, b| a.frequency_hz.total_cmp(&b.frequency_hz));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn offset(points: &[CalibrationPoint], frequency_hz: f64) -> f32 {
+        let offset = |p: &CalibrationPoint| p.dbm - p.dbfs;
+        let after = points.partition_point(|p| p.frequency_hz < frequency_hz);
+        match (after.checked_sub(1).map(|i| &points[i]), points.get(after)) {
+            (Some(a), Some(b)) => {
+                let t = ((frequency_hz - a.frequency_hz) / (b.frequency_hz - a.frequency_hz)) as f32;
+                offset(a) + t * (offset(b) - offset(a))
+            },
+            (Some(p), None) | (None, Some(p)) => offset(p),
+            (None, None) => unreachable!("entries are never empty"),
+        }
+    }
+
+    /// Estimated level at the antenna, `None` without any calibration for `device`.
+    pub fn dbm(&self, device: &str, gain_db: u32, frequency_hz: f64, dbfs: f32) -> Option<f32> {
+        let ((_, calibrated_gain), points) = self.entries.iter()
+            .filter(|((name, _), _)| name == device)
+            .min_by_key(|((_, gain), _)| gain.abs_diff(gain_db))?;
+        // more gain raises the measured dBFS for the same input level
+        let correction = *calibrated_gain as f32 - gain_db as f32;
+        Some(dbfs + Self::offset(points, frequency_hz) + correction)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut text = String::from("device,gain_db,frequency_hz,dbfs,dbm\n");
+        for ((device, gain), points) in &self.entries {
+            for p in points {
+                text += &format!("{},{},{},{},{}\n", device, gain, p.frequency_hz, p.dbfs, p.dbm);
+            }
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut table = Self::new();
+        for (number, line) in std::fs::read_to_string(path)?.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [device, gain, frequency_hz, dbfs, dbm] = fields[..] else {
+                return Err(format!("{}:{}: expected 5 fields", path.display(), number + 1).into());
+            };
+            table.add(device, gain.parse()?, CalibrationPoint {
+                frequency_hz: frequency_hz.parse()?,
+                dbfs: dbfs.parse()?,
+                dbm: dbm.parse()?,
+            });
+        }
+        Ok(table)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SMeterReading {
+    pub dbfs: f32,
+    pub dbm: Option<f32>,
+    /// S-units from 0 to 9, then dB over S9.
+    pub s_units: Option<(u8, f32)>,
+}
+
+
+pub fn s_units(dbm: f32, frequency_hz: f64) -> (u8, f32) {
+    let s9 = if frequency_hz < 30e6 { S9_HF_DBM } else { S9_VHF_DBM };
+    if dbm >= s9 {
+        (9, dbm - s9)
+    } else {
+        let s = 9.0 + ((dbm - s9) / DB_PER_S_UNIT).floor();
+        (s.max(0.0) as u8, 0.0)
+    }
+}
+
+
+impl Display for SMeterReading {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.dbm, self.s_units) {
+            (Some(dbm), Some((9, over))) if over >= 1.0 => write!(f, "S9+{:.0} ({:.1} dBm)", over, dbm),
+            (Some(dbm), Some((s, _))) => write!(f, "S{} ({:.1} dBm)", s, dbm),
+            _ => write!(f, "{:.1} dBFS", self.dbfs),
+        }
+    }
+}
+
+
+/// Turns measured levels, e.g. `ProbeLevels::rms_db`, into calibrated readings for the current tuning.
+pub struct SMeter {
+    table: CalibrationTable,
+    device: String,
+    gain_db: u32,
+    frequency_hz: f64,
+}
+
+
+impl SMeter {
+    pub fn new(table: CalibrationTable, device: &str, gain_db: u32, frequency_hz: f64) -> Self {
+        Self {
+            table,
+            device: device.to_string(),
+            gain_db,
+            frequency_hz,
+        }
+    }
+
+    pub fn set_gain(&mut self, gain_db: u32) {
+        self.gain_db = gain_db;
+    }
+
+    pub fn set_frequency(&mut self, frequency_hz: f64) {
+        self.frequency_hz = frequency_hz;
+    }
+
+    pub fn reading(&self, dbfs: f32) -> SMeterReading {
+        let dbm = self.table.dbm(&self.device, self.gain_db, self.frequency_hz, dbfs);
+        SMeterReading {
+            dbfs,
+            dbm,
+            s_units: dbm.map(|dbm| s_units(dbm, self.frequency_hz)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::smeter::{s_units, CalibrationPoint, CalibrationTable, SMeter};
+
+    #[test]
+    fn test_smeter_calibration() -> Result<(), Box<dyn std::error::Error>> {
+        let mut table = CalibrationTable::new();
+        table.add("hackrf", 40, CalibrationPoint { frequency_hz: 100e6, dbfs: -30.0, dbm: -70.0 });
+        table.add("hackrf", 40, CalibrationPoint { frequency_hz: 400e6, dbfs: -36.0, dbm: -70.0 });
+
+        // offset is -40 dB at 100 MHz and -34 dB at 400 MHz
+        assert_eq!(table.dbm("hackrf", 40, 250e6, -50.0), Some(-87.0));
+        assert_eq!(table.dbm("hackrf", 40, 50e6, -50.0), Some(-90.0));
+        assert_eq!(table.dbm("hackrf", 30, 100e6, -50.0), Some(-80.0));
+        assert_eq!(table.dbm("rtlsdr", 40, 100e6, -50.0), None);
+
+        let path = std::env::temp_dir().join(format!("rust_dsp_smeter_{}.csv", std::process::id()));
+        table.save(&path)?;
+        assert_eq!(CalibrationTable::load(&path)?, table);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(s_units(-73.0, 7e6), (9, 0.0));
+        assert_eq!(s_units(-79.5, 7e6), (7, 0.0));
+        let meter = SMeter::new(table, "hackrf", 40, 100e6);
+        assert_eq!(meter.reading(-33.0).to_string(), "S9+20 (-73.0 dBm)");
+        assert_eq!(meter.reading(-60.0).to_string(), "S7 (-100.0 dBm)");
+        Ok(())
+    }
+
+}
@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, SystemTime};
+use num_complex::Complex32;
+use crate::gnuradio::GrMetaSink;
+use crate::rate::RateAware;
+use crate::recorder::utc_timestamp;
+use crate::traits::*;
+
+
+/// Handed to decoders so they can ask for the IQ around something worth a closer look.
+#[derive(Clone)]
+pub struct SnapshotTrigger {
+    requests: Sender<String>,
+}
+
+
+impl SnapshotTrigger {
+    /// `reason` ends up in the file name, e.g. `crc` or `unknown-type`.
+    pub fn request(&self, reason: &str) {
+        let _ = self.requests.send(reason.to_string());
+    }
+}
+
+
+struct Capture {
+    sink: GrMetaSink<BufWriter<File>>,
+    path: PathBuf,
+    remaining: usize,
+}
+
+
+/// Keeps the last `pre` of IQ in a ring and, when triggered, dumps it together with the following
+/// `post` to a GNU Radio metadata file in `dir`. Triggers during a capture extend it.
+pub struct IqSnapshot {
+    dir: PathBuf,
+    sample_rate: u32,
+    frequency: Option<f64>,
+    pre: usize,
+    post: usize,
+    ring: VecDeque<Complex32>,
+    sender: Sender<String>,
+    requests: Receiver<String>,
+    capture: Option<Capture>,
+    finished: Vec<PathBuf>,
+    count: usize,
+}
+
+
+impl IqSnapshot {
+    pub fn new(dir: PathBuf, sample_rate: u32, pre: Duration, post: Duration) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(&dir)?;
+        let (sender, requests) = channel();
+        let pre = (pre.as_secs_f64() * sample_rate as f64) as usize;
+        Ok(Self {
+            dir,
+            sample_rate,
+            frequency: None,
+            pre,
+            post: (post.as_secs_f64() * sample_rate as f64) as usize,
+            ring: VecDeque::with_capacity(pre),
+            sender,
+            requests,
+            capture: None,
+            finished: Vec::new(),
+            count: 0,
+        })
+    }
+
+    pub fn trigger(&self) -> SnapshotTrigger {
+        SnapshotTrigger {
+            requests: self.sender.clone(),
+        }
+    }
+
+    /// Stored as `rx_freq` in the snapshot headers.
+    pub fn set_frequency(&mut self, frequency: Option<f64>) {
+        self.frequency = frequency;
+    }
+
+    /// Snapshots completed since the last call.
+    pub fn take_finished(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.finished)
+    }
+
+    fn start(&mut self, reason: &str) -> Result<(), Box<dyn Error>> {
+        let now = SystemTime::now();
+        let start = now - Duration::from_secs_f64(self.ring.len() as f64 / self.sample_rate as f64);
+        let reason: String = reason.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        let path = self.dir.join(format!("{}_{:04}_{}.dat", utc_timestamp(now), self.count, reason));
+        self.count += 1;
+
+        let mut sink = GrMetaSink::create::<Complex32>(path.clone(), self.sample_rate as f64, self.frequency, start)?;
+        let (a, b) = self.ring.as_slices();
+        sink.write(a)?;
+        sink.write(b)?;
+        self.capture = Some(Capture {
+            sink,
+            path,
+            remaining: self.post,
+        });
+        Ok(())
+    }
+}
+
+
+impl Sink<Complex32> for IqSnapshot {
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        while let Ok(reason) = self.requests.try_recv() {
+            match self.capture.as_mut() {
+                Some(capture) => capture.remaining = self.post,
+                None => self.start(&reason)?,
+            }
+        }
+
+        if let Some(capture) = self.capture.as_mut() {
+            let n = capture.remaining.min(src.len());
+            capture.sink.write(&src[..n])?;
+            capture.remaining -= n;
+            if capture.remaining == 0 {
+                let mut capture = self.capture.take().unwrap();
+                capture.sink.finish()?;
+                self.finished.push(capture.path);
+            }
+        }
+
+        let keep = src.len().min(self.pre);
+        let excess = (self.ring.len() + keep).saturating_sub(self.pre);
+        self.ring.drain(..excess);
+        self.ring.extend(&src[src.len() - keep..]);
+        Ok(())
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for IqSnapshot {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        self.write(input)?;
+        output.clear();
+        output.extend_from_slice(input);
+        Ok(())
+    }
+}
+
+
+impl RateAware for IqSnapshot {}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use num_complex::Complex32;
+    use crate::gnuradio::GrMetaSource;
+    use crate::snapshot::IqSnapshot;
+    use crate::traits::{Sink, Source};
+
+    #[test]
+    fn test_iq_snapshot() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join("rust_dsp_test_snapshot");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut snapshot = IqSnapshot::new(dir.clone(), 1000, Duration::from_millis(150), Duration::from_millis(100))?;
+        snapshot.set_frequency(Some(433.92e6));
+        let trigger = snapshot.trigger();
+
+        let block = |n: usize| (n * 50..n * 50 + 50).map(|i| Complex32::new(i as f32, 0.0)).collect::<Vec<_>>();
+        for n in 0..10 {
+            if n == 6 {
+                trigger.request("crc");
+            }
+            snapshot.write(&block(n))?;
+        }
+
+        let files = snapshot.take_finished();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_string_lossy().ends_with("_0000_crc.dat"));
+
+        let mut source = GrMetaSource::open(files[0].clone(), 1000)?;
+        let mut samples: Vec<Complex32> = Vec::new();
+        source.read(&mut samples)?;
+        assert_eq!(source.segment().and_then(|s| s.frequency()), Some(433.92e6));
+        // 150 samples of pre-roll before the trigger at sample 300, then 100 after
+        assert_eq!(samples, (150..400).map(|i| Complex32::new(i as f32, 0.0)).collect::<Vec<_>>());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+}
@@ -0,0 +1,225 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+
+/// Where to cut a recording into pieces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitLimit {
+    Duration(Duration),
+    /// Payload bytes per piece, rounded down to whole frames.
+    Bytes(u64),
+}
+
+
+impl SplitLimit {
+    /// `30s`, `10m`, `1h` for durations, `512K`, `100M`, `2G` for sizes.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let (number, unit) = text.split_at(text.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(text.len()));
+        let number: f64 = number.parse()?;
+        Ok(match unit {
+            "s" => SplitLimit::Duration(Duration::from_secs_f64(number)),
+            "m" => SplitLimit::Duration(Duration::from_secs_f64(number * 60.0)),
+            "h" => SplitLimit::Duration(Duration::from_secs_f64(number * 3600.0)),
+            "" | "B" => SplitLimit::Bytes(number as u64),
+            "K" => SplitLimit::Bytes((number * 1024.0) as u64),
+            "M" => SplitLimit::Bytes((number * 1024.0 * 1024.0) as u64),
+            "G" => SplitLimit::Bytes((number * 1024.0 * 1024.0 * 1024.0) as u64),
+            _ => return Err(format!("unknown split unit {:?}", unit).into()),
+        })
+    }
+
+    fn frames(&self, sample_rate: u32, frame_size: usize) -> Result<u64, Box<dyn Error>> {
+        let frames = match *self {
+            SplitLimit::Duration(duration) => (duration.as_secs_f64() * sample_rate as f64) as u64,
+            SplitLimit::Bytes(bytes) => bytes / frame_size as u64,
+        };
+        if frames == 0 {
+            return Err("split limit is smaller than one frame".into());
+        }
+        Ok(frames)
+    }
+}
+
+
+/// `dir/stem_000.ext`, `dir/stem_001.ext`, ...
+fn piece_path(path: &Path, dir: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(ext) => dir.join(format!("{}_{:03}.{}", stem, index, ext.to_string_lossy())),
+        None => dir.join(format!("{}_{:03}", stem, index)),
+    }
+}
+
+
+fn copy_samples<R: Read, W: Write + Seek>(reader: &mut WavReader<R>, writer: &mut WavWriter<W>, samples: u64) -> Result<u64, Box<dyn Error>> {
+    let mut copied = 0;
+    match reader.spec().sample_format {
+        SampleFormat::Float => for sample in reader.samples::<f32>().take(samples as usize) {
+            writer.write_sample(sample?)?;
+            copied += 1;
+        },
+        SampleFormat::Int => for sample in reader.samples::<i32>().take(samples as usize) {
+            writer.write_sample(sample?)?;
+            copied += 1;
+        },
+    }
+    Ok(copied)
+}
+
+
+/// Split a WAV recording into pieces in `dir`, every piece keeps the spec of the original.
+pub fn split_wav(path: &Path, dir: &Path, limit: SplitLimit) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    let frame_size = spec.channels as usize * spec.bits_per_sample.div_ceil(8) as usize;
+    let samples_per_piece = limit.frames(spec.sample_rate, frame_size)? * spec.channels as u64;
+
+    std::fs::create_dir_all(dir)?;
+    let mut remaining = reader.len() as u64;
+    let mut pieces = Vec::new();
+    while remaining > 0 {
+        let piece = piece_path(path, dir, pieces.len());
+        let mut writer = WavWriter::create(&piece, spec)?;
+        let copied = copy_samples(&mut reader, &mut writer, samples_per_piece.min(remaining))?;
+        writer.finalize()?;
+        pieces.push(piece);
+        if copied == 0 {
+            break;
+        }
+        remaining -= copied;
+    }
+    Ok(pieces)
+}
+
+
+/// Join WAV recordings with identical specs, returns the number of frames written.
+pub fn concat_wav(inputs: &[PathBuf], output: &Path) -> Result<u64, Box<dyn Error>> {
+    let first = inputs.first().ok_or("nothing to concatenate")?;
+    let spec: WavSpec = WavReader::open(first)?.spec();
+    let mut writer = WavWriter::create(output, spec)?;
+    let mut samples = 0;
+    for input in inputs {
+        let mut reader = WavReader::open(input)?;
+        if reader.spec() != spec {
+            return Err(format!("{}: {:?} does not match {:?}", input.display(), reader.spec(), spec).into());
+        }
+        let len = reader.len() as u64;
+        samples += copy_samples(&mut reader, &mut writer, len)?;
+    }
+    writer.finalize()?;
+    Ok(samples / spec.channels as u64)
+}
+
+
+/// Bytes per complex sample of headerless IQ recordings, from the usual file extensions.
+pub fn raw_frame_size(path: &Path) -> Option<usize> {
+    match path.extension()?.to_str()? {
+        "cu8" | "cs8" => Some(2),
+        "cs12" => Some(3),
+        "cs16" | "cf16" => Some(4),
+        "cf32" | "cfile" => Some(8),
+        "cf64" => Some(16),
+        _ => None,
+    }
+}
+
+
+/// Split headerless IQ, `sample_rate` is only needed for duration limits.
+pub fn split_raw(path: &Path, dir: &Path, frame_size: usize, sample_rate: Option<u32>, limit: SplitLimit) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let sample_rate = match (limit, sample_rate) {
+        (SplitLimit::Duration(_), None) => return Err("splitting headerless IQ by time needs the sample rate".into()),
+        (_, rate) => rate.unwrap_or(0),
+    };
+    let bytes_per_piece = limit.frames(sample_rate, frame_size)? * frame_size as u64;
+
+    std::fs::create_dir_all(dir)?;
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut pieces = Vec::new();
+    loop {
+        let piece = piece_path(path, dir, pieces.len());
+        let mut writer = BufWriter::new(File::create(&piece)?);
+        let copied = std::io::copy(&mut reader.by_ref().take(bytes_per_piece), &mut writer)?;
+        writer.flush()?;
+        if copied == 0 {
+            drop(writer);
+            std::fs::remove_file(&piece)?;
+            break;
+        }
+        pieces.push(piece);
+    }
+    Ok(pieces)
+}
+
+
+/// Join headerless IQ files, every input has to hold whole frames. Returns the number of frames written.
+pub fn concat_raw(inputs: &[PathBuf], output: &Path, frame_size: usize) -> Result<u64, Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(output)?);
+    let mut bytes = 0;
+    for input in inputs {
+        if raw_frame_size(input).is_some_and(|size| size != frame_size) {
+            return Err(format!("{}: sample format does not match", input.display()).into());
+        }
+        let len = std::fs::metadata(input)?.len();
+        if !len.is_multiple_of(frame_size as u64) {
+            return Err(format!("{}: {} bytes is not a whole number of {} byte frames", input.display(), len, frame_size).into());
+        }
+        bytes += std::io::copy(&mut BufReader::new(File::open(input)?), &mut writer)?;
+    }
+    writer.flush()?;
+    Ok(bytes / frame_size as u64)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::time::Duration;
+    use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+    use crate::split::{concat_raw, concat_wav, split_raw, split_wav, SplitLimit};
+
+    #[test]
+    fn test_split_concat() -> Result<(), Box<dyn Error>> {
+        assert_eq!(SplitLimit::parse("10m")?, SplitLimit::Duration(Duration::from_secs(600)));
+        assert_eq!(SplitLimit::parse("2K")?, SplitLimit::Bytes(2048));
+
+        let dir = std::env::temp_dir().join("rust_dsp_test_split");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+
+        let spec = WavSpec { channels: 2, sample_rate: 1000, bits_per_sample: 16, sample_format: SampleFormat::Int };
+        let path = dir.join("capture.wav");
+        let mut writer = WavWriter::create(&path, spec)?;
+        for i in 0..5000 {
+            writer.write_sample(i as i16)?;
+        }
+        writer.finalize()?;
+
+        let pieces = split_wav(&path, &dir.join("pieces"), SplitLimit::Duration(Duration::from_secs(1)))?;
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(WavReader::open(&pieces[2])?.duration(), 500);
+        assert_eq!(concat_wav(&pieces, &dir.join("joined.wav"))?, 2500);
+        let joined: Vec<i16> = WavReader::open(dir.join("joined.wav"))?.samples().collect::<Result<_, _>>()?;
+        assert_eq!(joined, (0..5000).map(|i| i as i16).collect::<Vec<_>>());
+
+        let other = dir.join("mono.wav");
+        WavWriter::create(&other, WavSpec { channels: 1, ..spec })?.finalize()?;
+        assert!(concat_wav(&[path.clone(), other], &dir.join("bad.wav")).is_err());
+
+        let raw = dir.join("capture.cs8");
+        std::fs::write(&raw, (0..=255).collect::<Vec<u8>>())?;
+        let pieces = split_raw(&raw, &dir.join("raw"), 2, None, SplitLimit::Bytes(101))?;
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(std::fs::metadata(&pieces[0])?.len(), 100);
+        assert!(split_raw(&raw, &dir, 2, None, SplitLimit::Duration(Duration::from_secs(1))).is_err());
+        assert_eq!(concat_raw(&pieces, &dir.join("joined.cs8"), 2)?, 128);
+        assert_eq!(std::fs::read(dir.join("joined.cs8"))?, std::fs::read(&raw)?);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+}
@@ -0,0 +1,233 @@
+use std::error::Error;
+use std::f32::consts::PI;
+use num_complex::Complex32;
+use crate::fft::FFT;
+use crate::rate::RateAware;
+use crate::traits::*;
+
+
+/// Bins on each side of a spur that get nulled, the Hann window spreads a tone over three.
+const NOTCH_HALF_WIDTH: usize = 1;
+/// Neighbours on each side a bin is compared against when looking for spurs.
+const NEIGHBOURS: usize = 8;
+
+
+/// Nulls DC and narrow spurs in the FFT domain, using 50% overlapped Hann frames added back together.
+/// Spurs come from a list of known frequencies and optionally from bins that stay well above their
+/// neighbours in the long term average. Delays the stream by `fft_size / 2` samples.
+pub struct SpurNotch {
+    sample_rate: u32,
+    fft: FFT,
+    window: Vec<f32>,
+    notch_dc: bool,
+    spurs: Vec<f32>,
+    auto_threshold_db: Option<f32>,
+    average: Vec<f32>,
+    detected: Vec<usize>,
+    mask: Vec<f32>,
+    input: Vec<Complex32>,
+    overlap: Vec<Complex32>,
+    frame: Vec<Complex32>,
+}
+
+
+impl SpurNotch {
+    pub fn new(sample_rate: u32, fft_size: usize) -> Self {
+        let hop = fft_size / 2;
+        let mut notch = Self {
+            sample_rate,
+            fft: FFT::new(fft_size),
+            // periodic Hann, 50% overlapped copies add up to exactly one
+            window: (0..fft_size).map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / fft_size as f32).cos()).collect(),
+            notch_dc: true,
+            spurs: Vec::new(),
+            auto_threshold_db: None,
+            average: vec![0.0; fft_size],
+            detected: Vec::new(),
+            mask: vec![1.0; fft_size],
+            input: vec![Complex32::new(0.0, 0.0); hop],
+            overlap: vec![Complex32::new(0.0, 0.0); fft_size],
+            frame: Vec::with_capacity(fft_size),
+        };
+        notch.update_mask();
+        notch
+    }
+
+    pub fn set_notch_dc(&mut self, notch_dc: bool) {
+        self.notch_dc = notch_dc;
+        self.update_mask();
+    }
+
+    /// Offsets from the center frequency in Hz.
+    pub fn set_spurs(&mut self, spurs: &[f32]) {
+        self.spurs = spurs.to_vec();
+        self.update_mask();
+    }
+
+    /// Also null bins whose average power is `threshold_db` above the median of their neighbours.
+    pub fn set_auto_detect(&mut self, threshold_db: Option<f32>) {
+        self.auto_threshold_db = threshold_db;
+        self.detected.clear();
+        self.update_mask();
+    }
+
+    /// Offsets in Hz of the spurs found by auto detection.
+    pub fn detected(&self) -> Vec<f32> {
+        self.detected.iter().map(|&bin| self.bin_frequency(bin)).collect()
+    }
+
+    fn bin(&self, frequency: f32) -> usize {
+        let size = self.fft.size() as isize;
+        let bin = (frequency / self.sample_rate as f32 * size as f32).round() as isize;
+        bin.rem_euclid(size) as usize
+    }
+
+    fn bin_frequency(&self, bin: usize) -> f32 {
+        let size = self.fft.size();
+        let signed = if bin >= size / 2 { bin as f32 - size as f32 } else { bin as f32 };
+        signed * self.sample_rate as f32 / size as f32
+    }
+
+    fn update_mask(&mut self) {
+        let size = self.fft.size();
+        let mut bins: Vec<usize> = self.spurs.iter().map(|&f| self.bin(f)).collect();
+        bins.extend(&self.detected);
+        if self.notch_dc {
+            bins.push(0);
+        }
+        self.mask.fill(1.0);
+        for bin in bins {
+            for k in 0..=2 * NOTCH_HALF_WIDTH {
+                self.mask[(bin + size + k - NOTCH_HALF_WIDTH) % size] = 0.0;
+            }
+        }
+    }
+
+    fn detect(&mut self) {
+        let Some(threshold_db) = self.auto_threshold_db else {
+            return;
+        };
+        let size = self.fft.size();
+        let ratio = 10f32.powf(threshold_db / 10.0);
+        let mut neighbours = Vec::with_capacity(2 * NEIGHBOURS);
+        let mut detected = Vec::new();
+        for bin in 0..size {
+            neighbours.clear();
+            neighbours.extend((1..=NEIGHBOURS).flat_map(|d| [self.average[(bin + d) % size], self.average[(bin + size - d) % size]]));
+            neighbours.sort_by(f32::total_cmp);
+            let median = neighbours[NEIGHBOURS];
+            // only the peak, the window leaks a spur into the bins next to it
+            let peak = self.average[bin] >= self.average[(bin + 1) % size] && self.average[bin] >= self.average[(bin + size - 1) % size];
+            if peak && self.average[bin] > median * ratio && self.average[bin] > 0.0 {
+                detected.push(bin);
+            }
+        }
+        if detected != self.detected {
+            self.detected = detected;
+            self.update_mask();
+        }
+    }
+
+    fn process_frame(&mut self) {
+        let size = self.fft.size();
+        self.frame.clear();
+        self.frame.extend(self.input[..size].iter().zip(&self.window).map(|(x, w)| x * w));
+        self.fft.forward(&mut self.frame);
+
+        if self.auto_threshold_db.is_some() {
+            // slow average so only persistent spikes count, bursts come and go
+            for (avg, x) in self.average.iter_mut().zip(&self.frame) {
+                *avg += (x.norm_sqr() - *avg) * 0.02;
+            }
+            self.detect();
+        }
+
+        for (x, m) in self.frame.iter_mut().zip(&self.mask) {
+            *x *= m;
+        }
+        self.fft.inverse(&mut self.frame);
+        for (acc, x) in self.overlap.iter_mut().zip(&self.frame) {
+            *acc += x;
+        }
+    }
+}
+
+
+impl Filter<Complex32, Complex32> for SpurNotch {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        let size = self.fft.size();
+        let hop = size / 2;
+        self.input.extend_from_slice(input);
+        while self.input.len() >= size {
+            self.process_frame();
+            output.extend_from_slice(&self.overlap[..hop]);
+            self.overlap.copy_within(hop.., 0);
+            self.overlap[hop..].fill(Complex32::new(0.0, 0.0));
+            self.input.drain(..hop);
+        }
+        Ok(())
+    }
+}
+
+
+impl RateAware for SpurNotch {}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::spur::SpurNotch;
+    use crate::traits::Filter;
+
+    fn level(samples: &[Complex32], frequency: f32, sample_rate: f32) -> f32 {
+        let sum: Complex32 = samples.iter().enumerate()
+            .map(|(n, x)| x * Complex32::from_polar(1.0, -2.0 * PI * frequency * n as f32 / sample_rate))
+            .sum();
+        sum.norm() / samples.len() as f32
+    }
+
+    #[test]
+    fn test_spur_notch() -> Result<(), Box<dyn Error>> {
+        let fs = 1_000_000.0;
+        // DC offset, a spur at +125 kHz and the wanted signal at -200 kHz
+        let input: Vec<Complex32> = (0..65536).map(|n| {
+            let t = n as f32 / fs;
+            Complex32::new(0.2, 0.1)
+                + Complex32::from_polar(0.5, 2.0 * PI * 125e3 * t)
+                + Complex32::from_polar(0.3, 2.0 * PI * -200e3 * t)
+        }).collect();
+
+        let mut notch = SpurNotch::new(1_000_000, 1024);
+        notch.set_spurs(&[125e3]);
+        let mut output = Vec::new();
+        notch.filter(&input, &mut output)?;
+        assert_eq!(output.len(), 65536);
+        let tail = &output[8192..];
+        assert!(level(tail, 0.0, fs) < 1e-3);
+        assert!(level(tail, 125e3, fs) < 1e-3);
+        assert!((level(tail, -200e3, fs) - 0.3).abs() < 0.01);
+
+        // without the list, auto detection finds the spur sticking out of a noise-like signal
+        let mut state = 0x9e37_79b9u32;
+        let mut rand = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        };
+        let input: Vec<Complex32> = (0..65536)
+            .map(|n| Complex32::new(rand(), rand()) + Complex32::from_polar(0.1, 2.0 * PI * 125e3 * n as f32 / fs))
+            .collect();
+        let mut notch = SpurNotch::new(1_000_000, 1024);
+        notch.set_notch_dc(false);
+        notch.set_auto_detect(Some(10.0));
+        notch.filter(&input, &mut output)?;
+        assert_eq!(notch.detected(), [125e3]);
+        assert!(level(&output[16384..], 125e3, fs) < 5e-3);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,288 @@
+use std::error::Error;
+use num_traits::Zero;
+use crate::rate::{RateAware, SampleRate};
+use crate::replay::Recordable;
+use crate::traits::Filter;
+
+
+const MAGIC: &[u8; 8] = b"RDSPSTA1";
+
+
+/// Little endian encoder for block state.
+#[derive(Default)]
+pub struct StateWriter {
+    buffer: Vec<u8>,
+}
+
+
+impl StateWriter {
+    pub fn put<T: Recordable>(&mut self, value: T) {
+        value.write_le(&mut self.buffer);
+    }
+
+    pub fn put_usize(&mut self, value: usize) {
+        self.put(value as u64);
+    }
+
+    /// Length prefixed so a mismatched block configuration is caught on restore.
+    pub fn put_slice<T: Recordable>(&mut self, values: &[T]) {
+        self.put_usize(values.len());
+        for value in values {
+            value.write_le(&mut self.buffer);
+        }
+    }
+
+    pub fn put_option<T: Recordable>(&mut self, value: Option<T>) {
+        match value {
+            Some(value) => {
+                self.put(1u8);
+                self.put(value);
+            },
+            None => self.put(0u8),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+
+pub struct StateReader<'a> {
+    src: &'a [u8],
+}
+
+
+impl<'a> StateReader<'a> {
+    pub fn new(src: &'a [u8]) -> Self {
+        Self { src }
+    }
+
+    pub fn get<T: Recordable>(&mut self) -> Result<T, Box<dyn Error>> {
+        if self.src.len() < T::SIZE {
+            return Err("state snapshot is truncated".into());
+        }
+        let (head, tail) = self.src.split_at(T::SIZE);
+        self.src = tail;
+        Ok(T::read_le(head))
+    }
+
+    pub fn get_usize(&mut self) -> Result<usize, Box<dyn Error>> {
+        Ok(self.get::<u64>()? as usize)
+    }
+
+    /// Read a slice written by `put_slice` into `dst`, which must already have the same length.
+    pub fn get_slice<T: Recordable>(&mut self, dst: &mut [T]) -> Result<(), Box<dyn Error>> {
+        let len = self.get_usize()?;
+        if len != dst.len() {
+            return Err(format!("state snapshot holds {} values, block expects {}", len, dst.len()).into());
+        }
+        for value in dst.iter_mut() {
+            *value = self.get()?;
+        }
+        Ok(())
+    }
+
+    pub fn get_option<T: Recordable>(&mut self) -> Result<Option<T>, Box<dyn Error>> {
+        match self.get::<u8>()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.get()?)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.src.is_empty()
+    }
+}
+
+
+/// Blocks whose mutable state (histories, oscillator phases, loop gains) can be checkpointed.
+/// Only the state is saved, restoring expects a block built with the same configuration.
+pub trait Stateful {
+    fn save_state(&self, writer: &mut StateWriter);
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Box<dyn Error>>;
+}
+
+
+/// Checkpoint a whole pipeline, blocks in pipeline order.
+pub fn snapshot(blocks: &[&dyn Stateful]) -> Vec<u8> {
+    let mut writer = StateWriter::default();
+    writer.buffer.extend_from_slice(MAGIC);
+    writer.put_usize(blocks.len());
+    for block in blocks {
+        block.save_state(&mut writer);
+    }
+    writer.into_bytes()
+}
+
+
+/// Restore a checkpoint made by `snapshot` into the same blocks in the same order.
+pub fn restore(blocks: &mut [&mut dyn Stateful], src: &[u8]) -> Result<(), Box<dyn Error>> {
+    if !src.starts_with(MAGIC) {
+        return Err("not a state snapshot".into());
+    }
+    let mut reader = StateReader::new(&src[MAGIC.len()..]);
+    let count = reader.get_usize()?;
+    if count != blocks.len() {
+        return Err(format!("state snapshot holds {} blocks, expected {}", count, blocks.len()).into());
+    }
+    for block in blocks.iter_mut() {
+        block.load_state(&mut reader)?;
+    }
+    if !reader.is_empty() {
+        return Err("trailing data in state snapshot".into());
+    }
+    Ok(())
+}
+
+
+/// Filters whose history can be pre-filled so the first output doesn't start from silence.
+pub trait Prime<T> {
+    /// Set the history as if `preamble` had just been filtered, oldest sample first.
+    /// A preamble shorter than the history is extended backwards with its first sample.
+    fn prime(&mut self, preamble: &[T]);
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Primer<T> {
+    Zeros,
+    /// Fill the history with the first sample, which removes the start up thump for a DC offset.
+    FirstSample,
+    Preamble(Vec<T>),
+}
+
+
+/// Primes the wrapped filter right before its first block, when the first sample is known.
+pub struct WarmStart<F, T> {
+    inner: F,
+    primer: Option<Primer<T>>,
+}
+
+
+impl<F, T> WarmStart<F, T> {
+    pub fn new(inner: F, primer: Primer<T>) -> Self {
+        Self {
+            inner,
+            primer: Some(primer),
+        }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+
+impl<F: Prime<I> + Filter<I, O>, I: Copy + Zero, O> Filter<I, O> for WarmStart<F, I> {
+    fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> Result<(), Box<dyn Error>> {
+        if let Some(&first) = input.first() {
+            match self.primer.take() {
+                Some(Primer::Zeros) => self.inner.prime(&[I::zero()]),
+                Some(Primer::FirstSample) => self.inner.prime(&[first]),
+                Some(Primer::Preamble(preamble)) => self.inner.prime(&preamble),
+                None => {},
+            }
+        }
+        self.inner.filter(input, output)
+    }
+}
+
+
+impl<F: RateAware, T> RateAware for WarmStart<F, T> {
+    fn input_rate(&self) -> Option<SampleRate> {
+        self.inner.input_rate()
+    }
+
+    fn output_rate(&self, input: Option<SampleRate>) -> Option<SampleRate> {
+        self.inner.output_rate(input)
+    }
+
+    fn delay(&self) -> f64 {
+        self.inner.delay()
+    }
+}
+
+
+/// `preamble` stretched or cut to the last `len` samples, oldest first.
+pub(crate) fn primed_history<T: Copy + Zero>(preamble: &[T], len: usize) -> impl Iterator<Item = T> + '_ {
+    let pad = len.saturating_sub(preamble.len());
+    let fill = preamble.first().copied().unwrap_or_else(T::zero);
+    std::iter::repeat_n(fill, pad).chain(preamble[preamble.len().saturating_sub(len)..].iter().copied())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex32;
+    use crate::block::{DeEmphasisFilter, FIRFilter, FMDemod, MixerFilter, RationalResampler};
+    use crate::util::lowpass_taps;
+    use crate::modem::AGC;
+    use crate::response::step_response;
+    use crate::state::{restore, snapshot, Primer, WarmStart};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_snapshot_restore() -> Result<(), Box<dyn std::error::Error>> {
+        let input: Vec<Complex32> = (0..4000).map(|n| Complex32::from_polar(0.3 + 0.001 * (n % 100) as f32, 0.05 * n as f32)).collect();
+        let (head, tail) = input.split_at(1500);
+
+        let new = || (
+            MixerFilter::new(48000, 3000.0),
+            FIRFilter::new(vec![Complex32::new(0.2, 0.0); 5]),
+            AGC::new(0.01, 1.0),
+            FMDemod::new(48000, 5000.0),
+            RationalResampler::<f32>::new(48000, 44100, 65),
+        );
+        let run = |blocks: &mut (MixerFilter, FIRFilter<Complex32>, AGC, FMDemod, RationalResampler<f32>), input: &[Complex32]| -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+            let (mut a, mut b, mut c, mut d) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+            Filter::<Complex32, Complex32>::filter(&mut blocks.0, input, &mut a)?;
+            blocks.1.filter(&a, &mut b)?;
+            blocks.2.filter(&b, &mut a)?;
+            blocks.3.filter(&a, &mut c)?;
+            blocks.4.filter(&c, &mut d)?;
+            Ok(d)
+        };
+
+        let mut original = new();
+        run(&mut original, head)?;
+        let state = snapshot(&[&original.0, &original.1, &original.2, &original.3, &original.4]);
+        let expected = run(&mut original, tail)?;
+
+        let mut resumed = new();
+        restore(&mut [&mut resumed.0, &mut resumed.1, &mut resumed.2, &mut resumed.3, &mut resumed.4], &state)?;
+        assert_eq!(run(&mut resumed, tail)?, expected);
+
+        let mut wrong = (MixerFilter::new(48000, 3000.0), FIRFilter::new(vec![Complex32::new(0.2, 0.0); 7]));
+        assert!(restore(&mut [&mut wrong.0, &mut wrong.1], &state).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_warm_start() -> Result<(), Box<dyn std::error::Error>> {
+        // a dc offset at the start makes an unprimed filter ramp up from silence
+        let taps: Vec<f32> = lowpass_taps(0.1, 31);
+        let gain: f32 = taps.iter().sum();
+
+        let cold = step_response(&mut FIRFilter::new(taps.clone()), 200)?;
+        assert!(cold[0].abs() < 0.1 * gain);
+        assert!((cold[199] - gain).abs() < 1e-5);
+
+        let warm = step_response(&mut WarmStart::new(FIRFilter::new(taps), Primer::FirstSample), 200)?;
+        assert!(warm.iter().all(|y| (y - gain).abs() < 1e-5));
+
+        let mut resampler = WarmStart::new(RationalResampler::<f32>::new(48000, 16000, 61), Primer::Preamble(vec![1.0; 10]));
+        let warm = step_response(&mut resampler, 200)?;
+        let gain = warm[warm.len() - 1];
+        assert!(warm.iter().all(|y| (y - gain).abs() < 1e-4));
+
+        let warm = step_response(&mut WarmStart::new(DeEmphasisFilter::new(48000, 75e-6), Primer::FirstSample), 200)?;
+        assert!(warm.iter().all(|y| (y - 1.0).abs() < 1e-6));
+
+        let cold = step_response(&mut WarmStart::new(DeEmphasisFilter::new(48000, 75e-6), Primer::Zeros), 200)?;
+        assert!(cold[0] < 0.5);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,531 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use num_complex::Complex32;
+use crate::block::{DeEmphasisFilter, FIRFilter, FractionalResampler, RationalResampler, RationalResamplerBuilder};
+use crate::error::ConfigError;
+use crate::ppm::FM_PILOT_HZ;
+use crate::probe::{ProbeReader, SignalProbe};
+use crate::rate::{RateAware, SampleRate};
+use crate::timing::ClockMismatch;
+use crate::traits::*;
+use crate::util::{bandpass_complex_taps, lowpass_taps};
+
+
+/// Pilot tracking state, published by a `StereoDecoder` through a `SignalProbe`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PilotLock {
+    pub locked: bool,
+    /// Pilot amplitude in the MPX signal.
+    pub level: f32,
+    /// Pilot frequency minus 19 kHz as tracked by the loop.
+    pub offset_hz: f64,
+}
+
+
+impl Magnitude for PilotLock {
+    fn magnitude_sqr(self) -> f32 {
+        self.level * self.level
+    }
+}
+
+
+/// How the 38 kHz subcarrier reference is recovered from the 19 kHz pilot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PilotRecovery {
+    /// Double a PLL locked to the pilot, holds up on weak signals.
+    #[default]
+    Pll,
+    /// Double the phase of the band-passed pilot, which lets the noise around the pilot through.
+    OpenLoop,
+}
+
+
+/// Locks a PLL to the 19 kHz stereo pilot of an FM MPX signal and outputs the 38 kHz subcarrier
+/// reference as `sin(2θ)` of the locked pilot phase, one per input sample. Doubling a tracked pilot
+/// keeps the reference clean on weak signals where mixing with the noisy pilot itself would not.
+pub struct PilotPll {
+    sample_rate: u32,
+    phase: f64,
+    omega: f64,
+    nominal: f64,
+    max_offset: f64,
+    alpha: f64,
+    beta: f64,
+    in_phase: f32,
+    quadrature: f32,
+    smoothing: f32,
+    threshold: f32,
+    lock: PilotLock,
+}
+
+
+impl PilotPll {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut pll = Self {
+            sample_rate,
+            phase: 0.0,
+            omega: 0.0,
+            nominal: 2.0 * PI * FM_PILOT_HZ / sample_rate as f64,
+            max_offset: 2.0 * PI * 50.0 / sample_rate as f64,
+            alpha: 0.0,
+            beta: 0.0,
+            in_phase: 0.0,
+            quadrature: 0.0,
+            // about 10 ms to settle the lock detector
+            smoothing: 1.0 - (-100.0 / sample_rate as f32).exp(),
+            threshold: 0.01,
+            lock: PilotLock::default(),
+        };
+        pll.omega = pll.nominal;
+        pll.set_bandwidth(20.0);
+        pll
+    }
+
+    /// Loop bandwidth in Hz, critically damped. Narrower rejects more noise but pulls in slower.
+    pub fn set_bandwidth(&mut self, hz: f64) {
+        let wn = 2.0 * PI * hz / self.sample_rate as f64;
+        let damping = std::f64::consts::FRAC_1_SQRT_2;
+        self.alpha = 2.0 * damping * wn;
+        self.beta = wn * wn;
+    }
+
+    /// Pilot amplitude below which the loop doesn't count as locked.
+    pub fn set_threshold(&mut self, level: f32) {
+        self.threshold = level;
+    }
+
+    /// Lock state as of the end of the last block.
+    pub fn lock(&self) -> PilotLock {
+        self.lock
+    }
+
+    /// Pilot phase for the next sample, the subcarrier is at twice this.
+    pub fn phase(&self) -> f64 {
+        self.phase
+    }
+}
+
+
+impl Filter<f32, f32> for PilotPll {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &x in input {
+            let (sin, cos) = self.phase.sin_cos();
+            output.push((2.0 * self.phase).sin() as f32);
+
+            // with the pilot at sin(φ), x·sin(θ) averages to half its amplitude once θ = φ and
+            // x·cos(θ) to half its amplitude times the phase error
+            let (i, q) = (x * sin as f32, x * cos as f32);
+            self.in_phase += self.smoothing * (i - self.in_phase);
+            self.quadrature += self.smoothing * (q - self.quadrature);
+            let level = self.in_phase.hypot(self.quadrature).max(1e-6);
+            let error = (q / level).clamp(-1.0, 1.0) as f64;
+
+            self.omega = (self.omega + self.beta * error).clamp(self.nominal - self.max_offset, self.nominal + self.max_offset);
+            self.phase = (self.phase + self.omega + self.alpha * error) % (2.0 * PI);
+        }
+
+        let level = 2.0 * self.in_phase;
+        self.lock = PilotLock {
+            locked: level > self.threshold && self.quadrature.abs() < 0.5 * self.in_phase,
+            level: level.max(0.0),
+            offset_hz: (self.omega - self.nominal) * self.sample_rate as f64 / (2.0 * PI),
+        };
+        Ok(())
+    }
+}
+
+
+impl RateAware for PilotPll {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+/// Open-loop pilot recovery: band-passes 19 kHz ± 500 Hz out of the MPX and outputs `sin(2φ)` of the
+/// filtered pilot's phase, advanced by the filter delay so it lines up with the input.
+pub struct OpenLoopPilot {
+    sample_rate: u32,
+    bandpass: FIRFilter<Complex32>,
+    advance: f64,
+    nominal: f64,
+    last: Complex32,
+    threshold: f32,
+    lock: PilotLock,
+    mpx: Vec<Complex32>,
+    pilot: Vec<Complex32>,
+}
+
+
+impl OpenLoopPilot {
+    pub fn new(sample_rate: u32) -> Self {
+        let num_taps = 401;
+        let nominal = 2.0 * PI * FM_PILOT_HZ / sample_rate as f64;
+        Self {
+            sample_rate,
+            bandpass: FIRFilter::new(bandpass_complex_taps(sample_rate, FM_PILOT_HZ as f32 - 500.0, FM_PILOT_HZ as f32 + 500.0, num_taps)),
+            // the pilot at sin(φ) comes out of the filter as ½·e^(j(φ − π/2)), `num_taps / 2` samples late
+            advance: nominal * (num_taps / 2) as f64 + PI / 2.0,
+            nominal,
+            last: Complex32::new(0.0, 0.0),
+            threshold: 0.01,
+            lock: PilotLock::default(),
+            mpx: Vec::new(),
+            pilot: Vec::new(),
+        }
+    }
+
+    /// Pilot amplitude below which the pilot doesn't count as locked.
+    pub fn set_threshold(&mut self, level: f32) {
+        self.threshold = level;
+    }
+
+    /// Lock state as of the end of the last block.
+    pub fn lock(&self) -> PilotLock {
+        self.lock
+    }
+}
+
+
+impl Filter<f32, f32> for OpenLoopPilot {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.mpx.clear();
+        self.mpx.extend(input.iter().map(|&x| Complex32::new(x, 0.0)));
+        self.bandpass.filter(&self.mpx, &mut self.pilot)?;
+        let mut rotation = 0.0;
+        for &z in &self.pilot {
+            output.push((2.0 * (z.arg() as f64 + self.advance)).sin() as f32);
+            rotation += (z * self.last.conj()).arg() as f64;
+            self.last = z;
+        }
+
+        let Some(last) = self.pilot.last() else {
+            return Ok(());
+        };
+        let level = 2.0 * last.norm();
+        let offset_hz = (rotation / self.pilot.len() as f64 - self.nominal) * self.sample_rate as f64 / (2.0 * PI);
+        self.lock = PilotLock {
+            locked: level > self.threshold && offset_hz.abs() < 50.0,
+            level,
+            offset_hz,
+        };
+        Ok(())
+    }
+}
+
+
+impl RateAware for OpenLoopPilot {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+}
+
+
+/// The pilot recovery a `StereoDecoder` runs.
+enum Pilot {
+    Pll(PilotPll),
+    OpenLoop(OpenLoopPilot),
+}
+
+
+impl Pilot {
+    fn new(sample_rate: u32, recovery: PilotRecovery) -> Self {
+        match recovery {
+            PilotRecovery::Pll => Pilot::Pll(PilotPll::new(sample_rate)),
+            PilotRecovery::OpenLoop => Pilot::OpenLoop(OpenLoopPilot::new(sample_rate)),
+        }
+    }
+
+    fn filter(&mut self, input: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        match self {
+            Pilot::Pll(pll) => pll.filter(input, output),
+            Pilot::OpenLoop(open_loop) => open_loop.filter(input, output),
+        }
+    }
+
+    fn lock(&self) -> PilotLock {
+        match self {
+            Pilot::Pll(pll) => pll.lock(),
+            Pilot::OpenLoop(open_loop) => open_loop.lock(),
+        }
+    }
+}
+
+
+/// Decodes broadcast FM stereo from demodulated MPX into interleaved left and right audio. The doubled
+/// pilot, by default from a `PilotPll`, demodulates the 38 kHz L−R subcarrier, both L+R and L−R are cut off at
+/// 15 kHz and the channels come out de-emphasized at the audio rate, at the level of the mono path.
+/// Without a pilot lock both channels get L+R, blending over 50 ms either way.
+pub struct StereoDecoder {
+    sample_rate: u32,
+    audio_rate: u32,
+    pilot: Pilot,
+    probe: SignalProbe<PilotLock>,
+    sum_filter: FIRFilter<f32>,
+    difference_filter: FIRFilter<f32>,
+    blend: f32,
+    blend_step: f32,
+    // left and right travel as the real and imaginary part, so they stay aligned
+    resampler: RationalResampler<Complex32>,
+    left_deemphasis: DeEmphasisFilter,
+    right_deemphasis: DeEmphasisFilter,
+    drift: FractionalResampler<Complex32>,
+    reference: Vec<f32>,
+    product: Vec<f32>,
+    sum: Vec<f32>,
+    difference: Vec<f32>,
+    stereo: Vec<Complex32>,
+    resampled: Vec<Complex32>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+    deemphasized: Vec<f32>,
+}
+
+
+impl StereoDecoder {
+    /// MPX at `sample_rate`, which has to carry the subcarrier up to 53 kHz.
+    pub fn new(sample_rate: u32, audio_rate: u32) -> Result<Self, ConfigError> {
+        if (sample_rate as f64) < 2.0 * 53e3 {
+            return Err(ConfigError::Invalid(format!("{} Hz MPX is too slow for the 38 kHz stereo subcarrier", sample_rate)));
+        }
+        let taps = lowpass_taps(15e3 / sample_rate as f32, 151);
+        let gain: f32 = taps.iter().sum();
+        let taps: Vec<f32> = taps.iter().map(|tap| tap / gain).collect();
+        Ok(Self {
+            sample_rate,
+            audio_rate,
+            pilot: Pilot::new(sample_rate, PilotRecovery::default()),
+            probe: SignalProbe::new(1),
+            sum_filter: FIRFilter::new(taps.clone()),
+            difference_filter: FIRFilter::new(taps),
+            blend: 0.0,
+            blend_step: 1.0 / (0.05 * sample_rate as f32),
+            resampler: RationalResamplerBuilder::new(sample_rate, audio_rate).num_taps(1001).build()?,
+            left_deemphasis: DeEmphasisFilter::new(audio_rate, 75e-6),
+            right_deemphasis: DeEmphasisFilter::new(audio_rate, 75e-6),
+            drift: FractionalResampler::new(audio_rate, audio_rate),
+            reference: Vec::new(),
+            product: Vec::new(),
+            sum: Vec::new(),
+            difference: Vec::new(),
+            stereo: Vec::new(),
+            resampled: Vec::new(),
+            left: Vec::new(),
+            right: Vec::new(),
+            deemphasized: Vec::new(),
+        })
+    }
+
+    /// Defaults to 75 µs as in the Americas, Europe uses 50 µs.
+    pub fn set_deemphasis(&mut self, tau: f32) {
+        self.left_deemphasis = DeEmphasisFilter::new(self.audio_rate, tau);
+        self.right_deemphasis = DeEmphasisFilter::new(self.audio_rate, tau);
+    }
+
+    /// Keeps the audio queue level, see `FractionalResampler::correct_drift`.
+    pub fn correct_drift(&mut self, mismatch: ClockMismatch, max_ppm: f64) {
+        self.drift.correct_drift(mismatch, max_ppm);
+    }
+
+    /// Switches between PLL and open-loop pilot recovery, the new one starts unlocked.
+    pub fn set_pilot_recovery(&mut self, recovery: PilotRecovery) {
+        self.pilot = Pilot::new(self.sample_rate, recovery);
+    }
+
+    /// Pilot lock state for UIs on other threads, in `last` of the levels after every block.
+    pub fn probe(&self) -> ProbeReader<PilotLock> {
+        self.probe.reader()
+    }
+
+    /// 0 for mono, 1 for full stereo.
+    pub fn blend(&self) -> f32 {
+        self.blend
+    }
+}
+
+
+impl Filter<f32, f32> for StereoDecoder {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        self.pilot.filter(input, &mut self.reference)?;
+        let lock = self.pilot.lock();
+        self.probe.write(&[lock])?;
+        // (L−R)·sin(2φ) times sin(2φ) leaves (L−R)/2 below 15 kHz
+        self.product.clear();
+        self.product.extend(input.iter().zip(&self.reference).map(|(x, r)| 2.0 * x * r));
+        self.sum_filter.filter(input, &mut self.sum)?;
+        self.difference_filter.filter(&self.product, &mut self.difference)?;
+
+        let target = if lock.locked { 1.0 } else { 0.0 };
+        self.stereo.clear();
+        for (&sum, &difference) in self.sum.iter().zip(&self.difference) {
+            self.blend = (self.blend + (target - self.blend).clamp(-self.blend_step, self.blend_step)).clamp(0.0, 1.0);
+            let difference = self.blend * difference;
+            self.stereo.push(Complex32::new(sum + difference, sum - difference));
+        }
+
+        self.resampler.filter(&self.stereo, &mut self.resampled)?;
+        self.left.clear();
+        self.left.extend(self.resampled.iter().map(|x| x.re));
+        self.right.clear();
+        self.right.extend(self.resampled.iter().map(|x| x.im));
+        self.left_deemphasis.filter(&self.left, &mut self.deemphasized)?;
+        std::mem::swap(&mut self.left, &mut self.deemphasized);
+        self.right_deemphasis.filter(&self.right, &mut self.deemphasized)?;
+        std::mem::swap(&mut self.right, &mut self.deemphasized);
+
+        self.stereo.clear();
+        self.stereo.extend(self.left.iter().zip(&self.right).map(|(&l, &r)| Complex32::new(l, r)));
+        self.drift.filter(&self.stereo, &mut self.resampled)?;
+        output.extend(self.resampled.iter().flat_map(|x| [x.re, x.im]));
+        Ok(())
+    }
+}
+
+
+impl RateAware for StereoDecoder {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+
+    /// Frames per second, two samples each.
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.audio_rate))
+    }
+
+    fn delay(&self) -> f64 {
+        let to_input = self.sample_rate as f64 / self.audio_rate as f64;
+        self.sum_filter.delay() + self.resampler.delay() + self.drift.delay() * to_input
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::f64::consts::PI;
+    use crate::seed::Rng;
+    use crate::stereo::{OpenLoopPilot, PilotPll, PilotRecovery, StereoDecoder};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_pilot_pll() -> Result<(), Box<dyn Error>> {
+        // mono audio, a pilot 0.7 Hz off and a little noise
+        let rate = 192_000.0;
+        let pilot_hz = 19_000.7;
+        let mut rng = Rng::new(0x2545f491);
+        let mut noise = move || rng.uniform() - 0.5;
+        let phase0 = 1.0;
+        let mpx: Vec<f32> = (0..rate as usize / 2).map(|n| {
+            let t = n as f64 / rate;
+            let audio = 0.5 * (2.0 * PI * 1000.0 * t).sin();
+            let pilot = 0.1 * (2.0 * PI * pilot_hz * t + phase0).sin();
+            (audio + pilot) as f32 + 0.1 * noise()
+        }).collect();
+
+        // mean of the last block times the doubled pilot, 0.5 when they line up
+        let correlation = |reference: &[f32]| {
+            let start = mpx.len() - reference.len();
+            reference.iter().enumerate().map(|(k, &r)| {
+                let t = (start + k) as f64 / rate;
+                r as f64 * (2.0 * (2.0 * PI * pilot_hz * t + phase0)).sin()
+            }).sum::<f64>() / reference.len() as f64
+        };
+
+        let mut pll = PilotPll::new(rate as u32);
+        let mut reference = Vec::new();
+        for block in mpx.chunks(4096) {
+            pll.filter(block, &mut reference)?;
+        }
+        let lock = pll.lock();
+        assert!(lock.locked, "{:?}", lock);
+        assert!((lock.level - 0.1).abs() < 0.01, "{:?}", lock);
+        assert!((lock.offset_hz - 0.7).abs() < 0.5, "{:?}", lock);
+        let correlation_pll = correlation(&reference);
+        assert!(correlation_pll > 0.48, "{}", correlation_pll);
+
+        // open loop lines up too, with the noise around the pilot on the reference
+        let mut open_loop = OpenLoopPilot::new(rate as u32);
+        for block in mpx.chunks(4096) {
+            open_loop.filter(block, &mut reference)?;
+        }
+        let lock = open_loop.lock();
+        assert!(lock.locked, "{:?}", lock);
+        assert!((lock.level - 0.1).abs() < 0.02, "{:?}", lock);
+        assert!((lock.offset_hz - 0.7).abs() < 5.0, "{:?}", lock);
+        let open = correlation(&reference);
+        assert!(open > 0.45, "{}", open);
+
+        // no pilot, no lock
+        let mut pll = PilotPll::new(rate as u32);
+        let mono: Vec<f32> = (0..rate as usize / 4).map(|_| 0.3 * noise()).collect();
+        pll.filter(&mono, &mut reference)?;
+        assert!(!pll.lock().locked, "{:?}", pll.lock());
+        let mut open_loop = OpenLoopPilot::new(rate as u32);
+        open_loop.filter(&mono, &mut reference)?;
+        assert!(!open_loop.lock().locked, "{:?}", open_loop.lock());
+        Ok(())
+    }
+    #[test]
+    fn test_stereo_decoder() -> Result<(), Box<dyn Error>> {
+        // 1 kHz on the left only, 400 Hz on the right only
+        let rate = 192_000.0;
+        let phase0 = 0.3;
+        let mpx = |pilot: bool, seconds: f64| -> Vec<f32> {
+            (0..(seconds * rate) as usize).map(|n| {
+                let t = n as f64 / rate;
+                let left = 0.4 * (2.0 * PI * 1000.0 * t).sin();
+                let right = 0.4 * (2.0 * PI * 400.0 * t).sin();
+                let pilot_phase = 2.0 * PI * 19_000.0 * t + phase0;
+                let stereo = if pilot { 0.45 * (left - right) * (2.0 * pilot_phase).sin() + 0.1 * pilot_phase.sin() } else { 0.0 };
+                (0.45 * (left + right) + stereo) as f32
+            }).collect()
+        };
+        // amplitude of `hz` in the second half of one channel
+        let level = |audio: &[f32], channel: usize, hz: f64| {
+            let frames: Vec<f32> = audio.chunks_exact(2).map(|frame| frame[channel]).collect();
+            let tail = &frames[frames.len() / 2..];
+            let (i, q) = tail.iter().enumerate().fold((0.0, 0.0), |(i, q), (n, &x)| {
+                let phase = 2.0 * PI * hz * n as f64 / 48000.0;
+                (i + x as f64 * phase.cos(), q + x as f64 * phase.sin())
+            });
+            2.0 * (i * i + q * q).sqrt() / tail.len() as f64
+        };
+
+        // the PLL goes last, its left channel is what the mono sum is held against below
+        let mut left = 0.0;
+        for recovery in [PilotRecovery::OpenLoop, PilotRecovery::Pll] {
+            let mut decoder = StereoDecoder::new(rate as u32, 48000)?;
+            decoder.set_pilot_recovery(recovery);
+            let probe = decoder.probe();
+            let mut audio = Vec::new();
+            let mut block = Vec::new();
+            for piece in mpx(true, 0.5).chunks(4096) {
+                decoder.filter(piece, &mut block)?;
+                audio.extend_from_slice(&block);
+            }
+            assert!(probe.levels().last.locked, "{:?}", recovery);
+            assert_eq!(decoder.blend(), 1.0);
+            assert!((audio.len() as i64 - 48000).abs() < 10, "{}", audio.len());
+            // 20 dB of separation either way
+            left = level(&audio, 0, 1000.0);
+            let leak = level(&audio, 1, 1000.0);
+            assert!(left > 10.0 * leak, "{:?} {} {}", recovery, left, leak);
+            let (right, leak) = (level(&audio, 1, 400.0), level(&audio, 0, 400.0));
+            assert!(right > 10.0 * leak, "{:?} {} {}", recovery, right, leak);
+        }
+
+        // no pilot, the same mono sum on both sides
+        let mut decoder = StereoDecoder::new(rate as u32, 48000)?;
+        let mut audio = Vec::new();
+        decoder.filter(&mpx(false, 0.2), &mut audio)?;
+        assert_eq!(decoder.blend(), 0.0);
+        assert!(audio.chunks_exact(2).all(|frame| frame[0] == frame[1]));
+        assert!(level(&audio, 0, 1000.0) > 0.5 * left);
+
+        assert!(StereoDecoder::new(48000, 48000).is_err());
+        Ok(())
+    }
+}
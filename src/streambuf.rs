@@ -1,18 +1,46 @@
 use std::io::{ErrorKind, Read, Write};
 use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use crate::pipeline::{CancelToken, CANCEL_POLL};
 use crate::util::resize_unchecked;
 
 struct StreamBuf<T: Copy> {
+    /// Ring of `mem.len()` slots. The allocation can be larger than asked for, so positions wrap
+    /// at the length, never at `capacity()`.
     mem: Vec<T>,
     rp: usize,
     wp: usize,
     size: usize,
+    /// Samples dropped before they were read, by overwriting or shrinking.
+    dropped: u64,
     overwrite: bool,
     block_read: bool,
     block_write: bool,
     read_closed: bool,
     write_closed: bool,
+    cancel: Option<CancelToken>,
+}
+
+
+impl<T: Copy> StreamBuf<T> {
+    /// Keeps as much of the newest queued data as fits, the oldest is dropped.
+    fn set_capacity(&mut self, capacity: usize) {
+        let capacity = capacity.max(1);
+        if capacity == self.mem.len() {
+            return;
+        }
+        let keep = self.size.min(capacity);
+        let skip = self.size - keep;
+        self.dropped += skip as u64;
+        let mut mem = Vec::with_capacity(capacity);
+        mem.extend((0..keep).map(|i| self.mem[(self.rp + skip + i) % self.mem.len()]));
+        unsafe { resize_unchecked(&mut mem, capacity); }
+        self.mem = mem;
+        self.rp = 0;
+        self.wp = keep % capacity;
+        self.size = keep;
+    }
 }
 
 
@@ -39,11 +67,13 @@ pub fn new_stream<'a, T: Copy>(capacity: usize, overwrite: bool, block_write: bo
         rp: 0,
         wp: 0,
         size: 0,
+        dropped: 0,
         overwrite,
         block_read,
         block_write,
         read_closed: false,
         write_closed: false,
+        cancel: None,
     };
     unsafe { resize_unchecked(&mut stream.mem, capacity); }
     let stream = Arc::new(Mutex::new(stream));
@@ -86,8 +116,8 @@ impl<'a, T: Copy> Iterator for PeekIter<'a, T> {
             return None;
         }
 
-        let rp = (stream.rp + self.off) % stream.mem.capacity();
-        let read = std::cmp::min(stream.mem.capacity() - rp, stream.size);
+        let rp = (stream.rp + self.off) % stream.mem.len();
+        let read = std::cmp::min(stream.mem.len() - rp, stream.size);
         let ptr = &stream.mem[rp] as *const T;
         self.off += read;
         Some(unsafe { std::slice::from_raw_parts(ptr, read) })
@@ -119,14 +149,22 @@ impl<'a, T: Copy> PeekIter<'a, T> {
         let stream = self.stream.as_deref().unwrap();
         stream.size
     }
+
+    /// Samples the stream dropped unread so far, all of them came before the ones peeked at.
+    pub fn dropped(&self) -> u64 {
+        self.stream.as_deref().unwrap().dropped
+    }
 }
 
 
 impl<'a, T: Copy> Drop for PeekIter<'a, T> {
     fn drop(&mut self) {
-        let stream = self.stream.as_deref_mut().unwrap();
+        // the guard is gone if waiting for data timed out
+        let Some(stream) = self.stream.as_deref_mut() else {
+            return;
+        };
         if self.consume > 0 {
-            stream.rp = (stream.rp + self.consume) % stream.mem.capacity();
+            stream.rp = (stream.rp + self.consume) % stream.mem.len();
             stream.size -= self.consume;
         }
         self.condvar.notify_all();
@@ -134,8 +172,59 @@ impl<'a, T: Copy> Drop for PeekIter<'a, T> {
 }
 
 
+fn wait<'a, T: Copy>(condvar: &Condvar, guard: MutexGuard<'a, StreamBuf<T>>, deadline: Option<Instant>) -> std::io::Result<MutexGuard<'a, StreamBuf<T>>> {
+    if guard.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+        return Err(std::io::Error::new(ErrorKind::Interrupted, "wait on stream was cancelled"));
+    }
+    let now = Instant::now();
+    if deadline.is_some_and(|deadline| now >= deadline) {
+        return Err(std::io::Error::new(ErrorKind::TimedOut, "timed out waiting on stream"));
+    }
+    // with a token attached wake up now and then, a cancel can slip in between the check and the wait
+    let poll = guard.cancel.as_ref().map(|_| CANCEL_POLL);
+    let timeout = match (deadline.map(|deadline| deadline - now), poll) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    match timeout {
+        None => Ok(condvar.wait(guard).unwrap()),
+        Some(timeout) => Ok(condvar.wait_timeout(guard, timeout).unwrap().0),
+    }
+}
+
+
 impl<T: Copy> StreamReader<T> {
+    /// Make blocking calls on either end of the stream fail with `ErrorKind::Interrupted` once `cancel` fires.
+    pub fn set_cancel(&self, cancel: &CancelToken) {
+        self.reader.lock().unwrap().cancel = Some(cancel.clone());
+        cancel.register(&self.condvar);
+    }
+
+    /// Samples waiting to be read.
+    pub fn queued(&self) -> usize {
+        self.reader.lock().unwrap().size
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.reader.lock().unwrap().mem.len()
+    }
+
+    /// Grow or shrink the buffer, shrinking below what is queued drops the oldest samples.
+    pub fn set_capacity(&self, capacity: usize) {
+        self.reader.lock().unwrap().set_capacity(capacity);
+        self.condvar.notify_all();
+    }
+
     pub fn get(&self, buffer: &mut [T]) -> std::io::Result<usize> {
+        self.get_until(buffer, None)
+    }
+
+    /// Like `get` but a blocking read gives up with `ErrorKind::TimedOut` after `timeout`.
+    pub fn get_timeout(&self, buffer: &mut [T], timeout: Duration) -> std::io::Result<usize> {
+        self.get_until(buffer, Some(Instant::now() + timeout))
+    }
+
+    fn get_until(&self, buffer: &mut [T], deadline: Option<Instant>) -> std::io::Result<usize> {
         if buffer.len() == 0 {
             return Err(std::io::Error::new(ErrorKind::InvalidInput, "buffer is zero length"));
         }
@@ -146,7 +235,7 @@ impl<T: Copy> StreamReader<T> {
                 if inner.write_closed {
                     return Ok(0);
                 }
-                inner = self.condvar.wait(inner).unwrap();
+                inner = wait(&self.condvar, inner, deadline)?;
             }
         } else if inner.size == 0 {
             return Err(std::io::Error::new(ErrorKind::WouldBlock, "buffer empty"));
@@ -156,9 +245,9 @@ impl<T: Copy> StreamReader<T> {
         let buf = &mut buffer[0..std::cmp::min(len, inner.size)];
         let mut off = 0;
         while off < buf.len() {
-            let read = std::cmp::min(buf.len() - off, inner.mem.capacity() - inner.rp);
+            let read = std::cmp::min(buf.len() - off, inner.mem.len() - inner.rp);
             buf[off..off + read].copy_from_slice(&inner.mem.as_slice()[inner.rp..inner.rp + read]);
-            inner.rp = (inner.rp + read) % inner.mem.capacity();
+            inner.rp = (inner.rp + read) % inner.mem.len();
             inner.size -= read;
             off += read;
         }
@@ -168,11 +257,20 @@ impl<T: Copy> StreamReader<T> {
         Ok(off)
     }
     
-    pub fn peek(&mut self) -> std::io::Result<PeekIter<T>> {
+    pub fn peek(&mut self) -> std::io::Result<PeekIter<'_, T>> {
+        self.peek_until(None)
+    }
+
+    /// Like `peek` but gives up with `ErrorKind::TimedOut` if nothing arrives within `timeout`.
+    pub fn peek_timeout(&mut self, timeout: Duration) -> std::io::Result<PeekIter<'_, T>> {
+        self.peek_until(Some(Instant::now() + timeout))
+    }
+
+    fn peek_until(&mut self, deadline: Option<Instant>) -> std::io::Result<PeekIter<'_, T>> {
         let mut it = PeekIter::new(self.reader.deref(), Arc::clone(&self.condvar));
         if it.stream.as_ref().unwrap().block_read {
             while it.stream.as_ref().unwrap().size == 0 {
-                it.stream = Some(self.condvar.wait(it.stream.take().unwrap()).unwrap());
+                it.stream = Some(wait(&self.condvar, it.stream.take().unwrap(), deadline)?);
             }
         } else {
             return Err(std::io::Error::new(ErrorKind::WouldBlock, "buffer is empty"));
@@ -201,41 +299,86 @@ impl Read for StreamReader<u8> {
 
 
 impl<T: Copy> StreamWriter<T> {
+    /// Make blocking calls on either end of the stream fail with `ErrorKind::Interrupted` once `cancel` fires.
+    pub fn set_cancel(&self, cancel: &CancelToken) {
+        self.writer.lock().unwrap().cancel = Some(cancel.clone());
+        cancel.register(&self.condvar);
+    }
+
+    /// Samples waiting to be read.
+    pub fn queued(&self) -> usize {
+        self.writer.lock().unwrap().size
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.writer.lock().unwrap().mem.len()
+    }
+
+    /// Grow or shrink the buffer, shrinking below what is queued drops the oldest samples.
+    pub fn set_capacity(&self, capacity: usize) {
+        self.writer.lock().unwrap().set_capacity(capacity);
+        self.condvar.notify_all();
+    }
+
+    /// Drop the oldest samples until at most `keep` are queued, returns how many were dropped.
+    pub fn discard_oldest(&self, keep: usize) -> usize {
+        let mut inner = self.writer.lock().unwrap();
+        let dropped = inner.size.saturating_sub(keep);
+        if dropped > 0 {
+            inner.rp = (inner.rp + dropped) % inner.mem.len();
+            inner.size -= dropped;
+            inner.dropped += dropped as u64;
+            self.condvar.notify_all();
+        }
+        dropped
+    }
+
     pub fn put(&self, buffer: &[T]) -> std::io::Result<usize> {
+        self.put_until(buffer, None)
+    }
+
+    /// Like `put` but a blocking write gives up with `ErrorKind::TimedOut` after `timeout`.
+    pub fn put_timeout(&self, buffer: &[T], timeout: Duration) -> std::io::Result<usize> {
+        self.put_until(buffer, Some(Instant::now() + timeout))
+    }
+
+    fn put_until(&self, buffer: &[T], deadline: Option<Instant>) -> std::io::Result<usize> {
         if buffer.len() == 0 {
             return Err(std::io::Error::new(ErrorKind::InvalidInput, "buffer is zero length"));
         }
 
         let mut inner = self.writer.lock().unwrap();
         if inner.block_write {
-            while inner.size == inner.mem.capacity() {
-                if inner.write_closed {
+            while inner.size == inner.mem.len() {
+                if inner.read_closed {
                     return Err(std::io::Error::new(ErrorKind::Other, "output is closed"));
                 }
-                inner = self.condvar.wait(inner).unwrap();
+                inner = wait(&self.condvar, inner, deadline)?;
             }
-        } else if !inner.overwrite && inner.size == inner.mem.capacity() {
+        } else if !inner.overwrite && inner.size == inner.mem.len() {
             return Err(std::io::Error::new(ErrorKind::WouldBlock, "buffer full"));
         }
 
         let buf = if !inner.overwrite {
-            &buffer[..std::cmp::min(buffer.len(), inner.mem.capacity() - inner.size)]
+            &buffer[..std::cmp::min(buffer.len(), inner.mem.len() - inner.size)]
         } else {
             buffer
         };
         
         let mut off = 0;
         while off < buf.len() {
-            let write = std::cmp::min(buf.len() - off, inner.mem.capacity() - inner.wp);
+            let write = std::cmp::min(buf.len() - off, inner.mem.len() - inner.wp);
             let wp = inner.wp;
             inner.mem.as_mut_slice()[wp..wp + write].copy_from_slice(&buf[off..off + write]);
             off += write;
-            inner.wp = (inner.wp + write) % inner.mem.capacity();
+            inner.wp = (inner.wp + write) % inner.mem.len();
             inner.size += write;
-            if inner.size > inner.mem.capacity() {
+            if inner.size > inner.mem.len() {
                 debug_assert!(inner.overwrite);
-                inner.rp = (inner.rp + (inner.size - inner.mem.capacity())) % inner.mem.capacity();
-                inner.size = inner.mem.capacity();
+                let overwritten = inner.size - inner.mem.len();
+                inner.rp = (inner.rp + overwritten) % inner.mem.len();
+                inner.size = inner.mem.len();
+                inner.dropped += overwritten as u64;
             }
         }
         if off > 0 {
@@ -245,10 +388,18 @@ impl<T: Copy> StreamWriter<T> {
     }
     
     pub fn drain(&mut self) -> std::io::Result<()> {
+        self.drain_until(None)
+    }
+
+    pub fn drain_timeout(&mut self, timeout: Duration) -> std::io::Result<()> {
+        self.drain_until(Some(Instant::now() + timeout))
+    }
+
+    fn drain_until(&mut self, deadline: Option<Instant>) -> std::io::Result<()> {
         let mut inner = self.writer.lock().unwrap();
         if inner.block_write {
             while inner.size > 0 {
-                inner = self.condvar.wait(inner).unwrap();
+                inner = wait(&self.condvar, inner, deadline)?;
             }
         } else if inner.size > 0 {
             return Err(std::io::Error::new(ErrorKind::WouldBlock, "buffer is not empty yet"));
@@ -277,9 +428,63 @@ impl Write for StreamWriter<u8> {
     }
 }
 
+
+/// Copies everything put into it to each subscribed reader. Readers that get dropped are unsubscribed.
+pub struct BroadcastWriter<T: Copy> {
+    writers: Vec<StreamWriter<T>>,
+}
+
+
+impl<T: Copy> BroadcastWriter<T> {
+    pub fn new() -> Self {
+        Self {
+            writers: Vec::new(),
+        }
+    }
+
+    /// Add a reader that sees everything put from now on. An `overwrite` reader drops its oldest samples
+    /// when it falls behind, otherwise `put` blocks until it catches up.
+    pub fn subscribe(&mut self, capacity: usize, overwrite: bool) -> std::io::Result<StreamReader<T>> {
+        let (reader, writer) = new_stream(capacity, overwrite, !overwrite, true)?;
+        self.writers.push(writer);
+        Ok(reader)
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.writers.len()
+    }
+
+    pub fn put(&mut self, buffer: &[T]) -> std::io::Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.writers.retain(|w| !w.writer.lock().unwrap().read_closed);
+        for writer in self.writers.iter_mut() {
+            let mut off = 0;
+            while off < buffer.len() {
+                match writer.put(&buffer[off..]) {
+                    Ok(written) => off += written,
+                    Err(_) if writer.writer.lock().unwrap().read_closed => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl<T: Copy> Default for BroadcastWriter<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::streambuf::new_stream;
+    use std::io::ErrorKind;
+    use std::time::Duration;
+    use crate::streambuf::{new_stream, BroadcastWriter};
 
     #[test]
     fn test_create() -> std::io::Result<()> {
@@ -314,5 +519,91 @@ mod tests {
         
         Ok(())
     }
-    
+
+
+    #[test]
+    fn test_timeout() -> std::io::Result<()> {
+        let (reader, writer) = new_stream::<f32>(2, false, true, true)?;
+        let mut buff = [0f32; 2];
+        let error = reader.get_timeout(&mut buff, Duration::from_millis(10)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+
+        assert_eq!(writer.put_timeout(&buff, Duration::from_millis(10))?, 2);
+        let error = writer.put_timeout(&buff, Duration::from_millis(10)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(reader.get_timeout(&mut buff, Duration::from_millis(10))?, 2);
+
+        let (mut reader, _writer) = new_stream::<f32>(2, true, false, true)?;
+        assert_eq!(reader.peek_timeout(Duration::from_millis(10)).err().unwrap().kind(), ErrorKind::TimedOut);
+        Ok(())
+    }
+
+
+    #[test]
+    fn test_broadcast() -> std::io::Result<()> {
+        let mut writer = BroadcastWriter::<f32>::new();
+        let blocking = writer.subscribe(8, false)?;
+        let lossy = writer.subscribe(2, true)?;
+        let dropped = writer.subscribe(2, false)?;
+        drop(dropped);
+
+        writer.put(&[1.0, 2.0, 3.0])?;
+        assert_eq!(writer.subscribers(), 2);
+        let mut buff = [0f32; 8];
+        assert_eq!(blocking.get(&mut buff)?, 3);
+        assert_eq!(buff[..3], [1.0, 2.0, 3.0]);
+        assert_eq!(lossy.get(&mut buff)?, 2);
+        assert_eq!(buff[..2], [2.0, 3.0]);
+
+        drop(writer);
+        assert_eq!(blocking.get(&mut buff)?, 0);
+        Ok(())
+    }
+
+
+    #[test]
+    fn test_set_capacity() -> std::io::Result<()> {
+        let (reader, writer) = new_stream::<f32>(4, false, false, true)?;
+        let mut buff = [0f32; 8];
+        writer.put(&[1.0, 2.0, 3.0])?;
+        reader.get(&mut buff[..2])?;
+        writer.put(&[4.0, 5.0, 6.0])?;
+
+        // the queue wraps around the end of the old buffer
+        writer.set_capacity(8);
+        assert_eq!((writer.capacity(), reader.queued()), (8, 4));
+        writer.put(&[7.0, 8.0])?;
+        writer.set_capacity(3);
+        assert_eq!(reader.get(&mut buff)?, 3);
+        assert_eq!(buff[..3], [6.0, 7.0, 8.0]);
+        Ok(())
+    }
+
+
+    #[test]
+    fn test_discard_oldest() -> std::io::Result<()> {
+        let (reader, writer) = new_stream::<f32>(4, false, true, true)?;
+        writer.put(&[1.0, 2.0, 3.0])?;
+        assert_eq!(writer.discard_oldest(5), 0);
+        assert_eq!(writer.discard_oldest(1), 2);
+        writer.put(&[4.0, 5.0, 6.0])?;
+        let mut buff = [0f32; 4];
+        assert_eq!(reader.get(&mut buff)?, 4);
+        assert_eq!(buff, [3.0, 4.0, 5.0, 6.0]);
+        Ok(())
+    }
+
+
+    #[test]
+    fn test_wrap_at_len() -> std::io::Result<()> {
+        // the allocation behind 3 bytes is bigger than 3, the ring still wraps after the third
+        let (reader, writer) = new_stream::<u8>(3, false, false, false)?;
+        assert_eq!(writer.put(&[1, 2, 3, 4])?, 3);
+        let mut buff = [0u8; 3];
+        assert_eq!(reader.get(&mut buff[..2])?, 2);
+        assert_eq!(writer.put(&[4, 5])?, 2);
+        assert_eq!(reader.get(&mut buff)?, 3);
+        assert_eq!(buff, [3, 4, 5]);
+        Ok(())
+    }
 }
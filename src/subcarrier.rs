@@ -0,0 +1,158 @@
+use std::error::Error;
+use num_complex::Complex32;
+use crate::block::{FIRFilter, FMDemod, MixerFilter, RationalResampler, RationalResamplerBuilder};
+use crate::channel::ChannelFilter;
+use crate::error::ConfigError;
+use crate::rate::{RateAware, SampleRate};
+use crate::traits::*;
+use crate::util::lowpass_taps;
+
+
+/// An FM modulated subcarrier inside the MPX signal of a broadcast FM station.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Subcarrier {
+    pub frequency: f32,
+    /// Peak deviation of the subcarrier itself.
+    pub deviation: f32,
+    /// Highest frequency of the program carried on it.
+    pub bandwidth: f32,
+}
+
+
+impl Subcarrier {
+    /// The usual SCA audio channels, 150 µs de-emphasis is common on both.
+    pub const SCA_67K: Self = Self { frequency: 67e3, deviation: 6e3, bandwidth: 5e3 };
+    pub const SCA_92K: Self = Self { frequency: 92e3, deviation: 6e3, bandwidth: 5e3 };
+
+    /// Half the Carson bandwidth, how far the channel reaches either side of the subcarrier.
+    pub fn half_width(&self) -> f32 {
+        self.deviation + self.bandwidth
+    }
+}
+
+
+/// Pulls one subcarrier out of demodulated FM MPX and demodulates it: mixes it to baseband,
+/// decimates to the output rate, filters the channel and runs its own NBFM demodulator.
+pub struct SubcarrierExtractor {
+    subcarrier: Subcarrier,
+    mpx_rate: u32,
+    audio_rate: u32,
+    mixer: MixerFilter,
+    resampler: RationalResampler<Complex32>,
+    channel: ChannelFilter,
+    demod: FMDemod,
+    audio: FIRFilter<f32>,
+    mixed: Vec<Complex32>,
+    baseband: Vec<Complex32>,
+    filtered: Vec<Complex32>,
+    demodulated: Vec<f32>,
+}
+
+
+impl SubcarrierExtractor {
+    pub fn new(mpx_rate: u32, audio_rate: u32, subcarrier: Subcarrier) -> Result<Self, Box<dyn Error>> {
+        if subcarrier.frequency + subcarrier.half_width() >= mpx_rate as f32 / 2.0 {
+            return Err(ConfigError::Invalid(format!("a {} Hz subcarrier needs more than {} Hz of MPX", subcarrier.frequency, mpx_rate)).into());
+        }
+        if subcarrier.half_width() >= audio_rate as f32 / 2.0 {
+            return Err(ConfigError::Invalid(format!("{} Hz either side of the subcarrier doesn't fit {} Hz audio", subcarrier.half_width(), audio_rate)).into());
+        }
+
+        let taps = lowpass_taps(subcarrier.bandwidth / audio_rate as f32, 63);
+        let gain: f32 = taps.iter().sum();
+        Ok(Self {
+            subcarrier,
+            mpx_rate,
+            audio_rate,
+            mixer: MixerFilter::new(mpx_rate, subcarrier.frequency),
+            resampler: RationalResamplerBuilder::new(mpx_rate, audio_rate).taps_per_ratio().build()?,
+            channel: ChannelFilter::new(audio_rate, subcarrier.half_width(), 129),
+            demod: FMDemod::new(audio_rate, subcarrier.deviation),
+            audio: FIRFilter::new(taps.into_iter().map(|t| t / gain).collect()),
+            mixed: Vec::new(),
+            baseband: Vec::new(),
+            filtered: Vec::new(),
+            demodulated: Vec::new(),
+        })
+    }
+
+    pub fn subcarrier(&self) -> Subcarrier {
+        self.subcarrier
+    }
+
+    /// Move to another subcarrier with the same deviation and bandwidth, e.g. from 67 to 92 kHz.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.subcarrier.frequency = frequency;
+        self.mixer.set_frequency(frequency);
+    }
+}
+
+
+impl Filter<f32, f32> for SubcarrierExtractor {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        self.mixer.filter(input, &mut self.mixed)?;
+        self.resampler.filter(&self.mixed, &mut self.baseband)?;
+        self.channel.filter(&self.baseband, &mut self.filtered)?;
+        self.demod.filter(&self.filtered, &mut self.demodulated)?;
+        self.audio.filter(&self.demodulated, output)
+    }
+}
+
+
+impl RateAware for SubcarrierExtractor {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.mpx_rate))
+    }
+
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.audio_rate))
+    }
+
+    fn delay(&self) -> f64 {
+        let decimation = self.mpx_rate as f64 / self.audio_rate as f64;
+        self.resampler.delay() + (self.channel.delay() + self.audio.delay()) * decimation
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::subcarrier::{Subcarrier, SubcarrierExtractor};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_subcarrier_extractor() -> Result<(), Box<dyn Error>> {
+        // loud mono program, the pilot and a 67 kHz SCA carrying a 400 Hz tone at full deviation
+        let (mpx_rate, audio_rate) = (240_000u32, 24_000u32);
+        let sca = Subcarrier::SCA_67K;
+        let mut phase = 0f32;
+        let mpx: Vec<f32> = (0..mpx_rate as usize / 2).map(|n| {
+            let t = n as f32 / mpx_rate as f32;
+            phase = (phase + 2.0 * PI * (sca.frequency + sca.deviation * (2.0 * PI * 400.0 * t).sin()) / mpx_rate as f32) % (2.0 * PI);
+            0.8 * (2.0 * PI * 1000.0 * t).sin() + 0.1 * (2.0 * PI * 19e3 * t).sin() + 0.1 * phase.cos()
+        }).collect();
+
+        let mut extractor = SubcarrierExtractor::new(mpx_rate, audio_rate, sca)?;
+        let mut audio = Vec::new();
+        let mut block = Vec::new();
+        for chunk in mpx.chunks(4800) {
+            extractor.filter(chunk, &mut block)?;
+            audio.extend_from_slice(&block);
+        }
+        assert_eq!(audio.len(), audio_rate as usize / 2);
+
+        let settled = &audio[2400..];
+        let amplitude = |freq: f32| 2.0 * settled.iter().enumerate()
+            .map(|(n, &x)| Complex32::from_polar(x, -2.0 * PI * freq * n as f32 / audio_rate as f32))
+            .sum::<Complex32>().norm() / settled.len() as f32;
+        assert!((amplitude(400.0) - 1.0).abs() < 0.1, "{}", amplitude(400.0));
+        assert!(amplitude(1000.0) < 0.02, "{}", amplitude(1000.0));
+
+        assert!(SubcarrierExtractor::new(mpx_rate, 16_000, sca).is_err());
+        assert!(SubcarrierExtractor::new(96_000, audio_rate, sca).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagValue {
+    /// Host clock reading taken when the tagged sample arrived from the device.
+    Timestamp(SystemTime),
+    /// `count` I/Q components at ADC full scale in the buffer starting at the tagged sample.
+    Clipping { count: usize },
+    /// First sample read from this file of a `PlaylistSource`.
+    FileStart(PathBuf),
+}
+
+
+/// Metadata attached to the sample at `offset`, counted from the first sample a source produced.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+    pub offset: u64,
+    pub value: TagValue,
+}
+
+
+/// Implemented by blocks that attach tags to the samples they produce.
+pub trait Tagged {
+    /// Move the tags for every sample handed out so far into `dst`.
+    fn take_tags(&mut self, dst: &mut Vec<Tag>);
+}
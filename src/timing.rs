@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::tag::{Tag, TagValue};
+use crate::traits::*;
+
+
+/// A sample known to line up with a reference time, e.g. a PPS edge or an NTP disciplined host timestamp.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReferenceEvent {
+    pub sample: u64,
+    pub time: SystemTime,
+}
+
+
+impl ReferenceEvent {
+    /// Use a `Timestamp` tag as a reference, only as good as the host clock that produced it.
+    pub fn from_tag(tag: &Tag) -> Option<Self> {
+        match tag.value {
+            TagValue::Timestamp(time) => Some(Self { sample: tag.offset, time }),
+            _ => None,
+        }
+    }
+}
+
+
+/// Least squares fit of sample index against reference time over the most recent events,
+/// giving the actual sample rate, its error in ppm and a disciplined time for any sample.
+pub struct ClockDiscipline {
+    nominal_rate: f64,
+    max_events: usize,
+    events: VecDeque<(u64, f64)>,
+    rate: f64,
+    origin: (u64, f64),
+}
+
+
+fn seconds(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
+
+
+impl ClockDiscipline {
+    pub fn new(nominal_rate: f64, max_events: usize) -> Self {
+        Self {
+            nominal_rate,
+            max_events: max_events.max(2),
+            events: VecDeque::new(),
+            rate: nominal_rate,
+            origin: (0, 0.0),
+        }
+    }
+
+    pub fn add_reference(&mut self, event: ReferenceEvent) {
+        if self.events.len() == self.max_events {
+            self.events.pop_front();
+        }
+        self.events.push_back((event.sample, seconds(event.time)));
+
+        // fit relative to the first event to keep the f64 sums well conditioned
+        let (s0, t0) = self.events[0];
+        let n = self.events.len() as f64;
+        let mean_t = self.events.iter().map(|&(_, t)| t - t0).sum::<f64>() / n;
+        let mean_s = self.events.iter().map(|&(s, _)| (s - s0) as f64).sum::<f64>() / n;
+        let mut cov = 0.0;
+        let mut var = 0.0;
+        for &(s, t) in &self.events {
+            let dt = t - t0 - mean_t;
+            cov += dt * ((s - s0) as f64 - mean_s);
+            var += dt * dt;
+        }
+        if var > 0.0 {
+            self.rate = cov / var;
+        }
+        // anchor the line at the centroid of the events
+        let centroid = mean_s.round();
+        self.origin = (s0 + centroid as u64, t0 + mean_t + (centroid - mean_s) / self.rate);
+    }
+
+    pub fn reset(&mut self) {
+        self.events.clear();
+        self.rate = self.nominal_rate;
+        self.origin = (0, 0.0);
+    }
+
+    /// Whether enough events have been seen to estimate the rate.
+    pub fn locked(&self) -> bool {
+        self.events.len() >= 2
+    }
+
+    /// Estimated actual sample rate in Hz.
+    pub fn sample_rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Sample clock error in parts per million, positive when the clock runs fast.
+    pub fn ppm(&self) -> f64 {
+        (self.rate / self.nominal_rate - 1.0) * 1e6
+    }
+
+    /// Disciplined time of `sample`, `None` until a reference has been added.
+    pub fn time_of(&self, sample: u64) -> Option<SystemTime> {
+        if self.events.is_empty() {
+            return None;
+        }
+        let t = self.origin.1 + (sample as f64 - self.origin.0 as f64) / self.rate;
+        if t >= 0.0 {
+            Some(UNIX_EPOCH + Duration::from_secs_f64(t))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs_f64(-t))
+        }
+    }
+
+    /// Replace the time in every `Timestamp` tag with the disciplined one.
+    pub fn apply(&self, tags: &mut [Tag]) {
+        for tag in tags.iter_mut() {
+            if let TagValue::Timestamp(_) = tag.value
+                && let Some(time) = self.time_of(tag.offset) {
+                tag.value = TagValue::Timestamp(time);
+            }
+        }
+    }
+}
+
+
+/// Finds rising edges of a PPS pulse, e.g. fed into a spare audio or ADC channel, and outputs their sample index.
+pub struct PpsDetector {
+    threshold: f32,
+    holdoff: u64,
+    position: u64,
+    last_edge: Option<u64>,
+    high: bool,
+}
+
+
+impl PpsDetector {
+    /// Edges closer than `holdoff` samples to the previous one are ignored.
+    pub fn new(threshold: f32, holdoff: u64) -> Self {
+        Self {
+            threshold,
+            holdoff,
+            position: 0,
+            last_edge: None,
+            high: true,
+        }
+    }
+}
+
+
+impl Filter<f32, u64> for PpsDetector {
+    fn filter(&mut self, input: &[f32], output: &mut Vec<u64>) -> Result<(), Box<dyn Error>> {
+        output.clear();
+        for &sample in input {
+            let high = sample >= self.threshold;
+            if high && !self.high {
+                let ready = self.last_edge.is_none_or(|last| self.position - last >= self.holdoff);
+                if ready {
+                    output.push(self.position);
+                    self.last_edge = Some(self.position);
+                }
+            }
+            self.high = high;
+            self.position += 1;
+        }
+        Ok(())
+    }
+}
+
+
+/// Samples handed to a device and samples its own clock has taken, shared with the device callback.
+#[derive(Debug, Default)]
+pub struct SampleCounters {
+    pub produced: AtomicU64,
+    pub consumed: AtomicU64,
+}
+
+
+/// Compares how fast a pipeline produces samples with how fast a device consumes them, e.g. an SDR
+/// feeding a sound card. Each runs off its own crystal, so the backlog between them creeps up or
+/// down at a steady rate; a least squares fit of that backlog over time gives the mismatch in ppm.
+/// Only meaningful while the queue in between is neither pinned full nor empty.
+pub struct ClockMismatch {
+    counters: Arc<SampleCounters>,
+    nominal_rate: f64,
+    window: Duration,
+    min_span: Duration,
+    start: Instant,
+    points: VecDeque<(f64, f64)>,
+}
+
+
+impl ClockMismatch {
+    pub fn new(counters: Arc<SampleCounters>, nominal_rate: f64) -> Self {
+        Self {
+            counters,
+            nominal_rate,
+            window: Duration::from_secs(60),
+            min_span: Duration::from_secs(30),
+            start: Instant::now(),
+            points: VecDeque::new(),
+        }
+    }
+
+    /// Fit over the last `window`, reporting once at least `min_span` has been seen.
+    pub fn window(mut self, window: Duration, min_span: Duration) -> Self {
+        self.window = window;
+        self.min_span = min_span.min(window);
+        self
+    }
+
+    /// Sample the shared counters now.
+    pub fn update(&mut self) -> Option<f64> {
+        let produced = self.counters.produced.load(Ordering::Relaxed);
+        let consumed = self.counters.consumed.load(Ordering::Relaxed);
+        self.observe(produced, consumed, self.start.elapsed())
+    }
+
+    /// Add a reading of both counters taken `elapsed` after the first, returns the new estimate.
+    pub fn observe(&mut self, produced: u64, consumed: u64, elapsed: Duration) -> Option<f64> {
+        let t = elapsed.as_secs_f64();
+        // a point every 100 ms is plenty for a drift of a few samples per second
+        if self.points.back().is_none_or(|&(last, _)| t - last >= 0.1) {
+            self.points.push_back((t, produced as f64 - consumed as f64));
+        }
+        while self.points.front().is_some_and(|&(first, _)| t - first > self.window.as_secs_f64()) {
+            self.points.pop_front();
+        }
+        self.ppm()
+    }
+
+    pub fn reset(&mut self) {
+        self.points.clear();
+    }
+
+    /// Producer clock relative to the consumer's in parts per million, positive when the producer
+    /// runs fast. `None` until `min_span` of readings has been seen.
+    pub fn ppm(&self) -> Option<f64> {
+        let (&(first, _), &(last, _)) = (self.points.front()?, self.points.back()?);
+        if last - first < self.min_span.as_secs_f64() {
+            return None;
+        }
+        let n = self.points.len() as f64;
+        let mean_t = self.points.iter().map(|&(t, _)| t).sum::<f64>() / n;
+        let mean_b = self.points.iter().map(|&(_, b)| b).sum::<f64>() / n;
+        let (mut cov, mut var) = (0.0, 0.0);
+        for &(t, b) in &self.points {
+            cov += (t - mean_t) * (b - mean_b);
+            var += (t - mean_t) * (t - mean_t);
+        }
+        // the backlog grows by the difference of the two rates
+        Some(cov / var / self.nominal_rate * 1e6)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use std::sync::Arc;
+    use crate::timing::{ClockDiscipline, ClockMismatch, PpsDetector, ReferenceEvent, SampleCounters};
+    use crate::traits::Filter;
+
+    #[test]
+    fn test_clock_discipline() -> Result<(), Box<dyn std::error::Error>> {
+        // 1 MHz nominal clock running 25 ppm fast
+        let actual = 1_000_025.0;
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut pulse = vec![0f32; 3_500_100];
+        for second in 0..4 {
+            let edge = 1000 + (second as f64 * actual) as usize;
+            pulse[edge..edge + 100].fill(1.0);
+        }
+        let mut detector = PpsDetector::new(0.5, 500_000);
+        let mut edges = Vec::new();
+        detector.filter(&pulse, &mut edges)?;
+        assert_eq!(edges.len(), 4);
+
+        let mut clock = ClockDiscipline::new(1e6, 16);
+        for (second, &sample) in edges.iter().enumerate() {
+            clock.add_reference(ReferenceEvent { sample, time: start + Duration::from_secs(second as u64) });
+        }
+        assert!(clock.locked());
+        assert!((clock.ppm() - 25.0).abs() < 1.0, "ppm {}", clock.ppm());
+
+        let half = clock.time_of(edges[1] + 500_012).unwrap();
+        let expected = start + Duration::from_millis(1500);
+        let error = half.duration_since(expected).or(expected.duration_since(half))?.as_secs_f64();
+        assert!(error < 1e-5, "error {}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clock_mismatch() {
+        // 48 kHz audio produced 100 ppm fast, consumed in bursts of 256
+        let mut mismatch = ClockMismatch::new(Arc::new(SampleCounters::default()), 48_000.0)
+            .window(Duration::from_secs(60), Duration::from_secs(30));
+        let mut estimate = None;
+        for ms in (0..120_000u64).step_by(10) {
+            let t = ms as f64 / 1000.0;
+            let produced = (t * 48_000.0 * (1.0 + 100e-6)) as u64 + 4800;
+            let consumed = (t * 48_000.0) as u64 / 256 * 256;
+            estimate = mismatch.observe(produced, consumed, Duration::from_millis(ms));
+            if ms < 29_900 {
+                assert_eq!(estimate, None);
+            }
+        }
+        let ppm = estimate.unwrap();
+        assert!((ppm - 100.0).abs() < 5.0, "{}", ppm);
+    }
+}
@@ -8,14 +8,66 @@ pub trait Source<I> {
     fn read(&mut self, dst: &mut Vec<I>) -> Result<(), Box<dyn Error>>;
 }
 
+/// A source of several phase coherent channels sampled on a shared clock, e.g. a two antenna receiver.
+/// Every call fills one Vec per channel and all of them end up the same length.
+pub trait CoherentSource<I> {
+    fn channels(&self) -> usize;
+
+    fn read_coherent(&mut self, dst: &mut [Vec<I>]) -> Result<(), Box<dyn Error>>;
+}
+
 pub trait Filter<I, O> {
     fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> Result<(), Box<dyn Error>>;
 }
 
+/// Processes the aligned channels of a `CoherentSource` together.
+pub trait CoherentFilter<I, O> {
+    fn filter_coherent(&mut self, input: &[Vec<I>], output: &mut Vec<O>) -> Result<(), Box<dyn Error>>;
+}
+
 pub trait Sink<O> {
     fn write(&mut self, src: &[O]) -> Result<(), Box<dyn Error>>;
 }
 
+// borrowed blocks work wherever owned ones do, so they can be inspected after a run, and boxed
+// ones so blocks picked at runtime can be chained
+
+impl<I, S: Source<I> + ?Sized> Source<I> for &mut S {
+    fn read(&mut self, dst: &mut Vec<I>) -> Result<(), Box<dyn Error>> {
+        (**self).read(dst)
+    }
+}
+
+impl<I, O, F: Filter<I, O> + ?Sized> Filter<I, O> for &mut F {
+    fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> Result<(), Box<dyn Error>> {
+        (**self).filter(input, output)
+    }
+}
+
+impl<O, S: Sink<O> + ?Sized> Sink<O> for &mut S {
+    fn write(&mut self, src: &[O]) -> Result<(), Box<dyn Error>> {
+        (**self).write(src)
+    }
+}
+
+impl<I, S: Source<I> + ?Sized> Source<I> for Box<S> {
+    fn read(&mut self, dst: &mut Vec<I>) -> Result<(), Box<dyn Error>> {
+        (**self).read(dst)
+    }
+}
+
+impl<I, O, F: Filter<I, O> + ?Sized> Filter<I, O> for Box<F> {
+    fn filter(&mut self, input: &[I], output: &mut Vec<O>) -> Result<(), Box<dyn Error>> {
+        (**self).filter(input, output)
+    }
+}
+
+impl<O, S: Sink<O> + ?Sized> Sink<O> for Box<S> {
+    fn write(&mut self, src: &[O]) -> Result<(), Box<dyn Error>> {
+        (**self).write(src)
+    }
+}
+
 pub trait Arithmetic:
 Add<Output = Self>
 + Sub<Output = Self>
@@ -56,6 +108,32 @@ impl FloatLike for Complex32 {}
 impl FloatLike for Complex64 {}
 
 
+/// Squared magnitude of a real or complex sample, for level measurements.
+pub trait Magnitude: Copy {
+    fn magnitude_sqr(self) -> f32;
+}
+
+
+impl Magnitude for f32 {
+    fn magnitude_sqr(self) -> f32 { self * self }
+}
+
+
+impl Magnitude for f64 {
+    fn magnitude_sqr(self) -> f32 { (self * self) as f32 }
+}
+
+
+impl Magnitude for Complex32 {
+    fn magnitude_sqr(self) -> f32 { self.norm_sqr() }
+}
+
+
+impl Magnitude for Complex64 {
+    fn magnitude_sqr(self) -> f32 { self.norm_sqr() as f32 }
+}
+
+
 pub trait TrigCore: FloatLike {
     fn sin(self) -> Self;
     fn cos(self) -> Self;
@@ -0,0 +1,299 @@
+use std::error::Error;
+use num_complex::Complex32;
+use crate::block::MixerFilter;
+use crate::rate::{RateAware, SampleRate};
+use crate::tag::{Tag, Tagged};
+use crate::traits::*;
+
+
+/// Sources whose center frequency can be changed while running.
+pub trait Tunable {
+    fn frequency(&self) -> u64;
+
+    fn set_frequency(&mut self, frequency: u64) -> Result<(), Box<dyn Error>>;
+}
+
+
+/// Keeps the wanted signal off the hardware's DC spike by tuning the hardware `offset_hz` away and
+/// mixing the signal back to the center. Users only see the logical frequency.
+pub struct TunedSource<S> {
+    source: S,
+    mixer: MixerFilter,
+    sample_rate: u32,
+    frequency: u64,
+    offset_hz: i64,
+    buffer: Vec<Complex32>,
+}
+
+
+impl<S: Source<Complex32> + Tunable> TunedSource<S> {
+    /// Tunes `source` so that `frequency` ends up at the center of the output.
+    pub fn new(mut source: S, sample_rate: u32, frequency: u64, offset_hz: i64) -> Result<Self, Box<dyn Error>> {
+        source.set_frequency(frequency.checked_add_signed(offset_hz).ok_or("tune offset below 0 Hz")?)?;
+        Ok(Self {
+            source,
+            mixer: MixerFilter::new(sample_rate, offset_hz as f32),
+            sample_rate,
+            frequency,
+            offset_hz,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// An offset a quarter of the sample rate below, out of the DC spike and still clear of the band edge.
+    pub fn default_offset(sample_rate: u32) -> i64 {
+        -(sample_rate as i64 / 4)
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset_hz
+    }
+
+    pub fn set_offset(&mut self, offset_hz: i64) -> Result<(), Box<dyn Error>> {
+        self.source.set_frequency(self.frequency.checked_add_signed(offset_hz).ok_or("tune offset below 0 Hz")?)?;
+        self.offset_hz = offset_hz;
+        self.mixer.set_frequency(offset_hz as f32);
+        Ok(())
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.source
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+}
+
+
+impl<S: Source<Complex32> + Tunable> Tunable for TunedSource<S> {
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn set_frequency(&mut self, frequency: u64) -> Result<(), Box<dyn Error>> {
+        self.source.set_frequency(frequency.checked_add_signed(self.offset_hz).ok_or("tune offset below 0 Hz")?)?;
+        self.frequency = frequency;
+        Ok(())
+    }
+}
+
+
+impl<S: Source<Complex32>> Source<Complex32> for TunedSource<S> {
+    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        self.source.read(&mut self.buffer)?;
+        Filter::<Complex32, Complex32>::filter(&mut self.mixer, &self.buffer, dst)
+    }
+}
+
+
+impl<S: Tagged> Tagged for TunedSource<S> {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        self.source.take_tags(dst);
+    }
+}
+
+
+impl<S: RateAware> RateAware for TunedSource<S> {
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+
+    fn delay(&self) -> f64 {
+        self.source.delay()
+    }
+}
+
+
+/// Relation between on-air frequencies and what the hardware is tuned to when an external converter
+/// is in front of it. A high side LO mirrors the spectrum, which `inverted` undoes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrequencyOffset {
+    /// Added to the on-air frequency to get the hardware frequency, e.g. +125 MHz for an HF upconverter
+    /// or -116 MHz for a 2 m transverter with a 28 MHz IF. With `inverted` it is the LO frequency itself.
+    pub offset_hz: i64,
+    pub inverted: bool,
+}
+
+
+impl FrequencyOffset {
+    pub fn new(offset_hz: i64, inverted: bool) -> Self {
+        Self {
+            offset_hz,
+            inverted,
+        }
+    }
+
+    pub fn to_hardware(&self, on_air: u64) -> Result<u64, Box<dyn Error>> {
+        let hardware = if self.inverted {
+            self.offset_hz.checked_sub_unsigned(on_air).and_then(|f| u64::try_from(f).ok())
+        } else {
+            on_air.checked_add_signed(self.offset_hz)
+        };
+        Ok(hardware.ok_or("frequency not reachable through the converter")?)
+    }
+
+    pub fn to_air(&self, hardware: u64) -> Result<u64, Box<dyn Error>> {
+        let on_air = if self.inverted {
+            self.offset_hz.checked_sub_unsigned(hardware).and_then(|f| u64::try_from(f).ok())
+        } else {
+            self.offset_hz.checked_neg().and_then(|offset| hardware.checked_add_signed(offset))
+        };
+        Ok(on_air.ok_or("hardware frequency not reachable through the converter")?)
+    }
+}
+
+
+/// Puts a transverter or converter between the user and the hardware so requested and reported
+/// frequencies are on-air frequencies.
+pub struct Converter<S> {
+    source: S,
+    offset: FrequencyOffset,
+}
+
+
+impl<S> Converter<S> {
+    pub fn new(source: S, offset: FrequencyOffset) -> Self {
+        Self {
+            source,
+            offset,
+        }
+    }
+
+    pub fn offset(&self) -> FrequencyOffset {
+        self.offset
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.source
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+}
+
+
+impl<S: Tunable> Tunable for Converter<S> {
+    /// 0 when the hardware was tuned past the converter through `inner_mut`, `set_frequency` only
+    /// tunes it where the converter reaches.
+    fn frequency(&self) -> u64 {
+        self.offset.to_air(self.source.frequency()).unwrap_or(0)
+    }
+
+    fn set_frequency(&mut self, frequency: u64) -> Result<(), Box<dyn Error>> {
+        self.source.set_frequency(self.offset.to_hardware(frequency)?)
+    }
+}
+
+
+impl<S: Source<Complex32>> Source<Complex32> for Converter<S> {
+    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        self.source.read(dst)?;
+        if self.offset.inverted {
+            dst.iter_mut().for_each(|x| *x = x.conj());
+        }
+        Ok(())
+    }
+}
+
+
+impl<S: Tagged> Tagged for Converter<S> {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        self.source.take_tags(dst);
+    }
+}
+
+
+impl<S: RateAware> RateAware for Converter<S> {
+    fn output_rate(&self, input: Option<SampleRate>) -> Option<SampleRate> {
+        self.source.output_rate(input)
+    }
+
+    fn delay(&self) -> f64 {
+        self.source.delay()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::traits::Source;
+    use crate::tuning::{Converter, FrequencyOffset, Tunable, TunedSource};
+
+    /// A carrier at a fixed frequency as seen by a receiver tuned to `frequency`.
+    struct Carrier {
+        carrier: u64,
+        frequency: u64,
+        sample_rate: u32,
+        n: usize,
+    }
+
+    impl Source<Complex32> for Carrier {
+        fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+            let offset = self.carrier as f32 - self.frequency as f32;
+            dst.clear();
+            dst.extend((self.n..self.n + 1000).map(|n| Complex32::from_polar(1.0, 2.0 * PI * offset * n as f32 / self.sample_rate as f32)));
+            self.n += 1000;
+            Ok(())
+        }
+    }
+
+    impl Tunable for Carrier {
+        fn frequency(&self) -> u64 {
+            self.frequency
+        }
+
+        fn set_frequency(&mut self, frequency: u64) -> Result<(), Box<dyn Error>> {
+            self.frequency = frequency;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tuned_source() -> Result<(), Box<dyn Error>> {
+        let carrier = Carrier { carrier: 100_000_000, frequency: 0, sample_rate: 1_000_000, n: 0 };
+        let mut source = TunedSource::new(carrier, 1_000_000, 100_000_000, -250_000)?;
+        assert_eq!(source.inner().frequency(), 99_750_000);
+
+        // the carrier sits at DC of the output while the hardware is tuned away from it
+        let mut dst = Vec::new();
+        source.read(&mut dst)?;
+        assert!(dst.windows(2).all(|w| (w[1] * w[0].conj()).arg().abs() < 1e-3));
+
+        source.set_frequency(100_010_000)?;
+        assert_eq!(source.frequency(), 100_010_000);
+        assert_eq!(source.inner().frequency(), 99_760_000);
+        source.read(&mut dst)?;
+        let step = (dst[1] * dst[0].conj()).arg();
+        assert!((step - (-2.0 * PI * 10e3 / 1e6)).abs() < 1e-3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_converter() -> Result<(), Box<dyn Error>> {
+        // 2 m transverter with a high side 172 MHz LO, 144.2 MHz ends up at 27.8 MHz and mirrored
+        let offset = FrequencyOffset::new(172_000_000, true);
+        let carrier = Carrier { carrier: 172_000_000 - 144_210_000, frequency: 0, sample_rate: 1_000_000, n: 0 };
+        let converter = Converter::new(carrier, offset);
+        let mut source = TunedSource::new(converter, 1_000_000, 144_200_000, -250_000)?;
+        assert_eq!(source.inner().inner().frequency(), 172_000_000 - 143_950_000);
+        assert_eq!(source.inner().frequency(), 143_950_000);
+        assert!(FrequencyOffset::new(172_000_000, true).to_hardware(200_000_000).is_err());
+        assert_eq!(offset.to_air(27_800_000)?, 144_200_000);
+        assert!(offset.to_air(200_000_000).is_err());
+        assert!(FrequencyOffset::new(116_000_000, false).to_air(28_000_000).is_err());
+        assert!(FrequencyOffset::new(i64::MIN, false).to_air(0).is_err());
+
+        // the on-air signal 10 kHz above the tuned frequency shows up 10 kHz above DC
+        let mut dst = Vec::new();
+        source.read(&mut dst)?;
+        let step = (dst[1] * dst[0].conj()).arg();
+        assert!((step - 2.0 * PI * 10e3 / 1e6).abs() < 1e-3);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,410 @@
+use std::error::Error;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use num_complex::Complex;
+use crate::rate::{RateAware, SampleRate};
+use crate::replay::Recordable;
+use crate::traits::*;
+use crate::vita49::unspecified;
+
+
+const MAGIC: [u8; 2] = *b"IQ";
+/// Magic, sample type, flags and a little endian u32 sequence number.
+pub const HEADER_LEN: usize = 8;
+/// Largest payload that fits an ethernet MTU without fragmenting.
+const MAX_DATAGRAM: usize = 1472;
+const COMPLEX: u8 = 0x80;
+/// Flag: the samples are compressed into one zstd frame.
+const ZSTD: u8 = 0x01;
+/// Datagrams this far behind the expected sequence number are late, further back the sender restarted.
+const REORDER_WINDOW: u32 = 64;
+/// Most bytes of samples a compressed datagram carries. Even when they don't compress at all they
+/// fit the 65507 bytes a UDP datagram can hold.
+#[cfg(feature = "zstd")]
+const MAX_BATCH: usize = 60 << 10;
+
+
+/// None for a payload that isn't a zstd frame or holds more than a datagram can, e.g. one damaged
+/// on the way.
+#[cfg(feature = "zstd")]
+fn decompress(payload: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    Ok(zstd::bulk::decompress(payload, MAX_BATCH).ok())
+}
+
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    Err("received compressed samples, built without the zstd feature".into())
+}
+
+
+/// Sample types that can go over `UdpSink`, identified by a type code in every datagram.
+pub trait UdpSample: Recordable {
+    const TYPE: u8;
+}
+
+
+impl UdpSample for u8 {
+    const TYPE: u8 = 1;
+}
+
+
+impl UdpSample for i8 {
+    const TYPE: u8 = 2;
+}
+
+
+impl UdpSample for i16 {
+    const TYPE: u8 = 3;
+}
+
+
+impl UdpSample for f32 {
+    const TYPE: u8 = 4;
+}
+
+
+impl<T: UdpSample> UdpSample for Complex<T> {
+    const TYPE: u8 = COMPLEX | T::TYPE;
+}
+
+
+/// Streams raw samples to a UDP destination, each datagram prefixed with a small header so the
+/// receiver can spot lost datagrams and samples of the wrong type.
+pub struct UdpSink<T: UdpSample> {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    sample_rate: Option<u32>,
+    samples_per_datagram: usize,
+    #[cfg(feature = "zstd")]
+    compression: Option<i32>,
+    /// Samples per compressed datagram, following the compression ratio.
+    #[cfg(feature = "zstd")]
+    batch: usize,
+    sequence: u32,
+    datagram: Vec<u8>,
+    _marker: PhantomData<T>,
+}
+
+
+impl<T: UdpSample> UdpSink<T> {
+    pub fn new(destination: impl ToSocketAddrs) -> Result<Self, Box<dyn Error>> {
+        let destination = destination.to_socket_addrs()?.next().ok_or("no destination address")?;
+        Ok(Self {
+            socket: UdpSocket::bind(unspecified(&destination))?,
+            destination,
+            sample_rate: None,
+            samples_per_datagram: (MAX_DATAGRAM - HEADER_LEN) / T::SIZE,
+            #[cfg(feature = "zstd")]
+            compression: None,
+            #[cfg(feature = "zstd")]
+            batch: 2 * (MAX_DATAGRAM - HEADER_LEN) / T::SIZE,
+            sequence: 0,
+            datagram: Vec::new(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Defaults to as many as fit an ethernet MTU.
+    pub fn set_samples_per_datagram(&mut self, samples: usize) {
+        self.samples_per_datagram = samples.max(1);
+    }
+
+    /// Only used for rate checks, nothing about the rate is sent.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Some(sample_rate);
+    }
+
+    /// Compress every datagram with zstd at `level`, None sends raw samples. Datagrams then carry as
+    /// many samples as compressed the last time into one MTU, so one may now and then come out
+    /// larger when the signal changes and get fragmented by IP. Samples that don't compress go raw,
+    /// at most 60 KiB of them. The receiver needs the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    pub fn set_compression(&mut self, level: Option<i32>) {
+        self.compression = level;
+    }
+
+    fn send(&mut self, flags: u8, payload: impl FnOnce(&mut Vec<u8>)) -> Result<(), Box<dyn Error>> {
+        self.datagram.clear();
+        self.datagram.extend_from_slice(&MAGIC);
+        self.datagram.extend_from_slice(&[T::TYPE, flags]);
+        self.datagram.extend_from_slice(&self.sequence.to_le_bytes());
+        payload(&mut self.datagram);
+        self.socket.send_to(&self.datagram, self.destination)?;
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    fn send_raw(&mut self, samples: &[T]) -> Result<(), Box<dyn Error>> {
+        self.send(0, |datagram| for sample in samples {
+            sample.write_le(datagram);
+        })
+    }
+
+    #[cfg(feature = "zstd")]
+    fn write_compressed(&mut self, mut src: &[T], level: i32) -> Result<(), Box<dyn Error>> {
+        let target = MAX_DATAGRAM - HEADER_LEN;
+        let mut raw = Vec::new();
+        while !src.is_empty() {
+            let (chunk, rest) = src.split_at(self.batch.min(src.len()));
+            src = rest;
+            raw.clear();
+            for sample in chunk {
+                sample.write_le(&mut raw);
+            }
+            let compressed = zstd::bulk::compress(&raw, level)?;
+            if compressed.len() < raw.len() {
+                self.send(ZSTD, |datagram| datagram.extend_from_slice(&compressed))?;
+            } else {
+                self.send_raw(chunk)?;
+            }
+            let fit = chunk.len() * target / compressed.len().max(1);
+            self.batch = fit.clamp(1, (2 * self.batch).min(MAX_BATCH / T::SIZE));
+        }
+        Ok(())
+    }
+}
+
+
+impl<T: UdpSample> Sink<T> for UdpSink<T> {
+    fn write(&mut self, src: &[T]) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "zstd")]
+        if let Some(level) = self.compression {
+            return self.write_compressed(src, level);
+        }
+        for chunk in src.chunks(self.samples_per_datagram) {
+            self.send_raw(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl<T: UdpSample> RateAware for UdpSink<T> {
+    fn input_rate(&self) -> Option<SampleRate> {
+        self.sample_rate.map(SampleRate)
+    }
+}
+
+
+/// Receives what a `UdpSink` sends, one datagram per read. Datagrams from other programs are
+/// skipped, ones carrying another sample type are an error. Datagrams arriving after later ones
+/// were played are dropped, they stay counted as lost. So are compressed ones that don't decompress.
+pub struct UdpSource<T: UdpSample> {
+    socket: UdpSocket,
+    sample_rate: Option<u32>,
+    next_sequence: Option<u32>,
+    lost: u64,
+    reordered: u64,
+    invalid: u64,
+    buffer: Vec<u8>,
+    _marker: PhantomData<T>,
+}
+
+
+impl<T: UdpSample> UdpSource<T> {
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            socket: UdpSocket::bind(address)?,
+            sample_rate: None,
+            next_sequence: None,
+            lost: 0,
+            reordered: 0,
+            invalid: 0,
+            buffer: vec![0u8; 65536],
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// A read that sees no datagram within `timeout` returns no samples.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        Ok(self.socket.set_read_timeout(timeout)?)
+    }
+
+    /// The sender's rate, for rate checks downstream.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Some(sample_rate);
+    }
+
+    /// Datagrams missed according to the sequence numbers.
+    pub fn lost_datagrams(&self) -> u64 {
+        self.lost
+    }
+
+    /// Datagrams dropped for arriving out of order, each of them also counted as lost.
+    pub fn reordered_datagrams(&self) -> u64 {
+        self.reordered
+    }
+
+    /// Compressed datagrams skipped for not decompressing.
+    pub fn invalid_datagrams(&self) -> u64 {
+        self.invalid
+    }
+}
+
+
+impl<T: UdpSample> Source<T> for UdpSource<T> {
+    fn read(&mut self, dst: &mut Vec<T>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        loop {
+            let len = match self.socket.recv(&mut self.buffer) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            let datagram = &self.buffer[..len];
+            if len < HEADER_LEN || datagram[..2] != MAGIC {
+                continue;
+            }
+            if datagram[2] != T::TYPE {
+                return Err(format!("received sample type {:#04x}, expected {:#04x}", datagram[2], T::TYPE).into());
+            }
+
+            let sequence = u32::from_le_bytes(datagram[4..8].try_into().unwrap());
+            let gap = self.next_sequence.map_or(0, |expected| sequence.wrapping_sub(expected));
+            if gap < 1 << 31 {
+                self.lost += gap as u64;
+                self.next_sequence = Some(sequence.wrapping_add(1));
+            } else if gap.wrapping_neg() > REORDER_WINDOW {
+                // a gap of more than half the sequence space is a restart, nothing was lost
+                self.next_sequence = Some(sequence.wrapping_add(1));
+            } else {
+                // the samples after it already went out
+                self.reordered += 1;
+                continue;
+            }
+            if datagram[3] & ZSTD != 0 {
+                let Some(raw) = decompress(&datagram[HEADER_LEN..])? else {
+                    self.invalid += 1;
+                    continue;
+                };
+                dst.extend(raw.chunks_exact(T::SIZE).map(T::read_le));
+            } else {
+                dst.extend(datagram[HEADER_LEN..].chunks_exact(T::SIZE).map(T::read_le));
+            }
+            return Ok(());
+        }
+    }
+}
+
+
+impl<T: UdpSample> RateAware for UdpSource<T> {
+    fn output_rate(&self, _input: Option<SampleRate>) -> Option<SampleRate> {
+        self.sample_rate.map(SampleRate)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+    use num_complex::{Complex, Complex32};
+    #[cfg(feature = "zstd")]
+    use crate::seed::Rng;
+    use crate::traits::{Sink, Source};
+    use crate::udp::{UdpSink, UdpSource};
+
+    #[test]
+    fn test_udp_iq() -> Result<(), Box<dyn Error>> {
+        let mut source = UdpSource::<Complex<i16>>::bind("127.0.0.1:0")?;
+        source.set_timeout(Some(Duration::from_millis(500)))?;
+        let mut sink = UdpSink::new(source.local_addr()?)?;
+        sink.set_samples_per_datagram(100);
+        let samples: Vec<Complex<i16>> = (0..250).map(|n| Complex::new(n, -n)).collect();
+        sink.write(&samples)?;
+
+        let mut received = Vec::new();
+        let mut buffer = Vec::new();
+        while received.len() < samples.len() {
+            source.read(&mut buffer)?;
+            assert!(!buffer.is_empty());
+            received.extend_from_slice(&buffer);
+        }
+        assert_eq!(received, samples);
+        assert_eq!(source.lost_datagrams(), 0);
+
+        // a skipped sequence number counts as lost, strangers are ignored
+        let stranger = UdpSocket::bind("127.0.0.1:0")?;
+        stranger.send_to(b"hello", source.local_addr()?)?;
+        stranger.send_to(&[b'I', b'Q', 0x83, 0, 5, 0, 0, 0, 1, 0, 2, 0], source.local_addr()?)?;
+        source.read(&mut buffer)?;
+        assert_eq!(buffer, [Complex::new(1, 2)]);
+        assert_eq!(source.lost_datagrams(), 2);
+
+        // a late datagram isn't a gap of four billion, it's dropped and the one after it is on time
+        for sequence in [3, 6] {
+            stranger.send_to(&[b'I', b'Q', 0x83, 0, sequence, 0, 0, 0, sequence, 0, 2, 0], source.local_addr()?)?;
+        }
+        source.read(&mut buffer)?;
+        assert_eq!(buffer, [Complex::new(6, 2)]);
+        assert_eq!((source.lost_datagrams(), source.reordered_datagrams()), (2, 1));
+        // neither is a sender starting over from zero
+        stranger.send_to(&[b'I', b'Q', 0x83, 0, 0xe8, 0x03, 0, 0, 1, 0, 2, 0], source.local_addr()?)?;
+        source.read(&mut buffer)?;
+        let lost = source.lost_datagrams();
+        for sequence in [0, 1] {
+            stranger.send_to(&[b'I', b'Q', 0x83, 0, sequence, 0, 0, 0, 1, 0, 2, 0], source.local_addr()?)?;
+            source.read(&mut buffer)?;
+        }
+        assert_eq!(source.lost_datagrams(), lost);
+
+        // the wrong sample type is an error
+        UdpSink::<Complex32>::new(source.local_addr()?)?.write(&[Complex32::new(0.5, 0.5)])?;
+        assert!(source.read(&mut buffer).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_udp_zstd() -> Result<(), Box<dyn Error>> {
+        let mut source = UdpSource::<Complex<i8>>::bind("127.0.0.1:0")?;
+        source.set_timeout(Some(Duration::from_millis(500)))?;
+        let mut sink = UdpSink::new(source.local_addr()?)?;
+        sink.set_compression(Some(3));
+        // a tone repeating every 100 samples compresses well, so datagrams grow past what fits raw
+        let mut samples: Vec<Complex<i8>> = (0..100_000).map(|n| {
+            let phase = (n % 100) as f32 * std::f32::consts::TAU / 100.0;
+            Complex::new((100.0 * phase.cos()) as i8, (100.0 * phase.sin()) as i8)
+        }).collect();
+        // then noise that doesn't compress at all still has to fit a datagram
+        let mut rng = Rng::new(1);
+        samples.extend((0..40_000).map(|_| Complex::new(rng.next_u32() as i8, rng.next_u32() as i8)));
+        let sent = samples.clone();
+        let sender = std::thread::spawn(move || -> Result<(), String> {
+            for block in sent.chunks(50_000) {
+                sink.write(block).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        });
+
+        let mut received = Vec::new();
+        let mut buffer = Vec::new();
+        let mut datagrams = 0;
+        while received.len() < samples.len() {
+            source.read(&mut buffer)?;
+            assert!(!buffer.is_empty());
+            received.extend_from_slice(&buffer);
+            datagrams += 1;
+        }
+        sender.join().unwrap()?;
+        assert_eq!(received, samples);
+        assert!(datagrams < samples.len() * 2 / (1472 - 8), "{} datagrams", datagrams);
+
+        // a corrupt frame is skipped, the stream goes on with the next datagram
+        let stranger = UdpSocket::bind("127.0.0.1:0")?;
+        stranger.send_to(&[b'I', b'Q', 0x82, 0x01, 0, 0x10, 0, 0, 0xde, 0xad, 0xbe, 0xef], source.local_addr()?)?;
+        stranger.send_to(&[b'I', b'Q', 0x82, 0, 1, 0x10, 0, 0, 3, 4], source.local_addr()?)?;
+        source.read(&mut buffer)?;
+        assert_eq!(buffer, [Complex::new(3, 4)]);
+        assert_eq!(source.invalid_datagrams(), 1);
+        Ok(())
+    }
+}
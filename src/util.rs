@@ -1,6 +1,8 @@
+use std::f32::consts::PI;
 use num_complex::Complex32;
 use num_traits::One;
 use crate::block::FIRFilter;
+use crate::error::{check_nyquist, check_taps, ConfigError};
 use crate::traits::{FloatLike, Trig};
 
 #[derive(Default)]
@@ -63,3 +65,128 @@ pub fn lowpass_complex(sample_rate: u32, cutoff_hz: f32, num_taps: usize) -> FIR
     let complex_taps = taps.iter().copied().map(|r| Complex32::new(r, 0.0)).collect();
     FIRFilter::new(complex_taps)
 }
+
+
+/// Complex taps passing `low_hz..high_hz` only, negative frequencies select the lower sideband. Unity gain in the passband.
+/// Ordered for `FIRFilter`, which applies the first tap to the oldest sample.
+pub fn bandpass_complex_taps(sample_rate: u32, low_hz: f32, high_hz: f32, num_taps: usize) -> Vec<Complex32> {
+    let half_width = 0.5 * (high_hz - low_hz).abs() / sample_rate as f32;
+    let center = 0.5 * (low_hz + high_hz) / sample_rate as f32;
+    let taps = lowpass_taps(half_width, num_taps);
+    let gain: f32 = taps.iter().sum();
+    let m = (num_taps - 1) as f32 / 2.0;
+    taps.iter().enumerate().map(|(n, &t)| Complex32::from_polar(t / gain, 2.0 * std::f32::consts::PI * center * (m - n as f32))).collect()
+}
+
+
+/// Real taps rejecting `low_hz..high_hz` with unity gain elsewhere, by spectral inversion of a bandpass.
+/// The inversion needs a center tap, so `num_taps` has to be odd.
+pub fn bandstop_taps(sample_rate: u32, low_hz: f32, high_hz: f32, num_taps: usize) -> Result<Vec<f32>, ConfigError> {
+    check_taps(num_taps)?;
+    let unity = |cutoff_hz: f32| {
+        let taps = lowpass_taps(cutoff_hz / sample_rate as f32, num_taps);
+        let gain: f32 = taps.iter().sum();
+        taps.into_iter().map(move |t| t / gain)
+    };
+    let mut taps: Vec<f32> = unity(low_hz).zip(unity(high_hz)).map(|(low, high)| low - high).collect();
+    taps[num_taps / 2] += 1.0;
+    Ok(taps)
+}
+
+
+/// Broadcast AM channel raster, which sets the pitch of the whistle from an adjacent carrier.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AmRegion {
+    /// 9 kHz spacing, ITU regions 1 and 3 (Europe, Africa, Asia, Oceania).
+    Itu1And3,
+    /// 10 kHz spacing, ITU region 2 (the Americas).
+    Itu2,
+}
+
+
+impl AmRegion {
+    pub fn spacing_hz(&self) -> f32 {
+        match self {
+            AmRegion::Itu1And3 => 9000.0,
+            AmRegion::Itu2 => 10000.0,
+        }
+    }
+}
+
+
+/// Notch for the adjacent channel whistle in demodulated AM audio at `sample_rate`.
+pub fn whistle_notch(sample_rate: u32, region: AmRegion) -> Result<FIRFilter<f32>, ConfigError> {
+    let spacing = region.spacing_hz();
+    check_nyquist("whistle", (spacing + 300.0) as f64, sample_rate)?;
+    Ok(FIRFilter::new(bandstop_taps(sample_rate, spacing - 300.0, spacing + 300.0, 255)?))
+}
+
+
+/// Validating form of `lowpass_real`/`lowpass_complex`.
+#[derive(Clone, Debug)]
+pub struct LowpassBuilder {
+    sample_rate: u32,
+    cutoff_hz: f32,
+    num_taps: usize,
+}
+
+
+impl LowpassBuilder {
+    pub fn new(sample_rate: u32, cutoff_hz: f32) -> Self {
+        Self {
+            sample_rate,
+            cutoff_hz,
+            num_taps: 101,
+        }
+    }
+
+    pub fn num_taps(mut self, num_taps: usize) -> Self {
+        self.num_taps = num_taps;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.cutoff_hz <= 0.0 {
+            return Err(ConfigError::Invalid(format!("cutoff must be positive, got {}", self.cutoff_hz)));
+        }
+        check_nyquist("cutoff", self.cutoff_hz as f64, self.sample_rate)?;
+        check_taps(self.num_taps)
+    }
+
+    pub fn build_real(self) -> Result<FIRFilter<f32>, ConfigError> {
+        self.validate()?;
+        Ok(lowpass_real(self.sample_rate, self.cutoff_hz, self.num_taps))
+    }
+
+    pub fn build_complex(self) -> Result<FIRFilter<Complex32>, ConfigError> {
+        self.validate()?;
+        Ok(lowpass_complex(self.sample_rate, self.cutoff_hz, self.num_taps))
+    }
+}
+
+
+pub fn rrc_taps(samples_per_symbol: f32, alpha: f32, num_taps: usize) -> Vec<f32> {
+    let center = (num_taps as f32 - 1.0) / 2.0;
+
+    let mut taps = Vec::with_capacity(num_taps);
+    for n in 0..num_taps {
+        let t = (n as f32 - center) / samples_per_symbol;
+        let tap = if t == 0.0 {
+            1.0 - alpha + 4.0 * alpha / PI
+        } else if alpha > 0.0 && (t.abs() - 1.0 / (4.0 * alpha)).abs() < 1e-6 {
+            alpha / 2f32.sqrt() * ((1.0 + 2.0 / PI) * (PI / (4.0 * alpha)).sin() + (1.0 - 2.0 / PI) * (PI / (4.0 * alpha)).cos())
+        } else {
+            let num = (PI * t * (1.0 - alpha)).sin() + 4.0 * alpha * t * (PI * t * (1.0 + alpha)).cos();
+            let den = PI * t * (1.0 - (4.0 * alpha * t).powi(2));
+            num / den
+        };
+        taps.push(tap);
+    }
+
+    let sum: f32 = taps.iter().sum();
+    for tap in taps.iter_mut() {
+        *tap /= sum;
+    }
+
+    taps
+}
@@ -0,0 +1,283 @@
+use std::error::Error;
+use num_complex::Complex32;
+use crate::block::{MixerFilter, RationalResampler, RationalResamplerBuilder};
+use crate::channel::ChannelFilter;
+use crate::demod::{DemodMode, DemodSelector};
+use crate::rate::{RateAware, SampleRate};
+use crate::traits::Filter;
+
+
+/// Channel filter cutoff used for each mode.
+pub fn mode_cutoff(mode: DemodMode) -> f32 {
+    match mode {
+        DemodMode::FM => 6e3,
+        DemodMode::AM => 5e3,
+        DemodMode::USB | DemodMode::LSB => 3e3,
+        DemodMode::CW => 1e3,
+    }
+}
+
+
+/// Polyphase resampler between two rates, with an odd number of taps that covers every branch.
+fn resampler(start: u32, end: u32) -> Result<RationalResampler<Complex32>, Box<dyn Error>> {
+    Ok(RationalResamplerBuilder::new(start, end).taps_per_ratio().build()?)
+}
+
+
+/// Largest integer decimation of `sample_rate` that still leaves twice the audio rate, so the
+/// rational stage behind it works on a small ratio instead of e.g. 2 MS/s to 44.1 kHz in one go.
+fn decimation(sample_rate: u32, audio_rate: u32) -> u32 {
+    (1..=sample_rate / (2 * audio_rate).max(1)).rev().find(|&d| sample_rate.is_multiple_of(d)).unwrap_or(1)
+}
+
+
+/// One receiver inside a wideband capture: shifts its frequency to baseband, resamples to the
+/// audio rate, filters the channel and demodulates.
+pub struct Vfo {
+    sample_rate: u32,
+    audio_rate: u32,
+    center: u64,
+    frequency: u64,
+    mixer: MixerFilter,
+    decimator: Option<RationalResampler<Complex32>>,
+    resampler: RationalResampler<Complex32>,
+    channel: ChannelFilter,
+    demod: DemodSelector,
+    mixed: Vec<Complex32>,
+    decimated: Vec<Complex32>,
+    baseband: Vec<Complex32>,
+    filtered: Vec<Complex32>,
+}
+
+
+impl Vfo {
+    pub fn new(sample_rate: u32, center: u64, audio_rate: u32, frequency: u64, mode: DemodMode) -> Result<Self, Box<dyn Error>> {
+        let decimation = decimation(sample_rate, audio_rate);
+        let decimator = if decimation > 1 { Some(resampler(sample_rate, sample_rate / decimation)?) } else { None };
+        let mut vfo = Self {
+            sample_rate,
+            audio_rate,
+            center,
+            frequency: center,
+            mixer: MixerFilter::new(sample_rate, 0.0),
+            decimator,
+            resampler: resampler(sample_rate / decimation, audio_rate)?,
+            channel: ChannelFilter::new(audio_rate, mode_cutoff(mode), 129),
+            demod: DemodSelector::new(audio_rate, mode),
+            mixed: Vec::new(),
+            decimated: Vec::new(),
+            baseband: Vec::new(),
+            filtered: Vec::new(),
+        };
+        vfo.set_frequency(frequency)?;
+        Ok(vfo)
+    }
+
+    pub fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    pub fn mode(&self) -> DemodMode {
+        self.demod.mode()
+    }
+
+    /// Fails if the channel would fall outside the capture bandwidth.
+    pub fn set_frequency(&mut self, frequency: u64) -> Result<(), Box<dyn Error>> {
+        let offset = self.offset(self.center, frequency)?;
+        self.frequency = frequency;
+        self.mixer.set_frequency(-offset as f32);
+        Ok(())
+    }
+
+    /// Offset of `frequency` in a capture around `center`, if the channel fits inside it.
+    fn offset(&self, center: u64, frequency: u64) -> Result<i64, Box<dyn Error>> {
+        let offset = frequency as i64 - center as i64;
+        let limit = (self.sample_rate / 2) as i64 - (self.audio_rate / 2) as i64;
+        if offset.abs() > limit {
+            return Err(format!("{} Hz is outside the capture, {} Hz ± {} Hz", frequency, center, limit).into());
+        }
+        Ok(offset)
+    }
+
+    pub fn set_mode(&mut self, mode: DemodMode) {
+        if mode != self.demod.mode() {
+            self.demod.set_mode(mode);
+            self.channel.set_cutoff(mode_cutoff(mode));
+        }
+    }
+
+    /// Follow a retune of the capture, keeping the VFO on its frequency. Fails and changes nothing
+    /// if the frequency is no longer inside.
+    pub fn set_center(&mut self, center: u64) -> Result<(), Box<dyn Error>> {
+        let offset = self.offset(center, self.frequency)?;
+        self.center = center;
+        self.mixer.set_frequency(-offset as f32);
+        Ok(())
+    }
+}
+
+
+impl Filter<Complex32, f32> for Vfo {
+    fn filter(&mut self, input: &[Complex32], output: &mut Vec<f32>) -> Result<(), Box<dyn Error>> {
+        self.mixer.filter(input, &mut self.mixed)?;
+        let mixed = match &mut self.decimator {
+            Some(decimator) => {
+                decimator.filter(&self.mixed, &mut self.decimated)?;
+                &self.decimated
+            },
+            None => &self.mixed,
+        };
+        self.resampler.filter(mixed, &mut self.baseband)?;
+        self.channel.filter(&self.baseband, &mut self.filtered)?;
+        self.demod.filter(&self.filtered, output)
+    }
+}
+
+
+impl RateAware for Vfo {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.sample_rate))
+    }
+
+    fn output_rate(&self, _: Option<SampleRate>) -> Option<SampleRate> {
+        Some(SampleRate(self.audio_rate))
+    }
+}
+
+
+/// Any number of VFOs fed from the same capture, e.g. one per connected client.
+pub struct MultiVfo {
+    sample_rate: u32,
+    center: u64,
+    audio_rate: u32,
+    vfos: Vec<(usize, Vfo)>,
+    next_id: usize,
+    audio: Vec<f32>,
+}
+
+
+impl MultiVfo {
+    pub fn new(sample_rate: u32, center: u64, audio_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            center,
+            audio_rate,
+            vfos: Vec::new(),
+            next_id: 0,
+            audio: Vec::new(),
+        }
+    }
+
+    pub fn center(&self) -> u64 {
+        self.center
+    }
+
+    /// Returns the id used to address the new VFO.
+    pub fn add(&mut self, frequency: u64, mode: DemodMode) -> Result<usize, Box<dyn Error>> {
+        let vfo = Vfo::new(self.sample_rate, self.center, self.audio_rate, frequency, mode)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.vfos.push((id, vfo));
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Vfo> {
+        let index = self.vfos.iter().position(|(i, _)| *i == id)?;
+        Some(self.vfos.remove(index).1)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Vfo> {
+        self.vfos.iter_mut().find(|(i, _)| *i == id).map(|(_, vfo)| vfo)
+    }
+
+    pub fn len(&self) -> usize {
+        self.vfos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vfos.is_empty()
+    }
+
+    /// Retune every VFO to a new capture center, VFOs left outside it keep their old offset and
+    /// the first error is returned.
+    pub fn set_center(&mut self, center: u64) -> Result<(), Box<dyn Error>> {
+        self.center = center;
+        let mut first = Ok(());
+        for (_, vfo) in self.vfos.iter_mut() {
+            if let Err(e) = vfo.set_center(center) && first.is_ok() {
+                first = Err(e);
+            }
+        }
+        first
+    }
+
+    /// Runs every VFO over `input` and hands each one's audio to `on_audio`.
+    pub fn process(&mut self, input: &[Complex32], mut on_audio: impl FnMut(usize, &[f32])) -> Result<(), Box<dyn Error>> {
+        for (id, vfo) in self.vfos.iter_mut() {
+            vfo.filter(input, &mut self.audio)?;
+            on_audio(*id, &self.audio);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::f32::consts::PI;
+    use num_complex::Complex32;
+    use crate::demod::DemodMode;
+    use crate::traits::Filter;
+    use crate::vfo::{MultiVfo, Vfo};
+
+    #[test]
+    fn test_multi_vfo() -> Result<(), Box<dyn Error>> {
+        // two FM stations in a 240 kHz capture, each modulated with its own tone
+        let (rate, center) = (240_000u32, 100_000_000u64);
+        let stations = [(-50e3f32, 400.0f32), (70e3, 1500.0)];
+        let mut phases = [0f32; 2];
+        let input: Vec<Complex32> = (0..rate as usize / 2).map(|n| {
+            let t = n as f32 / rate as f32;
+            stations.iter().zip(phases.iter_mut()).map(|(&(offset, tone), phase)| {
+                *phase = (*phase + 2.0 * PI * (offset + 3e3 * (2.0 * PI * tone * t).sin()) / rate as f32) % (2.0 * PI);
+                Complex32::from_polar(0.5, *phase)
+            }).sum()
+        }).collect();
+
+        let mut vfos = MultiVfo::new(rate, center, 24_000);
+        let a = vfos.add(center - 50_000, DemodMode::FM)?;
+        let b = vfos.add(center + 70_000, DemodMode::FM)?;
+        assert!(vfos.add(center + 115_000, DemodMode::FM).is_err());
+
+        let mut audio = [Vec::new(), Vec::new()];
+        for block in input.chunks(4800) {
+            vfos.process(block, |id, out| audio[if id == a { 0 } else { 1 }].extend_from_slice(out))?;
+        }
+        for (k, (_, tone)) in stations.iter().enumerate() {
+            let settled = &audio[k][2400..];
+            // correlate against each station's tone, the VFO should only hear its own
+            let power = |freq: f32| settled.iter().enumerate()
+                .map(|(n, &x)| Complex32::from_polar(x, -2.0 * PI * freq * n as f32 / 24_000.0))
+                .sum::<Complex32>().norm();
+            let other = stations[1 - k].1;
+            assert!(power(*tone) > 20.0 * power(other), "vfo {}: {} vs {}", k, power(*tone), power(other));
+        }
+
+        assert!(vfos.remove(b).is_some());
+        assert_eq!(vfos.len(), 1);
+
+        // a retune that leaves the VFO outside is refused and leaves it where it was
+        let mut vfo = Vfo::new(rate, center, 24_000, center + 70_000, DemodMode::FM)?;
+        vfo.set_center(center + 50_000)?;
+        assert!(vfo.set_center(center - 50_000).is_err());
+        assert_eq!((vfo.center, vfo.frequency()), (center + 50_000, center + 70_000));
+
+        // large ratios are split into an integer decimation and a short rational stage
+        let mut vfo = Vfo::new(2_000_000, center, 44_100, center, DemodMode::AM)?;
+        let mut out = Vec::new();
+        vfo.filter(&vec![Complex32::new(0.5, 0.0); 200_000], &mut out)?;
+        assert_eq!(out.len(), 4410);
+        Ok(())
+    }
+}
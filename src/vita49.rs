@@ -0,0 +1,558 @@
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use num_complex::{Complex, Complex32};
+use crate::rate::{RateAware, SampleRate};
+use crate::sample::Sample;
+use crate::tag::{Tag, TagValue, Tagged};
+use crate::traits::*;
+
+
+const TYPE_DATA: u32 = 0x1;
+const TYPE_CONTEXT: u32 = 0x4;
+const CLASS_ID: u32 = 1 << 27;
+const TSI_UTC: u32 = 0x1 << 22;
+const TSF_REAL_TIME: u32 = 0x2 << 20;
+
+const CIF_CHANGE: u32 = 1 << 31;
+const CIF_BANDWIDTH: u32 = 1 << 29;
+const CIF_RF_FREQUENCY: u32 = 1 << 27;
+const CIF_SAMPLE_RATE: u32 = 1 << 21;
+
+/// DIFI's IEEE registered OUI, used in the class ID of DIFI packets.
+pub const DIFI_OUI: u32 = 0x6A621E;
+const PICOS_PER_SECOND: u64 = 1_000_000_000_000;
+/// Fits a 1500 byte MTU with the largest header.
+pub const DEFAULT_SAMPLES_PER_PACKET: usize = 360;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    /// Plain VITA-49.2 signal data and context packets without a class ID.
+    Vita49,
+    /// DIFI 1.0 standard flow signal packets, which carry the DIFI class ID.
+    Difi,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Context {
+    pub sample_rate: f64,
+    pub frequency: f64,
+    pub bandwidth: f64,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    Data {
+        stream_id: u32,
+        count: u8,
+        time: SystemTime,
+        samples: Vec<Complex32>,
+    },
+    Context {
+        stream_id: u32,
+        count: u8,
+        time: SystemTime,
+        context: Context,
+    },
+}
+
+
+fn to_fixed(v: f64) -> u64 {
+    (v * (1u64 << 20) as f64).round() as i64 as u64
+}
+
+
+fn from_fixed(v: u64) -> f64 {
+    v as i64 as f64 / (1u64 << 20) as f64
+}
+
+
+fn split_time(time: SystemTime) -> (u32, u64) {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (since.as_secs() as u32, since.subsec_nanos() as u64 * 1000)
+}
+
+
+impl Packet {
+    /// Serialize into `out` as big endian 32-bit words.
+    pub fn encode(&self, profile: Profile, out: &mut Vec<u8>) {
+        let (kind, stream_id, count, time) = match self {
+            Packet::Data { stream_id, count, time, .. } => (TYPE_DATA, stream_id, count, time),
+            Packet::Context { stream_id, count, time, .. } => (TYPE_CONTEXT, stream_id, count, time),
+        };
+        let mut words = vec![0u32, *stream_id];
+        let mut header = kind << 28 | TSI_UTC | TSF_REAL_TIME | (*count as u32 & 0xf) << 16;
+        if profile == Profile::Difi {
+            header |= CLASS_ID;
+            words.push(DIFI_OUI);
+            words.push(if kind == TYPE_DATA { 0x0000_0000 } else { 0x0000_0001 });
+        }
+        let (seconds, picos) = split_time(*time);
+        words.extend([seconds, (picos >> 32) as u32, picos as u32]);
+
+        match self {
+            Packet::Data { samples, .. } => {
+                words.extend(samples.iter().map(|&s| {
+                    let s = Complex::<i16>::from_float(s);
+                    (s.re as u16 as u32) << 16 | s.im as u16 as u32
+                }));
+            },
+            Packet::Context { context, .. } => {
+                words.push(CIF_CHANGE | CIF_BANDWIDTH | CIF_RF_FREQUENCY | CIF_SAMPLE_RATE);
+                for v in [context.bandwidth, context.frequency, context.sample_rate] {
+                    let v = to_fixed(v);
+                    words.extend([(v >> 32) as u32, v as u32]);
+                }
+            },
+        }
+
+        words[0] = header | words.len() as u32;
+        out.clear();
+        out.extend(words.iter().flat_map(|w| w.to_be_bytes()));
+    }
+
+    pub fn decode(src: &[u8]) -> Result<Packet, Box<dyn Error>> {
+        if !src.len().is_multiple_of(4) || src.len() < 8 {
+            return Err("vita49 packet length is not a whole number of words".into());
+        }
+        let mut words: Vec<u32> = src.chunks_exact(4).map(|w| u32::from_be_bytes(w.try_into().unwrap())).collect();
+        let header = words[0];
+        let size = (header & 0xffff) as usize;
+        if size == 0 {
+            return Err("vita49 packet size is zero".into());
+        }
+        if size > words.len() {
+            return Err(format!("vita49 packet is truncated, {} of {} words", words.len(), size).into());
+        }
+        // anything after the packet is padding, the fields below must come from within it
+        words.truncate(size);
+        let kind = header >> 28;
+        let count = (header >> 16 & 0xf) as u8;
+        let has_stream_id = kind & 0x1 == 1 || kind == TYPE_CONTEXT;
+        let mut at = 1;
+        let stream_id = if has_stream_id {
+            at += 1;
+            *words.get(1).ok_or("vita49 header is truncated")?
+        } else {
+            0
+        };
+        if header & CLASS_ID != 0 {
+            at += 2;
+        }
+        let mut time = UNIX_EPOCH;
+        if header >> 22 & 0x3 != 0 {
+            time += Duration::from_secs(*words.get(at).ok_or("vita49 header is truncated")? as u64);
+            at += 1;
+        }
+        if header >> 20 & 0x3 != 0 {
+            let hi = *words.get(at).ok_or("vita49 header is truncated")? as u64;
+            let lo = *words.get(at + 1).ok_or("vita49 header is truncated")? as u64;
+            if header >> 20 & 0x3 == 0x2 {
+                time += Duration::from_nanos((hi << 32 | lo) % PICOS_PER_SECOND / 1000);
+            }
+            at += 2;
+        }
+        // the trailer is never part of the payload
+        let end = if header & (1 << 26) != 0 && kind & 0xe == 0 { size - 1 } else { size };
+        if at > end {
+            return Err(format!("vita49 packet of {} words is too short for its header", size).into());
+        }
+
+        match kind {
+            0x0 | TYPE_DATA => Ok(Packet::Data {
+                stream_id,
+                count,
+                time,
+                samples: words[at..end].iter()
+                    .map(|w| Complex::new((*w >> 16) as u16 as i16, *w as u16 as i16).to_float())
+                    .collect(),
+            }),
+            TYPE_CONTEXT => {
+                let cif = *words.get(at).ok_or("vita49 context packet is truncated")?;
+                at += 1;
+                let mut context = Context { sample_rate: 0.0, frequency: 0.0, bandwidth: 0.0 };
+                // only fields ahead of the sample rate are walked, anything after it is ignored
+                for bit in (CIF_SAMPLE_RATE.trailing_zeros()..31).rev() {
+                    if cif & 1 << bit == 0 {
+                        continue;
+                    }
+                    let width = match bit {
+                        30 | 24 | 23 | 22 => 1,
+                        29 | 28 | 27 | 26 | 25 | 21 => 2,
+                        _ => unreachable!(),
+                    };
+                    let field = words.get(at..at + width).ok_or("vita49 context packet is truncated")?;
+                    let value = if width == 2 { from_fixed((field[0] as u64) << 32 | field[1] as u64) } else { 0.0 };
+                    match 1 << bit {
+                        CIF_BANDWIDTH => context.bandwidth = value,
+                        CIF_RF_FREQUENCY => context.frequency = value,
+                        CIF_SAMPLE_RATE => context.sample_rate = value,
+                        _ => {},
+                    }
+                    at += width;
+                }
+                Ok(Packet::Context { stream_id, count, time, context })
+            },
+            _ => Err(format!("unsupported vita49 packet type {:#x}", kind).into()),
+        }
+    }
+}
+
+
+/// Splits I/Q into encoded VITA-49 signal data packets of 16-bit samples, preceded by a context packet
+/// and another one every `context_interval` data packets. Shared by the blocking and async sinks.
+pub struct Packetizer {
+    profile: Profile,
+    stream_id: u32,
+    context: Context,
+    start: SystemTime,
+    position: u64,
+    samples_per_packet: usize,
+    context_interval: usize,
+    packets: usize,
+    data_count: u8,
+    context_count: u8,
+}
+
+
+impl Packetizer {
+    pub fn new(profile: Profile, stream_id: u32, context: Context, start: SystemTime) -> Self {
+        Self {
+            profile,
+            stream_id,
+            context,
+            start,
+            position: 0,
+            samples_per_packet: DEFAULT_SAMPLES_PER_PACKET,
+            context_interval: 100,
+            packets: 0,
+            data_count: 0,
+            context_count: 0,
+        }
+    }
+
+    pub fn set_samples_per_packet(&mut self, samples: usize) {
+        self.samples_per_packet = samples.clamp(1, 0xffff - 7);
+    }
+
+    pub fn set_context_interval(&mut self, packets: usize) {
+        self.context_interval = packets.max(1);
+    }
+
+    /// Announce a retune or rate change with a context packet ahead of the next data packet.
+    pub fn set_context(&mut self, context: Context) {
+        if context.sample_rate != self.context.sample_rate {
+            self.start = self.time();
+            self.position = 0;
+        }
+        self.context = context;
+        self.packets = 0;
+    }
+
+    pub fn context(&self) -> Context {
+        self.context
+    }
+
+    fn time(&self) -> SystemTime {
+        // without a usable rate there's no way to advance the clock
+        if self.context.sample_rate <= 0.0 {
+            return self.start;
+        }
+        Duration::try_from_secs_f64(self.position as f64 / self.context.sample_rate).ok()
+            .and_then(|offset| self.start.checked_add(offset))
+            .unwrap_or(self.start)
+    }
+
+    /// Encode `src` into datagrams, reusing the buffers already in `out`.
+    pub fn packetize(&mut self, src: &[Complex32], out: &mut Vec<Vec<u8>>) {
+        let mut n = 0;
+        let mut push = |packet: Packet, profile: Profile| {
+            if out.len() == n {
+                out.push(Vec::new());
+            }
+            packet.encode(profile, &mut out[n]);
+            n += 1;
+        };
+        for chunk in src.chunks(self.samples_per_packet) {
+            if self.packets.is_multiple_of(self.context_interval) {
+                push(Packet::Context { stream_id: self.stream_id, count: self.context_count, time: self.time(), context: self.context }, self.profile);
+                self.context_count = self.context_count.wrapping_add(1) & 0xf;
+            }
+            push(Packet::Data { stream_id: self.stream_id, count: self.data_count, time: self.time(), samples: chunk.to_vec() }, self.profile);
+            self.data_count = self.data_count.wrapping_add(1) & 0xf;
+            self.packets += 1;
+            self.position += chunk.len() as u64;
+        }
+        out.truncate(n);
+    }
+}
+
+
+/// Sends I/Q to a UDP destination as VITA-49 or DIFI packets.
+pub struct Vita49Sink {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    packetizer: Packetizer,
+    datagrams: Vec<Vec<u8>>,
+}
+
+
+pub(crate) fn unspecified(destination: &SocketAddr) -> SocketAddr {
+    if destination.is_ipv4() { (Ipv4Addr::UNSPECIFIED, 0).into() } else { (Ipv6Addr::UNSPECIFIED, 0).into() }
+}
+
+
+impl Vita49Sink {
+    pub fn new(destination: impl ToSocketAddrs, profile: Profile, stream_id: u32, context: Context, start: SystemTime) -> Result<Self, Box<dyn Error>> {
+        let destination = destination.to_socket_addrs()?.next().ok_or("no destination address")?;
+        Ok(Self {
+            socket: UdpSocket::bind(unspecified(&destination))?,
+            destination,
+            packetizer: Packetizer::new(profile, stream_id, context, start),
+            datagrams: Vec::new(),
+        })
+    }
+
+    pub fn set_samples_per_packet(&mut self, samples: usize) {
+        self.packetizer.set_samples_per_packet(samples);
+    }
+
+    pub fn set_context_interval(&mut self, packets: usize) {
+        self.packetizer.set_context_interval(packets);
+    }
+
+    pub fn set_context(&mut self, context: Context) {
+        self.packetizer.set_context(context);
+    }
+}
+
+
+impl Sink<Complex32> for Vita49Sink {
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        self.packetizer.packetize(src, &mut self.datagrams);
+        for datagram in &self.datagrams {
+            self.socket.send_to(datagram, self.destination)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Decodes received datagrams of one stream, tracking its context and lost packets.
+/// Shared by the blocking and async sources.
+pub struct Depacketizer {
+    stream_id: Option<u32>,
+    context: Option<Context>,
+    last_count: Option<u8>,
+    lost: u64,
+    invalid: u64,
+    position: u64,
+    tags: Vec<Tag>,
+}
+
+
+impl Depacketizer {
+    /// Accept only packets from `stream_id`, or any stream when it's `None`.
+    pub fn new(stream_id: Option<u32>) -> Self {
+        Self {
+            stream_id,
+            context: None,
+            last_count: None,
+            lost: 0,
+            invalid: 0,
+            position: 0,
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn context(&self) -> Option<Context> {
+        self.context
+    }
+
+    /// Data packets missed according to the 4-bit packet count.
+    pub fn lost_packets(&self) -> u64 {
+        self.lost
+    }
+
+    /// Datagrams skipped because they couldn't be decoded.
+    pub fn invalid_packets(&self) -> u64 {
+        self.invalid
+    }
+
+    /// Append the samples of a data packet to `dst`, returns false for packets without samples for us.
+    /// Corrupt or unsupported packets are counted and skipped so they don't end the stream.
+    pub fn accept(&mut self, datagram: &[u8], dst: &mut Vec<Complex32>) -> bool {
+        let Ok(packet) = Packet::decode(datagram) else {
+            self.invalid += 1;
+            return false;
+        };
+        match packet {
+            Packet::Context { stream_id, context, .. } => {
+                if self.stream_id.is_none_or(|id| id == stream_id) {
+                    self.context = Some(context);
+                }
+                false
+            },
+            Packet::Data { stream_id, count, time, samples } => {
+                if self.stream_id.is_some_and(|id| id != stream_id) {
+                    return false;
+                }
+                let expected = self.last_count.map(|c| (c + 1) & 0xf);
+                if expected != Some(count) {
+                    if let Some(expected) = expected {
+                        self.lost += (count.wrapping_sub(expected) & 0xf) as u64;
+                    }
+                    self.tags.push(Tag { offset: self.position, value: TagValue::Timestamp(time) });
+                }
+                self.last_count = Some(count);
+                self.position += samples.len() as u64;
+                dst.extend_from_slice(&samples);
+                true
+            },
+        }
+    }
+}
+
+
+impl Tagged for Depacketizer {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        dst.append(&mut self.tags);
+    }
+}
+
+
+/// Receives VITA-49 or DIFI signal data packets from UDP. Context packets update `context`,
+/// and each packet timestamp following a gap in the packet count is tagged.
+pub struct Vita49Source {
+    socket: UdpSocket,
+    depacketizer: Depacketizer,
+    buffer: Vec<u8>,
+}
+
+
+impl Vita49Source {
+    pub fn bind(address: impl ToSocketAddrs, stream_id: Option<u32>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            socket: UdpSocket::bind(address)?,
+            depacketizer: Depacketizer::new(stream_id),
+            buffer: vec![0u8; 65536],
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// A read that sees no data packet within `timeout` returns no samples.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        Ok(self.socket.set_read_timeout(timeout)?)
+    }
+
+    pub fn context(&self) -> Option<Context> {
+        self.depacketizer.context()
+    }
+
+    pub fn lost_packets(&self) -> u64 {
+        self.depacketizer.lost_packets()
+    }
+
+    pub fn invalid_packets(&self) -> u64 {
+        self.depacketizer.invalid_packets()
+    }
+}
+
+
+impl Source<Complex32> for Vita49Source {
+    fn read(&mut self, dst: &mut Vec<Complex32>) -> Result<(), Box<dyn Error>> {
+        dst.clear();
+        loop {
+            let len = match self.socket.recv(&mut self.buffer) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            if self.depacketizer.accept(&self.buffer[..len], dst) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+
+impl Tagged for Vita49Source {
+    fn take_tags(&mut self, dst: &mut Vec<Tag>) {
+        self.depacketizer.take_tags(dst);
+    }
+}
+
+
+impl RateAware for Vita49Sink {
+    fn input_rate(&self) -> Option<SampleRate> {
+        Some(SampleRate(self.packetizer.context().sample_rate.round() as u32))
+    }
+}
+
+
+impl RateAware for Vita49Source {
+    fn output_rate(&self, _input: Option<SampleRate>) -> Option<SampleRate> {
+        self.context().map(|c| SampleRate(c.sample_rate.round() as u32))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use num_complex::Complex32;
+    use crate::tag::{TagValue, Tagged};
+    use crate::traits::{Sink, Source};
+    use crate::vita49::{Context, Packet, Profile, Vita49Sink, Vita49Source};
+
+    #[test]
+    fn test_vita49_udp() -> Result<(), Box<dyn std::error::Error>> {
+        let context = Context { sample_rate: 2.5e6, frequency: 162.425e6, bandwidth: 2e6 };
+        let start = UNIX_EPOCH + Duration::from_micros(1_700_000_000_500_000);
+
+        let mut encoded = Vec::new();
+        Packet::Context { stream_id: 7, count: 3, time: start, context }.encode(Profile::Difi, &mut encoded);
+        assert_eq!(&encoded[..4], [0x48, 0x63, 0x00, 0x0e]);
+        assert_eq!(Packet::decode(&encoded)?, Packet::Context { stream_id: 7, count: 3, time: start, context });
+        // sizes from the network are checked before they're used
+        assert!(Packet::decode(&[0x14, 0x00, 0x00, 0x00, 0, 0, 0, 0]).is_err());
+        assert!(Packet::decode(&[0x48, 0x63, 0x00, 0x02, 0, 0, 0, 7, 0, 0, 0, 0]).is_err());
+        assert!(Packet::decode(&[0x10, 0x00, 0x00, 0x01, 0, 0, 0, 7]).is_err());
+        assert!(Packet::decode(&[0x18, 0x00, 0x00, 0x02, 0, 0, 0, 7]).is_err());
+
+        let mut source = Vita49Source::bind("127.0.0.1:0", Some(7))?;
+        source.set_timeout(Some(Duration::from_millis(500)))?;
+        let mut sink = Vita49Sink::new(source.local_addr()?, Profile::Difi, 7, context, start)?;
+        sink.set_samples_per_packet(100);
+        let samples: Vec<Complex32> = (0..250).map(|n| Complex32::from_polar(0.5, n as f32 * 0.1)).collect();
+        // a corrupt datagram is skipped rather than ending the stream
+        std::net::UdpSocket::bind("127.0.0.1:0")?.send_to(&[0x10, 0x00, 0x00, 0x01], source.local_addr()?)?;
+        sink.write(&samples)?;
+
+        let mut received = Vec::new();
+        let mut buffer = Vec::new();
+        while received.len() < samples.len() {
+            source.read(&mut buffer)?;
+            assert!(!buffer.is_empty());
+            received.extend_from_slice(&buffer);
+        }
+        assert!(received.iter().zip(&samples).all(|(a, b)| (a - b).norm() < 1e-4));
+        assert_eq!(source.context(), Some(context));
+        assert_eq!(source.lost_packets(), 0);
+        assert_eq!(source.invalid_packets(), 1);
+
+        let mut tags = Vec::new();
+        source.take_tags(&mut tags);
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].value, TagValue::Timestamp(start));
+        Ok(())
+    }
+
+}
@@ -0,0 +1,257 @@
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver};
+use num_complex::Complex32;
+use serde_json::{json, Value};
+use tungstenite::{Message, WebSocket};
+use crate::demod::DemodMode;
+use crate::traits::Sink;
+use crate::vfo::MultiVfo;
+use crate::websocket::{accept_in_background, mulaw_encode, AUDIO};
+
+
+/// Audio queued for a client that isn't reading, about 20 s at 24 kHz. Past it the client is dropped.
+const MAX_QUEUED_BYTES: usize = 512 << 10;
+
+
+fn parse_mode(mode: &str) -> Result<DemodMode, Box<dyn Error>> {
+    Ok(match mode.to_ascii_uppercase().as_str() {
+        "FM" | "NFM" => DemodMode::FM,
+        "AM" => DemodMode::AM,
+        "USB" => DemodMode::USB,
+        "LSB" => DemodMode::LSB,
+        "CW" => DemodMode::CW,
+        _ => return Err(format!("unknown mode {:?}", mode).into()),
+    })
+}
+
+
+struct Client {
+    socket: WebSocket<TcpStream>,
+    vfo: usize,
+}
+
+
+/// Small WebSDR backend: every WebSocket client gets its own VFO inside the shared capture.
+/// Clients send text commands like `{"frequency": 145500000, "mode": "FM"}` and receive their
+/// audio as binary `[AUDIO, sample_rate u32, μ-law bytes...]` messages, the same framing as
+/// `websocket::AudioStream`. Replies and errors come back as JSON text.
+pub struct ChannelServer {
+    vfos: MultiVfo,
+    audio_rate: u32,
+    sample_rate: u32,
+    max_clients: usize,
+    clients: Vec<Client>,
+    incoming: Receiver<WebSocket<TcpStream>>,
+    local_addr: SocketAddr,
+}
+
+
+impl ChannelServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A, sample_rate: u32, center: u64, audio_rate: u32) -> Result<Self, Box<dyn Error>> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (tx, incoming) = channel();
+        // after the handshake the socket is polled once per block
+        accept_in_background(listener, move |socket| socket.get_ref().set_nonblocking(true).is_err() || tx.send(socket).is_ok());
+        Ok(Self {
+            vfos: MultiVfo::new(sample_rate, center, audio_rate),
+            audio_rate,
+            sample_rate,
+            max_clients: 16,
+            clients: Vec::new(),
+            incoming,
+            local_addr,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn set_max_clients(&mut self, max_clients: usize) {
+        self.max_clients = max_clients;
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Follow a retune of the capture, clients keep their frequencies where they still fit. The
+    /// status goes out either way, the error says which client no longer fits.
+    pub fn set_center(&mut self, center: u64) -> Result<(), Box<dyn Error>> {
+        let result = self.vfos.set_center(center);
+        self.broadcast_status();
+        result
+    }
+
+    fn status(&self) -> Value {
+        json!({
+            "type": "status",
+            "center": self.vfos.center(),
+            "sample_rate": self.sample_rate,
+            "audio_rate": self.audio_rate,
+        })
+    }
+
+    fn broadcast_status(&mut self) {
+        let status = Message::Text(self.status().to_string().into());
+        for client in self.clients.iter_mut() {
+            let _ = client.socket.send(status.clone());
+        }
+    }
+
+    fn accept_clients(&mut self) {
+        while let Ok(mut socket) = self.incoming.try_recv() {
+            if self.clients.len() >= self.max_clients {
+                let _ = socket.send(Message::Text(json!({"type": "error", "error": "server is full"}).to_string().into()));
+                let _ = socket.close(None);
+                continue;
+            }
+            let vfo = match self.vfos.add(self.vfos.center(), DemodMode::FM) {
+                Ok(vfo) => vfo,
+                Err(e) => {
+                    let _ = socket.send(Message::Text(json!({"type": "error", "error": e.to_string()}).to_string().into()));
+                    let _ = socket.close(None);
+                    continue;
+                },
+            };
+            socket.set_config(|config| config.max_write_buffer_size = MAX_QUEUED_BYTES);
+            if socket.send(Message::Text(self.status().to_string().into())).is_ok() {
+                self.clients.push(Client { socket, vfo });
+            } else {
+                self.vfos.remove(vfo);
+            }
+        }
+    }
+
+    /// Applies a tune command to the client's VFO and describes the result.
+    fn command(vfos: &mut MultiVfo, vfo: usize, text: &str) -> Value {
+        let result = (|| -> Result<Value, Box<dyn Error>> {
+            let command: Value = serde_json::from_str(text)?;
+            let vfo = vfos.get_mut(vfo).ok_or("vfo is gone")?;
+            if let Some(mode) = command.get("mode") {
+                vfo.set_mode(parse_mode(mode.as_str().ok_or("mode must be a string")?)?);
+            }
+            if let Some(frequency) = command.get("frequency") {
+                vfo.set_frequency(frequency.as_u64().ok_or("frequency must be an integer in Hz")?)?;
+            }
+            Ok(json!({"type": "tuned", "frequency": vfo.frequency(), "mode": format!("{:?}", vfo.mode())}))
+        })();
+        result.unwrap_or_else(|e| json!({"type": "error", "error": e.to_string()}))
+    }
+
+    /// Reads pending commands, returns false once the client is gone.
+    fn poll(vfos: &mut MultiVfo, client: &mut Client) -> bool {
+        loop {
+            match client.socket.read() {
+                Ok(Message::Text(text)) => {
+                    let reply = Self::command(vfos, client.vfo, &text);
+                    if client.socket.send(Message::Text(reply.to_string().into())).is_err() {
+                        return false;
+                    }
+                },
+                Ok(Message::Close(_)) => return false,
+                Ok(_) => {},
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+
+impl Sink<Complex32> for ChannelServer {
+    fn write(&mut self, src: &[Complex32]) -> Result<(), Box<dyn Error>> {
+        self.accept_clients();
+
+        let vfos = &mut self.vfos;
+        let mut gone = Vec::new();
+        for client in self.clients.iter_mut() {
+            if !Self::poll(vfos, client) {
+                gone.push(client.vfo);
+            }
+        }
+
+        let mut audio: Vec<(usize, Vec<u8>)> = Vec::with_capacity(self.clients.len());
+        vfos.process(src, |vfo, samples| {
+            let mut message = Vec::with_capacity(5 + samples.len());
+            message.push(AUDIO);
+            message.extend_from_slice(&self.audio_rate.to_le_bytes());
+            message.extend(samples.iter().map(|&s| mulaw_encode(s)));
+            audio.push((vfo, message));
+        })?;
+        for (vfo, message) in audio {
+            let Some(client) = self.clients.iter_mut().find(|client| client.vfo == vfo) else { continue };
+            match client.socket.send(Message::Binary(message.into())) {
+                // queued, it goes out with the next write until the queue is full and the client is dropped
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(_) => gone.push(client.vfo),
+                Ok(()) => {},
+            }
+        }
+
+        for vfo in gone {
+            self.vfos.remove(vfo);
+            self.clients.retain(|client| client.vfo != vfo);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::net::TcpStream;
+    use std::time::{Duration, Instant};
+    use num_complex::Complex32;
+    use serde_json::Value;
+    use tungstenite::{connect, Message};
+    use crate::traits::Sink;
+    use crate::websdr::ChannelServer;
+    use crate::websocket::AUDIO;
+
+    #[test]
+    fn test_channel_server() -> Result<(), Box<dyn Error>> {
+        let mut server = ChannelServer::bind("127.0.0.1:0", 240_000, 145_000_000, 24_000)?;
+        let _idle = TcpStream::connect(server.local_addr())?;
+        let (mut client, _) = connect(format!("ws://{}", server.local_addr()))?;
+        let block = vec![Complex32::new(0.1, 0.0); 2400];
+        let start = Instant::now();
+        while server.clients() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            server.write(&block)?;
+        }
+
+        let status: Value = serde_json::from_str(client.read()?.to_text()?)?;
+        assert_eq!(status["center"], 145_000_000);
+        client.send(Message::Text(r#"{"frequency": 145050000, "mode": "am"}"#.into()))?;
+        client.send(Message::Text(r#"{"frequency": 146000000}"#.into()))?;
+
+        let mut replies = Vec::new();
+        let mut audio = 0;
+        while replies.len() < 2 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            server.write(&block)?;
+            match client.read()? {
+                Message::Text(text) => replies.push(serde_json::from_str::<Value>(&text)?),
+                Message::Binary(data) => {
+                    assert_eq!(data[0], AUDIO);
+                    audio += data.len() - 5;
+                },
+                _ => {},
+            }
+        }
+        assert!(audio > 0);
+        assert_eq!(replies[0]["frequency"], 145_050_000);
+        assert_eq!(replies[0]["mode"], "AM");
+        assert_eq!(replies[1]["type"], "error");
+
+        // the client's 145.05 MHz doesn't fit around 145.2 MHz
+        assert!(server.set_center(145_200_000).is_err());
+        server.set_center(145_100_000)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+use std::error::Error;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::{accept, Message, WebSocket};
+use crate::traits::Sink;
+
+
+/// First byte of a binary message.
+pub const SPECTRUM: u8 = 0x01;
+pub const AUDIO: u8 = 0x02;
+/// How long a new connection gets to send its HTTP upgrade.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// Accepts connections on a background thread and runs each handshake on a thread of its own, so a
+/// client that connects and never upgrades can't hold up the ones behind it. Accepting stops once
+/// `on_accept` returns false.
+pub(crate) fn accept_in_background<F>(listener: TcpListener, on_accept: F)
+where
+    F: Fn(WebSocket<TcpStream>) -> bool + Send + Sync + 'static,
+{
+    let on_accept = Arc::new(on_accept);
+    let closed = Arc::new(AtomicBool::new(false));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if closed.load(Ordering::Relaxed) {
+                break;
+            }
+            let (on_accept, closed) = (on_accept.clone(), closed.clone());
+            thread::spawn(move || {
+                if stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).is_err() || stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT)).is_err() {
+                    return;
+                }
+                if let Ok(socket) = accept(stream) && socket.get_ref().set_read_timeout(None).is_ok() && !on_accept(socket) {
+                    closed.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+}
+
+
+/// One connected client, locked on its own so a slow send only holds up the broadcasts to it.
+type Client = Arc<Mutex<WebSocket<TcpStream>>>;
+
+
+/// Accepts browser clients in the background and broadcasts binary messages to all of them.
+/// Clients that can't keep up within the write timeout are dropped. Cloning shares the clients.
+#[derive(Clone)]
+pub struct WebSocketServer {
+    clients: Arc<Mutex<Vec<Client>>>,
+    local_addr: SocketAddr,
+}
+
+
+impl WebSocketServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, Box<dyn Error>> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = clients.clone();
+        accept_in_background(listener, move |socket| {
+            if socket.get_ref().set_write_timeout(Some(Duration::from_millis(500))).is_ok() {
+                accepted.lock().unwrap().push(Arc::new(Mutex::new(socket)));
+            }
+            true
+        });
+        Ok(Self {
+            clients,
+            local_addr,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Sends to every client with the list unlocked, so a client blocking on its write timeout
+    /// doesn't hold up accepting, `clients` or broadcasts from the other clones.
+    pub fn broadcast(&self, message: Vec<u8>) {
+        let message = Message::Binary(message.into());
+        let clients = self.clients.lock().unwrap().clone();
+        let failed: Vec<Client> = clients.into_iter()
+            .filter(|client| client.lock().unwrap().send(message.clone()).is_err())
+            .collect();
+        if !failed.is_empty() {
+            self.clients.lock().unwrap().retain(|client| !failed.iter().any(|f| Arc::ptr_eq(f, client)));
+        }
+    }
+}
+
+
+/// Max-pools a DC centered dB spectrum down to `bins` columns, for a waterfall narrower than the FFT.
+pub fn bin_spectrum(row: &[f32], bins: usize, output: &mut Vec<f32>) {
+    output.clear();
+    if row.is_empty() || bins == 0 {
+        return;
+    }
+    for bin in 0..bins {
+        let start = bin * row.len() / bins;
+        let end = ((bin + 1) * row.len() / bins).max(start + 1);
+        output.push(row[start..end].iter().copied().fold(f32::NEG_INFINITY, f32::max));
+    }
+}
+
+
+/// Streams spectrogram rows as `[SPECTRUM, min_db f32, max_db f32, bins u8...]`, each bin scaled
+/// from the dB range to 0..=255.
+pub struct SpectrumStream {
+    server: WebSocketServer,
+    bins: usize,
+    min_db: f32,
+    max_db: f32,
+    binned: Vec<f32>,
+}
+
+
+impl SpectrumStream {
+    pub fn new(server: WebSocketServer, bins: usize, min_db: f32, max_db: f32) -> Self {
+        Self {
+            server,
+            bins,
+            min_db,
+            max_db,
+            binned: Vec::new(),
+        }
+    }
+
+    pub fn set_range(&mut self, min_db: f32, max_db: f32) {
+        self.min_db = min_db;
+        self.max_db = max_db;
+    }
+}
+
+
+impl Sink<Vec<f32>> for SpectrumStream {
+    fn write(&mut self, src: &[Vec<f32>]) -> Result<(), Box<dyn Error>> {
+        let scale = 255.0 / (self.max_db - self.min_db).max(f32::EPSILON);
+        for row in src {
+            bin_spectrum(row, self.bins, &mut self.binned);
+            let mut message = Vec::with_capacity(9 + self.binned.len());
+            message.push(SPECTRUM);
+            message.extend_from_slice(&self.min_db.to_le_bytes());
+            message.extend_from_slice(&self.max_db.to_le_bytes());
+            message.extend(self.binned.iter().map(|db| ((db - self.min_db) * scale).clamp(0.0, 255.0) as u8));
+            self.server.broadcast(message);
+        }
+        Ok(())
+    }
+}
+
+
+/// G.711 μ-law, 8 bits per sample at half the bandwidth of 16 bit PCM.
+pub fn mulaw_encode(sample: f32) -> u8 {
+    const BIAS: i32 = 0x84;
+    let mut s = (sample.clamp(-1.0, 1.0) * 32767.0) as i32;
+    let sign = if s < 0 { 0x80 } else { 0 };
+    s = s.abs().min(32635) + BIAS;
+    let exponent = 24 - s.leading_zeros() as i32;
+    let mantissa = (s >> (exponent + 3)) & 0x0f;
+    !(sign | (exponent << 4) as u8 | mantissa as u8)
+}
+
+
+pub fn mulaw_decode(byte: u8) -> f32 {
+    let byte = !byte;
+    let exponent = (byte >> 4) & 0x07;
+    let magnitude = ((((byte & 0x0f) as i32) << 3) + 0x84) << exponent;
+    let s = magnitude - 0x84;
+    (if byte & 0x80 != 0 { -s } else { s }) as f32 / 32767.0
+}
+
+
+/// Streams audio as `[AUDIO, sample_rate u32, μ-law bytes...]`, one message per write.
+pub struct AudioStream {
+    server: WebSocketServer,
+    sample_rate: u32,
+}
+
+
+impl AudioStream {
+    pub fn new(server: WebSocketServer, sample_rate: u32) -> Self {
+        Self {
+            server,
+            sample_rate,
+        }
+    }
+}
+
+
+impl Sink<f32> for AudioStream {
+    fn write(&mut self, src: &[f32]) -> Result<(), Box<dyn Error>> {
+        if src.is_empty() {
+            return Ok(());
+        }
+        let mut message = Vec::with_capacity(5 + src.len());
+        message.push(AUDIO);
+        message.extend_from_slice(&self.sample_rate.to_le_bytes());
+        message.extend(src.iter().map(|&s| mulaw_encode(s)));
+        self.server.broadcast(message);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::net::TcpStream;
+    use std::time::{Duration, Instant};
+    use tungstenite::connect;
+    use crate::traits::Sink;
+    use crate::websocket::{mulaw_decode, mulaw_encode, AudioStream, SpectrumStream, WebSocketServer, AUDIO, SPECTRUM};
+
+    #[test]
+    fn test_websocket_streams() -> Result<(), Box<dyn Error>> {
+        for s in [-1.0, -0.3, -0.001, 0.0, 0.02, 0.5, 0.99] {
+            assert!((mulaw_decode(mulaw_encode(s)) - s).abs() <= s.abs() / 16.0 + 1e-3, "{}", s);
+        }
+
+        let server = WebSocketServer::bind("127.0.0.1:0")?;
+        // a connection that never upgrades doesn't hold up the next one
+        let _idle = TcpStream::connect(server.local_addr())?;
+        let (mut client, _) = connect(format!("ws://{}", server.local_addr()))?;
+        let start = Instant::now();
+        while server.clients() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let mut spectrum = SpectrumStream::new(server.clone(), 4, -100.0, 0.0);
+        let row: Vec<f32> = (0..16).map(|i| if i == 9 { -10.0 } else { -90.0 }).collect();
+        spectrum.write(&[row])?;
+        let mut audio = AudioStream::new(server, 8000);
+        audio.write(&[0.0, 0.5, -0.5])?;
+
+        let message = client.read()?.into_data();
+        assert_eq!(message[0], SPECTRUM);
+        assert_eq!(&message[9..], [25, 25, 229, 25]);
+        let message = client.read()?.into_data();
+        assert_eq!(message[0], AUDIO);
+        assert_eq!(u32::from_le_bytes(message[1..5].try_into()?), 8000);
+        assert_eq!(message.len(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_slow_client() -> Result<(), Box<dyn Error>> {
+        let server = WebSocketServer::bind("127.0.0.1:0")?;
+        // never reads, so sends to it block until the write timeout once the socket buffers fill
+        let (_stuck, _) = connect(format!("ws://{}", server.local_addr()))?;
+        let start = Instant::now();
+        while server.clients() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let broadcaster = server.clone();
+        let sending = std::thread::spawn(move || {
+            for _ in 0..8 {
+                broadcaster.broadcast(vec![0; 16 << 20]);
+            }
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        let asked = Instant::now();
+        assert!(server.clients() <= 1);
+        assert!(asked.elapsed() < Duration::from_millis(50), "{:?}", asked.elapsed());
+
+        sending.join().unwrap();
+        assert_eq!(server.clients(), 0);
+        Ok(())
+    }
+}